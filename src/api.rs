@@ -1,99 +1,1577 @@
+mod auth;
+mod cors;
+mod error;
+mod explorer;
+mod graphql;
+mod grpc;
+mod openapi;
+mod rate_limit;
+mod rpc;
+mod tls;
+mod versioning;
+mod websocket;
+
+use std::time::{Duration, Instant};
+
 use crate::{
-    model::{Block, Blockchain, Transaction, TransactionPool},
-    util::{execution::Runnable, Context},
+    miner::{Miner, MinerError, MinerStats, MiningState},
+    model::{
+        address_bloom, hash_hex, merkle_root, AddressBloom, AddressTransaction, Block, BlockHash,
+        BlockHeader, Blockchain, BlockchainError, Contract, DropReason, PendingTransaction,
+        Receipt, SnapshotError, TokenId, Transaction, TransactionId, TransactionPool,
+        TransactionProof,
+    },
+    network::{Gossip, InclusionProofs, NetworkError, NetworkTime, Peers},
+    notifier::{Subscription, SubscriptionId, Subscriptions},
+    plugin::Plugins,
+    util::{termination::Shutdown, Byzantine, Context},
+    wallet::{self, decode_address, AddressBalance, Wallet, WalletMode},
+};
+use actix_http::{error::DispatchError, HttpService, Protocol};
+use actix_service::{fn_service, map_config, pipeline_factory, ServiceFactory};
+use actix_web::{
+    dev::{AppConfig, Body, Server, Service, ServiceRequest, ServiceResponse},
+    error::{BlockingError, JsonPayloadError},
+    http::Method,
+    rt::time::delay_for,
+    web, App, HttpResponse, HttpServer, ResponseError,
 };
-use actix_web::{web, App, HttpResponse, HttpServer, Responder};
-use anyhow::Result;
+use anyhow::{Context as _, Result};
+use futures::{
+    future::{ok, Either},
+    TryFutureExt,
+};
+use serde::{Deserialize, Serialize};
+use tokio::net::TcpStream;
+use tracing::{info_span, Instrument};
+
+use self::{auth::ApiAuth, cors::Cors, error::ApiError, rate_limit::RateLimiter};
+
+// Explicitly controlling which individual identifiers we export
+pub use self::{auth::API_KEY_HEADER, tls::TlsAcceptor};
+
+// Requests are rejected before reaching the routes if the node can't or won't take them
+fn check_request(state: &ApiState, req: &ServiceRequest) -> Result<(), ApiError> {
+    // while draining, only read requests are allowed
+    // so no new user submissions are accepted and then lost
+    if state.shutdown.is_draining() && !is_read(req.method(), req.path()) {
+        return Err(ApiError::Unavailable(SHUTTING_DOWN.to_string()));
+    }
+
+    // only clients with the api key can change the state of the node
+    state.auth.check(req)?;
+
+    // a single client can't flood the node with writes
+    state.rate_limiter.check(req)
+}
+
+// Requests that don't change the state of the node
+// GraphQL queries are sent with POST, but the node only supports queries (no mutations)
+// JSON-RPC calls are also sent with POST, and the methods that are writes check it themselves
+fn is_read(method: &Method, path: &str) -> bool {
+    let path = versioning::route_path(path);
+    *method == Method::GET || (*method == Method::POST && (path == "/graphql" || path == "/rpc"))
+}
+
+// Handlers either respond successfully or with one of our errors, always with a JSON body
+type ApiResult = Result<HttpResponse, ApiError>;
+
+// Time interval to check for changes while long polling
+const LONGPOLL_CHECK_MS: u64 = 50;
+
+// Number of blocks returned in a page of "/blocks", when the client does not ask for a limit
+const DEFAULT_BLOCKS_LIMIT: u64 = 100;
+
+// Max number of blocks returned in a page of "/blocks"
+const MAX_BLOCKS_LIMIT: u64 = 1000;
+
+// Max number of transactions submitted at once to "/transactions/batch"
+const MAX_BATCH_TRANSACTIONS: usize = 1000;
+
+// How long a light client waits for its peers to send the proof of a transaction,
+// and how often it asks them again meanwhile (e.g. in case it didn't have the header yet)
+const PROOF_TIMEOUT_MS: u64 = 3000;
+const PROOF_RETRY_MS: u64 = 500;
+
+// Number of recent blocks that fee estimates look at, so they can't be asked for a longer horizon
+const FEE_ESTIMATE_BLOCKS: u64 = 100;
+
+const SHUTTING_DOWN: &str = "The node is shutting down";
 
 struct ApiState {
+    started_at: Instant,
+    auth: ApiAuth,
+    rate_limiter: RateLimiter,
+    cors: Cors,
+    finality_depth: u64,
+    amount_decimals: u32,
+    longpoll_timeout_ms: u64,
     blockchain: Blockchain,
     pool: TransactionPool,
+    shutdown: Shutdown,
+    byzantine: Byzantine,
+    subscriptions: Subscriptions,
+    wallet: Wallet,
+    miner_stats: MinerStats,
+    miner: Miner,
+    gossip: Gossip,
+    peers: Peers,
+    network_time: NetworkTime,
+    light_client: bool,
+    proofs: InclusionProofs,
+    plugins: Plugins,
+}
+
+// Point of the chain to use when answering queries
+// The "safe" point is buried under enough blocks to be considered final
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum ChainPoint {
+    Latest,
+    Safe,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum BlockOrder {
+    Asc,
+    Desc,
+}
+
+#[derive(Deserialize)]
+struct BlocksQuery {
+    at: Option<ChainPoint>,
+    // index of the first block of the page
+    from: Option<u64>,
+    limit: Option<u64>,
+    order: Option<BlockOrder>,
+}
+
+impl BlocksQuery {
+    // Clients that don't ask for a page get the whole list, as older nodes expect
+    fn is_paginated(&self) -> bool {
+        self.from.is_some() || self.limit.is_some() || self.order.is_some()
+    }
+}
+
+// A page of blocks, along with the cursor to request the next one
+#[derive(Serialize)]
+struct BlocksPage {
+    // index of the last block at the queried chain point
+    height: u64,
+    blocks: Vec<Block>,
+    // value of "from" for the next page, absent on the last one
+    next: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct TipResponse {
+    index: u64,
+    #[serde(with = "hash_hex")]
+    hash: BlockHash,
+}
+
+impl From<Block> for TipResponse {
+    fn from(block: Block) -> Self {
+        TipResponse {
+            index: block.header.index,
+            hash: block.header.hash,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct StatusResponse {
+    version: &'static str,
+    uptime_secs: u64,
+    latest: TipResponse,
+    safe: TipResponse,
+    // expected number of hashes to produce the whole chain, peers follow the chain with the most work
+    #[serde(with = "hash_hex")]
+    total_work: BlockHash,
+    finality_depth: u64,
+    // digits of the amounts that are decimals, for clients to show them
+    amount_decimals: u32,
+    // target (in compact form) that the next block must carry in its header
+    next_bits: u32,
+    mempool_size: usize,
+    peer_count: usize,
+    // median offset of the clocks of the p2p peers from the one of the node, once there are enough of them
+    clock_offset_ms: Option<i64>,
+    mining: MiningState,
+}
+
+#[derive(Deserialize)]
+struct MineQuery {
+    // whether to respond with the mined block, instead of right away
+    wait: Option<bool>,
+}
+
+#[derive(Deserialize)]
+struct FeeEstimateQuery {
+    // number of blocks the transaction should be confirmed within
+    confirm_within: Option<u64>,
+}
+
+// Fee rate suggested for a transaction, with the data the estimate is based on
+#[derive(Serialize)]
+struct FeeEstimateResponse {
+    confirm_within: u64,
+    fee_rate: u64,
+    // the recent blocks analyzed, with the transactions they confirmed and the ones in the pool
+    blocks: u64,
+    confirmed_transactions: usize,
+    pending_transactions: usize,
+}
+
+#[derive(Deserialize)]
+struct PeerRequest {
+    // where the peer listens for p2p connections, e.g. "localhost:9000"
+    address: String,
+}
+
+#[derive(Deserialize)]
+struct SnapshotQuery {
+    // index of the last block of the snapshot, the safe block by default
+    height: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct BlockTemplateQuery {
+    longpoll_id: Option<String>,
+}
+
+// Everything an external miner needs to build the next block
+// The "longpoll_id" identifies the state of the chain and the pool used to build it
+#[derive(Serialize)]
+struct BlockTemplate {
+    longpoll_id: String,
+    index: u64,
+    #[serde(with = "hash_hex")]
+    previous_hash: BlockHash,
+    bits: u32,
+    // lowest timestamp (in milliseconds) that the block can carry
+    min_timestamp: i64,
+    // merkle and state roots and bloom of the block if it includes all the transactions of the template
+    #[serde(with = "hash_hex")]
+    merkle_root: BlockHash,
+    #[serde(with = "hash_hex")]
+    state_root: BlockHash,
+    bloom: AddressBloom,
+    transactions: Vec<Transaction>,
+}
+
+// Outcome of a block submitted to the node, so external miners know if their work made it into the chain
+// Rejections keep the "code" and "message" of the other errors, plus a stable "reason" for programs
+#[derive(Serialize)]
+#[serde(tag = "status", rename_all = "lowercase")]
+enum BlockSubmissionResponse {
+    Accepted {
+        index: u64,
+        #[serde(with = "hash_hex")]
+        hash: BlockHash,
+        // index of the last block after adding it
+        height: u64,
+    },
+    // the block was already in the chain, e.g. it arrived from a peer first
+    Duplicate {
+        index: u64,
+        #[serde(with = "hash_hex")]
+        hash: BlockHash,
+    },
+    Rejected {
+        code: &'static str,
+        reason: &'static str,
+        message: String,
+    },
+}
+
+#[derive(Serialize)]
+struct TransactionResponse {
+    #[serde(with = "hash_hex")]
+    id: TransactionId,
+}
+
+// Outcome of each transaction of a batch, with the "code" and "message" of the error if it was rejected
+#[derive(Serialize)]
+#[serde(tag = "status", rename_all = "lowercase")]
+enum TransactionSubmissionResponse {
+    Accepted {
+        #[serde(with = "hash_hex")]
+        id: TransactionId,
+    },
+    Rejected {
+        code: &'static str,
+        message: String,
+    },
+}
+
+// Status of a transaction, either waiting in the pool or already included in a block
+#[derive(Serialize)]
+#[serde(tag = "status", rename_all = "lowercase")]
+enum TransactionStatusResponse {
+    Pending(PendingTransaction),
+    Confirmed {
+        #[serde(with = "hash_hex")]
+        id: TransactionId,
+        block_index: u64,
+        #[serde(with = "hash_hex")]
+        block_hash: BlockHash,
+        // only contract transactions have a receipt
+        #[serde(skip_serializing_if = "Option::is_none")]
+        receipt: Option<Receipt>,
+    },
+}
+
+// What happened to a transaction sent to the node, without its contents
+#[derive(Serialize)]
+#[serde(tag = "status", rename_all = "lowercase")]
+enum TransactionFateResponse {
+    Pending {
+        #[serde(with = "hash_hex")]
+        id: TransactionId,
+        age_ms: u64,
+    },
+    Confirmed {
+        #[serde(with = "hash_hex")]
+        id: TransactionId,
+        block_index: u64,
+        #[serde(with = "hash_hex")]
+        block_hash: BlockHash,
+        // number of blocks from the one with the transaction to the last one, both included
+        confirmations: u64,
+    },
+    // it left the pool without being mined, so it must be sent again
+    Dropped {
+        #[serde(with = "hash_hex")]
+        id: TransactionId,
+        reason: DropReason,
+        dropped_at: i64,
+    },
+}
+
+#[derive(Serialize)]
+struct TransactionProofResponse {
+    #[serde(flatten)]
+    proof: TransactionProof,
+    // number of blocks from the one with the transaction to the last one, both included
+    confirmations: u64,
+}
+
+#[derive(Serialize)]
+struct ContractResponse {
+    address: String,
+    #[serde(flatten)]
+    contract: Contract,
+}
+
+// Confirmed balance of an address in a token
+#[derive(Serialize)]
+struct TokenBalance {
+    #[serde(with = "hash_hex")]
+    token_id: TokenId,
+    name: String,
+    balance: u64,
+}
+
+#[derive(Deserialize)]
+struct AddressBlocksQuery {
+    // index of the first block to look at
+    since: Option<u64>,
+    limit: Option<u64>,
+}
+
+// Headers of the blocks whose bloom filter matches an address, which may involve it
+#[derive(Serialize)]
+struct AddressBlocksResponse {
+    address: String,
+    // index of the last block when the query was made
+    height: u64,
+    blocks: Vec<BlockHeader>,
+    // value of "since" for the next page, absent if every block was looked at
+    next: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct AddressTransactionsQuery {
+    at: Option<ChainPoint>,
+    // position of the first transaction of the page in the history of the address
+    from: Option<u64>,
+    limit: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct BalanceQuery {
+    at: Option<ChainPoint>,
+}
+
+// Transactions of an address, from the oldest to the newest
+#[derive(Serialize)]
+struct AddressTransactionsResponse {
+    address: String,
+    // index of the last block when the query was made
+    height: u64,
+    // number of transactions of the address
+    total: usize,
+    transactions: Vec<AddressTransaction>,
+    // value of "from" for the next page, absent on the last one
+    next: Option<usize>,
+}
+
+#[derive(Serialize)]
+struct TokensResponse {
+    address: String,
+    tokens: Vec<TokenBalance>,
+}
+
+#[derive(Serialize)]
+struct SubscriptionResponse {
+    id: SubscriptionId,
+}
+
+#[derive(Serialize)]
+struct WalletResponse {
+    mode: WalletMode,
+    balances: Vec<AddressBalance>,
+}
+
+// Amounts received and sent by an address, and the resulting balance
+#[derive(Serialize)]
+struct BalanceAmounts {
+    received: u64,
+    sent: u64,
+    balance: i128,
+}
+
+impl From<AddressBalance> for BalanceAmounts {
+    fn from(balance: AddressBalance) -> Self {
+        BalanceAmounts {
+            received: balance.received,
+            sent: balance.sent,
+            balance: balance.balance(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct BalanceResponse {
+    address: String,
+    confirmed: BalanceAmounts,
+    // the confirmed amounts plus the ones of the transactions waiting in the pool
+    pending: BalanceAmounts,
 }
 
 pub struct Api {
-    port: u16,
+    started_at: Instant,
+    auth: ApiAuth,
+    rate_limiter: RateLimiter,
+    cors: Cors,
+    listen_addresses: Vec<String>,
+    grpc_port: u16,
+    finality_depth: u64,
+    amount_decimals: u32,
+    longpoll_timeout_ms: u64,
+    max_body_bytes: usize,
+    request_timeout_ms: u64,
+    // without a certificate the api is served over plain HTTP
+    tls_cert_path: String,
+    tls_key_path: String,
+    shutdown_drain_ms: u64,
+    shutdown_timeout_secs: u64,
     blockchain: Blockchain,
     pool: TransactionPool,
+    shutdown: Shutdown,
+    byzantine: Byzantine,
+    subscriptions: Subscriptions,
+    wallet: Wallet,
+    miner_stats: MinerStats,
+    miner: Miner,
+    gossip: Gossip,
+    peers: Peers,
+    network_time: NetworkTime,
+    light_client: bool,
+    proofs: InclusionProofs,
+    plugins: Plugins,
 }
 
-impl Runnable for Api {
-    fn run(&self) -> Result<()> {
-        let api_blockchain = self.blockchain.clone();
-        let api_pool = self.pool.clone();
+impl Api {
+    // Serves the REST (and gRPC) api in the runtime of the node, until the shutdown drains it
+    pub async fn serve(&self) -> Result<()> {
+        // These variables are really "Arc" pointers to a shared memory value
+        // So when we clone them, we are only cloning the pointers and not the actual data
+        let api_state = ApiState {
+            started_at: self.started_at,
+            auth: self.auth.clone(),
+            rate_limiter: self.rate_limiter.clone(),
+            cors: self.cors.clone(),
+            finality_depth: self.finality_depth,
+            amount_decimals: self.amount_decimals,
+            longpoll_timeout_ms: self.longpoll_timeout_ms,
+            blockchain: self.blockchain.clone(),
+            pool: self.pool.clone(),
+            shutdown: self.shutdown.clone(),
+            byzantine: self.byzantine.clone(),
+            subscriptions: self.subscriptions.clone(),
+            wallet: self.wallet.clone(),
+            miner_stats: self.miner_stats.clone(),
+            miner: self.miner.clone(),
+            gossip: self.gossip.clone(),
+            peers: self.peers.clone(),
+            network_time: self.network_time.clone(),
+            light_client: self.light_client,
+            proofs: self.proofs.clone(),
+            plugins: self.plugins.clone(),
+        };
+        let api_state = web::Data::new(api_state);
+
+        // the gRPC api shares the state (and checks) of the REST api, but runs on its own
+        if self.grpc_port != 0 {
+            let grpc_port = self.grpc_port;
+            let grpc_addresses = self.listen_addresses.clone();
+            let grpc_state = api_state.clone();
+            actix_web::rt::spawn(async move {
+                if let Err(error) = grpc::serve(&grpc_addresses, grpc_port, grpc_state).await {
+                    error!("gRPC api stopped: {}", error);
+                }
+            });
+        }
 
-        start_server(self.port, api_blockchain, api_pool)
+        // clients get as long to finish the handshake as to send the headers of a request
+        let tls = match self.tls_cert_path.is_empty() {
+            true => None,
+            false => Some(TlsAcceptor::new(
+                &self.tls_cert_path,
+                &self.tls_key_path,
+                self.request_timeout_ms,
+            )?),
+        };
+
+        let result = start_server(
+            self.listen_addresses.clone(),
+            RequestLimits {
+                max_body_bytes: self.max_body_bytes,
+                timeout_ms: self.request_timeout_ms,
+            },
+            tls,
+            self.shutdown_drain_ms,
+            self.shutdown_timeout_secs,
+            api_state,
+        )
+        .await;
+
+        // let the termination handler know that there are no more requests being processed
+        self.shutdown.mark_stopped();
+
+        result
     }
-}
 
-impl Api {
     pub fn new(context: &Context) -> Api {
         Api {
-            port: context.config.port,
+            started_at: Instant::now(),
+            auth: ApiAuth::new(&context.config.api_key, context.config.api_public_reads),
+            rate_limiter: RateLimiter::new(
+                context.config.rate_limit_per_sec,
+                context.config.rate_limit_burst,
+            ),
+            cors: Cors::new(
+                &context.config.cors_allowed_origins,
+                &context.config.cors_allowed_methods,
+                context.config.cors_max_age_secs,
+            ),
+            listen_addresses: context.config.api_listen_addresses(),
+            grpc_port: context.config.grpc_port,
+            finality_depth: context.config.finality_depth,
+            amount_decimals: context.config.amount_decimals,
+            longpoll_timeout_ms: context.config.longpoll_timeout_ms,
+            max_body_bytes: context.config.api_max_body_bytes,
+            request_timeout_ms: context.config.api_request_timeout_ms,
+            tls_cert_path: context.config.tls_cert_path.clone(),
+            tls_key_path: context.config.tls_key_path.clone(),
+            shutdown_drain_ms: context.config.shutdown_drain_ms,
+            shutdown_timeout_secs: context.config.shutdown_timeout_secs,
             blockchain: context.blockchain.clone(),
             pool: context.pool.clone(),
+            shutdown: context.shutdown.clone(),
+            byzantine: context.config.byzantine.clone(),
+            subscriptions: context.subscriptions.clone(),
+            wallet: context.wallet.clone(),
+            miner_stats: context.miner_stats.clone(),
+            miner: Miner::new(context),
+            gossip: context.gossip.clone(),
+            peers: context.peers.clone(),
+            network_time: context.network_time.clone(),
+            light_client: context.config.light_client,
+            proofs: context.proofs.clone(),
+            plugins: context.plugins.clone(),
         }
     }
 }
 
-#[actix_web::main]
-async fn start_server(port: u16, blockchain: Blockchain, pool: TransactionPool) -> Result<()> {
-    let url = format!("localhost:{}", port);
-    // These variables are really "Arc" pointers to a shared memory value
-    // So when we clone them, we are only cloning the pointers and not the actual data
-    let api_state = web::Data::new(ApiState { blockchain, pool });
+// Bounds of what a single request can take from the node, so one client can't exhaust its memory
+// or hold its connections
+#[derive(Clone, Copy)]
+struct RequestLimits {
+    max_body_bytes: usize,
+    // to send the headers of the request
+    timeout_ms: u64,
+}
 
-    HttpServer::new(move || {
-        App::new()
-            .app_data(api_state.clone())
-            .route("/blocks", web::get().to(get_blocks))
-            .route("/blocks", web::post().to(add_block))
-            .route("/transactions", web::post().to(add_transaction))
-    })
-    .bind(url)
-    .unwrap()
-    .run()
-    .await?;
+// Routes of the api, mounted under "/v1" and, for the clients that predate it, at the root
+// Breaking changes of the responses must go to the routes of a new version instead
+fn configure_routes(config: &mut web::ServiceConfig) {
+    config
+        .route("/status", web::get().to(get_status))
+        .route("/blocks", web::get().to(get_blocks))
+        .route("/blocks", web::post().to(add_block))
+        .route("/blocks/template", web::get().to(get_block_template))
+        .route("/blocks/{id}", web::get().to(get_block))
+        .route("/snapshot", web::get().to(get_snapshot))
+        .route("/contracts/{address}", web::get().to(get_contract))
+        .route("/transactions", web::get().to(get_transactions))
+        .route("/transactions", web::post().to(add_transaction))
+        .route("/transactions/batch", web::post().to(add_transactions))
+        .route("/transactions/{id}", web::get().to(get_transaction))
+        .route(
+            "/transactions/{id}/status",
+            web::get().to(get_transaction_fate),
+        )
+        .route(
+            "/transactions/{id}/proof",
+            web::get().to(get_transaction_proof),
+        )
+        .route("/subscriptions", web::post().to(add_subscription))
+        .route("/subscriptions/{id}", web::delete().to(delete_subscription))
+        .route("/wallet", web::get().to(get_wallet))
+        .route(
+            "/addresses/{address}/balance",
+            web::get().to(get_address_balance),
+        )
+        .route(
+            "/addresses/{address}/blocks",
+            web::get().to(get_address_blocks),
+        )
+        .route(
+            "/addresses/{address}/transactions",
+            web::get().to(get_address_transactions),
+        )
+        .route(
+            "/addresses/{address}/tokens",
+            web::get().to(get_address_tokens),
+        )
+        .route("/mine", web::post().to(mine_block))
+        .route("/miner/stats", web::get().to(get_miner_stats))
+        .route("/fees/estimate", web::get().to(get_fee_estimate))
+        .route("/peers", web::get().to(get_peers))
+        .route("/peers", web::post().to(add_peer))
+        .route("/peers/{id}", web::delete().to(delete_peer))
+        .route("/ws", web::get().to(websocket::websocket))
+        .route("/graphql", web::get().to(graphql::get_graphql))
+        .route("/graphql", web::post().to(graphql::post_graphql))
+        .route("/rpc", web::post().to(rpc::post_rpc))
+        .route("/openapi.json", web::get().to(openapi::get_openapi_spec));
+}
 
+async fn start_server(
+    listen_addresses: Vec<String>,
+    limits: RequestLimits,
+    tls: Option<TlsAcceptor>,
+    shutdown_drain_ms: u64,
+    shutdown_timeout_secs: u64,
+    api_state: web::Data<ApiState>,
+) -> Result<()> {
+    let shutdown = api_state.shutdown.clone();
+
+    // the startup checks can't catch everything, e.g. another process taking the port in the meantime
+    let server = match tls {
+        None => {
+            let mut server = HttpServer::new(move || create_app(api_state.clone(), limits))
+                .client_timeout(limits.timeout_ms)
+                // signals are handled by our own termination handler, to drain before stopping
+                .disable_signals()
+                .shutdown_timeout(shutdown_timeout_secs);
+            for address in &listen_addresses {
+                server = server
+                    .bind(address)
+                    .with_context(|| format!("Could not listen on {}", address))?;
+            }
+            server.run()
+        }
+        // actix-web only supports the TLS of other crates, so our own connections are handed
+        // to its HTTP service once their handshake is done
+        Some(acceptor) => {
+            let mut server = Server::build()
+                .disable_signals()
+                .shutdown_timeout(shutdown_timeout_secs);
+            for address in &listen_addresses {
+                let api_state = api_state.clone();
+                let acceptor = acceptor.clone();
+                let factory = move || {
+                    let acceptor = acceptor.clone();
+                    let app = create_app(api_state.clone(), limits);
+                    let http_service = HttpService::build()
+                        .client_timeout(limits.timeout_ms)
+                        .finish(map_config(app, |_| AppConfig::default()));
+
+                    pipeline_factory(fn_service(move |stream: TcpStream| {
+                        let acceptor = acceptor.clone();
+                        async move {
+                            let peer_address = stream.peer_addr().ok();
+                            match acceptor.accept(stream).await {
+                                Ok(stream) => Ok((stream, Protocol::Http1, peer_address)),
+                                Err(error) => {
+                                    debug!(
+                                        "TLS handshake with {:?} failed: {}",
+                                        peer_address, error
+                                    );
+                                    Err(DispatchError::Io(error))
+                                }
+                            }
+                        }
+                    }))
+                    .and_then(http_service)
+                };
+                // same name as the services of actix-web, so the logs don't depend on TLS
+                let name = format!("actix-web-service-{}", address);
+                server = server
+                    .bind(name, address, factory)
+                    .with_context(|| format!("Could not listen on {}", address))?;
+            }
+            server.run()
+        }
+    };
+
+    stop_server_on_shutdown(server.clone(), shutdown, shutdown_drain_ms);
+    server.await?;
+
+    info!("api stopped");
     Ok(())
 }
 
+// The app of the api, with its middlewares and routes, created for each worker
+fn create_app(
+    api_state: web::Data<ApiState>,
+    limits: RequestLimits,
+) -> App<
+    impl ServiceFactory<
+        Config = (),
+        Request = ServiceRequest,
+        Response = ServiceResponse<Body>,
+        Error = actix_web::Error,
+        InitError = (),
+    >,
+    Body,
+> {
+    App::new()
+        .app_data(api_state.clone())
+        .wrap_fn(|req, srv| {
+            let state = req.app_data::<web::Data<ApiState>>().unwrap();
+
+            // browsers ask before sending cross origin requests, which don't carry the api key
+            if let Some(result) = state.cors.preflight(&req) {
+                let response = result.unwrap_or_else(|error| error.error_response());
+                return Either::Left(ok(req.into_response(response.into_body())));
+            }
+
+            // every response must let the browser read it, even the errors
+            let cors_headers = state.cors.response_headers(&req);
+            let negotiated =
+                match check_request(state, &req).and_then(|_| versioning::negotiate(&req)) {
+                    Ok(negotiated) => negotiated,
+                    Err(error) => {
+                        let mut response = req.into_response(error.error_response().into_body());
+                        cors::add_headers(response.headers_mut(), cors_headers);
+                        return Either::Left(ok(response));
+                    }
+                };
+
+            Either::Right(srv.call(req).map_ok(move |mut response| {
+                cors::add_headers(response.headers_mut(), cors_headers);
+                if let Some(negotiated) = &negotiated {
+                    versioning::add_headers(response.headers_mut(), negotiated);
+                }
+                response
+            }))
+        })
+        // every log while handling a request carries its method and path, even if it's rejected
+        .wrap_fn(|req, srv| {
+            let span = info_span!("request", method = %req.method(), path = req.path());
+            let response = span.in_scope(|| srv.call(req));
+            response
+                .map_ok(|response| {
+                    debug!("responded with status {}", response.status().as_u16());
+                    response
+                })
+                .instrument(span)
+        })
+        // requests that can't even be parsed are also answered with our errors
+        // bodies are only read up to the limit, bigger ones are rejected as soon as they exceed it
+        .app_data(
+            web::JsonConfig::default()
+                .limit(limits.max_body_bytes)
+                .error_handler(move |error, _| match error {
+                    JsonPayloadError::Overflow => {
+                        ApiError::PayloadTooLarge(limits.max_body_bytes).into()
+                    }
+                    error => ApiError::BadRequest(error.to_string()).into(),
+                }),
+        )
+        .app_data(web::PayloadConfig::new(limits.max_body_bytes))
+        .app_data(
+            web::QueryConfig::default()
+                .error_handler(|error, _| ApiError::BadRequest(error.to_string()).into()),
+        )
+        .app_data(
+            web::PathConfig::default()
+                .error_handler(|error, _| ApiError::BadRequest(error.to_string()).into()),
+        )
+        .service(web::scope("/v1").configure(configure_routes))
+        .configure(configure_routes)
+        .route("/ready", web::get().to(get_readiness))
+        .route("/docs", web::get().to(openapi::get_swagger_ui))
+        .route("/explorer", web::get().to(explorer::get_explorer))
+        // the routes of each plugin live under its own path
+        .configure(|config| api_state.plugins.configure_routes(config))
+}
+
+// Wait in a separate task for the shutdown to start, then stop the server gracefully
+fn stop_server_on_shutdown(server: Server, shutdown: Shutdown, drain_ms: u64) {
+    actix_web::rt::spawn(async move {
+        shutdown.draining().await;
+
+        // keep serving reads (but not readiness) for a while,
+        // so load balancers have time to stop sending us traffic
+        delay_for(Duration::from_millis(drain_ms)).await;
+
+        // a graceful stop lets in-flight requests finish up to the shutdown timeout
+        info!("stopping the api");
+        server.stop(true).await;
+    });
+}
+
+// Returns whether the node is ready to receive traffic
+// Load balancers should stop routing requests to the node when it's not ready
+async fn get_readiness(state: web::Data<ApiState>) -> ApiResult {
+    if state.shutdown.is_draining() {
+        return Err(ApiError::Unavailable(SHUTTING_DOWN.to_string()));
+    }
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+// Returns the latest and the safe (final) blocks of the blockchain, the target of the next block
+// and the health of the node (pool size, p2p peers, mining...), so it can be monitored cheaply
+async fn get_status(state: web::Data<ApiState>) -> ApiResult {
+    let status = node_status(&state);
+
+    Ok(HttpResponse::Ok().json(&status))
+}
+
+fn node_status(state: &ApiState) -> StatusResponse {
+    let blockchain = &state.blockchain;
+    StatusResponse {
+        version: env!("CARGO_PKG_VERSION"),
+        uptime_secs: state.started_at.elapsed().as_secs(),
+        latest: blockchain.get_last_block().into(),
+        safe: blockchain.get_safe_block(state.finality_depth).into(),
+        total_work: blockchain.total_work(),
+        finality_depth: state.finality_depth,
+        amount_decimals: state.amount_decimals,
+        next_bits: blockchain.next_bits(),
+        mempool_size: state.pool.size(),
+        peer_count: state.peers.count_connected(),
+        clock_offset_ms: state.network_time.offset_ms(),
+        mining: state.miner.state(),
+    }
+}
+
 // Returns a list of all the blocks in the blockchain
-async fn get_blocks(state: web::Data<ApiState>) -> impl Responder {
+// With "?at=safe" only the blocks that are considered final are returned
+// With "?from=", "?limit=" or "?order=desc" a single page of blocks is returned instead
+async fn get_blocks(state: web::Data<ApiState>, query: web::Query<BlocksQuery>) -> ApiResult {
     let blockchain = &state.blockchain;
-    let blocks = blockchain.get_all_blocks();
+    let height = chain_height(&state, query.at.unwrap_or(ChainPoint::Latest));
 
-    HttpResponse::Ok().json(&blocks)
+    if !query.is_paginated() {
+        let blocks = blockchain.get_blocks_until(height);
+        return Ok(blocks_response(
+            &state,
+            &state.byzantine.corrupt_blocks(blocks),
+        ));
+    }
+
+    let limit = query.limit.unwrap_or(DEFAULT_BLOCKS_LIMIT);
+    if limit == 0 || limit > MAX_BLOCKS_LIMIT {
+        let message = format!("limit must be between 1 and {}", MAX_BLOCKS_LIMIT);
+        return Err(ApiError::BadRequest(message));
+    }
+
+    let (blocks, next) = match query.order.unwrap_or(BlockOrder::Asc) {
+        BlockOrder::Asc => {
+            let first_index = query.from.unwrap_or(0);
+            let last_index = first_index.saturating_add(limit - 1).min(height);
+            let blocks = blockchain.get_blocks_between(first_index, last_index);
+            let next = Some(last_index + 1).filter(|next| !blocks.is_empty() && *next <= height);
+            (blocks, next)
+        }
+        BlockOrder::Desc => {
+            let last_index = query.from.unwrap_or(height).min(height);
+            let first_index = (last_index + 1).saturating_sub(limit);
+            let mut blocks = blockchain.get_blocks_between(first_index, last_index);
+            blocks.reverse();
+            (blocks, first_index.checked_sub(1))
+        }
+    };
+
+    let page = BlocksPage {
+        height,
+        blocks: state.byzantine.corrupt_blocks(blocks),
+        next,
+    };
+    Ok(blocks_response(&state, &page))
+}
+
+// Index of the block at a point of the chain
+fn chain_height(state: &ApiState, at: ChainPoint) -> u64 {
+    match at {
+        ChainPoint::Latest => state.blockchain.get_last_block().header.index,
+        ChainPoint::Safe => {
+            state
+                .blockchain
+                .get_safe_block(state.finality_depth)
+                .header
+                .index
+        }
+    }
+}
+
+// A byzantine node may tamper or withhold the blocks shared with peers, so they must be
+// corrupted (if enabled) before building the response
+fn blocks_response<T: Serialize>(state: &ApiState, blocks: &T) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .body(state.byzantine.serialize(blocks))
+}
+
+// Returns a single block, identified either by its index or by its hash
+// Hashes are 64 hex digits (optionally prefixed by "0x"), so they are never taken for indices
+async fn get_block(state: web::Data<ApiState>, id: web::Path<String>) -> ApiResult {
+    let block = match parse_block_id(&id) {
+        Some(BlockId::Index(index)) => state.blockchain.get_block_at(index),
+        Some(BlockId::Hash(hash)) => state.blockchain.get_block(hash),
+        None => {
+            let message = "Invalid block index or hash".to_string();
+            return Err(ApiError::BadRequest(message));
+        }
+    };
+
+    match block {
+        Some(block) => Ok(HttpResponse::Ok().json(&block)),
+        None => Err(ApiError::NotFound("Block not found".to_string())),
+    }
+}
+
+enum BlockId {
+    Index(u64),
+    Hash(BlockHash),
+}
+
+fn parse_block_id(id: &str) -> Option<BlockId> {
+    if id.starts_with("0x") || id.len() == 64 {
+        let hash = hash_hex::parse_hex(id)?;
+        return Some(BlockId::Hash(hash));
+    }
+
+    id.parse().ok().map(BlockId::Index)
+}
+
+// Returns a template of the next block, for external miners
+// With "?longpoll_id=..." the response is delayed until the chain tip or the pool change,
+// so miners are notified right away of new templates without polling continuously
+async fn get_block_template(
+    state: web::Data<ApiState>,
+    query: web::Query<BlockTemplateQuery>,
+) -> ApiResult {
+    if let Some(longpoll_id) = &query.longpoll_id {
+        let timeout = Duration::from_millis(state.longpoll_timeout_ms);
+        let start = Instant::now();
+
+        // we don't block the worker thread while waiting, so other requests are still served
+        while *longpoll_id == get_longpoll_id(&state)
+            && start.elapsed() < timeout
+            && !state.shutdown.is_draining()
+        {
+            delay_for(Duration::from_millis(LONGPOLL_CHECK_MS)).await;
+        }
+    }
+
+    // the id is calculated before reading the data, so a change in the meantime
+    // makes the next long poll return right away instead of being missed
+    let longpoll_id = get_longpoll_id(&state);
+    let last_block = state.blockchain.get_last_block();
+    let (transactions, _) = state.blockchain.select_transactions(state.pool.get_all());
+    let template = BlockTemplate {
+        longpoll_id,
+        index: last_block.header.index + 1,
+        previous_hash: last_block.header.hash,
+        bits: state.blockchain.next_bits(),
+        min_timestamp: state.blockchain.min_timestamp(),
+        merkle_root: merkle_root(
+            &transactions
+                .iter()
+                .map(Transaction::calculate_id)
+                .collect::<Vec<_>>(),
+        ),
+        state_root: state.blockchain.next_state_root(&transactions),
+        bloom: address_bloom(&transactions),
+        transactions,
+    };
+
+    Ok(HttpResponse::Ok().json(&template))
+}
+
+// The template changes materially when a new block arrives or when new transactions are added
+fn get_longpoll_id(state: &ApiState) -> String {
+    let last_hash = state.blockchain.get_last_block().header.hash;
+    format!("{:x}-{}", last_hash, state.pool.version())
 }
 
 // Adds a new block to the blockchain
-async fn add_block(state: web::Data<ApiState>, block_json: web::Json<Block>) -> HttpResponse {
+async fn add_block(state: web::Data<ApiState>, block_json: web::Json<Block>) -> ApiResult {
     let mut block = block_json.into_inner();
 
     // The hash of the block is mandatory and the blockchain checks if it's correct
     // That's a bit unconvenient for manual use of the API
     // So we ignore the comming hash and recalculate it again before adding to the blockchain
-    block.hash = block.calculate_hash();
+    // The same goes for the merkle root and the bloom, which are derived from the transactions
+    block.header.merkle_root = block.calculate_merkle_root();
+    block.header.bloom = block.calculate_bloom();
+    block.header.hash = block.calculate_hash();
+
+    let (index, hash) = (block.header.index, block.header.hash);
+    if state.blockchain.contains_block(hash) {
+        return Ok(HttpResponse::Ok().json(&BlockSubmissionResponse::Duplicate { index, hash }));
+    }
+
+    // the block goes through the same validation as the ones of our miner and our peers
+    // errors that are not about the block (e.g. of the storage) are still internal errors
+    if let Err(error) = state.blockchain.add_block(block) {
+        let reason = rejection_reason(&error);
+        return match ApiError::from(error) {
+            error @ ApiError::BadRequest(_) => Ok(HttpResponse::BadRequest().json(
+                &BlockSubmissionResponse::Rejected {
+                    code: error.code(),
+                    reason,
+                    message: error.to_string(),
+                },
+            )),
+            error => Err(error),
+        };
+    }
+    info!("Received new block {}", index);
+
+    let height = state.blockchain.get_last_block().header.index;
+    Ok(HttpResponse::Ok().json(&BlockSubmissionResponse::Accepted {
+        index,
+        hash,
+        height,
+    }))
+}
+
+// Stable code of the rule that a submitted block breaks
+fn rejection_reason(error: &anyhow::Error) -> &'static str {
+    match error.downcast_ref::<BlockchainError>() {
+        Some(error) => match error {
+            BlockchainError::InvalidIndex => "invalid_index",
+            BlockchainError::InvalidPreviousHash => "invalid_previous_hash",
+            BlockchainError::InvalidHash => "invalid_hash",
+            BlockchainError::InvalidDifficulty => "invalid_difficulty",
+            BlockchainError::InvalidSignature => "invalid_signature",
+            BlockchainError::InvalidTarget => "invalid_target",
+            BlockchainError::InvalidMerkleRoot => "invalid_merkle_root",
+            BlockchainError::InvalidBloom => "invalid_bloom",
+            BlockchainError::InvalidStateRoot => "invalid_state_root",
+            BlockchainError::InvalidTransactionSignature(_) => "invalid_transaction_signature",
+            BlockchainError::InvalidTimestampTooOld(_) => "timestamp_too_old",
+            BlockchainError::InvalidTimestampInFuture(_) => "timestamp_in_future",
+            BlockchainError::DifferentGenesis(_) => "different_genesis",
+            BlockchainError::ForkTooDeep(_) => "fork_too_deep",
+            BlockchainError::NotEnoughWork => "not_enough_work",
+        },
+        // the transactions of the block can't be applied, e.g. a sender spends more than its balance
+        None => "invalid_transactions",
+    }
+}
 
+// Returns the state of the chain right after a block, so new nodes can start from it
+// instead of replaying the whole chain (see SNAPSHOT_PATH)
+async fn get_snapshot(state: web::Data<ApiState>, query: web::Query<SnapshotQuery>) -> ApiResult {
     let blockchain = &state.blockchain;
-    let result = blockchain.add_block(block.clone());
+    let height = query
+        .height
+        .unwrap_or_else(|| blockchain.get_safe_block(state.finality_depth).header.index);
 
-    match result {
-        Ok(_) => {
-            info!("Received new block {}", block.index);
-            HttpResponse::Ok().finish()
-        }
-        Err(error) => HttpResponse::BadRequest().body(error.to_string()),
+    match blockchain.snapshot(height) {
+        Ok(snapshot) => Ok(HttpResponse::Ok().json(&snapshot)),
+        Err(error @ SnapshotError::UnknownHeight(_)) => Err(ApiError::NotFound(error.to_string())),
+        Err(error) => Err(ApiError::Conflict(error.to_string())),
     }
 }
 
+// Returns the code and the storage of the contract deployed at an address
+async fn get_contract(state: web::Data<ApiState>, address: web::Path<String>) -> ApiResult {
+    let contract = state
+        .blockchain
+        .get_contract(&address)
+        .ok_or_else(|| ApiError::NotFound("Contract not found".to_string()))?;
+
+    Ok(HttpResponse::Ok().json(&ContractResponse {
+        address: address.into_inner(),
+        contract,
+    }))
+}
+
+// Returns the transactions waiting in the pool, with their ids and how long they have been waiting
+async fn get_transactions(state: web::Data<ApiState>) -> ApiResult {
+    let transactions = state.pool.get_pending();
+
+    Ok(HttpResponse::Ok().json(&transactions))
+}
+
+// Returns a transaction waiting in the pool, or the block that includes it
+async fn get_transaction(state: web::Data<ApiState>, id: web::Path<String>) -> ApiResult {
+    let id = hash_hex::parse_hex(&id)
+        .ok_or_else(|| ApiError::BadRequest("Invalid transaction id".to_string()))?;
+    let status = transaction_status(&state, id)?;
+
+    Ok(HttpResponse::Ok().json(&status))
+}
+
+// Returns whether a transaction is pending, confirmed (and how deep) or was dropped from the pool
+// A transaction is only reported as dropped if it's not in the pool or the chain anymore,
+// as it may have entered them again since
+async fn get_transaction_fate(state: web::Data<ApiState>, id: web::Path<String>) -> ApiResult {
+    let id = hash_hex::parse_hex(&id)
+        .ok_or_else(|| ApiError::BadRequest("Invalid transaction id".to_string()))?;
+
+    let pending = state
+        .pool
+        .get_pending()
+        .into_iter()
+        .find(|pending| pending.id == id);
+    let fate = if let Some(pending) = pending {
+        TransactionFateResponse::Pending {
+            id,
+            age_ms: pending.age_ms,
+        }
+    } else if let Some(header) = state.blockchain.find_transaction_block(id) {
+        let last_index = state.blockchain.get_last_block().header.index;
+        TransactionFateResponse::Confirmed {
+            id,
+            block_index: header.index,
+            block_hash: header.hash,
+            // a reorg may have shortened the chain since the block was found
+            confirmations: (last_index + 1).saturating_sub(header.index),
+        }
+    } else if let Some(dropped) = state.pool.get_dropped(id) {
+        TransactionFateResponse::Dropped {
+            id,
+            reason: dropped.reason,
+            dropped_at: dropped.dropped_at,
+        }
+    } else {
+        return Err(ApiError::NotFound("Transaction not found".to_string()));
+    };
+
+    Ok(HttpResponse::Ok().json(&fate))
+}
+
+// Returns the proof that a block of the chain includes a transaction
+// Full nodes build it from their blocks, while light clients get it from their peers
+// and only return it once it matches their headers
+async fn get_transaction_proof(state: web::Data<ApiState>, id: web::Path<String>) -> ApiResult {
+    let id = hash_hex::parse_hex(&id)
+        .ok_or_else(|| ApiError::BadRequest("Invalid transaction id".to_string()))?;
+
+    let proof = if state.light_client {
+        let start = Instant::now();
+        let mut last_request: Option<Instant> = None;
+        loop {
+            if let Some(proof) = state.proofs.get(id) {
+                break Some(proof);
+            }
+            if start.elapsed() >= Duration::from_millis(PROOF_TIMEOUT_MS)
+                || state.shutdown.is_draining()
+            {
+                break None;
+            }
+            if last_request.is_none_or(|at| at.elapsed() >= Duration::from_millis(PROOF_RETRY_MS)) {
+                state.proofs.request(id);
+                last_request = Some(Instant::now());
+            }
+            delay_for(Duration::from_millis(LONGPOLL_CHECK_MS)).await;
+        }
+    } else {
+        state.blockchain.get_transaction_proof(id)
+    };
+
+    let proof = proof.ok_or_else(|| {
+        ApiError::NotFound("No block with the transaction and all its transactions".to_string())
+    })?;
+    let last_index = state.blockchain.get_last_block().header.index;
+    let response = TransactionProofResponse {
+        confirmations: (last_index + 1).saturating_sub(proof.block_index),
+        proof,
+    };
+
+    Ok(HttpResponse::Ok().json(&response))
+}
+
+fn transaction_status(
+    state: &ApiState,
+    id: TransactionId,
+) -> Result<TransactionStatusResponse, ApiError> {
+    let pending = state
+        .pool
+        .get_pending()
+        .into_iter()
+        .find(|pending| pending.id == id);
+    let status = match pending {
+        Some(pending) => TransactionStatusResponse::Pending(pending),
+        None => match state.blockchain.find_transaction_block(id) {
+            Some(header) => TransactionStatusResponse::Confirmed {
+                id,
+                block_index: header.index,
+                block_hash: header.hash,
+                receipt: state.blockchain.get_receipt(id),
+            },
+            None => return Err(ApiError::NotFound("Transaction not found".to_string())),
+        },
+    };
+
+    Ok(status)
+}
+
 // Adds a new transaction to the pool, to be included on the next block
+// Returns the id of the transaction, so clients can track it
 async fn add_transaction(
     state: web::Data<ApiState>,
     transaction_json: web::Json<Transaction>,
-) -> impl Responder {
-    let transaction = transaction_json.into_inner();
-    let pool = &state.pool;
-    pool.add_transaction(transaction);
+) -> ApiResult {
+    let id = submit_transaction(&state, transaction_json.into_inner())?;
 
-    HttpResponse::Ok()
+    Ok(HttpResponse::Ok().json(TransactionResponse { id }))
+}
+
+// Adds many transactions at once, e.g. the transfers of an exchange or a faucet
+// Each one is parsed and checked on its own, so a rejected transaction doesn't stop the rest,
+// and the response has the outcome of each of them in the same order
+async fn add_transactions(
+    state: web::Data<ApiState>,
+    transactions_json: web::Json<Vec<serde_json::Value>>,
+) -> ApiResult {
+    let transactions = transactions_json.into_inner();
+    if transactions.len() > MAX_BATCH_TRANSACTIONS {
+        let message = format!(
+            "a batch can't have more than {} transactions",
+            MAX_BATCH_TRANSACTIONS
+        );
+        return Err(ApiError::BadRequest(message));
+    }
+
+    let responses: Vec<TransactionSubmissionResponse> = transactions
+        .into_iter()
+        .map(|transaction| {
+            serde_json::from_value(transaction)
+                .map_err(|error| ApiError::BadRequest(error.to_string()))
+                .and_then(|transaction| submit_transaction(&state, transaction))
+        })
+        .map(|result| match result {
+            Ok(id) => TransactionSubmissionResponse::Accepted { id },
+            Err(error) => TransactionSubmissionResponse::Rejected {
+                code: error.code(),
+                message: error.to_string(),
+            },
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(responses))
+}
+
+// Checks a transaction sent by a client and adds it to the pool, letting subscribers and peers know
+fn submit_transaction(
+    state: &ApiState,
+    mut transaction: Transaction,
+) -> Result<TransactionId, ApiError> {
+    // signatures are over the raw form, so wallets sign the decoded one too
+    transaction.sender = raw_address(&transaction.sender)?;
+    transaction.recipient = raw_address(&transaction.recipient)?;
+
+    // in cold mode the spending keys are not in the node, so transactions must come signed
+    let chain_id = state.blockchain.chain_id();
+    state.wallet.check_transaction(&transaction, &chain_id)?;
+    state.blockchain.check_transaction(&transaction)?;
+
+    // transactions already included in a block must not be mined again
+    let id = transaction.calculate_id();
+    if state.blockchain.contains_transaction(id) {
+        let message = "Transaction already included in a block".to_string();
+        return Err(ApiError::Conflict(message));
+    }
+
+    let id = state.pool.add_transaction(transaction.clone())?;
+    state.subscriptions.notify_pending(&transaction);
+    state.gossip.relay_transaction(&transaction);
+
+    Ok(id)
+}
+
+// Addresses from clients can be checksummed, which must not have typos,
+// while blocks and the pool only have the raw form
+fn raw_address(address: &str) -> Result<String, ApiError> {
+    decode_address(address).map_err(|error| ApiError::BadRequest(error.to_string()))
+}
+
+// Registers a webhook to be notified of the events touching a set of addresses
+async fn add_subscription(
+    state: web::Data<ApiState>,
+    subscription_json: web::Json<Subscription>,
+) -> ApiResult {
+    let mut subscription = subscription_json.into_inner();
+    subscription.addresses = subscription
+        .addresses
+        .iter()
+        .map(|address| raw_address(address))
+        .collect::<Result<_, _>>()?;
+    let id = state.subscriptions.subscribe(subscription);
+
+    Ok(HttpResponse::Ok().json(SubscriptionResponse { id }))
+}
+
+// Removes a webhook subscription
+async fn delete_subscription(
+    state: web::Data<ApiState>,
+    id: web::Path<SubscriptionId>,
+) -> ApiResult {
+    if state.subscriptions.unsubscribe(id.into_inner()) {
+        return Ok(HttpResponse::Ok().finish());
+    }
+
+    Err(ApiError::NotFound("Subscription not found".to_string()))
+}
+
+// Returns the wallet mode and the balances of the watched addresses
+async fn get_wallet(state: web::Data<ApiState>) -> ApiResult {
+    let wallet = WalletResponse {
+        mode: state.wallet.mode(),
+        balances: state.wallet.balances(&state.blockchain),
+    };
+
+    Ok(HttpResponse::Ok().json(&wallet))
+}
+
+// Returns the balance of any address, both confirmed and including the pending transactions
+async fn get_address_balance(
+    state: web::Data<ApiState>,
+    address: web::Path<String>,
+    query: web::Query<BalanceQuery>,
+) -> ApiResult {
+    let at = query.at.unwrap_or(ChainPoint::Latest);
+    let balance = address_balance(&state, &address, at)?;
+
+    Ok(HttpResponse::Ok().json(&balance))
+}
+
+// Returns the headers of the blocks that may involve an address (as sender or recipient),
+// from the block "?since=" on, so clients only need to download those blocks
+// They are found with the bloom filters of the headers, so some of them may not involve it
+async fn get_address_blocks(
+    state: web::Data<ApiState>,
+    address: web::Path<String>,
+    query: web::Query<AddressBlocksQuery>,
+) -> ApiResult {
+    let limit = query.limit.unwrap_or(DEFAULT_BLOCKS_LIMIT);
+    if limit == 0 || limit > MAX_BLOCKS_LIMIT {
+        let message = format!("limit must be between 1 and {}", MAX_BLOCKS_LIMIT);
+        return Err(ApiError::BadRequest(message));
+    }
+
+    let address = raw_address(&address)?;
+    let height = state.blockchain.get_last_block().header.index;
+    let (blocks, next) =
+        state
+            .blockchain
+            .find_address_blocks(&address, query.since.unwrap_or(0), limit as usize);
+
+    Ok(HttpResponse::Ok().json(&AddressBlocksResponse {
+        address,
+        height,
+        blocks,
+        next,
+    }))
+}
+
+// Returns the transactions that an address sent or received, with the block that includes them
+async fn get_address_transactions(
+    state: web::Data<ApiState>,
+    address: web::Path<String>,
+    query: web::Query<AddressTransactionsQuery>,
+) -> ApiResult {
+    let limit = query.limit.unwrap_or(DEFAULT_BLOCKS_LIMIT);
+    if limit == 0 || limit > MAX_BLOCKS_LIMIT {
+        let message = format!("limit must be between 1 and {}", MAX_BLOCKS_LIMIT);
+        return Err(ApiError::BadRequest(message));
+    }
+
+    let address = raw_address(&address)?;
+    let height = chain_height(&state, query.at.unwrap_or(ChainPoint::Latest));
+    let (transactions, next, total) = state.blockchain.get_address_transactions(
+        &address,
+        query.from.unwrap_or(0) as usize,
+        limit as usize,
+        height,
+    );
+
+    Ok(HttpResponse::Ok().json(&AddressTransactionsResponse {
+        address,
+        height,
+        total,
+        transactions,
+        next,
+    }))
+}
+
+// Returns the confirmed balances of an address in every token it holds
+async fn get_address_tokens(state: web::Data<ApiState>, address: web::Path<String>) -> ApiResult {
+    let address = raw_address(&address)?;
+    let tokens = state
+        .blockchain
+        .get_token_balances(&address)
+        .into_iter()
+        .map(|(token_id, token, balance)| TokenBalance {
+            token_id,
+            name: token.name,
+            balance,
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(&TokensResponse { address, tokens }))
+}
+
+fn address_balance(
+    state: &ApiState,
+    address: &str,
+    at: ChainPoint,
+) -> Result<BalanceResponse, ApiError> {
+    let address = raw_address(address)?;
+    let (confirmed, pending) = wallet::address_balance(&address, &state.blockchain, &state.pool);
+    // the pending amounts don't depend on the point, as they count every block and the pool
+    let confirmed = match at {
+        ChainPoint::Latest => confirmed,
+        ChainPoint::Safe => {
+            let height = chain_height(state, at);
+            AddressBalance::confirmed_at(&address, &state.blockchain, height)
+        }
+    };
+    Ok(BalanceResponse {
+        address,
+        confirmed: confirmed.into(),
+        pending: pending.into(),
+    })
+}
+
+// Mines a single block with the transactions in the pool, even if there are none
+// Useful when automatic mining is disabled, e.g. in development networks
+// With "?wait=true" the response is delayed until the block is mined, and includes it
+async fn mine_block(state: web::Data<ApiState>, query: web::Query<MineQuery>) -> ApiResult {
+    if !state.blockchain.consensus().can_seal() {
+        return Err(ApiError::Conflict(MinerError::CannotSeal.to_string()));
+    }
+    if state.network_time.stops_mining() {
+        return Err(ApiError::Conflict(MinerError::ClockOffset.to_string()));
+    }
+
+    // mining is cpu intensive, so it must not block the api
+    let miner = state.miner.clone();
+    let mining = web::block(move || miner.mine_once());
+    if !query.wait.unwrap_or(false) {
+        actix_web::rt::spawn(async move {
+            if let Err(error) = mining.await {
+                error!("could not mine a block on demand: {}", error);
+            }
+        });
+        return Ok(HttpResponse::Accepted().finish());
+    }
+
+    match mining.await {
+        Ok(block) => Ok(HttpResponse::Ok().json(&block)),
+        Err(BlockingError::Error(error)) => Err(error.into()),
+        Err(BlockingError::Canceled) => Err(ApiError::Internal("Mining was canceled".to_string())),
+    }
+}
+
+// Returns the statistics of the miner (hash rate, nonces tried, blocks found, time per block)
+async fn get_miner_stats(state: web::Data<ApiState>) -> ApiResult {
+    let stats = state.miner_stats.report();
+
+    Ok(HttpResponse::Ok().json(&stats))
+}
+
+// Suggests the fee rate for a transaction to be confirmed within "?confirm_within=" blocks,
+// from the recent blocks and the transactions waiting in the pool
+// Transactions don't pay fees yet and the miner takes every valid transaction of the pool,
+// so none of them outbids a transaction without fees: the estimate is 0 until that changes
+async fn get_fee_estimate(
+    state: web::Data<ApiState>,
+    query: web::Query<FeeEstimateQuery>,
+) -> ApiResult {
+    let confirm_within = match query.confirm_within {
+        Some(blocks) if (1..=FEE_ESTIMATE_BLOCKS).contains(&blocks) => blocks,
+        _ => {
+            let message = format!(
+                "confirm_within must be between 1 and {}",
+                FEE_ESTIMATE_BLOCKS
+            );
+            return Err(ApiError::BadRequest(message));
+        }
+    };
+
+    // the genesis block has no transactions to learn from
+    let last_index = state.blockchain.get_last_block().header.index;
+    let first_index = last_index.saturating_sub(FEE_ESTIMATE_BLOCKS - 1).max(1);
+    let blocks = state.blockchain.get_blocks_between(first_index, last_index);
+    let confirmed_transactions = blocks.iter().map(|block| block.transactions.len()).sum();
+
+    Ok(HttpResponse::Ok().json(&FeeEstimateResponse {
+        confirm_within,
+        fee_rate: 0,
+        blocks: blocks.len() as u64,
+        confirmed_transactions,
+        pending_transactions: state.pool.size(),
+    }))
+}
+
+// Returns the connected p2p peers and the ones that misbehaved, with their score and bans
+async fn get_peers(state: web::Data<ApiState>) -> ApiResult {
+    let peers = state.peers.report();
+
+    Ok(HttpResponse::Ok().json(&peers))
+}
+
+// Connects to a p2p peer, which is kept connected as the ones in P2P_PEERS until the node stops
+// The connection is opened in the background, so it's only accepted here
+async fn add_peer(state: web::Data<ApiState>, request: web::Json<PeerRequest>) -> ApiResult {
+    match state.peers.dial(&request.address) {
+        Ok(()) => Ok(HttpResponse::Accepted().finish()),
+        Err(error @ NetworkError::BannedPeer(_)) => Err(ApiError::Conflict(error.to_string())),
+        Err(error) => Err(ApiError::BadRequest(error.to_string())),
+    }
+}
+
+// Disconnects from a p2p peer and bans it, so the node never talks to it again
+async fn delete_peer(state: web::Data<ApiState>, id: web::Path<String>) -> ApiResult {
+    if state.peers.ban(&id.into_inner()) {
+        return Ok(HttpResponse::Ok().finish());
+    }
+
+    Err(ApiError::NotFound("Peer not found".to_string()))
 }
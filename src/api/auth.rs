@@ -0,0 +1,121 @@
+use actix_web::{dev::ServiceRequest, http::Method};
+
+use super::ApiError;
+
+// Header where clients send the api key
+pub const API_KEY_HEADER: &str = "X-Api-Key";
+
+// Routes that are always public, so load balancers can check the node without the key
+// and client developers can read the documentation of the api or open the explorer
+// (which only has the page, the data is read from the rest of the api)
+const PUBLIC_PATHS: [&str; 4] = ["/ready", "/openapi.json", "/docs", "/explorer"];
+
+// Decides which requests need the api key
+// Requests that change the state of the node (transactions, blocks, mining, bans...) always need it,
+// while reads only need it if they are not public
+#[derive(Debug, Clone)]
+pub struct ApiAuth {
+    // No key means that authentication is disabled
+    api_key: Option<String>,
+    public_reads: bool,
+}
+
+impl ApiAuth {
+    pub fn new(api_key: &str, public_reads: bool) -> ApiAuth {
+        let api_key = Some(api_key.trim().to_string()).filter(|key| !key.is_empty());
+
+        ApiAuth {
+            api_key,
+            public_reads,
+        }
+    }
+
+    pub fn check(&self, req: &ServiceRequest) -> Result<(), ApiError> {
+        let key = req
+            .headers()
+            .get(API_KEY_HEADER)
+            .and_then(|value| value.to_str().ok());
+
+        self.authorize(req.method(), req.path(), key)
+    }
+
+    fn authorize(&self, method: &Method, path: &str, key: Option<&str>) -> Result<(), ApiError> {
+        if PUBLIC_PATHS.contains(&super::versioning::route_path(path)) {
+            return Ok(());
+        }
+
+        self.check_key(super::is_read(method, path), key)
+    }
+
+    // Checks the key sent by a client for a call that may or may not change the state of the node
+    // Used directly by the gRPC server, where the key comes in the call metadata
+    pub fn check_key(&self, is_read: bool, key: Option<&str>) -> Result<(), ApiError> {
+        let api_key = match &self.api_key {
+            Some(api_key) => api_key,
+            None => return Ok(()),
+        };
+
+        if is_read && self.public_reads {
+            return Ok(());
+        }
+
+        match key {
+            Some(key) if constant_time_eq(key.as_bytes(), api_key.as_bytes()) => Ok(()),
+            Some(_) => Err(ApiError::Unauthorized("Invalid api key".to_string())),
+            None => Err(ApiError::Unauthorized(format!(
+                "Missing api key in the {} header",
+                API_KEY_HEADER
+            ))),
+        }
+    }
+}
+
+// Compares the keys without stopping at the first difference,
+// so the time to answer does not reveal how much of a guessed key is right
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    a.iter()
+        .zip(b.iter())
+        .fold(0, |diff, (x, y)| diff | (x ^ y))
+        == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_allow_everything_without_key() {
+        let auth = ApiAuth::new("", false);
+        assert!(auth.authorize(&Method::POST, "/transactions", None).is_ok());
+        assert!(auth.authorize(&Method::GET, "/blocks", None).is_ok());
+    }
+
+    #[test]
+    fn should_protect_writes() {
+        let auth = ApiAuth::new("secret", true);
+        assert!(auth.authorize(&Method::GET, "/blocks", None).is_ok());
+
+        let result = auth.authorize(&Method::POST, "/transactions", None);
+        assert_eq!(result.unwrap_err().code(), "unauthorized");
+        let result = auth.authorize(&Method::DELETE, "/peers/a:1", Some("wrong"));
+        assert!(result.is_err());
+        let result = auth.authorize(&Method::POST, "/transactions", Some("secret"));
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn should_protect_reads_if_not_public() {
+        let auth = ApiAuth::new("secret", false);
+        assert!(auth.authorize(&Method::GET, "/blocks", None).is_err());
+        assert!(auth
+            .authorize(&Method::GET, "/blocks", Some("secret"))
+            .is_ok());
+
+        // load balancers must still be able to check the node
+        assert!(auth.authorize(&Method::GET, "/ready", None).is_ok());
+    }
+}
@@ -0,0 +1,226 @@
+use actix_web::{
+    dev::ServiceRequest,
+    http::{
+        header::{self, HeaderMap, HeaderName, HeaderValue},
+        Method,
+    },
+    HttpResponse,
+};
+
+use super::ApiError;
+
+// Origin that allows requests from any website
+const ANY_ORIGIN: &str = "*";
+
+// Headers that browsers may send in cross origin requests
+const ALLOWED_HEADERS: &str = "Content-Type, X-Api-Key, API-Version";
+
+// Headers of our responses that browsers let the websites read
+const EXPOSED_HEADERS: &str = "Retry-After, API-Version, Deprecation, Link";
+
+// Decides which websites can call the api from a browser (Cross-Origin Resource Sharing)
+// Browsers first ask with a "preflight" OPTIONS request, and only send the real request if we allow it.
+// No allowed origins means that cross origin requests are not allowed at all
+#[derive(Debug, Clone)]
+pub struct Cors {
+    allowed_origins: Vec<String>,
+    allowed_methods: Vec<Method>,
+    max_age_secs: u64,
+}
+
+impl Cors {
+    pub fn new(allowed_origins: &[String], allowed_methods: &[String], max_age_secs: u64) -> Cors {
+        let allowed_origins = allowed_origins
+            .iter()
+            .map(|origin| origin.trim().trim_end_matches('/').to_string())
+            .filter(|origin| !origin.is_empty())
+            .collect();
+
+        // unknown methods are ignored, as no route would match them anyway
+        let allowed_methods = allowed_methods
+            .iter()
+            .filter_map(|method| Method::from_bytes(method.trim().to_uppercase().as_bytes()).ok())
+            .collect();
+
+        Cors {
+            allowed_origins,
+            allowed_methods,
+            max_age_secs,
+        }
+    }
+
+    // Answers the preflight requests, the rest of requests must go on to the routes
+    pub fn preflight(&self, req: &ServiceRequest) -> Option<Result<HttpResponse, ApiError>> {
+        if self.allowed_origins.is_empty() || *req.method() != Method::OPTIONS {
+            return None;
+        }
+        let requested_method = req
+            .headers()
+            .get(header::ACCESS_CONTROL_REQUEST_METHOD)?
+            .to_str()
+            .ok()
+            .and_then(|method| Method::from_bytes(method.as_bytes()).ok());
+
+        let allow_origin = match self.allow_origin(req.headers()) {
+            Some(allow_origin) => allow_origin,
+            None => return Some(Err(not_allowed("Origin"))),
+        };
+        let is_method_allowed = requested_method
+            .map(|method| self.allowed_methods.contains(&method))
+            .unwrap_or(false);
+        if !is_method_allowed {
+            return Some(Err(not_allowed("Method")));
+        }
+
+        let allowed_methods = self
+            .allowed_methods
+            .iter()
+            .map(Method::as_str)
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        let mut response = HttpResponse::NoContent();
+        response
+            .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin)
+            .header(header::ACCESS_CONTROL_ALLOW_METHODS, allowed_methods)
+            .header(header::ACCESS_CONTROL_ALLOW_HEADERS, ALLOWED_HEADERS)
+            .header(
+                header::ACCESS_CONTROL_MAX_AGE,
+                self.max_age_secs.to_string(),
+            )
+            .header(header::VARY, "Origin");
+
+        Some(Ok(response.finish()))
+    }
+
+    // Headers to add to the response, so the browser lets the website read it
+    pub fn response_headers(&self, req: &ServiceRequest) -> Vec<(HeaderName, HeaderValue)> {
+        match self.allow_origin(req.headers()) {
+            Some(allow_origin) => vec![
+                (header::ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin),
+                (
+                    header::ACCESS_CONTROL_EXPOSE_HEADERS,
+                    HeaderValue::from_static(EXPOSED_HEADERS),
+                ),
+                (header::VARY, HeaderValue::from_static("Origin")),
+            ],
+            None => Vec::new(),
+        }
+    }
+
+    // Value of the "Access-Control-Allow-Origin" header, if the origin of the request is allowed
+    fn allow_origin(&self, headers: &HeaderMap) -> Option<HeaderValue> {
+        let origin = headers.get(header::ORIGIN)?;
+        if self
+            .allowed_origins
+            .iter()
+            .any(|allowed| allowed == ANY_ORIGIN)
+        {
+            return Some(HeaderValue::from_static(ANY_ORIGIN));
+        }
+
+        let is_allowed = origin
+            .to_str()
+            .map(|origin| self.allowed_origins.iter().any(|allowed| allowed == origin))
+            .unwrap_or(false);
+        if is_allowed {
+            Some(origin.clone())
+        } else {
+            None
+        }
+    }
+}
+
+// Adds the headers of "response_headers" to a response
+pub fn add_headers(headers: &mut HeaderMap, cors_headers: Vec<(HeaderName, HeaderValue)>) {
+    for (name, value) in cors_headers {
+        headers.insert(name, value);
+    }
+}
+
+fn not_allowed(what: &str) -> ApiError {
+    ApiError::BadRequest(format!("{} not allowed for cross origin requests", what))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+
+    use super::*;
+    use crate::api::API_KEY_HEADER;
+
+    fn cors(origins: &[&str]) -> Cors {
+        let origins: Vec<String> = origins.iter().map(|origin| origin.to_string()).collect();
+        let methods = vec!["GET".to_string(), "post".to_string()];
+        Cors::new(&origins, &methods, 600)
+    }
+
+    fn preflight_request(origin: &str, method: &str) -> ServiceRequest {
+        TestRequest::default()
+            .method(Method::OPTIONS)
+            .header(header::ORIGIN, origin)
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, method)
+            .to_srv_request()
+    }
+
+    #[test]
+    fn should_answer_allowed_preflights() {
+        let cors = cors(&["https://explorer.example/"]);
+
+        let response = cors
+            .preflight(&preflight_request("https://explorer.example", "POST"))
+            .unwrap()
+            .unwrap();
+        let headers = response.headers();
+        assert_eq!(response.status().as_u16(), 204);
+        assert_eq!(
+            headers.get(header::ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(),
+            "https://explorer.example"
+        );
+        assert_eq!(
+            headers.get(header::ACCESS_CONTROL_ALLOW_METHODS).unwrap(),
+            "GET, POST"
+        );
+        assert!(headers
+            .get(header::ACCESS_CONTROL_ALLOW_HEADERS)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .contains(API_KEY_HEADER));
+        assert_eq!(headers.get(header::ACCESS_CONTROL_MAX_AGE).unwrap(), "600");
+    }
+
+    #[test]
+    fn should_reject_other_origins_and_methods() {
+        let cors = cors(&["https://explorer.example"]);
+
+        let result = cors.preflight(&preflight_request("https://evil.example", "GET"));
+        assert!(result.unwrap().is_err());
+        let result = cors.preflight(&preflight_request("https://explorer.example", "DELETE"));
+        assert!(result.unwrap().is_err());
+
+        let req = TestRequest::get()
+            .header(header::ORIGIN, "https://evil.example")
+            .to_srv_request();
+        assert!(cors.response_headers(&req).is_empty());
+    }
+
+    #[test]
+    fn should_ignore_requests_without_cors() {
+        // disabled
+        let cors_disabled = cors(&[]);
+        assert!(cors_disabled
+            .preflight(&preflight_request("https://explorer.example", "GET"))
+            .is_none());
+
+        // same origin requests don't send the "Origin" header
+        let cors = cors(&[ANY_ORIGIN]);
+        let req = TestRequest::get().to_srv_request();
+        assert!(cors.response_headers(&req).is_empty());
+
+        let req = TestRequest::get()
+            .header(header::ORIGIN, "https://any.example")
+            .to_srv_request();
+        assert_eq!(cors.response_headers(&req)[0].1, ANY_ORIGIN);
+    }
+}
@@ -0,0 +1,147 @@
+use actix_web::{
+    http::{header, StatusCode},
+    HttpResponse, ResponseError,
+};
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::{
+    model::{
+        AmountError, BlockchainError, ContractError, TokenError, TransactionError,
+        TransactionPoolError,
+    },
+    util::LockError,
+    wallet::WalletError,
+};
+
+// Errors returned by the api, every one of them is sent to the client as a JSON body
+// with a stable "code" for programs and a human readable "message"
+#[derive(Error, Debug, PartialEq)]
+pub enum ApiError {
+    #[error("{0}")]
+    BadRequest(String),
+
+    #[error("{0}")]
+    Unauthorized(String),
+
+    #[error("{0}")]
+    NotFound(String),
+
+    #[error("{0}")]
+    Conflict(String),
+
+    // Holds the max size of the bodies, in bytes
+    #[error("Request body larger than {0} bytes")]
+    PayloadTooLarge(usize),
+
+    // Holds the seconds the client must wait before retrying
+    #[error("Too many requests, retry in {0} seconds")]
+    TooManyRequests(u64),
+
+    #[error("{0}")]
+    Unavailable(String),
+
+    #[error("{0}")]
+    Internal(String),
+}
+
+#[derive(Serialize)]
+struct ErrorBody {
+    code: &'static str,
+    message: String,
+}
+
+impl ApiError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::BadRequest(_) => "bad_request",
+            ApiError::Unauthorized(_) => "unauthorized",
+            ApiError::NotFound(_) => "not_found",
+            ApiError::Conflict(_) => "conflict",
+            ApiError::PayloadTooLarge(_) => "payload_too_large",
+            ApiError::TooManyRequests(_) => "too_many_requests",
+            ApiError::Unavailable(_) => "unavailable",
+            ApiError::Internal(_) => "internal",
+        }
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
+            ApiError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            ApiError::TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let body = ErrorBody {
+            code: self.code(),
+            message: self.to_string(),
+        };
+
+        let mut response = HttpResponse::build(self.status_code());
+        if let ApiError::TooManyRequests(retry_after_secs) = self {
+            response.header(header::RETRY_AFTER, retry_after_secs.to_string());
+        }
+
+        response.json(&body)
+    }
+}
+
+// Errors of the rest of the node are classified by their type, so handlers can just use "?"
+// Anything we don't know about is an internal error
+impl From<anyhow::Error> for ApiError {
+    fn from(error: anyhow::Error) -> Self {
+        let message = error.to_string();
+        if error.is::<BlockchainError>()
+            || error.is::<AmountError>()
+            || error.is::<ContractError>()
+            || error.is::<TokenError>()
+            || error.is::<TransactionError>()
+            || error.is::<WalletError>()
+        {
+            return ApiError::BadRequest(message);
+        }
+        if error.is::<TransactionPoolError>() {
+            return ApiError::Conflict(message);
+        }
+        // the node keeps serving what it has, but can't take anything that changes it
+        if error.is::<LockError>() {
+            return ApiError::Unavailable(message);
+        }
+
+        ApiError::Internal(message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_classify_node_errors() {
+        let error: ApiError =
+            anyhow::Error::from(TransactionPoolError::DuplicateTransaction).into();
+        assert_eq!(
+            error,
+            ApiError::Conflict("Duplicate transaction".to_string())
+        );
+
+        let error: ApiError = anyhow::Error::from(WalletError::MissingSignature).into();
+        assert_eq!(error.status_code(), StatusCode::BAD_REQUEST);
+
+        let error: ApiError = anyhow::Error::from(LockError::Poisoned("state")).into();
+        assert_eq!(error.status_code(), StatusCode::SERVICE_UNAVAILABLE);
+
+        let error: ApiError = anyhow::anyhow!("something broke").into();
+        assert_eq!(error.code(), "internal");
+        assert_eq!(error.status_code(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+}
@@ -0,0 +1,13 @@
+use actix_web::HttpResponse;
+
+use super::ApiResult;
+
+// Single page explorer of the chain, for developers running a devnet
+// It only uses the public api of the node (GraphQL and REST), from the browser
+const EXPLORER_PAGE: &str = include_str!("explorer.html");
+
+pub async fn get_explorer() -> ApiResult {
+    Ok(HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(EXPLORER_PAGE))
+}
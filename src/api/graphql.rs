@@ -0,0 +1,511 @@
+mod parser;
+
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    convert::{Infallible, TryFrom},
+    sync::Arc,
+};
+
+use actix_web::{error::BlockingError, web, HttpResponse};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value as JsonValue};
+
+use self::parser::{Field, Operation};
+use super::{ApiError, ApiResult, ApiState, DEFAULT_BLOCKS_LIMIT, MAX_BLOCKS_LIMIT};
+use crate::{
+    model::{hash_hex, Block, BlockHash, Transaction, TransactionId},
+    wallet::{self, decode_address, AddressBalance},
+};
+
+// Max number of nested levels of fields in a query, to keep queries from getting too expensive
+const MAX_QUERY_DEPTH: usize = 8;
+
+type ExecutionResult = Result<JsonValue, String>;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GraphQLRequest {
+    query: String,
+    #[serde(default)]
+    variables: Option<Map<String, JsonValue>>,
+    operation_name: Option<String>,
+}
+
+// Same as "GraphQLRequest", but the variables come as JSON in the query string
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GraphQLQuery {
+    query: String,
+    variables: Option<String>,
+    operation_name: Option<String>,
+}
+
+#[derive(Serialize)]
+struct GraphQLResponse {
+    // absent when the request could not even be executed, null when the execution failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<JsonValue>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    errors: Vec<GraphQLError>,
+}
+
+#[derive(Serialize)]
+struct GraphQLError {
+    message: String,
+}
+
+impl GraphQLResponse {
+    fn error(data: Option<JsonValue>, message: String) -> GraphQLResponse {
+        GraphQLResponse {
+            data,
+            errors: vec![GraphQLError { message }],
+        }
+    }
+}
+
+// Answers GraphQL queries over blocks, transactions, addresses and the pool,
+// so clients can fetch all the nested data they need in a single request
+pub async fn post_graphql(
+    state: web::Data<ApiState>,
+    request: web::Json<GraphQLRequest>,
+) -> ApiResult {
+    respond(state, request.into_inner()).await
+}
+
+pub async fn get_graphql(state: web::Data<ApiState>, query: web::Query<GraphQLQuery>) -> ApiResult {
+    let query = query.into_inner();
+    let variables = match query.variables.as_deref() {
+        Some(variables) => serde_json::from_str(variables)
+            .map_err(|error| ApiError::BadRequest(format!("Invalid variables: {}", error)))?,
+        None => None,
+    };
+
+    let request = GraphQLRequest {
+        query: query.query,
+        variables,
+        operation_name: query.operation_name,
+    };
+    respond(state, request).await
+}
+
+async fn respond(state: web::Data<ApiState>, request: GraphQLRequest) -> ApiResult {
+    // queries may go over many blocks, so they must not block the api
+    let state = state.into_inner();
+    let execution = web::block(move || Ok::<_, Infallible>(execute(state, request)));
+
+    match execution.await {
+        Ok(response) => Ok(HttpResponse::Ok().json(&response)),
+        Err(BlockingError::Error(never)) => match never {},
+        Err(BlockingError::Canceled) => Err(ApiError::Internal("Query was canceled".to_string())),
+    }
+}
+
+fn execute(state: Arc<ApiState>, request: GraphQLRequest) -> GraphQLResponse {
+    let (operation, variables) = match prepare(request) {
+        Ok(prepared) => prepared,
+        Err(message) => return GraphQLResponse::error(None, message),
+    };
+
+    let executor = Executor {
+        state: &state,
+        variables,
+        balances: RefCell::default(),
+    };
+    match executor.query(&operation.selection) {
+        Ok(data) => GraphQLResponse {
+            data: Some(data),
+            errors: Vec::new(),
+        },
+        Err(message) => GraphQLResponse::error(Some(JsonValue::Null), message),
+    }
+}
+
+// Picks the operation to execute and the values of its variables
+fn prepare(request: GraphQLRequest) -> Result<(Operation, Map<String, JsonValue>), String> {
+    let mut operations = parser::parse_document(&request.query)?;
+    let operation = match &request.operation_name {
+        Some(name) => operations
+            .into_iter()
+            .find(|operation| operation.name.as_ref() == Some(name))
+            .ok_or_else(|| format!("Unknown operation \"{}\"", name))?,
+        None if operations.len() == 1 => operations.remove(0),
+        None => return Err("operationName is required for documents with many operations".into()),
+    };
+
+    let depth = operation.selection.iter().map(Field::depth).max();
+    if depth.unwrap_or(0) > MAX_QUERY_DEPTH {
+        return Err(format!("Queries can't be deeper than {}", MAX_QUERY_DEPTH));
+    }
+
+    let provided = request.variables.unwrap_or_default();
+    let mut variables = Map::new();
+    for definition in &operation.variables {
+        let value = match (provided.get(&definition.name), &definition.default_value) {
+            (Some(value), _) => value.clone(),
+            (None, Some(default_value)) => default_value.resolve(&Map::new())?,
+            (None, None) => JsonValue::Null,
+        };
+        if definition.required && value.is_null() {
+            return Err(format!("Variable \"${}\" is required", definition.name));
+        }
+        variables.insert(definition.name.clone(), value);
+    }
+
+    Ok((operation, variables))
+}
+
+// Where a transaction is, which decides the fields that have a value
+enum TransactionLocation {
+    Pending { age_ms: u64 },
+    Confirmed { block_hash: BlockHash },
+}
+
+struct Executor<'a> {
+    state: &'a ApiState,
+    variables: Map<String, JsonValue>,
+    // the same address usually appears many times in a query, and every balance goes over the whole chain
+    balances: RefCell<HashMap<String, (AddressBalance, AddressBalance)>>,
+}
+
+impl<'a> Executor<'a> {
+    fn query(&self, selection: &[Field]) -> ExecutionResult {
+        object("Query", selection, |field| match field.name.as_str() {
+            "chain" => self.chain(field),
+            "blocks" => self.blocks(field),
+            "block" => self.block(field),
+            "transaction" => self.transaction(field),
+            "mempool" => self.mempool(field),
+            "address" => {
+                let arguments = self.arguments(field, &["address"])?;
+                match string_argument(&arguments, "address")? {
+                    Some(address) => match decode_address(&address) {
+                        Ok(address) => self.address(field, &address),
+                        Err(error) => Err(error.to_string()),
+                    },
+                    None => Err("Argument \"address\" is required".to_string()),
+                }
+            }
+            _ => Err(unknown_field(field, "Query")),
+        })
+    }
+
+    fn chain(&self, field: &Field) -> ExecutionResult {
+        self.arguments(field, &[])?;
+        let blockchain = &self.state.blockchain;
+
+        object("Chain", subfields(field, "Chain")?, |field| {
+            match field.name.as_str() {
+                "height" => scalar(field, blockchain.get_last_block().header.index),
+                "finalityDepth" => scalar(field, self.state.finality_depth),
+                "nextBits" => scalar(field, blockchain.next_bits()),
+                "latestBlock" => self.block_object(field, &blockchain.get_last_block()),
+                "safeBlock" => {
+                    let safe_block = blockchain.get_safe_block(self.state.finality_depth);
+                    self.block_object(field, &safe_block)
+                }
+                _ => Err(unknown_field(field, "Chain")),
+            }
+        })
+    }
+
+    // Pages of blocks, like "GET /blocks"
+    fn blocks(&self, field: &Field) -> ExecutionResult {
+        let arguments = self.arguments(field, &["from", "limit", "order"])?;
+        let from = u64_argument(&arguments, "from")?;
+        let limit = u64_argument(&arguments, "limit")?.unwrap_or(DEFAULT_BLOCKS_LIMIT);
+        if limit == 0 || limit > MAX_BLOCKS_LIMIT {
+            return Err(format!("limit must be between 1 and {}", MAX_BLOCKS_LIMIT));
+        }
+
+        let blockchain = &self.state.blockchain;
+        let height = blockchain.get_last_block().header.index;
+        let blocks = match string_argument(&arguments, "order")?.as_deref() {
+            None | Some("ASC") => {
+                let first_index = from.unwrap_or(0);
+                let last_index = first_index.saturating_add(limit - 1).min(height);
+                blockchain.get_blocks_between(first_index, last_index)
+            }
+            Some("DESC") => {
+                let last_index = from.unwrap_or(height).min(height);
+                let first_index = (last_index + 1).saturating_sub(limit);
+                let mut blocks = blockchain.get_blocks_between(first_index, last_index);
+                blocks.reverse();
+                blocks
+            }
+            Some(order) => return Err(format!("Invalid order \"{}\", use ASC or DESC", order)),
+        };
+
+        list(blocks.iter(), |block| self.block_object(field, block))
+    }
+
+    // A single block, by index or by hash
+    fn block(&self, field: &Field) -> ExecutionResult {
+        let arguments = self.arguments(field, &["index", "hash"])?;
+        let index = u64_argument(&arguments, "index")?;
+        let hash = string_argument(&arguments, "hash")?;
+
+        let block = match (index, hash) {
+            (Some(index), None) => self.state.blockchain.get_block_at(index),
+            (None, Some(hash)) => self.state.blockchain.get_block(parse_hash(&hash)?),
+            _ => return Err("Either \"index\" or \"hash\" must be provided".to_string()),
+        };
+
+        nullable(block, |block| self.block_object(field, &block))
+    }
+
+    // A transaction, either waiting in the pool or included in a block
+    fn transaction(&self, field: &Field) -> ExecutionResult {
+        let arguments = self.arguments(field, &["id"])?;
+        let id = match string_argument(&arguments, "id")? {
+            Some(id) => parse_hash(&id)?,
+            None => return Err("Argument \"id\" is required".to_string()),
+        };
+
+        let pending = self
+            .state
+            .pool
+            .get_pending()
+            .into_iter()
+            .find(|pending| pending.id == id);
+        if let Some(pending) = pending {
+            let location = TransactionLocation::Pending {
+                age_ms: pending.age_ms,
+            };
+            return self.transaction_object(field, &pending.transaction, id, &location);
+        }
+
+        let block = self
+            .state
+            .blockchain
+            .find_transaction_block(id)
+            .and_then(|header| self.state.blockchain.get_block(header.hash));
+        let confirmed = block.and_then(|block| {
+            let transaction = block
+                .transactions
+                .into_iter()
+                .find(|transaction| transaction.calculate_id() == id)?;
+            Some((transaction, block.header.hash))
+        });
+
+        nullable(confirmed, |(transaction, block_hash)| {
+            let location = TransactionLocation::Confirmed { block_hash };
+            self.transaction_object(field, &transaction, id, &location)
+        })
+    }
+
+    fn mempool(&self, field: &Field) -> ExecutionResult {
+        self.arguments(field, &[])?;
+        let pending = self.state.pool.get_pending();
+
+        list(pending.iter(), |pending| {
+            let location = TransactionLocation::Pending {
+                age_ms: pending.age_ms,
+            };
+            self.transaction_object(field, &pending.transaction, pending.id, &location)
+        })
+    }
+
+    fn block_object(&self, field: &Field, block: &Block) -> ExecutionResult {
+        object("Block", subfields(field, "Block")?, |field| {
+            match field.name.as_str() {
+                "index" => scalar(field, block.header.index),
+                "timestamp" => scalar(field, block.header.timestamp),
+                "nonce" => scalar(field, block.header.nonce),
+                "bits" => scalar(field, block.header.bits),
+                "previousHash" => scalar(field, hash_hex::to_hex(&block.header.previous_hash)),
+                "hash" => scalar(field, hash_hex::to_hex(&block.header.hash)),
+                "merkleRoot" => scalar(field, hash_hex::to_hex(&block.header.merkle_root)),
+                "stateRoot" => scalar(field, hash_hex::to_hex(&block.header.state_root)),
+                "bloom" => scalar(field, block.header.bloom),
+                "signature" => scalar(field, &block.signature),
+                "transactionCount" => scalar(field, block.transactions.len()),
+                "pruned" => scalar(field, block.pruned),
+                "transactions" => {
+                    let location = TransactionLocation::Confirmed {
+                        block_hash: block.header.hash,
+                    };
+                    list(block.transactions.iter(), |transaction| {
+                        let id = transaction.calculate_id();
+                        self.transaction_object(field, transaction, id, &location)
+                    })
+                }
+                _ => Err(unknown_field(field, "Block")),
+            }
+        })
+    }
+
+    fn transaction_object(
+        &self,
+        field: &Field,
+        transaction: &Transaction,
+        id: TransactionId,
+        location: &TransactionLocation,
+    ) -> ExecutionResult {
+        let selection = subfields(field, "Transaction")?;
+        object("Transaction", selection, |field| {
+            match field.name.as_str() {
+                "id" => scalar(field, hash_hex::to_hex(&id)),
+                "sender" => self.address(field, &transaction.sender),
+                "recipient" => self.address(field, &transaction.recipient),
+                "amount" => scalar(field, transaction.amount),
+                "signature" => scalar(field, &transaction.signature),
+                "status" => match location {
+                    TransactionLocation::Pending { .. } => scalar(field, "PENDING"),
+                    TransactionLocation::Confirmed { .. } => scalar(field, "CONFIRMED"),
+                },
+                "ageMs" => match location {
+                    TransactionLocation::Pending { age_ms } => scalar(field, age_ms),
+                    TransactionLocation::Confirmed { .. } => scalar(field, JsonValue::Null),
+                },
+                "block" => match location {
+                    TransactionLocation::Pending { .. } => Ok(JsonValue::Null),
+                    TransactionLocation::Confirmed { block_hash } => {
+                        let block = self.state.blockchain.get_block(*block_hash);
+                        nullable(block, |block| self.block_object(field, &block))
+                    }
+                },
+                _ => Err(unknown_field(field, "Transaction")),
+            }
+        })
+    }
+
+    fn address(&self, field: &Field, address: &str) -> ExecutionResult {
+        object(
+            "Address",
+            subfields(field, "Address")?,
+            |field| match field.name.as_str() {
+                "address" => scalar(field, address),
+                "confirmed" => balance_object(field, &self.balances_of(address).0),
+                "pending" => balance_object(field, &self.balances_of(address).1),
+                _ => Err(unknown_field(field, "Address")),
+            },
+        )
+    }
+
+    fn balances_of(&self, address: &str) -> (AddressBalance, AddressBalance) {
+        let mut balances = self.balances.borrow_mut();
+        balances
+            .entry(address.to_string())
+            .or_insert_with(|| {
+                wallet::address_balance(address, &self.state.blockchain, &self.state.pool)
+            })
+            .clone()
+    }
+
+    // Values of the arguments of the field, with the variables already replaced
+    fn arguments(&self, field: &Field, allowed: &[&str]) -> Result<Map<String, JsonValue>, String> {
+        let mut arguments = Map::new();
+        for (name, value) in &field.arguments {
+            if !allowed.contains(&name.as_str()) {
+                let message = format!("Unknown argument \"{}\" on field \"{}\"", name, field.name);
+                return Err(message);
+            }
+            arguments.insert(name.clone(), value.resolve(&self.variables)?);
+        }
+
+        Ok(arguments)
+    }
+}
+
+fn balance_object(field: &Field, balance: &AddressBalance) -> ExecutionResult {
+    object(
+        "Balance",
+        subfields(field, "Balance")?,
+        |field| match field.name.as_str() {
+            "received" => scalar(field, balance.received),
+            "sent" => scalar(field, balance.sent),
+            // values don't fit in 64 bits only with absurd amounts, which can still be told apart
+            "balance" => match i64::try_from(balance.balance()) {
+                Ok(value) => scalar(field, value),
+                Err(_) => scalar(field, balance.balance() as f64),
+            },
+            _ => Err(unknown_field(field, "Balance")),
+        },
+    )
+}
+
+// Resolves the selected fields of an object, in the same order as they were requested
+fn object<F>(type_name: &str, selection: &[Field], mut resolve: F) -> ExecutionResult
+where
+    F: FnMut(&Field) -> ExecutionResult,
+{
+    let mut values = Map::new();
+    for field in selection {
+        let value = match field.name.as_str() {
+            "__typename" => scalar(field, type_name)?,
+            _ => resolve(field)?,
+        };
+        values.insert(field.response_key().to_string(), value);
+    }
+
+    Ok(JsonValue::Object(values))
+}
+
+fn list<T, I, F>(items: I, resolve: F) -> ExecutionResult
+where
+    I: Iterator<Item = T>,
+    F: FnMut(T) -> ExecutionResult,
+{
+    let values = items.map(resolve).collect::<Result<_, _>>()?;
+
+    Ok(JsonValue::Array(values))
+}
+
+fn nullable<T, F>(item: Option<T>, resolve: F) -> ExecutionResult
+where
+    F: FnOnce(T) -> ExecutionResult,
+{
+    item.map(resolve).unwrap_or(Ok(JsonValue::Null))
+}
+
+// Values of scalar types are serialized like in the rest of the api (e.g. hashes as hex strings)
+fn scalar<T: Serialize>(field: &Field, value: T) -> ExecutionResult {
+    if !field.selection.is_empty() {
+        return Err(format!("Field \"{}\" can't have subfields", field.name));
+    }
+
+    serde_json::to_value(value).map_err(|error| error.to_string())
+}
+
+// Fields of object types must select some of their subfields
+fn subfields<'f>(field: &'f Field, type_name: &str) -> Result<&'f [Field], String> {
+    if field.selection.is_empty() {
+        let message = format!(
+            "Field \"{}\" of type \"{}\" must have a selection of subfields",
+            field.name, type_name
+        );
+        return Err(message);
+    }
+
+    Ok(&field.selection)
+}
+
+fn unknown_field(field: &Field, type_name: &str) -> String {
+    format!("Unknown field \"{}\" on type \"{}\"", field.name, type_name)
+}
+
+fn u64_argument(arguments: &Map<String, JsonValue>, name: &str) -> Result<Option<u64>, String> {
+    match arguments.get(name) {
+        None | Some(JsonValue::Null) => Ok(None),
+        Some(value) => value
+            .as_u64()
+            .map(Some)
+            .ok_or_else(|| format!("Argument \"{}\" must be a positive integer", name)),
+    }
+}
+
+fn string_argument(
+    arguments: &Map<String, JsonValue>,
+    name: &str,
+) -> Result<Option<String>, String> {
+    match arguments.get(name) {
+        None | Some(JsonValue::Null) => Ok(None),
+        Some(JsonValue::String(value)) => Ok(Some(value.clone())),
+        Some(_) => Err(format!("Argument \"{}\" must be a string", name)),
+    }
+}
+
+fn parse_hash(hash: &str) -> Result<BlockHash, String> {
+    hash_hex::parse_hex(hash).ok_or_else(|| format!("Invalid hash {}", hash))
+}
@@ -0,0 +1,516 @@
+use std::{iter::Peekable, str::Chars};
+
+use serde_json::{Map, Number, Value as JsonValue};
+
+// Only the parts of GraphQL that explorers need are supported:
+// queries with nested fields, aliases, arguments and variables.
+// Mutations, subscriptions, fragments and directives are rejected with an error
+type ParseResult<T> = Result<T, String>;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Punctuator(char),
+    Spread,
+    Name(String),
+    Int(i64),
+    Float(f64),
+    String(String),
+}
+
+// A value written in the query, which may still refer to the variables of the request
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Boolean(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+    Enum(String),
+    List(Vec<Value>),
+    Object(Vec<(String, Value)>),
+    Variable(String),
+}
+
+impl Value {
+    // Replaces the variables by their values, to get a plain JSON value
+    pub fn resolve(&self, variables: &Map<String, JsonValue>) -> ParseResult<JsonValue> {
+        let value = match self {
+            Value::Null => JsonValue::Null,
+            Value::Boolean(value) => JsonValue::Bool(*value),
+            Value::Int(value) => JsonValue::from(*value),
+            Value::Float(value) => Number::from_f64(*value)
+                .map(JsonValue::Number)
+                .unwrap_or(JsonValue::Null),
+            Value::String(value) | Value::Enum(value) => JsonValue::String(value.clone()),
+            Value::List(values) => JsonValue::Array(
+                values
+                    .iter()
+                    .map(|value| value.resolve(variables))
+                    .collect::<ParseResult<_>>()?,
+            ),
+            Value::Object(fields) => JsonValue::Object(
+                fields
+                    .iter()
+                    .map(|(name, value)| Ok((name.clone(), value.resolve(variables)?)))
+                    .collect::<ParseResult<_>>()?,
+            ),
+            Value::Variable(name) => match variables.get(name) {
+                Some(value) => value.clone(),
+                None => return Err(format!("Variable \"${}\" is not defined", name)),
+            },
+        };
+
+        Ok(value)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Field {
+    pub alias: Option<String>,
+    pub name: String,
+    pub arguments: Vec<(String, Value)>,
+    // empty for fields of scalar types
+    pub selection: Vec<Field>,
+}
+
+impl Field {
+    // Name of the field in the response, which can be renamed with an alias
+    pub fn response_key(&self) -> &str {
+        self.alias.as_deref().unwrap_or(&self.name)
+    }
+
+    // Number of nested levels of fields, including this one
+    pub fn depth(&self) -> usize {
+        1 + self.selection.iter().map(Field::depth).max().unwrap_or(0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VariableDefinition {
+    pub name: String,
+    pub required: bool,
+    pub default_value: Option<Value>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Operation {
+    pub name: Option<String>,
+    pub variables: Vec<VariableDefinition>,
+    pub selection: Vec<Field>,
+}
+
+// Parses a whole document, which may contain several operations
+pub fn parse_document(source: &str) -> ParseResult<Vec<Operation>> {
+    let tokens = tokenize(source)?;
+    let mut parser = Parser {
+        tokens,
+        position: 0,
+    };
+
+    let mut operations = Vec::new();
+    while parser.peek().is_some() {
+        operations.push(parser.parse_operation()?);
+    }
+    if operations.is_empty() {
+        return Err("The document does not contain any operation".to_string());
+    }
+
+    Ok(operations)
+}
+
+fn tokenize(source: &str) -> ParseResult<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            // commas are insignificant, like whitespace
+            ' ' | '\t' | '\n' | '\r' | ',' | '\u{feff}' => {
+                chars.next();
+            }
+            '#' => while chars.next_if(|&c| c != '\n' && c != '\r').is_some() {},
+            '!' | '$' | '(' | ')' | ':' | '=' | '@' | '[' | ']' | '{' | '}' | '|' => {
+                chars.next();
+                tokens.push(Token::Punctuator(c));
+            }
+            '.' => {
+                let dots: String = (0..3).filter_map(|_| chars.next_if_eq(&'.')).collect();
+                if dots != "..." {
+                    return Err("Unexpected \".\"".to_string());
+                }
+                tokens.push(Token::Spread);
+            }
+            '"' => tokens.push(Token::String(read_string(&mut chars)?)),
+            '-' | '0'..='9' => tokens.push(read_number(&mut chars)?),
+            c if c == '_' || c.is_ascii_alphabetic() => {
+                let mut name = String::new();
+                while let Some(c) = chars.next_if(|&c| c == '_' || c.is_ascii_alphanumeric()) {
+                    name.push(c);
+                }
+                tokens.push(Token::Name(name));
+            }
+            c => return Err(format!("Unexpected character \"{}\"", c)),
+        }
+    }
+
+    Ok(tokens)
+}
+
+fn read_string(chars: &mut Peekable<Chars>) -> ParseResult<String> {
+    chars.next();
+    if chars.peek() == Some(&'"') {
+        chars.next();
+        // block strings start with three quotes
+        if chars.peek() == Some(&'"') {
+            return Err("Block strings are not supported".to_string());
+        }
+        return Ok(String::new());
+    }
+
+    let mut value = String::new();
+    loop {
+        match chars.next() {
+            Some('"') => return Ok(value),
+            Some('\\') => {
+                let escaped = match chars.next() {
+                    Some('"') => '"',
+                    Some('\\') => '\\',
+                    Some('/') => '/',
+                    Some('b') => '\u{8}',
+                    Some('f') => '\u{c}',
+                    Some('n') => '\n',
+                    Some('r') => '\r',
+                    Some('t') => '\t',
+                    Some('u') => {
+                        let code: String = chars.by_ref().take(4).collect();
+                        u32::from_str_radix(&code, 16)
+                            .ok()
+                            .and_then(char::from_u32)
+                            .ok_or_else(|| format!("Invalid unicode escape \"\\u{}\"", code))?
+                    }
+                    _ => return Err("Invalid escape sequence in string".to_string()),
+                };
+                value.push(escaped);
+            }
+            Some('\n') | Some('\r') | None => return Err("Unterminated string".to_string()),
+            Some(c) => value.push(c),
+        }
+    }
+}
+
+fn read_number(chars: &mut Peekable<Chars>) -> ParseResult<Token> {
+    let mut number = String::new();
+    let mut is_float = false;
+    while let Some(c) = chars.next_if(|&c| {
+        c.is_ascii_digit() || c == '-' || c == '+' || c == '.' || c == 'e' || c == 'E'
+    }) {
+        is_float |= c == '.' || c == 'e' || c == 'E';
+        number.push(c);
+    }
+
+    let invalid = || format!("Invalid number \"{}\"", number);
+    if is_float {
+        number.parse().map(Token::Float).map_err(|_| invalid())
+    } else {
+        number.parse().map(Token::Int).map_err(|_| invalid())
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> ParseResult<Token> {
+        let token = self
+            .tokens
+            .get(self.position)
+            .cloned()
+            .ok_or_else(|| "Unexpected end of the document".to_string())?;
+        self.position += 1;
+
+        Ok(token)
+    }
+
+    fn is_next(&self, punctuator: char) -> bool {
+        self.peek() == Some(&Token::Punctuator(punctuator))
+    }
+
+    fn skip(&mut self, punctuator: char) -> bool {
+        let is_next = self.is_next(punctuator);
+        if is_next {
+            self.position += 1;
+        }
+
+        is_next
+    }
+
+    fn expect(&mut self, punctuator: char) -> ParseResult<()> {
+        match self.next()? {
+            Token::Punctuator(c) if c == punctuator => Ok(()),
+            token => Err(unexpected(&token, &format!("\"{}\"", punctuator))),
+        }
+    }
+
+    fn expect_name(&mut self) -> ParseResult<String> {
+        match self.next()? {
+            Token::Name(name) => Ok(name),
+            token => Err(unexpected(&token, "a name")),
+        }
+    }
+
+    fn parse_operation(&mut self) -> ParseResult<Operation> {
+        // the query shorthand, without the "query" keyword
+        if self.is_next('{') {
+            return Ok(Operation {
+                name: None,
+                variables: Vec::new(),
+                selection: self.parse_selection_set()?,
+            });
+        }
+
+        match self.expect_name()?.as_str() {
+            "query" => {}
+            "mutation" | "subscription" => {
+                return Err("Only queries are supported, the node is read only".to_string())
+            }
+            "fragment" => return Err("Fragments are not supported".to_string()),
+            name => return Err(format!("Unexpected \"{}\"", name)),
+        }
+
+        let name = match self.peek() {
+            Some(Token::Name(_)) => Some(self.expect_name()?),
+            _ => None,
+        };
+        let variables = if self.is_next('(') {
+            self.parse_variable_definitions()?
+        } else {
+            Vec::new()
+        };
+        self.reject_directives()?;
+
+        Ok(Operation {
+            name,
+            variables,
+            selection: self.parse_selection_set()?,
+        })
+    }
+
+    fn parse_variable_definitions(&mut self) -> ParseResult<Vec<VariableDefinition>> {
+        self.expect('(')?;
+        let mut definitions = Vec::new();
+        while !self.skip(')') {
+            self.expect('$')?;
+            let name = self.expect_name()?;
+            self.expect(':')?;
+            let required = self.parse_type()?;
+            let default_value = if self.skip('=') {
+                Some(self.parse_value()?)
+            } else {
+                None
+            };
+
+            definitions.push(VariableDefinition {
+                name,
+                required,
+                default_value,
+            });
+        }
+
+        Ok(definitions)
+    }
+
+    // The types of the variables are not checked, the arguments are checked when used
+    // Returns whether the type is non-null
+    fn parse_type(&mut self) -> ParseResult<bool> {
+        if self.skip('[') {
+            self.parse_type()?;
+            self.expect(']')?;
+        } else {
+            self.expect_name()?;
+        }
+
+        Ok(self.skip('!'))
+    }
+
+    fn parse_selection_set(&mut self) -> ParseResult<Vec<Field>> {
+        self.expect('{')?;
+        let mut selection = Vec::new();
+        while !self.skip('}') {
+            if self.peek() == Some(&Token::Spread) {
+                return Err("Fragments are not supported".to_string());
+            }
+            selection.push(self.parse_field()?);
+        }
+        if selection.is_empty() {
+            return Err("Selection sets can't be empty".to_string());
+        }
+
+        Ok(selection)
+    }
+
+    fn parse_field(&mut self) -> ParseResult<Field> {
+        let mut alias = None;
+        let mut name = self.expect_name()?;
+        if self.skip(':') {
+            alias = Some(name);
+            name = self.expect_name()?;
+        }
+
+        let mut arguments = Vec::new();
+        if self.skip('(') {
+            while !self.skip(')') {
+                let name = self.expect_name()?;
+                self.expect(':')?;
+                arguments.push((name, self.parse_value()?));
+            }
+        }
+        self.reject_directives()?;
+
+        let selection = if self.is_next('{') {
+            self.parse_selection_set()?
+        } else {
+            Vec::new()
+        };
+
+        Ok(Field {
+            alias,
+            name,
+            arguments,
+            selection,
+        })
+    }
+
+    fn parse_value(&mut self) -> ParseResult<Value> {
+        let value = match self.next()? {
+            Token::Punctuator('$') => Value::Variable(self.expect_name()?),
+            Token::Int(value) => Value::Int(value),
+            Token::Float(value) => Value::Float(value),
+            Token::String(value) => Value::String(value),
+            Token::Name(name) => match name.as_str() {
+                "true" => Value::Boolean(true),
+                "false" => Value::Boolean(false),
+                "null" => Value::Null,
+                _ => Value::Enum(name),
+            },
+            Token::Punctuator('[') => {
+                let mut values = Vec::new();
+                while !self.skip(']') {
+                    values.push(self.parse_value()?);
+                }
+                Value::List(values)
+            }
+            Token::Punctuator('{') => {
+                let mut fields = Vec::new();
+                while !self.skip('}') {
+                    let name = self.expect_name()?;
+                    self.expect(':')?;
+                    fields.push((name, self.parse_value()?));
+                }
+                Value::Object(fields)
+            }
+            token => return Err(unexpected(&token, "a value")),
+        };
+
+        Ok(value)
+    }
+
+    fn reject_directives(&self) -> ParseResult<()> {
+        if self.is_next('@') {
+            return Err("Directives are not supported".to_string());
+        }
+
+        Ok(())
+    }
+}
+
+fn unexpected(token: &Token, expected: &str) -> String {
+    let found = match token {
+        Token::Punctuator(c) => format!("\"{}\"", c),
+        Token::Spread => "\"...\"".to_string(),
+        Token::Name(name) => format!("\"{}\"", name),
+        Token::Int(value) => value.to_string(),
+        Token::Float(value) => value.to_string(),
+        Token::String(value) => format!("{:?}", value),
+    };
+
+    format!("Expected {}, found {}", expected, found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field(name: &str, selection: Vec<Field>) -> Field {
+        Field {
+            alias: None,
+            name: name.to_string(),
+            arguments: Vec::new(),
+            selection,
+        }
+    }
+
+    #[test]
+    fn should_parse_nested_fields() {
+        let operations =
+            parse_document("{ block(index: 1) { hash tx: transactions { amount } } }").unwrap();
+
+        let mut transactions = field("transactions", vec![field("amount", Vec::new())]);
+        transactions.alias = Some("tx".to_string());
+        let mut block = field("block", vec![field("hash", Vec::new()), transactions]);
+        block.arguments = vec![("index".to_string(), Value::Int(1))];
+        assert_eq!(operations[0].selection, vec![block]);
+        assert_eq!(operations[0].selection[0].depth(), 3);
+    }
+
+    #[test]
+    fn should_parse_variables() {
+        let source = r#"
+            # comments are ignored
+            query Blocks($from: Int!, $order: BlockOrder = DESC) {
+                blocks(from: $from, order: $order, hashes: ["0x1", "a\"b"]) { index }
+            }
+        "#;
+        let operation = parse_document(source).unwrap().remove(0);
+
+        assert_eq!(operation.name, Some("Blocks".to_string()));
+        assert_eq!(
+            operation.variables,
+            vec![
+                VariableDefinition {
+                    name: "from".to_string(),
+                    required: true,
+                    default_value: None,
+                },
+                VariableDefinition {
+                    name: "order".to_string(),
+                    required: false,
+                    default_value: Some(Value::Enum("DESC".to_string())),
+                },
+            ]
+        );
+
+        let arguments = &operation.selection[0].arguments;
+        assert_eq!(arguments[0].1, Value::Variable("from".to_string()));
+        let hashes = Value::List(vec![
+            Value::String("0x1".to_string()),
+            Value::String("a\"b".to_string()),
+        ]);
+        assert_eq!(arguments[2].1, hashes);
+    }
+
+    #[test]
+    fn should_reject_unsupported_documents() {
+        assert!(parse_document("mutation { mine }").is_err());
+        assert!(parse_document("{ block { ...BlockFields } }").is_err());
+        assert!(parse_document("{ block @skip(if: true) { hash } }").is_err());
+        assert!(parse_document("{ block { hash }").is_err());
+        assert!(parse_document("{ }").is_err());
+        assert!(parse_document("").is_err());
+    }
+}
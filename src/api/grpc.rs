@@ -0,0 +1,536 @@
+mod messages;
+mod protobuf;
+
+use std::{collections::BTreeMap, convert::TryFrom, net::IpAddr, time::Duration};
+
+use actix_web::web;
+use anyhow::Result;
+use bytes::{BufMut, Bytes, BytesMut};
+use futures::future::{self, Either};
+use h2::{
+    server::{self, SendResponse},
+    RecvStream, SendStream,
+};
+use http::{HeaderMap, HeaderValue, Request, Response};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    time::delay_for,
+};
+
+use self::protobuf::{Encoder, ProtobufError};
+use super::{ApiError, ApiState, BlockId, ChainPoint, TransactionStatusResponse, MAX_BLOCKS_LIMIT};
+use crate::model::{Block, BlockHash, Blockchain};
+
+// Every call of the service is sent to "/<package>.<service>/<method>"
+const SERVICE_PATH: &str = "/blockchain.v1.Node/";
+
+// Metadata (http/2 header) where clients send the api key, as gRPC metadata keys are lowercase
+const API_KEY_METADATA: &str = "x-api-key";
+
+// Same default limit as the official gRPC implementations
+const MAX_MESSAGE_BYTES: usize = 4 * 1024 * 1024;
+
+// Time interval to check for new blocks while streaming them
+const STREAM_CHECK_MS: u64 = 100;
+
+// Number of streamed blocks remembered to detect reorgs, deeper reorgs resend everything remembered
+const MAX_TRACKED_BLOCKS: usize = 1000;
+
+// Time to wait after a failed accept (e.g. too many open files), so the listener doesn't spin on it
+const ACCEPT_RETRY_MS: u64 = 100;
+
+// Status codes of gRPC, sent in the "grpc-status" trailer
+// See https://grpc.github.io/grpc/core/md_doc_statuscodes.html
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Code {
+    Ok = 0,
+    InvalidArgument = 3,
+    NotFound = 5,
+    AlreadyExists = 6,
+    ResourceExhausted = 8,
+    Unimplemented = 12,
+    Internal = 13,
+    Unavailable = 14,
+    Unauthenticated = 16,
+}
+
+#[derive(Debug)]
+struct Status {
+    code: Code,
+    message: String,
+}
+
+impl Status {
+    fn new(code: Code, message: &str) -> Status {
+        Status {
+            code,
+            message: message.to_string(),
+        }
+    }
+}
+
+// The errors of the api keep their meaning, so clients can handle them the same way
+impl From<ApiError> for Status {
+    fn from(error: ApiError) -> Self {
+        let code = match error {
+            ApiError::BadRequest(_) => Code::InvalidArgument,
+            ApiError::Unauthorized(_) => Code::Unauthenticated,
+            ApiError::NotFound(_) => Code::NotFound,
+            ApiError::Conflict(_) => Code::AlreadyExists,
+            ApiError::TooManyRequests(_) | ApiError::PayloadTooLarge(_) => Code::ResourceExhausted,
+            ApiError::Unavailable(_) => Code::Unavailable,
+            ApiError::Internal(_) => Code::Internal,
+        };
+
+        Status::new(code, &error.to_string())
+    }
+}
+
+impl From<ProtobufError> for Status {
+    fn from(error: ProtobufError) -> Self {
+        Status::new(Code::InvalidArgument, &error.to_string())
+    }
+}
+
+impl From<h2::Error> for Status {
+    fn from(error: h2::Error) -> Self {
+        Status::new(Code::Internal, &error.to_string())
+    }
+}
+
+// Serves the gRPC api of "proto/node.proto" until the shutdown drains the node
+// It runs in the runtime of the node, each connection as its own task
+pub async fn serve(
+    listen_addresses: &[String],
+    port: u16,
+    state: web::Data<ApiState>,
+) -> Result<()> {
+    let mut listeners = Vec::new();
+    for address in grpc_addresses(listen_addresses, port) {
+        listeners.push(TcpListener::bind(&address).await?);
+        info!("gRPC api listening on {}", address);
+    }
+
+    // new connections are refused once draining starts, open streams end on their own
+    let accepting = listeners
+        .into_iter()
+        .map(|listener| accept_connections(listener, state.clone()));
+    future::select(
+        Box::pin(future::join_all(accepting)),
+        Box::pin(state.shutdown.draining()),
+    )
+    .await;
+    info!("stopping the gRPC api");
+
+    Ok(())
+}
+
+// The gRPC api listens on the same hosts as the REST api, but on its own port
+fn grpc_addresses(listen_addresses: &[String], port: u16) -> Vec<String> {
+    let mut addresses = Vec::new();
+    for address in listen_addresses {
+        let host = address
+            .rsplit_once(':')
+            .map_or(address.as_str(), |(host, _)| host);
+        let address = format!("{}:{}", host, port);
+        if !addresses.contains(&address) {
+            addresses.push(address);
+        }
+    }
+
+    addresses
+}
+
+async fn accept_connections(mut listener: TcpListener, state: web::Data<ApiState>) {
+    loop {
+        // a failed accept only affects that connection, the rest of the clients are still served
+        let (socket, address) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(error) => {
+                warn!("gRPC api could not accept a connection: {}", error);
+                delay_for(Duration::from_millis(ACCEPT_RETRY_MS)).await;
+                continue;
+            }
+        };
+
+        let state = state.clone();
+        tokio::spawn(async move {
+            if let Err(error) = serve_connection(socket, address.ip(), state).await {
+                debug!("gRPC connection from {} failed: {}", address, error);
+            }
+        });
+    }
+}
+
+async fn serve_connection(
+    socket: TcpStream,
+    ip: IpAddr,
+    state: web::Data<ApiState>,
+) -> Result<(), h2::Error> {
+    let mut connection = server::handshake(socket).await?;
+
+    // calls are handled concurrently, so a stream of blocks doesn't block the rest
+    while let Some(call) = connection.accept().await {
+        let (request, respond) = call?;
+        tokio::spawn(handle_call(request, respond, ip, state.clone()));
+    }
+
+    Ok(())
+}
+
+async fn handle_call(
+    request: Request<RecvStream>,
+    mut respond: SendResponse<Bytes>,
+    ip: IpAddr,
+    state: web::Data<ApiState>,
+) {
+    let (parts, body) = request.into_parts();
+    let method = parts.uri.path().strip_prefix(SERVICE_PATH).unwrap_or("");
+    let key = parts
+        .headers
+        .get(API_KEY_METADATA)
+        .and_then(|value| value.to_str().ok());
+
+    let message = match check_call(&state, ip, method, key) {
+        Ok(()) => read_message(body).await,
+        Err(status) => Err(status),
+    };
+    let message = match message {
+        Ok(message) => message,
+        Err(status) => return send_error(&mut respond, status),
+    };
+
+    let result = match method {
+        "SubmitTransaction" => submit_transaction(&state, &message),
+        "GetStatus" => Ok(messages::encode_status(&super::node_status(&state))),
+        "GetBlock" => get_block(&state, &message),
+        "GetTransaction" => get_transaction(&state, &message),
+        "GetBalance" => messages::decode_balance_request(&message)
+            .map_err(Status::from)
+            .and_then(|address| {
+                let balance = super::address_balance(&state, &address, ChainPoint::Latest)?;
+                Ok(messages::encode_balance(&balance))
+            }),
+        "StreamBlocks" => {
+            match messages::decode_stream_request(&message) {
+                Ok(from_index) => stream_blocks(&state, respond, from_index).await,
+                Err(error) => send_error(&mut respond, error.into()),
+            }
+            return;
+        }
+        _ => unreachable!("unknown methods are rejected before reading the message"),
+    };
+
+    match result {
+        Ok(response) => send_unary(&mut respond, response),
+        Err(status) => send_error(&mut respond, status),
+    }
+}
+
+// Same checks as the REST api: no writes while draining, the api key and the rate limits
+fn check_call(state: &ApiState, ip: IpAddr, method: &str, key: Option<&str>) -> Result<(), Status> {
+    let is_read = match method {
+        "SubmitTransaction" => false,
+        "GetStatus" | "GetBlock" | "GetTransaction" | "GetBalance" | "StreamBlocks" => true,
+        _ => {
+            let message = format!("Unknown method \"{}\"", method);
+            return Err(Status::new(Code::Unimplemented, &message));
+        }
+    };
+
+    if state.shutdown.is_draining() && !is_read {
+        return Err(ApiError::Unavailable(super::SHUTTING_DOWN.to_string()).into());
+    }
+
+    state.auth.check_key(is_read, key)?;
+    if !is_read {
+        state.rate_limiter.check_client(ip)?;
+    }
+
+    Ok(())
+}
+
+fn submit_transaction(state: &ApiState, message: &[u8]) -> Result<Encoder, Status> {
+    let transaction = messages::decode_transaction(message)?;
+    let id = super::submit_transaction(state, transaction)?;
+
+    Ok(messages::encode_submit_transaction_response(id))
+}
+
+fn get_block(state: &ApiState, message: &[u8]) -> Result<Encoder, Status> {
+    let block = match messages::decode_block_request(message)? {
+        BlockId::Index(index) => state.blockchain.get_block_at(index),
+        BlockId::Hash(hash) => state.blockchain.get_block(hash),
+    };
+
+    match block {
+        Some(block) => Ok(messages::encode_block(&block)),
+        None => Err(Status::new(Code::NotFound, "Block not found")),
+    }
+}
+
+fn get_transaction(state: &ApiState, message: &[u8]) -> Result<Encoder, Status> {
+    let id = messages::decode_transaction_request(message)?;
+    let status = super::transaction_status(state, id)?;
+
+    // unlike the REST api, the response includes the transaction when it's already in a block
+    let transaction = match &status {
+        TransactionStatusResponse::Pending(pending) => Some(pending.transaction.clone()),
+        TransactionStatusResponse::Confirmed { block_index, .. } => state
+            .blockchain
+            .get_block_at(*block_index)
+            .and_then(|block| {
+                block
+                    .transactions
+                    .into_iter()
+                    .find(|transaction| transaction.calculate_id() == id)
+            }),
+    };
+
+    match transaction {
+        Some(transaction) => Ok(messages::encode_transaction_status(&transaction, &status)),
+        // the block was replaced by a reorg right after finding it
+        None => Err(Status::new(Code::NotFound, "Transaction not found")),
+    }
+}
+
+// Sends the new blocks of the chain until the client cancels the call or the node shuts down
+async fn stream_blocks(
+    state: &ApiState,
+    mut respond: SendResponse<Bytes>,
+    from_index: Option<u64>,
+) {
+    let first_index =
+        from_index.unwrap_or_else(|| state.blockchain.get_last_block().header.index + 1);
+    let mut cursor = BlockCursor::new(first_index);
+    let mut tip = state.blockchain.watch_tip();
+
+    let mut stream = match respond.send_response(response_headers(), false) {
+        Ok(stream) => stream,
+        Err(_) => return,
+    };
+
+    loop {
+        // marked as seen before reading the blocks, so a change in the meantime is not missed
+        tip.borrow_and_update();
+        let blocks = cursor.next_blocks(&state.blockchain);
+        for block in &blocks {
+            let message = messages::encode_block(block).into_bytes();
+            if stream.send_data(frame(&message), false).is_err() {
+                return;
+            }
+        }
+
+        // a long chain is sent in batches, without waiting for new blocks
+        if blocks.len() as u64 >= MAX_BLOCKS_LIMIT {
+            continue;
+        }
+
+        while !tip.has_changed() {
+            if state.shutdown.is_draining() {
+                let _ = stream.send_trailers(trailers(Code::Ok, ""));
+                return;
+            }
+
+            if !wait_unless_reset(&mut stream).await {
+                return;
+            }
+        }
+    }
+}
+
+// Returns false if the client reset the stream (e.g. cancelled the call) while waiting
+async fn wait_unless_reset(stream: &mut SendStream<Bytes>) -> bool {
+    let delay = delay_for(Duration::from_millis(STREAM_CHECK_MS));
+    let reset = future::poll_fn(|cx| stream.poll_reset(cx));
+
+    matches!(future::select(delay, reset).await, Either::Left(_))
+}
+
+// Position of a client in the chain while streaming blocks
+// It remembers the hashes of the last blocks sent, so blocks replaced by a reorg are sent again
+struct BlockCursor {
+    next_index: u64,
+    sent: BTreeMap<u64, BlockHash>,
+}
+
+impl BlockCursor {
+    fn new(first_index: u64) -> BlockCursor {
+        BlockCursor {
+            next_index: first_index,
+            sent: BTreeMap::new(),
+        }
+    }
+
+    fn next_blocks(&mut self, blockchain: &Blockchain) -> Vec<Block> {
+        // go back to the first block that is no longer in the chain
+        while let Some((&index, &hash)) = self.sent.iter().next_back() {
+            if blockchain
+                .get_block_at(index)
+                .map(|block| block.header.hash)
+                == Some(hash)
+            {
+                break;
+            }
+
+            self.sent.remove(&index);
+            self.next_index = index;
+        }
+
+        let last_index = blockchain.get_last_block().header.index;
+        if self.next_index > last_index {
+            return Vec::new();
+        }
+
+        let last_index = last_index.min(self.next_index + MAX_BLOCKS_LIMIT - 1);
+        let blocks = blockchain.get_blocks_between(self.next_index, last_index);
+        for block in &blocks {
+            self.sent.insert(block.header.index, block.header.hash);
+            self.next_index = block.header.index + 1;
+        }
+
+        while self.sent.len() > MAX_TRACKED_BLOCKS {
+            let oldest = *self.sent.keys().next().unwrap();
+            self.sent.remove(&oldest);
+        }
+
+        blocks
+    }
+}
+
+// Reads the single message of a unary call (or the request of a server streaming call)
+async fn read_message(mut body: RecvStream) -> Result<Vec<u8>, Status> {
+    let mut buffer = Vec::new();
+    while let Some(data) = body.data().await {
+        let data = data?;
+
+        // let the client keep sending
+        let _ = body.flow_control().release_capacity(data.len());
+
+        buffer.extend_from_slice(&data);
+        if buffer.len() > MAX_MESSAGE_BYTES {
+            return Err(Status::new(Code::ResourceExhausted, "Message too large"));
+        }
+    }
+
+    // messages are prefixed by a compression flag and their length (big endian)
+    if buffer.len() < 5 {
+        return Err(Status::new(
+            Code::InvalidArgument,
+            "Missing request message",
+        ));
+    }
+    if buffer[0] != 0 {
+        let message = "Compressed messages are not supported";
+        return Err(Status::new(Code::Unimplemented, message));
+    }
+
+    let length = u32::from_be_bytes([buffer[1], buffer[2], buffer[3], buffer[4]]);
+    if usize::try_from(length).ok() != Some(buffer.len() - 5) {
+        let message = "Expected a single request message";
+        return Err(Status::new(Code::InvalidArgument, message));
+    }
+
+    buffer.drain(..5);
+    Ok(buffer)
+}
+
+fn frame(message: &[u8]) -> Bytes {
+    let mut frame = BytesMut::with_capacity(message.len() + 5);
+    frame.put_u8(0);
+    frame.put_u32(message.len() as u32);
+    frame.put_slice(message);
+    frame.freeze()
+}
+
+fn send_unary(respond: &mut SendResponse<Bytes>, response: Encoder) {
+    let message = response.into_bytes();
+
+    // the client may be gone already, and there is nobody else to tell
+    if let Ok(mut stream) = respond.send_response(response_headers(), false) {
+        if stream.send_data(frame(&message), false).is_ok() {
+            let _ = stream.send_trailers(trailers(Code::Ok, ""));
+        }
+    }
+}
+
+// Errors are sent as a "trailers only" response, with the status in the headers
+fn send_error(respond: &mut SendResponse<Bytes>, status: Status) {
+    let mut response = response_headers();
+    response
+        .headers_mut()
+        .extend(trailers(status.code, &status.message));
+
+    let _ = respond.send_response(response, true);
+}
+
+fn response_headers() -> Response<()> {
+    Response::builder()
+        .status(200)
+        .header("content-type", "application/grpc")
+        .body(())
+        .unwrap()
+}
+
+fn trailers(code: Code, message: &str) -> HeaderMap {
+    let mut trailers = HeaderMap::new();
+    trailers.insert("grpc-status", HeaderValue::from(code as u16));
+    if !message.is_empty() {
+        // only printable ASCII is allowed in the header, the rest is percent-encoded
+        let encoded = percent_encode(message);
+        if let Ok(value) = HeaderValue::from_str(&encoded) {
+            trailers.insert("grpc-message", value);
+        }
+    }
+    trailers
+}
+
+fn percent_encode(message: &str) -> String {
+    message
+        .bytes()
+        .map(|byte| match byte {
+            b' '..=b'~' if byte != b'%' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_percent_encode_status_messages() {
+        assert_eq!(percent_encode("Block not found"), "Block not found");
+        assert_eq!(percent_encode("100% ünique"), "100%25 %C3%BCnique");
+    }
+
+    #[test]
+    fn should_map_api_errors_to_status_codes() {
+        let status = Status::from(ApiError::Unauthorized("Invalid api key".to_string()));
+        assert_eq!(status.code, Code::Unauthenticated);
+        assert_eq!(status.message, "Invalid api key");
+
+        let status = Status::from(ApiError::TooManyRequests(2));
+        assert_eq!(status.code, Code::ResourceExhausted);
+    }
+
+    #[test]
+    fn should_listen_on_the_hosts_of_the_rest_api() {
+        let listen_addresses = vec!["localhost:8000".to_string()];
+        assert_eq!(
+            grpc_addresses(&listen_addresses, 50051),
+            vec!["localhost:50051"]
+        );
+
+        let listen_addresses = vec![
+            "0.0.0.0:8000".to_string(),
+            "[::1]:8000".to_string(),
+            "0.0.0.0:8001".to_string(),
+        ];
+        assert_eq!(
+            grpc_addresses(&listen_addresses, 50051),
+            vec!["0.0.0.0:50051", "[::1]:50051"]
+        );
+    }
+}
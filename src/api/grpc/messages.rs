@@ -0,0 +1,531 @@
+use ethereum_types::U256;
+
+use super::protobuf::{Decoder, Encoder, ProtobufError};
+use crate::{
+    api::{
+        BalanceAmounts, BalanceResponse, BlockId, StatusResponse, TipResponse,
+        TransactionStatusResponse,
+    },
+    model::{
+        Block, BlockHeader, ContractAction, MultiSig, TokenAction, Transaction, TransactionId,
+    },
+};
+
+// Conversions between the messages of "proto/node.proto" (and "proto/types.proto") and the types of the node
+// Field numbers must match the ones in the proto files
+
+pub fn encode_transaction(transaction: &Transaction) -> Encoder {
+    let mut encoder = Encoder::new();
+    encoder
+        .string(1, &transaction.sender)
+        .string(2, &transaction.recipient)
+        .uint64(3, transaction.amount)
+        .string(4, transaction.signature.as_deref().unwrap_or_default());
+    // messages are only written when present, so the other side reads them as not set
+    if let Some(multisig) = &transaction.multisig {
+        encoder.message(5, &encode_multisig(multisig));
+    }
+    if let Some(contract) = &transaction.contract {
+        encoder.message(6, &encode_contract_action(contract));
+    }
+    if let Some(token) = &transaction.token {
+        encoder.message(7, &encode_token_action(token));
+    }
+    if let Some(data) = &transaction.data {
+        encoder.bytes(8, data);
+    }
+    encoder
+}
+
+pub fn decode_transaction(bytes: &[u8]) -> Result<Transaction, ProtobufError> {
+    let mut transaction = Transaction {
+        sender: String::new(),
+        recipient: String::new(),
+        amount: 0,
+        signature: None,
+        multisig: None,
+        contract: None,
+        token: None,
+        data: None,
+    };
+
+    let mut decoder = Decoder::new(bytes);
+    while let Some((field, value)) = decoder.next_field()? {
+        match field {
+            1 => transaction.sender = value.as_string(field)?,
+            2 => transaction.recipient = value.as_string(field)?,
+            3 => transaction.amount = value.as_u64(field)?,
+            // an empty string is the default value, so it means that the transaction is unsigned
+            4 => transaction.signature = Some(value.as_string(field)?).filter(|s| !s.is_empty()),
+            5 => transaction.multisig = Some(decode_multisig(value.as_bytes(field)?)?),
+            6 => transaction.contract = decode_contract_action(value.as_bytes(field)?)?,
+            7 => transaction.token = decode_token_action(value.as_bytes(field)?)?,
+            // like the signature, empty data is the same as no data
+            8 => transaction.data = Some(value.as_bytes(field)?.to_vec()).filter(|d| !d.is_empty()),
+            _ => {}
+        }
+    }
+
+    Ok(transaction)
+}
+
+fn encode_multisig(multisig: &MultiSig) -> Encoder {
+    let mut encoder = Encoder::new();
+    encoder.uint32(1, multisig.threshold as u32);
+    for public_key in &multisig.public_keys {
+        encoder.string(2, public_key);
+    }
+    // maps are sent as repeated entries with the key and the value
+    for (public_key, signature) in &multisig.signatures {
+        let mut entry = Encoder::new();
+        entry.string(1, public_key).string(2, signature);
+        encoder.message(3, &entry);
+    }
+    encoder
+}
+
+fn decode_multisig(bytes: &[u8]) -> Result<MultiSig, ProtobufError> {
+    let mut multisig = MultiSig {
+        threshold: 0,
+        public_keys: Vec::new(),
+        signatures: Default::default(),
+    };
+
+    let mut decoder = Decoder::new(bytes);
+    while let Some((field, value)) = decoder.next_field()? {
+        match field {
+            1 => multisig.threshold = value.as_u64(field)? as usize,
+            2 => multisig.public_keys.push(value.as_string(field)?),
+            3 => {
+                let (mut key, mut signature) = (String::new(), String::new());
+                let mut entry = Decoder::new(value.as_bytes(field)?);
+                while let Some((field, value)) = entry.next_field()? {
+                    match field {
+                        1 => key = value.as_string(field)?,
+                        2 => signature = value.as_string(field)?,
+                        _ => {}
+                    }
+                }
+                multisig.signatures.insert(key, signature);
+            }
+            _ => {}
+        }
+    }
+
+    Ok(multisig)
+}
+
+fn encode_contract_action(action: &ContractAction) -> Encoder {
+    let mut inner = Encoder::new();
+    let field = match action {
+        ContractAction::Deploy {
+            code,
+            input,
+            gas_limit,
+        } => {
+            inner.string(1, code).string(2, input).uint64(3, *gas_limit);
+            1
+        }
+        ContractAction::Call { input, gas_limit } => {
+            inner.string(1, input).uint64(2, *gas_limit);
+            2
+        }
+    };
+
+    let mut encoder = Encoder::new();
+    encoder.message(field, &inner);
+    encoder
+}
+
+// An action without any of the fields of the "oneof" is the same as no action
+fn decode_contract_action(bytes: &[u8]) -> Result<Option<ContractAction>, ProtobufError> {
+    let mut action = None;
+
+    let mut decoder = Decoder::new(bytes);
+    while let Some((field, value)) = decoder.next_field()? {
+        let (mut code, mut input, mut gas_limit) = (String::new(), String::new(), 0);
+        let mut inner = Decoder::new(value.as_bytes(field)?);
+        match field {
+            1 => {
+                while let Some((field, value)) = inner.next_field()? {
+                    match field {
+                        1 => code = value.as_string(field)?,
+                        2 => input = value.as_string(field)?,
+                        3 => gas_limit = value.as_u64(field)?,
+                        _ => {}
+                    }
+                }
+                action = Some(ContractAction::Deploy {
+                    code,
+                    input,
+                    gas_limit,
+                });
+            }
+            2 => {
+                while let Some((field, value)) = inner.next_field()? {
+                    match field {
+                        1 => input = value.as_string(field)?,
+                        2 => gas_limit = value.as_u64(field)?,
+                        _ => {}
+                    }
+                }
+                action = Some(ContractAction::Call { input, gas_limit });
+            }
+            _ => {}
+        }
+    }
+
+    Ok(action)
+}
+
+fn encode_token_action(action: &TokenAction) -> Encoder {
+    let mut inner = Encoder::new();
+    let field = match action {
+        TokenAction::Issue { name, supply } => {
+            inner.string(1, name).uint64(2, *supply);
+            1
+        }
+        TokenAction::Transfer { token_id, amount } => {
+            inner.bytes(1, &hash_bytes(*token_id)).uint64(2, *amount);
+            2
+        }
+    };
+
+    let mut encoder = Encoder::new();
+    encoder.message(field, &inner);
+    encoder
+}
+
+fn decode_token_action(bytes: &[u8]) -> Result<Option<TokenAction>, ProtobufError> {
+    let mut action = None;
+
+    let mut decoder = Decoder::new(bytes);
+    while let Some((field, value)) = decoder.next_field()? {
+        let mut inner = Decoder::new(value.as_bytes(field)?);
+        match field {
+            1 => {
+                let (mut name, mut supply) = (String::new(), 0);
+                while let Some((field, value)) = inner.next_field()? {
+                    match field {
+                        1 => name = value.as_string(field)?,
+                        2 => supply = value.as_u64(field)?,
+                        _ => {}
+                    }
+                }
+                action = Some(TokenAction::Issue { name, supply });
+            }
+            2 => {
+                let (mut token_id, mut amount) = (None, 0);
+                while let Some((field, value)) = inner.next_field()? {
+                    match field {
+                        1 => token_id = Some(parse_hash(value.as_bytes(field)?, field)?),
+                        2 => amount = value.as_u64(field)?,
+                        _ => {}
+                    }
+                }
+                action = Some(TokenAction::Transfer {
+                    token_id: token_id.ok_or(ProtobufError::MissingField(1))?,
+                    amount,
+                });
+            }
+            _ => {}
+        }
+    }
+
+    Ok(action)
+}
+
+// The header has the same field numbers in "BlockHeader" and "Block"
+fn encode_block_header(header: &BlockHeader) -> Encoder {
+    let mut encoder = Encoder::new();
+    encoder
+        .uint64(1, header.index)
+        .int64(2, header.timestamp)
+        .uint64(3, header.nonce)
+        .uint32(4, header.bits)
+        .bytes(5, &hash_bytes(header.previous_hash))
+        .bytes(6, &hash_bytes(header.hash))
+        .bytes(10, &hash_bytes(header.state_root))
+        .bytes(11, &hash_bytes(header.merkle_root))
+        .bytes(12, header.bloom.as_bytes());
+    encoder
+}
+
+pub fn encode_block(block: &Block) -> Encoder {
+    let mut encoder = encode_block_header(&block.header);
+    for transaction in &block.transactions {
+        encoder.message(7, &encode_transaction(transaction));
+    }
+    encoder
+        .string(8, block.signature.as_deref().unwrap_or_default())
+        .uint64(9, block.pruned as u64);
+    encoder
+}
+
+pub fn encode_submit_transaction_response(id: TransactionId) -> Encoder {
+    let mut encoder = Encoder::new();
+    encoder.bytes(1, &hash_bytes(id));
+    encoder
+}
+
+pub fn encode_status(status: &StatusResponse) -> Encoder {
+    let mut encoder = Encoder::new();
+    encoder
+        .string(1, status.version)
+        .uint64(2, status.uptime_secs)
+        .message(3, &encode_tip(&status.latest))
+        .message(4, &encode_tip(&status.safe))
+        .uint64(5, status.finality_depth)
+        .uint32(6, status.next_bits)
+        .uint64(7, status.mempool_size as u64)
+        .uint64(8, status.peer_count as u64)
+        .bytes(9, &hash_bytes(status.total_work));
+    encoder
+}
+
+fn encode_tip(tip: &TipResponse) -> Encoder {
+    let mut encoder = Encoder::new();
+    encoder.uint64(1, tip.index).bytes(2, &hash_bytes(tip.hash));
+    encoder
+}
+
+pub fn decode_block_request(bytes: &[u8]) -> Result<BlockId, ProtobufError> {
+    let mut id = None;
+
+    // in a "oneof" the last field on the wire wins
+    let mut decoder = Decoder::new(bytes);
+    while let Some((field, value)) = decoder.next_field()? {
+        match field {
+            1 => id = Some(BlockId::Index(value.as_u64(field)?)),
+            2 => id = Some(BlockId::Hash(parse_hash(value.as_bytes(field)?, field)?)),
+            _ => {}
+        }
+    }
+
+    id.ok_or(ProtobufError::MissingField(1))
+}
+
+pub fn decode_transaction_request(bytes: &[u8]) -> Result<TransactionId, ProtobufError> {
+    let mut id = None;
+
+    let mut decoder = Decoder::new(bytes);
+    while let Some((field, value)) = decoder.next_field()? {
+        if field == 1 {
+            id = Some(parse_hash(value.as_bytes(field)?, field)?);
+        }
+    }
+
+    id.ok_or(ProtobufError::MissingField(1))
+}
+
+// Confirmed transactions are looked up in their block by the caller, as the status only has its id
+pub fn encode_transaction_status(
+    transaction: &Transaction,
+    status: &TransactionStatusResponse,
+) -> Encoder {
+    let mut encoder = Encoder::new();
+    encoder.message(1, &encode_transaction(transaction));
+
+    let mut status_encoder = Encoder::new();
+    match status {
+        TransactionStatusResponse::Pending(pending) => {
+            status_encoder.uint64(1, pending.age_ms);
+            encoder.message(2, &status_encoder);
+        }
+        TransactionStatusResponse::Confirmed {
+            block_index,
+            block_hash,
+            ..
+        } => {
+            status_encoder
+                .uint64(1, *block_index)
+                .bytes(2, &hash_bytes(*block_hash));
+            encoder.message(3, &status_encoder);
+        }
+    }
+    encoder
+}
+
+pub fn decode_balance_request(bytes: &[u8]) -> Result<String, ProtobufError> {
+    let mut address = None;
+
+    let mut decoder = Decoder::new(bytes);
+    while let Some((field, value)) = decoder.next_field()? {
+        if field == 1 {
+            address = Some(value.as_string(field)?);
+        }
+    }
+
+    address
+        .filter(|address| !address.is_empty())
+        .ok_or(ProtobufError::MissingField(1))
+}
+
+pub fn encode_balance(balance: &BalanceResponse) -> Encoder {
+    let mut encoder = Encoder::new();
+    encoder
+        .string(1, &balance.address)
+        .message(2, &encode_balance_amounts(&balance.confirmed))
+        .message(3, &encode_balance_amounts(&balance.pending));
+    encoder
+}
+
+fn encode_balance_amounts(amounts: &BalanceAmounts) -> Encoder {
+    // the difference of two u64 always fits in an i128, but not in the "sint64" of the message
+    let balance = amounts.balance.clamp(i64::MIN as i128, i64::MAX as i128) as i64;
+
+    let mut encoder = Encoder::new();
+    encoder
+        .uint64(1, amounts.received)
+        .uint64(2, amounts.sent)
+        .sint64(3, balance);
+    encoder
+}
+
+// Returns the index of the first block to stream, if the client asked for one
+pub fn decode_stream_request(bytes: &[u8]) -> Result<Option<u64>, ProtobufError> {
+    let mut from_index = None;
+
+    let mut decoder = Decoder::new(bytes);
+    while let Some((field, value)) = decoder.next_field()? {
+        if field == 1 {
+            from_index = Some(value.as_u64(field)?);
+        }
+    }
+
+    Ok(from_index)
+}
+
+fn hash_bytes(hash: U256) -> [u8; 32] {
+    let mut bytes = [0; 32];
+    hash.to_big_endian(&mut bytes);
+    bytes
+}
+
+fn parse_hash(bytes: &[u8], field: u32) -> Result<U256, ProtobufError> {
+    if bytes.len() != 32 {
+        return Err(ProtobufError::InvalidHash(field));
+    }
+
+    Ok(U256::from_big_endian(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_decode_encoded_transactions() {
+        let mut transaction = Transaction {
+            sender: "alice".to_string(),
+            recipient: "bob".to_string(),
+            amount: 3,
+            signature: None,
+            multisig: None,
+            contract: None,
+            token: None,
+            data: None,
+        };
+
+        let bytes = encode_transaction(&transaction).into_bytes();
+        assert_same_transaction(&decode_transaction(&bytes).unwrap(), &transaction);
+
+        transaction.signature = Some("abcd".to_string());
+        let bytes = encode_transaction(&transaction).into_bytes();
+        assert_same_transaction(&decode_transaction(&bytes).unwrap(), &transaction);
+
+        transaction.data = Some(b"invoice 42".to_vec());
+        let bytes = encode_transaction(&transaction).into_bytes();
+        assert_eq!(decode_transaction(&bytes).unwrap().data, transaction.data);
+    }
+
+    #[test]
+    fn should_decode_transactions_with_actions() {
+        let mut transaction = Transaction {
+            sender: "alice".to_string(),
+            recipient: "bob".to_string(),
+            amount: 0,
+            signature: None,
+            multisig: Some(MultiSig {
+                threshold: 2,
+                public_keys: vec!["a".to_string(), "b".to_string()],
+                signatures: vec![("a".to_string(), "1".to_string())]
+                    .into_iter()
+                    .collect(),
+            }),
+            contract: Some(ContractAction::Call {
+                input: "ff".to_string(),
+                gas_limit: 1000,
+            }),
+            token: None,
+            data: None,
+        };
+
+        let bytes = encode_transaction(&transaction).into_bytes();
+        let decoded = decode_transaction(&bytes).unwrap();
+        assert_same_transaction(&decoded, &transaction);
+        assert_eq!(decoded.multisig, transaction.multisig);
+
+        transaction.multisig = None;
+        transaction.contract = None;
+        transaction.token = Some(TokenAction::Transfer {
+            token_id: U256::from(5),
+            amount: 7,
+        });
+        let bytes = encode_transaction(&transaction).into_bytes();
+        assert_same_transaction(&decode_transaction(&bytes).unwrap(), &transaction);
+    }
+
+    #[test]
+    fn should_read_blocks_as_headers() {
+        let mut block = Block::new(3, 42, U256::from(9), Vec::new());
+        block.header.hash = block.calculate_hash();
+
+        // a service that only knows "BlockHeader" skips the fields of the transactions
+        let bytes = encode_block(&block).into_bytes();
+        let mut decoder = Decoder::new(&bytes);
+        let mut header = Vec::new();
+        while let Some((field, value)) = decoder.next_field().unwrap() {
+            if ![7, 8, 9].contains(&field) {
+                header.push((field, value));
+            }
+        }
+
+        let header_bytes = encode_block_header(&block.header).into_bytes();
+        let mut decoder = Decoder::new(&header_bytes);
+        let mut expected = Vec::new();
+        while let Some(field) = decoder.next_field().unwrap() {
+            expected.push(field);
+        }
+        assert_eq!(header, expected);
+    }
+
+    #[test]
+    fn should_decode_block_requests() {
+        let mut encoder = Encoder::new();
+        encoder.uint64(1, 7);
+        let id = decode_block_request(&encoder.into_bytes()).unwrap();
+        assert!(matches!(id, BlockId::Index(7)));
+
+        let hash = U256::from(42);
+        let mut encoder = Encoder::new();
+        encoder.bytes(2, &hash_bytes(hash));
+        let id = decode_block_request(&encoder.into_bytes()).unwrap();
+        assert!(matches!(id, BlockId::Hash(h) if h == hash));
+
+        // hashes must have the full size, so they can't be mistaken for indices
+        let mut encoder = Encoder::new();
+        encoder.bytes(2, &[42]);
+        let result = decode_block_request(&encoder.into_bytes());
+        assert_eq!(result.err(), Some(ProtobufError::InvalidHash(2)));
+
+        let result = decode_block_request(&[]);
+        assert_eq!(result.err(), Some(ProtobufError::MissingField(1)));
+    }
+
+    fn assert_same_transaction(actual: &Transaction, expected: &Transaction) {
+        // the id covers every field but the signature
+        assert_eq!(actual.calculate_id(), expected.calculate_id());
+        assert_eq!(actual.signature, expected.signature);
+    }
+}
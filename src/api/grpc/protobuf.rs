@@ -0,0 +1,261 @@
+use std::convert::TryFrom;
+
+use thiserror::Error;
+
+// Minimal implementation of the protobuf wire format, enough for the messages in "proto/node.proto"
+// Every field is written, even the ones with default values, which is valid for any decoder
+
+#[derive(Error, PartialEq, Debug)]
+pub enum ProtobufError {
+    #[error("Message is truncated")]
+    Truncated,
+
+    #[error("Unsupported wire type {0}")]
+    UnsupportedWireType(u64),
+
+    #[error("Field {0} has an unexpected type")]
+    UnexpectedType(u32),
+
+    #[error("Field {0} is not valid UTF-8")]
+    InvalidUtf8(u32),
+
+    #[error("Field {0} is required")]
+    MissingField(u32),
+
+    #[error("Field {0} must be a 32-byte hash")]
+    InvalidHash(u32),
+}
+
+const VARINT: u64 = 0;
+const FIXED64: u64 = 1;
+const LENGTH_DELIMITED: u64 = 2;
+const FIXED32: u64 = 5;
+
+#[derive(Debug, Default)]
+pub struct Encoder {
+    buffer: Vec<u8>,
+}
+
+impl Encoder {
+    pub fn new() -> Encoder {
+        Encoder::default()
+    }
+
+    pub fn uint64(&mut self, field: u32, value: u64) -> &mut Encoder {
+        self.key(field, VARINT);
+        self.varint(value);
+        self
+    }
+
+    pub fn uint32(&mut self, field: u32, value: u32) -> &mut Encoder {
+        self.uint64(field, value as u64)
+    }
+
+    // Negative values of "int64" fields take 10 bytes, like in any other implementation
+    pub fn int64(&mut self, field: u32, value: i64) -> &mut Encoder {
+        self.uint64(field, value as u64)
+    }
+
+    // ZigZag encoding, so small negative values take few bytes
+    pub fn sint64(&mut self, field: u32, value: i64) -> &mut Encoder {
+        self.uint64(field, ((value << 1) ^ (value >> 63)) as u64)
+    }
+
+    pub fn bytes(&mut self, field: u32, value: &[u8]) -> &mut Encoder {
+        self.key(field, LENGTH_DELIMITED);
+        self.varint(value.len() as u64);
+        self.buffer.extend_from_slice(value);
+        self
+    }
+
+    pub fn string(&mut self, field: u32, value: &str) -> &mut Encoder {
+        self.bytes(field, value.as_bytes())
+    }
+
+    pub fn message(&mut self, field: u32, message: &Encoder) -> &mut Encoder {
+        self.bytes(field, &message.buffer)
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buffer
+    }
+
+    fn key(&mut self, field: u32, wire_type: u64) {
+        self.varint(((field as u64) << 3) | wire_type);
+    }
+
+    fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.buffer.push((value as u8) | 0x80);
+            value >>= 7;
+        }
+        self.buffer.push(value as u8);
+    }
+}
+
+// Value of a field as read from the wire, its meaning depends on the message
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FieldValue<'a> {
+    Varint(u64),
+    LengthDelimited(&'a [u8]),
+    // none of our messages have fixed size fields, so their values are skipped
+    Fixed64,
+    Fixed32,
+}
+
+impl<'a> FieldValue<'a> {
+    pub fn as_u64(&self, field: u32) -> Result<u64, ProtobufError> {
+        match self {
+            FieldValue::Varint(value) => Ok(*value),
+            _ => Err(ProtobufError::UnexpectedType(field)),
+        }
+    }
+
+    pub fn as_bytes(&self, field: u32) -> Result<&'a [u8], ProtobufError> {
+        match self {
+            FieldValue::LengthDelimited(bytes) => Ok(bytes),
+            _ => Err(ProtobufError::UnexpectedType(field)),
+        }
+    }
+
+    pub fn as_string(&self, field: u32) -> Result<String, ProtobufError> {
+        let bytes = self.as_bytes(field)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| ProtobufError::InvalidUtf8(field))
+    }
+}
+
+// Reads the fields of a message one by one
+// Unknown fields must be skipped by the caller, so newer clients can talk to older nodes
+pub struct Decoder<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(bytes: &'a [u8]) -> Decoder<'a> {
+        Decoder { bytes, position: 0 }
+    }
+
+    pub fn next_field(&mut self) -> Result<Option<(u32, FieldValue<'a>)>, ProtobufError> {
+        if self.position >= self.bytes.len() {
+            return Ok(None);
+        }
+
+        let key = self.varint()?;
+        let field = u32::try_from(key >> 3).map_err(|_| ProtobufError::Truncated)?;
+        let value = match key & 0x7 {
+            VARINT => FieldValue::Varint(self.varint()?),
+            FIXED64 => {
+                self.take(8)?;
+                FieldValue::Fixed64
+            }
+            LENGTH_DELIMITED => {
+                let length = self.varint()?;
+                let length = usize::try_from(length).map_err(|_| ProtobufError::Truncated)?;
+                FieldValue::LengthDelimited(self.take(length)?)
+            }
+            FIXED32 => {
+                self.take(4)?;
+                FieldValue::Fixed32
+            }
+            wire_type => return Err(ProtobufError::UnsupportedWireType(wire_type)),
+        };
+
+        Ok(Some((field, value)))
+    }
+
+    fn varint(&mut self) -> Result<u64, ProtobufError> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = *self
+                .bytes
+                .get(self.position)
+                .ok_or(ProtobufError::Truncated)?;
+            self.position += 1;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte < 0x80 {
+                return Ok(value);
+            }
+        }
+
+        Err(ProtobufError::Truncated)
+    }
+
+    fn take(&mut self, length: usize) -> Result<&'a [u8], ProtobufError> {
+        let end = self
+            .position
+            .checked_add(length)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or(ProtobufError::Truncated)?;
+        let bytes = &self.bytes[self.position..end];
+        self.position = end;
+
+        Ok(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_encode_like_the_protobuf_docs() {
+        // examples from https://protobuf.dev/programming-guides/encoding/
+        let mut encoder = Encoder::new();
+        encoder.uint64(1, 150);
+        assert_eq!(encoder.into_bytes(), vec![0x08, 0x96, 0x01]);
+
+        let mut encoder = Encoder::new();
+        encoder.string(2, "testing");
+        assert_eq!(
+            encoder.into_bytes(),
+            vec![0x12, 0x07, 0x74, 0x65, 0x73, 0x74, 0x69, 0x6e, 0x67]
+        );
+    }
+
+    #[test]
+    fn should_decode_what_was_encoded() {
+        let mut nested = Encoder::new();
+        nested.string(1, "inner");
+
+        let mut encoder = Encoder::new();
+        encoder
+            .uint64(1, u64::MAX)
+            .sint64(2, -3)
+            .int64(3, -1)
+            .message(4, &nested);
+        let bytes = encoder.into_bytes();
+
+        let mut decoder = Decoder::new(&bytes);
+        let (field, value) = decoder.next_field().unwrap().unwrap();
+        assert_eq!((field, value.as_u64(field).unwrap()), (1, u64::MAX));
+        let (field, value) = decoder.next_field().unwrap().unwrap();
+        assert_eq!(value.as_u64(field).unwrap(), 5);
+        let (field, value) = decoder.next_field().unwrap().unwrap();
+        assert_eq!(value.as_u64(field).unwrap() as i64, -1);
+
+        let (field, value) = decoder.next_field().unwrap().unwrap();
+        let mut nested = Decoder::new(value.as_bytes(field).unwrap());
+        let (field, value) = nested.next_field().unwrap().unwrap();
+        assert_eq!(value.as_string(field).unwrap(), "inner");
+        assert_eq!(decoder.next_field().unwrap(), None);
+    }
+
+    #[test]
+    fn should_reject_truncated_messages() {
+        let mut encoder = Encoder::new();
+        encoder.string(1, "truncated");
+        let bytes = encoder.into_bytes();
+
+        let mut decoder = Decoder::new(&bytes[..bytes.len() - 1]);
+        assert_eq!(decoder.next_field(), Err(ProtobufError::Truncated));
+
+        let mut decoder = Decoder::new(&[0x80]);
+        assert_eq!(decoder.next_field(), Err(ProtobufError::Truncated));
+
+        // a message where the string field was expected as a number
+        let mut decoder = Decoder::new(&bytes);
+        let (field, value) = decoder.next_field().unwrap().unwrap();
+        assert_eq!(value.as_u64(field), Err(ProtobufError::UnexpectedType(1)));
+    }
+}
@@ -0,0 +1,82 @@
+use actix_web::HttpResponse;
+
+use super::ApiResult;
+
+// OpenAPI description of the api, maintained along with the routes in "doc/openapi.json"
+const OPENAPI_SPEC: &str = include_str!("../../doc/openapi.json");
+
+// Swagger UI page to explore the api from a browser, the assets are loaded from a CDN
+const SWAGGER_UI: &str = r##"<!DOCTYPE html>
+<html lang="en">
+<head>
+  <meta charset="utf-8">
+  <title>rust-blockchain node API</title>
+  <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@4/swagger-ui.css">
+</head>
+<body>
+  <div id="swagger-ui"></div>
+  <script src="https://unpkg.com/swagger-ui-dist@4/swagger-ui-bundle.js"></script>
+  <script>
+    window.onload = () => {
+      window.ui = SwaggerUIBundle({ url: "/v1/openapi.json", dom_id: "#swagger-ui" });
+    };
+  </script>
+</body>
+</html>
+"##;
+
+pub async fn get_openapi_spec() -> ApiResult {
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(OPENAPI_SPEC))
+}
+
+pub async fn get_swagger_ui() -> ApiResult {
+    Ok(HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(SWAGGER_UI))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use super::*;
+
+    #[test]
+    fn should_describe_the_current_version() {
+        let spec: Value = serde_json::from_str(OPENAPI_SPEC).unwrap();
+
+        assert_eq!(spec["openapi"], "3.0.3");
+        assert_eq!(spec["info"]["version"], env!("CARGO_PKG_VERSION"));
+    }
+
+    #[test]
+    fn should_only_reference_known_schemas() {
+        let spec: Value = serde_json::from_str(OPENAPI_SPEC).unwrap();
+        let mut references = Vec::new();
+        find_references(&spec, &mut references);
+
+        assert!(!references.is_empty());
+        for reference in references {
+            let path = reference.trim_start_matches('#');
+            assert!(spec.pointer(path).is_some(), "unknown {}", reference);
+        }
+    }
+
+    fn find_references(value: &Value, references: &mut Vec<String>) {
+        match value {
+            Value::Object(map) => {
+                if let Some(Value::String(reference)) = map.get("$ref") {
+                    references.push(reference.clone());
+                }
+                map.values()
+                    .for_each(|value| find_references(value, references));
+            }
+            Value::Array(values) => values
+                .iter()
+                .for_each(|value| find_references(value, references)),
+            _ => {}
+        }
+    }
+}
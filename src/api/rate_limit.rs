@@ -0,0 +1,131 @@
+use std::{collections::HashMap, net::IpAddr, sync::Arc, time::Instant};
+
+use actix_web::dev::ServiceRequest;
+
+use super::ApiError;
+use crate::util::Lock;
+
+// Number of clients tracked before forgetting the ones that are not limited anymore
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+// Tokens of a single client, refilled continuously over time
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+// Limits the requests that change the state of the node (transactions, blocks, mining...)
+// with a token bucket per client ip, so a single client can't flood the pool.
+// Each request takes a token, and tokens are refilled at "per_sec" up to "burst"
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    per_sec: f64,
+    burst: f64,
+    buckets: Arc<Lock<HashMap<IpAddr, Bucket>>>,
+}
+
+impl RateLimiter {
+    pub fn new(per_sec: u32, burst: u32) -> RateLimiter {
+        RateLimiter {
+            per_sec: per_sec as f64,
+            // the burst can't be smaller than a single request
+            burst: burst.max(1) as f64,
+            buckets: Arc::default(),
+        }
+    }
+
+    pub fn check(&self, req: &ServiceRequest) -> Result<(), ApiError> {
+        // reads are cheap, and peers poll them constantly
+        if super::is_read(req.method(), req.path()) {
+            return Ok(());
+        }
+
+        match req.peer_addr() {
+            Some(addr) => self.check_client(addr.ip()),
+            None => Ok(()),
+        }
+    }
+
+    // Counts a write from a client, wherever it comes from (REST or gRPC)
+    pub fn check_client(&self, ip: IpAddr) -> Result<(), ApiError> {
+        self.take(ip, Instant::now())
+    }
+
+    // Takes a token from the bucket of the client, or returns the seconds to wait for the next one
+    fn take(&self, ip: IpAddr, now: Instant) -> Result<(), ApiError> {
+        // a rate of 0 disables the limits
+        if self.per_sec <= 0.0 {
+            return Ok(());
+        }
+
+        let mut buckets = self.buckets.lock();
+        if buckets.len() >= MAX_TRACKED_CLIENTS {
+            self.forget_refilled(&mut buckets, now);
+        }
+
+        let bucket = buckets.entry(ip).or_insert(Bucket {
+            tokens: self.burst,
+            updated_at: now,
+        });
+        bucket.tokens = self.refill(bucket, now);
+        bucket.updated_at = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Ok(());
+        }
+
+        let retry_after_secs = ((1.0 - bucket.tokens) / self.per_sec).ceil() as u64;
+        Err(ApiError::TooManyRequests(retry_after_secs.max(1)))
+    }
+
+    fn refill(&self, bucket: &Bucket, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(bucket.updated_at);
+        (bucket.tokens + elapsed.as_secs_f64() * self.per_sec).min(self.burst)
+    }
+
+    // A full bucket is the same as a new one, so there is no need to remember it
+    fn forget_refilled(&self, buckets: &mut HashMap<IpAddr, Bucket>, now: Instant) {
+        buckets.retain(|_, bucket| self.refill(bucket, now) < self.burst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn should_limit_requests_per_client() {
+        let limiter = RateLimiter::new(1, 2);
+        let now = Instant::now();
+        let client: IpAddr = "10.0.0.1".parse().unwrap();
+        let other_client: IpAddr = "10.0.0.2".parse().unwrap();
+
+        // the burst is allowed, but not more
+        assert!(limiter.take(client, now).is_ok());
+        assert!(limiter.take(client, now).is_ok());
+        assert_eq!(limiter.take(client, now), Err(ApiError::TooManyRequests(1)));
+
+        // other clients have their own bucket
+        assert!(limiter.take(other_client, now).is_ok());
+
+        // tokens are refilled over time
+        let later = now + Duration::from_secs(1);
+        assert!(limiter.take(client, later).is_ok());
+        assert!(limiter.take(client, later).is_err());
+    }
+
+    #[test]
+    fn should_not_limit_when_disabled() {
+        let limiter = RateLimiter::new(0, 1);
+        let now = Instant::now();
+        let client: IpAddr = "10.0.0.1".parse().unwrap();
+
+        for _ in 0..10 {
+            assert!(limiter.take(client, now).is_ok());
+        }
+    }
+}
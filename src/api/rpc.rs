@@ -0,0 +1,325 @@
+use std::{convert::Infallible, net::IpAddr, sync::Arc};
+
+use actix_web::{error::BlockingError, web, HttpRequest, HttpResponse};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{json, Value as JsonValue};
+
+use super::{ApiError, ApiResult, ApiState, BlockId, ChainPoint, API_KEY_HEADER, SHUTTING_DOWN};
+use crate::model::{hash_hex, Transaction};
+
+// Max number of calls in a batch, so a single request can't keep a worker busy for too long
+const MAX_BATCH_SIZE: usize = 100;
+
+// Error codes defined by the JSON-RPC 2.0 specification
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const INTERNAL_ERROR: i64 = -32603;
+
+#[derive(Debug, Serialize)]
+struct RpcError {
+    code: i64,
+    message: String,
+    // the code of the matching REST api error, when there is one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<JsonValue>,
+}
+
+impl RpcError {
+    fn new(code: i64, message: &str) -> RpcError {
+        RpcError {
+            code,
+            message: message.to_string(),
+            data: None,
+        }
+    }
+}
+
+// The codes of the errors of the node are in the range reserved for servers by the specification
+impl From<ApiError> for RpcError {
+    fn from(error: ApiError) -> Self {
+        let code = match error {
+            ApiError::BadRequest(_) => INVALID_PARAMS,
+            ApiError::Unauthorized(_) => -32001,
+            ApiError::NotFound(_) => -32002,
+            ApiError::Conflict(_) => -32003,
+            ApiError::TooManyRequests(_) => -32004,
+            ApiError::Unavailable(_) => -32005,
+            ApiError::PayloadTooLarge(_) => -32006,
+            ApiError::Internal(_) => INTERNAL_ERROR,
+        };
+
+        RpcError {
+            code,
+            message: error.to_string(),
+            data: Some(json!(error.code())),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct RpcResponse {
+    jsonrpc: &'static str,
+    // a null result is still a result, e.g. for blocks that don't exist
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<JsonValue>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<RpcError>,
+    id: JsonValue,
+}
+
+impl RpcResponse {
+    fn new(id: JsonValue, result: Result<JsonValue, RpcError>) -> RpcResponse {
+        let (result, error) = match result {
+            Ok(result) => (Some(result), None),
+            Err(error) => (None, Some(error)),
+        };
+
+        RpcResponse {
+            jsonrpc: "2.0",
+            result,
+            error,
+            id,
+        }
+    }
+}
+
+// Who sent the calls, as writes need the api key and are rate limited per client
+struct Caller {
+    api_key: Option<String>,
+    ip: Option<IpAddr>,
+}
+
+impl Caller {
+    // Same checks that the api does for any other write
+    fn check_write(&self, state: &ApiState) -> Result<(), ApiError> {
+        if state.shutdown.is_draining() {
+            return Err(ApiError::Unavailable(SHUTTING_DOWN.to_string()));
+        }
+
+        state.auth.check_key(false, self.api_key.as_deref())?;
+        match self.ip {
+            Some(ip) => state.rate_limiter.check_client(ip),
+            None => Ok(()),
+        }
+    }
+}
+
+// Answers JSON-RPC 2.0 calls, single or in batches, for tools that expect it instead of REST
+// The request is a read for the api, the methods that change the state of the node check it themselves
+pub async fn post_rpc(state: web::Data<ApiState>, req: HttpRequest, body: web::Bytes) -> ApiResult {
+    let caller = Caller {
+        api_key: req
+            .headers()
+            .get(API_KEY_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string),
+        ip: req.peer_addr().map(|addr| addr.ip()),
+    };
+
+    // some methods go over the whole chain, so they must not block the api
+    let state = state.into_inner();
+    let execution = web::block(move || Ok::<_, Infallible>(execute(state, caller, &body)));
+
+    match execution.await {
+        Ok(Some(response)) => Ok(HttpResponse::Ok().json(&response)),
+        // only notifications, which are never answered
+        Ok(None) => Ok(HttpResponse::NoContent().finish()),
+        Err(BlockingError::Error(never)) => match never {},
+        Err(BlockingError::Canceled) => Err(ApiError::Internal("Call was canceled".to_string())),
+    }
+}
+
+fn execute(state: Arc<ApiState>, caller: Caller, body: &[u8]) -> Option<JsonValue> {
+    let request: JsonValue = match serde_json::from_slice(body) {
+        Ok(request) => request,
+        Err(error) => {
+            let error = RpcError::new(PARSE_ERROR, &format!("Parse error: {}", error));
+            return Some(json!(RpcResponse::new(JsonValue::Null, Err(error))));
+        }
+    };
+
+    let calls = match request {
+        JsonValue::Array(calls) => calls,
+        call => return execute_call(&state, &caller, call).map(|response| json!(response)),
+    };
+
+    if calls.is_empty() || calls.len() > MAX_BATCH_SIZE {
+        let message = format!("Batches must have between 1 and {} calls", MAX_BATCH_SIZE);
+        let error = RpcError::new(INVALID_REQUEST, &message);
+        return Some(json!(RpcResponse::new(JsonValue::Null, Err(error))));
+    }
+
+    let responses: Vec<RpcResponse> = calls
+        .into_iter()
+        .filter_map(|call| execute_call(&state, &caller, call))
+        .collect();
+    Some(json!(responses)).filter(|_| !responses.is_empty())
+}
+
+// Calls without an id are notifications: they are executed, but not answered
+fn execute_call(state: &ApiState, caller: &Caller, call: JsonValue) -> Option<RpcResponse> {
+    let id = call.get("id").cloned();
+    let (method, params) = match parse_call(call) {
+        Ok(parsed) => parsed,
+        // the id may be what is wrong, so it's not trusted
+        Err(error) => return Some(RpcResponse::new(JsonValue::Null, Err(error))),
+    };
+
+    let result = call_method(state, caller, &method, &Params(params));
+    id.map(|id| RpcResponse::new(id, result))
+}
+
+fn parse_call(call: JsonValue) -> Result<(String, Option<JsonValue>), RpcError> {
+    let invalid = |message| Err(RpcError::new(INVALID_REQUEST, message));
+    let mut call = match call {
+        JsonValue::Object(call) => call,
+        _ => return invalid("Calls must be objects"),
+    };
+
+    if call.get("jsonrpc") != Some(&json!("2.0")) {
+        return invalid("Only JSON-RPC 2.0 is supported");
+    }
+
+    if !matches!(
+        call.get("id"),
+        None | Some(JsonValue::Null) | Some(JsonValue::Number(_)) | Some(JsonValue::String(_))
+    ) {
+        return invalid("Ids must be strings or numbers");
+    }
+
+    let method = match call.remove("method") {
+        Some(JsonValue::String(method)) => method,
+        _ => return invalid("The method must be a string"),
+    };
+
+    match call.remove("params") {
+        params @ (None | Some(JsonValue::Array(_)) | Some(JsonValue::Object(_))) => {
+            Ok((method, params))
+        }
+        _ => invalid("Params must be an array or an object"),
+    }
+}
+
+fn call_method(
+    state: &ApiState,
+    caller: &Caller,
+    method: &str,
+    params: &Params,
+) -> Result<JsonValue, RpcError> {
+    match method {
+        "chain_getHeight" => Ok(json!(state.blockchain.get_last_block().header.index)),
+        "chain_getStatus" => Ok(json!(super::node_status(state))),
+        "chain_getBlock" => {
+            let block = match block_id(&params.get(0, "id")?)? {
+                BlockId::Index(index) => state.blockchain.get_block_at(index),
+                BlockId::Hash(hash) => state.blockchain.get_block(hash),
+            };
+            Ok(json!(block))
+        }
+        "tx_submit" => {
+            caller.check_write(state)?;
+            let transaction: Transaction = params.get(0, "transaction")?;
+            let id = super::submit_transaction(state, transaction)?;
+            Ok(json!(hash_hex::to_hex(&id)))
+        }
+        "tx_get" => {
+            let id: String = params.get(0, "id")?;
+            let id = hash_hex::parse_hex(&id)
+                .ok_or_else(|| RpcError::new(INVALID_PARAMS, "Invalid transaction id"))?;
+            match super::transaction_status(state, id) {
+                Ok(status) => Ok(json!(status)),
+                Err(ApiError::NotFound(_)) => Ok(JsonValue::Null),
+                Err(error) => Err(error.into()),
+            }
+        }
+        "mempool_content" => Ok(json!(state.pool.get_pending())),
+        "address_getBalance" => {
+            let address: String = params.get(0, "address")?;
+            Ok(json!(super::address_balance(
+                state,
+                &address,
+                ChainPoint::Latest
+            )?))
+        }
+        _ => Err(RpcError::new(
+            METHOD_NOT_FOUND,
+            &format!("Method \"{}\" not found", method),
+        )),
+    }
+}
+
+// Blocks are identified either by their index (a number) or by their hash (a hex string)
+fn block_id(id: &JsonValue) -> Result<BlockId, RpcError> {
+    let block_id = match id {
+        JsonValue::Number(index) => index.as_u64().map(BlockId::Index),
+        JsonValue::String(id) => super::parse_block_id(id),
+        _ => None,
+    };
+
+    block_id.ok_or_else(|| RpcError::new(INVALID_PARAMS, "Invalid block index or hash"))
+}
+
+// Parameters of a call, either by position or by name
+struct Params(Option<JsonValue>);
+
+impl Params {
+    fn get<T: DeserializeOwned>(&self, position: usize, name: &str) -> Result<T, RpcError> {
+        let value = match &self.0 {
+            Some(JsonValue::Array(params)) => params.get(position),
+            Some(JsonValue::Object(params)) => params.get(name),
+            _ => None,
+        };
+
+        let value = value.ok_or_else(|| {
+            RpcError::new(INVALID_PARAMS, &format!("Missing parameter \"{}\"", name))
+        })?;
+        serde_json::from_value(value.clone()).map_err(|error| {
+            let message = format!("Invalid parameter \"{}\": {}", name, error);
+            RpcError::new(INVALID_PARAMS, &message)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_reject_invalid_calls() {
+        let error = parse_call(json!([1])).unwrap_err();
+        assert_eq!(error.code, INVALID_REQUEST);
+
+        let error = parse_call(json!({"jsonrpc": "1.0", "method": "chain_getHeight"})).unwrap_err();
+        assert_eq!(error.code, INVALID_REQUEST);
+
+        let call = json!({"jsonrpc": "2.0", "method": "chain_getHeight", "id": {}});
+        assert_eq!(parse_call(call).unwrap_err().code, INVALID_REQUEST);
+
+        let call = json!({"jsonrpc": "2.0", "method": "tx_get", "params": "0x1", "id": 1});
+        assert_eq!(parse_call(call).unwrap_err().code, INVALID_REQUEST);
+
+        let call = json!({"jsonrpc": "2.0", "method": "tx_get", "params": ["0x1"], "id": 1});
+        let (method, params) = parse_call(call).unwrap();
+        assert_eq!(method, "tx_get");
+        assert_eq!(params, Some(json!(["0x1"])));
+    }
+
+    #[test]
+    fn should_read_params_by_position_or_name() {
+        let params = Params(Some(json!(["a", 2])));
+        assert_eq!(params.get::<String>(0, "address").unwrap(), "a");
+        assert_eq!(params.get::<u64>(1, "index").unwrap(), 2);
+
+        let params = Params(Some(json!({"address": "a"})));
+        assert_eq!(params.get::<String>(0, "address").unwrap(), "a");
+
+        let error = Params(None).get::<String>(0, "address").unwrap_err();
+        assert_eq!(error.code, INVALID_PARAMS);
+        let error = Params(Some(json!([1])))
+            .get::<String>(0, "address")
+            .unwrap_err();
+        assert_eq!(error.code, INVALID_PARAMS);
+    }
+}
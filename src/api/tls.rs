@@ -0,0 +1,323 @@
+use std::{
+    ffi::{CStr, CString},
+    io,
+    os::raw::{c_int, c_void},
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use actix_codec::{AsyncRead, AsyncWrite};
+use futures::{future::poll_fn, ready};
+use openssl_sys as ffi;
+use thiserror::Error;
+use tokio::{net::TcpStream, time::timeout};
+
+// TLS of the api, terminated by the node itself with the OpenSSL library of the system
+// OpenSSL reads and writes the records in memory buffers, and they go through the async socket,
+// so a slow client never blocks the workers of the api
+
+// Most bytes read from (or moved to) the socket at once, a whole record fits in it
+const BUFFER_SIZE: usize = 16 * 1024;
+
+// Most plain bytes encrypted at once, so a big response isn't kept twice in memory
+const MAX_WRITE_SIZE: usize = 64 * 1024;
+
+// Error types to return when the certificate or the key can't be used
+#[derive(Error, PartialEq, Debug)]
+pub enum TlsError {
+    #[error("Could not load `{0}`: {1}")]
+    InvalidFile(String, String),
+
+    #[error("The key in `{0}` does not match the certificate")]
+    KeyMismatch(String),
+
+    #[error("Could not set up TLS: {0}")]
+    Setup(String),
+}
+
+// OpenSSL context with the certificate and the key, shared by every connection
+struct SslContext(*mut ffi::SSL_CTX);
+
+// Once it's set up, a context is only read, which OpenSSL allows from any thread
+unsafe impl Send for SslContext {}
+unsafe impl Sync for SslContext {}
+
+impl Drop for SslContext {
+    fn drop(&mut self) {
+        unsafe { ffi::SSL_CTX_free(self.0) }
+    }
+}
+
+// Accepts the TLS connections of the api, with its certificate
+#[derive(Clone)]
+pub struct TlsAcceptor {
+    context: Arc<SslContext>,
+    handshake_timeout: Duration,
+}
+
+impl TlsAcceptor {
+    // Loads the certificate (with its chain, if any) and the private key, both PEM files
+    // Clients that don't finish the handshake in time are disconnected, like slow requests
+    pub fn new(
+        cert_path: &str,
+        key_path: &str,
+        handshake_timeout_ms: u64,
+    ) -> Result<TlsAcceptor, TlsError> {
+        ffi::init();
+        let cert_file = c_path(cert_path)?;
+        let key_file = c_path(key_path)?;
+
+        unsafe {
+            ffi::ERR_clear_error();
+            let context = ffi::SSL_CTX_new(ffi::TLS_server_method());
+            if context.is_null() {
+                return Err(TlsError::Setup(last_error()));
+            }
+            // freed on any error from now on
+            let context = SslContext(context);
+
+            // older versions of the protocol have known weaknesses
+            if ffi::SSL_CTX_set_min_proto_version(context.0, ffi::TLS1_2_VERSION) != 1 {
+                return Err(TlsError::Setup(last_error()));
+            }
+            if ffi::SSL_CTX_use_certificate_chain_file(context.0, cert_file.as_ptr()) != 1 {
+                return Err(TlsError::InvalidFile(cert_path.to_string(), last_error()));
+            }
+            let key_type = ffi::SSL_FILETYPE_PEM;
+            if ffi::SSL_CTX_use_PrivateKey_file(context.0, key_file.as_ptr(), key_type) != 1 {
+                return Err(TlsError::InvalidFile(key_path.to_string(), last_error()));
+            }
+            if ffi::SSL_CTX_check_private_key(context.0) != 1 {
+                ffi::ERR_clear_error();
+                return Err(TlsError::KeyMismatch(key_path.to_string()));
+            }
+
+            Ok(TlsAcceptor {
+                context: Arc::new(context),
+                handshake_timeout: Duration::from_millis(handshake_timeout_ms),
+            })
+        }
+    }
+
+    // Runs the handshake of a new connection, which can then be read and written in plain text
+    pub async fn accept(&self, stream: TcpStream) -> io::Result<TlsStream> {
+        let mut stream = TlsStream::new(&self.context, stream)?;
+        let handshake = async {
+            poll_fn(|cx| stream.poll_operation(cx, |ssl| unsafe { ffi::SSL_do_handshake(ssl) }))
+                .await?;
+            // the last records of the handshake may still be waiting for the socket
+            poll_fn(|cx| stream.poll_send(cx)).await
+        };
+
+        match timeout(self.handshake_timeout, handshake).await {
+            Ok(result) => result.map(|_| stream),
+            Err(_) => Err(io::ErrorKind::TimedOut.into()),
+        }
+    }
+}
+
+// A connection of the api over TLS
+pub struct TlsStream {
+    ssl: *mut ffi::SSL,
+    // memory buffers owned by the ssl, with the records from the peer and the ones to send to it
+    incoming: *mut ffi::BIO,
+    outgoing: *mut ffi::BIO,
+    stream: TcpStream,
+    // records that the socket didn't take yet
+    unsent: Vec<u8>,
+    shutdown: bool,
+}
+
+impl TlsStream {
+    fn new(context: &SslContext, stream: TcpStream) -> io::Result<TlsStream> {
+        unsafe {
+            ffi::ERR_clear_error();
+            let ssl = ffi::SSL_new(context.0);
+            if ssl.is_null() {
+                return Err(ssl_error());
+            }
+            let incoming = ffi::BIO_new(ffi::BIO_s_mem());
+            let outgoing = ffi::BIO_new(ffi::BIO_s_mem());
+            if incoming.is_null() || outgoing.is_null() {
+                ffi::BIO_free_all(incoming);
+                ffi::BIO_free_all(outgoing);
+                ffi::SSL_free(ssl);
+                return Err(ssl_error());
+            }
+            ffi::SSL_set_bio(ssl, incoming, outgoing);
+            ffi::SSL_set_accept_state(ssl);
+
+            Ok(TlsStream {
+                ssl,
+                incoming,
+                outgoing,
+                stream,
+                unsent: Vec::new(),
+                shutdown: false,
+            })
+        }
+    }
+
+    // Takes the records that OpenSSL wrote and sends as many of them as the socket takes
+    fn poll_send(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let mut buffer = [0; BUFFER_SIZE];
+        loop {
+            let length = buffer.len() as c_int;
+            let read =
+                unsafe { ffi::BIO_read(self.outgoing, buffer.as_mut_ptr() as *mut c_void, length) };
+            if read <= 0 {
+                break;
+            }
+            self.unsent.extend_from_slice(&buffer[..read as usize]);
+        }
+
+        while !self.unsent.is_empty() {
+            let written = ready!(Pin::new(&mut self.stream).poll_write(cx, &self.unsent))?;
+            if written == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.unsent.drain(..written);
+        }
+
+        Poll::Ready(Ok(()))
+    }
+
+    // Reads more records from the socket for OpenSSL, or an error if the peer closed it
+    fn poll_receive(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let mut buffer = [0; BUFFER_SIZE];
+        let read = ready!(Pin::new(&mut self.stream).poll_read(cx, &mut buffer))?;
+        if read == 0 {
+            return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into()));
+        }
+
+        let data = buffer.as_ptr() as *const c_void;
+        // memory buffers take everything, unless there's no memory left
+        match unsafe { ffi::BIO_write(self.incoming, data, read as c_int) } {
+            written if written == read as c_int => Poll::Ready(Ok(())),
+            _ => Poll::Ready(Err(ssl_error())),
+        }
+    }
+
+    // Runs an OpenSSL operation until it's done, sending and receiving the records it needs
+    // Its result is 0 once the peer closed the connection cleanly
+    fn poll_operation(
+        &mut self,
+        cx: &mut Context<'_>,
+        operation: impl Fn(*mut ffi::SSL) -> c_int,
+    ) -> Poll<io::Result<c_int>> {
+        loop {
+            let (result, error) = unsafe {
+                ffi::ERR_clear_error();
+                let result = operation(self.ssl);
+                (result, ffi::SSL_get_error(self.ssl, result))
+            };
+
+            // whatever happened, the records it wrote (even alerts) must reach the peer
+            // the ones that don't fit in the socket yet are sent by the next operations
+            if let Poll::Ready(Err(error)) = self.poll_send(cx) {
+                return Poll::Ready(Err(error));
+            }
+            if result > 0 {
+                return Poll::Ready(Ok(result));
+            }
+
+            match error {
+                ffi::SSL_ERROR_WANT_READ => ready!(self.poll_receive(cx))?,
+                ffi::SSL_ERROR_ZERO_RETURN => return Poll::Ready(Ok(0)),
+                _ => return Poll::Ready(Err(ssl_error())),
+            }
+        }
+    }
+}
+
+impl Drop for TlsStream {
+    fn drop(&mut self) {
+        // the memory buffers go with it
+        unsafe { ffi::SSL_free(self.ssl) }
+    }
+}
+
+impl AsyncRead for TlsStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+
+        let length = buf.len().min(c_int::MAX as usize) as c_int;
+        let data = buf.as_mut_ptr() as *mut c_void;
+        let result = self
+            .get_mut()
+            .poll_operation(cx, |ssl| unsafe { ffi::SSL_read(ssl, data, length) });
+
+        match ready!(result) {
+            Ok(read) => Poll::Ready(Ok(read as usize)),
+            // clients often close the connection without a close_notify once they have their response
+            Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => Poll::Ready(Ok(0)),
+            Err(error) => Poll::Ready(Err(error)),
+        }
+    }
+}
+
+impl AsyncWrite for TlsStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        // the records of the previous writes go first, so a slow client can't fill the memory
+        ready!(this.poll_send(cx))?;
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+
+        let length = buf.len().min(MAX_WRITE_SIZE) as c_int;
+        let data = buf.as_ptr() as *const c_void;
+        this.poll_operation(cx, |ssl| unsafe { ffi::SSL_write(ssl, data, length) })
+            .map_ok(|written| written as usize)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_send(cx))?;
+        Pin::new(&mut this.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if !this.shutdown {
+            // only writes the close_notify, the peer doesn't need to answer it
+            unsafe { ffi::SSL_shutdown(this.ssl) };
+            this.shutdown = true;
+        }
+        ready!(this.poll_send(cx))?;
+        Pin::new(&mut this.stream).poll_shutdown(cx)
+    }
+}
+
+fn c_path(path: &str) -> Result<CString, TlsError> {
+    CString::new(path).map_err(|_| TlsError::InvalidFile(path.to_string(), "invalid path".into()))
+}
+
+// Reason of the last error of OpenSSL in this thread
+fn last_error() -> String {
+    unsafe {
+        let code = ffi::ERR_get_error();
+        let reason = ffi::ERR_reason_error_string(code);
+        ffi::ERR_clear_error();
+        match code == 0 || reason.is_null() {
+            true => "unknown error".to_string(),
+            false => CStr::from_ptr(reason).to_string_lossy().to_string(),
+        }
+    }
+}
+
+fn ssl_error() -> io::Error {
+    io::Error::other(last_error())
+}
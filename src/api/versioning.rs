@@ -0,0 +1,213 @@
+use actix_web::{
+    dev::ServiceRequest,
+    http::{HeaderMap, HeaderName, HeaderValue},
+};
+
+use super::ApiError;
+
+// Header where clients can ask for a version of the api, and where every response says which one answered
+pub const API_VERSION_HEADER: &str = "API-Version";
+
+// Version of the routes under "/v1", and of the unversioned aliases of them
+pub const CURRENT_VERSION: u32 = 1;
+
+// Versions that still have their routes mounted, under "/v<version>"
+// A breaking change of the responses ships as a new version, while the old one stays available
+const SUPPORTED_VERSIONS: [u32; 1] = [1];
+
+// Versions that are still served but will be removed, so their responses carry a deprecation warning
+const DEPRECATED_VERSIONS: [u32; 0] = [];
+
+// Routes that are not part of the versioned api, so they only live at the root and are not deprecated:
+// the readiness check of load balancers, the pages for browsers and the routes of the plugins
+const UNVERSIONED_PATHS: [&str; 3] = ["/ready", "/docs", "/explorer"];
+const PLUGINS_PATH: &str = "/plugins/";
+
+// How a request addressed the api, to add the version headers to its response
+#[derive(Debug, Clone, PartialEq)]
+pub struct Negotiated {
+    version: u32,
+    // Path of the same route in the current version, if the request used a deprecated one
+    successor: Option<String>,
+}
+
+// Returns the path of a route inside its version, e.g. "/blocks" for "/v1/blocks"
+// Unversioned paths are returned as they are
+pub fn route_path(path: &str) -> &str {
+    match split_version(path) {
+        Some((_, route)) => route,
+        None => path,
+    }
+}
+
+// Finds which version of the api a request is for, from its path or the version header
+// A request can't ask for a version in the header that doesn't match its path
+pub fn negotiate(req: &ServiceRequest) -> Result<Option<Negotiated>, ApiError> {
+    let path = req.path();
+    let requested = match req.headers().get(API_VERSION_HEADER) {
+        Some(value) => Some(
+            value
+                .to_str()
+                .ok()
+                .and_then(|value| value.trim().parse::<u32>().ok())
+                .ok_or_else(|| ApiError::BadRequest("Invalid api version".to_string()))?,
+        ),
+        None => None,
+    };
+
+    if let Some((version, _)) = split_version(path) {
+        if !SUPPORTED_VERSIONS.contains(&version) {
+            return Err(ApiError::NotFound(format!(
+                "Unknown api version {}",
+                version
+            )));
+        }
+        if requested.is_some_and(|requested| requested != version) {
+            return Err(ApiError::BadRequest(format!(
+                "The path is for api version {}",
+                version
+            )));
+        }
+        return Ok(Some(Negotiated {
+            version,
+            successor: None,
+        }));
+    }
+
+    if is_unversioned(path) {
+        return Ok(None);
+    }
+
+    // the routes at the root are the ones of the current version, kept for the clients that predate "/v1"
+    match requested {
+        Some(version) if version != CURRENT_VERSION => Err(ApiError::BadRequest(format!(
+            "Api version {} is only available under /v{}",
+            version, version
+        ))),
+        _ => Ok(Some(Negotiated {
+            version: CURRENT_VERSION,
+            successor: Some(format!("/v{}{}", CURRENT_VERSION, path)),
+        })),
+    }
+}
+
+// Tells the client which version answered, and where to go if it's deprecated
+pub fn add_headers(headers: &mut HeaderMap, negotiated: &Negotiated) {
+    headers.insert(
+        HeaderName::from_static("api-version"),
+        HeaderValue::from(negotiated.version),
+    );
+
+    let successor = match &negotiated.successor {
+        Some(successor) => Some(successor.clone()),
+        None if DEPRECATED_VERSIONS.contains(&negotiated.version) => SUPPORTED_VERSIONS
+            .iter()
+            .max()
+            .map(|latest| format!("/v{}", latest)),
+        None => None,
+    };
+    if let Some(successor) = successor {
+        headers.insert(
+            HeaderName::from_static("deprecation"),
+            HeaderValue::from_static("true"),
+        );
+        let link = format!("<{}>; rel=\"successor-version\"", successor);
+        if let Ok(link) = HeaderValue::from_str(&link) {
+            headers.insert(HeaderName::from_static("link"), link);
+        }
+        let warning = format!("299 - \"Deprecated api, use {}\"", successor);
+        if let Ok(warning) = HeaderValue::from_str(&warning) {
+            headers.insert(HeaderName::from_static("warning"), warning);
+        }
+    }
+}
+
+fn is_unversioned(path: &str) -> bool {
+    UNVERSIONED_PATHS.contains(&path) || path.starts_with(PLUGINS_PATH)
+}
+
+// Splits "/v<version>/<route>" in its version and the path of the route
+fn split_version(path: &str) -> Option<(u32, &str)> {
+    let rest = path.strip_prefix("/v")?;
+    let end = rest.find('/').unwrap_or(rest.len());
+    let version = rest[..end].parse::<u32>().ok()?;
+
+    Some((version, &rest[end..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+
+    use super::*;
+
+    #[test]
+    fn should_find_the_path_of_the_route() {
+        assert_eq!(route_path("/v1/blocks"), "/blocks");
+        assert_eq!(route_path("/v12/transactions/1"), "/transactions/1");
+        assert_eq!(route_path("/blocks"), "/blocks");
+        assert_eq!(route_path("/version"), "/version");
+    }
+
+    #[test]
+    fn should_negotiate_the_version() {
+        let req = TestRequest::with_uri("/v1/status").to_srv_request();
+        let negotiated = negotiate(&req).unwrap().unwrap();
+        assert_eq!(negotiated.version, 1);
+        assert_eq!(negotiated.successor, None);
+
+        // the root routes are deprecated aliases of the current version
+        let req = TestRequest::with_uri("/status")
+            .header(API_VERSION_HEADER, "1")
+            .to_srv_request();
+        let negotiated = negotiate(&req).unwrap().unwrap();
+        assert_eq!(negotiated.successor.as_deref(), Some("/v1/status"));
+
+        // except the ones that are not part of the api
+        let req = TestRequest::with_uri("/ready").to_srv_request();
+        assert_eq!(negotiate(&req).unwrap(), None);
+        let req = TestRequest::with_uri("/plugins/indexer/items").to_srv_request();
+        assert_eq!(negotiate(&req).unwrap(), None);
+    }
+
+    #[test]
+    fn should_reject_unknown_versions() {
+        let req = TestRequest::with_uri("/v2/status").to_srv_request();
+        assert!(matches!(negotiate(&req), Err(ApiError::NotFound(_))));
+
+        let req = TestRequest::with_uri("/status")
+            .header(API_VERSION_HEADER, "2")
+            .to_srv_request();
+        assert!(matches!(negotiate(&req), Err(ApiError::BadRequest(_))));
+
+        let req = TestRequest::with_uri("/v1/status")
+            .header(API_VERSION_HEADER, "latest")
+            .to_srv_request();
+        assert!(matches!(negotiate(&req), Err(ApiError::BadRequest(_))));
+    }
+
+    #[test]
+    fn should_warn_about_deprecated_routes() {
+        let mut headers = HeaderMap::new();
+        let negotiated = Negotiated {
+            version: 1,
+            successor: Some("/v1/status".to_string()),
+        };
+        add_headers(&mut headers, &negotiated);
+
+        assert_eq!(headers.get("api-version").unwrap(), "1");
+        assert_eq!(headers.get("deprecation").unwrap(), "true");
+        assert_eq!(
+            headers.get("link").unwrap(),
+            "</v1/status>; rel=\"successor-version\""
+        );
+
+        let mut headers = HeaderMap::new();
+        let negotiated = Negotiated {
+            version: 1,
+            successor: None,
+        };
+        add_headers(&mut headers, &negotiated);
+        assert!(headers.get("deprecation").is_none());
+    }
+}
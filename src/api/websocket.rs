@@ -0,0 +1,264 @@
+use std::{cell::RefCell, collections::BTreeSet, rc::Rc, sync::Arc, time::Duration};
+
+use actix_codec::{Decoder, Encoder};
+use actix_http::ws::{self, CloseCode, CloseReason, Codec, Frame};
+use actix_web::{rt::time::delay_for, web, Error, HttpRequest};
+use bytes::BytesMut;
+use futures::{channel::mpsc, StreamExt};
+use serde::{Deserialize, Serialize};
+
+use super::{ApiError, ApiResult, ApiState};
+use crate::model::{Block, ChainEvent, ReorgEvent, Transaction};
+
+// Time interval to check for new events to push to the clients
+const EVENTS_CHECK_MS: u64 = 100;
+
+// Kinds of events that clients can subscribe to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum EventKind {
+    NewBlock,
+    NewTransaction,
+    Reorg,
+}
+
+// Messages sent by the clients
+#[derive(Debug, Deserialize)]
+#[serde(tag = "action", rename_all = "lowercase")]
+enum ClientRequest {
+    Subscribe { events: Vec<EventKind> },
+    Unsubscribe { events: Vec<EventKind> },
+}
+
+// Messages pushed to the clients
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", content = "data", rename_all = "camelCase")]
+enum ServerMessage {
+    // Confirms the events the client is subscribed to, after every request
+    Subscribed(BTreeSet<EventKind>),
+    NewBlock(Box<Block>),
+    NewTransaction(Box<Transaction>),
+    // The blocks after "fork_index" were replaced, clients must discard the reverted ones
+    Reorg(ReorgEvent),
+    // The last request of the client could not be understood
+    Error(String),
+}
+
+type Subscriptions = Rc<RefCell<BTreeSet<EventKind>>>;
+type Sender = mpsc::UnboundedSender<ws::Message>;
+
+// Upgrades the connection to a websocket, where the events the client subscribes to are pushed in real time
+// Two tasks are spawned: one reads the requests of the client, the other one pushes the events
+pub async fn websocket(
+    req: HttpRequest,
+    payload: web::Payload,
+    state: web::Data<ApiState>,
+) -> ApiResult {
+    let mut response =
+        ws::handshake(req.head()).map_err(|error| ApiError::BadRequest(error.to_string()))?;
+
+    let (sender, receiver) = mpsc::unbounded();
+    let subscriptions = Subscriptions::default();
+    actix_web::rt::spawn(read_requests(
+        payload,
+        sender.clone(),
+        subscriptions.clone(),
+    ));
+    actix_web::rt::spawn(push_events(state.into_inner(), sender, subscriptions));
+
+    // everything sent to the channel is encoded into websocket frames
+    let mut codec = Codec::new();
+    let frames = receiver.map(move |message| {
+        let mut buffer = BytesMut::new();
+        codec.encode(message, &mut buffer)?;
+        Ok::<_, Error>(buffer.freeze())
+    });
+
+    Ok(response.streaming(frames))
+}
+
+// Decode the frames sent by the client and answer them, until the connection is closed
+async fn read_requests(mut payload: web::Payload, sender: Sender, subscriptions: Subscriptions) {
+    let mut codec = Codec::new();
+    let mut buffer = BytesMut::new();
+    'connection: while let Some(Ok(chunk)) = payload.next().await {
+        buffer.extend_from_slice(&chunk);
+        loop {
+            match codec.decode(&mut buffer) {
+                Ok(Some(frame)) => {
+                    if !handle_frame(frame, &sender, &subscriptions) {
+                        break 'connection;
+                    }
+                }
+                Ok(None) => break,
+                Err(error) => {
+                    let reason = CloseReason {
+                        code: CloseCode::Protocol,
+                        description: Some(error.to_string()),
+                    };
+                    let _ = sender.unbounded_send(ws::Message::Close(Some(reason)));
+                    break 'connection;
+                }
+            }
+        }
+    }
+
+    // ends the response, closing the connection
+    sender.close_channel();
+}
+
+// Returns false if the client wants to close the connection
+fn handle_frame(frame: Frame, sender: &Sender, subscriptions: &Subscriptions) -> bool {
+    match frame {
+        Frame::Text(text) => {
+            let reply = match serde_json::from_slice::<ClientRequest>(&text) {
+                Ok(request) => {
+                    let mut subscriptions = subscriptions.borrow_mut();
+                    match request {
+                        ClientRequest::Subscribe { events } => subscriptions.extend(events),
+                        ClientRequest::Unsubscribe { events } => {
+                            subscriptions.retain(|kind| !events.contains(kind))
+                        }
+                    }
+                    ServerMessage::Subscribed(subscriptions.clone())
+                }
+                Err(error) => ServerMessage::Error(error.to_string()),
+            };
+            send(sender, &reply);
+        }
+        Frame::Ping(message) => {
+            let _ = sender.unbounded_send(ws::Message::Pong(message));
+        }
+        Frame::Close(reason) => {
+            let _ = sender.unbounded_send(ws::Message::Close(reason));
+            return false;
+        }
+        Frame::Binary(_) | Frame::Continuation(_) => {
+            let error = ServerMessage::Error("Only text messages are supported".to_string());
+            send(sender, &error);
+        }
+        Frame::Pong(_) => {}
+    }
+
+    true
+}
+
+// Pushes the events of the chain the client is subscribed to, checking for them periodically
+async fn push_events(state: Arc<ApiState>, sender: Sender, subscriptions: Subscriptions) {
+    // only what happens from now on is pushed
+    let events = state.blockchain.events().subscribe();
+
+    while !sender.is_closed() {
+        delay_for(Duration::from_millis(EVENTS_CHECK_MS)).await;
+
+        // let the clients know that the node is stopping, instead of keeping the api waiting
+        if state.shutdown.is_draining() {
+            let reason = CloseReason::from(CloseCode::Away);
+            let _ = sender.unbounded_send(ws::Message::Close(Some(reason)));
+            sender.close_channel();
+            return;
+        }
+
+        let subscriptions = subscriptions.borrow().clone();
+        for event in events.pending() {
+            let (kind, message) = match event {
+                ChainEvent::BlockAdded(block) => (
+                    EventKind::NewBlock,
+                    ServerMessage::NewBlock(Box::new(Block::clone(&block))),
+                ),
+                ChainEvent::TransactionAdmitted(transaction) => (
+                    EventKind::NewTransaction,
+                    ServerMessage::NewTransaction(Box::new(Transaction::clone(&transaction))),
+                ),
+                ChainEvent::Reorg(reorg) => (EventKind::Reorg, ServerMessage::Reorg(reorg)),
+            };
+            if subscriptions.contains(&kind) {
+                send(&sender, &message);
+            }
+        }
+    }
+}
+
+fn send(sender: &Sender, message: &ServerMessage) {
+    let text = serde_json::to_string(message).unwrap();
+    let _ = sender.unbounded_send(ws::Message::Text(text));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_manage_subscriptions() {
+        let (sender, mut receiver) = mpsc::unbounded();
+        let subscriptions = Subscriptions::default();
+
+        let request = r#"{"action":"subscribe","events":["newBlock","reorg"]}"#;
+        assert!(handle_frame(
+            Frame::Text(request.into()),
+            &sender,
+            &subscriptions
+        ));
+        let request = r#"{"action":"unsubscribe","events":["reorg"]}"#;
+        handle_frame(Frame::Text(request.into()), &sender, &subscriptions);
+
+        assert_eq!(
+            *subscriptions.borrow(),
+            vec![EventKind::NewBlock].into_iter().collect()
+        );
+        assert_eq!(
+            receive_text(&mut receiver),
+            r#"{"event":"subscribed","data":["newBlock","reorg"]}"#
+        );
+        assert_eq!(
+            receive_text(&mut receiver),
+            r#"{"event":"subscribed","data":["newBlock"]}"#
+        );
+    }
+
+    #[test]
+    fn should_report_invalid_requests() {
+        let (sender, mut receiver) = mpsc::unbounded();
+        let subscriptions = Subscriptions::default();
+
+        let request = r#"{"action":"subscribe","events":["unknown"]}"#;
+        handle_frame(Frame::Text(request.into()), &sender, &subscriptions);
+
+        assert!(subscriptions.borrow().is_empty());
+        assert!(receive_text(&mut receiver).starts_with(r#"{"event":"error""#));
+    }
+
+    #[test]
+    fn should_push_the_reverted_and_applied_blocks_of_reorgs() {
+        let (sender, mut receiver) = mpsc::unbounded();
+
+        let reorg = ReorgEvent {
+            fork_index: 1,
+            reverted: vec![2.into()],
+            applied: vec![3.into(), 4.into()],
+        };
+        send(&sender, &ServerMessage::Reorg(reorg));
+
+        let zeros = "0".repeat(63);
+        let expected = format!(
+            r#"{{"event":"reorg","data":{{"fork_index":1,"reverted":["{0}2"],"applied":["{0}3","{0}4"]}}}}"#,
+            zeros
+        );
+        assert_eq!(receive_text(&mut receiver), expected);
+    }
+
+    #[test]
+    fn should_close_when_the_client_does() {
+        let (sender, _) = mpsc::unbounded();
+        let subscriptions = Subscriptions::default();
+
+        assert!(!handle_frame(Frame::Close(None), &sender, &subscriptions));
+    }
+
+    fn receive_text(receiver: &mut mpsc::UnboundedReceiver<ws::Message>) -> String {
+        match receiver.try_next() {
+            Ok(Some(ws::Message::Text(text))) => text,
+            message => panic!("unexpected message {:?}", message),
+        }
+    }
+}
@@ -0,0 +1,252 @@
+mod fixtures;
+
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use crossbeam_utils::thread;
+use log::LevelFilter;
+
+use crate::{
+    consensus::{Consensus, ProofOfWork},
+    model::{Block, Transaction, TransactionPool},
+};
+
+// Every benchmark runs at least this many times, for the measurement time of the arguments
+const MIN_ITERATIONS: u32 = 10;
+
+// Leading zeros of the hashes found by the nonce search, around 4096 hashes per block
+const POW_DIFFICULTY: u32 = 12;
+
+// Threads adding transactions to the pool at the same time
+const MEMPOOL_THREADS: usize = 4;
+
+const BENCHMARKS: [&str; 5] = [
+    "block_hash",
+    "pow_nonce_search",
+    "chain_validation",
+    "mempool_contention",
+    "blocks_serialization",
+];
+
+// Size of the generated chain, and only run the benchmarks whose name contains the filter
+#[derive(Debug, PartialEq)]
+pub struct BenchArgs {
+    pub blocks: u64,
+    pub transactions: usize,
+    pub filter: Option<String>,
+    // how long each benchmark runs for
+    pub measurement_time: Duration,
+}
+
+impl Default for BenchArgs {
+    fn default() -> Self {
+        BenchArgs {
+            blocks: 100,
+            transactions: 50,
+            filter: None,
+            measurement_time: Duration::from_secs(1),
+        }
+    }
+}
+
+// Timings of the runs of a benchmark
+#[derive(Debug)]
+pub struct Measurement {
+    pub name: &'static str,
+    pub iterations: u32,
+    pub mean: Duration,
+    pub min: Duration,
+    pub max: Duration,
+    // what an iteration goes through (e.g. hashes or transactions), to report the throughput
+    pub items: u64,
+}
+
+impl Measurement {
+    fn items_per_sec(&self) -> f64 {
+        self.items as f64 / self.mean.as_secs_f64()
+    }
+}
+
+// Measures the hot paths of the node with a generated chain, so the effect of a change
+// (e.g. in the locks) can be compared by running it before and after
+pub fn run(args: &BenchArgs) -> Result<()> {
+    // the node logs every transaction and every seal, which would flood the results
+    log::set_max_level(LevelFilter::Warn);
+
+    println!(
+        "generating a chain of {} blocks with {} transactions each",
+        args.blocks, args.transactions
+    );
+    let measurements = run_benchmarks(args);
+
+    println!(
+        "{:<22} {:>10} {:>12} {:>12} {:>12} {:>14}",
+        "benchmark", "iterations", "mean", "min", "max", "items/sec"
+    );
+    for measurement in measurements {
+        println!(
+            "{:<22} {:>10} {:>12} {:>12} {:>12} {:>14.0}",
+            measurement.name,
+            measurement.iterations,
+            format!("{:.2?}", measurement.mean),
+            format!("{:.2?}", measurement.min),
+            format!("{:.2?}", measurement.max),
+            measurement.items_per_sec()
+        );
+    }
+
+    Ok(())
+}
+
+pub fn run_benchmarks(args: &BenchArgs) -> Vec<Measurement> {
+    let blocks = fixtures::create_chain(args.blocks, args.transactions);
+    let time = args.measurement_time;
+
+    BENCHMARKS
+        .iter()
+        .filter(|name| match &args.filter {
+            Some(filter) => name.contains(filter.as_str()),
+            None => true,
+        })
+        .map(|name| match *name {
+            "block_hash" => bench_block_hash(&blocks, time),
+            "pow_nonce_search" => bench_pow_nonce_search(&blocks, time),
+            "chain_validation" => bench_chain_validation(&blocks, time),
+            "mempool_contention" => bench_mempool_contention(args.transactions, time),
+            _ => bench_blocks_serialization(&blocks, time),
+        })
+        .collect()
+}
+
+// Hashing the header of a block, which is done for every nonce tried and every block received
+fn bench_block_hash(blocks: &[Block], time: Duration) -> Measurement {
+    let block = blocks.last().unwrap();
+
+    measure("block_hash", 1, time, || {
+        block.calculate_hash();
+    })
+}
+
+// Sealing a block with a single thread, the items are the hashes tried
+fn bench_pow_nonce_search(blocks: &[Block], time: Duration) -> Measurement {
+    let consensus = ProofOfWork::new(POW_DIFFICULTY, u64::MAX, 1);
+    let mut block = blocks.last().unwrap().clone();
+    block.header.bits = consensus.next_bits(&[]);
+
+    // the block is always the same, so every seal tries the same nonces
+    consensus.seal(block.clone(), &|| false);
+    let hashes = consensus.hashes_tried();
+
+    measure("pow_nonce_search", hashes, time, || {
+        consensus.seal(block.clone(), &|| false);
+    })
+}
+
+// Adding every block of the chain to a new blockchain in a batch, as when a node syncs from scratch
+fn bench_chain_validation(blocks: &[Block], time: Duration) -> Measurement {
+    let added = blocks.len() as u64 - 1;
+
+    measure("chain_validation", added, time, || {
+        let blockchain = fixtures::create_blockchain();
+        blockchain.add_blocks(blocks[1..].to_vec()).unwrap();
+    })
+}
+
+// Several threads adding transactions to the same pool, as the api and the p2p network do
+fn bench_mempool_contention(transactions: usize, time: Duration) -> Measurement {
+    let mut rng = fixtures::Rng::new(1);
+    let batches: Vec<Vec<Transaction>> = (0..MEMPOOL_THREADS)
+        .map(|index| {
+            let first_number = (index * transactions) as u64;
+            fixtures::create_transactions(&mut rng, first_number, transactions)
+        })
+        .collect();
+    let added = (MEMPOOL_THREADS * transactions) as u64;
+
+    measure("mempool_contention", added, time, || {
+        let pool = TransactionPool::new();
+        thread::scope(|s| {
+            for batch in batches.iter() {
+                let pool = &pool;
+                s.spawn(move |_| {
+                    for transaction in batch {
+                        pool.add_transaction(transaction.clone()).unwrap();
+                    }
+                });
+            }
+        })
+        .unwrap();
+    })
+}
+
+// Serializing the whole chain, as "GET /blocks" does
+fn bench_blocks_serialization(blocks: &[Block], time: Duration) -> Measurement {
+    measure("blocks_serialization", blocks.len() as u64, time, || {
+        serde_json::to_string(blocks).unwrap();
+    })
+}
+
+// Runs the routine once to warm up, and then as many times as it fits in the measurement time
+fn measure(
+    name: &'static str,
+    items: u64,
+    time: Duration,
+    mut routine: impl FnMut(),
+) -> Measurement {
+    routine();
+
+    let mut timings = Vec::new();
+    let start = Instant::now();
+    while timings.len() < MIN_ITERATIONS as usize || start.elapsed() < time {
+        let iteration_start = Instant::now();
+        routine();
+        timings.push(iteration_start.elapsed());
+    }
+
+    let iterations = timings.len() as u32;
+    Measurement {
+        name,
+        iterations,
+        mean: timings.iter().sum::<Duration>() / iterations,
+        min: timings.iter().min().copied().unwrap(),
+        max: timings.iter().max().copied().unwrap(),
+        items,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_run_the_benchmarks() {
+        let args = BenchArgs {
+            blocks: 2,
+            transactions: 2,
+            filter: None,
+            measurement_time: Duration::from_millis(10),
+        };
+        let measurements = run_benchmarks(&args);
+
+        let names: Vec<&str> = measurements.iter().map(|m| m.name).collect();
+        assert_eq!(names, BENCHMARKS.to_vec());
+        for measurement in measurements {
+            assert!(measurement.iterations >= MIN_ITERATIONS);
+            assert!(measurement.min <= measurement.mean && measurement.mean <= measurement.max);
+            assert!(measurement.items > 0);
+        }
+    }
+
+    #[test]
+    fn should_only_run_the_filtered_benchmarks() {
+        let args = BenchArgs {
+            blocks: 1,
+            transactions: 1,
+            filter: Some("hash".to_string()),
+            measurement_time: Duration::from_millis(10),
+        };
+        let names: Vec<&str> = run_benchmarks(&args).iter().map(|m| m.name).collect();
+
+        assert_eq!(names, vec!["block_hash"]);
+    }
+}
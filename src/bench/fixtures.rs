@@ -0,0 +1,126 @@
+use crypto::ed25519;
+
+use crate::{
+    consensus::ProofOfWork,
+    model::{Block, Blockchain, Transaction, DEFAULT_CHAIN_ID},
+};
+
+// Number of different addresses sending coins to each other in the generated chains
+const ADDRESSES: u64 = 100;
+
+// Generates the same sequence of numbers for the same seed, so every run measures the same data
+#[derive(Debug)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        // xorshift gets stuck with a zero state
+        Rng(seed.max(1))
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+// Seed of the keys of an address, so the transfers it sends can be signed
+fn seed(number: u64) -> [u8; 32] {
+    let mut seed = [0; 32];
+    seed[24..].copy_from_slice(&number.to_be_bytes());
+    seed
+}
+
+// Address with the same format as the ones of wallets (a hex-encoded ed25519 public key)
+pub fn address(number: u64) -> String {
+    hex::encode(ed25519::keypair(&seed(number)).1)
+}
+
+// Signed transfers between random addresses, with different amounts so all of them have a different id
+pub fn create_transactions(rng: &mut Rng, first_number: u64, count: usize) -> Vec<Transaction> {
+    (first_number..first_number + count as u64)
+        .map(|number| {
+            let sender = rng.next_u64() % ADDRESSES;
+            let mut transaction = Transaction {
+                sender: String::new(),
+                recipient: address(rng.next_u64() % ADDRESSES),
+                amount: number + 1,
+                signature: None,
+                multisig: None,
+                contract: None,
+                token: None,
+                data: None,
+            };
+            transaction.sign(&seed(sender), DEFAULT_CHAIN_ID);
+            transaction
+        })
+        .collect()
+}
+
+// Blockchain that accepts any hash, so valid chains can be built without mining
+pub fn create_blockchain() -> Blockchain {
+    Blockchain::new(ProofOfWork::shared(0, u64::MAX, 1))
+}
+
+// Valid chain with "length" blocks after the genesis, each one with the same number of transactions
+// The blocks are added to a blockchain as they are created, so they have the right roots and timestamps
+pub fn create_chain(length: u64, transactions_per_block: usize) -> Vec<Block> {
+    let blockchain = create_blockchain();
+    let mut rng = Rng::new(length);
+
+    for _ in 0..length {
+        let last_block = blockchain.get_last_block();
+        let first_number = last_block.header.index * transactions_per_block as u64;
+        let transactions = create_transactions(&mut rng, first_number, transactions_per_block);
+        let block = create_next_block(&blockchain, transactions);
+        blockchain.add_block(block).unwrap();
+    }
+
+    blockchain.get_all_blocks()
+}
+
+// Block on top of the last one of the blockchain, ready to be added to it
+pub fn create_next_block(blockchain: &Blockchain, transactions: Vec<Transaction>) -> Block {
+    let last_block = blockchain.get_last_block();
+    let mut block = Block::new(
+        last_block.header.index + 1,
+        0,
+        last_block.header.hash,
+        transactions,
+    );
+    block.header.timestamp = block.header.timestamp.max(blockchain.min_timestamp());
+    block.header.bits = blockchain.next_bits();
+    block.header.state_root = blockchain.next_state_root(&block.transactions);
+    block.header.hash = block.calculate_hash();
+
+    block
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_create_valid_chains() {
+        let blocks = create_chain(5, 3);
+        assert_eq!(blocks.len(), 6);
+        assert!(blocks[1..]
+            .iter()
+            .all(|block| block.transactions.len() == 3));
+
+        // the chain can be validated again from the genesis
+        let blockchain = create_blockchain();
+        for block in blocks.into_iter().skip(1) {
+            blockchain.add_block(block).unwrap();
+        }
+        assert_eq!(blockchain.get_last_block().header.index, 5);
+
+        // and it's always the same one
+        assert_eq!(
+            create_chain(5, 3)[5].header.merkle_root,
+            create_chain(5, 3)[5].header.merkle_root
+        );
+    }
+}
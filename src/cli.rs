@@ -0,0 +1,748 @@
+use std::{collections::HashMap, env, fs, io::Read, path::Path};
+
+use anyhow::{Context as _, Result};
+use crypto::ed25519;
+use isahc::{Body, ReadResponseExt, Request, Response};
+use serde_json::Value;
+use thiserror::Error;
+
+use crate::{
+    bench::{self, BenchArgs},
+    compare::{self, CompareArgs},
+    model::{format_amount, parse_amount, Block, MultiSig, Snapshot, Transaction},
+    util::Config,
+    wallet::{decode_address, encode_address, Address},
+};
+
+pub const USAGE: &str = "usage: rust_blockchain <command> [arguments]
+
+commands:
+  node run [--port <port>] [--data-dir <dir>] [--peers <url,url>] [--snapshot <file>]
+                                  start the node (the default without a command)
+  wallet new                      generate a new key pair
+  wallet balance <address> [--node <url>]
+                                  show the balance of an address
+  wallet send --to <address> --amount <amount> [--node <url>]
+                                  sign a transaction with WALLET_SEED and submit it
+  wallet multisig --threshold <m> <address,address,...>
+                                  show the address spendable by m of the keys of the addresses
+  wallet cosign <file> [--node <url>]
+                                  sign a multisig transaction with WALLET_SEED, and submit it
+                                  once it has enough signatures
+  chain export [--output <file>] [--node <url>]
+                                  write the blocks of a node as json
+  chain import <file> [--node <url>]
+                                  send the blocks of an export to a node
+  chain snapshot [--height <index>] [--output <file>] [--node <url>]
+                                  write the state of a node at a final block, to start new nodes from it
+  mine once [--node <url>]        mine a block in a node and show it
+  compare --remote <url> [--local <url>]
+                                  report the differences between the chains of two nodes
+  bench [<name>] [--blocks <count>] [--transactions <count>]
+                                  measure the hot paths of the node with a generated chain
+  help                            show this message
+
+commands talking to a node use the one in this machine with the configured PORT by default
+and send the configured API_KEY, if any
+amounts are read and shown with AMOUNT_DECIMALS decimals, e.g. `--amount 1.5` with 2 is 150 units";
+
+// Error types to return when the command line is not valid or a command can not be performed
+#[derive(Error, PartialEq, Debug)]
+pub enum CliError {
+    #[error("Unknown command `{0}`, run `help` to see the available commands")]
+    UnknownCommand(String),
+
+    #[error("Missing value for argument `{0}`")]
+    MissingValue(String),
+
+    #[error("Unknown argument `{0}`, run `help` to see the arguments of each command")]
+    UnknownArgument(String),
+
+    #[error("Invalid value `{1}` for argument `{0}`")]
+    InvalidValue(String, String),
+
+    #[error("The `{0}` argument is required, run `help` to see the arguments of each command")]
+    MissingArgument(String),
+
+    #[error("WALLET_SEED must be the hex-encoded 32 bytes seed of the sender")]
+    InvalidSeed,
+
+    #[error("{0} is not a transaction with the keys of the multisig address that sends it")]
+    NotMultiSig(String),
+
+    #[error("Node {0} replied with status {1}: {2}")]
+    BadResponse(String, u16, String),
+}
+
+// Settings of the node that can be overridden from the command line
+#[derive(Debug, Default, PartialEq)]
+pub struct NodeArgs {
+    pub port: Option<u16>,
+    pub data_dir: Option<String>,
+    pub peers: Option<Vec<String>>,
+    pub snapshot: Option<String>,
+}
+
+impl NodeArgs {
+    // Flags take precedence over the environment, so a node can be tried out without editing it
+    pub fn apply(&self, config: &mut Config) {
+        if let Some(port) = self.port {
+            config.port = port;
+        }
+
+        if let Some(peers) = &self.peers {
+            config.peers = peers.clone();
+        }
+
+        if let Some(data_dir) = &self.data_dir {
+            config.data_dir = data_dir.clone();
+        }
+
+        if let Some(snapshot) = &self.snapshot {
+            config.snapshot_path = snapshot.clone();
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Help,
+    Run(NodeArgs),
+    WalletNew,
+    WalletBalance {
+        node: String,
+        address: String,
+    },
+    WalletSend {
+        node: String,
+        recipient: String,
+        // parsed when running the command, with the configured decimals
+        amount: String,
+    },
+    WalletMultiSig {
+        threshold: usize,
+        public_keys: Vec<String>,
+    },
+    WalletCosign {
+        node: String,
+        input: String,
+    },
+    ChainExport {
+        node: String,
+        output: Option<String>,
+    },
+    ChainImport {
+        node: String,
+        input: String,
+    },
+    ChainSnapshot {
+        node: String,
+        height: Option<u64>,
+        output: Option<String>,
+    },
+    MineOnce {
+        node: String,
+    },
+    Compare(CompareArgs),
+    Bench(BenchArgs),
+}
+
+impl Command {
+    // Parses the arguments of the binary, without the name of the program
+    // Commands that talk to a node use the one running in this machine with the configured port
+    pub fn parse(args: &[String], port: u16) -> Result<Command> {
+        let local_node = format!("http://localhost:{}", port);
+        let command = args.iter().take(2).map(String::as_str).collect::<Vec<_>>();
+
+        let command = match command.as_slice() {
+            [] => Command::Run(NodeArgs::default()),
+            ["help"] | ["--help"] | ["-h"] => Command::Help,
+            ["compare", ..] => Command::Compare(CompareArgs::parse(&args[1..], port)?),
+            ["bench", ..] => {
+                let args = Arguments::parse(&args[1..], &["--blocks", "--transactions"])?;
+                let defaults = BenchArgs::default();
+                Command::Bench(BenchArgs {
+                    blocks: args.parse_flag("--blocks")?.unwrap_or(defaults.blocks),
+                    transactions: args
+                        .parse_flag("--transactions")?
+                        .unwrap_or(defaults.transactions),
+                    filter: args.positional.clone(),
+                    ..defaults
+                })
+            }
+            ["node", "run"] => {
+                let args = Arguments::parse(
+                    &args[2..],
+                    &["--port", "--data-dir", "--peers", "--snapshot"],
+                )?;
+                Command::Run(NodeArgs {
+                    port: args.parse_flag("--port")?,
+                    data_dir: args.flag("--data-dir"),
+                    peers: args
+                        .flag("--peers")
+                        .map(|peers| peers.split_terminator(',').map(str::to_string).collect()),
+                    snapshot: args.flag("--snapshot"),
+                })
+            }
+            ["wallet", "new"] => {
+                Arguments::parse(&args[2..], &[])?;
+                Command::WalletNew
+            }
+            ["wallet", "balance"] => {
+                let args = Arguments::parse(&args[2..], &["--node"])?;
+                Command::WalletBalance {
+                    node: args.node(&local_node),
+                    address: args.positional("<address>")?,
+                }
+            }
+            ["wallet", "send"] => {
+                let args = Arguments::parse(&args[2..], &["--node", "--to", "--amount"])?;
+                Command::WalletSend {
+                    node: args.node(&local_node),
+                    recipient: args.required_flag("--to")?,
+                    amount: args.required_flag("--amount")?,
+                }
+            }
+            ["wallet", "multisig"] => {
+                let args = Arguments::parse(&args[2..], &["--threshold"])?;
+                Command::WalletMultiSig {
+                    threshold: args
+                        .parse_flag("--threshold")?
+                        .ok_or_else(|| CliError::MissingArgument("--threshold".to_string()))?,
+                    public_keys: args
+                        .positional("<key,key,...>")?
+                        .split_terminator(',')
+                        .map(str::to_string)
+                        .collect(),
+                }
+            }
+            ["wallet", "cosign"] => {
+                let args = Arguments::parse(&args[2..], &["--node"])?;
+                Command::WalletCosign {
+                    node: args.node(&local_node),
+                    input: args.positional("<file>")?,
+                }
+            }
+            ["chain", "export"] => {
+                let args = Arguments::parse(&args[2..], &["--node", "--output"])?;
+                Command::ChainExport {
+                    node: args.node(&local_node),
+                    output: args.flag("--output"),
+                }
+            }
+            ["chain", "import"] => {
+                let args = Arguments::parse(&args[2..], &["--node"])?;
+                Command::ChainImport {
+                    node: args.node(&local_node),
+                    input: args.positional("<file>")?,
+                }
+            }
+            ["chain", "snapshot"] => {
+                let args = Arguments::parse(&args[2..], &["--node", "--height", "--output"])?;
+                Command::ChainSnapshot {
+                    node: args.node(&local_node),
+                    height: args.parse_flag("--height")?,
+                    output: args.flag("--output"),
+                }
+            }
+            ["mine", "once"] => {
+                let args = Arguments::parse(&args[2..], &["--node"])?;
+                Command::MineOnce {
+                    node: args.node(&local_node),
+                }
+            }
+            _ => return Err(CliError::UnknownCommand(args.join(" ")).into()),
+        };
+
+        Ok(command)
+    }
+}
+
+// Arguments following a command: flags with a value ("--name value") and at most one positional
+struct Arguments {
+    flags: HashMap<String, String>,
+    positional: Option<String>,
+}
+
+impl Arguments {
+    fn parse(args: &[String], flag_names: &[&str]) -> Result<Arguments> {
+        let mut flags = HashMap::new();
+        let mut positional = None;
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if flag_names.contains(&arg.as_str()) {
+                let value = args
+                    .next()
+                    .ok_or_else(|| CliError::MissingValue(arg.to_string()))?;
+                flags.insert(arg.to_string(), value.to_string());
+            } else if arg.starts_with('-') || positional.is_some() {
+                return Err(CliError::UnknownArgument(arg.to_string()).into());
+            } else {
+                positional = Some(arg.to_string());
+            }
+        }
+
+        Ok(Arguments { flags, positional })
+    }
+
+    fn flag(&self, name: &str) -> Option<String> {
+        self.flags.get(name).cloned()
+    }
+
+    fn required_flag(&self, name: &str) -> Result<String> {
+        self.flag(name)
+            .ok_or_else(|| CliError::MissingArgument(name.to_string()).into())
+    }
+
+    fn parse_flag<T: std::str::FromStr>(&self, name: &str) -> Result<Option<T>> {
+        match self.flags.get(name) {
+            Some(value) => match value.parse() {
+                Ok(value) => Ok(Some(value)),
+                Err(_) => Err(CliError::InvalidValue(name.to_string(), value.to_string()).into()),
+            },
+            None => Ok(None),
+        }
+    }
+
+    fn positional(&self, name: &str) -> Result<String> {
+        self.positional
+            .clone()
+            .ok_or_else(|| CliError::MissingArgument(name.to_string()).into())
+    }
+
+    fn node(&self, local_node: &str) -> String {
+        self.flag("--node")
+            .unwrap_or_else(|| local_node.to_string())
+            .trim_end_matches('/')
+            .to_string()
+    }
+}
+
+// Runs any command but "run", which starts the node instead
+pub fn run(command: Command, config: &Config) -> Result<()> {
+    let client = NodeClient {
+        api_key: config.api_key.clone(),
+    };
+
+    match command {
+        Command::Help | Command::Run(_) => println!("{}", USAGE),
+        Command::WalletNew => new_wallet()?,
+        Command::WalletBalance { node, address } => {
+            let address = decode_address(&address)?;
+            let balance = client.get(&format!("{}/v1/addresses/{}/balance", node, address))?;
+            println!("{}", serde_json::to_string_pretty(&balance)?);
+            for kind in ["confirmed", "pending"] {
+                // balances go from -u64::MAX to u64::MAX
+                let amount = &balance[kind]["balance"];
+                let amount = (amount.as_i64().map(i128::from))
+                    .or_else(|| amount.as_u64().map(i128::from))
+                    .unwrap_or_default();
+                let amount = format_amount(amount, config.amount_decimals);
+                println!("{} balance: {}", kind, amount);
+            }
+        }
+        Command::WalletSend {
+            node,
+            recipient,
+            amount,
+        } => {
+            let amount = parse_amount(&amount, config.amount_decimals)?;
+            send_transaction(&client, &node, &config.chain_id, recipient, amount)?
+        }
+        Command::WalletMultiSig {
+            threshold,
+            public_keys,
+        } => {
+            let multisig = MultiSig {
+                threshold,
+                public_keys: decode_addresses(&public_keys)?,
+                signatures: Default::default(),
+            };
+            println!("address: {}", encode_address(&multisig.address()?));
+        }
+        Command::WalletCosign { node, input } => {
+            cosign_transaction(&client, &node, &config.chain_id, &input)?
+        }
+        Command::ChainExport { node, output } => export_chain(&client, &node, output)?,
+        Command::ChainImport { node, input } => import_chain(&client, &node, &input)?,
+        Command::ChainSnapshot {
+            node,
+            height,
+            output,
+        } => export_snapshot(&client, &node, height, output)?,
+        Command::MineOnce { node } => {
+            let block = client.post(&format!("{}/v1/mine?wait=true", node), "")?;
+            println!("{}", serde_json::to_string_pretty(&block)?);
+        }
+        Command::Compare(args) => compare::run(&args)?,
+        Command::Bench(args) => bench::run(&args)?,
+    }
+
+    Ok(())
+}
+
+// Keys are generated outside of the node, so they never need to be stored in it
+fn new_wallet() -> Result<()> {
+    let mut seed = [0; 32];
+    fs::File::open("/dev/urandom")
+        .and_then(|mut file| file.read_exact(&mut seed))
+        .context("could not read random bytes for the seed")?;
+    let (_, public_key) = ed25519::keypair(&seed);
+
+    // the address is the public key, with a checksum to share it safely
+    let mut key = [0; 32];
+    key.copy_from_slice(&public_key);
+    println!("address:    {}", Address::Key(key));
+    println!("public key: {}", hex::encode(public_key));
+    println!("seed:       {}", hex::encode(seed));
+    println!("keep the seed secret, it's needed to spend from the address (as WALLET_SEED)");
+    Ok(())
+}
+
+// The seed comes from the environment instead of a flag, so it doesn't end up in shell histories
+fn wallet_seed() -> Result<Vec<u8>> {
+    let seed = env::var("WALLET_SEED").unwrap_or_default();
+    match hex::decode(seed.trim()) {
+        Ok(seed) if seed.len() == 32 => Ok(seed),
+        _ => Err(CliError::InvalidSeed.into()),
+    }
+}
+
+// Transactions are signed for the network of CHAIN_ID, as nodes of other networks reject them
+fn send_transaction(
+    client: &NodeClient,
+    node: &str,
+    chain_id: &str,
+    recipient: String,
+    amount: u64,
+) -> Result<()> {
+    let seed = wallet_seed()?;
+    let mut transaction = Transaction {
+        sender: String::new(),
+        recipient: decode_address(&recipient)?,
+        amount,
+        signature: None,
+        multisig: None,
+        contract: None,
+        token: None,
+        data: None,
+    };
+    transaction.sign(&seed, chain_id);
+
+    submit_transaction(client, node, &transaction)
+}
+
+// The owners of a multisig address pass the file around, each of them adding their signature
+// The last one needed submits the transaction, so no one has to hold every key
+fn cosign_transaction(client: &NodeClient, node: &str, chain_id: &str, input: &str) -> Result<()> {
+    let seed = wallet_seed()?;
+    let json = fs::read_to_string(input).with_context(|| format!("could not read {}", input))?;
+    let mut transaction: Transaction =
+        serde_json::from_str(&json).with_context(|| format!("could not parse {}", input))?;
+    // the file may have checksummed addresses, but signatures are over the raw ones
+    transaction.sender = decode_address(&transaction.sender)?;
+    transaction.recipient = decode_address(&transaction.recipient)?;
+    if let Some(multisig) = transaction.multisig.as_mut() {
+        multisig.public_keys = decode_addresses(&multisig.public_keys)?;
+    }
+    // the keys must be the ones of the sender, or the signature would be wasted
+    match &transaction.multisig {
+        Some(multisig) if multisig.address()? == transaction.sender => {}
+        _ => return Err(CliError::NotMultiSig(input.to_string()).into()),
+    }
+
+    transaction.sign_multisig(&seed, chain_id);
+    fs::write(input, serde_json::to_string_pretty(&transaction)?)
+        .with_context(|| format!("could not write {}", input))?;
+
+    let multisig = transaction.multisig.as_ref().unwrap();
+    if multisig.signatures.len() < multisig.threshold {
+        println!(
+            "signed, {} of {} signatures",
+            multisig.signatures.len(),
+            multisig.threshold
+        );
+        return Ok(());
+    }
+    submit_transaction(client, node, &transaction)
+}
+
+// The public keys of the addresses of single keys are the addresses themselves
+fn decode_addresses(addresses: &[String]) -> Result<Vec<String>> {
+    let addresses = addresses.iter().map(|address| decode_address(address));
+    Ok(addresses.collect::<Result<_, _>>()?)
+}
+
+fn submit_transaction(client: &NodeClient, node: &str, transaction: &Transaction) -> Result<()> {
+    let body = serde_json::to_string(transaction)?;
+    let id = client.post(&format!("{}/v1/transactions", node), &body)?;
+    println!(
+        "submitted transaction {}",
+        id["id"].as_str().unwrap_or_default()
+    );
+    Ok(())
+}
+
+fn export_chain(client: &NodeClient, node: &str, output: Option<String>) -> Result<()> {
+    let blocks = client.get(&format!("{}/v1/blocks", node))?;
+    let blocks: Vec<Block> = serde_json::from_value(blocks)
+        .with_context(|| format!("could not parse the blocks of {}", node))?;
+    let json = serde_json::to_string_pretty(&blocks)?;
+
+    match output {
+        Some(path) => {
+            fs::write(&path, json).with_context(|| format!("could not write {}", path))?;
+            eprintln!("exported {} blocks to {}", blocks.len(), path);
+        }
+        None => println!("{}", json),
+    }
+    Ok(())
+}
+
+// Only the blocks that the node doesn't have yet are sent, in order
+// The node validates each one as if it was mined by a peer, so an export can't alter its chain
+fn import_chain(client: &NodeClient, node: &str, input: &str) -> Result<()> {
+    let json = fs::read_to_string(input).with_context(|| format!("could not read {}", input))?;
+    let blocks: Vec<Block> =
+        serde_json::from_str(&json).with_context(|| format!("could not parse {}", input))?;
+
+    let existing: Vec<Block> = serde_json::from_value(client.get(&format!("{}/v1/blocks", node))?)
+        .with_context(|| format!("could not parse the blocks of {}", node))?;
+
+    let mut imported = 0;
+    for block in blocks.iter() {
+        let known = existing.get(block.header.index as usize);
+        if known.is_some_and(|known| known.header.hash == block.header.hash) {
+            continue;
+        }
+
+        let body = serde_json::to_string(block)?;
+        client
+            .post(&format!("{}/v1/blocks", node), &body)
+            .with_context(|| format!("could not import block {}", block.header.index))?;
+        imported += 1;
+    }
+
+    println!("imported {} of {} blocks", imported, blocks.len());
+    Ok(())
+}
+
+// The node takes the snapshot at its safe block unless told otherwise, so it's not undone by a fork
+fn export_snapshot(
+    client: &NodeClient,
+    node: &str,
+    height: Option<u64>,
+    output: Option<String>,
+) -> Result<()> {
+    let uri = match height {
+        Some(height) => format!("{}/v1/snapshot?height={}", node, height),
+        None => format!("{}/v1/snapshot", node),
+    };
+    let snapshot: Snapshot = serde_json::from_value(client.get(&uri)?)
+        .with_context(|| format!("could not parse the snapshot of {}", node))?;
+
+    match output {
+        Some(path) => {
+            snapshot.save(Path::new(&path))?;
+            eprintln!(
+                "exported the snapshot at height {} (block {:#x}) to {}",
+                snapshot.height, snapshot.tip, path
+            );
+        }
+        None => println!("{}", serde_json::to_string(&snapshot)?),
+    }
+    Ok(())
+}
+
+// Sends requests to the api of a node, with the api key when there is one
+struct NodeClient {
+    api_key: String,
+}
+
+impl NodeClient {
+    fn get(&self, uri: &str) -> Result<Value> {
+        let request = self.authenticated(Request::get(uri)).body(())?;
+        let response = isahc::send(request).with_context(|| format!("could not reach {}", uri))?;
+        read_json(uri, response)
+    }
+
+    fn post(&self, uri: &str, body: &str) -> Result<Value> {
+        let request = self
+            .authenticated(Request::post(uri))
+            .header("Content-Type", "application/json")
+            .body(body.to_string())?;
+        let response = isahc::send(request).with_context(|| format!("could not reach {}", uri))?;
+        read_json(uri, response)
+    }
+
+    fn authenticated(
+        &self,
+        request: isahc::http::request::Builder,
+    ) -> isahc::http::request::Builder {
+        if self.api_key.is_empty() {
+            return request;
+        }
+
+        request.header("X-Api-Key", &self.api_key)
+    }
+}
+
+// Responses without a body are null, errors carry the message of the node
+fn read_json(uri: &str, mut response: Response<Body>) -> Result<Value> {
+    let status = response.status().as_u16();
+    let text = response.text()?;
+    if !(200..300).contains(&status) {
+        let body: Value = serde_json::from_str(&text).unwrap_or_default();
+        let message = body["message"].as_str().unwrap_or(&text).to_string();
+        return Err(CliError::BadResponse(uri.to_string(), status, message).into());
+    }
+
+    if text.is_empty() {
+        return Ok(Value::Null);
+    }
+    serde_json::from_str(&text).with_context(|| format!("could not parse the response of {}", uri))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_run_the_node_by_default() {
+        assert_eq!(
+            Command::parse(&[], 8000).unwrap(),
+            Command::Run(NodeArgs::default())
+        );
+
+        let args = to_args(&[
+            "node",
+            "run",
+            "--port",
+            "9000",
+            "--peers",
+            "http://a:1,http://b:2",
+        ]);
+        assert_eq!(
+            Command::parse(&args, 8000).unwrap(),
+            Command::Run(NodeArgs {
+                port: Some(9000),
+                data_dir: None,
+                peers: Some(vec!["http://a:1".to_string(), "http://b:2".to_string()]),
+                snapshot: None,
+            })
+        );
+    }
+
+    #[test]
+    fn should_parse_commands() {
+        let args = to_args(&["wallet", "balance", "abc"]);
+        assert_eq!(
+            Command::parse(&args, 8000).unwrap(),
+            Command::WalletBalance {
+                node: "http://localhost:8000".to_string(),
+                address: "abc".to_string(),
+            }
+        );
+
+        let args = to_args(&[
+            "wallet",
+            "send",
+            "--to",
+            "abc",
+            "--amount",
+            "5",
+            "--node",
+            "http://a:1/",
+        ]);
+        assert_eq!(
+            Command::parse(&args, 8000).unwrap(),
+            Command::WalletSend {
+                node: "http://a:1".to_string(),
+                recipient: "abc".to_string(),
+                amount: "5".to_string(),
+            }
+        );
+
+        let args = to_args(&["wallet", "multisig", "--threshold", "2", "a,b,c"]);
+        assert_eq!(
+            Command::parse(&args, 8000).unwrap(),
+            Command::WalletMultiSig {
+                threshold: 2,
+                public_keys: vec!["a".to_string(), "b".to_string(), "c".to_string()],
+            }
+        );
+
+        let args = to_args(&["chain", "import", "chain.json"]);
+        assert_eq!(
+            Command::parse(&args, 9000).unwrap(),
+            Command::ChainImport {
+                node: "http://localhost:9000".to_string(),
+                input: "chain.json".to_string(),
+            }
+        );
+
+        let args = to_args(&["chain", "snapshot", "--height", "10"]);
+        assert_eq!(
+            Command::parse(&args, 9000).unwrap(),
+            Command::ChainSnapshot {
+                node: "http://localhost:9000".to_string(),
+                height: Some(10),
+                output: None,
+            }
+        );
+
+        let args = to_args(&["bench", "hash", "--blocks", "10"]);
+        assert_eq!(
+            Command::parse(&args, 8000).unwrap(),
+            Command::Bench(BenchArgs {
+                blocks: 10,
+                filter: Some("hash".to_string()),
+                ..BenchArgs::default()
+            })
+        );
+
+        let args = to_args(&["compare", "--remote", "http://a:1"]);
+        assert!(matches!(
+            Command::parse(&args, 8000).unwrap(),
+            Command::Compare(_)
+        ));
+    }
+
+    #[test]
+    fn should_reject_invalid_arguments() {
+        assert_err(
+            Command::parse(&to_args(&["wallet"]), 8000),
+            CliError::UnknownCommand("wallet".to_string()),
+        );
+        assert_err(
+            Command::parse(&to_args(&["node", "run", "--port", "foo"]), 8000),
+            CliError::InvalidValue("--port".to_string(), "foo".to_string()),
+        );
+        assert_err(
+            Command::parse(&to_args(&["node", "run", "--port"]), 8000),
+            CliError::MissingValue("--port".to_string()),
+        );
+        assert_err(
+            Command::parse(&to_args(&["mine", "once", "--foo"]), 8000),
+            CliError::UnknownArgument("--foo".to_string()),
+        );
+        assert_err(
+            Command::parse(&to_args(&["wallet", "send", "--to", "abc"]), 8000),
+            CliError::MissingArgument("--amount".to_string()),
+        );
+        assert_err(
+            Command::parse(&to_args(&["wallet", "balance"]), 8000),
+            CliError::MissingArgument("<address>".to_string()),
+        );
+    }
+
+    fn to_args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    fn assert_err(result: Result<Command>, error_type: CliError) {
+        let err = result.unwrap_err().downcast::<CliError>().unwrap();
+        assert_eq!(err, error_type);
+    }
+}
@@ -0,0 +1,320 @@
+use anyhow::{Context as _, Result};
+use isahc::ReadResponseExt;
+use serde_json::Value;
+use thiserror::Error;
+
+use crate::model::Block;
+
+// Error types to return when the comparison can not be performed
+#[derive(Error, PartialEq, Debug)]
+pub enum CompareError {
+    #[error("Missing value for argument `{0}`")]
+    MissingValue(String),
+
+    #[error("Unknown argument `{0}`, usage: compare --remote <url> [--local <url>]")]
+    UnknownArgument(String),
+
+    #[error("The `--remote` argument is required, usage: compare --remote <url> [--local <url>]")]
+    MissingRemote,
+
+    #[error("Node {0} replied with status {1}")]
+    BadResponse(String, u16),
+}
+
+// Addresses of the two nodes to compare
+#[derive(Debug, PartialEq)]
+pub struct CompareArgs {
+    pub local: String,
+    pub remote: String,
+}
+
+impl CompareArgs {
+    // Parses the arguments following the "compare" command
+    // The local node defaults to the one running in this machine with the configured port
+    pub fn parse(args: &[String], port: u16) -> Result<CompareArgs> {
+        let mut local = format!("http://localhost:{}", port);
+        let mut remote = None;
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .map(|value| value.trim_end_matches('/').to_string())
+                    .ok_or_else(|| CompareError::MissingValue(arg.to_string()))
+            };
+
+            match arg.as_str() {
+                "--remote" => remote = Some(value()?),
+                "--local" => local = value()?,
+                _ => return Err(CompareError::UnknownArgument(arg.to_string()).into()),
+            }
+        }
+
+        match remote {
+            Some(remote) => Ok(CompareArgs { local, remote }),
+            None => Err(CompareError::MissingRemote.into()),
+        }
+    }
+}
+
+// Blocks at the same index that do not match in both chains
+// A missing block means that the chain is shorter than the other one
+#[derive(Debug)]
+pub struct BlockDifference {
+    pub index: u64,
+    pub local: Option<Block>,
+    pub remote: Option<Block>,
+}
+
+// Result of comparing two chains
+#[derive(Debug)]
+pub struct ChainComparison {
+    // Index of the last block that both chains have in common, none if even the genesis differs
+    pub common_index: Option<u64>,
+    pub differences: Vec<BlockDifference>,
+}
+
+impl ChainComparison {
+    // Walks both chains from the tip until finding the last block in common
+    // Blocks are linked by their hashes, so every block before it is also shared
+    pub fn new(local: &[Block], remote: &[Block]) -> ChainComparison {
+        let shared_height = local.len().min(remote.len());
+        let common_position = (0..shared_height)
+            .rev()
+            .find(|&position| local[position].header.hash == remote[position].header.hash);
+
+        let first_different = common_position.map_or(0, |position| position + 1);
+        let last_position = local.len().max(remote.len());
+        let differences = (first_different..last_position)
+            .map(|position| BlockDifference {
+                index: position as u64,
+                local: local.get(position).cloned(),
+                remote: remote.get(position).cloned(),
+            })
+            .collect();
+
+        ChainComparison {
+            common_index: common_position.map(|position| position as u64),
+            differences,
+        }
+    }
+
+    // The chains forked if they have different blocks at the same index,
+    // otherwise one of them is just behind the other
+    pub fn fork_index(&self) -> Option<u64> {
+        self.differences
+            .iter()
+            .find(|difference| difference.local.is_some() && difference.remote.is_some())
+            .map(|difference| difference.index)
+    }
+}
+
+// Compares the chains of two nodes and prints a report of the differences
+pub fn run(args: &CompareArgs) -> Result<()> {
+    let local_blocks = fetch_blocks(&args.local)?;
+    let remote_blocks = fetch_blocks(&args.remote)?;
+    let comparison = ChainComparison::new(&local_blocks, &remote_blocks);
+
+    println!("local  {}: {}", args.local, describe_tip(&local_blocks));
+    println!("remote {}: {}", args.remote, describe_tip(&remote_blocks));
+
+    // both nodes must require the same target for the next block, or they run different rules
+    let local_bits = fetch_next_bits(&args.local)?;
+    let remote_bits = fetch_next_bits(&args.remote)?;
+    if local_bits != remote_bits {
+        println!(
+            "state mismatch: next block target is {:#010x} in local and {:#010x} in remote",
+            local_bits, remote_bits
+        );
+    }
+
+    match (comparison.common_index, comparison.fork_index()) {
+        (None, _) => println!("the chains have different genesis blocks"),
+        (Some(common_index), Some(fork_index)) => println!(
+            "the chains forked at index {}, last common block is {}",
+            fork_index, common_index
+        ),
+        (Some(common_index), None) if comparison.differences.is_empty() => {
+            println!("the chains are identical up to block {}", common_index)
+        }
+        (Some(common_index), None) => println!(
+            "no fork, one chain is behind the other after block {}",
+            common_index
+        ),
+    }
+
+    for difference in comparison.differences.iter() {
+        println!(
+            "  block {}: local {} / remote {}",
+            difference.index,
+            describe_block(&difference.local),
+            describe_block(&difference.remote)
+        );
+    }
+
+    Ok(())
+}
+
+fn describe_tip(blocks: &[Block]) -> String {
+    match blocks.last() {
+        Some(tip) => format!(
+            "tip at index {} with hash {:#x}",
+            tip.header.index, tip.header.hash
+        ),
+        None => "no blocks".to_string(),
+    }
+}
+
+fn describe_block(block: &Option<Block>) -> String {
+    match block {
+        Some(block) => format!(
+            "{:#x} ({} transactions, bits {:#010x})",
+            block.header.hash,
+            block.transactions.len(),
+            block.header.bits
+        ),
+        None => "missing".to_string(),
+    }
+}
+
+fn fetch_blocks(address: &str) -> Result<Vec<Block>> {
+    let raw_body = get_body(&format!("{}/v1/blocks", address))?;
+    let blocks = serde_json::from_str(&raw_body)
+        .with_context(|| format!("could not parse the blocks of {}", address))?;
+
+    Ok(blocks)
+}
+
+fn fetch_next_bits(address: &str) -> Result<u32> {
+    let raw_body = get_body(&format!("{}/v1/status", address))?;
+    let status: Value = serde_json::from_str(&raw_body)
+        .with_context(|| format!("could not parse the status of {}", address))?;
+
+    Ok(status["next_bits"].as_u64().unwrap_or_default() as u32)
+}
+
+fn get_body(uri: &str) -> Result<String> {
+    let mut response = isahc::get(uri).with_context(|| format!("could not reach {}", uri))?;
+
+    let status = response.status().as_u16();
+    if status != 200 {
+        return Err(CompareError::BadResponse(uri.to_string(), status).into());
+    }
+
+    Ok(response.text()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{BlockHash, Transaction};
+
+    #[test]
+    fn should_parse_arguments() {
+        let args = to_args(&["--remote", "http://node:8000/"]);
+        assert_eq!(
+            CompareArgs::parse(&args, 8000).unwrap(),
+            CompareArgs {
+                local: "http://localhost:8000".to_string(),
+                remote: "http://node:8000".to_string(),
+            }
+        );
+
+        let args = to_args(&["--local", "http://a:1", "--remote", "http://b:2"]);
+        assert_eq!(
+            CompareArgs::parse(&args, 8000).unwrap(),
+            CompareArgs {
+                local: "http://a:1".to_string(),
+                remote: "http://b:2".to_string(),
+            }
+        );
+
+        assert_err(CompareArgs::parse(&[], 8000), CompareError::MissingRemote);
+        assert_err(
+            CompareArgs::parse(&to_args(&["--remote"]), 8000),
+            CompareError::MissingValue("--remote".to_string()),
+        );
+        assert_err(
+            CompareArgs::parse(&to_args(&["--foo"]), 8000),
+            CompareError::UnknownArgument("--foo".to_string()),
+        );
+    }
+
+    #[test]
+    fn should_find_no_differences_in_identical_chains() {
+        let chain = create_chain(&[0, 1, 2]);
+
+        let comparison = ChainComparison::new(&chain, &chain);
+        assert_eq!(comparison.common_index, Some(2));
+        assert!(comparison.differences.is_empty());
+        assert_eq!(comparison.fork_index(), None);
+    }
+
+    #[test]
+    fn should_detect_chains_behind() {
+        let local = create_chain(&[0, 1]);
+        let remote = create_chain(&[0, 1, 2, 3]);
+
+        let comparison = ChainComparison::new(&local, &remote);
+        assert_eq!(comparison.common_index, Some(1));
+        assert_eq!(comparison.fork_index(), None);
+
+        // the missing blocks are reported
+        let indexes: Vec<u64> = comparison.differences.iter().map(|d| d.index).collect();
+        assert_eq!(indexes, vec![2, 3]);
+        assert!(comparison.differences[0].local.is_none());
+    }
+
+    #[test]
+    fn should_find_fork_point() {
+        let local = create_chain(&[0, 1, 2, 3]);
+        let remote = create_chain(&[0, 1, 5]);
+
+        let comparison = ChainComparison::new(&local, &remote);
+        assert_eq!(comparison.common_index, Some(1));
+        assert_eq!(comparison.fork_index(), Some(2));
+        assert_eq!(comparison.differences.len(), 2);
+
+        // chains with different genesis blocks have nothing in common
+        let remote = create_chain(&[9, 1, 2, 3]);
+        let comparison = ChainComparison::new(&local, &remote);
+        assert_eq!(comparison.common_index, None);
+        assert_eq!(comparison.fork_index(), Some(0));
+    }
+
+    // Creates a chain of linked blocks, each one with as many transactions as indicated
+    // so chains with different amounts at the same index have different hashes from then on
+    fn create_chain(amounts: &[u64]) -> Vec<Block> {
+        let mut blocks: Vec<Block> = Vec::new();
+        for (index, amount) in amounts.iter().enumerate() {
+            let previous_hash = blocks
+                .last()
+                .map_or(BlockHash::default(), |last| last.header.hash);
+            let transaction = Transaction {
+                sender: "1".to_string(),
+                recipient: "2".to_string(),
+                amount: *amount,
+                signature: None,
+                multisig: None,
+                contract: None,
+                token: None,
+                data: None,
+            };
+            let mut block = Block::new(index as u64, 0, previous_hash, vec![transaction]);
+            block.header.timestamp = 0;
+            block.header.hash = block.calculate_hash();
+            blocks.push(block);
+        }
+
+        blocks
+    }
+
+    fn to_args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    fn assert_err(result: Result<CompareArgs>, error_type: CompareError) {
+        let err = result.unwrap_err().downcast::<CompareError>().unwrap();
+        assert_eq!(err, error_type);
+    }
+}
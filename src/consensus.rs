@@ -0,0 +1,105 @@
+mod proof_of_authority;
+mod proof_of_work;
+mod target;
+
+use std::{fmt::Debug, panic::RefUnwindSafe, sync::Arc};
+
+use anyhow::Result;
+use thiserror::Error;
+
+use crate::{
+    model::{Block, BlockHash},
+    util::Config,
+};
+
+// Explicitly controlling which individual identifiers we export
+// It also avoids verbose module imports from other files
+pub use proof_of_authority::ProofOfAuthority;
+pub use proof_of_work::ProofOfWork;
+
+// Consensus engines are shared between the blockchain, the miner and the api
+pub type SharedConsensus = Arc<dyn Consensus>;
+
+// Error types to return when the consensus engine is not properly configured
+#[derive(Error, PartialEq, Debug)]
+pub enum ConsensusError {
+    #[error("Unknown consensus engine `{0}`")]
+    UnknownEngine(String),
+
+    #[error("Invalid signer public key `{0}`")]
+    InvalidKey(String),
+
+    #[error("Invalid signer seed")]
+    InvalidSeed,
+
+    #[error("At least one signer is needed")]
+    NoSigners,
+
+    #[error("Invalid target bits `{0}`")]
+    InvalidTarget(String),
+}
+
+// Possible outcomes of trying to seal a new block
+#[derive(Debug)]
+pub enum SealOutcome {
+    // The block was sealed and is valid according to the consensus rules
+    Sealed(Box<Block>),
+    // The engine gave up without being able to seal the block
+    NotSealed,
+    // The sealing was cancelled before finishing
+    Cancelled,
+}
+
+// Rules that decide which blocks are valid and how new valid blocks are produced
+// The blockchain only checks the structure of the chain (indexes and hashes),
+// every other restriction is delegated to the consensus engine
+pub trait Consensus: Debug + Send + Sync + RefUnwindSafe {
+    // Try to make a new block valid according to the consensus rules
+    // Engines must periodically check "is_cancelled" and stop if it returns true
+    fn seal(&self, block: Block, is_cancelled: &(dyn Fn() -> bool + Sync)) -> SealOutcome;
+
+    // Check that a block follows the consensus rules, given its parent block
+    fn verify(&self, block: &Block, parent: &Block) -> Result<()>;
+
+    // Target, in compact form, that the next block of the chain must carry in its header
+    fn next_bits(&self, chain: &[Block]) -> u32;
+
+    // Whether this node is allowed to produce new blocks at all
+    fn can_seal(&self) -> bool {
+        true
+    }
+
+    // Total amount of hashes calculated while sealing blocks, for engines that do any work
+    fn hashes_tried(&self) -> u64 {
+        0
+    }
+
+    // Work that a block adds to the chain it's part of, chains with more work are preferred over longer ones
+    // Engines without work count every block the same
+    fn block_work(&self, _block: &Block) -> BlockHash {
+        BlockHash::one()
+    }
+}
+
+// Creates the consensus engine selected in the configuration
+pub fn from_config(config: &Config) -> Result<SharedConsensus> {
+    match config.consensus.as_str() {
+        // an explicit target is more precise than the difficulty, so it takes precedence
+        "pow" if !config.target_bits.trim().is_empty() => Ok(Arc::new(ProofOfWork::with_bits(
+            target::parse_compact(&config.target_bits)?,
+            config.max_nonce,
+            config.miner_threads,
+        )?)),
+        "pow" => Ok(ProofOfWork::shared(
+            config.difficulty,
+            config.max_nonce,
+            config.miner_threads,
+        )),
+        "poa" => Ok(Arc::new(ProofOfAuthority::from_hex(
+            &config.poa_signers,
+            &config.poa_signer_seed,
+            config.poa_block_interval_ms,
+        )?)),
+        engine => Err(ConsensusError::UnknownEngine(engine.to_string()).into()),
+    }
+}
@@ -0,0 +1,307 @@
+use std::fmt;
+
+use anyhow::Result;
+use crypto::ed25519;
+
+use super::{Consensus, ConsensusError, SealOutcome};
+use crate::{
+    model::{Block, BlockHash, BlockchainError},
+    util::execution::sleep_millis,
+};
+
+pub type PublicKey = [u8; 32];
+type SecretKey = [u8; 64];
+
+// Time interval to check if the sealing was cancelled while waiting
+const SEAL_POLL_MS: u64 = 10;
+
+// Proof of Authority consensus with a fixed set of signers taking turns (round-robin)
+// A block is valid only if it's signed by the signer whose turn matches the block index
+#[derive(Clone)]
+pub struct ProofOfAuthority {
+    signers: Vec<PublicKey>,
+    // Only present if this node is one of the signers
+    secret_key: Option<SecretKey>,
+    block_interval_ms: u64,
+}
+
+// We implement it manually to never leak the secret key into the logs
+impl fmt::Debug for ProofOfAuthority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let signers: Vec<String> = self.signers.iter().map(hex::encode).collect();
+        f.debug_struct("ProofOfAuthority")
+            .field("signers", &signers)
+            .field("local_signer", &self.local_public_key().map(hex::encode))
+            .field("block_interval_ms", &self.block_interval_ms)
+            .finish()
+    }
+}
+
+impl ProofOfAuthority {
+    // Creates the engine from the public keys of the signers and, optionally,
+    // the secret seed of this node if it must sign blocks
+    pub fn new(
+        signers: Vec<PublicKey>,
+        seed: Option<&[u8]>,
+        block_interval_ms: u64,
+    ) -> Result<ProofOfAuthority> {
+        if signers.is_empty() {
+            return Err(ConsensusError::NoSigners.into());
+        }
+
+        let secret_key = seed.map(|seed| ed25519::keypair(seed).0);
+
+        Ok(ProofOfAuthority {
+            signers,
+            secret_key,
+            block_interval_ms,
+        })
+    }
+
+    // Creates the engine from hex-encoded public keys and seed (empty if this node is not a signer)
+    pub fn from_hex(
+        signers: &[String],
+        seed: &str,
+        block_interval_ms: u64,
+    ) -> Result<ProofOfAuthority> {
+        let mut public_keys = Vec::new();
+        for signer in signers {
+            let bytes = hex::decode(signer.trim())
+                .map_err(|_| ConsensusError::InvalidKey(signer.to_string()))?;
+            if bytes.len() != 32 {
+                return Err(ConsensusError::InvalidKey(signer.to_string()).into());
+            }
+            let mut public_key = PublicKey::default();
+            public_key.copy_from_slice(&bytes);
+            public_keys.push(public_key);
+        }
+
+        let seed = match seed.trim() {
+            "" => None,
+            seed => Some(hex::decode(seed).map_err(|_| ConsensusError::InvalidSeed)?),
+        };
+
+        ProofOfAuthority::new(public_keys, seed.as_deref(), block_interval_ms)
+    }
+
+    // Returns the public key matching a secret seed, to set up signer sets in tests
+    #[cfg(test)]
+    pub fn public_key_from_seed(seed: &[u8]) -> PublicKey {
+        ed25519::keypair(seed).1
+    }
+
+    fn local_public_key(&self) -> Option<PublicKey> {
+        self.secret_key.map(|secret_key| {
+            let mut public_key = PublicKey::default();
+            // the last half of a ed25519 secret key is the public key
+            public_key.copy_from_slice(&secret_key[32..]);
+            public_key
+        })
+    }
+
+    // Signers take turns in the same order as they were configured
+    fn expected_signer(&self, index: u64) -> &PublicKey {
+        &self.signers[(index % self.signers.len() as u64) as usize]
+    }
+
+    // Wait for a number of milliseconds, returns false if cancelled in the meantime
+    fn wait(&self, millis: u64, is_cancelled: &(dyn Fn() -> bool + Sync)) -> bool {
+        let mut waited_ms = 0;
+        while waited_ms < millis {
+            if is_cancelled() {
+                return false;
+            }
+            sleep_millis(SEAL_POLL_MS);
+            waited_ms += SEAL_POLL_MS;
+        }
+
+        !is_cancelled()
+    }
+
+    fn hash_bytes(hash: &BlockHash) -> [u8; 32] {
+        let mut bytes = [0; 32];
+        hash.to_big_endian(&mut bytes);
+        bytes
+    }
+}
+
+impl Consensus for ProofOfAuthority {
+    // Sign the block if it's our turn, after waiting for the block interval
+    // If it's not our turn, we wait until the block of the signer in turn arrives
+    fn seal(&self, block: Block, is_cancelled: &(dyn Fn() -> bool + Sync)) -> SealOutcome {
+        let (secret_key, public_key) = match (self.secret_key, self.local_public_key()) {
+            (Some(secret_key), Some(public_key)) => (secret_key, public_key),
+            _ => return SealOutcome::NotSealed,
+        };
+
+        if *self.expected_signer(block.header.index) != public_key {
+            while !is_cancelled() {
+                sleep_millis(SEAL_POLL_MS);
+            }
+            return SealOutcome::Cancelled;
+        }
+
+        // the block production is scheduled at regular intervals
+        if !self.wait(self.block_interval_ms, is_cancelled) {
+            return SealOutcome::Cancelled;
+        }
+
+        let mut block = block;
+        block.header.hash = block.calculate_hash();
+        let message = ProofOfAuthority::hash_bytes(&block.header.hash);
+        let signature = ed25519::signature(&message, &secret_key);
+        block.signature = Some(hex::encode(&signature[..]));
+
+        SealOutcome::Sealed(Box::new(block))
+    }
+
+    fn verify(&self, block: &Block, _parent: &Block) -> Result<()> {
+        let signature = block
+            .signature
+            .as_ref()
+            .and_then(|signature| hex::decode(signature).ok())
+            .ok_or(BlockchainError::InvalidSignature)?;
+
+        let message = ProofOfAuthority::hash_bytes(&block.header.hash);
+        let signer = self.expected_signer(block.header.index);
+        if signature.len() != 64 || !ed25519::verify(&message, signer, &signature) {
+            return Err(BlockchainError::InvalidSignature.into());
+        }
+
+        Ok(())
+    }
+
+    // There is no target in proof of authority, only the signature matters
+    fn next_bits(&self, _chain: &[Block]) -> u32 {
+        0
+    }
+
+    // Only the nodes in the signer set can produce blocks
+    fn can_seal(&self) -> bool {
+        match self.local_public_key() {
+            Some(public_key) => self.signers.contains(&public_key),
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEED_A: [u8; 32] = [1; 32];
+    const SEED_B: [u8; 32] = [2; 32];
+
+    #[test]
+    fn test_seal_and_verify_in_turn() {
+        // the signer A has the turn of the block with index 0
+        let consensus = create_consensus(Some(&SEED_A));
+        assert!(consensus.can_seal());
+
+        let (parent, block) = create_mock_blocks(2);
+        let sealed_block = assert_sealed(consensus.seal(block, &|| false));
+        assert!(sealed_block.signature.is_some());
+        assert!(consensus.verify(&sealed_block, &parent).is_ok());
+    }
+
+    #[test]
+    fn test_seal_waits_for_signer_in_turn() {
+        // the signer A does not have the turn of the block with index 1,
+        // so it waits until cancelled (i.e. when the block of the signer B arrives)
+        let consensus = create_consensus(Some(&SEED_A));
+        let (_, block) = create_mock_blocks(1);
+
+        let result = consensus.seal(block, &|| true);
+        assert!(matches!(result, SealOutcome::Cancelled));
+    }
+
+    #[test]
+    fn test_seal_without_key() {
+        // nodes that are not signers can not seal blocks
+        let consensus = create_consensus(None);
+        assert!(!consensus.can_seal());
+
+        let (_, block) = create_mock_blocks(2);
+        let result = consensus.seal(block, &|| false);
+        assert!(matches!(result, SealOutcome::NotSealed));
+    }
+
+    #[test]
+    fn test_verify_rejects_signer_out_of_turn() {
+        // the signer B signs a block with index 2, which is the turn of signer A
+        let signer_b = create_consensus(Some(&SEED_B));
+        let (parent, block) = create_mock_blocks(2);
+        let mut block = block;
+        block.signature = Some(sign(&block, &SEED_B));
+
+        assert_invalid_signature(signer_b.verify(&block, &parent));
+    }
+
+    #[test]
+    fn test_verify_rejects_missing_or_tampered_signatures() {
+        let consensus = create_consensus(Some(&SEED_A));
+        let (parent, block) = create_mock_blocks(2);
+
+        // missing signature
+        assert_invalid_signature(consensus.verify(&block, &parent));
+
+        // the block was modified after being signed
+        let mut sealed_block = assert_sealed(consensus.seal(block, &|| false));
+        sealed_block.header.nonce += 1;
+        sealed_block.header.hash = sealed_block.calculate_hash();
+        assert_invalid_signature(consensus.verify(&sealed_block, &parent));
+
+        // the signature is not even valid hex
+        sealed_block.signature = Some("foo".to_string());
+        assert_invalid_signature(consensus.verify(&sealed_block, &parent));
+    }
+
+    #[test]
+    fn test_from_hex() {
+        let signers = vec![hex::encode(ProofOfAuthority::public_key_from_seed(&SEED_A))];
+
+        let consensus = ProofOfAuthority::from_hex(&signers, &hex::encode(SEED_A), 0).unwrap();
+        assert!(consensus.can_seal());
+
+        // invalid public keys or seeds must be rejected
+        let invalid_signers = vec!["foo".to_string()];
+        assert!(ProofOfAuthority::from_hex(&invalid_signers, "", 0).is_err());
+        assert!(ProofOfAuthority::from_hex(&signers, "foo", 0).is_err());
+
+        // there must be at least one signer
+        assert!(ProofOfAuthority::from_hex(&[], "", 0).is_err());
+    }
+
+    fn create_consensus(seed: Option<&[u8]>) -> ProofOfAuthority {
+        let signers = vec![
+            ProofOfAuthority::public_key_from_seed(&SEED_A),
+            ProofOfAuthority::public_key_from_seed(&SEED_B),
+        ];
+        ProofOfAuthority::new(signers, seed, 0).unwrap()
+    }
+
+    fn create_mock_blocks(index: u64) -> (Block, Block) {
+        let parent = Block::new(index - 1, 0, BlockHash::default(), Vec::new());
+        let block = Block::new(index, 0, parent.header.hash, Vec::new());
+
+        (parent, block)
+    }
+
+    fn sign(block: &Block, seed: &[u8]) -> String {
+        let (secret_key, _) = ed25519::keypair(seed);
+        let message = ProofOfAuthority::hash_bytes(&block.header.hash);
+        hex::encode(&ed25519::signature(&message, &secret_key)[..])
+    }
+
+    fn assert_sealed(result: SealOutcome) -> Block {
+        match result {
+            SealOutcome::Sealed(block) => *block,
+            _ => panic!("expected a sealed block, got {:?}", result),
+        }
+    }
+
+    fn assert_invalid_signature(result: Result<()>) {
+        let err = result.unwrap_err().downcast::<BlockchainError>().unwrap();
+        assert_eq!(err, BlockchainError::InvalidSignature);
+    }
+}
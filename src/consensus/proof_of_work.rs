@@ -0,0 +1,326 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Instant,
+};
+
+use anyhow::Result;
+use crossbeam_utils::thread;
+
+use super::{target, Consensus, SealOutcome, SharedConsensus};
+use crate::model::{Block, BlockHash, BlockchainError};
+
+// State shared by all the mining threads while searching for a valid nonce
+#[derive(Default)]
+struct NonceSearch {
+    found: AtomicBool,
+    cancelled: AtomicBool,
+    hashes: AtomicU64,
+    block: Mutex<Option<Block>>,
+}
+
+impl NonceSearch {
+    fn is_finished(&self) -> bool {
+        self.found.load(Ordering::SeqCst) || self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+// Proof of Work consensus with a fixed target
+// A block is valid if its hash, as a 256-bit integer, is not higher than the target
+// The target is carried in the block header in its compact form ("bits")
+#[derive(Debug, Clone)]
+pub struct ProofOfWork {
+    bits: u32,
+    max_nonce: u64,
+    threads: u64,
+    // accumulated across all seals, so the miner can calculate its hash rate
+    hashes_tried: Arc<AtomicU64>,
+}
+
+impl ProofOfWork {
+    // Creates the engine from a difficulty, the number of leading zeros of the target
+    pub fn new(difficulty: u32, max_nonce: u64, threads: u64) -> ProofOfWork {
+        let bits = target::to_compact(&ProofOfWork::create_target(difficulty));
+
+        // the compact target comes from a valid target, so it can always be decoded
+        ProofOfWork::with_bits(bits, max_nonce, threads).unwrap()
+    }
+
+    // Creates the engine from a target in its compact form, for a finer control than the difficulty
+    pub fn with_bits(bits: u32, max_nonce: u64, threads: u64) -> Result<ProofOfWork> {
+        // make sure that the target can be decoded
+        target::from_compact(bits)?;
+
+        Ok(ProofOfWork {
+            bits,
+            max_nonce,
+            // we need at least one thread to be able to mine
+            threads: threads.max(1),
+            hashes_tried: Arc::new(AtomicU64::new(0)),
+        })
+    }
+
+    // Creates a proof of work engine ready to be shared across threads
+    pub fn shared(difficulty: u32, max_nonce: u64, threads: u64) -> SharedConsensus {
+        Arc::new(ProofOfWork::new(difficulty, max_nonce, threads))
+    }
+
+    // Creates binary data mask with the amount of left padding zeroes indicated by the "difficulty" value
+    // Used to easily compare if a newly created block has a hash that matches the difficulty
+    fn create_target(difficulty: u32) -> BlockHash {
+        BlockHash::MAX >> difficulty
+    }
+
+    // Try nonces starting from "first_nonce" and skipping the ones handled by other threads
+    fn search_nonces(
+        &self,
+        first_nonce: u64,
+        block: &Block,
+        target: BlockHash,
+        is_cancelled: &(dyn Fn() -> bool + Sync),
+        search: &NonceSearch,
+    ) {
+        let mut candidate = block.clone();
+        let mut nonce = first_nonce;
+        while nonce < self.max_nonce && !search.is_finished() {
+            // Our block would not be valid anymore, e.g. it must follow a new last block
+            if is_cancelled() {
+                search.cancelled.store(true, Ordering::SeqCst);
+                return;
+            }
+
+            candidate.header.nonce = nonce;
+            candidate.header.hash = candidate.calculate_hash();
+            search.hashes.fetch_add(1, Ordering::Relaxed);
+
+            // A valid block must have a hash not higher than the target
+            if candidate.header.hash <= target {
+                // only the first thread to find a valid block gets to store it
+                let first_found = search
+                    .found
+                    .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+                    .is_ok();
+                if first_found {
+                    *search.block.lock().unwrap() = Some(candidate);
+                }
+                return;
+            }
+
+            nonce = match nonce.checked_add(self.threads) {
+                Some(next_nonce) => next_nonce,
+                None => return,
+            };
+        }
+    }
+}
+
+impl Consensus for ProofOfWork {
+    // Try different "nonce" values until the block has a hash that matches the difficulty
+    // The nonces are split between multiple threads, all of them stop as soon as one finds a valid block
+    fn seal(&self, block: Block, is_cancelled: &(dyn Fn() -> bool + Sync)) -> SealOutcome {
+        // the target to satisfy is the one in the block header
+        let target = match target::from_compact(block.header.bits) {
+            Ok(target) => target,
+            Err(_) => return SealOutcome::NotSealed,
+        };
+
+        let search = NonceSearch::default();
+        let start = Instant::now();
+
+        thread::scope(|s| {
+            for first_nonce in 0..self.threads {
+                let search = &search;
+                let block = &block;
+                s.spawn(move |_| {
+                    self.search_nonces(first_nonce, block, target, is_cancelled, search)
+                });
+            }
+        })
+        .unwrap();
+
+        // report the aggregated hash rate of all threads
+        let hashes = search.hashes.load(Ordering::SeqCst);
+        self.hashes_tried.fetch_add(hashes, Ordering::SeqCst);
+        let elapsed_secs = start.elapsed().as_secs_f64();
+        if elapsed_secs > 0.0 {
+            info!(
+                "tried {} nonces at {:.0} hashes/sec",
+                hashes,
+                hashes as f64 / elapsed_secs
+            );
+        }
+
+        if search.cancelled.load(Ordering::SeqCst) {
+            return SealOutcome::Cancelled;
+        }
+
+        match search.block.into_inner().unwrap() {
+            Some(block) => SealOutcome::Sealed(Box::new(block)),
+            None => SealOutcome::NotSealed,
+        }
+    }
+
+    fn verify(&self, block: &Block, _parent: &Block) -> Result<()> {
+        // the blockchain already checked that the header has the expected target
+        // so we only need to compare the hash against it
+        let target = target::from_compact(block.header.bits)
+            .map_err(|_| BlockchainError::InvalidDifficulty)?;
+        if block.header.hash > target {
+            return Err(BlockchainError::InvalidDifficulty.into());
+        }
+
+        Ok(())
+    }
+
+    fn next_bits(&self, _chain: &[Block]) -> u32 {
+        self.bits
+    }
+
+    fn hashes_tried(&self) -> u64 {
+        self.hashes_tried.load(Ordering::SeqCst)
+    }
+
+    // Blocks are worth the hashes needed to find them, so chains with easier targets count for less
+    fn block_work(&self, block: &Block) -> BlockHash {
+        target::work(block.header.bits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // We use SHA 256 hashes
+    const MAX_DIFFICULTY: u32 = 256;
+
+    #[test]
+    fn test_create_target_valid_difficulty() {
+        // try all possibilities of valid difficulties
+        // the target must have as many leading zeroes
+        for difficulty in 0..MAX_DIFFICULTY {
+            let target = ProofOfWork::create_target(difficulty);
+            assert_eq!(target.leading_zeros(), difficulty);
+        }
+    }
+
+    #[test]
+    fn test_create_target_overflowing_difficulty() {
+        // when passing an overflowing difficulty,
+        // it must default to the max difficulty
+        let target = ProofOfWork::create_target(MAX_DIFFICULTY + 1);
+        assert_eq!(target.leading_zeros(), MAX_DIFFICULTY);
+    }
+
+    #[test]
+    fn test_seal_block_found() {
+        // let's use a small difficulty target for fast testing
+        let difficulty = 1;
+
+        // this should be more than enough nonces to find a block with only 1 zero
+        let max_nonce = 1_000;
+
+        // check that the block is sealed and valid
+        let consensus = ProofOfWork::new(difficulty, max_nonce, 1);
+        let (parent, block) = create_mock_blocks(&consensus);
+        let result = consensus.seal(block, &|| false);
+        assert_sealed_block_is_valid(&consensus, result, &parent);
+    }
+
+    #[test]
+    fn test_seal_block_not_found() {
+        // with a max_nonce so low and the max difficulty, we will never find a block
+        // and also the test will end fast
+        let consensus = ProofOfWork::new(MAX_DIFFICULTY, 10, 1);
+        let (_, block) = create_mock_blocks(&consensus);
+        let result = consensus.seal(block, &|| false);
+        assert!(matches!(result, SealOutcome::NotSealed));
+
+        // every nonce was tried
+        assert_eq!(consensus.hashes_tried(), 10);
+    }
+
+    #[test]
+    fn test_seal_block_found_with_multiple_threads() {
+        // check that the block is sealed when splitting the nonces between threads
+        let consensus = ProofOfWork::new(1, 1_000, 4);
+        let (parent, block) = create_mock_blocks(&consensus);
+        let result = consensus.seal(block, &|| false);
+        assert_sealed_block_is_valid(&consensus, result, &parent);
+    }
+
+    #[test]
+    fn test_seal_block_not_found_with_multiple_threads() {
+        // no thread will ever find a block, but all of them must finish
+        let consensus = ProofOfWork::new(MAX_DIFFICULTY, 10, 4);
+        let (_, block) = create_mock_blocks(&consensus);
+        let result = consensus.seal(block, &|| false);
+        assert!(matches!(result, SealOutcome::NotSealed));
+    }
+
+    #[test]
+    fn test_seal_block_cancelled() {
+        // with a high difficulty and max_nonce we would be mining for a long time
+        let consensus = ProofOfWork::new(MAX_DIFFICULTY, u64::MAX, 1);
+        let (_, block) = create_mock_blocks(&consensus);
+
+        // the sealing must stop as soon as it's cancelled
+        let result = consensus.seal(block, &|| true);
+        assert!(matches!(result, SealOutcome::Cancelled));
+    }
+
+    #[test]
+    fn test_verify_difficulty() {
+        // with the max target, almost any block is valid
+        let consensus = ProofOfWork::new(0, 1, 1);
+        let (parent, mut block) = create_mock_blocks(&consensus);
+        assert!(consensus.verify(&block, &parent).is_ok());
+
+        // but the block will not satisfy the max difficulty
+        block.header.bits = ProofOfWork::new(MAX_DIFFICULTY, 1, 1).bits;
+        block.header.hash = block.calculate_hash();
+        assert_invalid_difficulty(consensus.verify(&block, &parent));
+
+        // targets that can not be decoded are never satisfied
+        block.header.bits = 0x2101_0000;
+        block.header.hash = block.calculate_hash();
+        assert_invalid_difficulty(consensus.verify(&block, &parent));
+    }
+
+    #[test]
+    fn test_with_bits() {
+        // targets more precise than a difficulty can be used
+        let consensus = ProofOfWork::with_bits(0x1f7f_ffff, 1, 1).unwrap();
+        assert_eq!(consensus.next_bits(&[]), 0x1f7f_ffff);
+
+        // but they must be valid
+        assert!(ProofOfWork::with_bits(0x2101_0000, 1, 1).is_err());
+    }
+
+    fn create_mock_blocks(consensus: &ProofOfWork) -> (Block, Block) {
+        let parent = Block::new(0, 0, BlockHash::default(), Vec::new());
+        let mut block = Block::new(1, 0, parent.header.hash, Vec::new());
+        block.header.bits = consensus.next_bits(&[]);
+        block.header.hash = block.calculate_hash();
+
+        (parent, block)
+    }
+
+    fn assert_invalid_difficulty(result: Result<()>) {
+        let err = result.unwrap_err().downcast::<BlockchainError>().unwrap();
+        assert_eq!(err, BlockchainError::InvalidDifficulty);
+    }
+
+    fn assert_sealed_block_is_valid(consensus: &ProofOfWork, result: SealOutcome, parent: &Block) {
+        match result {
+            SealOutcome::Sealed(block) => {
+                assert_eq!(block.header.index, parent.header.index + 1);
+                assert_eq!(block.header.previous_hash, parent.header.hash);
+                assert_eq!(block.header.hash, block.calculate_hash());
+                assert!(consensus.verify(&block, parent).is_ok());
+            }
+            _ => panic!("expected a sealed block, got {:?}", result),
+        }
+    }
+}
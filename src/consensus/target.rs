@@ -0,0 +1,152 @@
+use anyhow::Result;
+
+use super::ConsensusError;
+use crate::model::BlockHash;
+
+// Size of the mantissa in the compact representation, in bytes
+const MANTISSA_SIZE: usize = 3;
+
+// Max size of a target, in bytes
+const MAX_TARGET_SIZE: usize = 32;
+
+// Decodes a target from its compact representation ("bits"), in the same format as Bitcoin:
+// the most significant byte is the size of the target in bytes, and the other three bytes
+// are the most significant bytes of the target (the mantissa)
+// Unlike Bitcoin, targets can not be negative, so there is no sign bit in the mantissa
+pub fn from_compact(bits: u32) -> Result<BlockHash> {
+    let size = (bits >> 24) as usize;
+    let mantissa = BlockHash::from(bits & 0x00ff_ffff);
+
+    if size <= MANTISSA_SIZE {
+        return Ok(mantissa >> (8 * (MANTISSA_SIZE - size)));
+    }
+
+    // the mantissa must fit in the 256 bits of the target once shifted
+    let shift = 8 * (size - MANTISSA_SIZE);
+    if size > MAX_TARGET_SIZE + MANTISSA_SIZE || mantissa.bits() + shift > 8 * MAX_TARGET_SIZE {
+        return Err(ConsensusError::InvalidTarget(format!("{:#010x}", bits)).into());
+    }
+
+    Ok(mantissa << shift)
+}
+
+// Parses the compact representation of a target from an hex string (e.g. "0x1d00ffff")
+pub fn parse_compact(bits: &str) -> Result<u32> {
+    let digits = bits.trim().trim_start_matches("0x");
+    let bits = u32::from_str_radix(digits, 16)
+        .map_err(|_| ConsensusError::InvalidTarget(bits.to_string()))?;
+
+    // make sure that it can be decoded
+    from_compact(bits)?;
+
+    Ok(bits)
+}
+
+// Encodes a target into its compact representation ("bits")
+// Only the three most significant bytes are kept, so the encoded target may be slightly lower
+pub fn to_compact(target: &BlockHash) -> u32 {
+    let size = target.bits().div_ceil(8);
+    let mantissa = if size <= MANTISSA_SIZE {
+        target.low_u32() << (8 * (MANTISSA_SIZE - size))
+    } else {
+        (target >> (8 * (size - MANTISSA_SIZE))).low_u32()
+    };
+
+    ((size as u32) << 24) | mantissa
+}
+
+// Expected number of hashes to find a block with the target in "bits", like the chainwork of Bitcoin
+// It's what chains are compared by, a chain with more work took more effort to produce
+// Invalid targets have no work, as blocks with them are rejected anyway
+pub fn work(bits: u32) -> BlockHash {
+    let target = match from_compact(bits) {
+        Ok(target) => target,
+        Err(_) => return BlockHash::zero(),
+    };
+
+    // 2^256 / (target + 1), without overflowing 256 bits
+    match target.checked_add(BlockHash::one()) {
+        Some(divisor) => (!target / divisor).saturating_add(BlockHash::one()),
+        None => BlockHash::one(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_decode_compact_targets() {
+        // the genesis target of Bitcoin
+        let target = from_compact(0x1d00_ffff).unwrap();
+        assert_eq!(target, BlockHash::from(0xffff) << (8 * 26));
+
+        // small targets fit in the mantissa
+        assert_eq!(from_compact(0x0300_1234).unwrap(), BlockHash::from(0x1234));
+        assert_eq!(from_compact(0x0112_3456).unwrap(), BlockHash::from(0x12));
+        assert_eq!(from_compact(0).unwrap(), BlockHash::zero());
+    }
+
+    #[test]
+    fn should_reject_overflowing_targets() {
+        // a 33 bytes target does not fit in 256 bits
+        assert!(from_compact(0x2101_0000).is_err());
+        assert!(from_compact(0xff00_0001).is_err());
+
+        // but the max target does
+        assert_eq!(from_compact(0x20ff_ffff).unwrap().bits(), 256);
+    }
+
+    #[test]
+    fn should_encode_compact_targets() {
+        // there is no sign bit, so the mantissa is not padded with a zero byte as in Bitcoin
+        let target = BlockHash::from(0xffff) << (8 * 26);
+        assert_eq!(to_compact(&target), 0x1cff_ff00);
+        assert_eq!(from_compact(0x1cff_ff00).unwrap(), target);
+
+        assert_eq!(to_compact(&BlockHash::from(0x1234)), 0x0212_3400);
+        assert_eq!(to_compact(&BlockHash::zero()), 0);
+        assert_eq!(to_compact(&BlockHash::MAX), 0x20ff_ffff);
+    }
+
+    #[test]
+    fn should_parse_compact_targets() {
+        assert_eq!(parse_compact("0x1d00ffff").unwrap(), 0x1d00_ffff);
+        assert_eq!(parse_compact("1d00ffff").unwrap(), 0x1d00_ffff);
+
+        assert!(parse_compact("foo").is_err());
+        assert!(parse_compact("0x2101_0000").is_err());
+        assert!(parse_compact("0x21010000").is_err());
+    }
+
+    #[test]
+    fn should_calculate_the_work_of_targets() {
+        // each leading zero of the target doubles the expected number of hashes
+        assert_eq!(
+            work(to_compact(&(BlockHash::MAX >> 8))),
+            BlockHash::from(256)
+        );
+        assert_eq!(
+            work(to_compact(&(BlockHash::MAX >> 9))),
+            BlockHash::from(512)
+        );
+        assert_eq!(work(0x1d00_ffff), BlockHash::from(0x1_0001_0001u64));
+
+        // a zero target is as hard as it gets, and invalid ones count for nothing
+        assert_eq!(work(0), BlockHash::MAX);
+        assert_eq!(work(0xff00_0001), BlockHash::zero());
+    }
+
+    #[test]
+    fn should_keep_targets_after_roundtrip() {
+        // encoding only loses precision, so the decoded target is never higher
+        for difficulty in 0..256 {
+            let target = BlockHash::MAX >> difficulty;
+            let decoded_target = from_compact(to_compact(&target)).unwrap();
+
+            assert!(decoded_target <= target);
+            assert_eq!(decoded_target.leading_zeros(), difficulty);
+            assert_eq!(to_compact(&decoded_target), to_compact(&target));
+        }
+    }
+}
@@ -0,0 +1,208 @@
+#[macro_use]
+extern crate log;
+
+mod api;
+mod bench;
+mod cli;
+mod compare;
+mod consensus;
+mod miner;
+mod model;
+mod network;
+mod node;
+mod notifier;
+mod peer;
+mod plugin;
+mod scheduler;
+mod util;
+mod vm;
+mod wallet;
+
+// What programs embedding the node need to write their plugins
+pub use model::{Block, Blockchain, Transaction};
+pub use plugin::{NodePlugin, Plugins};
+
+use actix_web::rt::System;
+use cli::{Command, NodeArgs};
+use miner::MinerStats;
+use model::{Snapshot, TransactionPool};
+use network::{Gossip, InclusionProofs, NetworkTime, PeerBook, Peers};
+use node::Node;
+use notifier::Subscriptions;
+use std::{env, path::Path, process};
+
+use util::{
+    check_startup, initialize_logger,
+    termination::{self, Shutdown},
+    Config, Context,
+};
+use wallet::Wallet;
+
+// Runs the node or one of the commands, as given in the command line
+// Programs embedding the node call it with their own plugins, the node binary has none
+pub fn run(plugins: Vec<Box<dyn NodePlugin>>) {
+    // initialize shared data values
+    let config = Config::read();
+    initialize_logger(&config);
+
+    // the node runs without a command, the rest of them are tools for operators and users
+    let args: Vec<String> = env::args().skip(1).collect();
+    let command = Command::parse(&args, config.port).unwrap_or_else(|error| {
+        error!("{:#}", error);
+        process::exit(2);
+    });
+
+    match command {
+        Command::Run(node_args) => run_node(config, &node_args, Plugins::new(plugins)),
+        command => {
+            if let Err(error) = cli::run(command, &config) {
+                error!("command failed: {:#}", error);
+                process::exit(1);
+            }
+        }
+    }
+}
+
+fn run_node(mut config: Config, node_args: &NodeArgs, plugins: Plugins) {
+    info!("starting up");
+
+    // flags of the command line take precedence over the environment
+    node_args.apply(&mut config);
+
+    // fail fast with an actionable error if the node would not work properly
+    if let Err(error) = check_startup(&config) {
+        error!("startup self-test failed: {:#}", error);
+        process::exit(1);
+    }
+
+    let consensus = consensus::from_config(&config).expect("invalid consensus configuration");
+    info!("using consensus engine {:?}", consensus);
+    let wallet = Wallet::from_config(&config).expect("invalid wallet configuration");
+
+    // peers are discovered again if the book can't be read, so there is no need to stop
+    let peer_book = PeerBook::load(&config.peer_book_path()).unwrap_or_else(|error| {
+        warn!("starting with an empty peer book: {:#}", error);
+        PeerBook::new()
+    });
+    let peers = Peers::new(config.p2p_ban_secs);
+    let network_time = NetworkTime::new(
+        config.max_clock_offset_secs,
+        config.clock_offset_stops_mining,
+    );
+
+    // a trusted snapshot saves replaying the whole chain, the node syncs the rest from its peers
+    let snapshot = if config.snapshot_path.is_empty() {
+        None
+    } else {
+        Some(
+            Snapshot::load(Path::new(&config.snapshot_path)).unwrap_or_else(|error| {
+                error!("could not load the snapshot: {:#}", error);
+                process::exit(1);
+            }),
+        )
+    };
+
+    // without a data directory the chain only lives in memory, and starts from scratch every time
+    let blockchain = match (config.data_dir.is_empty(), snapshot) {
+        (true, None) => Ok(Blockchain::new(consensus)),
+        (true, Some(snapshot)) => Blockchain::from_snapshot(consensus, snapshot),
+        (false, snapshot) => Blockchain::open(
+            consensus,
+            &config.data_dir,
+            snapshot,
+            &config.chain_id,
+            config.storage_compression,
+        ),
+    };
+    let blockchain = blockchain.unwrap_or_else(|error| {
+        error!("could not load the chain: {:#}", error);
+        process::exit(1);
+    });
+    if config.prune_depth > 0 {
+        info!(
+            "pruning the transactions of blocks deeper than {}",
+            config.prune_depth
+        );
+        blockchain.set_prune_depth(config.prune_depth);
+    }
+    blockchain.set_contracts_enabled(config.contracts_enabled);
+    blockchain.set_max_data_bytes(config.max_data_bytes);
+    blockchain.set_chain_id(&config.chain_id);
+    blockchain.set_max_time_drift(config.max_time_drift_secs);
+    if config.light_client {
+        info!("running as a light client, only the headers of the blocks are downloaded");
+    }
+
+    // plugins see the chain as it was loaded, before anything else can change it
+    if let Err(error) = plugins.start(&blockchain) {
+        error!("could not start the plugins: {:#}", error);
+        process::exit(1);
+    }
+
+    let pool = TransactionPool::with_events(blockchain.events());
+
+    // the transactions saved before stopping go through the same checks as the new ones of the api,
+    // since the chain, the chain id or the wallet mode may have changed while the node was down
+    let mempool_path = config.mempool_path();
+    if !mempool_path.is_empty() {
+        let restored = pool.restore(Path::new(&mempool_path), |transaction| {
+            wallet.check_transaction(transaction, &blockchain.chain_id())?;
+            blockchain.check_transaction(transaction)?;
+            if blockchain.contains_transaction(transaction.calculate_id()) {
+                anyhow::bail!("already included in a block");
+            }
+            Ok(())
+        });
+        // users can send them again, so there is no need to stop
+        match restored {
+            Ok((0, 0)) => {}
+            Ok((restored, discarded)) => info!(
+                "restored {} transactions into the pool, {} were no longer valid",
+                restored, discarded
+            ),
+            Err(error) => warn!("starting with an empty pool: {:#}", error),
+        }
+    }
+
+    let context = Context {
+        config,
+        blockchain,
+        pool,
+        shutdown: Shutdown::new(),
+        subscriptions: Subscriptions::new(),
+        wallet,
+        miner_stats: MinerStats::new(),
+        gossip: Gossip::new(),
+        peer_book,
+        peers,
+        network_time,
+        proofs: InclusionProofs::new(),
+        plugins,
+    };
+
+    // quit the program when the user inputs Ctrl-C, after draining the api
+    // we add an extra second to the max waiting time to let the api finish on its own
+    let max_shutdown_ms =
+        context.config.shutdown_drain_ms + (context.config.shutdown_timeout_secs + 1) * 1000;
+    // the pool is saved once the api stopped, so it includes every transaction it accepted
+    let pool = context.pool.clone();
+    let on_exit = move || {
+        if mempool_path.is_empty() {
+            return;
+        }
+        if let Err(error) = pool.save(Path::new(&mempool_path)) {
+            error!("could not save the pool: {:#}", error);
+        }
+    };
+
+    // miner, api, peer system, notifier, p2p network, scheduler and plugins share a single runtime
+    let result = System::new("node").block_on(async move {
+        let node = Node::start(&context).await;
+        termination::set_ctrlc_handler(node.shutdown(), max_shutdown_ms, on_exit);
+        node.join().await
+    });
+    if let Err(error) = result {
+        error!("node stopped: {:#}", error);
+        process::exit(1);
+    }
+}
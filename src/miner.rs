@@ -1,26 +1,126 @@
 use crate::{
+    consensus::{SealOutcome, SharedConsensus},
     model::{Block, BlockHash, Blockchain, TransactionPool, TransactionVec},
-    util::{
-        execution::{sleep_millis, Runnable},
-        Context,
-    },
+    network::NetworkTime,
+    util::{execution::Runnable, watch::WatchReceiver, Context, Lock, SharedClock},
 };
 use anyhow::Result;
+use serde::Serialize;
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
 use thiserror::Error;
+use tracing::info_span;
 
 #[derive(Error, Debug)]
 pub enum MinerError {
     #[error("No valid block was mined at index `{0}`")]
     BlockNotMined(u64),
+
+    #[error("This node can not produce blocks")]
+    CannotSeal,
+
+    #[error("The clock of this node is too far from the one of its peers, fix it to mine blocks")]
+    ClockOffset,
+}
+
+// Snapshot of the mining statistics, to tell if mining is making progress
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct MinerStatsReport {
+    pub hashes_per_sec: f64,
+    pub nonces_tried: u64,
+    pub blocks_found: u64,
+    pub mining_time_ms: u64,
+    pub avg_block_time_ms: Option<u64>,
+}
+
+// Whether the node is producing blocks, as reported by the api
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MiningState {
+    // Blocks are mined as soon as there are transactions in the pool
+    Auto,
+    // Blocks are only mined when requested through the api
+    OnDemand,
+    // The consensus does not let this node produce blocks
+    Disabled,
+}
+
+#[derive(Debug, Default)]
+struct MiningCounters {
+    nonces_tried: u64,
+    blocks_found: u64,
+    mining_time: Duration,
+}
+
+// Statistics of the miner, updated after each sealing attempt
+// Multiple threads can read/write concurrently, e.g. the miner and the api
+#[derive(Debug, Clone, Default)]
+pub struct MinerStats {
+    counters: Arc<Lock<MiningCounters>>,
+}
+
+impl MinerStats {
+    pub fn new() -> MinerStats {
+        MinerStats::default()
+    }
+
+    // Accounts for an attempt to seal a block, successful or not
+    pub fn record_attempt(&self, nonces_tried: u64, elapsed: Duration) {
+        let mut counters = self.counters.lock();
+        counters.nonces_tried += nonces_tried;
+        counters.mining_time += elapsed;
+    }
+
+    // Accounts for a block that was mined and added to the blockchain
+    pub fn record_block_found(&self) {
+        let mut counters = self.counters.lock();
+        counters.blocks_found += 1;
+    }
+
+    pub fn report(&self) -> MinerStatsReport {
+        let counters = self.counters.lock();
+
+        // rates are calculated over the time spent mining, not over the time waiting for transactions
+        let mining_secs = counters.mining_time.as_secs_f64();
+        let hashes_per_sec = if mining_secs > 0.0 {
+            counters.nonces_tried as f64 / mining_secs
+        } else {
+            0.0
+        };
+        let mining_time_ms = counters.mining_time.as_millis() as u64;
+        let avg_block_time_ms = match counters.blocks_found {
+            0 => None,
+            blocks_found => Some(mining_time_ms / blocks_found),
+        };
+
+        MinerStatsReport {
+            hashes_per_sec,
+            nonces_tried: counters.nonces_tried,
+            blocks_found: counters.blocks_found,
+            mining_time_ms,
+            avg_block_time_ms,
+        }
+    }
 }
 
+#[derive(Clone)]
 pub struct Miner {
+    // Whether new blocks are mined as soon as there are transactions, or only on demand
+    auto_mining: bool,
     max_blocks: u64,
-    max_nonce: u64,
     tx_waiting_ms: u64,
     blockchain: Blockchain,
     pool: TransactionPool,
-    target: BlockHash,
+    consensus: SharedConsensus,
+    stats: MinerStats,
+    // Light clients don't have the transactions of the blocks, so they can't build on top of them
+    light_client: bool,
+    // Where the timestamps of the new blocks come from, the same clock that the blockchain checks them with
+    clock: SharedClock,
+    // Tells if the clock is too far from the one of the peers, which may stop mining (see CLOCK_OFFSET_STOPS_MINING)
+    network_time: NetworkTime,
 }
 
 impl Runnable for Miner {
@@ -31,26 +131,44 @@ impl Runnable for Miner {
 
 impl Miner {
     pub fn new(context: &Context) -> Miner {
-        let target = Miner::create_target(context.config.difficulty);
-
         Miner {
+            auto_mining: context.config.auto_mining,
             max_blocks: context.config.max_blocks,
-            max_nonce: context.config.max_nonce,
             tx_waiting_ms: context.config.tx_waiting_ms,
             blockchain: context.blockchain.clone(),
             pool: context.pool.clone(),
-            target,
+            consensus: context.blockchain.consensus(),
+            stats: context.miner_stats.clone(),
+            light_client: context.config.light_client,
+            clock: context.blockchain.clock(),
+            network_time: context.network_time.clone(),
         }
     }
 
     // Try to constanly calculate and append new valid blocks to the blockchain,
     // including all pending transactions in the transaction pool each time
     pub fn start(&self) -> Result<()> {
+        // nodes that are not allowed to produce blocks only follow the chain of their peers
+        if !self.can_seal() {
+            info!("this node can not produce blocks, stopping mining");
+            return Ok(());
+        }
+
+        if !self.auto_mining {
+            info!("automatic mining is disabled, blocks will only be mined on demand");
+            return Ok(());
+        }
+
         info!(
-            "start minining with difficulty {}",
-            self.blockchain.difficulty
+            "start minining with target bits {:#010x}",
+            self.blockchain.next_bits()
         );
 
+        // We get notified every time a new block is added to the blockchain (by us, peers or the api)
+        let mut tip = self.blockchain.watch_tip();
+        // and when transactions enter the pool, so we don't wait for them longer than needed
+        let events = self.blockchain.events().subscribe();
+
         // In each loop it tries to find the next valid block and append it to the blockchain
         let mut block_counter = 0;
         loop {
@@ -59,81 +177,183 @@ impl Miner {
                 return Ok(());
             }
 
-            // Empty all transactions from the pool, they will be included in the new block
-            let transactions = self.pool.pop();
+            // the blocks would be stamped with the wrong time, so the transactions wait in the pool
+            if self.network_time.stops_mining() {
+                events.wait(Duration::from_millis(self.tx_waiting_ms));
+                continue;
+            }
+
+            // the events of what we are about to pop are not needed anymore
+            events.pending();
+            let transactions = self.pop_transactions();
 
             // Do not try to mine a block if there are no transactions in the pool
             if transactions.is_empty() {
-                sleep_millis(self.tx_waiting_ms);
+                events.wait(Duration::from_millis(self.tx_waiting_ms));
                 continue;
             }
 
-            // try to find a valid next block of the blockchain
-            let last_block = self.blockchain.get_last_block();
-            let mining_result = self.mine_block(&last_block, transactions.clone());
-            match mining_result {
-                Some(block) => {
-                    info!("valid block found for index {}", block.index);
-                    self.blockchain.add_block(block.clone())?;
-                    block_counter += 1;
-                }
-                None => {
-                    let index = last_block.index + 1;
-                    error!("no valid block was foun for index {}", index);
-                    return Err(MinerError::BlockNotMined(index).into());
+            match self.mine_block(transactions, &mut tip) {
+                Ok(Some(_)) => block_counter += 1,
+                Ok(None) => {}
+                // the transactions are back in the pool, so a failed block doesn't stop the node
+                // from mining them, but it waits a bit so the same error doesn't repeat in a tight loop
+                Err(error) => {
+                    error!("could not mine a block: {}", error);
+                    events.wait(Duration::from_millis(self.tx_waiting_ms));
                 }
             }
         }
     }
 
-    // Creates binary data mask with the amount of left padding zeroes indicated by the "difficulty" value
-    // Used to easily compare if a newly created block has a hash that matches the difficulty
-    fn create_target(difficulty: u32) -> BlockHash {
-        BlockHash::MAX >> difficulty
+    pub fn state(&self) -> MiningState {
+        match (self.can_seal(), self.auto_mining) {
+            (false, _) => MiningState::Disabled,
+            (true, true) => MiningState::Auto,
+            (true, false) => MiningState::OnDemand,
+        }
     }
 
-    // check if we have hit the limit of mined blocks (if the limit is set)
-    fn must_stop_mining(&self, block_counter: u64) -> bool {
-        self.max_blocks > 0 && block_counter >= self.max_blocks
-    }
+    // Mine a single block with all pending transactions in the pool, even if there are none
+    // It keeps trying until the block is added, starting over if a new block arrives meanwhile
+    pub fn mine_once(&self) -> Result<Block> {
+        if !self.can_seal() {
+            return Err(MinerError::CannotSeal.into());
+        }
+        if self.network_time.stops_mining() {
+            return Err(MinerError::ClockOffset.into());
+        }
 
-    // Tries to find the next valid block of the blockchain
-    // It will create blocks with different "nonce" values until one has a hash that matches the difficulty
-    // Returns either a valid block (that satisfies the difficulty) or "None" if no block was found
-    fn mine_block(&self, last_block: &Block, transactions: TransactionVec) -> Option<Block> {
-        for nonce in 0..self.max_nonce {
-            let next_block = self.create_next_block(last_block, transactions.clone(), nonce);
-
-            // A valid block must have a hash with enough starting zeroes
-            // To check that, we simply compare against a binary data mask
-            if next_block.hash < self.target {
-                return Some(next_block);
+        let mut tip = self.blockchain.watch_tip();
+        loop {
+            let transactions = self.pop_transactions();
+            if let Some(block) = self.mine_block(transactions, &mut tip)? {
+                return Ok(block);
             }
         }
+    }
 
-        None
+    fn can_seal(&self) -> bool {
+        self.consensus.can_seal() && !self.light_client
     }
 
-    // Creates a valid next block for a blockchain
-    // Takes into account the index and the hash of the previous block
-    fn create_next_block(
+    // Empty all transactions from the pool, they will be included in the new block
+    // Transactions may have been included meanwhile in blocks from peers, so we skip them
+    // The ones that would overflow the amounts of an address stay in the pool instead
+    // The pool keeps the ids of the selected ones until the block is added, so they are still duplicates meanwhile
+    fn pop_transactions(&self) -> TransactionVec {
+        let (included, transactions): (TransactionVec, TransactionVec) = self
+            .pool
+            .pop()
+            .into_iter()
+            .partition(|tx| self.blockchain.contains_transaction(tx.calculate_id()));
+        self.pool.release(&included);
+        let (selected, overflowing) = self.blockchain.select_transactions(transactions);
+        if !overflowing.is_empty() {
+            self.pool.return_transactions(overflowing);
+        }
+
+        selected
+    }
+
+    // Try to find a valid next block on top of the current last block, and add it to the blockchain
+    // Returns None if a new block arrived meanwhile, as ours would be stale
+    // Unless the block is added, the transactions go back to the pool to be mined again
+    fn mine_block(
         &self,
-        last_block: &Block,
         transactions: TransactionVec,
-        nonce: u64,
-    ) -> Block {
-        let index = (last_block.index + 1) as u64;
-        let previous_hash = last_block.hash;
+        tip: &mut WatchReceiver<BlockHash>,
+    ) -> Result<Option<Block>> {
+        // the consensus engine stops if a new block arrives
+        tip.borrow_and_update();
+        let last_block = self.blockchain.get_last_block();
+        let next_block = self.create_next_block(&last_block, transactions.clone());
+        let _span = info_span!(
+            "mine",
+            index = next_block.header.index,
+            bits = next_block.header.bits
+        )
+        .entered();
+        let hashes_before = self.consensus.hashes_tried();
+        let start = Instant::now();
+        let seal_outcome = self.consensus.seal(next_block, &|| tip.has_changed());
+        self.stats.record_attempt(
+            self.consensus.hashes_tried() - hashes_before,
+            start.elapsed(),
+        );
+        match seal_outcome {
+            SealOutcome::Sealed(block) => {
+                let block = *block;
+                match self.blockchain.add_block(block.clone()) {
+                    // only logged once it's in the chain, so whoever waits for it can already see it
+                    Ok(_) => {
+                        info!("valid block found for index {}", block.header.index);
+                        self.pool.release(&transactions);
+                        self.stats.record_block_found();
+                        Ok(Some(block))
+                    }
+                    // a new block arrived right after we found ours, so we mine again on top of it
+                    Err(_) if tip.has_changed() => {
+                        info!(
+                            "mined block {} is stale, restarting mining",
+                            block.header.index
+                        );
+                        self.pool.return_transactions(transactions);
+                        Ok(None)
+                    }
+                    Err(error) => {
+                        self.pool.return_transactions(transactions);
+                        Err(error)
+                    }
+                }
+            }
+            SealOutcome::Cancelled => {
+                info!("new last block received, restarting mining");
+                self.pool.return_transactions(transactions);
+                Ok(None)
+            }
+            SealOutcome::NotSealed => {
+                self.pool.return_transactions(transactions);
+                let index = last_block.header.index + 1;
+                error!("no valid block was foun for index {}", index);
+                Err(MinerError::BlockNotMined(index).into())
+            }
+        }
+    }
 
-        // hash of the new block is automatically calculated on creation
-        Block::new(index, nonce, previous_hash, transactions)
+    // check if we have hit the limit of mined blocks (if the limit is set)
+    fn must_stop_mining(&self, block_counter: u64) -> bool {
+        self.max_blocks > 0 && block_counter >= self.max_blocks
+    }
+
+    // Creates a new block, not sealed yet, that follows the last block of the blockchain
+    // Takes into account the index and the hash of the previous block, the target to satisfy
+    // and the state after the transactions
+    fn create_next_block(&self, last_block: &Block, transactions: TransactionVec) -> Block {
+        let index = last_block.header.index + 1;
+        let previous_hash = last_block.header.hash;
+
+        let mut block = Block::new(index, 0, previous_hash, transactions);
+        // our clock may be behind the median of the last blocks, which the timestamp must be after
+        block.header.timestamp = self.clock.now_millis().max(self.blockchain.min_timestamp());
+        block.header.bits = self.blockchain.next_bits();
+        block.header.state_root = self.blockchain.next_state_root(&block.transactions);
+        block.header.hash = block.calculate_hash();
+
+        block
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::model::Transaction;
+    use std::sync::Arc;
+
+    use crate::{
+        consensus::{ProofOfAuthority, ProofOfWork},
+        model::Transaction,
+        util::MockClock,
+    };
 
     // We use SHA 256 hashes
     const MAX_DIFFICULTY: u32 = 256;
@@ -143,64 +363,27 @@ mod tests {
         let miner = create_default_miner();
         let block = create_empty_block();
 
-        let next_block = miner.create_next_block(&block, Vec::new(), 0);
+        let next_block = miner.create_next_block(&block, Vec::new());
 
         // the next block must follow the previous one
-        assert_eq!(next_block.index, block.index + 1);
-        assert_eq!(next_block.previous_hash, block.hash);
-    }
-
-    #[test]
-    fn test_create_target_valid_difficulty() {
-        // try all possibilities of valid difficulties
-        // the target must have as many leading zeroes
-        for difficulty in 0..MAX_DIFFICULTY {
-            let target = Miner::create_target(difficulty);
-            assert_eq!(target.leading_zeros(), difficulty);
-        }
+        assert_eq!(next_block.header.index, block.header.index + 1);
+        assert_eq!(next_block.header.previous_hash, block.header.hash);
+        assert_eq!(next_block.header.bits, miner.blockchain.next_bits());
     }
 
     #[test]
-    fn test_create_target_overflowing_difficulty() {
-        // when passing an overflowing difficulty,
-        // it must default to the max difficulty
-        let target = Miner::create_target(MAX_DIFFICULTY + 1);
-        assert_eq!(target.leading_zeros(), MAX_DIFFICULTY);
-    }
-
-    #[test]
-    fn test_mine_block_found() {
-        // let's use a small difficulty target for fast testing
-        let difficulty = 1;
-
-        // this should be more than enough nonces to find a block with only 1 zero
-        let max_nonce = 1_000;
-
-        // check that the block is mined
-        let miner = create_miner(difficulty, max_nonce);
-        let last_block = create_empty_block();
-        let result = miner.mine_block(&last_block, Vec::new());
-        assert!(result.is_some());
-
-        // check that the block is valid
-        let mined_block = result.unwrap();
-        assert_mined_block_is_valid(&mined_block, &last_block, difficulty);
-    }
-
-    #[test]
-    fn test_mine_block_not_found() {
-        // let's use a high difficulty target to never find a block
-        let difficulty = MAX_DIFFICULTY;
-
-        // with a max_nonce so low, we will never find a block
-        // and also the test will end fast
-        let max_nonce = 10;
-
-        // check that the block is not mined
-        let miner = create_miner(difficulty, max_nonce);
-        let last_block = create_empty_block();
-        let result = miner.mine_block(&last_block, Vec::new());
-        assert!(result.is_none());
+    fn test_create_next_block_with_the_time_of_the_clock() {
+        let clock = MockClock::new(1_000_000);
+        let mut miner = create_default_miner();
+        miner.clock = clock.shared();
+        let last_block = miner.blockchain.get_last_block();
+
+        let next_block = miner.create_next_block(&last_block, Vec::new());
+        assert_eq!(next_block.header.timestamp, 1_000_000);
+
+        clock.advance(Duration::from_secs(10));
+        let next_block = miner.create_next_block(&last_block, Vec::new());
+        assert_eq!(next_block.header.timestamp, 1_010_000);
     }
 
     #[test]
@@ -220,6 +403,12 @@ mod tests {
         // mining should be successful
         assert!(result.is_ok());
 
+        // the stats must account for the work done
+        let stats = miner.stats.report();
+        assert_eq!(stats.blocks_found, 1);
+        assert!(stats.nonces_tried >= 1);
+        assert_eq!(stats.avg_block_time_ms, Some(stats.mining_time_ms));
+
         // a new block should have been added to the blockchain
         let blocks = blockchain.get_all_blocks();
         assert_eq!(blocks.len(), 2);
@@ -227,7 +416,7 @@ mod tests {
         let mined_block = &blocks[1];
 
         // the mined block must be valid
-        assert_mined_block_is_valid(mined_block, genesis_block, blockchain.difficulty);
+        assert_mined_block_is_valid(mined_block, genesis_block, difficulty);
 
         // the mined block must include the transaction added previously
         let mined_transactions = &mined_block.transactions;
@@ -240,7 +429,6 @@ mod tests {
     }
 
     #[test]
-    #[should_panic(expected = "No valid block was mined at index `1`")]
     fn test_run_block_not_found() {
         // with a max_nonce so low and difficulty so high
         // we will never find a valid block
@@ -251,8 +439,120 @@ mod tests {
         let pool = &miner.pool;
         add_mock_transaction(pool);
 
-        // mining should return a BlockNotMined error
-        miner.run().unwrap();
+        // mining should return a BlockNotMined error, with the transaction back in the pool
+        let err = miner.mine_once().unwrap_err();
+        assert!(matches!(
+            err.downcast::<MinerError>().unwrap(),
+            MinerError::BlockNotMined(1)
+        ));
+        assert_eq!(pool.size(), 1);
+
+        // while automatic mining keeps trying instead of stopping the node
+        let handle = {
+            let miner = miner.clone();
+            std::thread::spawn(move || miner.run())
+        };
+        let attempts = miner.stats.report().nonces_tried;
+        while miner.stats.report().nonces_tried < attempts + 3 {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert!(!handle.is_finished());
+        assert_eq!(miner.blockchain.get_last_block().header.index, 0);
+    }
+
+    #[test]
+    fn test_run_with_proof_of_authority() {
+        // a single signer always has the turn, so it signs every block
+        let seed = [1; 32];
+        let signers = vec![ProofOfAuthority::public_key_from_seed(&seed)];
+        let consensus = ProofOfAuthority::new(signers.clone(), Some(&seed), 0).unwrap();
+        let miner = create_miner_with_consensus(Arc::new(consensus));
+
+        add_mock_transaction(&miner.pool);
+        assert!(miner.run().is_ok());
+
+        let last_block = miner.blockchain.get_last_block();
+        assert_eq!(last_block.header.index, 1);
+        assert!(last_block.signature.is_some());
+
+        // nodes outside of the signer set stop mining right away
+        let consensus = ProofOfAuthority::new(signers, None, 0).unwrap();
+        let miner = create_miner_with_consensus(Arc::new(consensus));
+
+        add_mock_transaction(&miner.pool);
+        assert!(miner.run().is_ok());
+        assert_eq!(miner.blockchain.get_last_block().header.index, 0);
+    }
+
+    #[test]
+    fn test_mine_once() {
+        let miner = create_miner(1, 1_000_000);
+
+        // the block is mined even if there are no transactions
+        let block = miner.mine_once().unwrap();
+        assert_eq!(block.header.index, 1);
+        assert!(block.transactions.is_empty());
+        assert_eq!(
+            miner.blockchain.get_last_block().header.hash,
+            block.header.hash
+        );
+
+        add_mock_transaction(&miner.pool);
+        let block = miner.mine_once().unwrap();
+        assert_eq!(block.transactions.len(), 1);
+
+        // nodes outside of the signer set can not mine on demand either
+        let signers = vec![ProofOfAuthority::public_key_from_seed(&[1; 32])];
+        let consensus = ProofOfAuthority::new(signers, None, 0).unwrap();
+        let miner = create_miner_with_consensus(Arc::new(consensus));
+        assert!(miner.mine_once().is_err());
+    }
+
+    #[test]
+    fn test_not_mine_with_a_skewed_clock() {
+        let mut miner = create_miner(1, 1_000_000);
+        miner.network_time = NetworkTime::new(60, true);
+        for address in ["a:1", "b:2", "c:3"].iter() {
+            miner.network_time.add_sample(address, 120_000);
+        }
+
+        let err = miner.mine_once().unwrap_err();
+        assert!(matches!(
+            err.downcast::<MinerError>().unwrap(),
+            MinerError::ClockOffset
+        ));
+
+        // automatic mining waits until the clock agrees with the peers
+        add_mock_transaction(&miner.pool);
+        let handle = {
+            let network_time = miner.network_time.clone();
+            std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(50));
+                network_time.remove_sample("a:1");
+            })
+        };
+        assert!(miner.run().is_ok());
+        handle.join().unwrap();
+        assert_eq!(miner.blockchain.get_last_block().header.index, 1);
+    }
+
+    #[test]
+    fn test_stats_report() {
+        let stats = MinerStats::new();
+
+        // no work done yet
+        assert_eq!(stats.report(), MinerStatsReport::default());
+
+        stats.record_attempt(1000, Duration::from_millis(500));
+        stats.record_attempt(3000, Duration::from_millis(1500));
+        stats.record_block_found();
+
+        let report = stats.report();
+        assert_eq!(report.nonces_tried, 4000);
+        assert_eq!(report.blocks_found, 1);
+        assert_eq!(report.mining_time_ms, 2000);
+        assert_eq!(report.avg_block_time_ms, Some(2000));
+        assert!((report.hashes_per_sec - 2000.0).abs() < f64::EPSILON);
     }
 
     fn create_default_miner() -> Miner {
@@ -262,25 +562,34 @@ mod tests {
     }
 
     fn create_miner(difficulty: u32, max_nonce: u64) -> Miner {
+        let threads = 1;
+        create_miner_with_consensus(ProofOfWork::shared(difficulty, max_nonce, threads))
+    }
+
+    fn create_miner_with_consensus(consensus: SharedConsensus) -> Miner {
         let max_blocks = 1;
         let tx_waiting_ms = 1;
-        let target = Miner::create_target(difficulty);
 
-        let blockchain = Blockchain::new(difficulty);
+        let blockchain = Blockchain::new(consensus.clone());
         let pool = TransactionPool::new();
+        let clock = blockchain.clock();
 
         Miner {
+            auto_mining: true,
             max_blocks,
-            max_nonce,
             tx_waiting_ms,
             blockchain,
             pool,
-            target,
+            consensus,
+            stats: MinerStats::new(),
+            light_client: false,
+            clock,
+            network_time: NetworkTime::new(60, false),
         }
     }
 
     fn create_empty_block() -> Block {
-        return Block::new(0, 0, BlockHash::default(), Vec::new());
+        Block::new(0, 0, BlockHash::default(), Vec::new())
     }
 
     fn add_mock_transaction(pool: &TransactionPool) {
@@ -288,13 +597,18 @@ mod tests {
             sender: "1".to_string(),
             recipient: "2".to_string(),
             amount: 3,
+            signature: None,
+            multisig: None,
+            contract: None,
+            token: None,
+            data: None,
         };
-        pool.add_transaction(transaction.clone());
+        pool.add_transaction(transaction.clone()).unwrap();
     }
 
     fn assert_mined_block_is_valid(mined_block: &Block, previous_block: &Block, difficulty: u32) {
-        assert_eq!(mined_block.index, previous_block.index + 1);
-        assert_eq!(mined_block.previous_hash, previous_block.hash);
-        assert!(mined_block.hash.leading_zeros() >= difficulty as u32);
+        assert_eq!(mined_block.header.index, previous_block.header.index + 1);
+        assert_eq!(mined_block.header.previous_hash, previous_block.header.hash);
+        assert!(mined_block.header.hash.leading_zeros() >= difficulty);
     }
 }
@@ -1,11 +1,43 @@
+mod amount;
 mod block;
+mod block_store;
 mod blockchain;
+mod bloom;
+mod canonical;
+mod contract;
+mod encoding;
+mod events;
+mod history;
+// Serialization of hashes in json, to be used in `#[serde(with = ...)]` attributes
+pub mod hash_hex;
+mod merkle;
+mod multisig;
+mod schema;
+mod snapshot;
+mod state;
 mod transaction;
 mod transaction_pool;
 
 // Explicitly controlling which individual identifiers we export
 // It also avoids verbose module imports from other files
-pub use block::{Block, BlockHash};
+pub use amount::{format_amount, parse_amount, AmountError, MAX_DECIMALS};
+pub use block::{Block, BlockHash, BlockHeader, SealedBlock, SealedHeader};
+pub use block_store::BlockStore;
 pub use blockchain::{Blockchain, BlockchainError};
-pub use transaction::Transaction;
-pub use transaction_pool::{TransactionPool, TransactionVec};
+pub use bloom::{address_bloom, bloom_contains, AddressBloom};
+pub use contract::{Contract, ContractAction, ContractError, ContractState, Receipt};
+pub use encoding::{from_bytes, to_bytes, Decode, Encode, EncodingError, Reader};
+pub use events::{ChainEvent, EventBus, EventReceiver, ReorgEvent};
+pub use history::{AddressHistory, AddressTransaction};
+pub use merkle::{merkle_root, MerkleProof, TransactionProof};
+pub use multisig::{is_multisig_address, MultiSig, MultiSigError};
+pub use snapshot::{Snapshot, SnapshotError};
+pub use state::{
+    state_root, AccountState, Amounts, Token, TokenAction, TokenError, TokenId, TokenState,
+};
+pub use transaction::{
+    Transaction, TransactionError, TransactionId, DEFAULT_CHAIN_ID, MAX_DATA_LENGTH,
+};
+pub use transaction_pool::{
+    DropReason, PendingTransaction, TransactionPool, TransactionPoolError, TransactionVec,
+};
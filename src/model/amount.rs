@@ -0,0 +1,105 @@
+use thiserror::Error;
+
+// Amounts are integers of the smallest unit of the coin, so they are exact and every node adds them
+// up the same way. How many of the digits are decimals is only a matter of display (see AMOUNT_DECIMALS),
+// e.g. with 2 decimals an amount of 150 is shown as "1.50"
+
+// More decimals than the digits of the largest amount would be pointless
+pub const MAX_DECIMALS: u32 = 19;
+
+// Error types to return when an amount can't be parsed or accounted for
+#[derive(Error, PartialEq, Debug)]
+pub enum AmountError {
+    #[error("The amounts of {0} would overflow")]
+    Overflow(String),
+
+    #[error("Invalid amount `{0}`")]
+    InvalidAmount(String),
+
+    #[error("Amounts can't have more than {0} decimals")]
+    TooManyDecimals(u32),
+}
+
+// Shows an amount in smallest units with the given number of decimals
+// Balances can be negative (e.g. with pending transactions), so it takes signed amounts
+pub fn format_amount(amount: i128, decimals: u32) -> String {
+    if decimals == 0 {
+        return amount.to_string();
+    }
+
+    let sign = if amount < 0 { "-" } else { "" };
+    let digits = format!(
+        "{:0>width$}",
+        amount.unsigned_abs(),
+        width = decimals as usize + 1
+    );
+    let (units, fraction) = digits.split_at(digits.len() - decimals as usize);
+    format!("{}{}.{}", sign, units, fraction)
+}
+
+// Reads an amount with up to the given number of decimals into smallest units
+// e.g. "1.5" with 2 decimals is 150
+pub fn parse_amount(value: &str, decimals: u32) -> Result<u64, AmountError> {
+    let invalid = || AmountError::InvalidAmount(value.to_string());
+    let (units, fraction) = match value.split_once('.') {
+        Some((units, fraction)) => (units, fraction),
+        None => (value, ""),
+    };
+    let is_number = |digits: &str| digits.bytes().all(|byte| byte.is_ascii_digit());
+    if units.is_empty() || !is_number(units) || !is_number(fraction) {
+        return Err(invalid());
+    }
+    if value.contains('.') && fraction.is_empty() {
+        return Err(invalid());
+    }
+    if fraction.len() > decimals as usize {
+        return Err(AmountError::TooManyDecimals(decimals));
+    }
+
+    let digits = format!("{}{:0<width$}", units, fraction, width = decimals as usize);
+    digits.parse().map_err(|_| invalid())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_format_amounts_with_decimals() {
+        assert_eq!(format_amount(150, 0), "150");
+        assert_eq!(format_amount(150, 2), "1.50");
+        assert_eq!(format_amount(5, 3), "0.005");
+        assert_eq!(format_amount(-5, 2), "-0.05");
+        assert_eq!(
+            format_amount(u64::MAX as i128, MAX_DECIMALS),
+            "1.8446744073709551615"
+        );
+    }
+
+    #[test]
+    fn should_parse_amounts_with_decimals() {
+        assert_eq!(parse_amount("150", 0), Ok(150));
+        assert_eq!(parse_amount("1.5", 2), Ok(150));
+        assert_eq!(parse_amount("0.05", 2), Ok(5));
+        assert_eq!(parse_amount("007", 2), Ok(700));
+        assert_eq!(
+            parse_amount("1.234", 2),
+            Err(AmountError::TooManyDecimals(2))
+        );
+        assert_eq!(parse_amount("1.5", 0), Err(AmountError::TooManyDecimals(0)));
+
+        // amounts that don't fit in 64 bits are also rejected
+        for value in [
+            "",
+            ".5",
+            "1.",
+            "-1",
+            "1e3",
+            "1.2.3",
+            "184467440737095516.16",
+        ] {
+            let error = AmountError::InvalidAmount(value.to_string());
+            assert_eq!(parse_amount(value, 2), Err(error), "{}", value);
+        }
+    }
+}
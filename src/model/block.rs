@@ -3,26 +3,75 @@ use crypto::digest::Digest;
 use crypto::sha2::Sha256;
 use ethereum_types::U256;
 use serde::{Deserialize, Serialize};
+use std::{borrow::Borrow, ops::Deref};
 
-use super::Transaction;
+use super::{
+    address_bloom, canonical, hash_hex, merkle_root, AddressBloom, Transaction, TransactionId,
+};
 
 // We encapsulate the paricular hash value implementation
 // to be able to easily change it in the future
 pub type BlockHash = U256;
 
-// Represents a block in a blockchain
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Block {
+// Everything in a block except its transactions, which are committed by the Merkle root
+// The hash only covers the header, so a chain of headers can be checked without the transactions
+// (e.g. to decide whether a block is worth downloading, or by light clients)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BlockHeader {
     pub index: u64,
     pub timestamp: i64,
     pub nonce: u64,
+    // Target that the hash must satisfy, in compact form (see "consensus/target.rs")
+    pub bits: u32,
+    #[serde(with = "hash_hex")]
     pub previous_hash: BlockHash,
+    // Root of the Merkle tree of the ids of the transactions (see "merkle.rs")
+    #[serde(with = "hash_hex")]
+    pub merkle_root: BlockHash,
+    // Hash of the balances of every address after applying the transactions (see "state.rs")
+    #[serde(with = "hash_hex")]
+    pub state_root: BlockHash,
+    // Bloom filter of the senders and recipients of the transactions (see "bloom.rs")
+    pub bloom: AddressBloom,
+    #[serde(with = "hash_hex")]
     pub hash: BlockHash,
+}
+
+impl BlockHeader {
+    // Calculate the hash value of the header, which is the hash of the whole block
+    pub fn calculate_hash(&self) -> BlockHash {
+        // The canonical bytes leave out the hash field, as it's what we are calculating
+        let serialized = canonical::header_bytes(self);
+
+        // Cacluate and return the SHA-256 hash value for the header
+        let mut byte_hash = <[u8; 32]>::default();
+        let mut hasher = Sha256::new();
+
+        hasher.input(&serialized);
+        hasher.result(&mut byte_hash);
+
+        U256::from(byte_hash)
+    }
+}
+
+// Represents a block in a blockchain: the header and a body with the transactions
+// The header is flattened, so blocks keep the same json representation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Block {
+    #[serde(flatten)]
+    pub header: BlockHeader,
     pub transactions: Vec<Transaction>,
+    // Only used by consensus engines that require blocks to be signed, it's made over the hash
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+    // Set when a pruned node dropped the transactions, only the header is left
+    // The hash can still be checked, as it only covers the header
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub pruned: bool,
 }
 
 impl Block {
-    // Create a brand new block. The hash value will be caclulated and set automatically.
+    // Create a brand new block. The Merkle root, the bloom and the hash will be caclulated and set automatically.
     pub fn new(
         index: u64,
         nonce: u64,
@@ -30,32 +79,187 @@ impl Block {
         transactions: Vec<Transaction>,
     ) -> Block {
         let mut block = Block {
-            index,
-            timestamp: Utc::now().timestamp_millis(),
-            nonce,
-            previous_hash,
-            hash: BlockHash::default(),
+            header: BlockHeader {
+                index,
+                timestamp: Utc::now().timestamp_millis(),
+                nonce,
+                bits: 0,
+                previous_hash,
+                merkle_root: BlockHash::default(),
+                // it depends on the state of the chain, so it's set by whoever builds the block
+                state_root: BlockHash::default(),
+                bloom: AddressBloom::default(),
+                hash: BlockHash::default(),
+            },
             transactions,
+            signature: None,
+            pruned: false,
         };
-        block.hash = block.calculate_hash();
+        block.header.merkle_root = block.calculate_merkle_root();
+        block.header.bloom = block.calculate_bloom();
+        block.header.hash = block.calculate_hash();
 
         block
     }
 
-    // Calculate the hash value of the block
+    pub fn header(&self) -> BlockHeader {
+        self.header.clone()
+    }
+
+    // Calculate the hash value of the block, from its header alone
     pub fn calculate_hash(&self) -> BlockHash {
-        // We cannot use the hash field to calculate the hash
-        let mut hashable_data = self.clone();
-        hashable_data.hash = BlockHash::default();
-        let serialized = serde_json::to_string(&hashable_data).unwrap();
+        self.header.calculate_hash()
+    }
 
-        // Cacluate and return the SHA-256 hash value for the block
-        let mut byte_hash = <[u8; 32]>::default();
-        let mut hasher = Sha256::new();
+    // Calculate the root that the header must carry for the transactions of the block
+    pub fn calculate_merkle_root(&self) -> BlockHash {
+        let ids: Vec<TransactionId> = self
+            .transactions
+            .iter()
+            .map(Transaction::calculate_id)
+            .collect();
 
-        hasher.input_str(&serialized);
-        hasher.result(&mut byte_hash);
+        merkle_root(&ids)
+    }
 
-        U256::from(byte_hash)
+    // Calculate the bloom filter that the header must carry for the transactions of the block
+    pub fn calculate_bloom(&self) -> AddressBloom {
+        address_bloom(&self.transactions)
+    }
+}
+
+// A header whose hash is known to match its contents, so it never needs to be calculated again
+// It can only be read, to change it it must be unsealed, and sealing it again checks the hash
+#[derive(Debug, Clone, PartialEq)]
+pub struct SealedHeader(BlockHeader);
+
+impl SealedHeader {
+    // Returns None if the hash of the header is not right
+    pub fn new(header: BlockHeader) -> Option<SealedHeader> {
+        match header.hash == header.calculate_hash() {
+            true => Some(SealedHeader(header)),
+            false => None,
+        }
+    }
+
+    pub fn into_inner(self) -> BlockHeader {
+        self.0
+    }
+}
+
+impl Deref for SealedHeader {
+    type Target = BlockHeader;
+
+    fn deref(&self) -> &BlockHeader {
+        &self.0
+    }
+}
+
+// A block whose hash is known to match its header, which is what the validation of the blockchain takes
+// Same as with headers, it can only be read, so the hash checked once can't become stale
+#[derive(Debug, Clone)]
+pub struct SealedBlock(Block);
+
+impl SealedBlock {
+    // Returns None if the hash of the block is not right
+    pub fn new(block: Block) -> Option<SealedBlock> {
+        match block.header.hash == block.calculate_hash() {
+            true => Some(SealedBlock(block)),
+            false => None,
+        }
+    }
+
+    // Seals a block with a header that was already sealed, without hashing it again
+    // e.g. the blocks downloaded after their headers when syncing. Returns None if the headers differ
+    pub fn with_header(block: Block, header: &SealedHeader) -> Option<SealedBlock> {
+        match block.header == **header {
+            true => Some(SealedBlock(block)),
+            false => None,
+        }
+    }
+
+    // For the blockchain, after checking the hashes of several blocks at once
+    pub(super) fn from_checked(block: Block) -> SealedBlock {
+        SealedBlock(block)
+    }
+
+    pub fn into_inner(self) -> Block {
+        self.0
+    }
+}
+
+impl Deref for SealedBlock {
+    type Target = Block;
+
+    fn deref(&self) -> &Block {
+        &self.0
+    }
+}
+
+impl Borrow<Block> for SealedBlock {
+    fn borrow(&self) -> &Block {
+        &self.0
+    }
+}
+
+// Unpruned blocks are serialized as they were before pruning existed
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_only_hash_the_header() {
+        let transaction = Transaction {
+            sender: "1".to_string(),
+            recipient: "2".to_string(),
+            amount: 3,
+            signature: None,
+            multisig: None,
+            contract: None,
+            token: None,
+            data: None,
+        };
+        let mut block = Block::new(1, 0, BlockHash::default(), vec![transaction]);
+        let hash = block.calculate_hash();
+        assert_eq!(block.header.hash, hash);
+        assert_eq!(block.header.merkle_root, block.calculate_merkle_root());
+
+        // the transactions are only committed through the merkle root
+        block.transactions.clear();
+        assert_eq!(block.calculate_hash(), hash);
+        assert_ne!(block.calculate_merkle_root(), block.header.merkle_root);
+
+        // the header keeps the same flat json representation of the block
+        let json = serde_json::to_value(&block).unwrap();
+        assert!(json["merkle_root"].is_string());
+        assert!(json["transactions"].is_array());
+        assert_eq!(
+            serde_json::from_value::<Block>(json).unwrap().header,
+            block.header
+        );
+    }
+
+    #[test]
+    fn should_only_seal_blocks_with_the_right_hash() {
+        let mut block = Block::new(1, 0, BlockHash::default(), Vec::new());
+        let header = SealedHeader::new(block.header()).unwrap();
+        assert_eq!(header.hash, block.header.hash);
+        assert!(SealedBlock::new(block.clone()).is_some());
+        assert!(SealedBlock::with_header(block.clone(), &header).is_some());
+
+        block.header.nonce += 1;
+        assert!(SealedHeader::new(block.header()).is_none());
+        assert!(SealedBlock::new(block.clone()).is_none());
+        // the header was sealed before the change, so they don't match anymore
+        assert!(SealedBlock::with_header(block.clone(), &header).is_none());
+
+        block.header.hash = block.calculate_hash();
+        let sealed = SealedBlock::new(block.clone()).unwrap();
+        assert_eq!(sealed.header.nonce, 1);
+        assert_eq!(sealed.into_inner().header, block.header);
     }
 }
@@ -0,0 +1,478 @@
+use std::{
+    convert::TryInto,
+    fs::{self, File, OpenOptions},
+    io::{Read, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context as _, Result};
+use crypto::{digest::Digest, sha2::Sha256};
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+use thiserror::Error;
+
+use super::{encoding, Block};
+
+// Name of the file with the blocks, inside the data directory
+const BLOCKS_FILE: &str = "blocks.log";
+
+// Each record is the length of the block, the SHA-256 checksum of the block and the encoded block
+// Stores written by older versions have json blocks instead, which are still read
+// Compressed blocks start with this flag instead, which is neither a version of the encoding nor a json brace
+const LENGTH_SIZE: usize = 4;
+const COMPRESSED_FLAG: u8 = 0xc0;
+const CHECKSUM_SIZE: usize = 32;
+const HEADER_SIZE: usize = LENGTH_SIZE + CHECKSUM_SIZE;
+
+// Error types to return when the stored blocks can not be used
+#[derive(Error, PartialEq, Debug)]
+pub enum BlockStoreError {
+    #[error("Block {0} of the store does not follow the previous one, the file was modified")]
+    Unordered(u64),
+}
+
+// How the block of a record is written, each record has its own so stores can mix them
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RecordFormat {
+    Json,
+    Encoded,
+    Compressed,
+}
+
+// Append-only log of the blocks of the chain, so the node doesn't start from scratch after a restart
+// A block is only part of the store once its whole record is on disk, so appends are all-or-nothing:
+// if the process dies in the middle of one, the partial record is discarded when opening the store
+// The only other change is dropping the last blocks, when the chain switches to a fork
+#[derive(Debug)]
+pub struct BlockStore {
+    path: PathBuf,
+    file: File,
+    // length of the valid records, where the next one starts
+    length: u64,
+    // index of each stored block and where its record ends
+    records: Vec<(u64, u64)>,
+    // whether new blocks are compressed, which takes a bit of cpu but most of their size
+    compress: bool,
+}
+
+impl BlockStore {
+    // Opens (or creates) the store in a directory, returning the blocks it contains
+    // A partially written record at the end (a torn write) is removed, the blocks before it are kept
+    // Blocks stored in another format (e.g. before compression was enabled) are written again with "compress"
+    pub fn open(data_dir: &str, compress: bool) -> Result<(BlockStore, Vec<Block>)> {
+        fs::create_dir_all(data_dir)
+            .with_context(|| format!("could not create the data directory {}", data_dir))?;
+        let path = Path::new(data_dir).join(BLOCKS_FILE);
+
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&path)
+            .with_context(|| format!("could not open {}", path.display()))?;
+
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)
+            .with_context(|| format!("could not read {}", path.display()))?;
+
+        let (blocks, ends, formats) = read_records(&bytes)?;
+        let length = ends.last().copied().unwrap_or(0);
+        if length < bytes.len() {
+            warn!(
+                "discarding {} bytes of a partially written block at the end of {}",
+                bytes.len() - length,
+                path.display()
+            );
+            file.set_len(length as u64)?;
+            file.sync_all()?;
+        }
+
+        let records = blocks
+            .iter()
+            .zip(ends)
+            .map(|(block, end)| (block.header.index, end as u64))
+            .collect();
+        let mut store = BlockStore {
+            path,
+            file,
+            length: length as u64,
+            records,
+            compress,
+        };
+
+        let format = match compress {
+            true => RecordFormat::Compressed,
+            false => RecordFormat::Encoded,
+        };
+        let outdated = formats.iter().filter(|other| **other != format).count();
+        if outdated > 0 {
+            store.rewrite(&blocks)?;
+            info!(
+                "rewrote {} blocks of {} as {:?}",
+                outdated,
+                store.path.display(),
+                format
+            );
+        }
+
+        Ok((store, blocks))
+    }
+
+    // Writes all the blocks again in the current format, into a new file that replaces the old one
+    // The old file is only replaced once the new one is complete, so a crash in the middle loses nothing
+    fn rewrite(&mut self, blocks: &[Block]) -> Result<()> {
+        let temp_path = self.path.with_extension("tmp");
+        let mut bytes = Vec::new();
+        let mut records = Vec::new();
+        for block in blocks {
+            bytes.extend(encode_record(block, self.compress)?);
+            records.push((block.header.index, bytes.len() as u64));
+        }
+
+        let mut file = File::create(&temp_path)
+            .with_context(|| format!("could not create {}", temp_path.display()))?;
+        file.write_all(&bytes)
+            .and_then(|_| file.sync_all())
+            .and_then(|_| fs::rename(&temp_path, &self.path))
+            .with_context(|| format!("could not rewrite {}", self.path.display()))?;
+
+        self.file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .open(&self.path)
+            .with_context(|| format!("could not open {}", self.path.display()))?;
+        self.length = bytes.len() as u64;
+        self.records = records;
+        Ok(())
+    }
+
+    // Writes a block and waits until it's on disk, so it survives a crash right after returning
+    pub fn append(&mut self, block: &Block) -> Result<()> {
+        self.append_all(std::slice::from_ref(block))
+    }
+
+    // Writes several blocks at once, with a single wait for the disk
+    // If the write fails none of them is kept, as they are truncated together
+    pub fn append_all(&mut self, blocks: &[Block]) -> Result<()> {
+        let mut record = Vec::new();
+        let mut records = Vec::new();
+        for block in blocks {
+            record.extend(encode_record(block, self.compress)?);
+            records.push((block.header.index, self.length + record.len() as u64));
+        }
+
+        let result = self
+            .file
+            .write_all(&record)
+            .and_then(|_| self.file.sync_data());
+        if let Err(error) = result {
+            // the partial record would be discarded on the next start anyway,
+            // but later appends would be written after it and lost with it
+            let _ = self.file.set_len(self.length);
+            return Err(error).with_context(|| format!("could not write {}", self.path.display()));
+        }
+
+        self.length += record.len() as u64;
+        self.records.extend(records);
+        Ok(())
+    }
+
+    // Drops the blocks after "index", waiting until they are gone from the disk
+    pub fn truncate_after(&mut self, index: u64) -> Result<()> {
+        let kept = self
+            .records
+            .iter()
+            .take_while(|(block_index, _)| *block_index <= index)
+            .count();
+        let length = match kept {
+            0 => 0,
+            kept => self.records[kept - 1].1,
+        };
+
+        self.file
+            .set_len(length)
+            .and_then(|_| self.file.sync_data())
+            .with_context(|| format!("could not truncate {}", self.path.display()))?;
+        self.length = length;
+        self.records.truncate(kept);
+        Ok(())
+    }
+}
+
+fn encode_record(block: &Block, compress: bool) -> Result<Vec<u8>> {
+    let mut payload = encoding::to_bytes(block);
+    if compress {
+        let mut encoder = DeflateEncoder::new(vec![COMPRESSED_FLAG], Compression::default());
+        encoder.write_all(&payload)?;
+        payload = encoder.finish()?;
+    }
+
+    let mut record = Vec::with_capacity(HEADER_SIZE + payload.len());
+    record.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    record.extend_from_slice(&checksum(&payload));
+    record.extend_from_slice(&payload);
+    Ok(record)
+}
+
+// Returns the blocks of the complete records, where each of them ends and their format
+// Reading stops at the first record that is truncated or doesn't match its checksum,
+// as only the last one can be partially written
+fn read_records(bytes: &[u8]) -> Result<(Vec<Block>, Vec<usize>, Vec<RecordFormat>)> {
+    let mut blocks: Vec<Block> = Vec::new();
+    let mut ends = Vec::new();
+    let mut formats = Vec::new();
+    let mut position = 0;
+
+    while bytes.len() - position >= HEADER_SIZE {
+        let header = &bytes[position..position + HEADER_SIZE];
+        let length = u32::from_be_bytes(header[..LENGTH_SIZE].try_into().unwrap()) as usize;
+        let payload_start = position + HEADER_SIZE;
+        if bytes.len() - payload_start < length {
+            break;
+        }
+
+        let payload = &bytes[payload_start..payload_start + length];
+        if checksum(payload) != header[LENGTH_SIZE..] {
+            break;
+        }
+        let (block, format) = match decode_block(payload) {
+            Some(decoded) => decoded,
+            None => break,
+        };
+
+        // the blocks were validated before being stored, so they must be consecutive
+        if let Some(previous) = blocks.last() {
+            if block.header.index != previous.header.index + 1
+                || block.header.previous_hash != previous.header.hash
+            {
+                return Err(BlockStoreError::Unordered(block.header.index).into());
+            }
+        }
+
+        blocks.push(block);
+        position = payload_start + length;
+        ends.push(position);
+        formats.push(format);
+    }
+
+    Ok((blocks, ends, formats))
+}
+
+// Json documents start with a brace, while encoded blocks start with their version
+fn decode_block(payload: &[u8]) -> Option<(Block, RecordFormat)> {
+    match payload.first() {
+        Some(b'{') => Some((serde_json::from_slice(payload).ok()?, RecordFormat::Json)),
+        Some(&COMPRESSED_FLAG) => {
+            let mut decoded = Vec::new();
+            DeflateDecoder::new(&payload[1..])
+                .read_to_end(&mut decoded)
+                .ok()?;
+            let block = encoding::from_bytes(&decoded).ok()?;
+            Some((block, RecordFormat::Compressed))
+        }
+        _ => Some((encoding::from_bytes(payload).ok()?, RecordFormat::Encoded)),
+    }
+}
+
+fn checksum(payload: &[u8]) -> [u8; CHECKSUM_SIZE] {
+    let mut hasher = Sha256::new();
+    hasher.input(payload);
+
+    let mut checksum = [0; CHECKSUM_SIZE];
+    hasher.result(&mut checksum);
+    checksum
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::BlockHash;
+    use std::env;
+
+    #[test]
+    fn should_reopen_stored_blocks() {
+        let data_dir = create_data_dir("reopen");
+        let blocks = create_chain(3);
+
+        let (mut store, stored_blocks) = BlockStore::open(&data_dir, false).unwrap();
+        assert!(stored_blocks.is_empty());
+        for block in blocks.iter() {
+            store.append(block).unwrap();
+        }
+        drop(store);
+
+        let (_, stored_blocks) = BlockStore::open(&data_dir, false).unwrap();
+        assert_eq!(stored_blocks.len(), 3);
+        assert_eq!(stored_blocks[2].header.hash, blocks[2].header.hash);
+
+        fs::remove_dir_all(data_dir).unwrap();
+    }
+
+    #[test]
+    fn should_repair_torn_writes() {
+        let data_dir = create_data_dir("torn");
+        let blocks = create_chain(3);
+        let (mut store, _) = BlockStore::open(&data_dir, false).unwrap();
+        store.append(&blocks[0]).unwrap();
+        store.append(&blocks[1]).unwrap();
+        let valid_length = store.length;
+        drop(store);
+
+        // the process died while writing the third block
+        let path = Path::new(&data_dir).join(BLOCKS_FILE);
+        let record = encode_record(&blocks[2], false).unwrap();
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&record[..record.len() / 2]).unwrap();
+        drop(file);
+
+        let (mut store, stored_blocks) = BlockStore::open(&data_dir, false).unwrap();
+        assert_eq!(stored_blocks.len(), 2);
+        assert_eq!(fs::metadata(&path).unwrap().len(), valid_length);
+
+        // new blocks are written right after the last complete one
+        store.append(&blocks[2]).unwrap();
+        drop(store);
+        let (_, stored_blocks) = BlockStore::open(&data_dir, false).unwrap();
+        assert_eq!(stored_blocks.len(), 3);
+
+        fs::remove_dir_all(data_dir).unwrap();
+    }
+
+    #[test]
+    fn should_drop_the_last_blocks() {
+        let data_dir = create_data_dir("truncate");
+        let blocks = create_chain(4);
+        let (mut store, _) = BlockStore::open(&data_dir, false).unwrap();
+        store.append_all(&blocks[..3]).unwrap();
+
+        // the blocks after the fork are replaced by the ones of the other branch
+        store.truncate_after(1).unwrap();
+        store.append(&blocks[2]).unwrap();
+        store.append(&blocks[3]).unwrap();
+        drop(store);
+
+        let (mut store, stored_blocks) = BlockStore::open(&data_dir, false).unwrap();
+        let indexes: Vec<u64> = stored_blocks
+            .iter()
+            .map(|block| block.header.index)
+            .collect();
+        assert_eq!(indexes, vec![0, 1, 2, 3]);
+
+        store.truncate_after(0).unwrap();
+        drop(store);
+        let (_, stored_blocks) = BlockStore::open(&data_dir, false).unwrap();
+        assert_eq!(stored_blocks.len(), 1);
+
+        fs::remove_dir_all(data_dir).unwrap();
+    }
+
+    #[test]
+    fn should_discard_records_not_matching_their_checksum() {
+        let mut bytes = Vec::new();
+        let blocks = create_chain(2);
+        bytes.extend(encode_record(&blocks[0], false).unwrap());
+        let valid_length = bytes.len();
+
+        let mut record = encode_record(&blocks[1], false).unwrap();
+        let last = record.len() - 1;
+        record[last] ^= 0xff;
+        bytes.extend(record);
+
+        let (stored_blocks, ends, _) = read_records(&bytes).unwrap();
+        assert_eq!(stored_blocks.len(), 1);
+        assert_eq!(ends, vec![valid_length]);
+    }
+
+    #[test]
+    fn should_reject_unordered_blocks() {
+        let blocks = create_chain(3);
+        let mut bytes = encode_record(&blocks[0], false).unwrap();
+        bytes.extend(encode_record(&blocks[2], false).unwrap());
+
+        let err = read_records(&bytes).unwrap_err();
+        let err = err.downcast::<BlockStoreError>().unwrap();
+        assert_eq!(err, BlockStoreError::Unordered(2));
+    }
+
+    #[test]
+    fn should_read_json_records_of_older_versions() {
+        let blocks = create_chain(3);
+        let mut bytes = Vec::new();
+        for block in blocks[..2].iter() {
+            let payload = serde_json::to_vec(block).unwrap();
+            bytes.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+            bytes.extend_from_slice(&checksum(&payload));
+            bytes.extend(payload);
+        }
+        bytes.extend(encode_record(&blocks[2], false).unwrap());
+
+        let (stored_blocks, ends, _) = read_records(&bytes).unwrap();
+        assert_eq!(stored_blocks.len(), 3);
+        assert_eq!(ends.last(), Some(&bytes.len()));
+        assert_eq!(stored_blocks[1].header.hash, blocks[1].header.hash);
+    }
+
+    #[test]
+    fn should_compress_blocks() {
+        let blocks = create_chain(2);
+        let mut bytes = encode_record(&blocks[0], true).unwrap();
+        bytes.extend(encode_record(&blocks[1], false).unwrap());
+        assert_eq!(bytes[LENGTH_SIZE + CHECKSUM_SIZE], COMPRESSED_FLAG);
+
+        let (stored_blocks, _, formats) = read_records(&bytes).unwrap();
+        assert_eq!(stored_blocks[0].header.hash, blocks[0].header.hash);
+        assert_eq!(
+            formats,
+            vec![RecordFormat::Compressed, RecordFormat::Encoded]
+        );
+    }
+
+    #[test]
+    fn should_rewrite_the_blocks_when_the_compression_changes() {
+        let data_dir = create_data_dir("compression");
+        let blocks = create_chain(3);
+        let (mut store, _) = BlockStore::open(&data_dir, false).unwrap();
+        store.append_all(&blocks[..2]).unwrap();
+        drop(store);
+
+        let path = Path::new(&data_dir).join(BLOCKS_FILE);
+        let (mut store, stored_blocks) = BlockStore::open(&data_dir, true).unwrap();
+        assert_eq!(stored_blocks.len(), 2);
+        store.append(&blocks[2]).unwrap();
+        drop(store);
+
+        let (_, _, formats) = read_records(&fs::read(&path).unwrap()).unwrap();
+        assert_eq!(formats, vec![RecordFormat::Compressed; 3]);
+
+        // and back, the rewritten file can still be truncated and appended to
+        let (mut store, stored_blocks) = BlockStore::open(&data_dir, false).unwrap();
+        assert_eq!(stored_blocks.len(), 3);
+        store.truncate_after(1).unwrap();
+        store.append(&blocks[2]).unwrap();
+        drop(store);
+
+        let (_, _, formats) = read_records(&fs::read(&path).unwrap()).unwrap();
+        assert_eq!(formats, vec![RecordFormat::Encoded; 3]);
+        assert!(!path.with_extension("tmp").exists());
+
+        fs::remove_dir_all(data_dir).unwrap();
+    }
+
+    fn create_chain(length: u64) -> Vec<Block> {
+        let mut blocks: Vec<Block> = Vec::new();
+        for index in 0..length {
+            let previous_hash = blocks
+                .last()
+                .map_or(BlockHash::default(), |last| last.header.hash);
+            let mut block = Block::new(index, 0, previous_hash, Vec::new());
+            block.header.hash = block.calculate_hash();
+            blocks.push(block);
+        }
+        blocks
+    }
+
+    fn create_data_dir(name: &str) -> String {
+        let path = env::temp_dir().join(format!("block_store_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        path.to_string_lossy().to_string()
+    }
+}
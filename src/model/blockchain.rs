@@ -1,13 +1,50 @@
-use anyhow::Result;
-use std::sync::{Arc, Mutex};
+use anyhow::{Context as _, Result};
+use std::{
+    borrow::Borrow,
+    collections::HashMap,
+    ops::Range,
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    thread,
+};
 use thiserror::Error;
+use tracing::info_span;
 
-use super::{Block, BlockHash};
+use super::{
+    bloom_contains, schema, state_root, AccountState, AddressHistory, AddressTransaction, Amounts,
+    Block, BlockHash, BlockHeader, BlockStore, ChainEvent, Contract, ContractError, ContractState,
+    EventBus, MerkleProof, Receipt, ReorgEvent, SealedBlock, SealedHeader, Snapshot, SnapshotError,
+    Token, TokenAction, TokenError, TokenId, TokenState, Transaction, TransactionId,
+    TransactionProof, TransactionVec, DEFAULT_CHAIN_ID,
+};
+use crate::{
+    consensus::SharedConsensus,
+    util::{
+        watch::{self, WatchReceiver, WatchSender},
+        Lock, SharedClock, SystemClock,
+    },
+};
 
 pub type BlockVec = Vec<Block>;
 
 // We don't need to export this because concurrency is encapsulated in this file
-type SyncedBlockVec = Arc<Mutex<BlockVec>>;
+type SyncedBlockVec = Arc<Lock<BlockVec>>;
+
+// Name of the file with the snapshot the chain started from, inside the data directory
+const SNAPSHOT_FILE: &str = "snapshot.json";
+
+// New blocks must be timestamped after the median of this many previous blocks, like in Bitcoin
+// The median can't be pushed back by a single miner with a wrong clock
+const MEDIAN_TIME_SPAN: usize = 11;
+
+// How far in the future the timestamp of a block can be, unless configured otherwise
+const DEFAULT_MAX_TIME_DRIFT_SECS: u64 = 2 * 60 * 60;
+
+// Blocks of a batch that each validation thread checks at least, it's not worth spawning a thread for less
+const MIN_BLOCKS_PER_THREAD: usize = 16;
 
 // Error types to return when trying to add blocks with invalid fields
 #[derive(Error, PartialEq, Debug)]
@@ -24,81 +61,1164 @@ pub enum BlockchainError {
 
     #[error("Invalid difficulty")]
     InvalidDifficulty,
+
+    #[error("Invalid signature")]
+    InvalidSignature,
+
+    #[error("Invalid target bits")]
+    InvalidTarget,
+
+    #[error("Invalid merkle_root")]
+    InvalidMerkleRoot,
+
+    #[error("Invalid bloom")]
+    InvalidBloom,
+
+    #[error("Invalid state_root")]
+    InvalidStateRoot,
+
+    #[error("Invalid signature of transaction {0:#x}, it must be made for this chain")]
+    InvalidTransactionSignature(TransactionId),
+
+    #[error("Invalid timestamp, it must be after {0} (the median of the last blocks)")]
+    InvalidTimestampTooOld(i64),
+
+    #[error("Invalid timestamp, it's more than {0} seconds in the future")]
+    InvalidTimestampInFuture(u64),
+
+    #[error("The stored blocks belong to a different chain, with genesis block {0:#x}")]
+    DifferentGenesis(BlockHash),
+
+    #[error("Can't switch to a fork from block {0}, the transactions of the blocks after it were pruned")]
+    ForkTooDeep(u64),
+
+    #[error("The fork doesn't have more work than the chain")]
+    NotEnoughWork,
+}
+
+// What is left of the transactions of the pruned blocks
+// It's enough for balances and transaction lookups to give the same answers as with the whole blocks
+#[derive(Debug, Clone, Default)]
+struct PrunedState {
+    // number of blocks at the end of the chain that keep their transactions, 0 keeps all of them
+    depth: u64,
+    // index of the first block that was not pruned yet
+    next_index: u64,
+    // amounts received and sent by each address in the pruned blocks
+    amounts: AccountState,
+    // contracts and tokens after running the transactions of the pruned blocks
+    contracts: ContractState,
+    tokens: TokenState,
+}
+
+impl PrunedState {
+    // Drops the transactions of the blocks that are more than "depth" blocks deep
+    fn prune(&mut self, blocks: &mut BlockVec) {
+        if self.depth == 0 {
+            return;
+        }
+
+        let last_index = blocks.len() as u64 - 1;
+        while self.next_index + self.depth <= last_index {
+            self.prune_block(&mut blocks[self.next_index as usize]);
+        }
+    }
+
+    // Blocks must be pruned in order, as "next_index" only moves forward
+    fn prune_block(&mut self, block: &mut Block) {
+        self.amounts
+            .apply(&block.transactions)
+            .expect("only valid blocks are pruned, their amounts can't overflow");
+        self.contracts.apply(&block.transactions);
+        self.tokens.apply(&block.transactions);
+        block.transactions.clear();
+        block.pruned = true;
+        self.next_index = block.header.index + 1;
+    }
+}
+
+// State derived from the transactions of the blocks, so it doesn't need to be calculated again
+#[derive(Debug, Default)]
+struct ChainState {
+    // amounts of every address, contracts and tokens after the last block, their root is in its header
+    accounts: AccountState,
+    contracts: ContractState,
+    tokens: TokenState,
+    // outcome of every contract and token transaction in the blocks added to this node
+    receipts: HashMap<TransactionId, Receipt>,
+    // index of the block that includes each transaction of the chain, even if it was pruned
+    transactions: HashMap<TransactionId, u64>,
+    // transactions of each address, also kept after their blocks are pruned
+    history: AddressHistory,
+    // whether the node takes contract transactions from clients and peers
+    contracts_enabled: bool,
+    // most bytes of data that the node takes in the transactions of clients and peers
+    max_data_bytes: usize,
+    // network that the signatures of the transactions must be made for
+    chain_id: String,
+    pruned: PrunedState,
+    // total work of the chain up to each block, the genesis block has none
+    work: Vec<BlockHash>,
 }
 
 // Struct that holds all the blocks in the blockhain
 // Multiple threads can read/write concurrently to the list of blocks
+// The blocks can still be read after a panic while holding the locks, but no more are added
 #[derive(Debug, Clone)]
 pub struct Blockchain {
-    pub difficulty: u32,
+    consensus: SharedConsensus,
     blocks: SyncedBlockVec,
+    tip: WatchSender<BlockHash>,
+    // published while holding the lock of the blocks, so events come in the same order as the blocks
+    events: EventBus,
+    // where new blocks are written before being added, if the chain is persisted
+    store: Option<Arc<Lock<BlockStore>>>,
+    // always locked after "blocks", as both change together
+    state: Arc<Lock<ChainState>>,
+    // how far in the future the timestamp of a new block can be, relative to the local clock
+    max_time_drift_secs: Arc<AtomicU64>,
+    // the local clock, which timestamps can't be too far ahead of
+    clock: SharedClock,
 }
 
 // Basic operations in the blockchain are encapsulated in the implementation
 // Encapsulates concurrency concerns, so external callers do not need to know how it's handled
 impl Blockchain {
     // Creates a brand new blockchain with a genesis block
-    pub fn new(difficulty: u32) -> Blockchain {
+    // New blocks must follow the rules of the consensus engine
+    pub fn new(consensus: SharedConsensus) -> Blockchain {
+        Blockchain::with_clock(consensus, SystemClock::shared())
+    }
+
+    // Same as "new", but checking the timestamps of the blocks against the time of "clock"
+    pub fn with_clock(consensus: SharedConsensus, clock: SharedClock) -> Blockchain {
         let genesis_block = Blockchain::create_genesis_block();
 
         // add the genesis block to the synced vec of blocks
-        let mut blocks = BlockVec::default();
-        blocks.push(genesis_block);
-        let synced_blocks = Arc::new(Mutex::new(blocks));
+        let (tip, _) = watch::channel(genesis_block.header.hash);
+        let blocks = vec![genesis_block];
+        let synced_blocks = Arc::new(Lock::new(blocks));
 
         Blockchain {
-            difficulty,
+            consensus,
             blocks: synced_blocks,
+            tip,
+            events: EventBus::new(),
+            store: None,
+            // the genesis block is never pruned, it must be the same for all the nodes
+            state: Arc::new(Lock::new(ChainState {
+                chain_id: DEFAULT_CHAIN_ID.to_string(),
+                pruned: PrunedState {
+                    next_index: 1,
+                    ..PrunedState::default()
+                },
+                work: vec![BlockHash::zero()],
+                ..ChainState::default()
+            })),
+            max_time_drift_secs: Arc::new(AtomicU64::new(DEFAULT_MAX_TIME_DRIFT_SECS)),
+            clock,
+        }
+    }
+
+    // Starts a blockchain from a trusted snapshot, instead of from the genesis block
+    // The transactions are not in the snapshot, but the hashes only cover the headers,
+    // so they must still be valid, follow each other and the rules of the consensus engine
+    pub fn from_snapshot(consensus: SharedConsensus, snapshot: Snapshot) -> Result<Blockchain> {
+        let blockchain = Blockchain::new(consensus);
+
+        let genesis_block = blockchain.get_genesis_block();
+        match snapshot.blocks.first() {
+            Some(first) if first.header.hash == genesis_block.header.hash => {}
+            first => {
+                let hash = first.map(|block| block.header.hash).unwrap_or_default();
+                return Err(BlockchainError::DifferentGenesis(hash).into());
+            }
+        }
+
+        let last = snapshot.blocks.last().unwrap();
+        if last.header.index != snapshot.height || last.header.hash != snapshot.tip {
+            return Err(SnapshotError::InvalidTip.into());
+        }
+        // the header of the tip commits to the balances, contracts and tokens, so they can't be altered
+        if state_root(&snapshot.balances, &snapshot.contracts, &snapshot.tokens)
+            != last.header.state_root
+        {
+            return Err(SnapshotError::InvalidStateRoot.into());
+        }
+        if let Some((id, _)) = snapshot
+            .transactions
+            .iter()
+            .find(|(_, index)| **index > snapshot.height)
+        {
+            return Err(SnapshotError::InvalidTransaction(*id).into());
+        }
+
+        let mut blocks = blockchain.blocks.lock();
+        let mut work = vec![BlockHash::zero()];
+        for block in snapshot.blocks.into_iter().skip(1) {
+            let index = block.header.index;
+            let context = || format!("block {} of the snapshot is not valid", index);
+            let block = SealedBlock::new(block)
+                .ok_or(BlockchainError::InvalidHash)
+                .with_context(context)?;
+            blockchain
+                .check_header(&blocks, &block)
+                .with_context(context)?;
+            let mut block = block.into_inner();
+            block.transactions.clear();
+            block.pruned = true;
+            work.push(blockchain.next_work(&work, &block));
+            blocks.push(block);
+        }
+
+        *blockchain.state.lock() = ChainState {
+            accounts: snapshot.balances.clone(),
+            contracts: snapshot.contracts.clone(),
+            tokens: snapshot.tokens.clone(),
+            pruned: PrunedState {
+                depth: 0,
+                next_index: snapshot.height + 1,
+                amounts: snapshot.balances,
+                contracts: snapshot.contracts,
+                tokens: snapshot.tokens,
+            },
+            transactions: snapshot.transactions.into_iter().collect(),
+            work,
+            ..ChainState::default()
+        };
+        blockchain.tip.send(snapshot.tip);
+        drop(blocks);
+
+        Ok(blockchain)
+    }
+
+    // Loads the blockchain stored in a directory, or starts a new one there
+    // Stored blocks go through the same validation as new ones, so a store can't bypass the rules
+    // A snapshot is only used to start a new chain, it's kept in the directory along with the blocks after it
+    // The signatures of the stored transactions are checked for the chain with "chain_id"
+    // With "compress" the blocks are compressed in the store, and the stored ones are rewritten if they were not
+    // Directories written by older versions are migrated first, and the ones of newer versions refused
+    pub fn open(
+        consensus: SharedConsensus,
+        data_dir: &str,
+        snapshot: Option<Snapshot>,
+        chain_id: &str,
+        compress: bool,
+    ) -> Result<Blockchain> {
+        schema::migrate(data_dir)?;
+        let (mut store, stored_blocks) = BlockStore::open(data_dir, compress)?;
+
+        let snapshot_path = Path::new(data_dir).join(SNAPSHOT_FILE);
+        let snapshot = match snapshot {
+            _ if snapshot_path.exists() => Some(Snapshot::load(&snapshot_path)?),
+            Some(snapshot) if stored_blocks.is_empty() => {
+                snapshot.save(&snapshot_path)?;
+                Some(snapshot)
+            }
+            Some(_) => {
+                warn!("ignoring the snapshot, {} already has a chain", data_dir);
+                None
+            }
+            None => None,
+        };
+
+        let (mut blockchain, stored_blocks) = match snapshot {
+            Some(snapshot) => (
+                Blockchain::from_snapshot(consensus, snapshot)?,
+                stored_blocks,
+            ),
+            None => {
+                let blockchain = Blockchain::new(consensus);
+                let genesis_block = blockchain.get_genesis_block();
+                match stored_blocks.first() {
+                    None => store.append(&genesis_block)?,
+                    Some(stored) if stored.header.hash != genesis_block.header.hash => {
+                        return Err(BlockchainError::DifferentGenesis(stored.header.hash).into());
+                    }
+                    Some(_) => {}
+                }
+                (blockchain, stored_blocks.into_iter().skip(1).collect())
+            }
+        };
+
+        // the store is not set yet, so the blocks are not written again
+        blockchain.set_chain_id(chain_id);
+        if !stored_blocks.is_empty() {
+            Blockchain::seal_blocks(stored_blocks)
+                .and_then(|blocks| blockchain.append_blocks(blocks))
+                .context("the stored blocks are not valid")?;
+        }
+
+        info!(
+            "loaded {} blocks from {}",
+            blockchain.get_last_block().header.index + 1,
+            data_dir
+        );
+        blockchain.store = Some(Arc::new(Lock::new(store)));
+        Ok(blockchain)
+    }
+
+    // Returns the state of the chain right after the block at "height"
+    // The transactions of the pruned blocks were already merged, so it can't go back before them
+    pub fn snapshot(&self, height: u64) -> Result<Snapshot, SnapshotError> {
+        let blocks = self.blocks.lock();
+        let chain_state = self.state.lock();
+        let pruned = &chain_state.pruned;
+
+        if height >= blocks.len() as u64 {
+            return Err(SnapshotError::UnknownHeight(height));
+        }
+        if height + 1 < pruned.next_index {
+            return Err(SnapshotError::PrunedHeight(height, pruned.next_index - 1));
+        }
+
+        let mut state = pruned.clone();
+        let mut headers = blocks[..=height as usize].to_vec();
+        for block in headers.iter_mut().skip(state.next_index as usize) {
+            state.prune_block(block);
+        }
+
+        Ok(Snapshot {
+            height,
+            tip: headers[height as usize].header.hash,
+            blocks: headers,
+            balances: state.amounts,
+            contracts: state.contracts,
+            tokens: state.tokens,
+            transactions: chain_state
+                .transactions
+                .iter()
+                .filter(|(_, index)| **index <= height)
+                .map(|(id, index)| (*id, *index))
+                .collect(),
+        })
+    }
+
+    // Keeps the transactions of only the last "depth" blocks, all of them if it's 0
+    // The headers of all the blocks are kept, so the chain can still be validated and extended
+    pub fn set_prune_depth(&self, depth: u64) {
+        let mut blocks = self.blocks.lock();
+        let pruned = &mut self.state.lock().pruned;
+
+        pruned.depth = depth;
+        pruned.prune(&mut blocks);
+    }
+
+    // Returns the amounts received and sent by an address in all the blocks, pruned or not
+    pub fn get_account(&self, address: &str) -> Amounts {
+        let state = self.state.lock();
+
+        state.accounts.get(address)
+    }
+
+    // Returns the amounts received and sent by an address up to the block "last_index"
+    // The ones of the later blocks are taken out with the history, which has every transaction after a snapshot
+    pub fn get_account_at(&self, address: &str, last_index: u64) -> Amounts {
+        let state = self.state.lock();
+
+        let amounts = state.accounts.get(address);
+        let after = state.history.amounts_after(address, last_index);
+        Amounts {
+            received: amounts.received - after.received,
+            sent: amounts.sent - after.sent,
         }
     }
 
+    // Returns the contract deployed at an address, if any
+    pub fn get_contract(&self, address: &str) -> Option<Contract> {
+        let state = self.state.lock();
+
+        state.contracts.get(address).cloned()
+    }
+
+    // Returns the tokens held by an address, with the balance of each of them
+    pub fn get_token_balances(&self, address: &str) -> Vec<(TokenId, Token, u64)> {
+        let state = self.state.lock();
+
+        state
+            .tokens
+            .balances(address)
+            .into_iter()
+            .map(|(id, token, balance)| (id, token.clone(), balance))
+            .collect()
+    }
+
+    // Returns the outcome of a contract or token transaction included in a block
+    pub fn get_receipt(&self, id: TransactionId) -> Option<Receipt> {
+        let state = self.state.lock();
+
+        state.receipts.get(&id).cloned()
+    }
+
+    // Contract transactions in blocks are always run, as the state root depends on them,
+    // but the node only takes new ones from clients and peers if they are enabled
+    pub fn set_contracts_enabled(&self, enabled: bool) {
+        self.state.lock().contracts_enabled = enabled;
+    }
+
+    // Like contracts, the data of the transactions in blocks is always accepted (up to MAX_DATA_LENGTH),
+    // but the node only takes new ones with at most "max_bytes" of data
+    pub fn set_max_data_bytes(&self, max_bytes: usize) {
+        self.state.lock().max_data_bytes = max_bytes;
+    }
+
+    // Transactions signed for other networks are rejected, even if they come in blocks
+    pub fn set_chain_id(&self, chain_id: &str) {
+        self.state.lock().chain_id = chain_id.to_string();
+    }
+
+    pub fn chain_id(&self) -> String {
+        self.state.lock().chain_id.clone()
+    }
+
+    // Clocks of the nodes are never perfectly in sync, so blocks can be a bit ahead of ours
+    pub fn set_max_time_drift(&self, secs: u64) {
+        self.max_time_drift_secs.store(secs, Ordering::SeqCst);
+    }
+
+    // Returns the lowest timestamp (in milliseconds) that the next block can carry
+    pub fn min_timestamp(&self) -> i64 {
+        let blocks = self.blocks.lock();
+
+        Self::median_timestamp(&blocks) + 1
+    }
+
+    fn median_timestamp(blocks: &[Block]) -> i64 {
+        let start = blocks.len().saturating_sub(MEDIAN_TIME_SPAN);
+        let mut timestamps: Vec<i64> = blocks[start..]
+            .iter()
+            .map(|block| block.header.timestamp)
+            .collect();
+        timestamps.sort_unstable();
+
+        timestamps[timestamps.len() / 2]
+    }
+
+    // Checks that the node can take a transaction into its pool
+    // Token transfers must be covered by the confirmed balance of the sender, as they would fail otherwise
+    pub fn check_transaction(&self, transaction: &Transaction) -> Result<()> {
+        transaction.check_size()?;
+        let state = self.state.lock();
+        Self::check_actions(transaction)?;
+        transaction.check_data(state.max_data_bytes)?;
+
+        // the amounts of the sender and the recipient must not overflow with the confirmed ones
+        state.accounts.check_transaction(transaction)?;
+        if transaction.contract.is_some() && !state.contracts_enabled {
+            return Err(ContractError::Disabled.into());
+        }
+        if let Some(TokenAction::Transfer { token_id, amount }) = &transaction.token {
+            if state.tokens.get(*token_id).is_none() {
+                return Err(TokenError::UnknownToken(*token_id).into());
+            }
+            let balance = state.tokens.balance(&transaction.sender, *token_id);
+            if balance < *amount {
+                return Err(TokenError::InsufficientBalance(balance).into());
+            }
+        }
+
+        Ok(())
+    }
+
+    // Contract and token actions that could never succeed make the whole block invalid
+    fn check_actions(transaction: &Transaction) -> Result<()> {
+        if let Some(action) = &transaction.contract {
+            action.check()?;
+        }
+        if let Some(action) = &transaction.token {
+            if transaction.contract.is_some() {
+                return Err(TokenError::WithContract.into());
+            }
+            action.check()?;
+        }
+
+        Ok(())
+    }
+
+    // Splits the transactions into those that the next block can include, in the same order,
+    // and those that would overflow the amounts of an address, which would make the block invalid
+    pub fn select_transactions(
+        &self,
+        transactions: TransactionVec,
+    ) -> (TransactionVec, TransactionVec) {
+        let mut accounts = self.state.lock().accounts.clone();
+
+        transactions
+            .into_iter()
+            .partition(|transaction| accounts.apply_transaction(transaction).is_ok())
+    }
+
+    // Returns the state root that the next block must carry if it includes these transactions
+    // Transactions that would overflow the amounts of an address are left out of the root,
+    // so they must be left out of the block too (see "select_transactions")
+    pub fn next_state_root(&self, transactions: &[Transaction]) -> BlockHash {
+        let state = self.state.lock();
+        let mut accounts = state.accounts.clone();
+        let mut contracts = state.contracts.clone();
+        let mut tokens = state.tokens.clone();
+        drop(state);
+        for transaction in transactions {
+            let _ = accounts.apply_transaction(transaction);
+        }
+        contracts.apply(transactions);
+        tokens.apply(transactions);
+
+        state_root(&accounts, &contracts, &tokens)
+    }
+
+    // Returns the consensus engine that decides which blocks are valid
+    pub fn consensus(&self) -> SharedConsensus {
+        self.consensus.clone()
+    }
+
+    // Returns the clock that the timestamps of new blocks are checked against
+    pub fn clock(&self) -> SharedClock {
+        self.clock.clone()
+    }
+
+    // Returns the total work of the chain, the sum of the work of all its blocks
+    // Chains are compared by their work, as a longer chain of easier blocks took less effort to produce
+    pub fn total_work(&self) -> BlockHash {
+        let state = self.state.lock();
+
+        state.work[state.work.len() - 1]
+    }
+
+    // Returns the total work of a chain that starts at the genesis block, e.g. the one of a peer
+    // The blocks are not validated, so it's only an upper bound of what the chain is worth
+    pub fn chain_work(&self, blocks: &[Block]) -> BlockHash {
+        blocks
+            .iter()
+            .skip(1)
+            .fold(BlockHash::zero(), |work, block| {
+                work.saturating_add(self.consensus.block_work(block))
+            })
+    }
+
+    // Returns the target, in compact form, that the next block must carry in its header
+    pub fn next_bits(&self) -> u32 {
+        let blocks = self.blocks.lock();
+
+        self.consensus.next_bits(&blocks)
+    }
+
+    // Returns a copy of the first block of the blockchain, which is the same for all the nodes
+    pub fn get_genesis_block(&self) -> Block {
+        let blocks = self.blocks.lock();
+
+        blocks[0].clone()
+    }
+
     // Returns a copy of the most recent block in the blockchain
     pub fn get_last_block(&self) -> Block {
-        let blocks = self.blocks.lock().unwrap();
+        let blocks = self.blocks.lock();
 
         blocks[blocks.len() - 1].clone()
     }
 
+    // Returns a copy of the most recent block buried under at least "depth" blocks
+    // Those blocks are considered final, as it's very unlikely that they get replaced
+    pub fn get_safe_block(&self, depth: u64) -> Block {
+        let blocks = self.blocks.lock();
+        let safe_index = (blocks.len() - 1).saturating_sub(depth as usize);
+
+        blocks[safe_index].clone()
+    }
+
     // Returns a copy of the whole list of blocks
     pub fn get_all_blocks(&self) -> BlockVec {
-        let blocks = self.blocks.lock().unwrap();
+        let blocks = self.blocks.lock();
 
         blocks.clone()
     }
 
+    // Returns a copy of the list of blocks up to the one with the indicated index (included)
+    pub fn get_blocks_until(&self, last_index: u64) -> BlockVec {
+        let blocks = self.blocks.lock();
+
+        blocks
+            .iter()
+            .take_while(|block| block.header.index <= last_index)
+            .cloned()
+            .collect()
+    }
+
+    // Returns a copy of the blocks with indices between "first_index" and "last_index" (both included)
+    // Only the blocks in that range are cloned, so it's cheap even for long chains
+    pub fn get_blocks_between(&self, first_index: u64, last_index: u64) -> BlockVec {
+        let blocks = self.blocks.lock();
+        if first_index > last_index {
+            return BlockVec::new();
+        }
+
+        blocks
+            .iter()
+            .skip(first_index as usize)
+            .take((last_index - first_index) as usize + 1)
+            .cloned()
+            .collect()
+    }
+
+    // Returns a copy of the block with the indicated hash, if it's in the blockchain
+    pub fn get_block(&self, hash: BlockHash) -> Option<Block> {
+        let blocks = self.blocks.lock();
+
+        blocks
+            .iter()
+            .find(|block| block.header.hash == hash)
+            .cloned()
+    }
+
+    // Returns a copy of the block with the indicated index, if the blockchain is that long
+    pub fn get_block_at(&self, index: u64) -> Option<Block> {
+        let blocks = self.blocks.lock();
+
+        blocks.get(index as usize).cloned()
+    }
+
+    // Returns a receiver that gets notified with the hash of every new last block
+    pub fn watch_tip(&self) -> WatchReceiver<BlockHash> {
+        self.tip.subscribe()
+    }
+
+    // Returns the bus where the additions of blocks are published, to subscribe to them
+    pub fn events(&self) -> EventBus {
+        self.events.clone()
+    }
+
+    // Returns true if the block with the given hash is part of the chain
+    // Recent blocks are the most likely to be asked for, so the search starts from the last one
+    pub fn contains_block(&self, hash: BlockHash) -> bool {
+        let blocks = self.blocks.lock();
+
+        blocks.iter().rev().any(|block| block.header.hash == hash)
+    }
+
+    // Returns true if a transaction with the given id was already included in any block
+    pub fn contains_transaction(&self, id: TransactionId) -> bool {
+        self.find_transaction_block(id).is_some()
+    }
+
+    // Returns the header of the block that includes the transaction with the given id, if any
+    pub fn find_transaction_block(&self, id: TransactionId) -> Option<BlockHeader> {
+        let blocks = self.blocks.lock();
+        let index = *self.state.lock().transactions.get(&id)?;
+
+        Some(blocks[index as usize].header())
+    }
+
+    // Returns the headers of the blocks from "first_index" whose bloom filter matches the address,
+    // up to "limit" of them, and the index to continue from if there may be more
+    // It only looks at the headers, so it also works with pruned blocks and in light clients,
+    // but some of the blocks may not involve the address after all (false positives)
+    pub fn find_address_blocks(
+        &self,
+        address: &str,
+        first_index: u64,
+        limit: usize,
+    ) -> (Vec<BlockHeader>, Option<u64>) {
+        let blocks = self.blocks.lock();
+
+        let mut headers = Vec::new();
+        for block in blocks.iter().skip(first_index as usize) {
+            if headers.len() == limit {
+                return (headers, Some(block.header.index));
+            }
+            if bloom_contains(&block.header.bloom, address) {
+                headers.push(block.header());
+            }
+        }
+
+        (headers, None)
+    }
+
+    // Returns a page of the transactions of an address, from the position "from" of its history,
+    // along with the position of the next page (if any) and the number of transactions of the address
+    // Only the blocks up to "last_index" are taken into account, e.g. to leave out the ones that are not safe yet
+    pub fn get_address_transactions(
+        &self,
+        address: &str,
+        from: usize,
+        limit: usize,
+        last_index: u64,
+    ) -> (Vec<AddressTransaction>, Option<usize>, usize) {
+        let state = self.state.lock();
+
+        state.history.page(address, from, limit, last_index)
+    }
+
+    // Returns the proof that the block including the transaction has it, to be checked by light clients
+    // Pruned blocks no longer have the rest of the transactions, so they can't prove anything
+    pub fn get_transaction_proof(&self, id: TransactionId) -> Option<TransactionProof> {
+        let blocks = self.blocks.lock();
+        // only the block that includes it is hashed, the index tells which one
+        let block_index = *self.state.lock().transactions.get(&id)?;
+        let block = &blocks[block_index as usize];
+
+        let ids: Vec<TransactionId> = block
+            .transactions
+            .iter()
+            .map(Transaction::calculate_id)
+            .collect();
+        let index = ids.iter().position(|other_id| *other_id == id)?;
+
+        Some(TransactionProof {
+            transaction_id: id,
+            block_index: block.header.index,
+            block_hash: block.header.hash,
+            proof: MerkleProof::new(&ids, index)?,
+        })
+    }
+
+    // Appends a block of which we only have the header, for light clients that don't download transactions
+    // The header must pass the same checks as in full blocks, except for the merkle and state roots,
+    // and the block is kept as pruned. There is no state either, so it's not written to the store
+    pub fn add_header(&self, header: BlockHeader) -> Result<()> {
+        let mut blocks = self.blocks.try_lock()?;
+
+        let header = SealedHeader::new(header).ok_or(BlockchainError::InvalidHash)?;
+        let block = Block {
+            header: header.into_inner(),
+            transactions: Vec::new(),
+            signature: None,
+            pruned: true,
+        };
+        self.check_header(&blocks, &block)?;
+
+        let hash = block.header.hash;
+        let mut state = self.state.try_lock()?;
+        let work = self.next_work(&state.work, &block);
+        state.work.push(work);
+        self.events
+            .publish(ChainEvent::BlockAdded(Arc::new(block.clone())));
+        blocks.push(block);
+        self.tip.send(hash);
+
+        Ok(())
+    }
+
     // Tries to append a new block into the blockchain
     // It will validate that the values of the new block are consistend with the blockchain state
     // This operation is safe to be called concurrently from multiple threads
     pub fn add_block(&self, block: Block) -> Result<()> {
-        // the "blocks" attribute is protected by a Mutex
+        // the logs of the validation (e.g. of the consensus engine) carry the index of the block
+        let _span = info_span!("validate_block", index = block.header.index).entered();
+        let result = match SealedBlock::new(block) {
+            Some(block) => self.append_block(block),
+            None => Err(BlockchainError::InvalidHash.into()),
+        };
+        if let Err(error) = &result {
+            debug!("rejected block: {}", error);
+        }
+
+        result
+    }
+
+    // Appends several blocks that follow each other on top of the last one, e.g. when syncing with a peer
+    // They can also start from an earlier block, to switch to a fork with more work than our chain
+    // It's all-or-nothing: if any of them is not valid, none is added
+    // The locks are taken once for the whole batch, which is much faster than adding them one by one
+    pub fn add_blocks(&self, new_blocks: Vec<Block>) -> Result<()> {
+        let new_blocks = Blockchain::seal_blocks(new_blocks).map_err(|error| {
+            debug!("rejected blocks: {:#}", error);
+            error
+        })?;
+
+        self.add_sealed_blocks(new_blocks)
+    }
+
+    // Same as "add_blocks", for blocks whose hashes were already checked (e.g. with the headers of a sync)
+    pub fn add_sealed_blocks(&self, new_blocks: Vec<SealedBlock>) -> Result<()> {
+        let (first, last) = match (new_blocks.first(), new_blocks.last()) {
+            (Some(first), Some(last)) => (first.header.index, last.header.index),
+            _ => return Ok(()),
+        };
+        let _span = info_span!("validate_blocks", first, last).entered();
+        let result = self.append_blocks(new_blocks);
+        if let Err(error) = &result {
+            debug!("rejected blocks: {}", error);
+        }
+
+        result
+    }
+
+    // Checks the hashes of the blocks, split among several threads for long batches
+    fn seal_blocks(new_blocks: Vec<Block>) -> Result<Vec<SealedBlock>> {
+        Blockchain::check_in_parallel(&new_blocks, |block, _| {
+            match block.header.hash == block.calculate_hash() {
+                true => Ok(()),
+                false => Err(BlockchainError::InvalidHash.into()),
+            }
+        })?;
+
+        Ok(new_blocks
+            .into_iter()
+            .map(SealedBlock::from_checked)
+            .collect())
+    }
+
+    // Appends the blocks on top of the last one, or on top of an earlier one if they are a fork
+    // A fork replaces the blocks after the one it starts from, but only if it has more work than them
+    fn append_blocks(&self, new_blocks: Vec<SealedBlock>) -> Result<()> {
+        // the checks that only need each block and its predecessor don't need the locks
+        self.check_batch(&new_blocks)?;
+
+        let mut blocks = self.blocks.try_lock()?;
+        let mut state = self.state.try_lock()?;
+
+        let first_index = new_blocks[0].header.index;
+        let is_fork = first_index > 0 && first_index < blocks.len() as u64;
+        let (mut accounts, mut contracts, mut tokens) = match is_fork {
+            true => self.check_fork(&blocks, &state, &new_blocks)?,
+            false => (
+                state.accounts.clone(),
+                state.contracts.clone(),
+                state.tokens.clone(),
+            ),
+        };
+        let reverted = match is_fork {
+            true => blocks.split_off(first_index as usize),
+            false => Vec::new(),
+        };
+        let mut receipts = HashMap::new();
+
+        // each block is checked on top of the previous ones of the batch, which are taken back if one fails
+        // the consensus rules of the blocks after the first one were already checked with their parent
+        let previous_len = blocks.len();
+        for block in new_blocks {
+            let result = if blocks.len() == previous_len {
+                self.check_header(&blocks, &block)
+            } else {
+                self.check_link(&blocks, &block)
+            };
+            let result = result.and_then(|_| {
+                Self::apply_transactions(&block, &mut accounts, &mut contracts, &mut tokens)
+            });
+            match result {
+                Ok(block_receipts) => {
+                    receipts.extend(block_receipts);
+                    blocks.push(block.into_inner());
+                }
+                Err(error) => {
+                    let index = block.header.index;
+                    blocks.truncate(previous_len);
+                    blocks.extend(reverted);
+                    return Err(error).with_context(|| format!("block {} is not valid", index));
+                }
+            }
+        }
+
+        if let Some(store) = &self.store {
+            let mut store = store.try_lock()?;
+            let result = match reverted.is_empty() {
+                true => Ok(()),
+                false => store.truncate_after(previous_len as u64 - 1),
+            };
+            if let Err(error) = result.and_then(|_| store.append_all(&blocks[previous_len..])) {
+                // the reverted blocks may be gone from the store, they are written back if possible
+                blocks.truncate(previous_len);
+                if !reverted.is_empty() {
+                    let _ = store
+                        .truncate_after(previous_len as u64 - 1)
+                        .and_then(|_| store.append_all(&reverted));
+                }
+                blocks.extend(reverted);
+                return Err(error);
+            }
+        }
+
+        let hash = blocks[blocks.len() - 1].header.hash;
+        if !reverted.is_empty() {
+            info!(
+                "switching to a fork with more work from block {}, replacing {} blocks",
+                previous_len - 1,
+                reverted.len()
+            );
+            self.events.publish(ChainEvent::Reorg(ReorgEvent {
+                fork_index: previous_len as u64 - 1,
+                reverted: reverted.iter().map(|block| block.header.hash).collect(),
+                applied: blocks[previous_len..]
+                    .iter()
+                    .map(|block| block.header.hash)
+                    .collect(),
+            }));
+            for transaction in reverted.iter().flat_map(|block| block.transactions.iter()) {
+                let id = transaction.calculate_id();
+                state.receipts.remove(&id);
+                state.transactions.remove(&id);
+            }
+            state.history.revert(&reverted);
+            state.work.truncate(previous_len);
+        }
+
+        // published before pruning, which could take the transactions of the new blocks in long batches
+        for block in blocks[previous_len..].iter() {
+            let work = self.next_work(&state.work, block);
+            state.work.push(work);
+            Self::index_transactions(&mut state, block);
+            self.events
+                .publish(ChainEvent::BlockAdded(Arc::new(block.clone())));
+        }
+        state.accounts = accounts;
+        state.contracts = contracts;
+        state.tokens = tokens;
+        state.receipts.extend(receipts);
+        state.pruned.prune(&mut blocks);
+        self.tip.send(hash);
+
+        Ok(())
+    }
+
+    // Checks that a batch that doesn't follow the last block can replace the blocks after its parent
+    // Returns the state right after the parent, which the blocks of the fork are applied to
+    fn check_fork(
+        &self,
+        blocks: &[Block],
+        state: &ChainState,
+        new_blocks: &[SealedBlock],
+    ) -> Result<(AccountState, ContractState, TokenState)> {
+        let fork_index = new_blocks[0].header.index - 1;
+        if new_blocks[0].header.previous_hash != blocks[fork_index as usize].header.hash {
+            return Err(BlockchainError::InvalidPreviousHash.into());
+        }
+
+        // a fork with the same work as our blocks is not better, the first one we got is kept
+        let fork_work = new_blocks
+            .iter()
+            .fold(state.work[fork_index as usize], |work, block| {
+                work.saturating_add(self.consensus.block_work(block))
+            });
+        if fork_work <= state.work[state.work.len() - 1] {
+            return Err(BlockchainError::NotEnoughWork.into());
+        }
+
+        // the state of the parent is calculated again from the last pruned block,
+        // so the transactions of all the blocks after it are needed
+        let reverted = &blocks[fork_index as usize + 1..];
+        if fork_index + 1 < state.pruned.next_index || reverted.iter().any(|block| block.pruned) {
+            return Err(BlockchainError::ForkTooDeep(fork_index).into());
+        }
+
+        let mut accounts = state.pruned.amounts.clone();
+        let mut contracts = state.pruned.contracts.clone();
+        let mut tokens = state.pruned.tokens.clone();
+        for block in blocks[state.pruned.next_index as usize..=fork_index as usize].iter() {
+            Self::apply_transactions(block, &mut accounts, &mut contracts, &mut tokens)?;
+        }
+
+        Ok((accounts, contracts, tokens))
+    }
+
+    fn index_transactions(state: &mut ChainState, block: &Block) {
+        for transaction in block.transactions.iter() {
+            state
+                .transactions
+                .insert(transaction.calculate_id(), block.header.index);
+        }
+        state.history.add_block(block);
+    }
+
+    // Total work of the chain after adding a block on top of the ones with "work"
+    fn next_work(&self, work: &[BlockHash], block: &Block) -> BlockHash {
+        work[work.len() - 1].saturating_add(self.consensus.block_work(block))
+    }
+
+    fn append_block(&self, block: SealedBlock) -> Result<()> {
+        // the "blocks" attribute is protected by a Lock
         // so only one thread at a time can access the value when the lock is held
         // that prevents adding multiple valid blocks at the same time
         // preserving the correct order of indexes and hashes of the blockchain
-        let mut blocks = self.blocks.lock().unwrap();
-        let last = &blocks[blocks.len() - 1];
+        // if a thread panicked while adding a block, the chain may be half-updated, so nothing is added on top
+        let chain_id = self.chain_id();
+        let mut blocks = self.blocks.try_lock()?;
 
-        // check that the index is valid
-        if block.index != last.index + 1 {
-            return Err(BlockchainError::InvalidIndex.into());
+        Self::check_contents(&block, &chain_id)?;
+        self.check_header(&blocks, &block)?;
+
+        let mut state = self.state.try_lock()?;
+        let mut accounts = state.accounts.clone();
+        let mut contracts = state.contracts.clone();
+        let mut tokens = state.tokens.clone();
+        let receipts =
+            Self::apply_transactions(&block, &mut accounts, &mut contracts, &mut tokens)?;
+
+        // the block must be on disk before anyone sees it, so a crash can't lose an announced block
+        // if it can't be written, it's not added at all
+        if let Some(store) = &self.store {
+            store.try_lock()?.append(&block)?;
         }
 
-        // check that the previous_hash is valid
-        if block.previous_hash != last.hash {
-            return Err(BlockchainError::InvalidPreviousHash.into());
+        // append the block to the end and notify the new tip
+        // we still hold the lock, so notifications are sent in the same order as the blocks
+        let hash = block.header.hash;
+        let block = block.into_inner();
+        let work = self.next_work(&state.work, &block);
+        state.work.push(work);
+        Self::index_transactions(&mut state, &block);
+        self.events
+            .publish(ChainEvent::BlockAdded(Arc::new(block.clone())));
+        blocks.push(block);
+        state.accounts = accounts;
+        state.contracts = contracts;
+        state.tokens = tokens;
+        state.receipts.extend(receipts);
+        state.pruned.prune(&mut blocks);
+        self.tip.send(hash);
+
+        Ok(())
+    }
+
+    // Checks the blocks of a batch on their own and against the previous one of the batch:
+    // the data of the headers, the actions of the transactions, the links and the consensus rules
+    // (e.g. the signatures of the authorities), which takes most of the time of validating a chain
+    fn check_batch(&self, new_blocks: &[SealedBlock]) -> Result<()> {
+        let chain_id = self.chain_id();
+        Blockchain::check_in_parallel(new_blocks, |block, position| {
+            Self::check_contents(block, &chain_id)?;
+            match position {
+                0 => Ok(()),
+                _ => {
+                    let parent = &new_blocks[position - 1];
+                    Self::check_parent(block, parent)?;
+                    self.consensus.verify(block, parent)
+                }
+            }
+        })
+    }
+
+    // Runs a check on every block of a batch, which gets the block and its position in the batch
+    // No block depends on the result of another one, so they are split among several threads
+    fn check_in_parallel<B>(
+        new_blocks: &[B],
+        check: impl Fn(&Block, usize) -> Result<()> + Sync,
+    ) -> Result<()>
+    where
+        B: Borrow<Block> + Sync,
+    {
+        let check_range = |range: Range<usize>| -> Result<()> {
+            for position in range {
+                let block = new_blocks[position].borrow();
+                check(block, position)
+                    .with_context(|| format!("block {} is not valid", block.header.index))?;
+            }
+            Ok(())
+        };
+
+        let threads = thread::available_parallelism()
+            .map_or(1, usize::from)
+            .min(new_blocks.len() / MIN_BLOCKS_PER_THREAD)
+            .max(1);
+        if threads == 1 {
+            return check_range(0..new_blocks.len());
         }
 
-        // check that the hash matches the data
-        if block.hash != block.calculate_hash() {
-            return Err(BlockchainError::InvalidHash.into());
+        let chunk_size = new_blocks.len().div_ceil(threads);
+        let results: Vec<Result<()>> = crossbeam_utils::thread::scope(|s| {
+            let handles: Vec<_> = (0..new_blocks.len())
+                .step_by(chunk_size)
+                .map(|start| {
+                    let end = (start + chunk_size).min(new_blocks.len());
+                    let check_range = &check_range;
+                    s.spawn(move |_| check_range(start..end))
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap())
+                .collect()
+        })
+        .unwrap();
+
+        // the error of the first invalid block is the one reported, like when checking them in order
+        results.into_iter().collect()
+    }
+
+    // Checks a sealed block on its own, so its hash is already right: the header matches the transactions,
+    // their actions are well formed and their signatures, if any, were made for this chain
+    // (transactions from public keys and multisig addresses always need them)
+    fn check_contents(block: &Block, chain_id: &str) -> Result<()> {
+        // check that the transactions are the ones committed in the header
+        if block.header.merkle_root != block.calculate_merkle_root() {
+            return Err(BlockchainError::InvalidMerkleRoot.into());
         }
 
-        // check that the difficulty is correct
-        if block.hash.leading_zeros() < self.difficulty {
-            return Err(BlockchainError::InvalidDifficulty.into());
+        // and that the bloom filter has their addresses
+        if block.header.bloom != block.calculate_bloom() {
+            return Err(BlockchainError::InvalidBloom.into());
         }
 
-        // append the block to the end
-        blocks.push(block);
+        for transaction in block.transactions.iter() {
+            Self::check_actions(transaction)?;
+            // the keys of the sender are what protect its funds, no matter who mined the block
+            if transaction.needs_signature() && !transaction.has_valid_signature(chain_id) {
+                let id = transaction.calculate_id();
+                return Err(BlockchainError::InvalidTransactionSignature(id).into());
+            }
+        }
+
+        Ok(())
+    }
+
+    // Runs the transactions of a block on top of the state of the previous one
+    // The state after them must be the one committed in the header
+    fn apply_transactions(
+        block: &Block,
+        accounts: &mut AccountState,
+        contracts: &mut ContractState,
+        tokens: &mut TokenState,
+    ) -> Result<HashMap<TransactionId, Receipt>> {
+        accounts.apply(&block.transactions)?;
+        let mut receipts = contracts.apply(&block.transactions);
+        receipts.extend(tokens.apply(&block.transactions));
+        if state_root(accounts, contracts, tokens) != block.header.state_root {
+            return Err(BlockchainError::InvalidStateRoot.into());
+        }
+
+        Ok(receipts)
+    }
+
+    // Checks that a block follows the last one, with the rules that don't need its transactions
+    fn check_header(&self, blocks: &BlockVec, block: &Block) -> Result<()> {
+        self.check_link(blocks, block)?;
+
+        // check the rest of the rules (e.g. the difficulty) with the consensus engine
+        self.consensus.verify(block, &blocks[blocks.len() - 1])
+    }
+
+    // Same as "check_header", except for the rules of the consensus engine
+    fn check_link(&self, blocks: &BlockVec, block: &Block) -> Result<()> {
+        Self::check_parent(block, &blocks[blocks.len() - 1])?;
+
+        // check that the timestamp moves forward, but not beyond our own clock
+        // otherwise miners could make the chain look faster or slower than it is
+        let median = Self::median_timestamp(blocks);
+        if block.header.timestamp <= median {
+            return Err(BlockchainError::InvalidTimestampTooOld(median).into());
+        }
+        let max_drift_secs = self.max_time_drift_secs.load(Ordering::SeqCst);
+        let max_timestamp = self.clock.now_millis() + (max_drift_secs * 1000) as i64;
+        if block.header.timestamp > max_timestamp {
+            return Err(BlockchainError::InvalidTimestampInFuture(max_drift_secs).into());
+        }
+
+        // check that the header has the target required by the consensus engine
+        if block.header.bits != self.consensus.next_bits(blocks) {
+            return Err(BlockchainError::InvalidTarget.into());
+        }
+
+        Ok(())
+    }
+
+    fn check_parent(block: &Block, parent: &Block) -> Result<()> {
+        // check that the index is valid
+        if block.header.index != parent.header.index + 1 {
+            return Err(BlockchainError::InvalidIndex.into());
+        }
+
+        // check that the previous_hash is valid
+        if block.header.previous_hash != parent.header.hash {
+            return Err(BlockchainError::InvalidPreviousHash.into());
+        }
 
         Ok(())
     }
@@ -113,8 +1233,9 @@ impl Blockchain {
 
         // to easily sync multiple nodes in a network, the genesis blocks must match
         // so we clear the timestamp so the hash of the genesis block is predictable
-        block.timestamp = 0;
-        block.hash = block.calculate_hash();
+        block.header.timestamp = 0;
+        block.header.state_root = AccountState::default().root();
+        block.header.hash = block.calculate_hash();
 
         block
     }
@@ -123,12 +1244,19 @@ impl Blockchain {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{
+        consensus::ProofOfWork,
+        model::{address_bloom, AmountError, MultiSig, Transaction, TransactionError},
+        util::MockClock,
+    };
+    use crypto::ed25519;
+    use std::{collections::BTreeMap, env, fs, time::Duration};
 
     const NO_DIFFICULTY: u32 = 0;
 
     #[test]
     fn should_have_valid_genesis_block() {
-        let blockchain = Blockchain::new(NO_DIFFICULTY);
+        let blockchain = create_blockchain(NO_DIFFICULTY);
 
         // check that a new blockchain has one and only one block
         let blocks = blockchain.get_all_blocks();
@@ -136,22 +1264,25 @@ mod tests {
 
         // check that the last block is in the blockchain
         let block = blockchain.get_last_block();
-        assert_eq!(block.hash, blocks[0].hash);
+        assert_eq!(block.header.hash, blocks[0].header.hash);
+        assert_eq!(
+            blockchain.get_genesis_block().header.hash,
+            block.header.hash
+        );
 
         // check that the genesis block has valid values
-        assert_eq!(block.index, 0);
-        assert_eq!(block.nonce, 0);
-        assert_eq!(block.previous_hash, BlockHash::default());
+        assert_eq!(block.header.index, 0);
+        assert_eq!(block.header.nonce, 0);
+        assert_eq!(block.header.previous_hash, BlockHash::default());
         assert!(block.transactions.is_empty());
     }
 
     #[test]
     fn should_let_adding_valid_blocks() {
-        let blockchain = Blockchain::new(NO_DIFFICULTY);
+        let blockchain = create_blockchain(NO_DIFFICULTY);
 
         // create a valid block
-        let previous_hash = blockchain.get_last_block().hash;
-        let block = Block::new(1, 0, previous_hash, Vec::new());
+        let block = create_next_block(&blockchain, Vec::new());
 
         // add it to the blockchain and check it was really added
         let result = blockchain.add_block(block.clone());
@@ -161,16 +1292,16 @@ mod tests {
         assert_eq!(blocks.len(), 2);
 
         let last_block = blockchain.get_last_block();
-        assert_eq!(last_block.hash, block.hash);
+        assert_eq!(last_block.header.hash, block.header.hash);
     }
 
     #[test]
     fn should_not_let_adding_block_with_invalid_index() {
-        let blockchain = Blockchain::new(NO_DIFFICULTY);
+        let blockchain = create_blockchain(NO_DIFFICULTY);
 
         // create a block with invalid index
         let invalid_index = 2;
-        let previous_hash = blockchain.get_last_block().hash;
+        let previous_hash = blockchain.get_last_block().header.hash;
         let block = Block::new(invalid_index, 0, previous_hash, Vec::new());
 
         // try adding the invalid block, it should return an error
@@ -180,7 +1311,7 @@ mod tests {
 
     #[test]
     fn should_not_let_adding_block_with_invalid_previous_hash() {
-        let blockchain = Blockchain::new(NO_DIFFICULTY);
+        let blockchain = create_blockchain(NO_DIFFICULTY);
 
         // create a block with invalid previous hash
         let invalid_previous_hash = BlockHash::default();
@@ -193,36 +1324,1014 @@ mod tests {
 
     #[test]
     fn should_not_let_adding_block_with_invalid_hash() {
-        let blockchain = Blockchain::new(NO_DIFFICULTY);
+        let blockchain = create_blockchain(NO_DIFFICULTY);
 
         // create a block with invalid hash
-        let previous_hash = blockchain.get_last_block().hash;
+        let previous_hash = blockchain.get_last_block().header.hash;
         let mut block = Block::new(1, 0, previous_hash, Vec::new());
-        block.hash = BlockHash::default();
+        block.header.hash = BlockHash::default();
 
         // try adding the invalid block, it should return an error
         let result = blockchain.add_block(block.clone());
         assert_err(result, BlockchainError::InvalidHash);
     }
 
+    #[test]
+    fn should_not_let_adding_block_with_invalid_merkle_root() {
+        let blockchain = create_blockchain(NO_DIFFICULTY);
+        let transaction = Transaction {
+            sender: "1".to_string(),
+            recipient: "2".to_string(),
+            amount: 3,
+            signature: None,
+            multisig: None,
+            contract: None,
+            token: None,
+            data: None,
+        };
+
+        // change the transactions after building the header, the hash is still right...
+        let mut block = create_next_block(&blockchain, vec![transaction]);
+        block.transactions[0].amount = 4;
+        assert_eq!(block.header.hash, block.calculate_hash());
+
+        // ...but the transactions are not the ones committed in the header
+        let result = blockchain.add_block(block.clone());
+        assert_err(result, BlockchainError::InvalidMerkleRoot);
+    }
+
+    #[test]
+    fn should_not_let_adding_block_with_invalid_bloom() {
+        let blockchain = create_blockchain(NO_DIFFICULTY);
+        let transaction = Transaction {
+            sender: "1".to_string(),
+            recipient: "2".to_string(),
+            amount: 3,
+            signature: None,
+            multisig: None,
+            contract: None,
+            token: None,
+            data: None,
+        };
+
+        // the bloom filter hides the recipient, even though the hash and the merkle root are right
+        let mut block = create_next_block(&blockchain, vec![transaction]);
+        block.header.bloom = address_bloom(&[]);
+        block.header.hash = block.calculate_hash();
+
+        let result = blockchain.add_block(block);
+        assert_err(result, BlockchainError::InvalidBloom);
+    }
+
+    #[test]
+    fn should_find_the_blocks_that_may_involve_an_address() {
+        let blockchain = create_blockchain(NO_DIFFICULTY);
+        for recipient in ["alice", "bob", "alice", "alice"].iter() {
+            let transaction = Transaction {
+                sender: "0".to_string(),
+                recipient: recipient.to_string(),
+                amount: 1,
+                signature: None,
+                multisig: None,
+                contract: None,
+                token: None,
+                data: None,
+            };
+            let block = create_next_block(&blockchain, vec![transaction]);
+            blockchain.add_block(block).unwrap();
+        }
+
+        let indices = |(headers, next): (Vec<BlockHeader>, Option<u64>)| {
+            let indices: Vec<u64> = headers.iter().map(|header| header.index).collect();
+            (indices, next)
+        };
+        assert_eq!(
+            indices(blockchain.find_address_blocks("alice", 0, 10)),
+            (vec![1, 3, 4], None)
+        );
+        assert_eq!(
+            indices(blockchain.find_address_blocks("bob", 0, 10)),
+            (vec![2], None)
+        );
+
+        // the next page starts right after the last block that was looked at
+        assert_eq!(
+            indices(blockchain.find_address_blocks("alice", 2, 1)),
+            (vec![3], Some(4))
+        );
+        assert_eq!(
+            indices(blockchain.find_address_blocks("alice", 4, 1)),
+            (vec![4], None)
+        );
+        assert_eq!(
+            indices(blockchain.find_address_blocks("carol", 0, 10)),
+            (vec![], None)
+        );
+    }
+
     #[test]
     fn should_not_let_adding_block_with_invalid_difficulty() {
         // set up a blockchain with an insane difficulty
         let difficulty: u32 = 30;
-        let blockchain = Blockchain::new(difficulty);
+        let blockchain = create_blockchain(difficulty);
 
         // create a valid block
-        let previous_hash = blockchain.get_last_block().hash;
-        let block = Block::new(1, 0, previous_hash, Vec::new());
+        let block = create_next_block(&blockchain, Vec::new());
 
         // ensure that the hash actually does NOT meet the difficulty
-        assert!(block.hash.leading_zeros() < difficulty);
+        assert!(block.header.hash.leading_zeros() < difficulty);
 
         // try adding the invalid block, it should return an error
         let result = blockchain.add_block(block.clone());
         assert_err(result, BlockchainError::InvalidDifficulty);
     }
 
+    #[test]
+    fn should_prove_included_transactions() {
+        let blockchain = create_blockchain(NO_DIFFICULTY);
+        let transactions: Vec<Transaction> = (1..=3)
+            .map(|amount| Transaction {
+                sender: "1".to_string(),
+                recipient: "2".to_string(),
+                amount,
+                signature: None,
+                multisig: None,
+                contract: None,
+                token: None,
+                data: None,
+            })
+            .collect();
+        let id = transactions[1].calculate_id();
+        assert_eq!(blockchain.get_transaction_proof(id), None);
+
+        let block = create_next_block(&blockchain, transactions);
+        blockchain.add_block(block.clone()).unwrap();
+        let proof = blockchain.get_transaction_proof(id).unwrap();
+        assert_eq!(proof.block_hash, block.header.hash);
+        assert_eq!(proof.proof.index, 1);
+        assert!(proof.verify(&block.header));
+    }
+
+    #[test]
+    fn should_follow_the_chain_with_headers_only() {
+        let blockchain = create_blockchain(NO_DIFFICULTY);
+        let light_blockchain = create_blockchain(NO_DIFFICULTY);
+
+        let block = create_next_block(&blockchain, Vec::new());
+        blockchain.add_block(block.clone()).unwrap();
+
+        // the header must still be valid
+        let mut header = block.header();
+        header.nonce += 1;
+        assert_err(
+            light_blockchain.add_header(header),
+            BlockchainError::InvalidHash,
+        );
+        let mut header = block.header();
+        header.previous_hash = BlockHash::default();
+        header.hash = header.calculate_hash();
+        assert_err(
+            light_blockchain.add_header(header),
+            BlockchainError::InvalidPreviousHash,
+        );
+
+        light_blockchain.add_header(block.header()).unwrap();
+        let last_block = light_blockchain.get_last_block();
+        assert_eq!(last_block.header, block.header);
+        assert!(last_block.pruned);
+    }
+
+    #[test]
+    fn should_return_safe_block() {
+        let blockchain = create_blockchain(NO_DIFFICULTY);
+
+        // with only the genesis block, it's always the safe one
+        assert_eq!(blockchain.get_safe_block(2).header.index, 0);
+
+        // add some blocks to the blockchain
+        for _ in 1..=3 {
+            let block = create_next_block(&blockchain, Vec::new());
+            blockchain.add_block(block).unwrap();
+        }
+
+        // the safe block is buried under "depth" blocks
+        assert_eq!(blockchain.get_safe_block(0).header.index, 3);
+        assert_eq!(blockchain.get_safe_block(2).header.index, 1);
+        assert_eq!(blockchain.get_safe_block(10).header.index, 0);
+
+        // we can retrieve all the blocks up to the safe one
+        let safe_blocks = blockchain.get_blocks_until(1);
+        assert_eq!(safe_blocks.len(), 2);
+    }
+
+    #[test]
+    fn should_notify_new_tips() {
+        let blockchain = create_blockchain(NO_DIFFICULTY);
+        let mut tip = blockchain.watch_tip();
+        assert!(!tip.has_changed());
+
+        // add a new valid block, the tip must change to its hash
+        let block = create_next_block(&blockchain, Vec::new());
+        blockchain.add_block(block.clone()).unwrap();
+
+        assert!(tip.has_changed());
+        assert_eq!(tip.borrow_and_update(), block.header.hash);
+    }
+
+    #[test]
+    fn should_publish_added_blocks() {
+        let blockchain = create_blockchain(NO_DIFFICULTY);
+        let events = blockchain.events().subscribe();
+
+        // blocks are published in order, whether they come alone or in batches
+        let chain = create_chain(3);
+        blockchain.add_block(chain[1].clone()).unwrap();
+        blockchain.add_blocks(chain[2..].to_vec()).unwrap();
+        // and rejected ones are not published at all
+        blockchain.add_block(chain[1].clone()).unwrap_err();
+
+        let hashes: Vec<BlockHash> = events
+            .pending()
+            .into_iter()
+            .filter_map(|event| match event {
+                ChainEvent::BlockAdded(block) => Some(block.header.hash),
+                _ => None,
+            })
+            .collect();
+        let expected: Vec<BlockHash> = chain[1..].iter().map(|block| block.header.hash).collect();
+        assert_eq!(hashes, expected);
+    }
+
+    #[test]
+    fn should_stop_adding_blocks_after_a_panic() {
+        let blockchain = create_blockchain(NO_DIFFICULTY);
+        let chain = create_chain(3);
+        blockchain.add_block(chain[1].clone()).unwrap();
+
+        let cloned = blockchain.clone();
+        let result = thread::spawn(move || {
+            let _state = cloned.state.lock();
+            panic!("bug while updating the state");
+        })
+        .join();
+        assert!(result.is_err());
+
+        // the chain can still be read, but it's not extended
+        assert_eq!(
+            blockchain.get_last_block().header.hash,
+            chain[1].header.hash
+        );
+        assert!(blockchain.add_block(chain[2].clone()).is_err());
+        assert!(blockchain.add_blocks(chain[2..].to_vec()).is_err());
+        assert_eq!(blockchain.get_last_block().header.index, 1);
+    }
+
+    #[test]
+    fn should_find_blocks_by_hash() {
+        let blockchain = create_blockchain(NO_DIFFICULTY);
+        let block = create_next_block(&blockchain, Vec::new());
+        blockchain.add_block(block.clone()).unwrap();
+
+        let found_block = blockchain.get_block(block.header.hash).unwrap();
+        assert_eq!(found_block.header.index, block.header.index);
+
+        assert!(blockchain.get_block(BlockHash::from(1)).is_none());
+
+        // blocks can also be found by index
+        assert_eq!(
+            blockchain.get_block_at(1).unwrap().header.hash,
+            block.header.hash
+        );
+        assert!(blockchain.get_block_at(2).is_none());
+    }
+
+    #[test]
+    fn should_return_blocks_in_a_range() {
+        let blockchain = create_blockchain(NO_DIFFICULTY);
+        for _ in 0..3 {
+            let block = create_next_block(&blockchain, Vec::new());
+            blockchain.add_block(block).unwrap();
+        }
+
+        let indices = |blocks: BlockVec| {
+            blocks
+                .iter()
+                .map(|block| block.header.index)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(indices(blockchain.get_blocks_between(1, 2)), vec![1, 2]);
+
+        // the range is cut at the last block
+        assert_eq!(indices(blockchain.get_blocks_between(2, 10)), vec![2, 3]);
+        assert!(blockchain.get_blocks_between(5, 10).is_empty());
+        assert!(blockchain.get_blocks_between(2, 1).is_empty());
+    }
+
+    #[test]
+    fn should_find_included_transactions() {
+        let blockchain = create_blockchain(NO_DIFFICULTY);
+
+        let transaction = Transaction {
+            sender: "1".to_string(),
+            recipient: "2".to_string(),
+            amount: 3,
+            signature: None,
+            multisig: None,
+            contract: None,
+            token: None,
+            data: None,
+        };
+        let id = transaction.calculate_id();
+
+        // the transaction is not in the blockchain yet
+        assert!(!blockchain.contains_transaction(id));
+
+        // add a block that includes the transaction
+        let block = create_next_block(&blockchain, vec![transaction]);
+        blockchain.add_block(block.clone()).unwrap();
+
+        assert!(blockchain.contains_transaction(id));
+        assert_eq!(blockchain.find_transaction_block(id), Some(block.header()));
+    }
+
+    #[test]
+    fn should_not_let_adding_block_with_invalid_target() {
+        let blockchain = create_blockchain(NO_DIFFICULTY);
+
+        // create a block with a target different from the one required
+        let mut block = create_next_block(&blockchain, Vec::new());
+        block.header.bits = 0;
+        block.header.hash = block.calculate_hash();
+
+        // try adding the invalid block, it should return an error
+        let result = blockchain.add_block(block.clone());
+        assert_err(result, BlockchainError::InvalidTarget);
+    }
+
+    #[test]
+    fn should_not_let_adding_block_with_invalid_state_root() {
+        let blockchain = create_blockchain(NO_DIFFICULTY);
+        let transaction = Transaction {
+            sender: "1".to_string(),
+            recipient: "2".to_string(),
+            amount: 3,
+            signature: None,
+            multisig: None,
+            contract: None,
+            token: None,
+            data: None,
+        };
+
+        // the state root of a block without the transaction
+        let mut block = create_next_block(&blockchain, vec![transaction]);
+        block.header.state_root = blockchain.next_state_root(&[]);
+        block.header.hash = block.calculate_hash();
+
+        let result = blockchain.add_block(block);
+        assert_err(result, BlockchainError::InvalidStateRoot);
+        assert_eq!(blockchain.get_account("2"), Amounts::default());
+    }
+
+    #[test]
+    fn should_not_let_adding_block_with_invalid_timestamp() {
+        let clock = MockClock::new(1_000_000);
+        let blockchain =
+            Blockchain::with_clock(ProofOfWork::shared(NO_DIFFICULTY, 1, 1), clock.shared());
+        for _ in 0..MEDIAN_TIME_SPAN {
+            let mut block = create_next_block(&blockchain, Vec::new());
+            // the timestamps go up by a second
+            block.header.timestamp =
+                blockchain.get_last_block().header.timestamp.max(1_000) + 1_000;
+            block.header.hash = block.calculate_hash();
+            blockchain.add_block(block).unwrap();
+        }
+        let median = blockchain.get_block_at(6).unwrap().header.timestamp;
+        assert_eq!(blockchain.min_timestamp(), median + 1);
+
+        // blocks can be older than the previous one, as long as they are after the median
+        let mut block = create_next_block(&blockchain, Vec::new());
+        block.header.timestamp = median;
+        block.header.hash = block.calculate_hash();
+        let result = blockchain.add_block(block.clone());
+        assert_err(result, BlockchainError::InvalidTimestampTooOld(median));
+
+        block.header.timestamp = median + 1;
+        block.header.hash = block.calculate_hash();
+        blockchain.add_block(block).unwrap();
+
+        // but not too far in the future
+        blockchain.set_max_time_drift(60);
+        let mut block = create_next_block(&blockchain, Vec::new());
+        block.header.timestamp = 1_000_000 + 61_000;
+        block.header.hash = block.calculate_hash();
+        let result = blockchain.add_block(block.clone());
+        assert_err(result, BlockchainError::InvalidTimestampInFuture(60));
+
+        // the same block is fine once our clock catches up
+        clock.advance(Duration::from_secs(2));
+        blockchain.add_block(block).unwrap();
+    }
+
+    #[test]
+    fn should_track_tokens() {
+        let blockchain = create_blockchain(NO_DIFFICULTY);
+        let issue = Transaction {
+            sender: "alice".to_string(),
+            recipient: "alice".to_string(),
+            amount: 0,
+            signature: None,
+            multisig: None,
+            contract: None,
+            token: Some(TokenAction::Issue {
+                name: "GOLD".to_string(),
+                supply: 10,
+            }),
+            data: None,
+        };
+        let token_id = issue.calculate_id();
+        let block = create_next_block(&blockchain, vec![issue.clone()]);
+        blockchain.add_block(block).unwrap();
+
+        let balances = blockchain.get_token_balances("alice");
+        assert_eq!(balances.len(), 1);
+        assert_eq!((balances[0].0, balances[0].2), (token_id, 10));
+        assert!(blockchain.get_receipt(token_id).unwrap().success);
+
+        // transfers above the confirmed balance are not taken into the pool
+        let mut transfer = issue.clone();
+        transfer.recipient = "bob".to_string();
+        transfer.token = Some(TokenAction::Transfer {
+            token_id,
+            amount: 11,
+        });
+        let error = blockchain.check_transaction(&transfer).unwrap_err();
+        assert_eq!(
+            error.downcast::<TokenError>().unwrap(),
+            TokenError::InsufficientBalance(10)
+        );
+
+        // and blocks with actions that can never succeed are rejected
+        let mut invalid = issue;
+        invalid.token = Some(TokenAction::Issue {
+            name: "SILVER".to_string(),
+            supply: 0,
+        });
+        let block = create_next_block(&blockchain, vec![invalid]);
+        let error = blockchain.add_block(block).unwrap_err();
+        assert_eq!(
+            error.downcast::<TokenError>().unwrap(),
+            TokenError::ZeroSupply
+        );
+        assert_eq!(blockchain.get_last_block().header.index, 1);
+    }
+
+    #[test]
+    fn should_reload_stored_blocks() {
+        let data_dir = env::temp_dir().join(format!("blockchain_{}", std::process::id()));
+        let data_dir = data_dir.to_str().unwrap();
+        let _ = fs::remove_dir_all(data_dir);
+
+        let consensus = ProofOfWork::shared(NO_DIFFICULTY, 1, 1);
+        let blockchain =
+            Blockchain::open(consensus.clone(), data_dir, None, DEFAULT_CHAIN_ID, false).unwrap();
+        let block = create_next_block(&blockchain, Vec::new());
+        blockchain.add_block(block.clone()).unwrap();
+
+        // the blocks survive a restart of the node
+        let blockchain =
+            Blockchain::open(consensus, data_dir, None, DEFAULT_CHAIN_ID, false).unwrap();
+        assert_eq!(blockchain.get_last_block().header.hash, block.header.hash);
+        assert_eq!(blockchain.get_all_blocks().len(), 2);
+
+        // stored blocks must still be valid with the current rules
+        let consensus = ProofOfWork::shared(255, 1, 1);
+        assert!(Blockchain::open(consensus, data_dir, None, DEFAULT_CHAIN_ID, false).is_err());
+
+        fs::remove_dir_all(data_dir).unwrap();
+    }
+
+    #[test]
+    fn should_switch_to_forks_with_more_work() {
+        let data_dir = env::temp_dir().join(format!("blockchain_fork_{}", std::process::id()));
+        let data_dir = data_dir.to_str().unwrap();
+        let _ = fs::remove_dir_all(data_dir);
+
+        let consensus = ProofOfWork::shared(NO_DIFFICULTY, 1, 1);
+        let blockchain =
+            Blockchain::open(consensus.clone(), data_dir, None, DEFAULT_CHAIN_ID, false).unwrap();
+        let ours = create_chain(2);
+        blockchain.add_blocks(ours[1..].to_vec()).unwrap();
+        let events = blockchain.events().subscribe();
+
+        // the other branch shares the first block, and sends an amount to "2" instead
+        let other = create_blockchain(NO_DIFFICULTY);
+        other.add_block(ours[1].clone()).unwrap();
+        let transaction = Transaction {
+            sender: "0".to_string(),
+            recipient: "2".to_string(),
+            amount: 5,
+            signature: None,
+            multisig: None,
+            contract: None,
+            token: None,
+            data: None,
+        };
+        let block = create_next_block(&other, vec![transaction]);
+        other.add_block(block).unwrap();
+
+        // with the same work, the blocks we already have are kept
+        let fork = other.get_all_blocks();
+        let result = blockchain.add_blocks(fork[2..].to_vec());
+        assert_err(result, BlockchainError::NotEnoughWork);
+
+        let block = create_next_block(&other, Vec::new());
+        other.add_block(block).unwrap();
+        let fork = other.get_all_blocks();
+        blockchain.add_blocks(fork[2..].to_vec()).unwrap();
+        assert_eq!(blockchain.get_last_block().header.hash, fork[3].header.hash);
+        assert_eq!(blockchain.total_work(), BlockHash::from(3));
+        assert_eq!(blockchain.get_account("1").received, 1);
+        assert_eq!(blockchain.get_account("2").received, 5);
+        let (history, _, _) = blockchain.get_address_transactions("2", 0, 10, u64::MAX);
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].block_index, 2);
+
+        let events = events.pending();
+        match &events[0] {
+            ChainEvent::Reorg(reorg) => {
+                assert_eq!(reorg.fork_index, 1);
+                assert_eq!(reorg.reverted, vec![ours[2].header.hash]);
+                assert_eq!(
+                    reorg.applied,
+                    vec![fork[2].header.hash, fork[3].header.hash]
+                );
+            }
+            event => panic!("unexpected event {:?}", event),
+        }
+        assert_eq!(events.len(), 3);
+
+        // the store has the blocks of the fork
+        let blockchain =
+            Blockchain::open(consensus, data_dir, None, DEFAULT_CHAIN_ID, false).unwrap();
+        assert_eq!(blockchain.get_last_block().header.hash, fork[3].header.hash);
+
+        fs::remove_dir_all(data_dir).unwrap();
+    }
+
+    #[test]
+    fn should_not_switch_to_forks_of_pruned_blocks() {
+        let blockchain = create_blockchain(NO_DIFFICULTY);
+        let ours = create_chain(2);
+        blockchain.add_blocks(ours[1..].to_vec()).unwrap();
+        blockchain.set_prune_depth(1);
+
+        // the state before the first block can't be calculated again
+        let fork = create_chain(3);
+        let result = blockchain.add_blocks(fork[1..].to_vec());
+        assert_err(result, BlockchainError::ForkTooDeep(0));
+        assert_eq!(blockchain.get_last_block().header.hash, ours[2].header.hash);
+    }
+
+    #[test]
+    fn should_add_a_batch_of_blocks() {
+        let data_dir = env::temp_dir().join(format!("blockchain_batch_{}", std::process::id()));
+        let data_dir = data_dir.to_str().unwrap();
+        let _ = fs::remove_dir_all(data_dir);
+
+        let consensus = ProofOfWork::shared(NO_DIFFICULTY, 1, 1);
+        let blocks = create_chain(3);
+        let blockchain =
+            Blockchain::open(consensus.clone(), data_dir, None, DEFAULT_CHAIN_ID, false).unwrap();
+        let mut tip = blockchain.watch_tip();
+
+        blockchain.add_blocks(blocks[1..].to_vec()).unwrap();
+        assert_eq!(blockchain.get_all_blocks().len(), 4);
+        assert_eq!(blockchain.get_account("1").received, 3);
+        assert_eq!(blockchain.get_account_at("1", 1).received, 1);
+        assert!(tip.has_changed());
+        assert_eq!(tip.borrow_and_update(), blocks[3].header.hash);
+
+        // the whole batch was stored
+        let blockchain =
+            Blockchain::open(consensus, data_dir, None, DEFAULT_CHAIN_ID, false).unwrap();
+        assert_eq!(
+            blockchain.get_last_block().header.hash,
+            blocks[3].header.hash
+        );
+
+        // an empty batch changes nothing
+        blockchain.add_blocks(Vec::new()).unwrap();
+        assert_eq!(blockchain.get_all_blocks().len(), 4);
+
+        fs::remove_dir_all(data_dir).unwrap();
+    }
+
+    #[test]
+    fn should_not_add_any_block_of_an_invalid_batch() {
+        let blockchain = create_blockchain(NO_DIFFICULTY);
+        let blocks = create_chain(3);
+
+        // the last block breaks a rule that is only checked with the state of the chain
+        let mut invalid_blocks = blocks[1..].to_vec();
+        invalid_blocks[2].header.state_root = BlockHash::default();
+        invalid_blocks[2].header.hash = invalid_blocks[2].calculate_hash();
+        let result = blockchain.add_blocks(invalid_blocks);
+        assert_err(result, BlockchainError::InvalidStateRoot);
+        assert_eq!(blockchain.get_all_blocks().len(), 1);
+        assert_eq!(blockchain.get_account("1"), Amounts::default());
+
+        // the blocks of the batch must follow each other
+        let unlinked_blocks = vec![blocks[1].clone(), blocks[3].clone()];
+        let result = blockchain.add_blocks(unlinked_blocks);
+        assert_err(result, BlockchainError::InvalidIndex);
+        assert_eq!(blockchain.get_all_blocks().len(), 1);
+
+        // and the first one must follow our last block
+        let result = blockchain.add_blocks(blocks[2..].to_vec());
+        assert_err(result, BlockchainError::InvalidIndex);
+
+        blockchain.add_blocks(blocks[1..].to_vec()).unwrap();
+        assert_eq!(blockchain.get_all_blocks().len(), 4);
+    }
+
+    #[test]
+    fn should_check_long_batches_in_parallel() {
+        let blockchain = create_blockchain(NO_DIFFICULTY);
+        let blocks = create_chain(4 * MIN_BLOCKS_PER_THREAD as u64);
+
+        // the blocks checked by different threads are broken, the first one is reported
+        let mut invalid_blocks = blocks[1..].to_vec();
+        for position in [50, 10] {
+            invalid_blocks[position].header.nonce += 1;
+        }
+        let error = blockchain.add_blocks(invalid_blocks).unwrap_err();
+        assert_eq!(
+            format!("{:#}", error),
+            "block 11 is not valid: Invalid hash"
+        );
+        assert_eq!(blockchain.get_all_blocks().len(), 1);
+
+        // and so are the broken links between them
+        let mut unlinked_blocks = blocks[1..].to_vec();
+        unlinked_blocks.remove(40);
+        let result = blockchain.add_blocks(unlinked_blocks);
+        assert_err(result, BlockchainError::InvalidIndex);
+
+        blockchain.add_blocks(blocks[1..].to_vec()).unwrap();
+        assert_eq!(blockchain.get_all_blocks().len(), blocks.len());
+    }
+
+    #[test]
+    fn should_prune_old_transactions() {
+        let blockchain = create_blockchain(NO_DIFFICULTY);
+        blockchain.set_prune_depth(2);
+
+        let mut ids = Vec::new();
+        for amount in 1..=4 {
+            let transaction = Transaction {
+                sender: "1".to_string(),
+                recipient: "2".to_string(),
+                amount,
+                signature: None,
+                multisig: None,
+                contract: None,
+                token: None,
+                data: None,
+            };
+            ids.push(transaction.calculate_id());
+            let block = create_next_block(&blockchain, vec![transaction]);
+            blockchain.add_block(block).unwrap();
+        }
+
+        // only the last 2 blocks keep their transactions
+        let blocks = blockchain.get_all_blocks();
+        assert_eq!(blocks.len(), 5);
+        assert!(!blocks[0].pruned);
+        for block in &blocks[1..=2] {
+            assert!(block.pruned);
+            assert!(block.transactions.is_empty());
+        }
+        for block in &blocks[3..=4] {
+            assert!(!block.pruned);
+            assert_eq!(block.transactions.len(), 1);
+        }
+
+        // the pruned transactions are still accounted for
+        assert_eq!(blockchain.get_account("2").received, 10);
+        assert_eq!(blockchain.get_account("1").sent, 10);
+        assert_eq!(blockchain.get_account("3"), Amounts::default());
+        assert!(blockchain.contains_transaction(ids[0]));
+        assert_eq!(
+            blockchain.find_transaction_block(ids[1]),
+            Some(blocks[2].header())
+        );
+
+        // new blocks can still be added on top of the pruned ones
+        let block = create_next_block(&blockchain, Vec::new());
+        assert!(blockchain.add_block(block).is_ok());
+        assert!(blockchain.get_block_at(3).unwrap().pruned);
+    }
+
+    #[test]
+    fn should_start_from_snapshots() {
+        let blockchain = create_blockchain(NO_DIFFICULTY);
+        let mut ids = Vec::new();
+        for amount in 1..=3 {
+            let transaction = Transaction {
+                sender: "1".to_string(),
+                recipient: "2".to_string(),
+                amount,
+                signature: None,
+                multisig: None,
+                contract: None,
+                token: None,
+                data: None,
+            };
+            ids.push(transaction.calculate_id());
+            let block = create_next_block(&blockchain, vec![transaction]);
+            blockchain.add_block(block).unwrap();
+        }
+
+        // the snapshot has the state right after the block at its height
+        let snapshot = blockchain.snapshot(2).unwrap();
+        assert_eq!(
+            snapshot.tip,
+            blockchain.get_block_at(2).unwrap().header.hash
+        );
+        assert_eq!(snapshot.balances.get("2").received, 3);
+        assert_eq!(snapshot.transactions.len(), 2);
+        assert!(snapshot.blocks[1..].iter().all(|block| block.pruned));
+
+        let consensus = ProofOfWork::shared(NO_DIFFICULTY, 1, 1);
+        let bootstrapped = Blockchain::from_snapshot(consensus, snapshot.clone()).unwrap();
+        assert_eq!(bootstrapped.get_last_block().header.hash, snapshot.tip);
+        assert_eq!(bootstrapped.get_account("2").received, 3);
+        assert!(bootstrapped.contains_transaction(ids[1]));
+        assert!(!bootstrapped.contains_transaction(ids[2]));
+
+        // the rest of the chain can be added on top of it
+        let block = blockchain.get_block_at(3).unwrap();
+        assert!(bootstrapped.add_block(block).is_ok());
+        assert!(bootstrapped.contains_transaction(ids[2]));
+        assert!(bootstrapped.snapshot(3).is_ok());
+
+        // the transactions before the snapshot are not known anymore
+        assert_eq!(
+            bootstrapped.snapshot(1).unwrap_err(),
+            SnapshotError::PrunedHeight(1, 2)
+        );
+        assert_eq!(
+            blockchain.snapshot(4).unwrap_err(),
+            SnapshotError::UnknownHeight(4)
+        );
+    }
+
+    #[test]
+    fn should_not_start_from_invalid_snapshots() {
+        let blockchain = create_blockchain(NO_DIFFICULTY);
+        for _ in 0..2 {
+            let block = create_next_block(&blockchain, Vec::new());
+            blockchain.add_block(block).unwrap();
+        }
+        let consensus = ProofOfWork::shared(NO_DIFFICULTY, 1, 1);
+
+        // the headers must still follow each other
+        let mut snapshot = blockchain.snapshot(2).unwrap();
+        snapshot.blocks[2].header.previous_hash = BlockHash::from(1);
+        assert!(Blockchain::from_snapshot(consensus.clone(), snapshot).is_err());
+
+        let mut snapshot = blockchain.snapshot(2).unwrap();
+        snapshot.tip = BlockHash::from(1);
+        let result = Blockchain::from_snapshot(consensus.clone(), snapshot);
+        let err = result.unwrap_err().downcast::<SnapshotError>().unwrap();
+        assert_eq!(err, SnapshotError::InvalidTip);
+
+        // and start from the same genesis block
+        let mut snapshot = blockchain.snapshot(2).unwrap();
+        snapshot.blocks.remove(0);
+        let result = Blockchain::from_snapshot(consensus, snapshot);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn should_only_take_transactions_with_the_allowed_data() {
+        let blockchain = create_blockchain(NO_DIFFICULTY);
+        blockchain.set_max_data_bytes(4);
+        let transaction = Transaction {
+            sender: "alice".to_string(),
+            recipient: "bob".to_string(),
+            amount: 1,
+            signature: None,
+            multisig: None,
+            contract: None,
+            token: None,
+            data: Some(b"invoice 42".to_vec()),
+        };
+
+        let err = blockchain.check_transaction(&transaction).unwrap_err();
+        assert_eq!(
+            err.downcast::<TransactionError>().unwrap(),
+            TransactionError::DataTooLong(4)
+        );
+
+        // other nodes may take more, so blocks can carry it
+        let block = create_next_block(&blockchain, vec![transaction]);
+        blockchain.add_block(block).unwrap();
+    }
+
+    #[test]
+    fn should_reject_blocks_with_overflowing_amounts() {
+        let blockchain = create_blockchain(NO_DIFFICULTY);
+        let transfer = |sender: &str, amount| Transaction {
+            sender: sender.to_string(),
+            recipient: "bob".to_string(),
+            amount,
+            signature: None,
+            multisig: None,
+            contract: None,
+            token: None,
+            data: None,
+        };
+        let block = create_next_block(&blockchain, vec![transfer("alice", u64::MAX)]);
+        blockchain.add_block(block).unwrap();
+
+        // bob can't receive anything else, whatever the state root says
+        let overflowing = transfer("carol", 1);
+        let err = blockchain.check_transaction(&overflowing).unwrap_err();
+        assert_eq!(
+            err.downcast::<AmountError>().unwrap(),
+            AmountError::Overflow("bob".to_string())
+        );
+        let block = create_next_block(&blockchain, vec![overflowing.clone()]);
+        let err = blockchain.add_block(block).unwrap_err();
+        assert_eq!(
+            err.downcast::<AmountError>().unwrap(),
+            AmountError::Overflow("bob".to_string())
+        );
+
+        // so it's left out of the next block, while the rest of the transactions can go in
+        let other = Transaction {
+            recipient: "dave".to_string(),
+            ..transfer("carol", 1)
+        };
+        let (selected, overflowing) = blockchain.select_transactions(vec![overflowing, other]);
+        assert_eq!(selected.len(), 1);
+        assert_eq!(selected[0].recipient, "dave");
+        assert_eq!(overflowing.len(), 1);
+        let block = create_next_block(&blockchain, selected);
+        assert!(blockchain.add_block(block).is_ok());
+    }
+
+    #[test]
+    fn should_reject_transactions_signed_for_other_chains() {
+        let blockchain = create_blockchain(NO_DIFFICULTY);
+        blockchain.set_chain_id("testnet");
+        let mut transaction = Transaction {
+            sender: String::new(),
+            recipient: "bob".to_string(),
+            amount: 1,
+            signature: None,
+            multisig: None,
+            contract: None,
+            token: None,
+            data: None,
+        };
+        transaction.sign(&[1; 32], DEFAULT_CHAIN_ID);
+
+        // a miner could include it, but no node of the chain takes the block
+        let block = create_next_block(&blockchain, vec![transaction.clone()]);
+        let id = transaction.calculate_id();
+        assert_err(
+            blockchain.add_block(block),
+            BlockchainError::InvalidTransactionSignature(id),
+        );
+
+        transaction.sign(&[1; 32], "testnet");
+        let block = create_next_block(&blockchain, vec![transaction]);
+        assert!(blockchain.add_block(block).is_ok());
+    }
+
+    #[test]
+    fn should_reject_unsigned_transactions_from_public_keys() {
+        let blockchain = create_blockchain(NO_DIFFICULTY);
+        let mut transaction = Transaction {
+            sender: String::new(),
+            recipient: "bob".to_string(),
+            amount: 10,
+            signature: None,
+            multisig: None,
+            contract: None,
+            token: None,
+            data: None,
+        };
+        transaction.sign(&[1; 32], DEFAULT_CHAIN_ID);
+
+        // a miner can't spend from a public key without the signature of its owner
+        let spend = Transaction {
+            signature: None,
+            ..transaction.clone()
+        };
+        let block = create_next_block(&blockchain, vec![spend.clone()]);
+        assert_err(
+            blockchain.add_block(block),
+            BlockchainError::InvalidTransactionSignature(spend.calculate_id()),
+        );
+
+        let block = create_next_block(&blockchain, vec![transaction]);
+        assert!(blockchain.add_block(block).is_ok());
+    }
+
+    #[test]
+    fn should_reject_unsigned_transactions_from_multisig_addresses() {
+        let blockchain = create_blockchain(NO_DIFFICULTY);
+        let multisig = MultiSig {
+            threshold: 2,
+            public_keys: [[1; 32], [2; 32], [3; 32]]
+                .iter()
+                .map(|seed| hex::encode(ed25519::keypair(seed).1))
+                .collect(),
+            signatures: BTreeMap::new(),
+        };
+        let address = multisig.address().unwrap();
+
+        // a miner can't spend from the address without the signatures of its owners
+        let spend = Transaction {
+            sender: address,
+            recipient: "bob".to_string(),
+            amount: 10,
+            signature: None,
+            multisig: None,
+            contract: None,
+            token: None,
+            data: None,
+        };
+        let id = spend.calculate_id();
+        let block = create_next_block(&blockchain, vec![spend.clone()]);
+        assert_err(
+            blockchain.add_block(block),
+            BlockchainError::InvalidTransactionSignature(id),
+        );
+
+        // nor with fewer signatures than the threshold
+        let mut spend = Transaction {
+            multisig: Some(multisig),
+            ..spend
+        };
+        spend.sign_multisig(&[1; 32], DEFAULT_CHAIN_ID);
+        let block = create_next_block(&blockchain, vec![spend.clone()]);
+        assert_err(
+            blockchain.add_block(block),
+            BlockchainError::InvalidTransactionSignature(spend.calculate_id()),
+        );
+
+        spend.sign_multisig(&[3; 32], DEFAULT_CHAIN_ID);
+        let block = create_next_block(&blockchain, vec![spend]);
+        assert!(blockchain.add_block(block).is_ok());
+        assert_eq!(blockchain.get_account("bob").received, 10);
+    }
+
+    fn create_blockchain(difficulty: u32) -> Blockchain {
+        Blockchain::new(ProofOfWork::shared(difficulty, 1, 1))
+    }
+
+    // Creates a block on top of the last one, with the target required by the blockchain
+    fn create_next_block(blockchain: &Blockchain, transactions: Vec<Transaction>) -> Block {
+        let last_block = blockchain.get_last_block();
+        let mut block = Block::new(
+            last_block.header.index + 1,
+            0,
+            last_block.header.hash,
+            transactions,
+        );
+        block.header.timestamp = block.header.timestamp.max(blockchain.min_timestamp());
+        block.header.bits = blockchain.next_bits();
+        block.header.state_root = blockchain.next_state_root(&block.transactions);
+        block.header.hash = block.calculate_hash();
+
+        block
+    }
+
+    // Valid chain with "length" blocks after the genesis, each one with a transfer of 1 coin to "1"
+    fn create_chain(length: u64) -> Vec<Block> {
+        let blockchain = create_blockchain(NO_DIFFICULTY);
+        for _ in 0..length {
+            let transaction = Transaction {
+                sender: "0".to_string(),
+                recipient: "1".to_string(),
+                amount: 1,
+                signature: None,
+                multisig: None,
+                contract: None,
+                token: None,
+                data: None,
+            };
+            let block = create_next_block(&blockchain, vec![transaction]);
+            blockchain.add_block(block).unwrap();
+        }
+
+        blockchain.get_all_blocks()
+    }
+
     fn assert_err(result: Result<(), anyhow::Error>, error_type: BlockchainError) {
         let err = result.unwrap_err().downcast::<BlockchainError>().unwrap();
         assert_eq!(err, error_type);
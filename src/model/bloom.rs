@@ -0,0 +1,53 @@
+use ethereum_types::{Bloom, BloomInput};
+
+use super::Transaction;
+
+// Bloom filter (2048 bits, like the logs bloom of Ethereum) of the addresses touched by a block
+// It's committed in the header, so light clients and explorers can skip the blocks
+// that surely don't involve an address without looking at their transactions
+pub type AddressBloom = Bloom;
+
+// Filter with the senders and the recipients (including contracts) of the transactions
+pub fn address_bloom(transactions: &[Transaction]) -> AddressBloom {
+    let mut bloom = AddressBloom::default();
+    for transaction in transactions {
+        bloom.accrue(BloomInput::Raw(transaction.sender.as_bytes()));
+        bloom.accrue(BloomInput::Raw(transaction.recipient.as_bytes()));
+    }
+
+    bloom
+}
+
+// False positives are possible, so a match only means that the block may involve the address
+// A miss is always right, the block doesn't involve the address
+pub fn bloom_contains(bloom: &AddressBloom, address: &str) -> bool {
+    bloom.contains_input(BloomInput::Raw(address.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_match_the_addresses_of_the_transactions() {
+        let transaction = Transaction {
+            sender: "alice".to_string(),
+            recipient: "bob".to_string(),
+            amount: 1,
+            signature: None,
+            multisig: None,
+            contract: None,
+            token: None,
+            data: None,
+        };
+
+        let bloom = address_bloom(&[transaction]);
+        assert!(bloom_contains(&bloom, "alice"));
+        assert!(bloom_contains(&bloom, "bob"));
+        assert!(!bloom_contains(&bloom, "carol"));
+
+        let empty = address_bloom(&[]);
+        assert!(empty.is_zero());
+        assert!(!bloom_contains(&empty, "alice"));
+    }
+}
@@ -0,0 +1,309 @@
+use ethereum_types::U256;
+
+use super::{AddressBloom, BlockHeader, ContractAction, TokenAction, Transaction, TransactionId};
+
+// Canonical encoding of what is hashed or signed: block headers and transactions
+// Hashes must never change, so every field is written explicitly, in a fixed order and format,
+// instead of depending on how serde or the storage encoding happen to lay out the structs.
+// New fields must be appended at the end, and add no bytes when they are not set, so old hashes stay valid.
+//
+// * Integers are big-endian, with their fixed size (u32, u64 and i64 as two's complement)
+// * Strings are their length (u32) followed by their UTF-8 bytes, and so are raw bytes
+// * Hashes are their 32 bytes and the bloom filter its 256 bytes
+// * Lists are their length (u32) followed by the items
+// * Optional values are a 0 byte if missing, or a 1 byte followed by the value
+// * Variants of enums are a byte with their position, followed by their fields
+//
+// Every encoding starts with a string of the kind of value, so the bytes of a transaction
+// can never be mistaken for the bytes of a header
+
+const HEADER_DOMAIN: &str = "rust-blockchain/block-header/v1";
+const TRANSACTION_DOMAIN: &str = "rust-blockchain/transaction/v1";
+const SIGNATURE_DOMAIN: &str = "rust-blockchain/signature/v1";
+
+// Bytes of the header covered by its hash, which are all the fields but the hash itself
+pub fn header_bytes(header: &BlockHeader) -> Vec<u8> {
+    let mut writer = Writer::new(HEADER_DOMAIN);
+    writer
+        .u64(header.index)
+        .i64(header.timestamp)
+        .u64(header.nonce)
+        .u32(header.bits)
+        .hash(header.previous_hash)
+        .hash(header.merkle_root)
+        .hash(header.state_root)
+        .bloom(&header.bloom);
+    writer.bytes
+}
+
+// Bytes of the transaction covered by its id, which are all the fields but the signatures,
+// as they are made over the id
+pub fn transaction_bytes(transaction: &Transaction) -> Vec<u8> {
+    let mut writer = Writer::new(TRANSACTION_DOMAIN);
+    writer
+        .str(&transaction.sender)
+        .str(&transaction.recipient)
+        .u64(transaction.amount);
+
+    match &transaction.multisig {
+        None => writer.flag(false),
+        Some(multisig) => {
+            writer.flag(true).u64(multisig.threshold as u64);
+            writer.u32(multisig.public_keys.len() as u32);
+            for public_key in &multisig.public_keys {
+                writer.str(public_key);
+            }
+            &mut writer
+        }
+    };
+
+    match &transaction.contract {
+        None => writer.flag(false),
+        Some(ContractAction::Deploy {
+            code,
+            input,
+            gas_limit,
+        }) => writer
+            .flag(true)
+            .variant(0)
+            .str(code)
+            .str(input)
+            .u64(*gas_limit),
+        Some(ContractAction::Call { input, gas_limit }) => {
+            writer.flag(true).variant(1).str(input).u64(*gas_limit)
+        }
+    };
+
+    match &transaction.token {
+        None => writer.flag(false),
+        Some(TokenAction::Issue { name, supply }) => {
+            writer.flag(true).variant(0).str(name).u64(*supply)
+        }
+        Some(TokenAction::Transfer { token_id, amount }) => {
+            writer.flag(true).variant(1).hash(*token_id).u64(*amount)
+        }
+    };
+
+    // added after the rest, so it's only written when set and the ids of older transactions don't change
+    if let Some(data) = &transaction.data {
+        writer.flag(true).data(data);
+    }
+
+    writer.bytes
+}
+
+// Bytes that the senders of a transaction sign: the id of the transaction for a given chain
+// The chain is not part of the id, but a signature for one chain is never valid in another
+pub fn signing_bytes(chain_id: &str, id: TransactionId) -> Vec<u8> {
+    let mut writer = Writer::new(SIGNATURE_DOMAIN);
+    writer.str(chain_id).hash(id);
+    writer.bytes
+}
+
+struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    fn new(domain: &str) -> Writer {
+        let mut writer = Writer { bytes: Vec::new() };
+        writer.str(domain);
+        writer
+    }
+
+    fn u32(&mut self, value: u32) -> &mut Writer {
+        self.bytes.extend_from_slice(&value.to_be_bytes());
+        self
+    }
+
+    fn u64(&mut self, value: u64) -> &mut Writer {
+        self.bytes.extend_from_slice(&value.to_be_bytes());
+        self
+    }
+
+    fn i64(&mut self, value: i64) -> &mut Writer {
+        self.bytes.extend_from_slice(&value.to_be_bytes());
+        self
+    }
+
+    fn str(&mut self, value: &str) -> &mut Writer {
+        self.data(value.as_bytes())
+    }
+
+    fn data(&mut self, value: &[u8]) -> &mut Writer {
+        self.u32(value.len() as u32);
+        self.bytes.extend_from_slice(value);
+        self
+    }
+
+    fn hash(&mut self, value: U256) -> &mut Writer {
+        let mut bytes = [0; 32];
+        value.to_big_endian(&mut bytes);
+        self.bytes.extend_from_slice(&bytes);
+        self
+    }
+
+    fn bloom(&mut self, value: &AddressBloom) -> &mut Writer {
+        self.bytes.extend_from_slice(value.as_bytes());
+        self
+    }
+
+    fn flag(&mut self, value: bool) -> &mut Writer {
+        self.bytes.push(value as u8);
+        self
+    }
+
+    fn variant(&mut self, position: u8) -> &mut Writer {
+        self.bytes.push(position);
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{BlockHash, MultiSig};
+
+    // The vectors must never change: a different value means that every hash of the chain changed
+
+    #[test]
+    fn should_encode_transactions_canonically() {
+        let transaction = create_transaction();
+        let expected = concat!(
+            "0000001e",
+            "727573742d626c6f636b636861696e2f7472616e73616374696f6e2f7631", // domain
+            "00000005616c696365",                                           // sender
+            "00000003626f62",                                               // recipient
+            "000000000000002a",                                             // amount
+            "00",                                                           // multisig
+            "00",                                                           // contract
+            "00",                                                           // token
+        );
+        assert_eq!(hex::encode(transaction_bytes(&transaction)), expected);
+        assert_eq!(
+            format!("{:x}", transaction.calculate_id()),
+            "dfd0914f167fd2887e4330b5747aa8030fc225b3ab2b7bf079c867d906963e44"
+        );
+    }
+
+    #[test]
+    fn should_hash_transactions_with_actions() {
+        let mut transaction = create_transaction();
+        transaction.multisig = Some(MultiSig {
+            threshold: 1,
+            public_keys: vec!["01".to_string(), "02".to_string()],
+            signatures: Default::default(),
+        });
+        transaction.contract = Some(ContractAction::Call {
+            input: "ff".to_string(),
+            gas_limit: 100,
+        });
+        transaction.token = Some(TokenAction::Transfer {
+            token_id: U256::from(1),
+            amount: 2,
+        });
+        assert_eq!(
+            format!("{:x}", transaction.calculate_id()),
+            "1729d6faffddaefd4520326144f4026b05ec3b5ed4547f5854f26d70b26d0786"
+        );
+
+        // the signatures are not covered, as they are made over the id
+        let id = transaction.calculate_id();
+        transaction.signature = Some("abcd".to_string());
+        if let Some(multisig) = transaction.multisig.as_mut() {
+            multisig
+                .signatures
+                .insert("01".to_string(), "ef".to_string());
+        }
+        assert_eq!(transaction.calculate_id(), id);
+    }
+
+    #[test]
+    fn should_append_the_data_of_transactions() {
+        let mut transaction = create_transaction();
+        let without_data = hex::encode(transaction_bytes(&transaction));
+        transaction.data = Some(vec![0xca, 0xfe]);
+
+        let expected = format!("{}{}", without_data, concat!("01", "00000002cafe"));
+        assert_eq!(hex::encode(transaction_bytes(&transaction)), expected);
+    }
+
+    #[test]
+    fn should_hash_headers_canonically() {
+        let header = BlockHeader {
+            index: 1,
+            timestamp: 1_600_000_000_000,
+            nonce: 7,
+            bits: 0x1f00_ffff,
+            previous_hash: BlockHash::from(2),
+            merkle_root: BlockHash::from(3),
+            state_root: BlockHash::from(4),
+            bloom: AddressBloom::default(),
+            hash: BlockHash::from(5),
+        };
+
+        let bytes = header_bytes(&header);
+        assert_eq!(
+            hex::encode(&bytes[..4 + HEADER_DOMAIN.len() + 28]),
+            concat!(
+                "0000001f",
+                "727573742d626c6f636b636861696e2f626c6f636b2d6865616465722f7631", // domain
+                "0000000000000001",                                               // index
+                "00000174876e8000",                                               // timestamp
+                "0000000000000007",                                               // nonce
+                "1f00ffff",                                                       // bits
+            )
+        );
+        // then the three hashes and the bloom filter, but not the hash of the header
+        assert_eq!(bytes.len(), 4 + HEADER_DOMAIN.len() + 28 + 3 * 32 + 256);
+        assert_eq!(
+            format!("{:x}", header.calculate_hash()),
+            "7630bc20c705ec11d17ed5c8ba0b8a9ea2d513f4d707f19582c115c00c6a3be8"
+        );
+    }
+
+    #[test]
+    fn should_sign_transactions_for_a_chain() {
+        let id = create_transaction().calculate_id();
+        let bytes = signing_bytes("main", id);
+        assert_eq!(
+            hex::encode(&bytes[..bytes.len() - 32]),
+            concat!(
+                "0000001c",
+                "727573742d626c6f636b636861696e2f7369676e61747572652f7631", // domain
+                "000000046d61696e",                                         // chain id
+            )
+        );
+        assert_eq!(hex::encode(&bytes[bytes.len() - 32..]), format!("{:x}", id));
+        assert_ne!(signing_bytes("testnet", id), bytes);
+    }
+
+    #[test]
+    fn should_not_depend_on_the_json_representation() {
+        // fields with default values are left out of the json, but not of the canonical bytes
+        let transaction = create_transaction();
+        let mut with_empty_multisig = create_transaction();
+        with_empty_multisig.multisig = Some(MultiSig {
+            threshold: 0,
+            public_keys: Vec::new(),
+            signatures: Default::default(),
+        });
+        assert_ne!(
+            transaction.calculate_id(),
+            with_empty_multisig.calculate_id()
+        );
+    }
+
+    fn create_transaction() -> Transaction {
+        Transaction {
+            sender: "alice".to_string(),
+            recipient: "bob".to_string(),
+            amount: 42,
+            signature: None,
+            multisig: None,
+            contract: None,
+            token: None,
+            data: None,
+        }
+    }
+}
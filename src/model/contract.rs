@@ -0,0 +1,501 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crypto::{digest::Digest, sha2::Sha256};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::{BlockHash, Transaction, TransactionId};
+use crate::vm::{self, FuncType, Gas, Host, Memory, Module, ValueType, VmError};
+
+// Max gas that a contract transaction can use, so a block can't take forever to validate
+pub const MAX_GAS_LIMIT: u64 = 10_000_000;
+
+// Max size of the code of a contract, and of the keys and values of its storage
+const MAX_CODE_SIZE: usize = 256 * 1024;
+const MAX_KEY_SIZE: u64 = 256;
+const MAX_VALUE_SIZE: u64 = 16 * 1024;
+
+// Gas of each call to the host, and of writing to the storage, on top of the bytes they move
+const HOST_CALL_GAS: u64 = 10;
+const STORAGE_WRITE_GAS: u64 = 200;
+
+// Error types to return when a contract transaction is not valid, or can't be executed
+#[derive(Error, PartialEq, Debug)]
+pub enum ContractError {
+    #[error("This node doesn't accept contract transactions")]
+    Disabled,
+
+    #[error("The {0} is not valid hex")]
+    InvalidHex(&'static str),
+
+    #[error("The code is larger than {0} bytes")]
+    CodeTooLarge(usize),
+
+    #[error("The gas limit can't be higher than {0}")]
+    GasLimitTooHigh(u64),
+
+    #[error("Invalid code: {0}")]
+    InvalidCode(VmError),
+
+    #[error("There is already a contract at {0}")]
+    AlreadyDeployed(String),
+
+    #[error("There is no contract at {0}")]
+    NotFound(String),
+
+    #[error("{0}")]
+    Execution(VmError),
+}
+
+// What a transaction does with a contract, besides transferring its amount to the recipient
+// Data is hex-encoded, and the recipient is always the address of the contract
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum ContractAction {
+    // Deploys the code (a WASM module) and runs its "init" function, if it exports one
+    Deploy {
+        code: String,
+        #[serde(default)]
+        input: String,
+        gas_limit: u64,
+    },
+    // Runs the "call" function of the contract
+    Call {
+        #[serde(default)]
+        input: String,
+        gas_limit: u64,
+    },
+}
+
+impl ContractAction {
+    fn gas_limit(&self) -> u64 {
+        match self {
+            ContractAction::Deploy { gas_limit, .. } | ContractAction::Call { gas_limit, .. } => {
+                *gas_limit
+            }
+        }
+    }
+
+    fn input(&self) -> Result<Vec<u8>, ContractError> {
+        match self {
+            ContractAction::Deploy { input, .. } | ContractAction::Call { input, .. } => {
+                hex::decode(input).map_err(|_| ContractError::InvalidHex("input"))
+            }
+        }
+    }
+
+    // Checks what doesn't depend on the state of the chain, blocks with invalid actions are rejected
+    // Everything else (e.g. running out of gas) makes the transaction fail, but it's still included
+    pub fn check(&self) -> Result<(), ContractError> {
+        if self.gas_limit() > MAX_GAS_LIMIT {
+            return Err(ContractError::GasLimitTooHigh(MAX_GAS_LIMIT));
+        }
+        self.input()?;
+        if let ContractAction::Deploy { code, .. } = self {
+            parse_code(code)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn parse_code(code: &str) -> Result<Module, ContractError> {
+    let bytes = hex::decode(code).map_err(|_| ContractError::InvalidHex("code"))?;
+    if bytes.len() > MAX_CODE_SIZE {
+        return Err(ContractError::CodeTooLarge(MAX_CODE_SIZE));
+    }
+
+    Module::parse(&bytes).map_err(ContractError::InvalidCode)
+}
+
+// A deployed contract: its code and everything it wrote, as hex-encoded keys and values
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Contract {
+    pub code: String,
+    pub storage: BTreeMap<String, String>,
+}
+
+// Outcome of a contract or token transaction, which is included in its block even if it failed
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Receipt {
+    pub success: bool,
+    pub gas_used: u64,
+    // hex-encoded data returned by the contract
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub output: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Receipt {
+    pub fn succeeded(gas_used: u64, output: Vec<u8>) -> Receipt {
+        Receipt {
+            success: true,
+            gas_used,
+            output: hex::encode(output),
+            error: None,
+        }
+    }
+
+    pub fn failed(gas_used: u64, error: String) -> Receipt {
+        Receipt {
+            success: false,
+            gas_used,
+            output: String::new(),
+            error: Some(error),
+        }
+    }
+}
+
+// Contracts deployed in a chain, by address
+// Like the balances, they are sorted so every node calculates the same root for them
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ContractState(BTreeMap<String, Contract>);
+
+impl ContractState {
+    pub fn get(&self, address: &str) -> Option<&Contract> {
+        self.0.get(address)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    // Runs the contract transactions, returning their receipts
+    // A failed transaction doesn't change any contract
+    pub fn apply(&mut self, transactions: &[Transaction]) -> HashMap<TransactionId, Receipt> {
+        transactions
+            .iter()
+            .filter_map(|transaction| {
+                let action = transaction.contract.as_ref()?;
+                Some((
+                    transaction.calculate_id(),
+                    self.execute(transaction, action),
+                ))
+            })
+            .collect()
+    }
+
+    fn execute(&mut self, transaction: &Transaction, action: &ContractAction) -> Receipt {
+        let mut gas = Gas::new(action.gas_limit());
+
+        match self.run(transaction, action, &mut gas) {
+            Ok(output) => Receipt::succeeded(gas.used(), output),
+            Err(error) => Receipt::failed(gas.used(), error.to_string()),
+        }
+    }
+
+    fn run(
+        &mut self,
+        transaction: &Transaction,
+        action: &ContractAction,
+        gas: &mut Gas,
+    ) -> Result<Vec<u8>, ContractError> {
+        action.check()?;
+        let address = &transaction.recipient;
+
+        let (code, storage, entry) = match action {
+            ContractAction::Deploy { code, .. } => {
+                if self.0.contains_key(address) {
+                    return Err(ContractError::AlreadyDeployed(address.clone()));
+                }
+                (code.clone(), BTreeMap::new(), "init")
+            }
+            ContractAction::Call { .. } => {
+                let contract = self
+                    .0
+                    .get(address)
+                    .ok_or_else(|| ContractError::NotFound(address.clone()))?;
+                (contract.code.clone(), contract.storage.clone(), "call")
+            }
+        };
+
+        // storing the code is paid by the byte
+        let module = parse_code(&code)?;
+        if entry == "init" {
+            gas.charge(code.len() as u64 / 2)
+                .map_err(ContractError::Execution)?;
+        }
+
+        let mut host = ContractHost {
+            storage,
+            input: action.input()?,
+            sender: transaction.sender.clone(),
+            amount: transaction.amount,
+            output: Vec::new(),
+        };
+        if entry != "init" || module.has_export(entry) {
+            vm::execute(&module, entry, &mut host, gas).map_err(ContractError::Execution)?;
+        }
+
+        self.0.insert(
+            address.clone(),
+            Contract {
+                code,
+                storage: host.storage,
+            },
+        );
+        Ok(host.output)
+    }
+
+    // Hash of all the contracts, every field is prefixed by its length like in the balances
+    pub fn root(&self) -> BlockHash {
+        let mut hasher = Sha256::new();
+        let mut input = |bytes: &[u8]| {
+            hasher.input(&(bytes.len() as u64).to_be_bytes());
+            hasher.input(bytes);
+        };
+        for (address, contract) in &self.0 {
+            input(address.as_bytes());
+            input(contract.code.as_bytes());
+            input(&(contract.storage.len() as u64).to_be_bytes());
+            for (key, value) in &contract.storage {
+                input(key.as_bytes());
+                input(value.as_bytes());
+            }
+        }
+
+        let mut root = [0; 32];
+        hasher.result(&mut root);
+        BlockHash::from(root)
+    }
+}
+
+// What a contract can do while running, through the functions it imports from "env"
+// Pointers and lengths are i32 values, reading or writing out of the memory is a trap
+struct ContractHost {
+    storage: BTreeMap<String, String>,
+    input: Vec<u8>,
+    sender: String,
+    amount: u64,
+    output: Vec<u8>,
+}
+
+impl ContractHost {
+    // Copies as much of the data as fits in the buffer, returning the whole length
+    fn write_buffer(
+        memory: &mut Memory,
+        data: &[u8],
+        pointer: u64,
+        capacity: u64,
+        gas: &mut Gas,
+    ) -> Result<u64, VmError> {
+        let length = data.len().min(capacity as usize);
+        gas.charge_bytes(length as u64)?;
+        memory.write(pointer, &data[..length])?;
+
+        Ok(data.len() as u64)
+    }
+
+    fn read_key(memory: &Memory, pointer: u64, length: u64) -> Result<String, VmError> {
+        if length > MAX_KEY_SIZE {
+            return Err(VmError::Trap("storage key too large".to_string()));
+        }
+
+        Ok(hex::encode(memory.read(pointer, length)?))
+    }
+}
+
+impl Host for ContractHost {
+    fn signature(&self, name: &str) -> Option<FuncType> {
+        use ValueType::{I32, I64};
+        let (params, results) = match name {
+            "input_size" => (vec![], vec![I32]),
+            "input_read" => (vec![I32], vec![]),
+            "output" => (vec![I32, I32], vec![]),
+            "sender" => (vec![I32, I32], vec![I32]),
+            "amount" => (vec![], vec![I64]),
+            "storage_read" => (vec![I32, I32, I32, I32], vec![I32]),
+            "storage_write" => (vec![I32, I32, I32, I32], vec![]),
+            "storage_remove" => (vec![I32, I32], vec![]),
+            _ => return None,
+        };
+
+        Some(FuncType { params, results })
+    }
+
+    fn call(
+        &mut self,
+        name: &str,
+        args: &[u64],
+        memory: &mut Memory,
+        gas: &mut Gas,
+    ) -> Result<Option<u64>, VmError> {
+        gas.charge(HOST_CALL_GAS)?;
+        // every argument is an i32, and the signature was checked when instantiating the module
+        let arg = |index: usize| u64::from(args[index] as u32);
+
+        match name {
+            "input_size" => Ok(Some(self.input.len() as u64)),
+            "input_read" => {
+                let length = self.input.len() as u64;
+                Self::write_buffer(memory, &self.input, arg(0), length, gas)?;
+                Ok(None)
+            }
+            "output" => {
+                gas.charge_bytes(arg(1))?;
+                self.output = memory.read(arg(0), arg(1))?.to_vec();
+                Ok(None)
+            }
+            "sender" => {
+                let sender = self.sender.as_bytes();
+                Ok(Some(Self::write_buffer(
+                    memory,
+                    sender,
+                    arg(0),
+                    arg(1),
+                    gas,
+                )?))
+            }
+            "amount" => Ok(Some(self.amount)),
+            "storage_read" => {
+                let key = Self::read_key(memory, arg(0), arg(1))?;
+                match self.storage.get(&key) {
+                    Some(value) => {
+                        let value = hex::decode(value).unwrap_or_default();
+                        Ok(Some(Self::write_buffer(
+                            memory,
+                            &value,
+                            arg(2),
+                            arg(3),
+                            gas,
+                        )?))
+                    }
+                    // -1 as an i32
+                    None => Ok(Some(u64::from(u32::MAX))),
+                }
+            }
+            "storage_write" => {
+                let key = Self::read_key(memory, arg(0), arg(1))?;
+                if arg(3) > MAX_VALUE_SIZE {
+                    return Err(VmError::Trap("storage value too large".to_string()));
+                }
+                gas.charge(STORAGE_WRITE_GAS + arg(1) + arg(3))?;
+                let value = hex::encode(memory.read(arg(2), arg(3))?);
+                self.storage.insert(key, value);
+                Ok(None)
+            }
+            "storage_remove" => {
+                let key = Self::read_key(memory, arg(0), arg(1))?;
+                self.storage.remove(&key);
+                Ok(None)
+            }
+            _ => Err(VmError::UnknownImport(name.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A counter: "call" adds 1 to the 8 bytes under the key "count" and returns them
+    // (module (import "env" "storage_read" ...) (import "env" "storage_write" ...)
+    //   (import "env" "output" ...) (memory 1) (data (i32.const 0) "count")
+    //   (func (export "call") ...))
+    const COUNTER: &str = "0061736d0100000001180460047f7f7f7f017f60047f7f7f7f0060027f7f00600000\
+        02350303656e760c73746f726167655f72656164000003656e760d73746f726167655f7772697465000103\
+        656e76066f75747075740002030201030503010001071102066d656d6f727902000463616c6c00030a2c01\
+        2a00410041054110410810001a4110411029030042017c370300410041054110410810014110410810020b\
+        0b0b010041000b05636f756e74";
+
+    #[test]
+    fn should_deploy_and_call_contracts() {
+        let mut contracts = ContractState::default();
+        let deploy = create_transaction("counter", deploy_action(COUNTER));
+        let receipts = contracts.apply(std::slice::from_ref(&deploy));
+        assert!(receipts[&deploy.calculate_id()].success);
+        let empty_root = contracts.root();
+
+        let first_call = create_transaction("counter", call_action(100_000));
+        let mut second_call = first_call.clone();
+        second_call.amount += 1;
+        let receipts = contracts.apply(&[first_call.clone(), second_call.clone()]);
+
+        let receipt = &receipts[&first_call.calculate_id()];
+        assert!(receipt.success);
+        assert_eq!(receipt.output, "0100000000000000");
+        assert!(receipt.gas_used > 0);
+        assert_eq!(
+            receipts[&second_call.calculate_id()].output,
+            "0200000000000000"
+        );
+
+        let contract = contracts.get("counter").unwrap();
+        assert_eq!(contract.code, COUNTER);
+        assert_eq!(contract.storage[&hex::encode("count")], "0200000000000000");
+        assert_ne!(contracts.root(), empty_root);
+    }
+
+    #[test]
+    fn should_not_change_contracts_when_failing() {
+        let mut contracts = ContractState::default();
+        contracts.apply(&[create_transaction("counter", deploy_action(COUNTER))]);
+        let root = contracts.root();
+
+        let failures = [
+            (
+                create_transaction("counter", deploy_action(COUNTER)),
+                "There is already a contract at counter",
+            ),
+            (
+                create_transaction("nobody", call_action(100_000)),
+                "There is no contract at nobody",
+            ),
+            (create_transaction("counter", call_action(10)), "Out of gas"),
+        ];
+        for (transaction, error) in failures.iter() {
+            let receipts = contracts.apply(std::slice::from_ref(transaction));
+            let receipt = &receipts[&transaction.calculate_id()];
+            assert!(!receipt.success);
+            assert_eq!(receipt.error.as_deref(), Some(*error));
+        }
+        assert_eq!(contracts.root(), root);
+    }
+
+    #[test]
+    fn should_check_actions() {
+        assert_eq!(
+            call_action(MAX_GAS_LIMIT + 1).check(),
+            Err(ContractError::GasLimitTooHigh(MAX_GAS_LIMIT))
+        );
+        assert_eq!(
+            deploy_action("not hex").check(),
+            Err(ContractError::InvalidHex("code"))
+        );
+        assert!(matches!(
+            deploy_action("00").check(),
+            Err(ContractError::InvalidCode(_))
+        ));
+        assert_eq!(deploy_action(COUNTER).check(), Ok(()));
+    }
+
+    fn deploy_action(code: &str) -> ContractAction {
+        ContractAction::Deploy {
+            code: code.to_string(),
+            input: String::new(),
+            gas_limit: 100_000,
+        }
+    }
+
+    fn call_action(gas_limit: u64) -> ContractAction {
+        ContractAction::Call {
+            input: String::new(),
+            gas_limit,
+        }
+    }
+
+    fn create_transaction(recipient: &str, action: ContractAction) -> Transaction {
+        Transaction {
+            sender: "alice".to_string(),
+            recipient: recipient.to_string(),
+            amount: 0,
+            signature: None,
+            multisig: None,
+            contract: Some(action),
+            token: None,
+            data: None,
+        }
+    }
+}
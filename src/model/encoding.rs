@@ -0,0 +1,680 @@
+use std::{collections::BTreeMap, convert::TryInto};
+
+use ethereum_types::U256;
+use thiserror::Error;
+
+use super::{
+    AddressBloom, Block, BlockHeader, ContractAction, MerkleProof, MultiSig, TokenAction,
+    Transaction, TransactionProof,
+};
+
+// Version of the binary encoding, written as the first byte of every encoded value
+// It must be increased on every incompatible change, values with other versions are rejected
+// Version 2 added the data of the transactions
+pub const ENCODING_VERSION: u8 = 2;
+
+// Oldest version that can still be decoded, e.g. in the blocks stored by older versions of the node
+const MIN_ENCODING_VERSION: u8 = 1;
+
+// Longest list or string accepted when decoding, so a corrupted length can't make us allocate gigabytes
+const MAX_LENGTH: u64 = 16 * 1024 * 1024;
+
+// Most memory reserved for a list before decoding its items, the rest is only taken as they decode
+// Items in memory can be much bigger than encoded (e.g. blocks), so the length alone can't tell
+const MAX_PREALLOCATION: usize = 64 * 1024;
+
+// Error types to return when some bytes are not a valid encoded value
+#[derive(Error, PartialEq, Debug)]
+pub enum EncodingError {
+    #[error("Unsupported encoding version {0}")]
+    UnsupportedVersion(u8),
+
+    #[error("Unexpected end of the data")]
+    UnexpectedEnd,
+
+    #[error("Invalid tag {1} for {0}")]
+    InvalidTag(&'static str, u8),
+
+    #[error("Length {0} is too large")]
+    LengthTooLarge(u64),
+
+    #[error("Invalid UTF-8 string")]
+    InvalidString,
+
+    #[error("{0} unexpected bytes after the value")]
+    TrailingBytes(usize),
+}
+
+// Compact binary representation of the values that nodes store and send to each other
+// Numbers are big-endian, lengths are varints, and optional values and enums start with a tag byte
+// The api keeps using JSON, which is more convenient for clients
+pub trait Encode {
+    fn encode(&self, out: &mut Vec<u8>);
+}
+
+pub trait Decode: Sized {
+    fn decode(reader: &mut Reader) -> Result<Self, EncodingError>;
+}
+
+// Encodes a value, preceded by the version of the encoding
+pub fn to_bytes<T: Encode>(value: &T) -> Vec<u8> {
+    let mut out = vec![ENCODING_VERSION];
+    value.encode(&mut out);
+    out
+}
+
+// Decodes a value encoded with "to_bytes", which must take all the bytes
+pub fn from_bytes<T: Decode>(bytes: &[u8]) -> Result<T, EncodingError> {
+    let mut reader = Reader {
+        bytes,
+        position: 0,
+        version: ENCODING_VERSION,
+    };
+    let version = u8::decode(&mut reader)?;
+    if !(MIN_ENCODING_VERSION..=ENCODING_VERSION).contains(&version) {
+        return Err(EncodingError::UnsupportedVersion(version));
+    }
+    // values of older versions are decoded without the fields added after them
+    reader.version = version;
+
+    let value = T::decode(&mut reader)?;
+    match bytes.len() - reader.position {
+        0 => Ok(value),
+        remaining => Err(EncodingError::TrailingBytes(remaining)),
+    }
+}
+
+// Position in the bytes being decoded
+#[derive(Debug)]
+pub struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    version: u8,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], EncodingError> {
+        if self.bytes.len() - self.position < count {
+            return Err(EncodingError::UnexpectedEnd);
+        }
+
+        let taken = &self.bytes[self.position..self.position + count];
+        self.position += count;
+        Ok(taken)
+    }
+
+    // Reads a tag byte, which must be lower than "count"
+    pub fn tag(&mut self, name: &'static str, count: u8) -> Result<u8, EncodingError> {
+        let tag = u8::decode(self)?;
+        match tag < count {
+            true => Ok(tag),
+            false => Err(EncodingError::InvalidTag(name, tag)),
+        }
+    }
+
+    fn length(&mut self) -> Result<usize, EncodingError> {
+        let length = read_varint(self)?;
+        match length <= MAX_LENGTH {
+            true => Ok(length as usize),
+            false => Err(EncodingError::LengthTooLarge(length)),
+        }
+    }
+}
+
+// Unsigned LEB128: 7 bits per byte, the highest bit is set in all the bytes except the last one
+fn write_varint(mut value: u64, out: &mut Vec<u8>) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(reader: &mut Reader) -> Result<u64, EncodingError> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = u8::decode(reader)?;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+
+    Err(EncodingError::LengthTooLarge(value))
+}
+
+impl Encode for u8 {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.push(*self);
+    }
+}
+
+impl Decode for u8 {
+    fn decode(reader: &mut Reader) -> Result<Self, EncodingError> {
+        Ok(reader.take(1)?[0])
+    }
+}
+
+impl Encode for bool {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.push(*self as u8);
+    }
+}
+
+impl Decode for bool {
+    fn decode(reader: &mut Reader) -> Result<Self, EncodingError> {
+        Ok(reader.tag("bool", 2)? == 1)
+    }
+}
+
+// Fixed-size integers, in big-endian
+macro_rules! encode_integer {
+    ($($type:ty),*) => {
+        $(
+            impl Encode for $type {
+                fn encode(&self, out: &mut Vec<u8>) {
+                    out.extend_from_slice(&self.to_be_bytes());
+                }
+            }
+
+            impl Decode for $type {
+                fn decode(reader: &mut Reader) -> Result<Self, EncodingError> {
+                    let bytes = reader.take(std::mem::size_of::<$type>())?;
+                    Ok(<$type>::from_be_bytes(bytes.try_into().unwrap()))
+                }
+            }
+        )*
+    };
+}
+
+encode_integer!(u16, u32, u64, i64);
+
+// Sizes and positions depend on the platform, so they are varints instead
+impl Encode for usize {
+    fn encode(&self, out: &mut Vec<u8>) {
+        write_varint(*self as u64, out);
+    }
+}
+
+impl Decode for usize {
+    fn decode(reader: &mut Reader) -> Result<Self, EncodingError> {
+        Ok(read_varint(reader)? as usize)
+    }
+}
+
+impl Encode for U256 {
+    fn encode(&self, out: &mut Vec<u8>) {
+        let mut bytes = [0; 32];
+        self.to_big_endian(&mut bytes);
+        out.extend_from_slice(&bytes);
+    }
+}
+
+impl Decode for U256 {
+    fn decode(reader: &mut Reader) -> Result<Self, EncodingError> {
+        Ok(U256::from_big_endian(reader.take(32)?))
+    }
+}
+
+// Most blocks don't have transactions, and their empty filter only takes a byte
+impl Encode for AddressBloom {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self.is_zero() {
+            true => out.push(0),
+            false => {
+                out.push(1);
+                out.extend_from_slice(self.as_bytes());
+            }
+        }
+    }
+}
+
+impl Decode for AddressBloom {
+    fn decode(reader: &mut Reader) -> Result<Self, EncodingError> {
+        match reader.tag("bloom", 2)? {
+            0 => Ok(AddressBloom::default()),
+            _ => Ok(AddressBloom::from_slice(reader.take(256)?)),
+        }
+    }
+}
+
+impl Encode for String {
+    fn encode(&self, out: &mut Vec<u8>) {
+        write_varint(self.len() as u64, out);
+        out.extend_from_slice(self.as_bytes());
+    }
+}
+
+impl Decode for String {
+    fn decode(reader: &mut Reader) -> Result<Self, EncodingError> {
+        let length = reader.length()?;
+        let bytes = reader.take(length)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| EncodingError::InvalidString)
+    }
+}
+
+impl<T: Encode> Encode for Option<T> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            None => out.push(0),
+            Some(value) => {
+                out.push(1);
+                value.encode(out);
+            }
+        }
+    }
+}
+
+impl<T: Decode> Decode for Option<T> {
+    fn decode(reader: &mut Reader) -> Result<Self, EncodingError> {
+        match reader.tag("option", 2)? {
+            0 => Ok(None),
+            _ => Ok(Some(T::decode(reader)?)),
+        }
+    }
+}
+
+impl<T: Encode> Encode for Vec<T> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        write_varint(self.len() as u64, out);
+        for item in self {
+            item.encode(out);
+        }
+    }
+}
+
+impl<T: Decode> Decode for Vec<T> {
+    fn decode(reader: &mut Reader) -> Result<Self, EncodingError> {
+        let length = reader.length()?;
+        let mut items = Vec::with_capacity(preallocation::<T>(length, reader));
+        for _ in 0..length {
+            items.push(T::decode(reader)?);
+        }
+        Ok(items)
+    }
+}
+
+// Number of items to reserve for a list of "length" items
+// Every item takes at least a byte, so the length can't be trusted beyond the remaining bytes
+fn preallocation<T>(length: usize, reader: &Reader) -> usize {
+    let max_items = MAX_PREALLOCATION / std::mem::size_of::<T>().max(1);
+    length
+        .min(reader.bytes.len() - reader.position)
+        .min(max_items)
+}
+
+impl Encode for BTreeMap<String, String> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        write_varint(self.len() as u64, out);
+        for (key, value) in self {
+            key.encode(out);
+            value.encode(out);
+        }
+    }
+}
+
+impl Decode for BTreeMap<String, String> {
+    fn decode(reader: &mut Reader) -> Result<Self, EncodingError> {
+        let length = reader.length()?;
+        let mut map = BTreeMap::new();
+        for _ in 0..length {
+            map.insert(String::decode(reader)?, String::decode(reader)?);
+        }
+        Ok(map)
+    }
+}
+
+impl Encode for BlockHeader {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.index.encode(out);
+        self.timestamp.encode(out);
+        self.nonce.encode(out);
+        self.bits.encode(out);
+        self.previous_hash.encode(out);
+        self.merkle_root.encode(out);
+        self.state_root.encode(out);
+        self.bloom.encode(out);
+        self.hash.encode(out);
+    }
+}
+
+impl Decode for BlockHeader {
+    fn decode(reader: &mut Reader) -> Result<Self, EncodingError> {
+        Ok(BlockHeader {
+            index: Decode::decode(reader)?,
+            timestamp: Decode::decode(reader)?,
+            nonce: Decode::decode(reader)?,
+            bits: Decode::decode(reader)?,
+            previous_hash: Decode::decode(reader)?,
+            merkle_root: Decode::decode(reader)?,
+            state_root: Decode::decode(reader)?,
+            bloom: Decode::decode(reader)?,
+            hash: Decode::decode(reader)?,
+        })
+    }
+}
+
+impl Encode for Block {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.header.encode(out);
+        self.transactions.encode(out);
+        self.signature.encode(out);
+        self.pruned.encode(out);
+    }
+}
+
+impl Decode for Block {
+    fn decode(reader: &mut Reader) -> Result<Self, EncodingError> {
+        Ok(Block {
+            header: Decode::decode(reader)?,
+            transactions: Decode::decode(reader)?,
+            signature: Decode::decode(reader)?,
+            pruned: Decode::decode(reader)?,
+        })
+    }
+}
+
+impl Encode for Transaction {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.sender.encode(out);
+        self.recipient.encode(out);
+        self.amount.encode(out);
+        self.signature.encode(out);
+        self.multisig.encode(out);
+        self.contract.encode(out);
+        self.token.encode(out);
+        self.data.encode(out);
+    }
+}
+
+impl Decode for Transaction {
+    fn decode(reader: &mut Reader) -> Result<Self, EncodingError> {
+        Ok(Transaction {
+            sender: Decode::decode(reader)?,
+            recipient: Decode::decode(reader)?,
+            amount: Decode::decode(reader)?,
+            signature: Decode::decode(reader)?,
+            multisig: Decode::decode(reader)?,
+            contract: Decode::decode(reader)?,
+            token: Decode::decode(reader)?,
+            data: match reader.version {
+                1 => None,
+                _ => Decode::decode(reader)?,
+            },
+        })
+    }
+}
+
+impl Encode for MultiSig {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.threshold.encode(out);
+        self.public_keys.encode(out);
+        self.signatures.encode(out);
+    }
+}
+
+impl Decode for MultiSig {
+    fn decode(reader: &mut Reader) -> Result<Self, EncodingError> {
+        Ok(MultiSig {
+            threshold: Decode::decode(reader)?,
+            public_keys: Decode::decode(reader)?,
+            signatures: Decode::decode(reader)?,
+        })
+    }
+}
+
+impl Encode for ContractAction {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            ContractAction::Deploy {
+                code,
+                input,
+                gas_limit,
+            } => {
+                out.push(0);
+                code.encode(out);
+                input.encode(out);
+                gas_limit.encode(out);
+            }
+            ContractAction::Call { input, gas_limit } => {
+                out.push(1);
+                input.encode(out);
+                gas_limit.encode(out);
+            }
+        }
+    }
+}
+
+impl Decode for ContractAction {
+    fn decode(reader: &mut Reader) -> Result<Self, EncodingError> {
+        match reader.tag("contract action", 2)? {
+            0 => Ok(ContractAction::Deploy {
+                code: Decode::decode(reader)?,
+                input: Decode::decode(reader)?,
+                gas_limit: Decode::decode(reader)?,
+            }),
+            _ => Ok(ContractAction::Call {
+                input: Decode::decode(reader)?,
+                gas_limit: Decode::decode(reader)?,
+            }),
+        }
+    }
+}
+
+impl Encode for TokenAction {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            TokenAction::Issue { name, supply } => {
+                out.push(0);
+                name.encode(out);
+                supply.encode(out);
+            }
+            TokenAction::Transfer { token_id, amount } => {
+                out.push(1);
+                token_id.encode(out);
+                amount.encode(out);
+            }
+        }
+    }
+}
+
+impl Decode for TokenAction {
+    fn decode(reader: &mut Reader) -> Result<Self, EncodingError> {
+        match reader.tag("token action", 2)? {
+            0 => Ok(TokenAction::Issue {
+                name: Decode::decode(reader)?,
+                supply: Decode::decode(reader)?,
+            }),
+            _ => Ok(TokenAction::Transfer {
+                token_id: Decode::decode(reader)?,
+                amount: Decode::decode(reader)?,
+            }),
+        }
+    }
+}
+
+impl Encode for MerkleProof {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.index.encode(out);
+        self.count.encode(out);
+        self.hashes.encode(out);
+    }
+}
+
+impl Decode for MerkleProof {
+    fn decode(reader: &mut Reader) -> Result<Self, EncodingError> {
+        Ok(MerkleProof {
+            index: Decode::decode(reader)?,
+            count: Decode::decode(reader)?,
+            hashes: Decode::decode(reader)?,
+        })
+    }
+}
+
+impl Encode for TransactionProof {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.transaction_id.encode(out);
+        self.block_index.encode(out);
+        self.block_hash.encode(out);
+        self.proof.encode(out);
+    }
+}
+
+impl Decode for TransactionProof {
+    fn decode(reader: &mut Reader) -> Result<Self, EncodingError> {
+        Ok(TransactionProof {
+            transaction_id: Decode::decode(reader)?,
+            block_index: Decode::decode(reader)?,
+            block_hash: Decode::decode(reader)?,
+            proof: Decode::decode(reader)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::BlockHash;
+
+    #[test]
+    fn should_roundtrip_blocks() {
+        let mut multisig_transaction = create_transaction("1", "2", 3);
+        multisig_transaction.multisig = Some(MultiSig {
+            threshold: 1,
+            public_keys: vec!["a".to_string(), "b".to_string()],
+            signatures: vec![("a".to_string(), "c".to_string())]
+                .into_iter()
+                .collect(),
+        });
+        let mut contract_transaction = create_transaction("2", "3", 0);
+        contract_transaction.contract = Some(ContractAction::Deploy {
+            code: "0061736d".to_string(),
+            input: String::new(),
+            gas_limit: 100,
+        });
+        contract_transaction.signature = Some("ff".to_string());
+        let mut token_transaction = create_transaction("3", "ü", 1);
+        token_transaction.token = Some(TokenAction::Transfer {
+            token_id: BlockHash::from(7),
+            amount: 5,
+        });
+        token_transaction.data = Some(vec![0, 1, 2]);
+        let transactions = vec![
+            multisig_transaction,
+            contract_transaction,
+            token_transaction,
+        ];
+        let mut block = Block::new(1, 42, BlockHash::from(9), transactions);
+        block.signature = Some("abcd".to_string());
+
+        let bytes = to_bytes(&block);
+        assert_eq!(bytes[0], ENCODING_VERSION);
+        let decoded: Block = from_bytes(&bytes).unwrap();
+
+        // the values are the same, as their json shows
+        assert_eq!(
+            serde_json::to_value(&decoded).unwrap(),
+            serde_json::to_value(&block).unwrap()
+        );
+        assert_eq!(decoded.calculate_hash(), block.header.hash);
+        assert_eq!(decoded.calculate_merkle_root(), block.header.merkle_root);
+
+        // and they take much less space
+        assert!(bytes.len() * 2 < serde_json::to_vec(&block).unwrap().len());
+    }
+
+    #[test]
+    fn should_encode_lengths_as_varints() {
+        for value in [0u64, 1, 127, 128, 300, u32::MAX as u64, u64::MAX] {
+            let mut out = Vec::new();
+            write_varint(value, &mut out);
+            let mut reader = Reader {
+                bytes: &out,
+                position: 0,
+                version: ENCODING_VERSION,
+            };
+            assert_eq!(read_varint(&mut reader).unwrap(), value);
+            assert_eq!(reader.position, out.len());
+        }
+
+        let mut out = Vec::new();
+        write_varint(300, &mut out);
+        assert_eq!(out, vec![0xac, 0x02]);
+    }
+
+    #[test]
+    fn should_decode_transactions_of_older_versions() {
+        // version 1 transactions end right after the token
+        let transaction = create_transaction("1", "2", 3);
+        let mut bytes = to_bytes(&transaction);
+        bytes.pop();
+        bytes[0] = 1;
+
+        let decoded: Transaction = from_bytes(&bytes).unwrap();
+        assert_eq!(decoded.calculate_id(), transaction.calculate_id());
+        assert_eq!(decoded.data, None);
+    }
+
+    #[test]
+    fn should_reject_invalid_bytes() {
+        let transaction = create_transaction("1", "2", 3);
+        let mut bytes = to_bytes(&transaction);
+
+        assert_eq!(
+            from_bytes::<Transaction>(&bytes[..bytes.len() - 1]).unwrap_err(),
+            EncodingError::UnexpectedEnd
+        );
+
+        bytes.push(0);
+        assert_eq!(
+            from_bytes::<Transaction>(&bytes).unwrap_err(),
+            EncodingError::TrailingBytes(1)
+        );
+
+        bytes[0] = ENCODING_VERSION + 1;
+        assert_eq!(
+            from_bytes::<Transaction>(&bytes).unwrap_err(),
+            EncodingError::UnsupportedVersion(ENCODING_VERSION + 1)
+        );
+
+        // a huge length doesn't allocate anything
+        let mut bytes = vec![ENCODING_VERSION];
+        write_varint(u64::MAX, &mut bytes);
+        assert_eq!(
+            from_bytes::<Vec<Block>>(&bytes).unwrap_err(),
+            EncodingError::LengthTooLarge(u64::MAX)
+        );
+
+        // nor does a length that the remaining bytes could hold, if the items are much bigger in memory
+        let mut bytes = vec![ENCODING_VERSION];
+        write_varint(MAX_LENGTH, &mut bytes);
+        bytes.resize(bytes.len() + 1024 * 1024, 0);
+        let reader = Reader {
+            bytes: &bytes,
+            position: 0,
+            version: ENCODING_VERSION,
+        };
+        let items = preallocation::<Block>(MAX_LENGTH as usize, &reader);
+        assert!(items * std::mem::size_of::<Block>() <= MAX_PREALLOCATION);
+        assert!(from_bytes::<Vec<Block>>(&bytes).is_err());
+
+        let bytes = vec![ENCODING_VERSION, 2];
+        assert_eq!(
+            from_bytes::<Option<u8>>(&bytes).unwrap_err(),
+            EncodingError::InvalidTag("option", 2)
+        );
+    }
+
+    fn create_transaction(sender: &str, recipient: &str, amount: u64) -> Transaction {
+        Transaction {
+            sender: sender.to_string(),
+            recipient: recipient.to_string(),
+            amount,
+            signature: None,
+            multisig: None,
+            contract: None,
+            token: None,
+            data: None,
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use std::{
+    sync::{
+        mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError},
+        Arc,
+    },
+    time::Duration,
+};
+
+use serde::Serialize;
+
+use super::{hash_hex, Block, BlockHash, Transaction};
+use crate::util::Lock;
+
+// Max number of events waiting to be read by each subscriber
+// Publishing never blocks the chain, so a subscriber that falls this far behind misses the newer events
+const MAX_PENDING_EVENTS: usize = 1024;
+
+// What happens in the chain, in the order it happens
+#[derive(Debug, Clone)]
+pub enum ChainEvent {
+    // A block was appended to the chain, by us, a peer or the api
+    BlockAdded(Arc<Block>),
+    // A transaction entered the pool
+    TransactionAdmitted(Arc<Transaction>),
+    // The blocks after "fork_index" were replaced by the ones of another branch,
+    // which are then published as added blocks
+    Reorg(ReorgEvent),
+}
+
+// Which blocks a reorg rolled back and which ones replaced them, so consumers that keep
+// their own view of the chain (indexers, wallets...) know exactly what to undo
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ReorgEvent {
+    pub fork_index: u64,
+    // The blocks that are no longer in the chain, from the oldest to the old tip
+    #[serde(with = "hash_hex::vec")]
+    pub reverted: Vec<BlockHash>,
+    // The blocks of the new branch, from the oldest to the new tip
+    #[serde(with = "hash_hex::vec")]
+    pub applied: Vec<BlockHash>,
+}
+
+// Publishes the events of the chain to every component that subscribed to them,
+// so they don't need to keep checking the blockchain or the pool for changes
+// Clones share the same subscribers
+#[derive(Debug, Clone, Default)]
+pub struct EventBus {
+    subscribers: Arc<Lock<Vec<SyncSender<ChainEvent>>>>,
+}
+
+// Receives the events published after subscribing, each subscriber gets all of them
+#[derive(Debug)]
+pub struct EventReceiver {
+    receiver: Receiver<ChainEvent>,
+}
+
+impl EventBus {
+    pub fn new() -> EventBus {
+        EventBus::default()
+    }
+
+    pub fn subscribe(&self) -> EventReceiver {
+        let (sender, receiver) = mpsc::sync_channel(MAX_PENDING_EVENTS);
+        self.subscribers.lock().push(sender);
+
+        EventReceiver { receiver }
+    }
+
+    // Sends the event to every subscriber, forgetting the ones that are gone
+    pub fn publish(&self, event: ChainEvent) {
+        let mut subscribers = self.subscribers.lock();
+        subscribers.retain(|subscriber| match subscriber.try_send(event.clone()) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                debug!("dropping chain event for a subscriber that fell behind");
+                true
+            }
+            Err(TrySendError::Disconnected(_)) => false,
+        });
+    }
+}
+
+impl EventReceiver {
+    // Returns the events published since the last call, without waiting for new ones
+    pub fn pending(&self) -> Vec<ChainEvent> {
+        self.receiver.try_iter().collect()
+    }
+
+    // Waits for the next event, up to "timeout"
+    pub fn wait(&self, timeout: Duration) -> Option<ChainEvent> {
+        match self.receiver.recv_timeout(timeout) {
+            Ok(event) => Some(event),
+            Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_deliver_events_to_every_subscriber() {
+        let bus = EventBus::new();
+        let first = bus.subscribe();
+        let second = bus.clone().subscribe();
+
+        bus.publish(ChainEvent::BlockAdded(Arc::new(Block::new(
+            1,
+            0,
+            BlockHash::default(),
+            Vec::new(),
+        ))));
+        assert_eq!(first.pending().len(), 1);
+        assert_eq!(second.pending().len(), 1);
+
+        // events are only received once, and only the ones published after subscribing
+        assert!(first.pending().is_empty());
+        let third = bus.subscribe();
+        assert!(third.wait(Duration::from_millis(1)).is_none());
+    }
+
+    #[test]
+    fn should_not_block_on_subscribers_that_fell_behind() {
+        let bus = EventBus::new();
+        let receiver = bus.subscribe();
+        let gone = bus.subscribe();
+        drop(gone);
+
+        for _ in 0..MAX_PENDING_EVENTS + 10 {
+            bus.publish(ChainEvent::Reorg(ReorgEvent {
+                fork_index: 0,
+                reverted: vec![BlockHash::default()],
+                applied: vec![BlockHash::default()],
+            }));
+        }
+        assert_eq!(receiver.pending().len(), MAX_PENDING_EVENTS);
+        assert_eq!(bus.subscribers.lock().len(), 1);
+    }
+}
@@ -0,0 +1,192 @@
+use std::collections::BTreeMap;
+
+use ethereum_types::U256;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+// Hashes, ids and roots are shown in json as their 64 lowercase hex digits, with leading zeros and
+// without prefix, so they can be copied between the api, the logs and other tools as they are.
+// When reading them, a "0x" prefix, uppercase digits and missing leading zeros are also accepted,
+// which is how older versions of the node wrote them (e.g. in stored blocks and snapshots).
+//
+// Fields use it with `#[serde(with = "hash_hex")]`, or with the module for their container type
+
+pub fn to_hex(hash: &U256) -> String {
+    let mut bytes = [0; 32];
+    hash.to_big_endian(&mut bytes);
+    hex::encode(bytes)
+}
+
+pub fn parse_hex(value: &str) -> Option<U256> {
+    let digits = value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+        .unwrap_or(value);
+    if digits.is_empty() || digits.len() > 64 {
+        return None;
+    }
+
+    let bytes = hex::decode(format!("{:0>64}", digits)).ok()?;
+    Some(U256::from_big_endian(&bytes))
+}
+
+pub fn serialize<S: Serializer>(hash: &U256, serializer: S) -> Result<S::Ok, S::Error> {
+    Hex(*hash).serialize(serializer)
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<U256, D::Error> {
+    Ok(Hex::deserialize(deserializer)?.0)
+}
+
+// Hashes as strings wherever serde expects a value, including keys of maps
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+struct Hex(U256);
+
+impl Serialize for Hex {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&to_hex(&self.0))
+    }
+}
+
+impl<'de> Deserialize<'de> for Hex {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Hex, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        parse_hex(&value)
+            .map(Hex)
+            .ok_or_else(|| de::Error::custom(format!("invalid hash `{}`", value)))
+    }
+}
+
+pub mod vec {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(hashes: &[U256], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(hashes.iter().map(|hash| Hex(*hash)))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<U256>, D::Error> {
+        let hashes = Vec::<Hex>::deserialize(deserializer)?;
+        Ok(hashes.into_iter().map(|hex| hex.0).collect())
+    }
+}
+
+// Maps by hash (e.g. by transaction or token id)
+pub mod keys {
+    use super::*;
+
+    pub fn serialize<S, V>(map: &BTreeMap<U256, V>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+        V: Serialize,
+    {
+        serializer.collect_map(map.iter().map(|(hash, value)| (Hex(*hash), value)))
+    }
+
+    pub fn deserialize<'de, D, V>(deserializer: D) -> Result<BTreeMap<U256, V>, D::Error>
+    where
+        D: Deserializer<'de>,
+        V: Deserialize<'de>,
+    {
+        let map = BTreeMap::<Hex, V>::deserialize(deserializer)?;
+        Ok(map.into_iter().map(|(hex, value)| (hex.0, value)).collect())
+    }
+}
+
+// Maps by address of maps by hash (e.g. the balances of every address in each token)
+pub mod nested_keys {
+    use super::*;
+
+    type NestedMap<V> = BTreeMap<String, BTreeMap<U256, V>>;
+
+    pub fn serialize<S, V>(map: &NestedMap<V>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+        V: Serialize,
+    {
+        serializer.collect_map(map.iter().map(|(key, inner)| {
+            let inner: BTreeMap<Hex, &V> = inner
+                .iter()
+                .map(|(hash, value)| (Hex(*hash), value))
+                .collect();
+            (key, inner)
+        }))
+    }
+
+    pub fn deserialize<'de, D, V>(deserializer: D) -> Result<NestedMap<V>, D::Error>
+    where
+        D: Deserializer<'de>,
+        V: Deserialize<'de>,
+    {
+        let map = BTreeMap::<String, BTreeMap<Hex, V>>::deserialize(deserializer)?;
+        Ok(map
+            .into_iter()
+            .map(|(key, inner)| {
+                let inner = inner.into_iter().map(|(hex, value)| (hex.0, value));
+                (key, inner.collect())
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Hashes {
+        #[serde(with = "super")]
+        hash: U256,
+        #[serde(with = "super::vec")]
+        parents: Vec<U256>,
+        #[serde(with = "super::keys")]
+        heights: BTreeMap<U256, u64>,
+    }
+
+    #[test]
+    fn should_serialize_hashes_as_lowercase_hex() {
+        let hashes = Hashes {
+            hash: U256::from(0xab),
+            parents: Vec::new(),
+            heights: vec![(U256::from(1), 2)].into_iter().collect(),
+        };
+        let zeros = "0".repeat(62);
+
+        assert_eq!(
+            serde_json::to_value(&hashes).unwrap(),
+            serde_json::json!({
+                "hash": format!("{}ab", zeros),
+                "parents": [],
+                "heights": {format!("{}01", zeros): 2},
+            })
+        );
+    }
+
+    #[test]
+    fn should_accept_hashes_in_other_formats() {
+        let expected = U256::from(0xab);
+        for value in ["ab", "0xab", "0xAB", &format!("{:0>64}", "AB")] {
+            assert_eq!(parse_hex(value), Some(expected));
+        }
+
+        // old nodes wrote hashes with a prefix and without leading zeros
+        let hashes: Hashes =
+            serde_json::from_str(r#"{"hash": "0xab", "parents": ["0x1"], "heights": {"0x1": 2}}"#)
+                .unwrap();
+        assert_eq!(hashes.hash, expected);
+        assert_eq!(hashes.parents, vec![U256::from(1)]);
+        assert_eq!(hashes.heights[&U256::from(1)], 2);
+    }
+
+    #[test]
+    fn should_reject_invalid_hashes() {
+        for value in ["", "0x", "xyz", "-1", &"1".repeat(65)] {
+            assert_eq!(parse_hex(value), None, "{}", value);
+        }
+
+        let result =
+            serde_json::from_str::<Hashes>(r#"{"hash": "xyz", "parents": [], "heights": {}}"#);
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("invalid hash `xyz`"));
+    }
+}
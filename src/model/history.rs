@@ -0,0 +1,208 @@
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+use super::{hash_hex, Amounts, Block, TransactionId};
+
+// Whether an address sent or received a transaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+// A transaction in the history of an address
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AddressTransaction {
+    pub block_index: u64,
+    #[serde(with = "hash_hex")]
+    pub id: TransactionId,
+    pub direction: Direction,
+    pub amount: u64,
+}
+
+// Index of the transactions of each address, in the order they were included in the chain,
+// so the history of an address doesn't need a scan of the whole chain
+// The transactions of pruned blocks stay in the index, as they were added before being pruned
+#[derive(Debug, Clone, Default)]
+pub struct AddressHistory {
+    transactions: HashMap<String, Vec<AddressTransaction>>,
+}
+
+impl AddressHistory {
+    // Adds the transactions of a block, which must come after the ones already indexed
+    pub fn add_block(&mut self, block: &Block) {
+        for transaction in block.transactions.iter() {
+            let id = transaction.calculate_id();
+            let entries = [
+                (&transaction.sender, Direction::Sent),
+                (&transaction.recipient, Direction::Received),
+            ];
+            for (address, direction) in entries.iter() {
+                self.transactions
+                    .entry(address.to_string())
+                    .or_default()
+                    .push(AddressTransaction {
+                        block_index: block.header.index,
+                        id,
+                        direction: *direction,
+                        amount: transaction.amount,
+                    });
+            }
+        }
+    }
+
+    // Removes the transactions of the blocks replaced by a reorg, which are always the last ones
+    pub fn revert(&mut self, reverted: &[Block]) {
+        let first_index = match reverted.first() {
+            Some(block) => block.header.index,
+            None => return,
+        };
+
+        for transaction in reverted.iter().flat_map(|block| block.transactions.iter()) {
+            for address in [&transaction.sender, &transaction.recipient].iter() {
+                if let Some(entries) = self.transactions.get_mut(address.as_str()) {
+                    entries.retain(|entry| entry.block_index < first_index);
+                    if entries.is_empty() {
+                        self.transactions.remove(address.as_str());
+                    }
+                }
+            }
+        }
+    }
+
+    // Returns up to "limit" transactions of an address from the position "from" of its history,
+    // the position where the next page starts (if there are more) and the size of the whole history
+    // Only the transactions of the blocks up to "last_index" are in the history, e.g. the safe ones
+    pub fn page(
+        &self,
+        address: &str,
+        from: usize,
+        limit: usize,
+        last_index: u64,
+    ) -> (Vec<AddressTransaction>, Option<usize>, usize) {
+        let entries = self.until(address, last_index);
+
+        let page: Vec<AddressTransaction> =
+            entries.iter().skip(from).take(limit).cloned().collect();
+        let next = Some(from + page.len()).filter(|next| *next < entries.len());
+
+        (page, next, entries.len())
+    }
+
+    // Amounts that an address received and sent in the blocks after "last_index"
+    pub fn amounts_after(&self, address: &str, last_index: u64) -> Amounts {
+        let entries = self
+            .transactions
+            .get(address)
+            .map_or(&[][..], Vec::as_slice);
+        let first_after = self.until(address, last_index).len();
+
+        let mut amounts = Amounts::default();
+        for entry in entries[first_after..].iter() {
+            match entry.direction {
+                Direction::Received => amounts.received += entry.amount,
+                Direction::Sent => amounts.sent += entry.amount,
+            }
+        }
+        amounts
+    }
+
+    // The transactions of an address in the blocks up to "last_index", which come first
+    fn until(&self, address: &str, last_index: u64) -> &[AddressTransaction] {
+        let entries = self
+            .transactions
+            .get(address)
+            .map_or(&[][..], Vec::as_slice);
+        let end = entries.partition_point(|entry| entry.block_index <= last_index);
+
+        &entries[..end]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{BlockHash, Transaction};
+
+    const TIP: u64 = u64::MAX;
+
+    #[test]
+    fn should_index_both_sides_of_each_transaction() {
+        let mut history = AddressHistory::default();
+        history.add_block(&create_block(1, &[("alice", "bob", 5)]));
+        history.add_block(&create_block(
+            2,
+            &[("bob", "carol", 3), ("alice", "carol", 1)],
+        ));
+
+        let (page, next, total) = history.page("bob", 0, 10, TIP);
+        assert_eq!(total, 2);
+        assert_eq!(next, None);
+        assert_eq!(page[0].block_index, 1);
+        assert_eq!(page[0].direction, Direction::Received);
+        assert_eq!(page[0].amount, 5);
+        assert_eq!(page[1].block_index, 2);
+        assert_eq!(page[1].direction, Direction::Sent);
+
+        // long histories come in pages
+        let (page, next, total) = history.page("carol", 0, 1, TIP);
+        assert_eq!((page.len(), next, total), (1, Some(1), 2));
+        let (page, next, _) = history.page("carol", 1, 1, TIP);
+        assert_eq!((page[0].amount, next), (1, None));
+
+        assert_eq!(history.page("nobody", 0, 10, TIP), (Vec::new(), None, 0));
+    }
+
+    #[test]
+    fn should_forget_the_transactions_of_reverted_blocks() {
+        let mut history = AddressHistory::default();
+        history.add_block(&create_block(1, &[("alice", "bob", 5)]));
+        let reverted = vec![
+            create_block(2, &[("alice", "bob", 3)]),
+            create_block(3, &[("carol", "alice", 1)]),
+        ];
+        reverted.iter().for_each(|block| history.add_block(block));
+
+        history.revert(&reverted);
+        let (page, _, total) = history.page("alice", 0, 10, TIP);
+        assert_eq!(total, 1);
+        assert_eq!(page[0].block_index, 1);
+        assert_eq!(history.page("carol", 0, 10, TIP).2, 0);
+    }
+
+    #[test]
+    fn should_leave_out_the_blocks_after_the_last_index() {
+        let mut history = AddressHistory::default();
+        history.add_block(&create_block(1, &[("alice", "bob", 5)]));
+        history.add_block(&create_block(2, &[("bob", "alice", 3)]));
+        history.add_block(&create_block(3, &[("alice", "bob", 1)]));
+
+        let (page, next, total) = history.page("bob", 0, 10, 1);
+        assert_eq!((page.len(), next, total), (1, None, 1));
+        assert_eq!(history.page("bob", 0, 10, 0).2, 0);
+
+        let after = history.amounts_after("bob", 1);
+        assert_eq!((after.received, after.sent), (1, 3));
+        assert_eq!(history.amounts_after("bob", 3), Amounts::default());
+        assert_eq!(history.amounts_after("nobody", 0), Amounts::default());
+    }
+
+    fn create_block(index: u64, transactions: &[(&str, &str, u64)]) -> Block {
+        let transactions = transactions
+            .iter()
+            .map(|(sender, recipient, amount)| Transaction {
+                sender: sender.to_string(),
+                recipient: recipient.to_string(),
+                amount: *amount,
+                signature: None,
+                multisig: None,
+                contract: None,
+                token: None,
+                data: None,
+            })
+            .collect();
+        Block::new(index, 0, BlockHash::default(), transactions)
+    }
+}
@@ -0,0 +1,206 @@
+use crypto::{digest::Digest, sha2::Sha256};
+use serde::{Deserialize, Serialize};
+
+use super::{hash_hex, BlockHash, BlockHeader, TransactionId};
+
+// Leaves and inner nodes are hashed with a different prefix (like in RFC 6962),
+// so an inner node can never be passed off as a transaction
+const LEAF_PREFIX: u8 = 0;
+const NODE_PREFIX: u8 = 1;
+
+// Root of the Merkle tree of the ids of the transactions of a block, in order
+// A node without sibling is moved up as it is, instead of being paired with itself like in Bitcoin,
+// so two different lists of transactions can't have the same root
+// A block without transactions has an empty (zero) root
+pub fn merkle_root(ids: &[TransactionId]) -> BlockHash {
+    if ids.is_empty() {
+        return BlockHash::default();
+    }
+
+    let mut level: Vec<[u8; 32]> = ids.iter().map(|id| hash_leaf(*id)).collect();
+    while level.len() > 1 {
+        level = next_level(&level);
+    }
+
+    BlockHash::from(level[0])
+}
+
+// Proof that a transaction is one of the leaves of the Merkle tree of a block
+// It has the hashes of the siblings on the way up to the root, so the inclusion can be
+// checked with the header of the block alone (e.g. by light clients)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MerkleProof {
+    // position of the transaction in the block, and number of transactions of the block
+    pub index: usize,
+    pub count: usize,
+    // from the leaf to the root, nodes without a sibling don't have a hash here
+    #[serde(with = "hash_hex::vec")]
+    pub hashes: Vec<BlockHash>,
+}
+
+impl MerkleProof {
+    // Builds the proof of the transaction at "index", if there is one
+    pub fn new(ids: &[TransactionId], index: usize) -> Option<MerkleProof> {
+        if index >= ids.len() {
+            return None;
+        }
+
+        let mut hashes = Vec::new();
+        let mut level: Vec<[u8; 32]> = ids.iter().map(|id| hash_leaf(*id)).collect();
+        let mut position = index;
+        while level.len() > 1 {
+            if let Some(sibling) = level.get(position ^ 1) {
+                hashes.push(BlockHash::from(*sibling));
+            }
+            level = next_level(&level);
+            position /= 2;
+        }
+
+        Some(MerkleProof {
+            index,
+            count: ids.len(),
+            hashes,
+        })
+    }
+
+    // Root of the tree that would include the transaction according to the proof
+    // Returns None if the proof doesn't have the right number of hashes for its position
+    pub fn root(&self, id: TransactionId) -> Option<BlockHash> {
+        if self.index >= self.count {
+            return None;
+        }
+
+        let mut hash = hash_leaf(id);
+        let mut hashes = self.hashes.iter();
+        let (mut position, mut size) = (self.index, self.count);
+        while size > 1 {
+            if position ^ 1 < size {
+                let mut sibling = [0; 32];
+                hashes.next()?.to_big_endian(&mut sibling);
+                hash = if position % 2 == 0 {
+                    hash_node(&hash, &sibling)
+                } else {
+                    hash_node(&sibling, &hash)
+                };
+            }
+            position /= 2;
+            size = size.div_ceil(2);
+        }
+
+        if hashes.next().is_some() {
+            return None;
+        }
+        Some(BlockHash::from(hash))
+    }
+
+    pub fn verify(&self, id: TransactionId, merkle_root: BlockHash) -> bool {
+        self.root(id) == Some(merkle_root)
+    }
+}
+
+// Block that includes a transaction, with the proof to check it against the header of the block
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TransactionProof {
+    #[serde(with = "hash_hex")]
+    pub transaction_id: TransactionId,
+    pub block_index: u64,
+    #[serde(with = "hash_hex")]
+    pub block_hash: BlockHash,
+    pub proof: MerkleProof,
+}
+
+impl TransactionProof {
+    // The header must be the one of the block of the proof, and its root must match the proof
+    pub fn verify(&self, header: &BlockHeader) -> bool {
+        header.index == self.block_index
+            && header.hash == self.block_hash
+            && self.proof.verify(self.transaction_id, header.merkle_root)
+    }
+}
+
+// Pairs the nodes of a level of the tree, a node without sibling is moved up as it is
+fn next_level(level: &[[u8; 32]]) -> Vec<[u8; 32]> {
+    level
+        .chunks(2)
+        .map(|pair| match pair {
+            [left, right] => hash_node(left, right),
+            [single] => *single,
+            _ => unreachable!(),
+        })
+        .collect()
+}
+
+fn hash_leaf(id: TransactionId) -> [u8; 32] {
+    let mut bytes = [0; 32];
+    id.to_big_endian(&mut bytes);
+
+    hash(&[&[LEAF_PREFIX], &bytes])
+}
+
+fn hash_node(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    hash(&[&[NODE_PREFIX], left, right])
+}
+
+fn hash(parts: &[&[u8]]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        hasher.input(part);
+    }
+
+    let mut hash = [0; 32];
+    hasher.result(&mut hash);
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_calculate_the_root_of_the_transactions() {
+        let ids: Vec<TransactionId> = (1..=5).map(TransactionId::from).collect();
+        assert_eq!(merkle_root(&[]), BlockHash::default());
+        assert_eq!(merkle_root(&ids[..1]), BlockHash::from(hash_leaf(ids[0])));
+
+        // the root depends on every transaction and on their order
+        let root = merkle_root(&ids);
+        assert_ne!(merkle_root(&ids[..4]), root);
+        let mut swapped = ids.clone();
+        swapped.swap(0, 1);
+        assert_ne!(merkle_root(&swapped), root);
+
+        // the last transaction isn't paired with itself, so repeating it changes the root
+        let mut repeated = ids.clone();
+        repeated.push(ids[4]);
+        assert_ne!(merkle_root(&repeated), root);
+    }
+
+    #[test]
+    fn should_prove_every_transaction() {
+        for count in 1..=9 {
+            let ids: Vec<TransactionId> = (1..=count).map(TransactionId::from).collect();
+            let root = merkle_root(&ids);
+
+            for (index, id) in ids.iter().enumerate() {
+                let proof = MerkleProof::new(&ids, index).unwrap();
+                assert!(proof.verify(*id, root), "{} of {}", index, count);
+
+                // the proof is only valid for its own transaction and position
+                assert!(!proof.verify(TransactionId::from(100), root));
+                let mut moved = proof.clone();
+                moved.index = (index + 1) % count as usize;
+                assert!(count == 1 || !moved.verify(*id, root));
+            }
+        }
+
+        let ids: Vec<TransactionId> = (1..=5).map(TransactionId::from).collect();
+        assert_eq!(MerkleProof::new(&ids, 5), None);
+
+        // a proof with missing or extra hashes doesn't lead to any root
+        let mut proof = MerkleProof::new(&ids, 0).unwrap();
+        proof.hashes.push(BlockHash::default());
+        assert_eq!(proof.root(ids[0]), None);
+        proof.hashes.truncate(1);
+        assert_eq!(proof.root(ids[0]), None);
+    }
+}
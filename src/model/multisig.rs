@@ -0,0 +1,214 @@
+use std::collections::BTreeMap;
+
+use crypto::{digest::Digest, ed25519, sha2::Sha256};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+// Max number of keys of a multisig address, so verifying a transaction is always cheap
+const MAX_MULTISIG_KEYS: usize = 16;
+
+// Multisig addresses are told apart from plain ones (hex-encoded public keys) by this prefix
+const ADDRESS_PREFIX: &str = "ms";
+
+// Error types to return when a multisig transaction is not properly signed
+#[derive(Error, PartialEq, Debug)]
+pub enum MultiSigError {
+    #[error(
+        "The threshold must be between 1 and the number of keys, which can't be more than {0}"
+    )]
+    InvalidThreshold(usize),
+
+    #[error("Invalid public key `{0}`, it must be a hex-encoded ed25519 public key")]
+    InvalidPublicKey(String),
+
+    #[error("The public key `{0}` is repeated")]
+    DuplicatePublicKey(String),
+
+    #[error("The keys and threshold don't match the address of the sender")]
+    AddressMismatch,
+
+    #[error("Invalid signature of `{0}`")]
+    InvalidSignature(String),
+
+    #[error("The transaction has {0} valid signatures, but it needs {1}")]
+    NotEnoughSignatures(usize, usize),
+}
+
+// Keys that can spend from a multisig address, and the signatures collected from them
+// Like in a P2SH script, the keys are only revealed when spending from the address
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MultiSig {
+    pub threshold: usize,
+    pub public_keys: Vec<String>,
+    // Signatures over the id of the transaction, by hex-encoded public key
+    #[serde(default)]
+    pub signatures: BTreeMap<String, String>,
+}
+
+impl MultiSig {
+    // Address of the keys, which doesn't depend on their order
+    // so every owner derives the same one from the list they know
+    pub fn address(&self) -> Result<String, MultiSigError> {
+        self.check_keys()?;
+
+        let mut public_keys = self.public_keys.clone();
+        public_keys.sort();
+        let mut hasher = Sha256::new();
+        hasher.input_str(&format!("{}:{}", self.threshold, public_keys.join(",")));
+
+        let mut hash = [0; 32];
+        hasher.result(&mut hash);
+        Ok(format!("{}{}", ADDRESS_PREFIX, hex::encode(hash)))
+    }
+
+    // Signs the id of a transaction with one of the keys, each owner does it on their own
+    pub fn sign(&mut self, message: &[u8; 32], seed: &[u8]) {
+        let (secret_key, public_key) = ed25519::keypair(seed);
+        let signature = ed25519::signature(message, &secret_key);
+
+        self.signatures
+            .insert(hex::encode(public_key), hex::encode(&signature[..]));
+    }
+
+    // Checks that the keys belong to the sender and that enough of them signed the message
+    // A wrong signature makes the whole transaction invalid, even if the others are enough
+    pub fn verify(&self, sender: &str, message: &[u8; 32]) -> Result<(), MultiSigError> {
+        if self.address()? != sender {
+            return Err(MultiSigError::AddressMismatch);
+        }
+
+        for (public_key, signature) in self.signatures.iter() {
+            let valid = self.public_keys.contains(public_key)
+                && match hex::decode(signature) {
+                    Ok(signature) if signature.len() == 64 => {
+                        ed25519::verify(message, &hex::decode(public_key).unwrap(), &signature)
+                    }
+                    _ => false,
+                };
+            if !valid {
+                return Err(MultiSigError::InvalidSignature(public_key.clone()));
+            }
+        }
+
+        if self.signatures.len() < self.threshold {
+            return Err(MultiSigError::NotEnoughSignatures(
+                self.signatures.len(),
+                self.threshold,
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn check_keys(&self) -> Result<(), MultiSigError> {
+        if self.threshold == 0
+            || self.threshold > self.public_keys.len()
+            || self.public_keys.len() > MAX_MULTISIG_KEYS
+        {
+            return Err(MultiSigError::InvalidThreshold(MAX_MULTISIG_KEYS));
+        }
+
+        for (index, public_key) in self.public_keys.iter().enumerate() {
+            // keys are compared as strings, so they must be in the same (lowercase) encoding
+            match hex::decode(public_key) {
+                Ok(bytes) if bytes.len() == 32 && hex::encode(&bytes) == *public_key => {}
+                _ => return Err(MultiSigError::InvalidPublicKey(public_key.clone())),
+            }
+            if self.public_keys[..index].contains(public_key) {
+                return Err(MultiSigError::DuplicatePublicKey(public_key.clone()));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Check if an address can only be spent from with the signatures of several keys
+pub fn is_multisig_address(address: &str) -> bool {
+    match address.strip_prefix(ADDRESS_PREFIX) {
+        Some(hash) => hash.len() == 64 && hex::decode(hash).is_ok(),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MESSAGE: [u8; 32] = [7; 32];
+
+    #[test]
+    fn should_derive_addresses_from_the_keys() {
+        let multisig = create_multisig(2, &[1, 2, 3]);
+        let address = multisig.address().unwrap();
+        assert!(is_multisig_address(&address));
+        assert!(!is_multisig_address(&public_key(1)));
+
+        // the order of the keys doesn't matter, but the threshold does
+        let mut reversed = multisig.clone();
+        reversed.public_keys.reverse();
+        assert_eq!(reversed.address().unwrap(), address);
+        assert_ne!(create_multisig(1, &[1, 2, 3]).address().unwrap(), address);
+
+        assert_eq!(
+            create_multisig(0, &[1, 2]).address(),
+            Err(MultiSigError::InvalidThreshold(MAX_MULTISIG_KEYS))
+        );
+        assert_eq!(
+            create_multisig(3, &[1, 2]).address(),
+            Err(MultiSigError::InvalidThreshold(MAX_MULTISIG_KEYS))
+        );
+        assert_eq!(
+            create_multisig(1, &[1, 1]).address(),
+            Err(MultiSigError::DuplicatePublicKey(public_key(1)))
+        );
+    }
+
+    #[test]
+    fn should_enforce_the_threshold() {
+        let mut multisig = create_multisig(2, &[1, 2, 3]);
+        let address = multisig.address().unwrap();
+
+        multisig.sign(&MESSAGE, &[1; 32]);
+        assert_eq!(
+            multisig.verify(&address, &MESSAGE),
+            Err(MultiSigError::NotEnoughSignatures(1, 2))
+        );
+
+        multisig.sign(&MESSAGE, &[3; 32]);
+        assert_eq!(multisig.verify(&address, &MESSAGE), Ok(()));
+
+        // the signatures are only valid for the message they were made for...
+        assert_eq!(
+            multisig.verify(&address, &[8; 32]),
+            Err(MultiSigError::InvalidSignature(public_key(1)))
+        );
+
+        // ...and the keys only for their address
+        let other_address = create_multisig(2, &[1, 2, 4]).address().unwrap();
+        assert_eq!(
+            multisig.verify(&other_address, &MESSAGE),
+            Err(MultiSigError::AddressMismatch)
+        );
+
+        // keys outside of the address can't sign
+        multisig.sign(&MESSAGE, &[4; 32]);
+        assert_eq!(
+            multisig.verify(&address, &MESSAGE),
+            Err(MultiSigError::InvalidSignature(public_key(4)))
+        );
+    }
+
+    fn create_multisig(threshold: usize, seeds: &[u8]) -> MultiSig {
+        MultiSig {
+            threshold,
+            public_keys: seeds.iter().map(|seed| public_key(*seed)).collect(),
+            signatures: BTreeMap::new(),
+        }
+    }
+
+    fn public_key(seed: u8) -> String {
+        hex::encode(ed25519::keypair(&[seed; 32]).1)
+    }
+}
@@ -0,0 +1,235 @@
+use std::{
+    fs::{self, File},
+    io::Write,
+    path::Path,
+};
+
+use anyhow::{Context as _, Result};
+use thiserror::Error;
+
+// Name of the file with the version of the layout of the data directory
+const SCHEMA_FILE: &str = "schema_version";
+
+// Version of the layout of the data directory written by this version of the node
+// Every change to how the files are written that older versions can't read needs a new one,
+// with the migration that upgrades the directories of the previous version
+pub const SCHEMA_VERSION: u32 = 1;
+
+// Error types to return when the data directory can not be used
+#[derive(Error, PartialEq, Debug)]
+pub enum SchemaError {
+    #[error("The data directory has schema version {0}, but this node only supports up to {1}")]
+    Unsupported(u32, u32),
+    #[error("Invalid schema version \"{0}\"")]
+    InvalidVersion(String),
+}
+
+// A step that upgrades a data directory from the previous version to "version"
+struct Migration {
+    version: u32,
+    description: &'static str,
+    migrate: fn(&Path) -> Result<()>,
+}
+
+// Every migration in order, the last one must be for SCHEMA_VERSION
+const MIGRATIONS: &[Migration] = &[Migration {
+    version: 1,
+    description: "record the schema version",
+    // directories from before the versioning already have the layout of the first version,
+    // blocks in older formats are written again by the store itself
+    migrate: |_| Ok(()),
+}];
+
+// Upgrades the data directory to SCHEMA_VERSION (creating it if it doesn't exist)
+// Directories without a version were written before the versioning, so they go through every migration
+// Directories of newer versions are refused, as this node would not understand (or would corrupt) them
+pub fn migrate(data_dir: &str) -> Result<()> {
+    run_migrations(Path::new(data_dir), MIGRATIONS, SCHEMA_VERSION)
+}
+
+fn run_migrations(data_dir: &Path, migrations: &[Migration], latest: u32) -> Result<()> {
+    fs::create_dir_all(data_dir)
+        .with_context(|| format!("could not create the data directory {}", data_dir.display()))?;
+
+    let version = match read_version(data_dir)? {
+        Some(version) => version,
+        // nothing to upgrade in a new directory
+        None if is_empty(data_dir)? => return write_version(data_dir, latest),
+        None => 0,
+    };
+    if version > latest {
+        return Err(SchemaError::Unsupported(version, latest).into());
+    }
+
+    // the version is recorded after every step, so an interrupted upgrade resumes from the last one
+    for migration in migrations.iter().filter(|step| step.version > version) {
+        info!(
+            "migrating {} to schema version {}: {}",
+            data_dir.display(),
+            migration.version,
+            migration.description
+        );
+        (migration.migrate)(data_dir).with_context(|| {
+            format!(
+                "could not migrate {} to schema version {}",
+                data_dir.display(),
+                migration.version
+            )
+        })?;
+        write_version(data_dir, migration.version)?;
+    }
+
+    Ok(())
+}
+
+fn read_version(data_dir: &Path) -> Result<Option<u32>> {
+    let path = data_dir.join(SCHEMA_FILE);
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let content =
+        fs::read_to_string(&path).with_context(|| format!("could not read {}", path.display()))?;
+    let version = content
+        .trim()
+        .parse()
+        .map_err(|_| SchemaError::InvalidVersion(content.trim().to_string()))?;
+    Ok(Some(version))
+}
+
+// Same as the blocks when they are rewritten, the file is only replaced once the new one is complete
+fn write_version(data_dir: &Path, version: u32) -> Result<()> {
+    let path = data_dir.join(SCHEMA_FILE);
+    let temp_path = path.with_extension("tmp");
+    let mut file = File::create(&temp_path)
+        .with_context(|| format!("could not create {}", temp_path.display()))?;
+    file.write_all(format!("{}\n", version).as_bytes())
+        .and_then(|_| file.sync_all())
+        .and_then(|_| fs::rename(&temp_path, &path))
+        .with_context(|| format!("could not write {}", path.display()))
+}
+
+fn is_empty(data_dir: &Path) -> Result<bool> {
+    let mut entries =
+        fs::read_dir(data_dir).with_context(|| format!("could not read {}", data_dir.display()))?;
+    Ok(entries.next().is_none())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn should_end_the_migrations_in_the_current_version() {
+        let versions: Vec<u32> = MIGRATIONS.iter().map(|step| step.version).collect();
+        let expected: Vec<u32> = (1..=SCHEMA_VERSION).collect();
+        assert_eq!(versions, expected);
+    }
+
+    #[test]
+    fn should_record_the_version_of_new_directories() {
+        let data_dir = create_data_dir("new");
+        migrate(data_dir.to_str().unwrap()).unwrap();
+        assert_eq!(read_version(&data_dir).unwrap(), Some(SCHEMA_VERSION));
+
+        fs::remove_dir_all(data_dir).unwrap();
+    }
+
+    #[test]
+    fn should_migrate_step_by_step() {
+        let data_dir = create_data_dir("steps");
+        fs::create_dir_all(&data_dir).unwrap();
+        fs::write(data_dir.join("blocks.log"), "").unwrap();
+
+        // directories without a version go through every step
+        run_migrations(&data_dir, &test_migrations(), 3).unwrap();
+        assert_eq!(steps_run(&data_dir), "1,2,3,");
+        assert_eq!(read_version(&data_dir).unwrap(), Some(3));
+
+        // and the ones with a version only through the steps after it
+        write_version(&data_dir, 1).unwrap();
+        fs::remove_file(data_dir.join("steps")).unwrap();
+        run_migrations(&data_dir, &test_migrations(), 3).unwrap();
+        assert_eq!(steps_run(&data_dir), "2,3,");
+
+        // up to date directories are left alone
+        run_migrations(&data_dir, &test_migrations(), 3).unwrap();
+        assert_eq!(steps_run(&data_dir), "2,3,");
+
+        fs::remove_dir_all(data_dir).unwrap();
+    }
+
+    #[test]
+    fn should_keep_the_version_of_the_last_completed_step() {
+        let data_dir = create_data_dir("failed");
+        fs::create_dir_all(&data_dir).unwrap();
+        write_version(&data_dir, 1).unwrap();
+        let mut migrations = test_migrations();
+        migrations[2].migrate = |_| Err(anyhow::anyhow!("disk full"));
+
+        let err = run_migrations(&data_dir, &migrations, 3).unwrap_err();
+        assert!(format!("{:#}", err).ends_with("to schema version 3: disk full"));
+        assert_eq!(read_version(&data_dir).unwrap(), Some(2));
+
+        fs::remove_dir_all(data_dir).unwrap();
+    }
+
+    #[test]
+    fn should_refuse_newer_versions() {
+        let data_dir = create_data_dir("newer");
+        fs::create_dir_all(&data_dir).unwrap();
+        write_version(&data_dir, SCHEMA_VERSION + 1).unwrap();
+
+        let err = migrate(data_dir.to_str().unwrap()).unwrap_err();
+        let err = err.downcast::<SchemaError>().unwrap();
+        assert_eq!(
+            err,
+            SchemaError::Unsupported(SCHEMA_VERSION + 1, SCHEMA_VERSION)
+        );
+
+        fs::write(data_dir.join(SCHEMA_FILE), "two").unwrap();
+        let err = migrate(data_dir.to_str().unwrap()).unwrap_err();
+        let err = err.downcast::<SchemaError>().unwrap();
+        assert_eq!(err, SchemaError::InvalidVersion("two".to_string()));
+
+        fs::remove_dir_all(data_dir).unwrap();
+    }
+
+    // Each step appends its version to a file, so the tests can tell which ones ran
+    fn test_migrations() -> Vec<Migration> {
+        fn record(data_dir: &Path, version: u32) -> Result<()> {
+            let steps = format!("{}{},", steps_run(data_dir), version);
+            fs::write(data_dir.join("steps"), steps)?;
+            Ok(())
+        }
+
+        vec![
+            Migration {
+                version: 1,
+                description: "first",
+                migrate: |data_dir| record(data_dir, 1),
+            },
+            Migration {
+                version: 2,
+                description: "second",
+                migrate: |data_dir| record(data_dir, 2),
+            },
+            Migration {
+                version: 3,
+                description: "third",
+                migrate: |data_dir| record(data_dir, 3),
+            },
+        ]
+    }
+
+    fn steps_run(data_dir: &Path) -> String {
+        fs::read_to_string(data_dir.join("steps")).unwrap_or_default()
+    }
+
+    fn create_data_dir(name: &str) -> std::path::PathBuf {
+        let path = env::temp_dir().join(format!("schema_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        path
+    }
+}
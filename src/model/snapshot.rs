@@ -0,0 +1,119 @@
+use std::{collections::BTreeMap, fs, path::Path};
+
+use anyhow::{Context as _, Result};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::{hash_hex, AccountState, Block, BlockHash, ContractState, TokenState, TransactionId};
+
+// Error types to return when a snapshot can't be taken or used
+#[derive(Error, PartialEq, Debug)]
+pub enum SnapshotError {
+    #[error("There is no block at height {0}")]
+    UnknownHeight(u64),
+
+    #[error("The transactions until height {0} were pruned, snapshots can only be taken from height {1}")]
+    PrunedHeight(u64, u64),
+
+    #[error("The blocks of the snapshot don't match its height and tip")]
+    InvalidTip,
+
+    #[error(
+        "The balances, contracts and tokens of the snapshot don't match the state root of its tip"
+    )]
+    InvalidStateRoot,
+
+    #[error("Transaction {0:#x} is in a block after the tip of the snapshot")]
+    InvalidTransaction(TransactionId),
+}
+
+// State of the chain right after the block at "height": the headers until it,
+// and what is left of the transactions (the amounts of each address and their ids)
+// A node can start from a trusted snapshot instead of replaying every block since the genesis
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub height: u64,
+    #[serde(with = "hash_hex")]
+    pub tip: BlockHash,
+    // all the blocks until the tip, pruned so they only have the header
+    pub blocks: Vec<Block>,
+    pub balances: AccountState,
+    #[serde(default, skip_serializing_if = "ContractState::is_empty")]
+    pub contracts: ContractState,
+    #[serde(default, skip_serializing_if = "TokenState::is_empty")]
+    pub tokens: TokenState,
+    // index of the block that included each transaction, so they can't be included again
+    #[serde(with = "hash_hex::keys")]
+    pub transactions: BTreeMap<TransactionId, u64>,
+}
+
+impl Snapshot {
+    pub fn load(path: &Path) -> Result<Snapshot> {
+        let json = fs::read_to_string(path)
+            .with_context(|| format!("could not read the snapshot {}", path.display()))?;
+
+        serde_json::from_str(&json)
+            .with_context(|| format!("could not parse the snapshot {}", path.display()))
+    }
+
+    // It's written to a temporary file first, so a crash can't leave a partial snapshot behind
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let temporary_path = path.with_extension("tmp");
+        let json = serde_json::to_vec(self)?;
+
+        fs::write(&temporary_path, json)
+            .and_then(|_| fs::File::open(&temporary_path)?.sync_all())
+            .and_then(|_| fs::rename(&temporary_path, path))
+            .with_context(|| format!("could not write the snapshot {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Transaction;
+    use std::env;
+
+    #[test]
+    fn should_save_and_load_snapshots() {
+        let mut genesis = Block::new(0, 0, BlockHash::default(), Vec::new());
+        genesis.pruned = true;
+
+        let mut balances = AccountState::default();
+        balances
+            .apply(&[Transaction {
+                sender: "alice".to_string(),
+                recipient: "bob".to_string(),
+                amount: 3,
+                signature: None,
+                multisig: None,
+                contract: None,
+                token: None,
+                data: None,
+            }])
+            .unwrap();
+        let mut transactions = BTreeMap::new();
+        transactions.insert(TransactionId::from(42), 0);
+
+        let snapshot = Snapshot {
+            height: 0,
+            tip: genesis.header.hash,
+            blocks: vec![genesis],
+            balances,
+            contracts: ContractState::default(),
+            tokens: TokenState::default(),
+            transactions,
+        };
+
+        let path = env::temp_dir().join(format!("snapshot_{}.json", std::process::id()));
+        snapshot.save(&path).unwrap();
+        let loaded = Snapshot::load(&path).unwrap();
+        assert_eq!(loaded.tip, snapshot.tip);
+        assert!(loaded.blocks[0].pruned);
+        assert_eq!(loaded.balances, snapshot.balances);
+        assert_eq!(loaded.transactions, snapshot.transactions);
+        assert!(!path.with_extension("tmp").exists());
+
+        fs::remove_file(path).unwrap();
+    }
+}
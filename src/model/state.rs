@@ -0,0 +1,478 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crypto::{digest::Digest, sha2::Sha256};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::{hash_hex, AmountError, BlockHash, ContractState, Receipt, Transaction, TransactionId};
+
+// Max length of the name of a token
+const MAX_TOKEN_NAME_LENGTH: usize = 32;
+
+// Tokens are identified by the id of the transaction that issued them
+pub type TokenId = TransactionId;
+
+// Error types to return when a token transaction is not valid
+#[derive(Error, PartialEq, Debug)]
+pub enum TokenError {
+    #[error("Token names must have between 1 and {0} characters")]
+    InvalidName(usize),
+
+    #[error("The supply of a token can't be zero")]
+    ZeroSupply,
+
+    #[error("The amount of tokens to transfer can't be zero")]
+    ZeroAmount,
+
+    #[error("Unknown token {0:#x}")]
+    UnknownToken(TokenId),
+
+    #[error("The sender only has {0} of the token")]
+    InsufficientBalance(u64),
+
+    #[error("A transaction can't both move tokens and run a contract")]
+    WithContract,
+}
+
+// Amounts received and sent by an address
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Amounts {
+    pub received: u64,
+    pub sent: u64,
+}
+
+// Amounts of every address that took part in a transaction, after applying the blocks of a chain
+// Addresses are kept sorted, so every node calculates the same root for the same state
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct AccountState(BTreeMap<String, Amounts>);
+
+impl AccountState {
+    pub fn get(&self, address: &str) -> Amounts {
+        self.0.get(address).copied().unwrap_or_default()
+    }
+
+    // Blocks with transactions that would overflow the amounts of an address are invalid,
+    // so the state is left as it was after the last transaction that could be applied
+    pub fn apply(&mut self, transactions: &[Transaction]) -> Result<(), AmountError> {
+        transactions
+            .iter()
+            .try_for_each(|transaction| self.apply_transaction(transaction))
+    }
+
+    // Either both amounts are updated or, if any of them would overflow, none
+    pub fn apply_transaction(&mut self, transaction: &Transaction) -> Result<(), AmountError> {
+        let (received, sent) = self.next_amounts(transaction)?;
+        self.0
+            .entry(transaction.recipient.clone())
+            .or_default()
+            .received = received;
+        self.0.entry(transaction.sender.clone()).or_default().sent = sent;
+        Ok(())
+    }
+
+    // Checks that the transaction could be applied, without changing the state
+    pub fn check_transaction(&self, transaction: &Transaction) -> Result<(), AmountError> {
+        self.next_amounts(transaction).map(|_| ())
+    }
+
+    // Amounts received by the recipient and sent by the sender after the transaction
+    fn next_amounts(&self, transaction: &Transaction) -> Result<(u64, u64), AmountError> {
+        let overflow = |address: &str| AmountError::Overflow(address.to_string());
+        let received = self
+            .get(&transaction.recipient)
+            .received
+            .checked_add(transaction.amount)
+            .ok_or_else(|| overflow(&transaction.recipient))?;
+        let sent = self
+            .get(&transaction.sender)
+            .sent
+            .checked_add(transaction.amount)
+            .ok_or_else(|| overflow(&transaction.sender))?;
+
+        Ok((received, sent))
+    }
+
+    // Hash of the balances of every address
+    // Every address is prefixed by its length, so two different states can't be serialized the same
+    pub fn root(&self) -> BlockHash {
+        let mut hasher = Sha256::new();
+        for (address, amounts) in &self.0 {
+            hasher.input(&(address.len() as u64).to_be_bytes());
+            hasher.input(address.as_bytes());
+            hasher.input(&amounts.received.to_be_bytes());
+            hasher.input(&amounts.sent.to_be_bytes());
+        }
+
+        let mut root = [0; 32];
+        hasher.result(&mut root);
+        BlockHash::from(root)
+    }
+}
+
+// What a transaction does with tokens, besides transferring its amount of the coin
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum TokenAction {
+    // Creates a token with a fixed supply, which is given to the sender
+    Issue {
+        name: String,
+        supply: u64,
+    },
+    // Moves an amount of the token from the sender to the recipient
+    Transfer {
+        #[serde(with = "hash_hex")]
+        token_id: TokenId,
+        amount: u64,
+    },
+}
+
+impl TokenAction {
+    // Checks what doesn't depend on the state of the chain, blocks with invalid actions are rejected
+    // Everything else (e.g. not having enough tokens) makes the transaction fail, but it's still included
+    pub fn check(&self) -> Result<(), TokenError> {
+        match self {
+            TokenAction::Issue { name, .. }
+                if name.trim().is_empty() || name.chars().count() > MAX_TOKEN_NAME_LENGTH =>
+            {
+                Err(TokenError::InvalidName(MAX_TOKEN_NAME_LENGTH))
+            }
+            TokenAction::Issue { supply: 0, .. } => Err(TokenError::ZeroSupply),
+            TokenAction::Transfer { amount: 0, .. } => Err(TokenError::ZeroAmount),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Token {
+    pub name: String,
+    pub supply: u64,
+    pub issuer: String,
+}
+
+// Tokens issued in a chain and the balances of every address that holds them
+// Both are sorted, like the amounts of the coin, so every node calculates the same root for them
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TokenState {
+    #[serde(with = "hash_hex::keys")]
+    tokens: BTreeMap<TokenId, Token>,
+    #[serde(with = "hash_hex::nested_keys")]
+    balances: BTreeMap<String, BTreeMap<TokenId, u64>>,
+}
+
+impl TokenState {
+    pub fn is_empty(&self) -> bool {
+        self.tokens.is_empty()
+    }
+
+    pub fn get(&self, id: TokenId) -> Option<&Token> {
+        self.tokens.get(&id)
+    }
+
+    // Tokens held by an address and their balances, addresses that gave away all of a token still list it
+    pub fn balances(&self, address: &str) -> Vec<(TokenId, &Token, u64)> {
+        self.balances
+            .get(address)
+            .into_iter()
+            .flatten()
+            .map(|(id, balance)| (*id, &self.tokens[id], *balance))
+            .collect()
+    }
+
+    pub fn balance(&self, address: &str, id: TokenId) -> u64 {
+        self.balances
+            .get(address)
+            .and_then(|balances| balances.get(&id))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    // Runs the token transactions, returning their receipts
+    // Unlike the coin, tokens can't be overdrawn, so their supply never changes
+    pub fn apply(&mut self, transactions: &[Transaction]) -> HashMap<TransactionId, Receipt> {
+        transactions
+            .iter()
+            .filter_map(|transaction| {
+                let action = transaction.token.as_ref()?;
+                let id = transaction.calculate_id();
+                let receipt = match self.execute(id, transaction, action) {
+                    Ok(()) => Receipt::succeeded(0, Vec::new()),
+                    Err(error) => Receipt::failed(0, error.to_string()),
+                };
+                Some((id, receipt))
+            })
+            .collect()
+    }
+
+    fn execute(
+        &mut self,
+        id: TransactionId,
+        transaction: &Transaction,
+        action: &TokenAction,
+    ) -> Result<(), TokenError> {
+        action.check()?;
+
+        match action {
+            TokenAction::Issue { name, supply } => {
+                self.tokens.insert(
+                    id,
+                    Token {
+                        name: name.clone(),
+                        supply: *supply,
+                        issuer: transaction.sender.clone(),
+                    },
+                );
+                self.add(&transaction.sender, id, *supply);
+            }
+            TokenAction::Transfer { token_id, amount } => {
+                if !self.tokens.contains_key(token_id) {
+                    return Err(TokenError::UnknownToken(*token_id));
+                }
+                let balance = self.balance(&transaction.sender, *token_id);
+                if balance < *amount {
+                    return Err(TokenError::InsufficientBalance(balance));
+                }
+                self.add(&transaction.sender, *token_id, balance - amount);
+                let received = self.balance(&transaction.recipient, *token_id) + amount;
+                self.add(&transaction.recipient, *token_id, received);
+            }
+        }
+
+        Ok(())
+    }
+
+    // Sets the balance of an address, the sum of all of them is never more than the supply
+    fn add(&mut self, address: &str, id: TokenId, balance: u64) {
+        self.balances
+            .entry(address.to_string())
+            .or_default()
+            .insert(id, balance);
+    }
+
+    // Hash of the tokens and the balances of every address
+    // Variable length fields are prefixed by their length, like in the root of the balances
+    pub fn root(&self) -> BlockHash {
+        let mut hasher = Sha256::new();
+        let input_id = |hasher: &mut Sha256, id: &TokenId| {
+            let mut bytes = [0; 32];
+            id.to_big_endian(&mut bytes);
+            hasher.input(&bytes);
+        };
+        let input_str = |hasher: &mut Sha256, value: &str| {
+            hasher.input(&(value.len() as u64).to_be_bytes());
+            hasher.input(value.as_bytes());
+        };
+
+        for (id, token) in &self.tokens {
+            input_id(&mut hasher, id);
+            input_str(&mut hasher, &token.name);
+            hasher.input(&token.supply.to_be_bytes());
+            input_str(&mut hasher, &token.issuer);
+        }
+        for (address, balances) in &self.balances {
+            input_str(&mut hasher, address);
+            hasher.input(&(balances.len() as u64).to_be_bytes());
+            for (id, balance) in balances {
+                input_id(&mut hasher, id);
+                hasher.input(&balance.to_be_bytes());
+            }
+        }
+
+        let mut root = [0; 32];
+        hasher.result(&mut root);
+        BlockHash::from(root)
+    }
+}
+
+// Root of the whole state, committed in the header of each block
+// Chains without contracts and tokens commit the root of the balances alone,
+// and the root of the tokens is only included once there are tokens
+pub fn state_root(
+    accounts: &AccountState,
+    contracts: &ContractState,
+    tokens: &TokenState,
+) -> BlockHash {
+    if contracts.is_empty() && tokens.is_empty() {
+        return accounts.root();
+    }
+
+    let mut roots = vec![accounts.root(), contracts.root()];
+    if !tokens.is_empty() {
+        roots.push(tokens.root());
+    }
+
+    let mut hasher = Sha256::new();
+    for root in roots.iter() {
+        let mut bytes = [0; 32];
+        root.to_big_endian(&mut bytes);
+        hasher.input(&bytes);
+    }
+
+    let mut root = [0; 32];
+    hasher.result(&mut root);
+    BlockHash::from(root)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_apply_transactions() {
+        let mut state = AccountState::default();
+        state
+            .apply(&[
+                create_transaction("alice", "bob", 10),
+                create_transaction("bob", "carol", 4),
+            ])
+            .unwrap();
+
+        assert_eq!(state.get("alice").sent, 10);
+        assert_eq!(
+            state.get("bob"),
+            Amounts {
+                received: 10,
+                sent: 4
+            }
+        );
+        assert_eq!(state.get("dave"), Amounts::default());
+
+        // transactions that would overflow an amount are not applied at all
+        let before = state.clone();
+        assert_eq!(
+            state.apply(&[create_transaction("alice", "bob", u64::MAX)]),
+            Err(AmountError::Overflow("bob".to_string()))
+        );
+        assert_eq!(state, before);
+        assert_eq!(
+            state.apply(&[create_transaction("bob", "dave", u64::MAX)]),
+            Err(AmountError::Overflow("bob".to_string()))
+        );
+        assert_eq!(state, before);
+    }
+
+    #[test]
+    fn should_calculate_the_same_root_for_the_same_state() {
+        let mut state = AccountState::default();
+        let empty_root = state.root();
+
+        state
+            .apply(&[create_transaction("alice", "bob", 10)])
+            .unwrap();
+        assert_ne!(state.root(), empty_root);
+
+        // only the resulting amounts matter, not the transactions that led to them
+        let mut other_state = AccountState::default();
+        other_state
+            .apply(&[
+                create_transaction("alice", "bob", 4),
+                create_transaction("alice", "bob", 6),
+            ])
+            .unwrap();
+        assert_eq!(other_state.root(), state.root());
+
+        other_state
+            .apply(&[create_transaction("bob", "alice", 1)])
+            .unwrap();
+        assert_ne!(other_state.root(), state.root());
+    }
+
+    #[test]
+    fn should_issue_and_transfer_tokens() {
+        let mut tokens = TokenState::default();
+        let issue = create_token_transaction("alice", "alice", issue_action("GOLD", 100));
+        let id = issue.calculate_id();
+        let receipts = tokens.apply(&[issue]);
+        assert!(receipts[&id].success);
+        assert_eq!(tokens.get(id).unwrap().issuer, "alice");
+        assert_eq!(tokens.balance("alice", id), 100);
+        let issued_root = tokens.root();
+
+        let transfer = create_token_transaction("alice", "bob", transfer_action(id, 30));
+        tokens.apply(&[transfer]);
+        assert_eq!(tokens.balance("alice", id), 70);
+        assert_eq!(tokens.balance("bob", id), 30);
+        assert_eq!(
+            tokens.balances("bob"),
+            vec![(id, tokens.get(id).unwrap(), 30)]
+        );
+        assert_ne!(tokens.root(), issued_root);
+    }
+
+    #[test]
+    fn should_not_overdraw_tokens() {
+        let mut tokens = TokenState::default();
+        let issue = create_token_transaction("alice", "alice", issue_action("GOLD", 100));
+        let id = issue.calculate_id();
+        tokens.apply(&[issue]);
+        let root = tokens.root();
+
+        let failures = [
+            (
+                create_token_transaction("alice", "bob", transfer_action(id, 101)),
+                TokenError::InsufficientBalance(100),
+            ),
+            (
+                create_token_transaction("bob", "alice", transfer_action(id, 1)),
+                TokenError::InsufficientBalance(0),
+            ),
+            (
+                create_token_transaction("alice", "bob", transfer_action(id + 1, 1)),
+                TokenError::UnknownToken(id + 1),
+            ),
+        ];
+        for (transaction, error) in failures.iter() {
+            let receipts = tokens.apply(std::slice::from_ref(transaction));
+            let receipt = &receipts[&transaction.calculate_id()];
+            assert!(!receipt.success);
+            assert_eq!(receipt.error, Some(error.to_string()));
+        }
+        assert_eq!(tokens.root(), root);
+    }
+
+    #[test]
+    fn should_check_token_actions() {
+        assert_eq!(
+            issue_action("", 1).check(),
+            Err(TokenError::InvalidName(MAX_TOKEN_NAME_LENGTH))
+        );
+        assert_eq!(issue_action("GOLD", 0).check(), Err(TokenError::ZeroSupply));
+        assert_eq!(
+            transfer_action(TokenId::default(), 0).check(),
+            Err(TokenError::ZeroAmount)
+        );
+        assert_eq!(issue_action("GOLD", 1).check(), Ok(()));
+    }
+
+    fn issue_action(name: &str, supply: u64) -> TokenAction {
+        TokenAction::Issue {
+            name: name.to_string(),
+            supply,
+        }
+    }
+
+    fn transfer_action(token_id: TokenId, amount: u64) -> TokenAction {
+        TokenAction::Transfer { token_id, amount }
+    }
+
+    fn create_token_transaction(sender: &str, recipient: &str, action: TokenAction) -> Transaction {
+        Transaction {
+            token: Some(action),
+            ..create_transaction(sender, recipient, 0)
+        }
+    }
+
+    fn create_transaction(sender: &str, recipient: &str, amount: u64) -> Transaction {
+        Transaction {
+            sender: sender.to_string(),
+            recipient: recipient.to_string(),
+            amount,
+            signature: None,
+            multisig: None,
+            contract: None,
+            token: None,
+            data: None,
+        }
+    }
+}
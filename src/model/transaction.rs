@@ -1,8 +1,402 @@
-use serde::{Deserialize, Serialize};
+use crypto::digest::Digest;
+use crypto::ed25519;
+use crypto::sha2::Sha256;
+use ethereum_types::U256;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use thiserror::Error;
 
+use super::{canonical, is_multisig_address, ContractAction, MultiSig, TokenAction};
+
+// Transactions are identified by the hash of their contents
+pub type TransactionId = U256;
+
+// Network that nodes and wallets sign for unless configured otherwise (see CHAIN_ID)
+pub const DEFAULT_CHAIN_ID: &str = "main";
+
+// Longest address a transaction can have, public keys and multisig addresses are way shorter
+const MAX_ADDRESS_LENGTH: usize = 128;
+
+// Length of a hex-encoded ed25519 signature
+const SIGNATURE_LENGTH: usize = 128;
+
+// Most bytes of data a transaction can ever carry, nodes usually take much less (see MAX_DATA_BYTES)
+pub const MAX_DATA_LENGTH: usize = 4096;
+
+// Error types to return when the fields of a transaction are too big to be accepted
+#[derive(Error, PartialEq, Debug)]
+pub enum TransactionError {
+    #[error("Addresses can't be longer than {0} characters")]
+    AddressTooLong(usize),
+
+    #[error("Signatures must be {0} hex characters")]
+    InvalidSignatureLength(usize),
+
+    #[error("Multisig transactions can't have more signatures than keys")]
+    TooManySignatures,
+
+    #[error("The data of a transaction can't be longer than {0} bytes")]
+    DataTooLong(usize),
+}
+
+// Unknown fields are rejected instead of ignored, so clients find out about their typos
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Transaction {
     pub sender: String,
     pub recipient: String,
     pub amount: u64,
+    // Signature of the sender (a hex-encoded ed25519 public key) over the id of the transaction in a chain
+    // It's produced outside of the node, so spending keys never need to be in the node
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+    // Keys and signatures of the owners when the sender is a multisig address, instead of the signature
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub multisig: Option<MultiSig>,
+    // Deploys or calls the contract at the address of the recipient, if present
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub contract: Option<ContractAction>,
+    // Issues or transfers a token, if present
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<TokenAction>,
+    // Arbitrary bytes anchored in the chain along with the transfer, e.g. a payment reference
+    // They are part of the id, so they are signed too, and shown in json as hex
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "data_hex")]
+    pub data: Option<Vec<u8>>,
+}
+
+impl Transaction {
+    // Calculate the deterministic id of the transaction
+    // Two transactions with the same contents will always have the same id
+    pub fn calculate_id(&self) -> TransactionId {
+        // The canonical bytes leave out the signatures, as they are calculated over the id
+        let serialized = canonical::transaction_bytes(self);
+
+        // Cacluate and return the SHA-256 hash value for the transaction
+        let mut byte_hash = <[u8; 32]>::default();
+        let mut hasher = Sha256::new();
+
+        hasher.input(&serialized);
+        hasher.result(&mut byte_hash);
+
+        U256::from(byte_hash)
+    }
+
+    // The message that the sender signs, which is the hash of the id of the transaction and the chain
+    // so a transaction signed for a network (e.g. a testnet) can't be replayed in another one
+    pub fn message(&self, chain_id: &str) -> [u8; 32] {
+        let mut message = [0; 32];
+        let mut hasher = Sha256::new();
+        hasher.input(&canonical::signing_bytes(chain_id, self.calculate_id()));
+        hasher.result(&mut message);
+        message
+    }
+
+    // Check that the transaction was signed by the sender for the chain, false if it's not signed at all
+    // Transactions from multisig addresses need the signatures of enough of their owners instead
+    pub fn has_valid_signature(&self, chain_id: &str) -> bool {
+        if let Some(multisig) = &self.multisig {
+            return multisig
+                .verify(&self.sender, &self.message(chain_id))
+                .is_ok();
+        }
+
+        let public_key = match hex::decode(&self.sender) {
+            Ok(public_key) if public_key.len() == 32 => public_key,
+            _ => return false,
+        };
+        let signature = match self.signature.as_ref().map(hex::decode) {
+            Some(Ok(signature)) if signature.len() == 64 => signature,
+            _ => return false,
+        };
+
+        ed25519::verify(&self.message(chain_id), &public_key, &signature)
+    }
+
+    // Check that the fields are not bigger than they can ever need to be, before doing any work with them
+    // Contract and token actions have their own limits
+    pub fn check_size(&self) -> Result<(), TransactionError> {
+        if self.sender.len() > MAX_ADDRESS_LENGTH || self.recipient.len() > MAX_ADDRESS_LENGTH {
+            return Err(TransactionError::AddressTooLong(MAX_ADDRESS_LENGTH));
+        }
+
+        let signatures = self.signature.iter().chain(
+            self.multisig
+                .iter()
+                .flat_map(|multisig| multisig.signatures.values()),
+        );
+        if signatures
+            .into_iter()
+            .any(|signature| signature.len() != SIGNATURE_LENGTH)
+        {
+            return Err(TransactionError::InvalidSignatureLength(SIGNATURE_LENGTH));
+        }
+
+        if let Some(multisig) = &self.multisig {
+            if multisig.signatures.len() > multisig.public_keys.len() {
+                return Err(TransactionError::TooManySignatures);
+            }
+        }
+
+        self.check_data(MAX_DATA_LENGTH)
+    }
+
+    // Check that the data is not longer than "max_length" bytes
+    pub fn check_data(&self, max_length: usize) -> Result<(), TransactionError> {
+        match &self.data {
+            Some(data) if data.len() > max_length => Err(TransactionError::DataTooLong(max_length)),
+            _ => Ok(()),
+        }
+    }
+
+    // Whether the transaction carries any signature, which must then be valid wherever it goes
+    pub fn is_signed(&self) -> bool {
+        self.signature.is_some() || self.multisig.is_some()
+    }
+
+    // Whether the transaction is only valid with the signature of its sender, in the pool and in blocks
+    // Public keys and multisig addresses can only spend with the keys of their owners, so no node
+    // or miner can spend their funds, while other names (like the ones of demos) can still spend unsigned
+    pub fn needs_signature(&self) -> bool {
+        let is_public_key = matches!(hex::decode(&self.sender), Ok(key) if key.len() == 32);
+        self.is_signed() || is_public_key || is_multisig_address(&self.sender)
+    }
+
+    // Sign the transaction with the secret key of the sender, only wallets outside the node do this
+    // (like the "wallet send" command), the node itself never holds spending keys
+    pub fn sign(&mut self, seed: &[u8], chain_id: &str) {
+        let (secret_key, public_key) = ed25519::keypair(seed);
+        self.sender = hex::encode(public_key);
+
+        let signature = ed25519::signature(&self.message(chain_id), &secret_key);
+        self.signature = Some(hex::encode(&signature[..]));
+    }
+
+    // Add the signature of one of the owners of the multisig address that sends the transaction
+    // Does nothing if the sender is not a multisig address
+    pub fn sign_multisig(&mut self, seed: &[u8], chain_id: &str) {
+        let message = self.message(chain_id);
+        if let Some(multisig) = self.multisig.as_mut() {
+            multisig.sign(&message, seed);
+        }
+    }
+}
+
+mod data_hex {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        data: &Option<Vec<u8>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        data.as_ref().map(hex::encode).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Vec<u8>>, D::Error> {
+        match Option::<String>::deserialize(deserializer)? {
+            Some(data) => hex::decode(&data)
+                .map(Some)
+                .map_err(|_| de::Error::custom(format!("invalid hex data `{}`", data))),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHAIN: &str = DEFAULT_CHAIN_ID;
+
+    #[test]
+    fn should_have_deterministic_id() {
+        let transaction = create_mock_transaction(1);
+        let same_transaction = create_mock_transaction(1);
+
+        assert_eq!(transaction.calculate_id(), same_transaction.calculate_id());
+    }
+
+    #[test]
+    fn should_have_different_ids_for_different_contents() {
+        let transaction_a = create_mock_transaction(1);
+        let transaction_b = create_mock_transaction(2);
+
+        assert_ne!(transaction_a.calculate_id(), transaction_b.calculate_id());
+    }
+
+    #[test]
+    fn should_not_include_signature_in_id() {
+        let mut transaction = create_mock_transaction(1);
+        transaction.signature = Some("foo".to_string());
+
+        assert_eq!(
+            transaction.calculate_id(),
+            create_mock_transaction(1).calculate_id()
+        );
+    }
+
+    #[test]
+    fn should_verify_signature_of_sender() {
+        let mut transaction = create_mock_transaction(1);
+
+        // unsigned transactions are not valid
+        assert!(!transaction.has_valid_signature(CHAIN));
+
+        transaction.sign(&[1; 32], CHAIN);
+        assert!(transaction.has_valid_signature(CHAIN));
+
+        // the signature does not match anymore if the contents change
+        transaction.amount += 1;
+        assert!(!transaction.has_valid_signature(CHAIN));
+
+        // only the sender can sign
+        let mut transaction = create_mock_transaction(1);
+        transaction.sign(&[1; 32], CHAIN);
+        transaction.sender = hex::encode([2; 32]);
+        assert!(!transaction.has_valid_signature(CHAIN));
+    }
+
+    #[test]
+    fn should_not_replay_signatures_in_other_chains() {
+        let mut transaction = create_mock_transaction(1);
+        transaction.sign(&[1; 32], "testnet");
+        assert!(transaction.has_valid_signature("testnet"));
+        assert!(!transaction.has_valid_signature(CHAIN));
+
+        // the id is the same in every chain, only the signature is bound to one
+        let mut same_transaction = transaction.clone();
+        same_transaction.sign(&[1; 32], CHAIN);
+        assert_eq!(same_transaction.calculate_id(), transaction.calculate_id());
+        assert!(same_transaction.has_valid_signature(CHAIN));
+    }
+
+    #[test]
+    fn should_verify_signatures_of_multisig_addresses() {
+        let public_keys = [1, 2, 3]
+            .iter()
+            .map(|seed| hex::encode(ed25519::keypair(&[*seed; 32]).1))
+            .collect();
+        let multisig = MultiSig {
+            threshold: 2,
+            public_keys,
+            signatures: Default::default(),
+        };
+        let mut transaction = create_mock_transaction(1);
+        transaction.sender = multisig.address().unwrap();
+        transaction.multisig = Some(multisig);
+        let id = transaction.calculate_id();
+
+        // the signatures are not part of the id, so each owner signs the same one
+        transaction.sign_multisig(&[1; 32], CHAIN);
+        assert_eq!(transaction.calculate_id(), id);
+        assert!(!transaction.has_valid_signature(CHAIN));
+
+        transaction.sign_multisig(&[2; 32], CHAIN);
+        assert!(transaction.has_valid_signature(CHAIN));
+        assert!(!transaction.has_valid_signature("testnet"));
+
+        // a plain signature is not enough, even if it's from one of the owners
+        let multisig = transaction.multisig.take().unwrap();
+        transaction.signature = multisig.signatures.values().next().cloned();
+        assert!(!transaction.has_valid_signature(CHAIN));
+    }
+
+    #[test]
+    fn should_limit_the_size_of_the_fields() {
+        let mut transaction = create_mock_transaction(1);
+        transaction.sign(&[1; 32], CHAIN);
+        assert_eq!(transaction.check_size(), Ok(()));
+
+        let mut long_address = transaction.clone();
+        long_address.recipient = "a".repeat(MAX_ADDRESS_LENGTH + 1);
+        assert_eq!(
+            long_address.check_size(),
+            Err(TransactionError::AddressTooLong(MAX_ADDRESS_LENGTH))
+        );
+
+        transaction.signature = Some("ab".repeat(1024));
+        assert_eq!(
+            transaction.check_size(),
+            Err(TransactionError::InvalidSignatureLength(SIGNATURE_LENGTH))
+        );
+
+        transaction.signature = None;
+        transaction.multisig = Some(MultiSig {
+            threshold: 1,
+            public_keys: vec![hex::encode([1; 32])],
+            signatures: (0..2)
+                .map(|key| (key.to_string(), "a".repeat(SIGNATURE_LENGTH)))
+                .collect(),
+        });
+        assert_eq!(
+            transaction.check_size(),
+            Err(TransactionError::TooManySignatures)
+        );
+
+        let mut transaction = create_mock_transaction(1);
+        transaction.data = Some(vec![0; MAX_DATA_LENGTH]);
+        assert_eq!(transaction.check_size(), Ok(()));
+        assert_eq!(
+            transaction.check_data(16),
+            Err(TransactionError::DataTooLong(16))
+        );
+        transaction.data = Some(vec![0; MAX_DATA_LENGTH + 1]);
+        assert_eq!(
+            transaction.check_size(),
+            Err(TransactionError::DataTooLong(MAX_DATA_LENGTH))
+        );
+    }
+
+    #[test]
+    fn should_sign_the_data() {
+        let mut transaction = create_mock_transaction(1);
+        transaction.data = Some(b"invoice 42".to_vec());
+        assert_ne!(
+            transaction.calculate_id(),
+            create_mock_transaction(1).calculate_id()
+        );
+
+        transaction.sign(&[1; 32], CHAIN);
+        assert!(transaction.has_valid_signature(CHAIN));
+        transaction.data = Some(b"invoice 43".to_vec());
+        assert!(!transaction.has_valid_signature(CHAIN));
+    }
+
+    #[test]
+    fn should_show_the_data_as_hex() {
+        let json = r#"{"sender":"1","recipient":"2","amount":3,"data":"cafe"}"#;
+        let transaction: Transaction = serde_json::from_str(json).unwrap();
+        assert_eq!(transaction.data, Some(vec![0xca, 0xfe]));
+        assert_eq!(serde_json::to_string(&transaction).unwrap(), json);
+
+        let json = r#"{"sender": "1", "recipient": "2", "amount": 3, "data": "memo"}"#;
+        assert!(serde_json::from_str::<Transaction>(json).is_err());
+    }
+
+    #[test]
+    fn should_reject_unknown_fields() {
+        let json = r#"{"sender": "1", "recipient": "2", "amount": 3}"#;
+        assert!(serde_json::from_str::<Transaction>(json).is_ok());
+
+        let json = r#"{"sender": "1", "recipient": "2", "amount": 3, "fee": 1}"#;
+        assert!(serde_json::from_str::<Transaction>(json).is_err());
+
+        let json = r#"{"sender": "1", "recipient": "2", "amount": 3,
+            "token": {"type": "issue", "name": "gold", "supply": 1, "decimals": 2}}"#;
+        assert!(serde_json::from_str::<Transaction>(json).is_err());
+    }
+
+    fn create_mock_transaction(amount: u64) -> Transaction {
+        Transaction {
+            sender: "1".to_string(),
+            recipient: "2".to_string(),
+            amount,
+            signature: None,
+            multisig: None,
+            contract: None,
+            token: None,
+            data: None,
+        }
+    }
 }
@@ -1,16 +1,136 @@
-use super::Transaction;
-use std::sync::{Arc, Mutex};
+use super::{hash_hex, ChainEvent, EventBus, Transaction, TransactionId};
+use crate::util::{Lock, SharedClock, SystemClock};
+use anyhow::{Context as _, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fs,
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use thiserror::Error;
 
 pub type TransactionVec = Vec<Transaction>;
 
 // We don't need to export this type because concurrency is encapsulated in this file
-type SyncedTransactionVec = Arc<Mutex<TransactionVec>>;
+type SyncedTransactionVec = Arc<Lock<TransactionVec>>;
+type SyncedIds = Arc<Lock<HashSet<TransactionId>>>;
+type SyncedArrivals = Arc<Lock<HashMap<TransactionId, i64>>>;
+type SyncedDropped = Arc<Lock<DroppedTransactions>>;
+type SyncedRebroadcasts = Arc<Lock<HashMap<TransactionId, Rebroadcast>>>;
+
+// Max number of dropped transactions remembered, the oldest ones are forgotten first
+const MAX_DROPPED_TRANSACTIONS: usize = 10_000;
+
+// The wait between rebroadcasts of a transaction doubles each time, up to 2^6 times the interval
+const MAX_REBROADCAST_BACKOFF: u32 = 6;
+
+// A transaction waiting in the pool, as reported to clients
+#[derive(Debug, Clone, Serialize)]
+pub struct PendingTransaction {
+    #[serde(with = "hash_hex")]
+    pub id: TransactionId,
+    #[serde(flatten)]
+    pub transaction: Transaction,
+    // Time since the transaction entered the pool
+    pub age_ms: u64,
+    // Number of times it was sent again to the peers, because it's still waiting
+    pub rebroadcasts: u32,
+}
+
+// How many times a transaction was sent again to the peers, and the last time it was sent
+#[derive(Debug, Clone, Copy)]
+struct Rebroadcast {
+    count: u32,
+    last_at: i64,
+}
+
+// Why a transaction left the pool without being mined
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DropReason {
+    // It waited in the pool for longer than the max age
+    Expired,
+    // It was saved in the pool, but it was no longer valid when the node started again
+    Invalid,
+}
+
+// A transaction that left the pool without being mined, as reported to clients
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DroppedTransaction {
+    pub reason: DropReason,
+    // When it was dropped, in millis of the clock
+    pub dropped_at: i64,
+}
+
+// The dropped transactions, so clients can still learn what happened to them
+#[derive(Debug, Default)]
+struct DroppedTransactions {
+    transactions: HashMap<TransactionId, DroppedTransaction>,
+    // ids in the order they were dropped, some may have entered the pool again
+    order: VecDeque<TransactionId>,
+}
+
+impl DroppedTransactions {
+    fn insert(&mut self, id: TransactionId, dropped: DroppedTransaction) {
+        if self.transactions.insert(id, dropped).is_none() {
+            self.order.push_back(id);
+        }
+        while self.transactions.len() > MAX_DROPPED_TRANSACTIONS {
+            match self.order.pop_front() {
+                Some(oldest) => self.transactions.remove(&oldest),
+                None => break,
+            };
+        }
+    }
+
+    fn remove(&mut self, id: &TransactionId) {
+        if self.transactions.remove(id).is_some() {
+            self.order.retain(|dropped| dropped != id);
+        }
+    }
+}
+
+// A transaction of the pool as saved to disk, with the moment it entered the pool
+// so it doesn't wait longer to expire because the node was restarted
+#[derive(Debug, Serialize, Deserialize)]
+struct SavedTransaction {
+    transaction: Transaction,
+    added_at: i64,
+}
+
+// Error types to return when trying to add invalid transactions to the pool
+#[derive(Error, PartialEq, Debug)]
+pub enum TransactionPoolError {
+    #[error("Duplicate transaction")]
+    DuplicateTransaction,
+}
 
 // Represents a pool of unrealized transactions
 // Multiple threads can read/write concurrently to the pool
 #[derive(Debug, Clone)]
 pub struct TransactionPool {
     transactions: SyncedTransactionVec,
+    // Ids of the transactions in the pool and of the popped ones that are not in a block yet,
+    // so duplicates are found without hashing every transaction, even while the miner seals their block
+    // Taken right after the lock of the transactions
+    ids: SyncedIds,
+    // Moment in which each transaction entered the pool (in millis of the clock), to expire the ones waiting for too long
+    // To avoid deadlocks, this lock is always taken after the one of the transactions
+    arrivals: SyncedArrivals,
+    // The transactions that left the pool without being mined, taken after the lock of the arrivals
+    dropped: SyncedDropped,
+    // The transactions that were sent again to the peers, also taken after the lock of the arrivals
+    rebroadcasts: SyncedRebroadcasts,
+    // Increased every time transactions are added, so clients can cheaply detect changes
+    version: Arc<AtomicU64>,
+    // Where the transactions that enter the pool are published
+    events: EventBus,
+    clock: SharedClock,
 }
 
 // Basic operations in the transaction pool are encapsulated in the implementation
@@ -18,35 +138,322 @@ pub struct TransactionPool {
 impl TransactionPool {
     // Creates a empty transaction pool
     pub fn new() -> TransactionPool {
+        TransactionPool::with_clock(SystemClock::shared())
+    }
+
+    // Same as "new", but measuring how long transactions wait with "clock"
+    pub fn with_clock(clock: SharedClock) -> TransactionPool {
         TransactionPool {
             transactions: SyncedTransactionVec::default(),
+            ids: SyncedIds::default(),
+            arrivals: SyncedArrivals::default(),
+            dropped: SyncedDropped::default(),
+            rebroadcasts: SyncedRebroadcasts::default(),
+            version: Arc::new(AtomicU64::new(0)),
+            events: EventBus::new(),
+            clock,
         }
     }
 
-    // Adds a new transaction to the pool
-    pub fn add_transaction(&self, transaction: Transaction) {
-        let mut transactions = self.transactions.lock().unwrap();
-        transactions.push(transaction);
+    // Same as "new", but publishing the transactions that enter the pool in "events"
+    // (usually the bus of the blockchain, so subscribers get everything in one place)
+    pub fn with_events(events: EventBus) -> TransactionPool {
+        TransactionPool {
+            events,
+            ..TransactionPool::new()
+        }
+    }
+
+    // Adds a new transaction to the pool, returning its id
+    // Transactions already present in the pool, or popped and not released yet, are rejected
+    pub fn add_transaction(&self, transaction: Transaction) -> Result<TransactionId> {
+        let mut transactions = self.transactions.lock();
+
+        let id = transaction.calculate_id();
+        if !self.ids.lock().insert(id) {
+            return Err(TransactionPoolError::DuplicateTransaction.into());
+        }
+
+        transactions.push(transaction.clone());
+        let mut arrivals = self.arrivals.lock();
+        arrivals
+            .entry(id)
+            .or_insert_with(|| self.clock.now_millis());
+        self.dropped.lock().remove(&id);
+
+        self.version.fetch_add(1, Ordering::SeqCst);
+        self.events
+            .publish(ChainEvent::TransactionAdmitted(Arc::new(transaction)));
         info!("transaction added");
+
+        Ok(id)
+    }
+
+    // Puts back popped transactions that could not be mined, ahead of the newer ones
+    // Their ids were kept, so they can't have been added again in the meantime
+    pub fn return_transactions(&self, mut restored: TransactionVec) {
+        let mut transactions = self.transactions.lock();
+
+        if !restored.is_empty() {
+            let mut ids = self.ids.lock();
+            // returned transactions keep their original arrival, unless they were swept meanwhile
+            let mut arrivals = self.arrivals.lock();
+            let mut dropped = self.dropped.lock();
+            let now = self.clock.now_millis();
+            for tx in restored.iter() {
+                let id = tx.calculate_id();
+                ids.insert(id);
+                arrivals.entry(id).or_insert(now);
+                dropped.remove(&id);
+            }
+            self.version.fetch_add(1, Ordering::SeqCst);
+        }
+        restored.append(&mut transactions);
+        *transactions = restored;
+    }
+
+    // Returns a copy of all transactions, without removing them from the pool
+    pub fn get_all(&self) -> TransactionVec {
+        let transactions = self.transactions.lock();
+
+        transactions.clone()
+    }
+
+    // Returns the transactions in the pool, with their ids and how long they have been waiting
+    pub fn get_pending(&self) -> Vec<PendingTransaction> {
+        let transactions = self.transactions.lock();
+        let arrivals = self.arrivals.lock();
+        let rebroadcasts = self.rebroadcasts.lock();
+        let now = self.clock.now_millis();
+
+        transactions
+            .iter()
+            .map(|transaction| {
+                let id = transaction.calculate_id();
+                let arrival = arrivals.get(&id).copied().unwrap_or(now);
+                PendingTransaction {
+                    id,
+                    transaction: transaction.clone(),
+                    // the wall clock can go back, but an age can't be negative
+                    age_ms: (now - arrival).max(0) as u64,
+                    rebroadcasts: rebroadcasts
+                        .get(&id)
+                        .map_or(0, |rebroadcast| rebroadcast.count),
+                }
+            })
+            .collect()
+    }
+
+    // Returns the number of transactions waiting in the pool
+    pub fn size(&self) -> usize {
+        let transactions = self.transactions.lock();
+
+        transactions.len()
+    }
+
+    // Returns the number of times that transactions were added to the pool (or expired)
+    // Popping transactions does not count, as it happens when they are mined
+    pub fn version(&self) -> u64 {
+        self.version.load(Ordering::SeqCst)
+    }
+
+    // Returns why a transaction left the pool without being mined, if it did recently
+    pub fn get_dropped(&self, id: TransactionId) -> Option<DroppedTransaction> {
+        let dropped = self.dropped.lock();
+
+        dropped.transactions.get(&id).copied()
+    }
+
+    // Removes the transactions that have been waiting in the pool for longer than "max_age"
+    // Returns the number of expired transactions, which are remembered as dropped
+    pub fn expire(&self, max_age: Duration) -> usize {
+        let mut transactions = self.transactions.lock();
+        let mut tracked_ids = self.ids.lock();
+        let mut arrivals = self.arrivals.lock();
+
+        // forget the transactions that left the pool, e.g. because they were mined
+        let ids: HashSet<TransactionId> = transactions.iter().map(|tx| tx.calculate_id()).collect();
+        arrivals.retain(|id, _| ids.contains(id));
+
+        let now = self.clock.now_millis();
+        let max_age_ms = max_age.as_millis() as i64;
+        let mut expired = Vec::new();
+        transactions.retain(|tx| {
+            let id = tx.calculate_id();
+            match arrivals.get(&id) {
+                Some(arrival) if now - arrival >= max_age_ms => {
+                    expired.push(id);
+                    false
+                }
+                _ => true,
+            }
+        });
+        arrivals.retain(|_, arrival| now - *arrival < max_age_ms);
+
+        if !expired.is_empty() {
+            let mut dropped = self.dropped.lock();
+            for id in expired.iter() {
+                tracked_ids.remove(id);
+                let transaction = DroppedTransaction {
+                    reason: DropReason::Expired,
+                    dropped_at: now,
+                };
+                dropped.insert(*id, transaction);
+            }
+            self.version.fetch_add(1, Ordering::SeqCst);
+        }
+
+        expired.len()
+    }
+
+    // Returns the transactions that must be sent again to the peers, as they are still waiting
+    // A transaction is due once "interval" passed since it was last sent (or entered the pool),
+    // and the wait doubles after every rebroadcast, so the ones that never get mined don't flood the network
+    pub fn take_rebroadcasts(&self, interval: Duration) -> TransactionVec {
+        let transactions = self.transactions.lock();
+        let arrivals = self.arrivals.lock();
+        let mut rebroadcasts = self.rebroadcasts.lock();
+
+        // forget the transactions that left the pool, e.g. because they were mined
+        let ids: HashSet<TransactionId> = transactions.iter().map(|tx| tx.calculate_id()).collect();
+        rebroadcasts.retain(|id, _| ids.contains(id));
+
+        let now = self.clock.now_millis();
+        let interval_ms = interval.as_millis() as i64;
+        let mut due = TransactionVec::new();
+        for transaction in transactions.iter() {
+            let id = transaction.calculate_id();
+            let rebroadcast = rebroadcasts.entry(id).or_insert_with(|| Rebroadcast {
+                count: 0,
+                last_at: arrivals.get(&id).copied().unwrap_or(now),
+            });
+            let backoff =
+                interval_ms.saturating_mul(1 << rebroadcast.count.min(MAX_REBROADCAST_BACKOFF));
+            if now - rebroadcast.last_at >= backoff {
+                rebroadcast.count += 1;
+                rebroadcast.last_at = now;
+                due.push(transaction.clone());
+            }
+        }
+
+        due
     }
 
     // Returns a copy of all transactions and empties the pool
+    // Their ids are kept until they are released or returned, so they can't be added again while
+    // their block is being mined
     // This operation is safe to be called concurrently from multiple threads
     pub fn pop(&self) -> TransactionVec {
-        // the "transactions" attribute is protected by a Mutex
+        // the "transactions" attribute is protected by a Lock
         // so only one thread at a time can access the value when the lock is held
         // preventing inconsitencies when adding new transactions while a pop is in course
-        let mut transactions = self.transactions.lock().unwrap();
+        let mut transactions = self.transactions.lock();
         let transactions_clone = transactions.clone();
         transactions.clear();
 
         transactions_clone
     }
+
+    // Forgets popped transactions that are already in a block (or that won't be mined at all)
+    // so they are no longer duplicates, the blockchain rejects the mined ones from then on
+    pub fn release(&self, popped: &[Transaction]) {
+        let mut ids = self.ids.lock();
+
+        for tx in popped.iter() {
+            ids.remove(&tx.calculate_id());
+        }
+    }
+
+    // Writes the transactions waiting in the pool into a file, as a JSON list
+    // The ones that the miner is trying to include in a block are not there, but they are only lost
+    // if the node stops before the block is mined
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let saved: Vec<SavedTransaction> = {
+            let transactions = self.transactions.lock();
+            let arrivals = self.arrivals.lock();
+            let now = self.clock.now_millis();
+            transactions
+                .iter()
+                .map(|transaction| SavedTransaction {
+                    transaction: transaction.clone(),
+                    added_at: arrivals
+                        .get(&transaction.calculate_id())
+                        .copied()
+                        .unwrap_or(now),
+                })
+                .collect()
+        };
+        let raw_transactions = serde_json::to_string(&saved)?;
+
+        // we write a temporary file first, so a crash never leaves a half-written pool
+        let temp_path = path.with_extension("tmp");
+        fs::write(&temp_path, raw_transactions)?;
+        fs::rename(&temp_path, path)?;
+
+        Ok(())
+    }
+
+    // Adds the transactions saved in a file, keeping the moment they first entered the pool
+    // The file may not exist yet, e.g. the first time the node is started
+    // Transactions that don't pass "check" anymore (e.g. they were mined or the chain changed meanwhile)
+    // are remembered as dropped instead
+    // Returns the number of restored and discarded transactions
+    pub fn restore<F>(&self, path: &Path, check: F) -> Result<(usize, usize)>
+    where
+        F: Fn(&Transaction) -> Result<()>,
+    {
+        if !path.exists() {
+            return Ok((0, 0));
+        }
+        let raw_transactions = fs::read_to_string(path)
+            .with_context(|| format!("could not read the saved pool {}", path.display()))?;
+        let saved: Vec<SavedTransaction> = serde_json::from_str(&raw_transactions)
+            .with_context(|| format!("invalid saved pool {}", path.display()))?;
+
+        let mut transactions = self.transactions.lock();
+        let mut ids = self.ids.lock();
+        let mut arrivals = self.arrivals.lock();
+        let mut dropped = self.dropped.lock();
+        let now = self.clock.now_millis();
+        let (mut restored, mut discarded) = (0, 0);
+        for SavedTransaction {
+            transaction,
+            added_at,
+        } in saved
+        {
+            let id = transaction.calculate_id();
+            if ids.contains(&id) {
+                continue;
+            }
+            if let Err(error) = check(&transaction) {
+                warn!("discarding saved transaction {:#x}: {:#}", id, error);
+                let transaction = DroppedTransaction {
+                    reason: DropReason::Invalid,
+                    dropped_at: now,
+                };
+                dropped.insert(id, transaction);
+                discarded += 1;
+                continue;
+            }
+
+            transactions.push(transaction);
+            ids.insert(id);
+            arrivals.insert(id, added_at);
+            restored += 1;
+        }
+        if restored > 0 {
+            self.version.fetch_add(1, Ordering::SeqCst);
+        }
+
+        Ok((restored, discarded))
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::util::MockClock;
+    use std::env;
 
     #[test]
     fn should_be_empty_after_creation() {
@@ -62,7 +469,9 @@ mod tests {
 
         // add a new transaction to the pool
         let transaction = create_mock_transaction(1);
-        transaction_pool.add_transaction(transaction.clone());
+        transaction_pool
+            .add_transaction(transaction.clone())
+            .unwrap();
 
         // pop the values and check that the transaction is included
         let mut transactions = transaction_pool.pop();
@@ -81,8 +490,12 @@ mod tests {
         // add a new transaction to the pool
         let transaction_a = create_mock_transaction(1);
         let transaction_b = create_mock_transaction(2);
-        transaction_pool.add_transaction(transaction_a.clone());
-        transaction_pool.add_transaction(transaction_b.clone());
+        transaction_pool
+            .add_transaction(transaction_a.clone())
+            .unwrap();
+        transaction_pool
+            .add_transaction(transaction_b.clone())
+            .unwrap();
 
         // pop the values and check that the transactions are included
         let mut transactions = transaction_pool.pop();
@@ -95,11 +508,303 @@ mod tests {
         assert!(transactions.is_empty());
     }
 
+    #[test]
+    fn should_return_transactions_ahead_of_new_ones() {
+        let transaction_pool = TransactionPool::new();
+
+        // the older transactions are popped, then a new one arrives
+        let transaction_a = create_mock_transaction(1);
+        let transaction_b = create_mock_transaction(2);
+        transaction_pool
+            .add_transaction(transaction_a.clone())
+            .unwrap();
+        let popped = transaction_pool.pop();
+        transaction_pool
+            .add_transaction(transaction_b.clone())
+            .unwrap();
+
+        // when returned, the older transactions must come first
+        transaction_pool.return_transactions(popped);
+        let transactions = transaction_pool.pop();
+        assert_eq!(transactions.len(), 2);
+        assert_eq!(transactions[0].amount, transaction_a.amount);
+        assert_eq!(transactions[1].amount, transaction_b.amount);
+    }
+
+    #[test]
+    fn should_return_transaction_id() {
+        let transaction_pool = TransactionPool::new();
+
+        let transaction = create_mock_transaction(1);
+        let id = transaction_pool
+            .add_transaction(transaction.clone())
+            .unwrap();
+
+        assert_eq!(id, transaction.calculate_id());
+    }
+
+    #[test]
+    fn should_report_pending_transactions() {
+        let transaction_pool = TransactionPool::new();
+        let transaction = create_mock_transaction(1);
+        transaction_pool
+            .add_transaction(transaction.clone())
+            .unwrap();
+
+        let pending = transaction_pool.get_pending();
+        assert_eq!(transaction_pool.size(), 1);
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].id, transaction.calculate_id());
+        assert_eq!(pending[0].transaction.amount, transaction.amount);
+        assert!(pending[0].age_ms < 1000);
+    }
+
+    #[test]
+    fn should_not_let_adding_duplicate_transactions() {
+        let transaction_pool = TransactionPool::new();
+
+        // add the same transaction twice, the second one must be rejected
+        let transaction = create_mock_transaction(1);
+        let result = transaction_pool.add_transaction(transaction.clone());
+        assert!(result.is_ok());
+
+        let result = transaction_pool.add_transaction(transaction.clone());
+        let err = result
+            .unwrap_err()
+            .downcast::<TransactionPoolError>()
+            .unwrap();
+        assert_eq!(err, TransactionPoolError::DuplicateTransaction);
+
+        // only one copy must be in the pool
+        let transactions = transaction_pool.pop();
+        assert_eq!(transactions.len(), 1);
+    }
+
+    #[test]
+    fn should_reject_popped_transactions_until_released() {
+        let transaction_pool = TransactionPool::new();
+        let transaction = create_mock_transaction(1);
+        transaction_pool
+            .add_transaction(transaction.clone())
+            .unwrap();
+
+        // while its block is being mined, the transaction is still a duplicate
+        let popped = transaction_pool.pop();
+        assert!(transaction_pool
+            .add_transaction(transaction.clone())
+            .is_err());
+
+        // and it still is once it's returned to the pool
+        transaction_pool.return_transactions(popped);
+        assert!(transaction_pool
+            .add_transaction(transaction.clone())
+            .is_err());
+
+        // until its block is added, then the blockchain is the one that rejects it
+        let popped = transaction_pool.pop();
+        transaction_pool.release(&popped);
+        assert!(transaction_pool.add_transaction(transaction).is_ok());
+    }
+
+    #[test]
+    fn should_track_changes_without_popping() {
+        let transaction_pool = TransactionPool::new();
+        assert_eq!(transaction_pool.version(), 0);
+
+        // adding a transaction is a change, and we can read it without emptying the pool
+        transaction_pool
+            .add_transaction(create_mock_transaction(1))
+            .unwrap();
+        assert_eq!(transaction_pool.version(), 1);
+        assert_eq!(transaction_pool.get_all().len(), 1);
+        assert_eq!(transaction_pool.get_all().len(), 1);
+
+        // popping is not a change, but returning the transactions is
+        let popped = transaction_pool.pop();
+        assert_eq!(transaction_pool.version(), 1);
+        transaction_pool.return_transactions(popped);
+        assert_eq!(transaction_pool.version(), 2);
+    }
+
+    #[test]
+    fn should_expire_old_transactions() {
+        let transaction_pool = TransactionPool::new();
+        transaction_pool
+            .add_transaction(create_mock_transaction(1))
+            .unwrap();
+
+        // the transaction is still recent
+        assert_eq!(transaction_pool.expire(Duration::from_secs(60)), 0);
+        assert_eq!(transaction_pool.get_all().len(), 1);
+
+        // but not for a max age of zero, so it's removed and the pool changes
+        let version = transaction_pool.version();
+        assert_eq!(transaction_pool.expire(Duration::from_secs(0)), 1);
+        assert!(transaction_pool.get_all().is_empty());
+        assert_eq!(transaction_pool.version(), version + 1);
+
+        // the same transaction can enter the pool again
+        assert!(transaction_pool
+            .add_transaction(create_mock_transaction(1))
+            .is_ok());
+    }
+
+    #[test]
+    fn should_remember_dropped_transactions() {
+        let clock = MockClock::new(0);
+        let transaction_pool = TransactionPool::with_clock(clock.shared());
+        let id = transaction_pool
+            .add_transaction(create_mock_transaction(1))
+            .unwrap();
+        assert_eq!(transaction_pool.get_dropped(id), None);
+
+        clock.advance(Duration::from_secs(60));
+        transaction_pool.expire(Duration::from_secs(60));
+        let expected = DroppedTransaction {
+            reason: DropReason::Expired,
+            dropped_at: 60_000,
+        };
+        assert_eq!(transaction_pool.get_dropped(id), Some(expected));
+
+        // it's no longer dropped once it enters the pool again
+        transaction_pool
+            .add_transaction(create_mock_transaction(1))
+            .unwrap();
+        assert_eq!(transaction_pool.get_dropped(id), None);
+    }
+
+    #[test]
+    fn should_rebroadcast_waiting_transactions_with_backoff() {
+        let clock = MockClock::new(0);
+        let transaction_pool = TransactionPool::with_clock(clock.shared());
+        transaction_pool
+            .add_transaction(create_mock_transaction(1))
+            .unwrap();
+        let interval = Duration::from_secs(60);
+
+        // it was just broadcast when it entered the pool
+        assert!(transaction_pool.take_rebroadcasts(interval).is_empty());
+        clock.advance(interval);
+        assert_eq!(transaction_pool.take_rebroadcasts(interval).len(), 1);
+        assert_eq!(transaction_pool.get_pending()[0].rebroadcasts, 1);
+
+        // the next time it waits twice as long
+        clock.advance(interval);
+        assert!(transaction_pool.take_rebroadcasts(interval).is_empty());
+        clock.advance(interval);
+        assert_eq!(transaction_pool.take_rebroadcasts(interval).len(), 1);
+        assert_eq!(transaction_pool.get_pending()[0].rebroadcasts, 2);
+
+        // mined transactions are not sent again
+        transaction_pool.pop();
+        clock.advance(interval * 10);
+        assert!(transaction_pool.take_rebroadcasts(interval).is_empty());
+    }
+
+    #[test]
+    fn should_restore_the_saved_transactions() {
+        let path = env::temp_dir().join(format!("mempool_{}.json", std::process::id()));
+        let clock = MockClock::new(0);
+        let transaction_pool = TransactionPool::with_clock(clock.shared());
+        transaction_pool
+            .add_transaction(create_mock_transaction(1))
+            .unwrap();
+        let invalid_id = transaction_pool
+            .add_transaction(create_mock_transaction(2))
+            .unwrap();
+        transaction_pool.save(&path).unwrap();
+
+        // the valid transactions keep waiting since they entered the first pool
+        clock.advance(Duration::from_secs(60));
+        let transaction_pool = TransactionPool::with_clock(clock.shared());
+        let restored = transaction_pool.restore(&path, |transaction| match transaction.amount {
+            1 => Ok(()),
+            _ => Err(anyhow::anyhow!("invalid")),
+        });
+        assert_eq!(restored.unwrap(), (1, 1));
+        let pending = transaction_pool.get_pending();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].age_ms, 60_000);
+
+        // while the rest are dropped
+        let dropped = transaction_pool.get_dropped(invalid_id).unwrap();
+        assert_eq!(dropped.reason, DropReason::Invalid);
+
+        // a corrupted file is reported
+        fs::write(&path, "foo").unwrap();
+        assert!(transaction_pool.restore(&path, |_| Ok(())).is_err());
+        fs::remove_file(&path).unwrap();
+
+        // and a missing one is just an empty pool
+        assert_eq!(transaction_pool.restore(&path, |_| Ok(())).unwrap(), (0, 0));
+    }
+
+    #[test]
+    fn should_bound_the_dropped_transactions() {
+        let mut dropped = DroppedTransactions::default();
+        let transaction = DroppedTransaction {
+            reason: DropReason::Expired,
+            dropped_at: 0,
+        };
+        for id in 0..MAX_DROPPED_TRANSACTIONS as u64 + 1 {
+            dropped.insert(id.into(), transaction);
+        }
+
+        // the oldest one made room for the last one
+        assert_eq!(dropped.transactions.len(), MAX_DROPPED_TRANSACTIONS);
+        assert!(!dropped.transactions.contains_key(&0.into()));
+        assert!(dropped
+            .transactions
+            .contains_key(&(MAX_DROPPED_TRANSACTIONS as u64).into()));
+    }
+
+    #[test]
+    fn should_measure_the_waiting_time_with_the_clock() {
+        let clock = MockClock::new(0);
+        let transaction_pool = TransactionPool::with_clock(clock.shared());
+        transaction_pool
+            .add_transaction(create_mock_transaction(1))
+            .unwrap();
+
+        clock.advance(Duration::from_secs(59));
+        assert_eq!(transaction_pool.get_pending()[0].age_ms, 59_000);
+        assert_eq!(transaction_pool.expire(Duration::from_secs(60)), 0);
+
+        // a second later, the transaction has been waiting for too long
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(transaction_pool.expire(Duration::from_secs(60)), 1);
+        assert!(transaction_pool.get_all().is_empty());
+    }
+
+    #[test]
+    fn should_publish_added_transactions() {
+        let events = EventBus::new();
+        let receiver = events.subscribe();
+        let transaction_pool = TransactionPool::with_events(events);
+
+        transaction_pool
+            .add_transaction(create_mock_transaction(1))
+            .unwrap();
+        let added = receiver.pending();
+        assert_eq!(added.len(), 1);
+        assert!(matches!(&added[0], ChainEvent::TransactionAdmitted(tx) if tx.amount == 1));
+
+        // transactions returned to the pool are not new additions
+        let popped = transaction_pool.pop();
+        transaction_pool.return_transactions(popped);
+        assert!(receiver.pending().is_empty());
+    }
+
     fn create_mock_transaction(amount: u64) -> Transaction {
         Transaction {
             sender: "1".to_string(),
             recipient: "2".to_string(),
-            amount: amount,
+            amount,
+            signature: None,
+            multisig: None,
+            contract: None,
+            token: None,
+            data: None,
         }
     }
 }
@@ -0,0 +1,1595 @@
+mod compact;
+mod message;
+mod network_time;
+mod orphans;
+mod peer_book;
+mod peers;
+mod proofs;
+mod sync;
+#[cfg(test)]
+pub mod testkit;
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    io::{BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use thiserror::Error;
+use tracing::info_span;
+
+use self::{
+    compact::Reconstruction,
+    peers::{Connection, Link, Misbehavior, SyncedConnections},
+};
+use crate::{
+    model::{
+        Block, BlockHash, BlockHeader, Blockchain, BlockchainError, ChainEvent, EventReceiver,
+        Transaction, TransactionId, TransactionPool, TransactionProof,
+    },
+    util::{
+        execution::{sleep_millis, Runnable},
+        random::random_u64,
+        Context,
+    },
+    wallet::Wallet,
+};
+
+// Explicitly controlling which individual identifiers we export
+// It also avoids verbose module imports from other files
+pub use compact::{CompactBlock, CompactBlocks};
+pub use message::{Handshake, Message};
+pub use network_time::NetworkTime;
+pub use orphans::OrphanBlocks;
+pub use peer_book::PeerBook;
+pub use peers::Peers;
+pub use proofs::InclusionProofs;
+pub use sync::ChainSync;
+
+// Max time to wait when connecting or writing to another node
+const CONNECTION_TIMEOUT_MS: u64 = 1000;
+
+// Time interval between attempts to connect to a configured peer
+const RECONNECT_MS: u64 = 1000;
+
+// Max number of transaction ids remembered to avoid relaying the same transaction twice
+const MAX_SEEN_TRANSACTIONS: usize = 10_000;
+
+// Max number of messages per second that a peer can send before being penalized for flooding
+const MAX_MESSAGES_PER_SEC: u64 = 1000;
+
+// Error types to return when communicating with other nodes
+#[derive(Error, PartialEq, Debug)]
+pub enum NetworkError {
+    #[error("Malformed message: {0}")]
+    MalformedMessage(String),
+
+    #[error("Invalid peer address `{0}`")]
+    InvalidAddress(String),
+
+    #[error("Peer `{0}` is banned")]
+    BannedPeer(String),
+
+    #[error("Header {0} does not follow the previous one")]
+    UnlinkedHeader(u64),
+
+    #[error("Header {0} does not match its hash")]
+    InvalidHeaderHash(u64),
+
+    #[error("Unsupported protocol version {0}")]
+    UnsupportedVersion(u32),
+
+    #[error("Peer follows a different chain `{0}`")]
+    ChainIdMismatch(String),
+
+    #[error("Peer has a different genesis block {0:x}")]
+    GenesisMismatch(BlockHash),
+}
+
+// A transaction waiting to be relayed, with the address of the node that sent it to us (if any)
+type RelayedTransaction = (Transaction, Option<String>);
+
+// Ids of the most recent transactions seen by this node, the oldest ones are forgotten first
+#[derive(Debug, Default)]
+struct SeenTransactions {
+    ids: HashSet<TransactionId>,
+    order: VecDeque<TransactionId>,
+}
+
+// Queue of transactions waiting to be relayed to the rest of the network
+// Every transaction is relayed only once, so they don't bounce forever between nodes
+// Cloning only clones the pointer, so the api and the network share the same queue
+#[derive(Debug, Clone, Default)]
+pub struct Gossip {
+    transactions: Arc<Mutex<Vec<RelayedTransaction>>>,
+    seen: Arc<Mutex<SeenTransactions>>,
+}
+
+impl Gossip {
+    pub fn new() -> Gossip {
+        Gossip::default()
+    }
+
+    // Queue a transaction that was just accepted into the pool
+    pub fn relay_transaction(&self, transaction: &Transaction) {
+        self.queue(transaction, None);
+    }
+
+    // Same as above, but the node that sent us the transaction already has it
+    fn relay_network_transaction(&self, transaction: &Transaction, origin: &str) {
+        self.queue(transaction, Some(origin.to_string()));
+    }
+
+    fn queue(&self, transaction: &Transaction, origin: Option<String>) {
+        self.mark_seen(transaction.calculate_id());
+
+        let mut transactions = self.transactions.lock().unwrap();
+        transactions.push((transaction.clone(), origin));
+    }
+
+    // Returns true only the first time a transaction is seen
+    fn mark_seen(&self, id: TransactionId) -> bool {
+        let mut seen = self.seen.lock().unwrap();
+        if !seen.ids.insert(id) {
+            return false;
+        }
+
+        seen.order.push_back(id);
+        if seen.order.len() > MAX_SEEN_TRANSACTIONS {
+            if let Some(oldest_id) = seen.order.pop_front() {
+                seen.ids.remove(&oldest_id);
+            }
+        }
+
+        true
+    }
+
+    fn pop_transactions(&self) -> Vec<RelayedTransaction> {
+        let mut transactions = self.transactions.lock().unwrap();
+        transactions.drain(..).collect()
+    }
+}
+
+// Applies the messages received from other nodes to the blockchain, the transaction pool
+// and the peer book
+#[derive(Clone)]
+struct Handler {
+    node_id: u64,
+    port: u16,
+    chain_id: String,
+    blockchain: Blockchain,
+    pool: TransactionPool,
+    wallet: Wallet,
+    gossip: Gossip,
+    peer_book: PeerBook,
+    sync: ChainSync,
+    orphans: OrphanBlocks,
+    peers: Peers,
+    // Light clients only follow the headers, and check transactions with the proofs of full nodes
+    light_client: bool,
+    proofs: InclusionProofs,
+    // Announced blocks are downloaded as compact blocks from the peers that support them
+    compact_relay: bool,
+    compact_blocks: CompactBlocks,
+    // Offsets of the clocks of the peers, from the time in their hello
+    network_time: NetworkTime,
+}
+
+impl Handler {
+    // Returns the message to reply to the sender, if any
+    fn handle(&self, address: &str, message: Message) -> Option<Message> {
+        let _span = info_span!("p2p", peer = address).entered();
+        match message {
+            Message::Hello(handshake) => {
+                // light clients don't have the transactions, so full nodes can't sync from them
+                let is_light = handshake.capabilities.iter().any(|name| name == "light");
+                // and a chain is only worth following if it took more work than ours, not just if it's longer
+                let has_more_work = handshake.total_work > self.blockchain.total_work();
+                if self.greet(address, &handshake)
+                    && (self.light_client || !is_light)
+                    && has_more_work
+                {
+                    self.sync_with(address, handshake.last_index)
+                } else {
+                    None
+                }
+            }
+            Message::NewBlock(header) => {
+                self.peers.update_height(address, header.index);
+                self.request_block(address, &header)
+            }
+            Message::GetBlock { hash } => self
+                .blockchain
+                .get_block(hash)
+                .filter(|block| !block.pruned)
+                .map(Message::Block),
+            Message::Block(block) => self.add_network_block(address, block),
+            Message::NewTransaction(transaction) => {
+                self.add_transaction(address, transaction);
+                None
+            }
+            Message::GetHeaders { from_index } => {
+                // only the requested range is copied, not the whole chain
+                let last_index = from_index.saturating_add(sync::MAX_HEADERS as u64 - 1);
+                let headers = self
+                    .blockchain
+                    .get_blocks_between(from_index, last_index)
+                    .iter()
+                    .map(Block::header)
+                    .collect();
+                Some(Message::Headers(headers))
+            }
+            Message::Headers(headers) => self.add_headers(address, headers),
+            Message::GetBlocks { hashes } => {
+                let blocks = hashes
+                    .into_iter()
+                    .take(sync::BLOCKS_BATCH)
+                    .filter_map(|hash| self.blockchain.get_block(hash))
+                    // without the transactions the peer couldn't check the merkle and state roots
+                    .filter(|block| !block.pruned)
+                    .collect();
+                Some(Message::Blocks(blocks))
+            }
+            Message::Blocks(blocks) => self.add_synced_blocks(address, &blocks),
+            Message::GetPeers => Some(Message::Peers(self.peer_book.good_addresses())),
+            Message::Peers(addresses) => {
+                // it's the reply to the request sent when connecting
+                self.peers.measure_latency(address);
+                for discovered in addresses.iter() {
+                    self.peer_book.discover(discovered);
+                }
+                None
+            }
+            Message::GetProof { id } => Some(Message::Proof {
+                id,
+                proof: self.blockchain.get_transaction_proof(id),
+            }),
+            Message::Proof { id, proof } => {
+                self.add_proof(address, id, proof);
+                None
+            }
+            Message::GetCompactBlock { hash } => self
+                .blockchain
+                .get_block(hash)
+                .filter(|block| !block.pruned)
+                .map(|block| Message::CompactBlock(CompactBlock::new(&block))),
+            Message::CompactBlock(compact) => self.add_compact_block(address, compact),
+            Message::GetBlockTransactions { hash, indexes } => {
+                let block = self
+                    .blockchain
+                    .get_block(hash)
+                    .filter(|block| !block.pruned)?;
+                // all the positions must exist, otherwise the peer couldn't tell which ones are missing
+                let transactions = indexes
+                    .into_iter()
+                    .map(|index| block.transactions.get(index).cloned())
+                    .collect::<Option<Vec<Transaction>>>()?;
+                Some(Message::BlockTransactions { hash, transactions })
+            }
+            Message::BlockTransactions { hash, transactions } => {
+                let reconstruction = self.compact_blocks.fill(hash, transactions)?;
+                self.add_reconstructed_block(address, hash, reconstruction)
+            }
+        }
+    }
+
+    // Our own introduction, to be sent when a connection is opened
+    fn handshake(&self) -> Handshake {
+        let mut capabilities = Vec::new();
+        if self.blockchain.consensus().can_seal() && !self.light_client {
+            capabilities.push("mining".to_string());
+        }
+        // full nodes have the transactions of their blocks, so they can prove their inclusion
+        if self.light_client {
+            capabilities.push("light".to_string());
+        } else {
+            capabilities.push("proofs".to_string());
+        }
+        // light clients only download headers, so they never need compact blocks
+        if self.compact_relay && !self.light_client {
+            capabilities.push("compact".to_string());
+        }
+
+        Handshake {
+            protocol_version: message::PROTOCOL_VERSION,
+            chain_id: self.chain_id.clone(),
+            genesis_hash: self.blockchain.get_genesis_block().header.hash,
+            node_id: self.node_id,
+            port: self.port,
+            last_index: self.blockchain.get_last_block().header.index,
+            total_work: self.blockchain.total_work(),
+            capabilities,
+            timestamp: self.blockchain.clock().now_millis(),
+        }
+    }
+
+    // Identify the node at the other side of a connection
+    // Returns false if the connection was dropped
+    fn greet(&self, address: &str, handshake: &Handshake) -> bool {
+        let mut connections = self.peers.connections.lock().unwrap();
+        let outbound = match connections.get(address) {
+            Some(connection) => connection.outbound,
+            None => return false,
+        };
+        let node_id = handshake.node_id;
+
+        // e.g. a node from a test network, we don't want to mix our chains
+        if let Err(error) = self.handshake().check_compatibility(handshake) {
+            error!(
+                "Disconnected from incompatible p2p peer {}: {}",
+                address, error
+            );
+            Handler::close(&mut connections, address);
+            if outbound {
+                self.peer_book.ignore(address);
+            }
+            return false;
+        }
+
+        // e.g. another node shared an address that points to us
+        if node_id == self.node_id {
+            info!("p2p address {} points to this node, ignoring it", address);
+            Handler::close(&mut connections, address);
+            self.peer_book.ignore(address);
+            return false;
+        }
+
+        // the same node can be reached with different addresses (e.g. "localhost" and "127.0.0.1")
+        // we only drop our own connections, otherwise both sides could drop theirs
+        let is_duplicate = outbound
+            && connections.iter().any(|(other_address, connection)| {
+                other_address != address
+                    && connection.outbound
+                    && connection.node_id == Some(node_id)
+            });
+        if is_duplicate {
+            info!("already connected to the node at {}, ignoring it", address);
+            Handler::close(&mut connections, address);
+            self.peer_book.ignore(address);
+            return false;
+        }
+
+        // we could connect to the node, or it told us where it listens, so we can share it
+        let dial_address = if outbound {
+            Some(address.to_string())
+        } else {
+            Handler::dial_address(address, handshake.port)
+        };
+
+        // banned peers can still connect to us, but we don't talk to them
+        let peer_id = dial_address.as_deref().unwrap_or(address);
+        if self.peers.is_banned(peer_id) {
+            info!("Disconnected from banned p2p peer {}", peer_id);
+            Handler::close(&mut connections, address);
+            return false;
+        }
+
+        if let Some(dial_address) = &dial_address {
+            self.peer_book.add_good(dial_address);
+        }
+
+        if let Some(connection) = connections.get_mut(address) {
+            connection.node_id = Some(node_id);
+            connection.dial_address = dial_address;
+            connection.capabilities = handshake.capabilities.clone();
+            connection.height = Some(handshake.last_index);
+        }
+        // the timestamp comes from the peer, so it may be anything
+        let offset = handshake
+            .timestamp
+            .saturating_sub(self.blockchain.clock().now_millis());
+        self.network_time.add_sample(address, offset);
+
+        true
+    }
+
+    // Address where a node that connected to us listens for connections
+    fn dial_address(address: &str, port: u16) -> Option<String> {
+        if port == 0 {
+            return None;
+        }
+
+        let socket_address: SocketAddr = address.parse().ok()?;
+        Some(SocketAddr::new(socket_address.ip(), port).to_string())
+    }
+
+    fn close(connections: &mut HashMap<String, Connection>, address: &str) {
+        if let Some(connection) = connections.remove(address) {
+            connection.close();
+        }
+    }
+
+    // Decide whether an announced block is worth downloading
+    // Only blocks that we don't have and that could be appended to our blockchain are requested
+    fn request_block(&self, address: &str, header: &BlockHeader) -> Option<Message> {
+        let last_block = self.blockchain.get_last_block();
+
+        // we already have a block for this index
+        if header.index <= last_block.header.index {
+            return None;
+        }
+
+        // the sender is ahead of us, so we need to synchronize with it
+        if header.index > last_block.header.index + 1 {
+            return self.sync_with(address, header.index);
+        }
+
+        // light clients only follow the headers, so there is nothing else to download
+        // they can't check the work of another branch without its headers, so they only follow ours
+        if self.light_client {
+            if header.previous_hash == last_block.header.hash {
+                self.add_headers_only(address, std::slice::from_ref(header));
+            }
+            return None;
+        }
+
+        // the block may be built on top of another branch, which we follow if it has more work than ours
+        // the blocks of the branch that we are missing are asked for once this one arrives
+        if self.compact_relay && self.has_capability(address, "compact") {
+            return Some(Message::GetCompactBlock { hash: header.hash });
+        }
+        Some(Message::GetBlock { hash: header.hash })
+    }
+
+    fn has_capability(&self, address: &str, capability: &str) -> bool {
+        let connections = self.peers.connections.lock().unwrap();
+        connections.get(address).is_some_and(|connection| {
+            connection
+                .capabilities
+                .iter()
+                .any(|name| name == capability)
+        })
+    }
+
+    // Rebuild a compact block with the transactions of our pool, asking the sender for the missing ones
+    fn add_compact_block(&self, address: &str, compact: CompactBlock) -> Option<Message> {
+        let hash = compact.header.hash;
+        if self.blockchain.contains_block(hash) {
+            return None;
+        }
+
+        // without the parent we can't add it anyway, so the whole block is kept as an orphan
+        if !self.blockchain.contains_block(compact.header.previous_hash) {
+            return Some(Message::GetBlock { hash });
+        }
+
+        let reconstruction = self
+            .compact_blocks
+            .reconstruct(compact, &self.pool.get_all());
+        self.add_reconstructed_block(address, hash, reconstruction)
+    }
+
+    fn add_reconstructed_block(
+        &self,
+        address: &str,
+        hash: BlockHash,
+        reconstruction: Reconstruction,
+    ) -> Option<Message> {
+        match reconstruction {
+            Reconstruction::Complete(block) => self.add_network_block(address, *block),
+            Reconstruction::Missing(indexes) => {
+                Some(Message::GetBlockTransactions { hash, indexes })
+            }
+            // e.g. two transactions share a short id, so we just download the whole block
+            Reconstruction::Failed => {
+                info!("Could not rebuild compact block {:x}, downloading it", hash);
+                Some(Message::GetBlock { hash })
+            }
+        }
+    }
+
+    // Start synchronizing with a node that is ahead of us, unless we are already doing it
+    // Returns the first request to send to the node
+    fn sync_with(&self, address: &str, last_index: u64) -> Option<Message> {
+        let our_last_index = self.blockchain.get_last_block().header.index;
+        if last_index <= our_last_index || !self.sync.start(address, last_index) {
+            return None;
+        }
+
+        info!(
+            "Synchronizing with p2p peer {} up to block {}",
+            address, last_index
+        );
+        self.sync.next_request(our_last_index)
+    }
+
+    // Validate the chain of headers sent by the node we are synchronizing with
+    fn add_headers(&self, address: &str, headers: Vec<BlockHeader>) -> Option<Message> {
+        if !self.sync.is_syncing_with(address) {
+            return None;
+        }
+
+        let last_block = self.blockchain.get_last_block();
+        if let Err(error) = self.sync.add_headers(headers.clone(), &last_block) {
+            error!("Stopped synchronizing with p2p peer {}: {}", address, error);
+            self.sync.cancel();
+            return None;
+        }
+
+        // light clients are done once they have the headers, they never download the blocks
+        if self.light_client {
+            if !self.add_headers_only(address, &headers) {
+                error!("Stopped synchronizing with p2p peer {}", address);
+                self.sync.cancel();
+                return None;
+            }
+
+            let last_index = self.blockchain.get_last_block().header.index;
+            self.sync.remove_downloaded(last_index);
+            let request = self.sync.next_request(last_index);
+            if request.is_none() {
+                info!("Finished synchronizing headers with p2p peer {}", address);
+            }
+            return request;
+        }
+
+        self.sync.next_request(last_block.header.index)
+    }
+
+    // Apply a batch of blocks and ask for the next one, reporting the progress
+    fn add_synced_blocks(&self, address: &str, blocks: &[Block]) -> Option<Message> {
+        let is_valid = self.add_blocks(address, blocks);
+        if !self.sync.is_syncing_with(address) {
+            return None;
+        }
+
+        if !is_valid {
+            error!("Stopped synchronizing with p2p peer {}", address);
+            self.sync.cancel();
+            return None;
+        }
+
+        let last_index = self.blockchain.get_last_block().header.index;
+        self.sync.remove_downloaded(last_index);
+        if let Some(target_index) = self.sync.target_index() {
+            info!(
+                "Synchronized {} of {} blocks with p2p peer {}",
+                last_index, target_index, address
+            );
+        }
+
+        let request = self.sync.next_request(last_index);
+        if request.is_none() {
+            info!("Finished synchronizing with p2p peer {}", address);
+        }
+
+        request
+    }
+
+    // A block whose parent we don't have is kept as an orphan, and the parent is asked to the sender
+    // Otherwise it's added along with the orphans that descend from it, which may be a fork with more work
+    fn add_network_block(&self, address: &str, block: Block) -> Option<Message> {
+        if self.blockchain.contains_block(block.header.hash) {
+            return None;
+        }
+
+        let parent = block.header.previous_hash;
+        if !self.blockchain.contains_block(parent) {
+            // the rest of the rules need the parent, but a wrong hash is enough to drop it right away
+            if block.header.hash != block.calculate_hash() {
+                self.penalize(address, Misbehavior::InvalidBlock);
+                return None;
+            }
+            let index = block.header.index;
+            if !self.orphans.add(block) {
+                return None;
+            }
+            info!(
+                "Keeping network block {} until its parent arrives ({} orphans)",
+                index,
+                self.orphans.count()
+            );
+            return Some(Message::GetBlock { hash: parent });
+        }
+
+        // the parent of an orphan is asked to the node that sent it, so the whole branch usually comes from it
+        let mut blocks = vec![block];
+        blocks.extend(self.orphans.take_descendants(blocks[0].header.hash));
+        self.add_blocks(address, &blocks);
+
+        None
+    }
+
+    // Try to append blocks through the normal validation, on top of our last block or of an earlier one
+    // if they are a fork with more work than our chain
+    // They are added as a batch, so if any of them is invalid none is added
+    // Returns false if they could not be added
+    fn add_blocks(&self, address: &str, blocks: &[Block]) -> bool {
+        // we already have some of them
+        let new_blocks: Vec<Block> = blocks
+            .iter()
+            .filter(|block| !self.blockchain.contains_block(block.header.hash))
+            .cloned()
+            .collect();
+        let (first, last) = match (new_blocks.first(), new_blocks.last()) {
+            (Some(first), Some(last)) => (first.header.index, last.header.index),
+            _ => return true,
+        };
+
+        // the blocks that we asked for match headers that we already checked, so they are not hashed again
+        let result = match self.sync.seal(&new_blocks) {
+            Some(sealed_blocks) => self.blockchain.add_sealed_blocks(sealed_blocks),
+            None => self.blockchain.add_blocks(new_blocks),
+        };
+        if let Err(error) = result {
+            error!(
+                "Could not add network blocks {}-{}: {:#}",
+                first, last, error
+            );
+            if Handler::is_forged(&error) {
+                self.penalize(address, Misbehavior::InvalidBlock);
+            }
+            return false;
+        }
+
+        info!(
+            "Added new network blocks {}-{} to the blockchain",
+            first, last
+        );
+        true
+    }
+
+    // Light clients append the headers that follow our last one, with the same validation as the blocks
+    // Returns false if any of them could not be added
+    fn add_headers_only(&self, address: &str, headers: &[BlockHeader]) -> bool {
+        for header in headers.iter() {
+            // we already have a block for this index
+            if header.index <= self.blockchain.get_last_block().header.index {
+                continue;
+            }
+
+            if let Err(error) = self.blockchain.add_header(header.clone()) {
+                error!("Could not add network header {}: {}", header.index, error);
+                if Handler::is_forged(&error) {
+                    self.penalize(address, Misbehavior::InvalidBlock);
+                }
+                return false;
+            }
+
+            info!(
+                "Added new network header {} to the blockchain",
+                header.index
+            );
+        }
+
+        true
+    }
+
+    // Light clients keep the proofs that match the header of the block in our chain
+    // Honest nodes never send a proof that doesn't match a block they have, so it must be forged
+    fn add_proof(&self, address: &str, id: TransactionId, proof: Option<TransactionProof>) {
+        let proof = match proof {
+            Some(proof) if self.light_client && proof.transaction_id == id => proof,
+            _ => return,
+        };
+
+        // e.g. we are not synchronized up to the block yet
+        let header = match self.blockchain.get_block_at(proof.block_index) {
+            Some(block) if block.header.hash == proof.block_hash => block.header,
+            _ => return,
+        };
+
+        if proof.verify(&header) {
+            info!(
+                "Verified the inclusion of transaction {:x} in block {}",
+                id, proof.block_index
+            );
+            self.proofs.add_verified(proof);
+        } else {
+            error!(
+                "Invalid proof of transaction {:x} from p2p peer {}",
+                id, address
+            );
+            self.penalize(address, Misbehavior::InvalidProof);
+        }
+    }
+
+    // Honest nodes may send blocks that don't fit in our chain (e.g. after a fork)
+    // but they never send blocks with invalid hashes, targets or signatures
+    fn is_forged(error: &anyhow::Error) -> bool {
+        matches!(
+            error.downcast_ref::<BlockchainError>(),
+            Some(BlockchainError::InvalidHash)
+                | Some(BlockchainError::InvalidDifficulty)
+                | Some(BlockchainError::InvalidSignature)
+                | Some(BlockchainError::InvalidTarget)
+        )
+    }
+
+    fn penalize(&self, address: &str, misbehavior: Misbehavior) {
+        if self.peers.penalize(address, misbehavior) {
+            warn!("Banned p2p peer {} after {:?}", address, misbehavior);
+        }
+    }
+
+    // Transactions follow the same rules as the ones received in the api
+    // and they are relayed only the first time, so they don't bounce forever between nodes
+    fn add_transaction(&self, address: &str, transaction: Transaction) {
+        let id = transaction.calculate_id();
+
+        // we don't even validate the transactions we have already seen
+        if !self.gossip.mark_seen(id) || self.blockchain.contains_transaction(id) {
+            return;
+        }
+
+        let check = self
+            .wallet
+            .check_transaction(&transaction, &self.blockchain.chain_id())
+            .and_then(|_| self.blockchain.check_transaction(&transaction));
+        if let Err(error) = check {
+            error!("Rejected network transaction {:x}: {}", id, error);
+            return;
+        }
+
+        if self.pool.add_transaction(transaction.clone()).is_ok() {
+            info!("Added new network transaction {:x} to the pool", id);
+            self.gossip.relay_network_transaction(&transaction, address);
+        }
+    }
+}
+
+// Peer to peer communication with other nodes over TCP
+// Nodes announce their new blocks, relay transactions, request the blocks they are missing
+// and share the addresses of the nodes they know, so new nodes only need a seed to join
+pub struct Network {
+    peer_addresses: Vec<String>,
+    seed_addresses: Vec<String>,
+    max_peers: usize,
+    announce_ms: u64,
+    handler: Handler,
+}
+
+impl Runnable for Network {
+    fn run(&self) -> Result<()> {
+        self.start()
+    }
+}
+
+impl Network {
+    pub fn new(context: &Context) -> Network {
+        Network {
+            peer_addresses: context.config.p2p_peers.clone(),
+            seed_addresses: context.config.p2p_seeds.clone(),
+            max_peers: context.config.p2p_max_peers,
+            announce_ms: context.config.p2p_announce_ms,
+            handler: Handler {
+                node_id: random_u64(),
+                port: context.config.p2p_port,
+                chain_id: context.config.chain_id.clone(),
+                blockchain: context.blockchain.clone(),
+                pool: context.pool.clone(),
+                wallet: context.wallet.clone(),
+                gossip: context.gossip.clone(),
+                peer_book: context.peer_book.clone(),
+                sync: ChainSync::new(),
+                orphans: OrphanBlocks::new(context.blockchain.clock()),
+                peers: context.peers.clone(),
+                light_client: context.config.light_client,
+                proofs: context.proofs.clone(),
+                compact_relay: context.config.p2p_compact_blocks,
+                compact_blocks: CompactBlocks::new(),
+                network_time: context.network_time.clone(),
+            },
+        }
+    }
+
+    pub fn start(&self) -> Result<()> {
+        let port = self.handler.port;
+        let has_peers = !self.peer_addresses.is_empty()
+            || !self.seed_addresses.is_empty()
+            || !self.handler.peer_book.candidates().is_empty();
+        // operators can still add peers with the api, so the network keeps running
+        if port == 0 && !has_peers {
+            info!("No p2p port or peers configured, waiting for peers added with the api");
+        }
+
+        // other nodes can connect to us only if we listen on a port
+        if port != 0 {
+            let listener = TcpListener::bind(("localhost", port))?;
+            info!("listening for p2p connections on port {}", port);
+
+            let handler = self.handler.clone();
+            thread::spawn(move || Network::accept_connections(listener, handler));
+        }
+
+        // At regular intervals of time, we announce our new blocks and transactions
+        let events = self.handler.blockchain.events().subscribe();
+        let mut last_attempts = HashMap::new();
+        loop {
+            self.connect_to_peers(&mut last_attempts);
+            self.announce(&events);
+            sleep_millis(self.announce_ms);
+        }
+    }
+
+    // Send what changed since the last time to the connected nodes
+    fn announce(&self, events: &EventReceiver) {
+        // transactions go first, as the new blocks may already include them
+        // there is no need to send a transaction back to the node that sent it
+        for (transaction, origin) in self.handler.gossip.pop_transactions() {
+            let message = Message::NewTransaction(transaction);
+            self.broadcast(&message, origin.as_deref());
+        }
+
+        // light clients ask the nodes that serve proofs for the ones requested in the api
+        for id in self.handler.proofs.pop_requests() {
+            self.broadcast_to_capable(&Message::GetProof { id }, "proofs");
+        }
+
+        // light clients can't serve the blocks, so there is no point in announcing them
+        for event in events.pending() {
+            match event {
+                ChainEvent::BlockAdded(block) if !self.handler.light_client => {
+                    self.broadcast(&Message::NewBlock(block.header()), None)
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn accept_connections(listener: TcpListener, handler: Handler) {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let address = match stream.peer_addr() {
+                        Ok(address) => address.to_string(),
+                        Err(_) => continue,
+                    };
+                    info!("Accepted p2p connection from {}", address);
+                    Network::open_connection(address, stream, false, &handler);
+                }
+                Err(error) => error!("Could not accept p2p connection: {}", error),
+            }
+        }
+    }
+
+    // Try to connect to the configured peers and to the peers in the book we are not connected to
+    // Seeds are only used to join the network, when we don't know any other peer
+    fn connect_to_peers(&self, last_attempts: &mut HashMap<String, Instant>) {
+        let peer_book = &self.handler.peer_book;
+        let mut candidates = peer_book.candidates();
+        if candidates.is_empty() {
+            candidates = self.seed_addresses.clone();
+        }
+
+        // peers added with the api are treated as configured ones
+        let dials = self.handler.peers.dials();
+        let configured = self
+            .peer_addresses
+            .iter()
+            .chain(dials.iter())
+            .map(|address| (address, true));
+        let discovered = candidates.iter().map(|address| (address, false));
+        for (address, is_configured) in configured.chain(discovered) {
+            if peer_book.is_ignored(address)
+                || self.handler.peers.is_banned(address)
+                || self.is_connected_to(address)
+            {
+                continue;
+            }
+
+            // configured peers are always connected, the rest only up to a limit
+            if !is_configured && self.count_outbound_connections() >= self.max_peers {
+                continue;
+            }
+
+            // we don't want to flood unavailable peers with connection attempts
+            let retry_interval = Duration::from_millis(RECONNECT_MS);
+            if let Some(last_attempt) = last_attempts.get(address) {
+                if last_attempt.elapsed() < retry_interval {
+                    continue;
+                }
+            }
+            last_attempts.insert(address.to_string(), Instant::now());
+
+            match Network::connect(address) {
+                Ok(stream) => {
+                    info!("Connected to p2p peer {}", address);
+                    Network::open_connection(address.to_string(), stream, true, &self.handler);
+                }
+                Err(error) => {
+                    error!("Could not connect to p2p peer {}: {}", address, error);
+                    if !is_configured {
+                        peer_book.forget(address);
+                    }
+                }
+            }
+        }
+    }
+
+    // A node that connected to us may be listening in the address
+    fn is_connected_to(&self, address: &str) -> bool {
+        let connections = self.handler.peers.connections.lock().unwrap();
+        connections.contains_key(address)
+            || connections
+                .values()
+                .any(|connection| connection.dial_address.as_deref() == Some(address))
+    }
+
+    fn count_outbound_connections(&self) -> usize {
+        let connections = self.handler.peers.connections.lock().unwrap();
+        connections
+            .values()
+            .filter(|connection| connection.outbound)
+            .count()
+    }
+
+    fn connect(address: &str) -> Result<TcpStream> {
+        let socket_address = address
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| NetworkError::InvalidAddress(address.to_string()))?;
+        let timeout = Duration::from_millis(CONNECTION_TIMEOUT_MS);
+
+        Ok(TcpStream::connect_timeout(&socket_address, timeout)?)
+    }
+
+    // Register a new connection and start reading its messages in a separate thread
+    fn open_connection(address: String, stream: TcpStream, outbound: bool, handler: &Handler) {
+        let reader = match stream.try_clone() {
+            Ok(reader) => reader,
+            Err(_) => return,
+        };
+        let timeout = Some(Duration::from_millis(CONNECTION_TIMEOUT_MS));
+        if stream.set_write_timeout(timeout).is_err() {
+            return;
+        }
+        Network::add_connection(&address, stream, outbound, handler);
+
+        let handler = handler.clone();
+        thread::spawn(move || Network::read_messages(address, reader, handler));
+    }
+
+    // Both sides introduce themselves and ask for the peers they are missing right away
+    // The one that is behind will start synchronizing after the hello of the other
+    fn add_connection(address: &str, link: impl Link + 'static, outbound: bool, handler: &Handler) {
+        let connection = Connection::new(link, outbound);
+        let connections = &handler.peers.connections;
+        connections
+            .lock()
+            .unwrap()
+            .insert(address.to_string(), connection);
+
+        let hello = Message::Hello(handler.handshake());
+        Network::send(connections, address, &hello);
+        Network::send(connections, address, &Message::GetPeers);
+    }
+
+    // Handle all the messages received through a connection, until it's closed
+    fn read_messages(address: String, stream: TcpStream, handler: Handler) {
+        let mut window_start = Instant::now();
+        let mut window_messages = 0;
+        let mut reader = BufReader::new(stream);
+        while let Ok(payload) = message::read_frame(&mut reader) {
+            // a node sending too many messages is probably trying to exhaust our resources
+            if window_start.elapsed() >= Duration::from_secs(1) {
+                window_start = Instant::now();
+                window_messages = 0;
+            }
+            window_messages += 1;
+            if window_messages == MAX_MESSAGES_PER_SEC {
+                handler.penalize(&address, Misbehavior::Flooding);
+            }
+
+            // we don't want to drop the connection because of a single bad message
+            let message = match Message::decode(&payload) {
+                Ok(message) => message,
+                Err(error) => {
+                    error!("Ignoring message from p2p peer {}: {}", address, error);
+                    handler.penalize(&address, Misbehavior::MalformedMessage);
+                    continue;
+                }
+            };
+
+            Network::receive(&handler, &address, message);
+        }
+
+        info!("Disconnected from p2p peer {}", address);
+        Network::remove_connection(&handler, &address);
+    }
+
+    fn receive(handler: &Handler, address: &str, message: Message) {
+        if let Some(reply) = handler.handle(address, message) {
+            Network::send(&handler.peers.connections, address, &reply);
+        }
+    }
+
+    fn remove_connection(handler: &Handler, address: &str) {
+        handler.peers.connections.lock().unwrap().remove(address);
+        handler.network_time.remove_sample(address);
+        if handler.sync.is_syncing_with(address) {
+            handler.sync.cancel();
+        }
+    }
+
+    // Send a message to all the connected nodes, except the one in "skipped_address" (if any)
+    fn broadcast(&self, message: &Message, skipped_address: Option<&str>) {
+        let connections = &self.handler.peers.connections;
+        let addresses: Vec<String> = connections
+            .lock()
+            .unwrap()
+            .keys()
+            .filter(|address| Some(address.as_str()) != skipped_address)
+            .cloned()
+            .collect();
+        for address in addresses.iter() {
+            Network::send(connections, address, message);
+        }
+    }
+
+    // Send a message to the connected nodes that announced a capability (e.g. serving proofs)
+    fn broadcast_to_capable(&self, message: &Message, capability: &str) {
+        let connections = &self.handler.peers.connections;
+        let addresses: Vec<String> = connections
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, connection)| {
+                connection
+                    .capabilities
+                    .iter()
+                    .any(|name| name == capability)
+            })
+            .map(|(address, _)| address.clone())
+            .collect();
+        for address in addresses.iter() {
+            Network::send(connections, address, message);
+        }
+    }
+
+    // Send a message through a connection, dropping the connection if it's not working
+    // Configured peers will be connected again later
+    fn send(connections: &SyncedConnections, address: &str, message: &Message) {
+        let mut connections = connections.lock().unwrap();
+        let connection = match connections.get_mut(address) {
+            Some(connection) => connection,
+            None => return,
+        };
+
+        if let Err(error) = connection.stream.write_all(&message.encode()) {
+            error!("Could not send message to p2p peer {}: {}", address, error);
+            Handler::close(&mut connections, address);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{consensus::ProofOfWork, model::BlockHash, wallet::WalletMode};
+
+    #[test]
+    fn should_fetch_announced_blocks_following_the_last_one() {
+        let (handler, other_blockchain) = create_handlers();
+        let blocks = add_blocks(&other_blockchain, 1);
+
+        // the announced block follows our last block, so we ask for the whole of it
+        let reply = handler.handle("a:1", Message::NewBlock(blocks[0].header()));
+        assert!(matches!(reply, Some(Message::GetBlock { hash }) if hash == blocks[0].header.hash));
+
+        let reply = handler.handle("a:1", Message::Block(blocks[0].clone()));
+        assert!(reply.is_none());
+        assert_eq!(handler.blockchain.get_last_block().header.index, 1);
+
+        // blocks we already have are ignored
+        let reply = handler.handle("a:1", Message::NewBlock(blocks[0].header()));
+        assert!(reply.is_none());
+        let reply = handler.handle("a:1", Message::Block(blocks[0].clone()));
+        assert!(reply.is_none());
+        assert_eq!(handler.blockchain.get_last_block().header.index, 1);
+    }
+
+    #[test]
+    fn should_download_only_the_missing_transactions_of_compact_blocks() {
+        let (handler, other_blockchain) = create_handlers();
+        let sender = create_sender(&other_blockchain);
+        let transactions = vec![create_transaction(1), create_transaction(2)];
+        let block = add_block(&other_blockchain, transactions.clone());
+        handler
+            .pool
+            .add_transaction(transactions[0].clone())
+            .unwrap();
+
+        // peers without compact blocks get the whole block
+        add_outbound_connection(&handler, "a:1");
+        let reply = handler.handle("a:1", Message::NewBlock(block.header()));
+        assert!(matches!(reply, Some(Message::GetBlock { .. })));
+
+        handler
+            .peers
+            .connections
+            .lock()
+            .unwrap()
+            .get_mut("a:1")
+            .unwrap()
+            .capabilities = vec!["compact".to_string()];
+        let request = handler.handle("a:1", Message::NewBlock(block.header()));
+        assert!(
+            matches!(request, Some(Message::GetCompactBlock { hash }) if hash == block.header.hash)
+        );
+
+        // only the transaction that is not in the pool is asked for
+        let compact = sender.handle("b:2", request.unwrap());
+        let request = handler.handle("a:1", compact.unwrap());
+        assert!(
+            matches!(&request, Some(Message::GetBlockTransactions { indexes, .. }) if indexes == &vec![1])
+        );
+
+        let reply = sender.handle("b:2", request.unwrap());
+        assert!(handler.handle("a:1", reply.unwrap()).is_none());
+        assert_eq!(
+            handler.blockchain.get_last_block().header.hash,
+            block.header.hash
+        );
+    }
+
+    #[test]
+    fn should_keep_blocks_until_their_parent_arrives() {
+        let (handler, other_blockchain) = create_handlers();
+        let blocks = add_blocks(&other_blockchain, 2);
+
+        // the parent of the orphan is asked to the node that sent it
+        let reply = handler.handle("a:1", Message::Block(blocks[1].clone()));
+        assert!(matches!(reply, Some(Message::GetBlock { hash }) if hash == blocks[0].header.hash));
+        assert_eq!(handler.blockchain.get_last_block().header.index, 0);
+        assert_eq!(handler.orphans.count(), 1);
+
+        // and the orphan follows it once it arrives
+        let reply = handler.handle("a:1", Message::Block(blocks[0].clone()));
+        assert!(reply.is_none());
+        assert_eq!(handler.blockchain.get_last_block().header.index, 2);
+        assert_eq!(handler.orphans.count(), 0);
+    }
+
+    #[test]
+    fn should_not_keep_orphans_with_invalid_hashes() {
+        let (handler, other_blockchain) = create_handlers();
+        let mut block = add_blocks(&other_blockchain, 2)[1].clone();
+        block.header.nonce += 1;
+
+        let reply = handler.handle("a:1", Message::Block(block));
+        assert!(reply.is_none());
+        assert_eq!(handler.orphans.count(), 0);
+    }
+
+    #[test]
+    fn should_serve_blocks_by_hash() {
+        let (_, other_blockchain) = create_handlers();
+        let blocks = add_blocks(&other_blockchain, 2);
+        let sender = create_sender(&other_blockchain);
+
+        let reply = sender.handle(
+            "a:1",
+            Message::GetBlock {
+                hash: blocks[0].header.hash,
+            },
+        );
+        assert!(
+            matches!(reply, Some(Message::Block(block)) if block.header.hash == blocks[0].header.hash)
+        );
+
+        // we don't reply for blocks we don't have
+        let hash = BlockHash::from(1);
+        assert!(sender.handle("a:1", Message::GetBlock { hash }).is_none());
+    }
+
+    #[test]
+    fn should_sync_with_peers_ahead() {
+        let (handler, other_blockchain) = create_handlers();
+        let blocks = add_blocks(&other_blockchain, 3);
+        let sender = create_sender(&other_blockchain);
+
+        // the sender is ahead of us, so we ask for the headers after our last block
+        let request = handler.handle("a:1", Message::NewBlock(blocks[2].header()));
+        assert!(matches!(
+            request,
+            Some(Message::GetHeaders { from_index: 1 })
+        ));
+
+        // once the headers are validated, we ask for the blocks
+        let headers = sender.handle("b:2", request.unwrap()).unwrap();
+        let request = handler.handle("a:1", headers);
+        assert!(matches!(&request, Some(Message::GetBlocks { hashes }) if hashes.len() == 3));
+
+        let blocks = sender.handle("b:2", request.unwrap()).unwrap();
+        assert!(handler.handle("a:1", blocks).is_none());
+        assert_eq!(handler.blockchain.get_last_block().header.index, 3);
+        assert!(!handler.sync.is_syncing_with("a:1"));
+    }
+
+    #[test]
+    fn should_stop_syncing_on_invalid_blocks() {
+        let (handler, other_blockchain) = create_handlers();
+        let mut blocks = add_blocks(&other_blockchain, 2);
+        let headers = blocks.iter().map(Block::header).collect();
+
+        handler.handle("a:1", Message::NewBlock(blocks[1].header()));
+        handler.handle("a:1", Message::Headers(headers));
+
+        // the block does not match its hash anymore
+        blocks[1].header.nonce += 1;
+        assert!(handler.handle("a:1", Message::Blocks(blocks)).is_none());
+        // the blocks are added as a batch, so the valid one before it is not added either
+        assert_eq!(handler.blockchain.get_last_block().header.index, 0);
+        assert!(!handler.sync.is_syncing_with("a:1"));
+    }
+
+    #[test]
+    fn should_ban_peers_sending_forged_blocks() {
+        let (handler, other_blockchain) = create_handlers();
+        add_outbound_connection(&handler, "a:1");
+        let mut block = add_blocks(&other_blockchain, 1).remove(0);
+        block.header.nonce += 1;
+
+        // the hash of the block does not match its contents
+        handler.handle("a:1", Message::Block(block.clone()));
+        assert!(!handler.peers.is_banned("a:1"));
+
+        handler.handle("a:1", Message::Block(block));
+        assert!(handler.peers.is_banned("a:1"));
+        assert!(handler.peers.connections.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn should_only_sync_headers_as_light_client() {
+        let handler = create_light_handler();
+        let other_blockchain = Blockchain::new(ProofOfWork::shared(0, 1, 1));
+        let blocks = add_blocks(&other_blockchain, 3);
+        let sender = create_sender(&other_blockchain);
+
+        // the headers are all we need, so the synchronization finishes with them
+        let request = handler.handle("a:1", Message::NewBlock(blocks[2].header()));
+        assert!(matches!(
+            request,
+            Some(Message::GetHeaders { from_index: 1 })
+        ));
+        let headers = sender.handle("b:2", request.unwrap()).unwrap();
+        assert!(handler.handle("a:1", headers).is_none());
+        assert!(!handler.sync.is_syncing_with("a:1"));
+
+        let last_block = handler.blockchain.get_last_block();
+        assert_eq!(last_block.header, blocks[2].header);
+        assert!(last_block.pruned);
+
+        // new blocks are followed with their announcement alone
+        let block = add_blocks(&other_blockchain, 1).remove(0);
+        assert!(handler
+            .handle("a:1", Message::NewBlock(block.header()))
+            .is_none());
+        assert_eq!(handler.blockchain.get_last_block().header, block.header);
+    }
+
+    #[test]
+    fn should_verify_proofs_as_light_client() {
+        let handler = create_light_handler();
+        add_outbound_connection(&handler, "a:1");
+        let other_blockchain = Blockchain::new(ProofOfWork::shared(0, 1, 1));
+        let sender = create_sender(&other_blockchain);
+        let transactions: Vec<Transaction> = (1..=3).map(create_transaction).collect();
+        let block = add_block(&other_blockchain, transactions.clone());
+        handler.handle("a:1", Message::NewBlock(block.header()));
+
+        // full nodes prove that their blocks include a transaction
+        let id = transactions[2].calculate_id();
+        let reply = sender.handle("b:2", Message::GetProof { id }).unwrap();
+        let proof = match &reply {
+            Message::Proof { proof, .. } => proof.clone().unwrap(),
+            message => panic!("unexpected message {:?}", message),
+        };
+        handler.handle("a:1", reply);
+        assert_eq!(handler.proofs.get(id), Some(proof.clone()));
+
+        // there is nothing to prove for unknown transactions
+        let unknown_id = create_transaction(4).calculate_id();
+        let reply = sender.handle("b:2", Message::GetProof { id: unknown_id });
+        assert!(matches!(reply, Some(Message::Proof { proof: None, .. })));
+
+        // a proof that doesn't match the header of the block is forged
+        let other_id = transactions[0].calculate_id();
+        let mut forged_proof = proof;
+        forged_proof.transaction_id = other_id;
+        for _ in 0..2 {
+            let message = Message::Proof {
+                id: other_id,
+                proof: Some(forged_proof.clone()),
+            };
+            handler.handle("a:1", message);
+        }
+        assert_eq!(handler.proofs.get(other_id), None);
+        assert!(handler.peers.is_banned("a:1"));
+    }
+
+    #[test]
+    fn should_not_sync_full_nodes_with_light_clients() {
+        let (handler, other_blockchain) = create_handlers();
+        add_blocks(&other_blockchain, 2);
+        let light_handler = Handler {
+            light_client: true,
+            ..create_sender(&other_blockchain)
+        };
+
+        // the light client is ahead, but it can't send the blocks
+        add_outbound_connection(&handler, "a:1");
+        let hello = Message::Hello(light_handler.handshake());
+        assert!(handler.handle("a:1", hello).is_none());
+
+        // while a full node can
+        add_outbound_connection(&handler, "b:2");
+        let hello = Message::Hello(create_sender(&other_blockchain).handshake());
+        assert!(matches!(
+            handler.handle("b:2", hello),
+            Some(Message::GetHeaders { from_index: 1 })
+        ));
+    }
+
+    #[test]
+    fn should_serve_headers_from_an_index() {
+        let (_, other_blockchain) = create_handlers();
+        add_blocks(&other_blockchain, 3);
+        let sender = create_sender(&other_blockchain);
+
+        match sender.handle("a:1", Message::GetHeaders { from_index: 2 }) {
+            Some(Message::Headers(headers)) => {
+                let indexes: Vec<u64> = headers.iter().map(|header| header.index).collect();
+                assert_eq!(indexes, vec![2, 3]);
+            }
+            reply => panic!("unexpected reply {:?}", reply),
+        }
+    }
+
+    #[test]
+    fn should_relay_new_transactions_only_once() {
+        let (handler, _) = create_handlers();
+        let transaction = Transaction {
+            sender: "1".to_string(),
+            recipient: "2".to_string(),
+            amount: 3,
+            signature: None,
+            multisig: None,
+            contract: None,
+            token: None,
+            data: None,
+        };
+
+        handler.handle("a:1", Message::NewTransaction(transaction.clone()));
+        assert_eq!(handler.pool.get_all().len(), 1);
+
+        // it's not relayed back to the node that sent it
+        let relayed = handler.gossip.pop_transactions();
+        assert_eq!(relayed.len(), 1);
+        assert_eq!(relayed[0].1.as_deref(), Some("a:1"));
+
+        // the transaction bounces back from another node
+        handler.handle("b:2", Message::NewTransaction(transaction));
+        assert_eq!(handler.pool.get_all().len(), 1);
+        assert!(handler.gossip.pop_transactions().is_empty());
+    }
+
+    #[test]
+    fn should_not_relay_transactions_seen_before() {
+        let (handler, _) = create_handlers();
+        let transaction = Transaction {
+            sender: "1".to_string(),
+            recipient: "2".to_string(),
+            amount: 3,
+            signature: None,
+            multisig: None,
+            contract: None,
+            token: None,
+            data: None,
+        };
+
+        // the transaction was submitted to our api, then mined and removed from the pool
+        handler.gossip.relay_transaction(&transaction);
+        handler.gossip.pop_transactions();
+
+        handler.handle("a:1", Message::NewTransaction(transaction));
+        assert!(handler.pool.get_all().is_empty());
+        assert!(handler.gossip.pop_transactions().is_empty());
+    }
+
+    #[test]
+    fn should_forget_the_oldest_seen_transactions() {
+        let gossip = Gossip::new();
+        for id in 0..MAX_SEEN_TRANSACTIONS + 1 {
+            assert!(gossip.mark_seen(TransactionId::from(id)));
+        }
+
+        assert!(!gossip.mark_seen(TransactionId::from(MAX_SEEN_TRANSACTIONS)));
+        assert!(gossip.mark_seen(TransactionId::from(0)));
+    }
+
+    #[test]
+    fn should_exchange_peers() {
+        let (handler, _) = create_handlers();
+        handler.peer_book.add_good("b:2");
+
+        // we share our good peers
+        let reply = handler.handle("a:1", Message::GetPeers);
+        assert!(matches!(reply, Some(Message::Peers(addresses)) if addresses == vec!["b:2"]));
+
+        // and remember the ones shared with us to try them later
+        handler.handle("a:1", Message::Peers(vec!["c:3".to_string()]));
+        assert_eq!(handler.peer_book.candidates(), vec!["b:2", "c:3"]);
+    }
+
+    #[test]
+    fn should_ignore_connections_to_itself() {
+        let (handler, _) = create_handlers();
+        add_outbound_connection(&handler, "a:1");
+
+        // the node at the other side has our own id
+        let hello = Message::Hello(handler.handshake());
+        handler.handle("a:1", hello);
+
+        assert!(handler.peers.connections.lock().unwrap().is_empty());
+        assert!(handler.peer_book.is_ignored("a:1"));
+    }
+
+    #[test]
+    fn should_drop_peers_from_other_chains() {
+        let (handler, _) = create_handlers();
+        add_outbound_connection(&handler, "a:1");
+
+        let mut handshake = handler.handshake();
+        handshake.node_id = random_u64();
+        handshake.chain_id = "testnet".to_string();
+        handler.handle("a:1", Message::Hello(handshake));
+
+        assert!(handler.peers.connections.lock().unwrap().is_empty());
+        assert!(handler.peer_book.is_ignored("a:1"));
+    }
+
+    #[test]
+    fn should_accept_peers_from_the_same_chain() {
+        let (handler, _) = create_handlers();
+        add_outbound_connection(&handler, "a:1");
+
+        let mut handshake = handler.handshake();
+        handshake.node_id = random_u64();
+        handler.handle("a:1", Message::Hello(handshake));
+
+        assert_eq!(handler.peers.connections.lock().unwrap().len(), 1);
+        assert_eq!(handler.peer_book.good_addresses(), vec!["a:1"]);
+    }
+
+    #[test]
+    fn should_compare_our_clock_with_the_peers() {
+        let (handler, _) = create_handlers();
+        for address in ["a:1", "b:2", "c:3"].iter() {
+            add_outbound_connection(&handler, address);
+            // the clocks of the peers are 10 minutes ahead of ours
+            let mut handshake = handler.handshake();
+            handshake.node_id = random_u64();
+            handshake.timestamp += 600_000;
+            handler.handle(address, Message::Hello(handshake));
+        }
+
+        let offset = handler.network_time.drift_ms().unwrap();
+        assert!((599_000..=600_000).contains(&offset));
+
+        // with too few peers, the time of the network is unknown
+        Network::remove_connection(&handler, "a:1");
+        assert_eq!(handler.network_time.offset_ms(), None);
+    }
+
+    #[test]
+    fn should_bound_the_clock_offsets_of_the_peers() {
+        let (handler, _) = create_handlers();
+        let timestamps = [i64::MIN, i64::MAX, i64::MAX];
+        for (address, timestamp) in ["a:1", "b:2", "c:3"].iter().zip(timestamps.iter()) {
+            add_outbound_connection(&handler, address);
+            let mut handshake = handler.handshake();
+            handshake.node_id = random_u64();
+            handshake.timestamp = *timestamp;
+            handler.handle(address, Message::Hello(handshake));
+        }
+
+        // absurd clocks don't overflow, they just count as far away as a clock can be
+        assert_eq!(
+            handler.network_time.drift_ms(),
+            Some(network_time::MAX_CLOCK_OFFSET_MS)
+        );
+        assert_eq!(handler.peers.count_connected(), 3);
+    }
+
+    #[test]
+    fn should_learn_where_inbound_peers_listen() {
+        assert_eq!(
+            Handler::dial_address("127.0.0.1:54321", 9001),
+            Some("127.0.0.1:9001".to_string())
+        );
+
+        // the node does not listen for connections
+        assert_eq!(Handler::dial_address("127.0.0.1:54321", 0), None);
+    }
+
+    // Registers a connection opened by us, backed by a real TCP stream to a local listener
+    fn add_outbound_connection(handler: &Handler, address: &str) {
+        let listener = TcpListener::bind(("localhost", 0)).unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let connection = Connection::new(stream, true);
+        handler
+            .peers
+            .connections
+            .lock()
+            .unwrap()
+            .insert(address.to_string(), connection);
+    }
+
+    // Creates a handler with an empty blockchain, and another blockchain to create blocks
+    fn create_handlers() -> (Handler, Blockchain) {
+        let handler = create_sender(&Blockchain::new(ProofOfWork::shared(0, 1, 1)));
+        let other_blockchain = Blockchain::new(ProofOfWork::shared(0, 1, 1));
+
+        (handler, other_blockchain)
+    }
+
+    fn create_sender(blockchain: &Blockchain) -> Handler {
+        Handler {
+            node_id: random_u64(),
+            port: 0,
+            chain_id: "main".to_string(),
+            blockchain: blockchain.clone(),
+            pool: TransactionPool::new(),
+            wallet: Wallet::new(WalletMode::Hot, Vec::new()).unwrap(),
+            gossip: Gossip::new(),
+            peer_book: PeerBook::new(),
+            sync: ChainSync::new(),
+            orphans: OrphanBlocks::new(blockchain.clock()),
+            peers: Peers::new(60),
+            light_client: false,
+            proofs: InclusionProofs::new(),
+            compact_relay: true,
+            compact_blocks: CompactBlocks::new(),
+            network_time: NetworkTime::new(60, false),
+        }
+    }
+
+    fn create_light_handler() -> Handler {
+        Handler {
+            light_client: true,
+            ..create_sender(&Blockchain::new(ProofOfWork::shared(0, 1, 1)))
+        }
+    }
+
+    // Appends valid blocks to a blockchain, returning them
+    fn add_blocks(blockchain: &Blockchain, amount: u64) -> Vec<Block> {
+        (0..amount)
+            .map(|_| add_block(blockchain, Vec::new()))
+            .collect()
+    }
+
+    fn add_block(blockchain: &Blockchain, transactions: Vec<Transaction>) -> Block {
+        let last_block = blockchain.get_last_block();
+        let mut block = Block::new(
+            last_block.header.index + 1,
+            0,
+            last_block.header.hash,
+            transactions,
+        );
+        block.header.timestamp = block.header.timestamp.max(blockchain.min_timestamp());
+        block.header.bits = blockchain.next_bits();
+        block.header.state_root = blockchain.next_state_root(&block.transactions);
+        block.header.hash = block.calculate_hash();
+        blockchain.add_block(block.clone()).unwrap();
+        block
+    }
+
+    fn create_transaction(amount: u64) -> Transaction {
+        Transaction {
+            sender: "1".to_string(),
+            recipient: "2".to_string(),
+            amount,
+            signature: None,
+            multisig: None,
+            contract: None,
+            token: None,
+            data: None,
+        }
+    }
+}
@@ -0,0 +1,307 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Arc,
+};
+
+use crate::{
+    model::{
+        merkle_root, Block, BlockHash, BlockHeader, Decode, Encode, EncodingError, Reader,
+        Transaction, TransactionId,
+    },
+    util::Lock,
+};
+
+// Max number of blocks waiting for their missing transactions, the oldest ones are forgotten first
+const MAX_PENDING_BLOCKS: usize = 16;
+
+// Short id of a transaction in a compact block, the lowest 8 bytes of its id
+// Two transactions may share it, but then the rebuilt block doesn't match its merkle root
+// and the whole block is downloaded instead
+pub fn short_id(id: TransactionId) -> u64 {
+    id.low_u64()
+}
+
+// A block as relayed to the peers that support it: the header and the short ids of its transactions
+// Peers usually have most of the transactions in their pool already, so they only download the rest
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompactBlock {
+    pub header: BlockHeader,
+    pub signature: Option<String>,
+    pub short_ids: Vec<u64>,
+}
+
+impl CompactBlock {
+    pub fn new(block: &Block) -> CompactBlock {
+        CompactBlock {
+            header: block.header(),
+            signature: block.signature.clone(),
+            short_ids: block
+                .transactions
+                .iter()
+                .map(|transaction| short_id(transaction.calculate_id()))
+                .collect(),
+        }
+    }
+}
+
+// What is left to do with a compact block
+#[derive(Debug)]
+pub enum Reconstruction {
+    // All the transactions were found, ready to be added like any other network block
+    Complete(Box<Block>),
+    // Positions of the transactions to ask the sender for, the block is kept until they arrive
+    Missing(Vec<usize>),
+    // The transactions don't match the merkle root of the header, the whole block must be downloaded
+    Failed,
+}
+
+// A compact block with the transactions found so far, and a hole for each missing one
+#[derive(Debug)]
+struct PartialBlock {
+    header: BlockHeader,
+    signature: Option<String>,
+    transactions: Vec<Option<Transaction>>,
+}
+
+impl PartialBlock {
+    fn missing(&self) -> Vec<usize> {
+        self.transactions
+            .iter()
+            .enumerate()
+            .filter(|(_, transaction)| transaction.is_none())
+            .map(|(index, _)| index)
+            .collect()
+    }
+
+    // The transactions are only committed by the merkle root, so that's what tells if they are the right ones
+    fn into_block(self) -> Reconstruction {
+        let transactions: Option<Vec<Transaction>> = self.transactions.into_iter().collect();
+        let transactions = match transactions {
+            Some(transactions) => transactions,
+            None => return Reconstruction::Failed,
+        };
+        let ids: Vec<TransactionId> = transactions.iter().map(Transaction::calculate_id).collect();
+        if merkle_root(&ids) != self.header.merkle_root {
+            return Reconstruction::Failed;
+        }
+
+        Reconstruction::Complete(Box::new(Block {
+            header: self.header,
+            transactions,
+            signature: self.signature,
+            pruned: false,
+        }))
+    }
+}
+
+#[derive(Debug, Default)]
+struct PendingBlocks {
+    blocks: HashMap<BlockHash, PartialBlock>,
+    order: VecDeque<BlockHash>,
+}
+
+// Compact blocks being rebuilt with the transactions of our pool
+// The ones missing some transactions wait here until the sender sends them
+// Cloning only clones the pointer, so all the connections share the same blocks
+#[derive(Debug, Clone, Default)]
+pub struct CompactBlocks {
+    pending: Arc<Lock<PendingBlocks>>,
+}
+
+impl CompactBlocks {
+    pub fn new() -> CompactBlocks {
+        CompactBlocks::default()
+    }
+
+    // Fills the transactions of a compact block with the ones of the pool
+    pub fn reconstruct(&self, compact: CompactBlock, pool: &[Transaction]) -> Reconstruction {
+        // short ids shared by several transactions of the pool are ambiguous, so they are asked for
+        let mut candidates: HashMap<u64, Option<&Transaction>> = HashMap::new();
+        for transaction in pool.iter() {
+            candidates
+                .entry(short_id(transaction.calculate_id()))
+                .and_modify(|candidate| *candidate = None)
+                .or_insert(Some(transaction));
+        }
+
+        let partial = PartialBlock {
+            transactions: compact
+                .short_ids
+                .iter()
+                .map(|id| candidates.get(id).copied().flatten().cloned())
+                .collect(),
+            header: compact.header,
+            signature: compact.signature,
+        };
+        let missing = partial.missing();
+        if missing.is_empty() {
+            return partial.into_block();
+        }
+
+        let hash = partial.header.hash;
+        let mut pending = self.pending.lock();
+        if pending.blocks.insert(hash, partial).is_none() {
+            pending.order.push_back(hash);
+        }
+        while pending.order.len() > MAX_PENDING_BLOCKS {
+            if let Some(oldest) = pending.order.pop_front() {
+                pending.blocks.remove(&oldest);
+            }
+        }
+
+        Reconstruction::Missing(missing)
+    }
+
+    // Fills the holes of a pending block with the transactions sent by the peer, in the order they were asked for
+    // Returns None if we were not waiting for the block (e.g. it was forgotten or already completed)
+    pub fn fill(&self, hash: BlockHash, transactions: Vec<Transaction>) -> Option<Reconstruction> {
+        let mut partial = {
+            let mut pending = self.pending.lock();
+            let partial = pending.blocks.remove(&hash)?;
+            pending.order.retain(|other| *other != hash);
+            partial
+        };
+
+        let missing = partial.missing();
+        if missing.len() != transactions.len() {
+            return Some(Reconstruction::Failed);
+        }
+        for (index, transaction) in missing.into_iter().zip(transactions) {
+            partial.transactions[index] = Some(transaction);
+        }
+
+        Some(partial.into_block())
+    }
+}
+
+impl Encode for CompactBlock {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.header.encode(out);
+        self.signature.encode(out);
+        self.short_ids.encode(out);
+    }
+}
+
+impl Decode for CompactBlock {
+    fn decode(reader: &mut Reader) -> Result<Self, EncodingError> {
+        Ok(CompactBlock {
+            header: Decode::decode(reader)?,
+            signature: Decode::decode(reader)?,
+            short_ids: Decode::decode(reader)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{from_bytes, to_bytes};
+
+    #[test]
+    fn should_rebuild_blocks_with_the_pool() {
+        let block = create_block(3);
+        let compact = CompactBlock::new(&block);
+        let compact_blocks = CompactBlocks::new();
+
+        // the pool may have transactions of other blocks, they are just not used
+        let mut pool = block.transactions.clone();
+        pool.push(create_transaction(10));
+        match compact_blocks.reconstruct(compact, &pool) {
+            Reconstruction::Complete(rebuilt) => {
+                assert_eq!(rebuilt.header, block.header);
+                assert_eq!(rebuilt.calculate_merkle_root(), block.header.merkle_root);
+            }
+            reconstruction => panic!("unexpected reconstruction {:?}", reconstruction),
+        }
+    }
+
+    #[test]
+    fn should_ask_for_the_missing_transactions() {
+        let block = create_block(3);
+        let compact = CompactBlock::new(&block);
+        let compact_blocks = CompactBlocks::new();
+
+        let pool = vec![block.transactions[1].clone()];
+        match compact_blocks.reconstruct(compact, &pool) {
+            Reconstruction::Missing(missing) => assert_eq!(missing, vec![0, 2]),
+            reconstruction => panic!("unexpected reconstruction {:?}", reconstruction),
+        }
+
+        let sent = vec![block.transactions[0].clone(), block.transactions[2].clone()];
+        assert!(matches!(
+            compact_blocks.fill(block.header.hash, sent.clone()),
+            Some(Reconstruction::Complete(_))
+        ));
+        // once completed, the block is not pending anymore
+        assert!(compact_blocks.fill(block.header.hash, sent).is_none());
+    }
+
+    #[test]
+    fn should_fail_with_the_wrong_transactions() {
+        let block = create_block(2);
+        let compact_blocks = CompactBlocks::new();
+
+        compact_blocks.reconstruct(CompactBlock::new(&block), &[]);
+        let sent = vec![create_transaction(10), create_transaction(11)];
+        assert!(matches!(
+            compact_blocks.fill(block.header.hash, sent),
+            Some(Reconstruction::Failed)
+        ));
+
+        compact_blocks.reconstruct(CompactBlock::new(&block), &[]);
+        let sent = vec![block.transactions[0].clone()];
+        assert!(matches!(
+            compact_blocks.fill(block.header.hash, sent),
+            Some(Reconstruction::Failed)
+        ));
+    }
+
+    #[test]
+    fn should_forget_the_oldest_pending_blocks() {
+        let compact_blocks = CompactBlocks::new();
+        let blocks: Vec<Block> = (0..=MAX_PENDING_BLOCKS)
+            .map(|nonce| {
+                let mut block = create_block(1);
+                block.header.nonce = nonce as u64;
+                block.header.hash = block.calculate_hash();
+                block
+            })
+            .collect();
+        for block in blocks.iter() {
+            compact_blocks.reconstruct(CompactBlock::new(block), &[]);
+        }
+
+        assert!(compact_blocks
+            .fill(blocks[0].header.hash, Vec::new())
+            .is_none());
+        let last = &blocks[MAX_PENDING_BLOCKS];
+        assert!(compact_blocks
+            .fill(last.header.hash, last.transactions.clone())
+            .is_some());
+    }
+
+    #[test]
+    fn should_roundtrip_compact_blocks() {
+        let compact = CompactBlock::new(&create_block(2));
+        let decoded: CompactBlock = from_bytes(&to_bytes(&compact)).unwrap();
+        assert_eq!(decoded, compact);
+    }
+
+    fn create_block(transactions: u64) -> Block {
+        let transactions = (0..transactions).map(create_transaction).collect();
+        Block::new(1, 0, BlockHash::default(), transactions)
+    }
+
+    fn create_transaction(amount: u64) -> Transaction {
+        Transaction {
+            sender: "1".to_string(),
+            recipient: "2".to_string(),
+            amount,
+            signature: None,
+            multisig: None,
+            contract: None,
+            token: None,
+            data: None,
+        }
+    }
+}
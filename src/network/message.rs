@@ -0,0 +1,453 @@
+use std::io::{self, Read};
+
+use anyhow::Result;
+
+use super::{CompactBlock, NetworkError};
+use crate::model::{
+    from_bytes, to_bytes, Block, BlockHash, BlockHeader, Decode, Encode, EncodingError, Reader,
+    Transaction, TransactionId, TransactionProof,
+};
+
+// Version of the messages sent by this node, it must be increased on every incompatible change
+// Version 2 replaced the json lines with binary frames
+// Version 3 added the total work of the chain to the handshake
+// Version 4 added compact blocks, which are only requested to nodes with "compact"
+// Version 5 added the data of the transactions (version 2 of the encoding), which older nodes would drop
+// Version 6 added the time of the node to the handshake, to detect skewed clocks
+pub const PROTOCOL_VERSION: u32 = 6;
+
+// Oldest version of the protocol that this node still understands
+pub const MIN_PROTOCOL_VERSION: u32 = 6;
+
+// Every message is preceded by its length, which can't be larger than this
+// A batch of blocks is the largest message, and it's far from the limit
+pub const MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+const LENGTH_SIZE: usize = 4;
+
+// Introduction of a node, sent as the first message of every connection
+#[derive(Debug, Clone, PartialEq)]
+pub struct Handshake {
+    pub protocol_version: u32,
+    // Nodes of different networks (e.g. test networks) must never mix their chains
+    pub chain_id: String,
+    pub genesis_hash: BlockHash,
+    // Random id of the node, to detect connections to itself
+    pub node_id: u64,
+    // Port where the node listens for connections, if any
+    pub port: u16,
+    // Index of the last block of the node, so the receiver knows if it needs to synchronize
+    pub last_index: u64,
+    // Total work of the chain of the node, only chains with more work than ours are followed
+    pub total_work: BlockHash,
+    // Optional features of the node (e.g. "mining", "light", "proofs" or "compact"), unknown ones must be ignored
+    pub capabilities: Vec<String>,
+    // Time of the clock of the node when it sent the handshake, in millis like the timestamps of the blocks
+    pub timestamp: i64,
+}
+
+impl Handshake {
+    // Nodes only talk to each other if they understand each other's messages and follow the same chain
+    pub fn check_compatibility(&self, other: &Handshake) -> Result<()> {
+        if other.protocol_version < MIN_PROTOCOL_VERSION {
+            return Err(NetworkError::UnsupportedVersion(other.protocol_version).into());
+        }
+
+        if other.chain_id != self.chain_id {
+            return Err(NetworkError::ChainIdMismatch(other.chain_id.clone()).into());
+        }
+
+        if other.genesis_hash != self.genesis_hash {
+            return Err(NetworkError::GenesisMismatch(other.genesis_hash).into());
+        }
+
+        Ok(())
+    }
+}
+
+// Messages exchanged between nodes, each one sent as a binary frame
+#[derive(Debug, Clone)]
+pub enum Message {
+    // First message sent through every connection, the connection is dropped if the nodes are not compatible
+    Hello(Handshake),
+    // A new block was added to the blockchain of the sender
+    // Only the header is announced, receivers ask for the whole block if they don't have it
+    NewBlock(BlockHeader),
+    // Request of a single block, usually after it was announced
+    GetBlock {
+        hash: BlockHash,
+    },
+    // Response to a "GetBlock" request
+    Block(Block),
+    // A new transaction entered the pool of the sender, to be relayed to the rest of the network
+    NewTransaction(Transaction),
+    // Request of the headers of the blocks starting from an index, to synchronize with the receiver
+    GetHeaders {
+        from_index: u64,
+    },
+    // Response to a "GetHeaders" request, in chain order and limited in number
+    Headers(Vec<BlockHeader>),
+    // Request of a batch of blocks, once their headers are validated
+    GetBlocks {
+        hashes: Vec<BlockHash>,
+    },
+    // Response to a "GetBlocks" request, in chain order
+    Blocks(Vec<Block>),
+    // Request of the addresses of the nodes known by the receiver
+    GetPeers,
+    // Response to a "GetPeers" request
+    Peers(Vec<String>),
+    // Request of the proof that a transaction is included in a block, only sent to nodes with "proofs"
+    GetProof {
+        id: TransactionId,
+    },
+    // Response to a "GetProof" request, without proof if the receiver has no block with the transaction
+    Proof {
+        id: TransactionId,
+        proof: Option<TransactionProof>,
+    },
+    // Request of a block in compact form, usually after it was announced, only sent to nodes with "compact"
+    GetCompactBlock {
+        hash: BlockHash,
+    },
+    // Response to a "GetCompactBlock" request, with the short ids of the transactions instead of them
+    CompactBlock(CompactBlock),
+    // Request of the transactions at some positions of a block, the ones missing to rebuild a compact block
+    GetBlockTransactions {
+        hash: BlockHash,
+        indexes: Vec<usize>,
+    },
+    // Response to a "GetBlockTransactions" request, in the same order as the positions
+    BlockTransactions {
+        hash: BlockHash,
+        transactions: Vec<Transaction>,
+    },
+}
+
+impl Message {
+    // Serializes the message into a frame, ready to be written into a connection
+    pub fn encode(&self) -> Vec<u8> {
+        let payload = to_bytes(self);
+
+        let mut frame = Vec::with_capacity(LENGTH_SIZE + payload.len());
+        frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        frame.extend_from_slice(&payload);
+        frame
+    }
+
+    // Deserializes the payload of a frame
+    pub fn decode(payload: &[u8]) -> Result<Message> {
+        from_bytes(payload)
+            .map_err(|error| NetworkError::MalformedMessage(error.to_string()).into())
+    }
+}
+
+// Reads the payload of the next frame of a connection
+// Frames too large to be a valid message are an error, as the rest of the connection can't be trusted
+pub fn read_frame(reader: &mut impl Read) -> io::Result<Vec<u8>> {
+    let mut length = [0; LENGTH_SIZE];
+    reader.read_exact(&mut length)?;
+    let length = u32::from_be_bytes(length) as usize;
+    if length > MAX_MESSAGE_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("message of {} bytes is too large", length),
+        ));
+    }
+
+    let mut payload = vec![0; length];
+    reader.read_exact(&mut payload)?;
+    Ok(payload)
+}
+
+impl Encode for Handshake {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.protocol_version.encode(out);
+        self.chain_id.encode(out);
+        self.genesis_hash.encode(out);
+        self.node_id.encode(out);
+        self.port.encode(out);
+        self.last_index.encode(out);
+        self.total_work.encode(out);
+        self.capabilities.encode(out);
+        self.timestamp.encode(out);
+    }
+}
+
+impl Decode for Handshake {
+    fn decode(reader: &mut Reader) -> Result<Self, EncodingError> {
+        Ok(Handshake {
+            protocol_version: Decode::decode(reader)?,
+            chain_id: Decode::decode(reader)?,
+            genesis_hash: Decode::decode(reader)?,
+            node_id: Decode::decode(reader)?,
+            port: Decode::decode(reader)?,
+            last_index: Decode::decode(reader)?,
+            total_work: Decode::decode(reader)?,
+            capabilities: Decode::decode(reader)?,
+            timestamp: Decode::decode(reader)?,
+        })
+    }
+}
+
+// The tag of every message is its position in the enum, so new messages must go at the end
+impl Encode for Message {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Message::Hello(handshake) => {
+                out.push(0);
+                handshake.encode(out);
+            }
+            Message::NewBlock(header) => {
+                out.push(1);
+                header.encode(out);
+            }
+            Message::GetBlock { hash } => {
+                out.push(2);
+                hash.encode(out);
+            }
+            Message::Block(block) => {
+                out.push(3);
+                block.encode(out);
+            }
+            Message::NewTransaction(transaction) => {
+                out.push(4);
+                transaction.encode(out);
+            }
+            Message::GetHeaders { from_index } => {
+                out.push(5);
+                from_index.encode(out);
+            }
+            Message::Headers(headers) => {
+                out.push(6);
+                headers.encode(out);
+            }
+            Message::GetBlocks { hashes } => {
+                out.push(7);
+                hashes.encode(out);
+            }
+            Message::Blocks(blocks) => {
+                out.push(8);
+                blocks.encode(out);
+            }
+            Message::GetPeers => out.push(9),
+            Message::Peers(peers) => {
+                out.push(10);
+                peers.encode(out);
+            }
+            Message::GetProof { id } => {
+                out.push(11);
+                id.encode(out);
+            }
+            Message::Proof { id, proof } => {
+                out.push(12);
+                id.encode(out);
+                proof.encode(out);
+            }
+            Message::GetCompactBlock { hash } => {
+                out.push(13);
+                hash.encode(out);
+            }
+            Message::CompactBlock(compact) => {
+                out.push(14);
+                compact.encode(out);
+            }
+            Message::GetBlockTransactions { hash, indexes } => {
+                out.push(15);
+                hash.encode(out);
+                indexes.encode(out);
+            }
+            Message::BlockTransactions { hash, transactions } => {
+                out.push(16);
+                hash.encode(out);
+                transactions.encode(out);
+            }
+        }
+    }
+}
+
+impl Decode for Message {
+    fn decode(reader: &mut Reader) -> Result<Self, EncodingError> {
+        Ok(match reader.tag("message", 17)? {
+            0 => Message::Hello(Decode::decode(reader)?),
+            1 => Message::NewBlock(Decode::decode(reader)?),
+            2 => Message::GetBlock {
+                hash: Decode::decode(reader)?,
+            },
+            3 => Message::Block(Decode::decode(reader)?),
+            4 => Message::NewTransaction(Decode::decode(reader)?),
+            5 => Message::GetHeaders {
+                from_index: Decode::decode(reader)?,
+            },
+            6 => Message::Headers(Decode::decode(reader)?),
+            7 => Message::GetBlocks {
+                hashes: Decode::decode(reader)?,
+            },
+            8 => Message::Blocks(Decode::decode(reader)?),
+            9 => Message::GetPeers,
+            10 => Message::Peers(Decode::decode(reader)?),
+            11 => Message::GetProof {
+                id: Decode::decode(reader)?,
+            },
+            12 => Message::Proof {
+                id: Decode::decode(reader)?,
+                proof: Decode::decode(reader)?,
+            },
+            13 => Message::GetCompactBlock {
+                hash: Decode::decode(reader)?,
+            },
+            14 => Message::CompactBlock(Decode::decode(reader)?),
+            15 => Message::GetBlockTransactions {
+                hash: Decode::decode(reader)?,
+                indexes: Decode::decode(reader)?,
+            },
+            _ => Message::BlockTransactions {
+                hash: Decode::decode(reader)?,
+                transactions: Decode::decode(reader)?,
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_encode_messages_in_frames() {
+        let message = Message::GetHeaders { from_index: 3 };
+        let frame = message.encode();
+
+        // length, encoding version, message tag and the index
+        assert_eq!(&frame[..4], &[0, 0, 0, 10]);
+        assert_eq!(&frame[5..], &[5, 0, 0, 0, 0, 0, 0, 0, 3]);
+
+        let payload = read_frame(&mut frame.as_slice()).unwrap();
+        assert!(matches!(
+            Message::decode(&payload).unwrap(),
+            Message::GetHeaders { from_index: 3 }
+        ));
+    }
+
+    #[test]
+    fn should_read_consecutive_frames() {
+        let mut bytes = Message::GetPeers.encode();
+        bytes.extend(Message::Peers(vec!["a:1".to_string()]).encode());
+        let mut reader = bytes.as_slice();
+
+        let payload = read_frame(&mut reader).unwrap();
+        assert!(matches!(
+            Message::decode(&payload).unwrap(),
+            Message::GetPeers
+        ));
+        let payload = read_frame(&mut reader).unwrap();
+        match Message::decode(&payload).unwrap() {
+            Message::Peers(peers) => assert_eq!(peers, vec!["a:1".to_string()]),
+            message => panic!("unexpected message {:?}", message),
+        }
+        assert!(read_frame(&mut reader).is_err());
+    }
+
+    #[test]
+    fn should_roundtrip_transactions() {
+        let transaction = Transaction {
+            sender: "1".to_string(),
+            recipient: "2".to_string(),
+            amount: 3,
+            signature: None,
+            multisig: None,
+            contract: None,
+            token: None,
+            data: None,
+        };
+        let frame = Message::NewTransaction(transaction.clone()).encode();
+
+        match Message::decode(&frame[4..]).unwrap() {
+            Message::NewTransaction(decoded) => {
+                assert_eq!(decoded.calculate_id(), transaction.calculate_id())
+            }
+            message => panic!("unexpected message {:?}", message),
+        }
+    }
+
+    #[test]
+    fn should_roundtrip_handshakes() {
+        let handshake = create_handshake();
+        let frame = Message::Hello(handshake.clone()).encode();
+
+        match Message::decode(&frame[4..]).unwrap() {
+            Message::Hello(decoded) => assert_eq!(decoded, handshake),
+            message => panic!("unexpected message {:?}", message),
+        }
+    }
+
+    #[test]
+    fn should_reject_malformed_messages() {
+        let err = Message::decode(&[1, 42]).unwrap_err();
+        assert_eq!(
+            err.downcast::<NetworkError>().unwrap(),
+            NetworkError::MalformedMessage("Invalid tag 42 for message".to_string())
+        );
+
+        assert!(Message::decode(b"{\"type\":\"get_peers\"}").is_err());
+    }
+
+    #[test]
+    fn should_reject_frames_too_large() {
+        let length = (MAX_MESSAGE_SIZE as u32 + 1).to_be_bytes();
+        let err = read_frame(&mut &length[..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn should_accept_compatible_handshakes() {
+        let handshake = create_handshake();
+        let mut other = create_handshake();
+        other.protocol_version = PROTOCOL_VERSION + 1;
+        other.capabilities = vec!["unknown".to_string()];
+
+        assert!(handshake.check_compatibility(&other).is_ok());
+    }
+
+    #[test]
+    fn should_reject_incompatible_handshakes() {
+        let handshake = create_handshake();
+
+        let mut other = create_handshake();
+        other.protocol_version = MIN_PROTOCOL_VERSION - 1;
+        let err = handshake.check_compatibility(&other).unwrap_err();
+        assert_eq!(
+            err.downcast::<NetworkError>().unwrap(),
+            NetworkError::UnsupportedVersion(MIN_PROTOCOL_VERSION - 1)
+        );
+
+        let mut other = create_handshake();
+        other.chain_id = "testnet".to_string();
+        let err = handshake.check_compatibility(&other).unwrap_err();
+        assert_eq!(
+            err.downcast::<NetworkError>().unwrap(),
+            NetworkError::ChainIdMismatch("testnet".to_string())
+        );
+
+        let mut other = create_handshake();
+        other.genesis_hash = BlockHash::from(1);
+        let err = handshake.check_compatibility(&other).unwrap_err();
+        assert_eq!(
+            err.downcast::<NetworkError>().unwrap(),
+            NetworkError::GenesisMismatch(BlockHash::from(1))
+        );
+    }
+
+    fn create_handshake() -> Handshake {
+        Handshake {
+            protocol_version: PROTOCOL_VERSION,
+            chain_id: "main".to_string(),
+            genesis_hash: BlockHash::default(),
+            node_id: 1,
+            port: 0,
+            last_index: 0,
+            total_work: BlockHash::zero(),
+            capabilities: Vec::new(),
+            timestamp: 1_000,
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+
+// Peers needed before trusting the time of the network, so a single one can't make us distrust our clock
+const MIN_TIME_SAMPLES: usize = 3;
+
+// Offsets are capped at a day either way, further than that a clock is just wrong
+// and the math on them can't overflow
+pub const MAX_CLOCK_OFFSET_MS: i64 = 24 * 60 * 60 * 1000;
+
+// Difference between the clocks of the peers and ours, from the time they send in their hello
+// The timestamps of the blocks are checked against our clock, so if it's wrong the node rejects valid blocks
+// or mines blocks that the rest of the network rejects, which is hard to tell from the logs alone
+// Cloning only clones the pointers, so the network, the miner and the api share the same state
+#[derive(Debug, Clone)]
+pub struct NetworkTime {
+    // offset of the clock of the node at the other side of each connection, in millis
+    offsets: Arc<Mutex<HashMap<String, i64>>>,
+    max_offset_ms: i64,
+    stops_mining: bool,
+    // whether our clock was too far from the one of the network the last time, to only log the changes
+    drifting: Arc<AtomicBool>,
+}
+
+impl NetworkTime {
+    // With "stops_mining" the node doesn't mine while its clock is more than "max_offset_secs" off
+    pub fn new(max_offset_secs: u64, stops_mining: bool) -> NetworkTime {
+        NetworkTime {
+            offsets: Arc::new(Mutex::new(HashMap::new())),
+            max_offset_ms: max_offset_secs as i64 * 1000,
+            stops_mining,
+            drifting: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    // Records how far ahead (or behind, if negative) of our clock is the one of a peer
+    pub fn add_sample(&self, address: &str, offset_ms: i64) {
+        let offset_ms = offset_ms.clamp(-MAX_CLOCK_OFFSET_MS, MAX_CLOCK_OFFSET_MS);
+        self.offsets
+            .lock()
+            .unwrap()
+            .insert(address.to_string(), offset_ms);
+        self.check();
+    }
+
+    // Forgets the offset of a peer we disconnected from, as our clock may have been fixed meanwhile
+    pub fn remove_sample(&self, address: &str) {
+        if self.offsets.lock().unwrap().remove(address).is_some() {
+            self.check();
+        }
+    }
+
+    // Median offset of the clocks of the peers, unless there are too few of them to tell
+    pub fn offset_ms(&self) -> Option<i64> {
+        let mut offsets: Vec<i64> = self.offsets.lock().unwrap().values().copied().collect();
+        if offsets.len() < MIN_TIME_SAMPLES {
+            return None;
+        }
+
+        offsets.sort_unstable();
+        let middle = offsets.len() / 2;
+        match offsets.len() % 2 {
+            0 => {
+                let (low, high) = (offsets[middle - 1], offsets[middle]);
+                Some(low + (high - low) / 2)
+            }
+            _ => Some(offsets[middle]),
+        }
+    }
+
+    // The offset of the network, only if our clock is too far from it
+    pub fn drift_ms(&self) -> Option<i64> {
+        self.offset_ms()
+            .filter(|offset| offset.abs() > self.max_offset_ms)
+    }
+
+    pub fn stops_mining(&self) -> bool {
+        self.stops_mining && self.drift_ms().is_some()
+    }
+
+    fn check(&self) {
+        let drift = self.drift_ms();
+        let was_drifting = self.drifting.swap(drift.is_some(), Ordering::SeqCst);
+        match drift {
+            Some(offset) if !was_drifting => {
+                let direction = if offset > 0 { "behind" } else { "ahead of" };
+                error!(
+                    "the clock of this node is {} secs {} the median of its peers, check the time of the system: \
+                    blocks of the peers may be rejected, and so may the blocks of this node{}",
+                    offset.abs() / 1000,
+                    direction,
+                    if self.stops_mining {
+                        ", which stops mining until then"
+                    } else {
+                        ""
+                    }
+                );
+            }
+            None if was_drifting => info!("the clock of this node agrees with its peers again"),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_take_the_median_offset_of_the_peers() {
+        let time = NetworkTime::new(60, false);
+        time.add_sample("a:1", 1_000);
+        time.add_sample("b:2", -500_000);
+        assert_eq!(time.offset_ms(), None);
+
+        time.add_sample("c:3", 3_000);
+        assert_eq!(time.offset_ms(), Some(1_000));
+        time.add_sample("d:4", 5_000);
+        assert_eq!(time.offset_ms(), Some(2_000));
+
+        // a peer that connects again replaces its previous offset
+        time.add_sample("b:2", 9_000);
+        assert_eq!(time.offset_ms(), Some(4_000));
+        time.remove_sample("d:4");
+        assert_eq!(time.offset_ms(), Some(3_000));
+    }
+
+    #[test]
+    fn should_detect_drifts_larger_than_allowed() {
+        let time = NetworkTime::new(60, false);
+        for (address, offset) in [("a:1", 61_000), ("b:2", 120_000), ("c:3", -1_000)].iter() {
+            time.add_sample(address, *offset);
+        }
+        assert_eq!(time.drift_ms(), Some(61_000));
+        // unless asked to, mining goes on
+        assert!(!time.stops_mining());
+
+        time.add_sample("b:2", 0);
+        assert_eq!(time.drift_ms(), None);
+    }
+
+    #[test]
+    fn should_cap_the_offsets_of_absurd_clocks() {
+        let time = NetworkTime::new(60, false);
+        for (address, offset) in [("a:1", i64::MIN), ("b:2", i64::MIN), ("c:3", 0)].iter() {
+            time.add_sample(address, *offset);
+        }
+        assert_eq!(time.drift_ms(), Some(-MAX_CLOCK_OFFSET_MS));
+
+        time.add_sample("d:4", i64::MAX);
+        assert_eq!(time.offset_ms(), Some(-MAX_CLOCK_OFFSET_MS / 2));
+    }
+
+    #[test]
+    fn should_stop_mining_while_drifting() {
+        let time = NetworkTime::new(60, true);
+        for address in ["a:1", "b:2", "c:3"].iter() {
+            time.add_sample(address, -61_000);
+        }
+        assert!(time.stops_mining());
+
+        time.remove_sample("a:1");
+        assert!(!time.stops_mining());
+    }
+}
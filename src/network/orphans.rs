@@ -0,0 +1,156 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    model::{Block, BlockHash},
+    util::SharedClock,
+};
+
+// Max number of blocks waiting for their parent, the oldest ones are forgotten first
+const MAX_ORPHAN_BLOCKS: usize = 100;
+
+// Time after which an orphan is forgotten, its parent is not coming anymore
+const ORPHAN_TTL_MS: i64 = 10 * 60 * 1000;
+
+#[derive(Debug)]
+struct Orphan {
+    block: Block,
+    received_at: i64,
+}
+
+// Blocks that arrived before their parent (e.g. because they took different routes through the network)
+// They are kept until the parent arrives, instead of being dropped and downloaded again
+// Cloning only clones the pointer, so all the connections share the same orphans
+#[derive(Debug, Clone)]
+pub struct OrphanBlocks {
+    orphans: Arc<Mutex<HashMap<BlockHash, Orphan>>>,
+    clock: SharedClock,
+}
+
+impl OrphanBlocks {
+    pub fn new(clock: SharedClock) -> OrphanBlocks {
+        OrphanBlocks {
+            orphans: Arc::default(),
+            clock,
+        }
+    }
+
+    // Keeps a block until its parent arrives
+    // Returns false if we already had it, so its parent is not requested again
+    pub fn add(&self, block: Block) -> bool {
+        let now = self.clock.now_millis();
+        let mut orphans = self.orphans.lock().unwrap();
+        Self::expire(&mut orphans, now);
+        if orphans.contains_key(&block.header.hash) {
+            return false;
+        }
+
+        if orphans.len() >= MAX_ORPHAN_BLOCKS {
+            let oldest = orphans
+                .iter()
+                .min_by_key(|(_, orphan)| orphan.received_at)
+                .map(|(hash, _)| *hash);
+            if let Some(hash) = oldest {
+                orphans.remove(&hash);
+            }
+        }
+
+        let orphan = Orphan {
+            block,
+            received_at: now,
+        };
+        orphans.insert(orphan.block.header.hash, orphan);
+        true
+    }
+
+    // Takes the orphans that descend from a block, in order
+    // If the block has several children, only the first branch is taken
+    pub fn take_descendants(&self, hash: BlockHash) -> Vec<Block> {
+        let mut orphans = self.orphans.lock().unwrap();
+        Self::expire(&mut orphans, self.clock.now_millis());
+
+        let mut descendants = Vec::new();
+        let mut parent = hash;
+        loop {
+            let child = orphans
+                .values()
+                .find(|orphan| orphan.block.header.previous_hash == parent)
+                .map(|orphan| orphan.block.header.hash);
+            match child.and_then(|child| orphans.remove(&child)) {
+                Some(orphan) => {
+                    parent = orphan.block.header.hash;
+                    descendants.push(orphan.block);
+                }
+                None => return descendants,
+            }
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.orphans.lock().unwrap().len()
+    }
+
+    fn expire(orphans: &mut HashMap<BlockHash, Orphan>, now: i64) {
+        orphans.retain(|_, orphan| now - orphan.received_at < ORPHAN_TTL_MS);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::MockClock;
+    use std::time::Duration;
+
+    #[test]
+    fn should_take_the_descendants_of_a_block() {
+        let orphans = OrphanBlocks::new(MockClock::new(0).shared());
+        let chain = create_chain(4);
+
+        // they may arrive in any order
+        assert!(orphans.add(chain[3].clone()));
+        assert!(orphans.add(chain[2].clone()));
+        assert!(!orphans.add(chain[2].clone()));
+        assert_eq!(orphans.count(), 2);
+
+        // once the parent arrives, they come back in order
+        let descendants = orphans.take_descendants(chain[1].header.hash);
+        let hashes: Vec<BlockHash> = descendants.iter().map(|block| block.header.hash).collect();
+        assert_eq!(hashes, vec![chain[2].header.hash, chain[3].header.hash]);
+        assert_eq!(orphans.count(), 0);
+    }
+
+    #[test]
+    fn should_bound_the_orphans() {
+        let clock = MockClock::new(0);
+        let orphans = OrphanBlocks::new(clock.shared());
+
+        // the oldest orphan makes room for the new ones
+        let chain = create_chain(MAX_ORPHAN_BLOCKS + 2);
+        for block in chain[1..].iter() {
+            orphans.add(block.clone());
+            clock.advance(Duration::from_millis(1));
+        }
+        assert_eq!(orphans.count(), MAX_ORPHAN_BLOCKS);
+        assert!(orphans.take_descendants(chain[0].header.hash).is_empty());
+
+        // and they are forgotten after a while
+        clock.advance(Duration::from_millis(ORPHAN_TTL_MS as u64));
+        assert!(orphans.take_descendants(chain[1].header.hash).is_empty());
+        assert_eq!(orphans.count(), 0);
+    }
+
+    fn create_chain(length: usize) -> Vec<Block> {
+        let mut blocks: Vec<Block> = Vec::new();
+        for index in 0..length as u64 {
+            let previous_hash = blocks
+                .last()
+                .map_or(BlockHash::default(), |last| last.header.hash);
+            let mut block = Block::new(index, 0, previous_hash, Vec::new());
+            block.header.hash = block.calculate_hash();
+            blocks.push(block);
+        }
+        blocks
+    }
+}
@@ -0,0 +1,207 @@
+use std::{
+    collections::{BTreeSet, HashSet},
+    fs,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{Context as _, Result};
+
+#[derive(Debug, Default)]
+struct KnownPeers {
+    // Peers we were able to connect to, they are persisted across restarts
+    good: BTreeSet<String>,
+    // Peers shared by other nodes, not tried yet
+    discovered: BTreeSet<String>,
+    // Addresses that must never be tried again, e.g. because they point to ourselves
+    ignored: HashSet<String>,
+}
+
+// Addresses of the other nodes of the network known by this node
+// Cloning only clones the pointer, so the network and the scheduler share the same book
+#[derive(Debug, Clone, Default)]
+pub struct PeerBook {
+    path: Option<PathBuf>,
+    peers: Arc<Mutex<KnownPeers>>,
+}
+
+impl PeerBook {
+    // Creates a peer book that is only kept in memory
+    pub fn new() -> PeerBook {
+        PeerBook::default()
+    }
+
+    // Loads the good peers saved in a file, if a path is given
+    // The file may not exist yet, e.g. the first time the node is started
+    pub fn load(path: &str) -> Result<PeerBook> {
+        if path.trim().is_empty() {
+            return Ok(PeerBook::new());
+        }
+
+        let path = PathBuf::from(path.trim());
+        let mut peers = KnownPeers::default();
+        if path.exists() {
+            let raw_peers = fs::read_to_string(&path)
+                .with_context(|| format!("could not read the peer book {}", path.display()))?;
+            peers.good = serde_json::from_str(&raw_peers)
+                .with_context(|| format!("invalid peer book {}", path.display()))?;
+        }
+
+        Ok(PeerBook {
+            path: Some(path),
+            peers: Arc::new(Mutex::new(peers)),
+        })
+    }
+
+    // Whether the good peers are saved to a file
+    pub fn is_persistent(&self) -> bool {
+        self.path.is_some()
+    }
+
+    // Writes the good peers into the file of the book, as a JSON list
+    pub fn save(&self) -> Result<()> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+
+        let raw_peers = {
+            let peers = self.peers.lock().unwrap();
+            serde_json::to_string_pretty(&peers.good)?
+        };
+
+        // we write a temporary file first, so a crash never leaves a half-written book
+        let temp_path = path.with_extension("tmp");
+        fs::write(&temp_path, raw_peers)?;
+        fs::rename(&temp_path, path)?;
+
+        Ok(())
+    }
+
+    // Remember a peer that we could connect to
+    pub fn add_good(&self, address: &str) {
+        let mut peers = self.peers.lock().unwrap();
+        if peers.ignored.contains(address) {
+            return;
+        }
+
+        peers.discovered.remove(address);
+        peers.good.insert(address.to_string());
+    }
+
+    // Remember a peer shared by another node, to try it later
+    pub fn discover(&self, address: &str) {
+        let mut peers = self.peers.lock().unwrap();
+        if peers.ignored.contains(address) || peers.good.contains(address) {
+            return;
+        }
+
+        peers.discovered.insert(address.to_string());
+    }
+
+    // Forget a peer that is not working, it can be discovered again later
+    pub fn forget(&self, address: &str) {
+        let mut peers = self.peers.lock().unwrap();
+        peers.good.remove(address);
+        peers.discovered.remove(address);
+    }
+
+    // Never try an address again
+    pub fn ignore(&self, address: &str) {
+        self.forget(address);
+
+        let mut peers = self.peers.lock().unwrap();
+        peers.ignored.insert(address.to_string());
+    }
+
+    pub fn is_ignored(&self, address: &str) -> bool {
+        let peers = self.peers.lock().unwrap();
+        peers.ignored.contains(address)
+    }
+
+    // Addresses that we can share with other nodes
+    pub fn good_addresses(&self) -> Vec<String> {
+        let peers = self.peers.lock().unwrap();
+        peers.good.iter().cloned().collect()
+    }
+
+    // Addresses to try to connect to, the good ones first
+    pub fn candidates(&self) -> Vec<String> {
+        let peers = self.peers.lock().unwrap();
+        peers
+            .good
+            .iter()
+            .chain(peers.discovered.iter())
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn should_track_good_and_discovered_peers() {
+        let book = PeerBook::new();
+        book.discover("a:1");
+        book.discover("b:2");
+        book.add_good("b:2");
+
+        // only the good peers are shared, but all of them are tried
+        assert_eq!(book.good_addresses(), vec!["b:2"]);
+        assert_eq!(book.candidates(), vec!["b:2", "a:1"]);
+
+        // good peers are not downgraded when discovered again
+        book.discover("b:2");
+        assert_eq!(book.candidates(), vec!["b:2", "a:1"]);
+
+        book.forget("b:2");
+        assert_eq!(book.candidates(), vec!["a:1"]);
+    }
+
+    #[test]
+    fn should_never_try_ignored_addresses() {
+        let book = PeerBook::new();
+        book.add_good("a:1");
+        book.ignore("a:1");
+        assert!(book.is_ignored("a:1"));
+        assert!(book.candidates().is_empty());
+
+        book.discover("a:1");
+        book.add_good("a:1");
+        assert!(book.candidates().is_empty());
+    }
+
+    #[test]
+    fn should_persist_good_peers() {
+        let path = env::temp_dir().join(format!("peer_book_{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        // there is no file yet
+        let book = PeerBook::load(path).unwrap();
+        assert!(book.is_persistent());
+        book.add_good("a:1");
+        book.discover("b:2");
+        book.save().unwrap();
+
+        // only the good peers survive a restart
+        let book = PeerBook::load(path).unwrap();
+        assert_eq!(book.candidates(), vec!["a:1"]);
+
+        // a corrupted book is reported
+        fs::write(path, "foo").unwrap();
+        assert!(PeerBook::load(path).is_err());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn should_not_persist_without_path() {
+        let book = PeerBook::load("").unwrap();
+        assert!(!book.is_persistent());
+
+        book.add_good("a:1");
+        assert!(book.save().is_ok());
+    }
+}
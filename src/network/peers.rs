@@ -0,0 +1,462 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt::Debug,
+    io::Write,
+    net::{Shutdown, TcpStream},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+
+use super::NetworkError;
+
+// Score at which a peer gets banned
+const BAN_SCORE: u32 = 100;
+
+// Peers banned this many times are banned permanently, they will only misbehave again
+const MAX_TEMPORARY_BANS: u32 = 3;
+
+// Ways in which a peer can misbehave
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Misbehavior {
+    MalformedMessage,
+    InvalidBlock,
+    InvalidProof,
+    Flooding,
+}
+
+impl Misbehavior {
+    // The more harmful the misbehavior, the sooner the peer gets banned
+    fn penalty(&self) -> u32 {
+        match self {
+            Misbehavior::MalformedMessage => 10,
+            Misbehavior::InvalidBlock => 50,
+            Misbehavior::InvalidProof => 50,
+            Misbehavior::Flooding => 25,
+        }
+    }
+}
+
+// Where the messages to another node are written
+// It's a TCP stream, except in the testkit, where nodes talk through memory
+pub trait Link: Write + Send + Debug {
+    fn shutdown(&self);
+}
+
+impl Link for TcpStream {
+    fn shutdown(&self) {
+        let _ = TcpStream::shutdown(self, Shutdown::Both);
+    }
+}
+
+// A connection with another node
+#[derive(Debug)]
+pub struct Connection {
+    pub stream: Box<dyn Link>,
+    // Whether we opened the connection, or the other node did
+    pub outbound: bool,
+    // Identity of the other node, known once it says hello
+    pub node_id: Option<u64>,
+    // Address where the other node listens, for connections it opened
+    pub dial_address: Option<String>,
+    // Optional features announced by the other node in its hello
+    pub capabilities: Vec<String>,
+    // Index of the last block of the other node, as far as we know
+    pub height: Option<u64>,
+    // Round trip of the request for its peers sent when connecting, known once it replies
+    pub latency: Option<Duration>,
+    opened_at: Instant,
+}
+
+impl Connection {
+    pub fn new(stream: impl Link + 'static, outbound: bool) -> Connection {
+        Connection {
+            stream: Box::new(stream),
+            outbound,
+            node_id: None,
+            dial_address: None,
+            capabilities: Vec::new(),
+            height: None,
+            latency: None,
+            opened_at: Instant::now(),
+        }
+    }
+
+    // Address that identifies the node at the other side of the connection
+    // For connections opened by the other node, it's the one where it listens (if we know it)
+    fn peer_id(&self, address: &str) -> String {
+        self.dial_address
+            .clone()
+            .unwrap_or_else(|| address.to_string())
+    }
+
+    pub fn close(&self) {
+        self.stream.shutdown();
+    }
+}
+
+// Open connections to other nodes, indexed by their address
+// Writes are done while holding the lock, so messages are never interleaved
+pub type SyncedConnections = Arc<Mutex<HashMap<String, Connection>>>;
+
+#[derive(Debug, Default)]
+struct Reputation {
+    score: u32,
+    bans: u32,
+    banned_until: Option<Instant>,
+    permanent_ban: bool,
+}
+
+impl Reputation {
+    fn is_banned(&self) -> bool {
+        self.permanent_ban || matches!(self.banned_until, Some(until) if Instant::now() < until)
+    }
+}
+
+// Status of a peer, as reported by the api
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PeerReport {
+    pub id: String,
+    pub connected: bool,
+    pub outbound: bool,
+    // Address at the other side of the connection, which differs from the id for inbound ones
+    pub address: Option<String>,
+    pub height: Option<u64>,
+    pub latency_ms: Option<u64>,
+    pub score: u32,
+    pub banned: bool,
+    pub permanent_ban: bool,
+}
+
+// Connected nodes, and the reputation of the ones that misbehaved
+// Every misbehavior adds a penalty to the score of the peer, which gets banned when it's too high
+// Cloning only clones the pointers, so the network and the api share the same state
+#[derive(Debug, Clone)]
+pub struct Peers {
+    pub connections: SyncedConnections,
+    reputations: Arc<Mutex<HashMap<String, Reputation>>>,
+    // Addresses that operators asked to connect to, kept connected as the configured peers
+    dials: Arc<Mutex<Vec<String>>>,
+    ban_duration: Duration,
+}
+
+impl Peers {
+    pub fn new(ban_secs: u64) -> Peers {
+        Peers {
+            connections: SyncedConnections::default(),
+            reputations: Arc::new(Mutex::new(HashMap::new())),
+            dials: Arc::new(Mutex::new(Vec::new())),
+            ban_duration: Duration::from_secs(ban_secs),
+        }
+    }
+
+    // Add the penalty of a misbehavior to the peer at the other side of a connection
+    // Returns true if the peer got banned, in which case we disconnect from it
+    pub fn penalize(&self, address: &str, misbehavior: Misbehavior) -> bool {
+        let mut connections = self.connections.lock().unwrap();
+        let id = match connections.get(address) {
+            Some(connection) => connection.peer_id(address),
+            None => return false,
+        };
+
+        let mut reputations = self.reputations.lock().unwrap();
+        let reputation = reputations.entry(id.clone()).or_default();
+        reputation.score += misbehavior.penalty();
+        if reputation.score < BAN_SCORE {
+            return false;
+        }
+
+        // the peer starts from scratch when the ban expires, but it's not forgotten
+        reputation.score = 0;
+        reputation.bans += 1;
+        if reputation.bans >= MAX_TEMPORARY_BANS {
+            reputation.permanent_ban = true;
+        } else {
+            reputation.banned_until = Some(Instant::now() + self.ban_duration);
+        }
+        Peers::disconnect(&mut connections, &id);
+
+        true
+    }
+
+    pub fn is_banned(&self, id: &str) -> bool {
+        let reputations = self.reputations.lock().unwrap();
+        reputations
+            .get(id)
+            .map(Reputation::is_banned)
+            .unwrap_or(false)
+    }
+
+    // Disconnect from a peer and never connect to it again, even if an operator asked to
+    // Returns false if we don't know the peer
+    pub fn ban(&self, id: &str) -> bool {
+        let mut connections = self.connections.lock().unwrap();
+        let mut reputations = self.reputations.lock().unwrap();
+        let mut dials = self.dials.lock().unwrap();
+        let is_connected = connections
+            .iter()
+            .any(|(address, connection)| connection.peer_id(address) == id);
+        let is_dialed = dials.iter().any(|dial| dial == id);
+        if !is_connected && !is_dialed && !reputations.contains_key(id) {
+            return false;
+        }
+
+        dials.retain(|dial| dial != id);
+
+        let reputation = reputations.entry(id.to_string()).or_default();
+        reputation.permanent_ban = true;
+        Peers::disconnect(&mut connections, id);
+
+        true
+    }
+
+    // Connect to a peer from now on, as if it was configured (the network connects to it shortly)
+    // Banned peers are refused, the ban is not lifted by asking to connect to them
+    pub fn dial(&self, address: &str) -> Result<(), NetworkError> {
+        let is_valid = match address.rsplit_once(':') {
+            Some((host, port)) => !host.is_empty() && port.parse::<u16>().is_ok(),
+            None => false,
+        };
+        if !is_valid {
+            return Err(NetworkError::InvalidAddress(address.to_string()));
+        }
+        if self.is_banned(address) {
+            return Err(NetworkError::BannedPeer(address.to_string()));
+        }
+
+        let mut dials = self.dials.lock().unwrap();
+        if !dials.iter().any(|dial| dial == address) {
+            dials.push(address.to_string());
+        }
+        Ok(())
+    }
+
+    // Addresses to keep connected to, besides the configured ones
+    pub fn dials(&self) -> Vec<String> {
+        self.dials.lock().unwrap().clone()
+    }
+
+    // The node at the other side of a connection has a new last block
+    pub fn update_height(&self, address: &str, height: u64) {
+        let mut connections = self.connections.lock().unwrap();
+        if let Some(connection) = connections.get_mut(address) {
+            connection.height = connection.height.max(Some(height));
+        }
+    }
+
+    // The node at the other side of a connection replied to the request sent when connecting
+    pub fn measure_latency(&self, address: &str) {
+        let mut connections = self.connections.lock().unwrap();
+        if let Some(connection) = connections.get_mut(address) {
+            let elapsed = connection.opened_at.elapsed();
+            connection.latency.get_or_insert(elapsed);
+        }
+    }
+
+    // Number of distinct peers we are connected to
+    pub fn count_connected(&self) -> usize {
+        let connections = self.connections.lock().unwrap();
+        let ids: HashSet<String> = connections
+            .iter()
+            .map(|(address, connection)| connection.peer_id(address))
+            .collect();
+
+        ids.len()
+    }
+
+    // Status of the connected peers and the ones with a bad reputation, sorted by id
+    pub fn report(&self) -> Vec<PeerReport> {
+        let connections = self.connections.lock().unwrap();
+        let reputations = self.reputations.lock().unwrap();
+
+        let mut reports = BTreeMap::new();
+        for (id, reputation) in reputations.iter() {
+            let report = PeerReport {
+                id: id.clone(),
+                connected: false,
+                outbound: false,
+                address: None,
+                height: None,
+                latency_ms: None,
+                score: reputation.score,
+                banned: reputation.is_banned(),
+                permanent_ban: reputation.permanent_ban,
+            };
+            reports.insert(id.clone(), report);
+        }
+
+        // the same node may be connected more than once, e.g. if both sides opened a connection
+        // so the best of its connections is reported
+        for (address, connection) in connections.iter() {
+            let id = connection.peer_id(address);
+            let report = reports.entry(id.clone()).or_insert_with(|| PeerReport {
+                id,
+                connected: false,
+                outbound: false,
+                address: None,
+                height: None,
+                latency_ms: None,
+                score: 0,
+                banned: false,
+                permanent_ban: false,
+            });
+            report.connected = true;
+            report.outbound |= connection.outbound;
+            report.address.get_or_insert_with(|| address.clone());
+            report.height = report.height.max(connection.height);
+            if let Some(latency) = connection.latency {
+                let latency_ms = latency.as_millis() as u64;
+                report.latency_ms = Some(
+                    report
+                        .latency_ms
+                        .map_or(latency_ms, |ms| ms.min(latency_ms)),
+                );
+            }
+        }
+
+        reports.into_values().collect()
+    }
+
+    // Close all the connections with a peer
+    fn disconnect(connections: &mut HashMap<String, Connection>, id: &str) {
+        connections.retain(|address, connection| {
+            if connection.peer_id(address) != id {
+                return true;
+            }
+
+            connection.close();
+            false
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn should_ban_misbehaving_peers() {
+        let peers = Peers::new(60);
+        add_connection(&peers, "a:1");
+
+        // a few malformed messages are tolerated
+        for _ in 0..9 {
+            assert!(!peers.penalize("a:1", Misbehavior::MalformedMessage));
+        }
+        assert!(!peers.is_banned("a:1"));
+
+        assert!(peers.penalize("a:1", Misbehavior::MalformedMessage));
+        assert!(peers.is_banned("a:1"));
+        assert!(peers.connections.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn should_ban_permanently_after_repeated_bans() {
+        let peers = Peers::new(0);
+        for _ in 0..MAX_TEMPORARY_BANS - 1 {
+            add_connection(&peers, "a:1");
+            peers.penalize("a:1", Misbehavior::InvalidBlock);
+            assert!(peers.penalize("a:1", Misbehavior::InvalidBlock));
+
+            // the ban has already expired
+            assert!(!peers.is_banned("a:1"));
+        }
+
+        add_connection(&peers, "a:1");
+        peers.penalize("a:1", Misbehavior::InvalidBlock);
+        assert!(peers.penalize("a:1", Misbehavior::InvalidBlock));
+        assert!(peers.is_banned("a:1"));
+    }
+
+    #[test]
+    fn should_identify_peers_by_their_listening_address() {
+        let peers = Peers::new(60);
+        add_connection(&peers, "127.0.0.1:54321");
+        peers
+            .connections
+            .lock()
+            .unwrap()
+            .get_mut("127.0.0.1:54321")
+            .unwrap()
+            .dial_address = Some("127.0.0.1:9001".to_string());
+        peers.penalize("127.0.0.1:54321", Misbehavior::Flooding);
+
+        let reports = peers.report();
+        assert_eq!(peers.count_connected(), 1);
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].id, "127.0.0.1:9001");
+        assert_eq!(reports[0].score, 25);
+        assert!(reports[0].connected);
+        assert!(!reports[0].outbound);
+    }
+
+    #[test]
+    fn should_let_operators_ban_peers() {
+        let peers = Peers::new(60);
+        add_connection(&peers, "a:1");
+
+        assert!(!peers.ban("b:2"));
+        assert!(peers.ban("a:1"));
+
+        let reports = peers.report();
+        assert_eq!(reports.len(), 1);
+        assert!(!reports[0].connected);
+        assert!(reports[0].permanent_ban);
+    }
+
+    #[test]
+    fn should_let_operators_dial_peers() {
+        let peers = Peers::new(60);
+        assert_eq!(
+            peers.dial("localhost"),
+            Err(NetworkError::InvalidAddress("localhost".to_string()))
+        );
+        assert!(peers.dial(":9000").is_err());
+
+        peers.dial("localhost:9000").unwrap();
+        peers.dial("localhost:9000").unwrap();
+        peers.dial("localhost:9001").unwrap();
+        assert_eq!(peers.dials(), vec!["localhost:9000", "localhost:9001"]);
+
+        // banning a peer cancels the request to connect to it
+        assert!(peers.ban("localhost:9000"));
+        assert_eq!(peers.dials(), vec!["localhost:9001"]);
+        assert_eq!(
+            peers.dial("localhost:9000"),
+            Err(NetworkError::BannedPeer("localhost:9000".to_string()))
+        );
+    }
+
+    #[test]
+    fn should_report_the_height_and_latency_of_peers() {
+        let peers = Peers::new(60);
+        add_connection(&peers, "a:1");
+        peers.update_height("a:1", 5);
+        peers.update_height("a:1", 3);
+
+        let reports = peers.report();
+        assert_eq!(reports[0].address.as_deref(), Some("a:1"));
+        assert_eq!(reports[0].height, Some(5));
+        assert_eq!(reports[0].latency_ms, None);
+
+        // only the first reply is measured
+        peers.measure_latency("a:1");
+        let latency = peers.connections.lock().unwrap()["a:1"].latency;
+        peers.measure_latency("a:1");
+        assert_eq!(peers.connections.lock().unwrap()["a:1"].latency, latency);
+        assert!(peers.report()[0].latency_ms.is_some());
+    }
+
+    // Registers a connection backed by a real TCP stream to a local listener
+    fn add_connection(peers: &Peers, address: &str) {
+        let listener = TcpListener::bind(("localhost", 0)).unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        peers
+            .connections
+            .lock()
+            .unwrap()
+            .insert(address.to_string(), Connection::new(stream, false));
+    }
+}
@@ -0,0 +1,111 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+use crate::model::{TransactionId, TransactionProof};
+
+// Max number of verified proofs kept by a light client, the oldest ones are forgotten first
+const MAX_VERIFIED_PROOFS: usize = 10_000;
+
+#[derive(Debug, Default)]
+struct ProofsState {
+    // transactions whose proofs were asked for, but not requested to the peers yet
+    requested: Vec<TransactionId>,
+    // proofs already checked against our headers, and the order in which they arrived
+    verified: HashMap<TransactionId, TransactionProof>,
+    order: VecDeque<TransactionId>,
+}
+
+// Proofs of inclusion of transactions that a light client gets from full nodes
+// The api asks for them and the network requests them to the peers that serve proofs,
+// then the ones that match our headers are kept to answer the api
+// Cloning only clones the pointer, so the api and the network share the same state
+#[derive(Debug, Clone, Default)]
+pub struct InclusionProofs {
+    state: Arc<Mutex<ProofsState>>,
+}
+
+impl InclusionProofs {
+    pub fn new() -> InclusionProofs {
+        InclusionProofs::default()
+    }
+
+    // Ask the peers for the proof of a transaction, unless we already have it or it's on its way
+    pub fn request(&self, id: TransactionId) {
+        let mut state = self.state.lock().unwrap();
+        if !state.verified.contains_key(&id) && !state.requested.contains(&id) {
+            state.requested.push(id);
+        }
+    }
+
+    pub fn pop_requests(&self) -> Vec<TransactionId> {
+        let mut state = self.state.lock().unwrap();
+        state.requested.drain(..).collect()
+    }
+
+    // Keep a proof that was already checked against the header of its block
+    pub fn add_verified(&self, proof: TransactionProof) {
+        let mut state = self.state.lock().unwrap();
+        let id = proof.transaction_id;
+        if state.verified.insert(id, proof).is_none() {
+            state.order.push_back(id);
+        }
+
+        while state.order.len() > MAX_VERIFIED_PROOFS {
+            if let Some(oldest) = state.order.pop_front() {
+                state.verified.remove(&oldest);
+            }
+        }
+    }
+
+    pub fn get(&self, id: TransactionId) -> Option<TransactionProof> {
+        let state = self.state.lock().unwrap();
+        state.verified.get(&id).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{BlockHash, MerkleProof};
+
+    #[test]
+    fn should_request_each_proof_once() {
+        let proofs = InclusionProofs::new();
+        let id = TransactionId::from(1);
+
+        proofs.request(id);
+        proofs.request(id);
+        assert_eq!(proofs.pop_requests(), vec![id]);
+        assert!(proofs.pop_requests().is_empty());
+        assert_eq!(proofs.get(id), None);
+
+        // once we have the proof, there is no need to ask for it again
+        let proof = create_proof(id);
+        proofs.add_verified(proof.clone());
+        proofs.request(id);
+        assert!(proofs.pop_requests().is_empty());
+        assert_eq!(proofs.get(id), Some(proof));
+    }
+
+    #[test]
+    fn should_forget_the_oldest_proofs() {
+        let proofs = InclusionProofs::new();
+        for id in 0..=MAX_VERIFIED_PROOFS as u64 {
+            proofs.add_verified(create_proof(TransactionId::from(id)));
+        }
+
+        assert_eq!(proofs.get(TransactionId::from(0)), None);
+        assert!(proofs.get(TransactionId::from(1)).is_some());
+    }
+
+    fn create_proof(id: TransactionId) -> TransactionProof {
+        TransactionProof {
+            transaction_id: id,
+            block_index: 1,
+            block_hash: BlockHash::from(2),
+            proof: MerkleProof::new(&[id], 0).unwrap(),
+        }
+    }
+}
@@ -0,0 +1,302 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+
+use super::{Message, NetworkError};
+use crate::model::{Block, BlockHeader, SealedBlock, SealedHeader};
+
+// Max number of headers sent in a single "Headers" message
+pub const MAX_HEADERS: usize = 500;
+
+// Max number of blocks requested in a single "GetBlocks" message
+pub const BLOCKS_BATCH: usize = 50;
+
+// A synchronization without progress for this long is considered stalled,
+// so we can start over with another peer
+const SYNC_TIMEOUT_MS: u64 = 5000;
+
+// Progress of the synchronization with a single peer
+#[derive(Debug)]
+struct SyncProgress {
+    peer: String,
+    // Index of the last block of the peer, as far as we know
+    target_index: u64,
+    // Validated headers of the blocks we have not downloaded yet, in chain order
+    headers: VecDeque<SealedHeader>,
+    // Whether we already received all the headers up to the target
+    has_all_headers: bool,
+    last_update: Instant,
+}
+
+// Headers-first synchronization with the best known peer
+// First we download and validate the chain of headers, then the blocks in batches
+// Cloning only clones the pointer, so all the connections share the same state
+#[derive(Debug, Clone, Default)]
+pub struct ChainSync {
+    progress: Arc<Mutex<Option<SyncProgress>>>,
+}
+
+impl ChainSync {
+    pub fn new() -> ChainSync {
+        ChainSync::default()
+    }
+
+    // Start synchronizing with a peer that is ahead of us
+    // Returns false if we are already synchronizing with another peer that is still responding
+    pub fn start(&self, peer: &str, target_index: u64) -> bool {
+        let mut progress = self.progress.lock().unwrap();
+        if let Some(current) = progress.as_mut() {
+            // the peer we are synchronizing with kept adding blocks
+            if current.peer == peer {
+                if target_index > current.target_index {
+                    current.target_index = target_index;
+                    current.has_all_headers = false;
+                }
+                return false;
+            }
+
+            let timeout = Duration::from_millis(SYNC_TIMEOUT_MS);
+            if current.last_update.elapsed() < timeout {
+                return false;
+            }
+        }
+
+        *progress = Some(SyncProgress {
+            peer: peer.to_string(),
+            target_index,
+            headers: VecDeque::new(),
+            has_all_headers: false,
+            last_update: Instant::now(),
+        });
+
+        true
+    }
+
+    pub fn is_syncing_with(&self, peer: &str) -> bool {
+        let progress = self.progress.lock().unwrap();
+        matches!(progress.as_ref(), Some(current) if current.peer == peer)
+    }
+
+    // Returns the index of the last block of the peer we are synchronizing with, if any
+    pub fn target_index(&self) -> Option<u64> {
+        let progress = self.progress.lock().unwrap();
+        progress.as_ref().map(|current| current.target_index)
+    }
+
+    pub fn cancel(&self) {
+        let mut progress = self.progress.lock().unwrap();
+        *progress = None;
+    }
+
+    // Queue the headers received from the peer, they must follow the last queued header
+    // (or our last block, if there are none) without gaps, and their hashes must be right
+    // The hashes only cover the headers, so forged headers are caught before downloading any block
+    pub fn add_headers(&self, headers: Vec<BlockHeader>, last_block: &Block) -> Result<()> {
+        let mut progress = self.progress.lock().unwrap();
+        let current = match progress.as_mut() {
+            Some(current) => current,
+            None => return Ok(()),
+        };
+
+        let (mut previous_index, mut previous_hash) = match current.headers.back() {
+            Some(header) => (header.index, header.hash),
+            None => (last_block.header.index, last_block.header.hash),
+        };
+        let count = headers.len();
+        let mut sealed_headers = Vec::with_capacity(count);
+        for header in headers {
+            let index = header.index;
+            if index != previous_index + 1 || header.previous_hash != previous_hash {
+                return Err(NetworkError::UnlinkedHeader(index).into());
+            }
+            let header = SealedHeader::new(header).ok_or(NetworkError::InvalidHeaderHash(index))?;
+            previous_index = index;
+            previous_hash = header.hash;
+            sealed_headers.push(header);
+        }
+
+        // the peer sends fewer headers than the max only when there are no more
+        if count < MAX_HEADERS || previous_index >= current.target_index {
+            current.has_all_headers = true;
+        }
+        current.headers.extend(sealed_headers);
+        current.last_update = Instant::now();
+
+        Ok(())
+    }
+
+    // Seals the downloaded blocks with their queued headers, as their hashes were already checked
+    // Returns None if any of them doesn't match its header, so it must be checked like any other block
+    pub fn seal(&self, blocks: &[Block]) -> Option<Vec<SealedBlock>> {
+        let progress = self.progress.lock().unwrap();
+        let current = progress.as_ref()?;
+
+        blocks
+            .iter()
+            .map(|block| {
+                let index = block.header.index;
+                let header = current
+                    .headers
+                    .iter()
+                    .find(|header| header.index == index)?;
+                SealedBlock::with_header(block.clone(), header)
+            })
+            .collect()
+    }
+
+    // Forget the headers of the blocks already in our blockchain
+    pub fn remove_downloaded(&self, last_index: u64) {
+        let mut progress = self.progress.lock().unwrap();
+        if let Some(current) = progress.as_mut() {
+            while matches!(current.headers.front(), Some(header) if header.index <= last_index) {
+                current.headers.pop_front();
+            }
+            current.last_update = Instant::now();
+        }
+    }
+
+    // Next request to send to the peer: more headers until we have all of them, then the blocks
+    // Returns None, and finishes the synchronization, when there is nothing left to download
+    pub fn next_request(&self, last_index: u64) -> Option<Message> {
+        let mut progress = self.progress.lock().unwrap();
+        let current = progress.as_mut()?;
+
+        if !current.has_all_headers {
+            let from_index = match current.headers.back() {
+                Some(header) => header.index + 1,
+                None => last_index + 1,
+            };
+            return Some(Message::GetHeaders { from_index });
+        }
+
+        if !current.headers.is_empty() {
+            let hashes = current
+                .headers
+                .iter()
+                .take(BLOCKS_BATCH)
+                .map(|header| header.hash)
+                .collect();
+            return Some(Message::GetBlocks { hashes });
+        }
+
+        *progress = None;
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::BlockHash;
+
+    #[test]
+    fn should_download_headers_before_blocks() {
+        let blocks = create_chain(3);
+        let sync = ChainSync::new();
+        assert!(sync.start("a:1", 2));
+
+        // we start asking for the headers after our last block
+        let request = sync.next_request(0);
+        assert!(matches!(
+            request,
+            Some(Message::GetHeaders { from_index: 1 })
+        ));
+
+        let headers = blocks[1..].iter().map(Block::header).collect();
+        sync.add_headers(headers, &blocks[0]).unwrap();
+
+        match sync.next_request(0) {
+            Some(Message::GetBlocks { hashes }) => {
+                assert_eq!(hashes, vec![blocks[1].header.hash, blocks[2].header.hash])
+            }
+            request => panic!("unexpected request {:?}", request),
+        }
+
+        // once all the blocks are added, the synchronization finishes
+        sync.remove_downloaded(2);
+        assert!(sync.next_request(2).is_none());
+        assert!(!sync.is_syncing_with("a:1"));
+    }
+
+    #[test]
+    fn should_reject_unlinked_headers() {
+        let blocks = create_chain(3);
+        let sync = ChainSync::new();
+        sync.start("a:1", 2);
+
+        // there is a gap between our last block and the headers
+        let headers = vec![blocks[2].header()];
+        let err = sync.add_headers(headers, &blocks[0]).unwrap_err();
+        assert_eq!(
+            err.downcast::<NetworkError>().unwrap(),
+            NetworkError::UnlinkedHeader(2)
+        );
+
+        // the header belongs to another chain
+        let mut header = blocks[1].header();
+        header.previous_hash = BlockHash::from(1);
+        assert!(sync.add_headers(vec![header], &blocks[0]).is_err());
+
+        // the header was altered after being hashed
+        let mut header = blocks[1].header();
+        header.nonce += 1;
+        let err = sync.add_headers(vec![header], &blocks[0]).unwrap_err();
+        assert_eq!(
+            err.downcast::<NetworkError>().unwrap(),
+            NetworkError::InvalidHeaderHash(1)
+        );
+    }
+
+    #[test]
+    fn should_seal_the_blocks_of_the_checked_headers() {
+        let blocks = create_chain(3);
+        let sync = ChainSync::new();
+        sync.start("a:1", 2);
+        let headers = blocks[1..].iter().map(Block::header).collect();
+        sync.add_headers(headers, &blocks[0]).unwrap();
+
+        let sealed = sync.seal(&blocks[1..]).unwrap();
+        assert_eq!(sealed.len(), 2);
+        assert_eq!(sealed[1].header, blocks[2].header);
+
+        // a block that is not the one of its header must be checked as usual
+        let mut block = blocks[2].clone();
+        block.header.nonce += 1;
+        block.header.hash = block.calculate_hash();
+        assert!(sync.seal(&[blocks[1].clone(), block]).is_none());
+
+        // and so are the blocks that we didn't ask for
+        sync.remove_downloaded(2);
+        assert!(sync.seal(&blocks[1..]).is_none());
+    }
+
+    #[test]
+    fn should_sync_with_a_single_peer_at_a_time() {
+        let sync = ChainSync::new();
+        assert!(sync.start("a:1", 2));
+        assert!(!sync.start("b:2", 5));
+        assert!(sync.is_syncing_with("a:1"));
+
+        // the peer we are synchronizing with can raise the target
+        assert!(!sync.start("a:1", 3));
+        assert_eq!(sync.target_index(), Some(3));
+
+        sync.cancel();
+        assert!(sync.start("b:2", 5));
+    }
+
+    // Creates a list of linked blocks, starting with a genesis block
+    fn create_chain(length: u64) -> Vec<Block> {
+        let mut blocks = vec![Block::new(0, 0, BlockHash::default(), Vec::new())];
+        for index in 1..length {
+            let previous_hash = blocks.last().unwrap().header.hash;
+            blocks.push(Block::new(index, 0, previous_hash, Vec::new()));
+        }
+
+        blocks
+    }
+}
@@ -0,0 +1,428 @@
+use std::{
+    collections::VecDeque,
+    io::{self, Write},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use super::{
+    message, peers::Link, ChainSync, CompactBlocks, Gossip, Handler, InclusionProofs, Message,
+    Network, NetworkTime, OrphanBlocks, PeerBook, Peers,
+};
+use crate::{
+    consensus::ProofOfWork,
+    model::{Block, BlockHash, Blockchain, EventReceiver, Transaction, TransactionPool},
+    wallet::{Wallet, WalletMode},
+};
+
+// Port where every simulated node listens, they are told apart by their ip
+const P2P_PORT: u16 = 9000;
+
+// Rounds of messages after which a simulation that is still busy is considered stuck
+const MAX_ROUNDS: usize = 1000;
+
+// A message written by a node, waiting to be received by another one
+#[derive(Debug)]
+struct Envelope {
+    to: usize,
+    // address of the sender in the connections of the receiver
+    from_address: String,
+    data: Vec<u8>,
+}
+
+type SharedQueue = Arc<Mutex<VecDeque<Envelope>>>;
+
+// One direction of a connection between simulated nodes
+// Everything written to it is queued until the simulation delivers it
+#[derive(Debug)]
+struct MemoryLink {
+    to: usize,
+    from_address: String,
+    queue: SharedQueue,
+    // shared by both directions, closing one side breaks the whole connection like in TCP
+    open: Arc<AtomicBool>,
+}
+
+impl Write for MemoryLink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !self.open.load(Ordering::SeqCst) {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+
+        self.queue.lock().unwrap().push_back(Envelope {
+            to: self.to,
+            from_address: self.from_address.clone(),
+            data: buf.to_vec(),
+        });
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Link for MemoryLink {
+    fn shutdown(&self) {
+        self.open.store(false, Ordering::SeqCst);
+    }
+}
+
+// A connection between two simulated nodes, and the address of each one in the other
+#[derive(Debug)]
+struct SimulatedConnection {
+    nodes: (usize, usize),
+    addresses: (String, String),
+    open: Arc<AtomicBool>,
+}
+
+struct SimulatedNode {
+    network: Network,
+    events: EventReceiver,
+}
+
+// Several nodes running in the same process, talking to each other through memory instead of TCP
+// Tests script scenarios step by step (transactions, mined blocks, partitions...) and then let
+// the messages flow until the network settles, to check where every node ends up
+// Nothing runs in the background: messages are only delivered in "run_until_idle"
+pub struct Simulation {
+    nodes: Vec<SimulatedNode>,
+    queue: SharedQueue,
+    connections: Vec<SimulatedConnection>,
+    // connections dropped by a partition, to open them again once it heals
+    partitioned: Vec<(usize, usize)>,
+    next_port: u16,
+}
+
+impl Simulation {
+    // Creates unconnected nodes with the same genesis block, mining with a target that any hash meets
+    pub fn new(count: usize) -> Simulation {
+        let nodes = (0..count)
+            .map(|index| {
+                let blockchain = Blockchain::new(ProofOfWork::shared(0, u64::MAX, 1));
+                let events = blockchain.events().subscribe();
+                let orphans = OrphanBlocks::new(blockchain.clock());
+                SimulatedNode {
+                    network: Network {
+                        peer_addresses: Vec::new(),
+                        seed_addresses: Vec::new(),
+                        max_peers: count,
+                        announce_ms: 0,
+                        handler: Handler {
+                            node_id: index as u64 + 1,
+                            port: P2P_PORT,
+                            chain_id: "main".to_string(),
+                            blockchain,
+                            pool: TransactionPool::new(),
+                            wallet: Wallet::new(WalletMode::Hot, Vec::new()).unwrap(),
+                            gossip: Gossip::new(),
+                            peer_book: PeerBook::new(),
+                            sync: ChainSync::new(),
+                            orphans,
+                            peers: Peers::new(60),
+                            light_client: false,
+                            proofs: InclusionProofs::new(),
+                            compact_relay: true,
+                            compact_blocks: CompactBlocks::new(),
+                            network_time: NetworkTime::new(60, false),
+                        },
+                    },
+                    events,
+                }
+            })
+            .collect();
+
+        Simulation {
+            nodes,
+            queue: SharedQueue::default(),
+            connections: Vec::new(),
+            partitioned: Vec::new(),
+            next_port: 40000,
+        }
+    }
+
+    pub fn blockchain(&self, node: usize) -> &Blockchain {
+        &self.nodes[node].network.handler.blockchain
+    }
+
+    pub fn pool(&self, node: usize) -> &TransactionPool {
+        &self.nodes[node].network.handler.pool
+    }
+
+    // Hash of the last block of every node
+    pub fn tips(&self) -> Vec<BlockHash> {
+        (0..self.nodes.len())
+            .map(|node| self.blockchain(node).get_last_block().header.hash)
+            .collect()
+    }
+
+    pub fn is_converged(&self) -> bool {
+        let tips = self.tips();
+        tips.iter().all(|tip| *tip == tips[0])
+    }
+
+    // Address where a node listens for connections
+    fn listen_address(node: usize) -> String {
+        format!("10.0.0.{}:{}", node + 1, P2P_PORT)
+    }
+
+    // Opens a connection from the first node to the second one, which say hello to each other
+    pub fn connect(&mut self, from: usize, to: usize) {
+        // like in TCP, the node that receives the connection sees a random port of the other one
+        let addresses = (
+            Simulation::listen_address(to),
+            format!("10.0.0.{}:{}", from + 1, self.next_port),
+        );
+        self.next_port += 1;
+
+        let open = Arc::new(AtomicBool::new(true));
+        let link = |to: usize, from_address: &str| MemoryLink {
+            to,
+            from_address: from_address.to_string(),
+            queue: self.queue.clone(),
+            open: open.clone(),
+        };
+        let outbound = link(to, &addresses.1);
+        let inbound = link(from, &addresses.0);
+
+        Network::add_connection(&addresses.0, outbound, true, self.handler(from));
+        Network::add_connection(&addresses.1, inbound, false, self.handler(to));
+        self.connections.push(SimulatedConnection {
+            nodes: (from, to),
+            addresses,
+            open,
+        });
+    }
+
+    // Connects every node with the rest of them
+    pub fn connect_all(&mut self) {
+        for from in 0..self.nodes.len() {
+            for to in from + 1..self.nodes.len() {
+                self.connect(from, to);
+            }
+        }
+    }
+
+    // Closes the connections between two nodes, the messages on their way are lost
+    pub fn disconnect(&mut self, a: usize, b: usize) {
+        let (closed, open): (Vec<_>, Vec<_>) = self
+            .connections
+            .drain(..)
+            .partition(|connection| connection.nodes == (a, b) || connection.nodes == (b, a));
+        self.connections = open;
+
+        for connection in closed {
+            connection.open.store(false, Ordering::SeqCst);
+            let (from, to) = connection.nodes;
+            Network::remove_connection(self.handler(from), &connection.addresses.0);
+            Network::remove_connection(self.handler(to), &connection.addresses.1);
+        }
+    }
+
+    // Splits the nodes in two groups that can't reach each other
+    pub fn partition(&mut self, group: &[usize], other_group: &[usize]) {
+        for a in group {
+            for b in other_group {
+                let is_connected = self
+                    .connections
+                    .iter()
+                    .any(|connection| connection.nodes == (*a, *b) || connection.nodes == (*b, *a));
+                if is_connected {
+                    self.disconnect(*a, *b);
+                    self.partitioned.push((*a, *b));
+                }
+            }
+        }
+    }
+
+    // Opens again the connections dropped by the partitions
+    pub fn heal(&mut self) {
+        for (a, b) in std::mem::take(&mut self.partitioned) {
+            self.connect(a, b);
+        }
+    }
+
+    // Adds a transaction to the pool of a node, as if a client sent it through the api
+    pub fn add_transaction(&self, node: usize, transaction: Transaction) {
+        let handler = self.handler(node);
+        handler.pool.add_transaction(transaction.clone()).unwrap();
+        handler.gossip.relay_transaction(&transaction);
+    }
+
+    // Mines a block with the transactions in the pool of the node on top of its last block
+    // Every node mines with a different nonce, so blocks mined at the same time never match
+    pub fn mine(&self, node: usize) -> Block {
+        let handler = self.handler(node);
+        let blockchain = &handler.blockchain;
+        let last_block = blockchain.get_last_block();
+        let transactions = handler.pool.pop();
+        let mut block = Block::new(
+            last_block.header.index + 1,
+            node as u64,
+            last_block.header.hash,
+            transactions.clone(),
+        );
+        block.header.timestamp = block.header.timestamp.max(blockchain.min_timestamp());
+        block.header.bits = blockchain.next_bits();
+        block.header.state_root = blockchain.next_state_root(&block.transactions);
+        block.header.hash = block.calculate_hash();
+        blockchain.add_block(block.clone()).unwrap();
+        handler.pool.release(&transactions);
+
+        block
+    }
+
+    // Lets the nodes announce their news and answer each other until no messages are left
+    pub fn run_until_idle(&mut self) {
+        for _ in 0..MAX_ROUNDS {
+            for node in self.nodes.iter_mut() {
+                node.network.announce(&node.events);
+            }
+
+            let envelopes: Vec<Envelope> = self.queue.lock().unwrap().drain(..).collect();
+            if envelopes.is_empty() {
+                return;
+            }
+            for envelope in envelopes {
+                self.deliver(envelope);
+            }
+        }
+
+        panic!("the simulation is still busy after {} rounds", MAX_ROUNDS);
+    }
+
+    fn deliver(&self, envelope: Envelope) {
+        // the connection was closed while the message was on its way
+        let handler = self.handler(envelope.to);
+        let address = &envelope.from_address;
+        if !handler
+            .peers
+            .connections
+            .lock()
+            .unwrap()
+            .contains_key(address)
+        {
+            return;
+        }
+
+        let mut data = envelope.data.as_slice();
+        while !data.is_empty() {
+            let payload = message::read_frame(&mut data).unwrap();
+            let message = Message::decode(&payload).unwrap();
+            Network::receive(handler, address, message);
+        }
+    }
+
+    fn handler(&self, node: usize) -> &Handler {
+        &self.nodes[node].network.handler
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_propagate_transactions_and_blocks() {
+        // a line of nodes, the ends only reach each other through the one in the middle
+        let mut simulation = Simulation::new(3);
+        simulation.connect(0, 1);
+        simulation.connect(1, 2);
+        simulation.run_until_idle();
+
+        simulation.add_transaction(0, create_transaction(10));
+        simulation.run_until_idle();
+        assert_eq!(simulation.pool(2).size(), 1);
+
+        let block = simulation.mine(2);
+        assert_eq!(block.transactions.len(), 1);
+        simulation.run_until_idle();
+        assert!(simulation.is_converged());
+        assert_eq!(
+            simulation.blockchain(0).get_last_block().header,
+            block.header
+        );
+    }
+
+    #[test]
+    fn should_sync_nodes_that_join_later() {
+        let mut simulation = Simulation::new(2);
+        for _ in 0..5 {
+            simulation.mine(0);
+        }
+
+        simulation.connect(1, 0);
+        simulation.run_until_idle();
+        assert!(simulation.is_converged());
+        assert_eq!(simulation.blockchain(1).get_last_block().header.index, 5);
+    }
+
+    #[test]
+    fn should_converge_after_a_partition_heals() {
+        let mut simulation = Simulation::new(4);
+        simulation.connect_all();
+        simulation.run_until_idle();
+
+        // only one side of the partition keeps mining
+        simulation.partition(&[0, 1], &[2, 3]);
+        for _ in 0..3 {
+            simulation.mine(0);
+            simulation.run_until_idle();
+        }
+        assert_eq!(simulation.blockchain(1).get_last_block().header.index, 3);
+        assert_eq!(simulation.blockchain(2).get_last_block().header.index, 0);
+        assert!(!simulation.is_converged());
+
+        simulation.heal();
+        simulation.run_until_idle();
+        assert!(simulation.is_converged());
+        assert_eq!(simulation.blockchain(3).get_last_block().header.index, 3);
+    }
+
+    #[test]
+    fn should_converge_on_the_branch_of_racing_miners_with_more_work() {
+        let mut simulation = Simulation::new(2);
+        simulation.connect(0, 1);
+        simulation.run_until_idle();
+
+        // both miners find a block at the same height before hearing from the other one
+        simulation.mine(0);
+        simulation.mine(1);
+        simulation.run_until_idle();
+        assert!(!simulation.is_converged());
+
+        // the next block makes one branch heavier, and the other node switches to it
+        // after asking for the block it's missing from that branch
+        let block = simulation.mine(0);
+        simulation.run_until_idle();
+        assert!(simulation.is_converged());
+        assert_eq!(simulation.tips()[1], block.header.hash);
+    }
+
+    #[test]
+    fn should_lose_the_messages_sent_to_disconnected_nodes() {
+        let mut simulation = Simulation::new(2);
+        simulation.connect(0, 1);
+        simulation.run_until_idle();
+
+        simulation.add_transaction(0, create_transaction(10));
+        simulation.disconnect(0, 1);
+        simulation.run_until_idle();
+        assert_eq!(simulation.pool(1).size(), 0);
+    }
+
+    fn create_transaction(amount: u64) -> Transaction {
+        Transaction {
+            sender: "1".to_string(),
+            recipient: "2".to_string(),
+            amount,
+            signature: None,
+            multisig: None,
+            contract: None,
+            token: None,
+            data: None,
+        }
+    }
+}
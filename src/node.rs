@@ -0,0 +1,96 @@
+use anyhow::{anyhow, Result};
+use futures::{future::try_join_all, FutureExt};
+use tokio::task::{self, JoinHandle};
+
+use crate::{
+    api::Api,
+    miner::Miner,
+    network::Network,
+    notifier::Notifier,
+    peer::Peer,
+    plugin::PluginRunner,
+    scheduler::Scheduler,
+    util::{execution::Runnable, termination::Shutdown, Context},
+};
+
+// The systems of a running node, all of them tasks of the same runtime
+// The api is served by async tasks, while the rest run as blocking tasks in their own threads,
+// because mining is very cpu intensive and the p2p network uses blocking sockets
+pub struct Node {
+    shutdown: Shutdown,
+    tasks: Vec<(&'static str, JoinHandle<Result<()>>)>,
+}
+
+impl Node {
+    // Spawns all the systems of the node, it's async as they can only be spawned inside the runtime
+    pub async fn start(context: &Context) -> Node {
+        let systems: Vec<(&'static str, Box<dyn Runnable>)> = vec![
+            ("miner", Box::new(Miner::new(context))),
+            ("peer system", Box::new(Peer::new(context))),
+            ("notifier", Box::new(Notifier::new(context))),
+            ("p2p network", Box::new(Network::new(context))),
+            ("scheduler", Box::new(Scheduler::new(context))),
+            ("plugins", Box::new(PluginRunner::new(context))),
+        ];
+        let mut tasks: Vec<(&'static str, JoinHandle<Result<()>>)> = systems
+            .into_iter()
+            .map(|(name, system)| (name, task::spawn_blocking(move || system.run())))
+            .collect();
+
+        // the server of actix is not Send, so it stays in the thread of the runtime
+        let api = Api::new(context);
+        tasks.push(("api", task::spawn_local(async move { api.serve().await })));
+
+        Node {
+            shutdown: context.shutdown.clone(),
+            tasks,
+        }
+    }
+
+    // Handle to start the graceful shutdown, which drains the api and stops it
+    pub fn shutdown(&self) -> Shutdown {
+        self.shutdown.clone()
+    }
+
+    // Waits until every system finishes, or returns the error of the first one that fails
+    // Some systems (e.g. the miner) run until the process exits, so this only returns on errors
+    pub async fn join(self) -> Result<()> {
+        let tasks = self.tasks.into_iter().map(|(name, task)| {
+            task.map(move |joined| match joined {
+                Ok(result) => result.map_err(|error| error.context(format!("the {} failed", name))),
+                Err(error) => Err(anyhow!("the {} panicked: {}", name, error)),
+            })
+        });
+        try_join_all(tasks).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_return_the_first_error_of_the_systems() {
+        let mut runtime = actix_web::rt::Runtime::new().unwrap();
+        let result = runtime.block_on(async {
+            let node = Node {
+                shutdown: Shutdown::new(),
+                tasks: vec![
+                    ("forever", task::spawn_local(futures::future::pending())),
+                    (
+                        "api",
+                        task::spawn_local(async { Err(anyhow!("port already in use")) }),
+                    ),
+                ],
+            };
+            node.join().await
+        });
+
+        let error = result.unwrap_err();
+        assert_eq!(
+            format!("{:#}", error),
+            "the api failed: port already in use"
+        );
+    }
+}
@@ -0,0 +1,418 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::Arc,
+};
+
+use crate::{
+    model::{
+        hash_hex, Block, BlockHash, Blockchain, ChainEvent, ReorgEvent, Transaction, TransactionId,
+    },
+    util::{
+        execution::{sleep_millis, Runnable},
+        Context, Lock,
+    },
+};
+use anyhow::Result;
+use isahc::Request;
+use serde::{Deserialize, Serialize};
+
+pub type SubscriptionId = u64;
+
+// Max number of recent blocks whose confirmations are remembered, to undo them if a reorg reverts the blocks
+const MAX_CONFIRMED_BLOCKS: usize = 1_000;
+
+// A webhook that wants to be notified only of events touching a set of addresses
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Subscription {
+    pub url: String,
+    pub addresses: HashSet<String>,
+}
+
+impl Subscription {
+    // A transaction touches an address if it's either the sender or the recipient
+    fn is_interested_in(&self, transaction: &Transaction) -> bool {
+        self.addresses.contains(&transaction.sender)
+            || self.addresses.contains(&transaction.recipient)
+    }
+}
+
+// Events sent to subscribers
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum AddressEvent {
+    // The transaction entered the pool and is waiting to be mined
+    Pending {
+        #[serde(with = "hash_hex")]
+        transaction_id: TransactionId,
+        transaction: Transaction,
+    },
+    // The transaction was included in a block
+    Confirmed {
+        #[serde(with = "hash_hex")]
+        transaction_id: TransactionId,
+        transaction: Transaction,
+        block_index: u64,
+        #[serde(with = "hash_hex")]
+        block_hash: BlockHash,
+    },
+    // The block that included the transaction was replaced by a reorg
+    // It may be confirmed again in a block of the new branch, or go back to waiting
+    Unconfirmed {
+        #[serde(with = "hash_hex")]
+        transaction_id: TransactionId,
+        transaction: Transaction,
+        block_index: u64,
+        #[serde(with = "hash_hex")]
+        block_hash: BlockHash,
+    },
+}
+
+impl AddressEvent {
+    fn transaction(&self) -> &Transaction {
+        match self {
+            AddressEvent::Pending { transaction, .. } => transaction,
+            AddressEvent::Confirmed { transaction, .. } => transaction,
+            AddressEvent::Unconfirmed { transaction, .. } => transaction,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct SubscriptionsState {
+    next_id: SubscriptionId,
+    subscriptions: Vec<(SubscriptionId, Subscription)>,
+    pending_events: Vec<AddressEvent>,
+    // The confirmations sent for the last blocks, by block hash, and the order they arrived
+    confirmed: HashMap<BlockHash, Vec<AddressEvent>>,
+    confirmed_order: VecDeque<BlockHash>,
+}
+
+impl SubscriptionsState {
+    fn is_relevant(&self, event: &AddressEvent) -> bool {
+        self.subscriptions
+            .iter()
+            .any(|(_, sub)| sub.is_interested_in(event.transaction()))
+    }
+
+    // Keeps the confirmations of a block, forgetting the oldest blocks beyond the max
+    fn remember_confirmed(&mut self, block_hash: BlockHash, events: Vec<AddressEvent>) {
+        if self.confirmed.insert(block_hash, events).is_none() {
+            self.confirmed_order.push_back(block_hash);
+        }
+        while self.confirmed_order.len() > MAX_CONFIRMED_BLOCKS {
+            if let Some(oldest) = self.confirmed_order.pop_front() {
+                self.confirmed.remove(&oldest);
+            }
+        }
+    }
+}
+
+// Registry of subscriptions and queue of events waiting to be delivered
+// Multiple threads can read/write concurrently to the registry
+#[derive(Debug, Clone, Default)]
+pub struct Subscriptions {
+    state: Arc<Lock<SubscriptionsState>>,
+}
+
+impl Subscriptions {
+    pub fn new() -> Subscriptions {
+        Subscriptions::default()
+    }
+
+    // Registers a new subscription, returning its id
+    pub fn subscribe(&self, subscription: Subscription) -> SubscriptionId {
+        let mut state = self.state.lock();
+        state.next_id += 1;
+        let id = state.next_id;
+        state.subscriptions.push((id, subscription));
+
+        id
+    }
+
+    // Removes a subscription, returns false if it did not exist
+    pub fn unsubscribe(&self, id: SubscriptionId) -> bool {
+        let mut state = self.state.lock();
+        let previous_len = state.subscriptions.len();
+        state.subscriptions.retain(|(sub_id, _)| *sub_id != id);
+
+        state.subscriptions.len() != previous_len
+    }
+
+    // Queues the notification of a new transaction in the pool
+    pub fn notify_pending(&self, transaction: &Transaction) {
+        let event = AddressEvent::Pending {
+            transaction_id: transaction.calculate_id(),
+            transaction: transaction.clone(),
+        };
+        self.push_event(event);
+    }
+
+    // Queues the notification of all the transactions included in a block
+    // They are remembered for a while, in case a reorg reverts the block
+    pub fn notify_confirmed(&self, block: &Block) {
+        let mut state = self.state.lock();
+
+        let events: Vec<AddressEvent> = block
+            .transactions
+            .iter()
+            .map(|transaction| AddressEvent::Confirmed {
+                transaction_id: transaction.calculate_id(),
+                transaction: transaction.clone(),
+                block_index: block.header.index,
+                block_hash: block.header.hash,
+            })
+            .filter(|event| state.is_relevant(event))
+            .collect();
+        if !events.is_empty() {
+            state.pending_events.extend(events.iter().cloned());
+            state.remember_confirmed(block.header.hash, events);
+        }
+    }
+
+    // Queues the notification of the confirmed transactions of the blocks reverted by a reorg
+    // The blocks of the new branch come afterwards, so they are confirmed again if they are there
+    pub fn notify_reverted(&self, reorg: &ReorgEvent) {
+        let mut state = self.state.lock();
+
+        for block_hash in reorg.reverted.iter() {
+            let events = state.confirmed.remove(block_hash).unwrap_or_default();
+            for event in events {
+                if let AddressEvent::Confirmed {
+                    transaction_id,
+                    transaction,
+                    block_index,
+                    block_hash,
+                } = event
+                {
+                    state.pending_events.push(AddressEvent::Unconfirmed {
+                        transaction_id,
+                        transaction,
+                        block_index,
+                        block_hash,
+                    });
+                }
+            }
+        }
+    }
+
+    // Generates the confirmation events of the new blocks, and undoes them for the reverted ones
+    fn notify_chain_event(&self, event: &ChainEvent) {
+        match event {
+            ChainEvent::BlockAdded(block) => self.notify_confirmed(block),
+            ChainEvent::Reorg(reorg) => self.notify_reverted(reorg),
+            ChainEvent::TransactionAdmitted(_) => {}
+        }
+    }
+
+    fn push_event(&self, event: AddressEvent) {
+        let mut state = self.state.lock();
+
+        // no point in keeping events that nobody is interested in
+        if state.is_relevant(&event) {
+            state.pending_events.push(event);
+        }
+    }
+
+    // Empties the event queue, returning the events that each subscriber must receive
+    fn pop_deliveries(&self) -> Vec<(Subscription, AddressEvent)> {
+        let mut state = self.state.lock();
+        let events: Vec<AddressEvent> = state.pending_events.drain(..).collect();
+
+        let mut deliveries = Vec::new();
+        for event in events {
+            for (_, subscription) in state.subscriptions.iter() {
+                if subscription.is_interested_in(event.transaction()) {
+                    deliveries.push((subscription.clone(), event.clone()));
+                }
+            }
+        }
+
+        deliveries
+    }
+}
+
+// Delivers the events to the subscribed webhooks
+// It also watches the blockchain to generate the confirmation events, and to undo them on reorgs
+pub struct Notifier {
+    blockchain: Blockchain,
+    subscriptions: Subscriptions,
+    poll_ms: u64,
+}
+
+impl Runnable for Notifier {
+    fn run(&self) -> Result<()> {
+        self.start()
+    }
+}
+
+impl Notifier {
+    pub fn new(context: &Context) -> Notifier {
+        Notifier {
+            blockchain: context.blockchain.clone(),
+            subscriptions: context.subscriptions.clone(),
+            poll_ms: context.config.notification_poll_ms,
+        }
+    }
+
+    pub fn start(&self) -> Result<()> {
+        let events = self.blockchain.events().subscribe();
+
+        loop {
+            for event in events.pending() {
+                self.subscriptions.notify_chain_event(&event);
+            }
+
+            for (subscription, event) in self.subscriptions.pop_deliveries() {
+                Notifier::deliver(&subscription, &event);
+            }
+
+            sleep_millis(self.poll_ms);
+        }
+    }
+
+    // Send an event to a webhook, we don't want to stop if one subscriber is not working
+    fn deliver(subscription: &Subscription, event: &AddressEvent) {
+        let body = serde_json::to_string(event).unwrap();
+        let request = Request::post(&subscription.url)
+            .header("Content-Type", "application/json")
+            .body(body)
+            .unwrap();
+
+        if let Err(error) = isahc::send(request) {
+            error!(
+                "Could not notify subscriber {}: {}",
+                subscription.url, error
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::ProofOfWork;
+
+    #[test]
+    fn should_only_deliver_events_touching_subscribed_addresses() {
+        let subscriptions = Subscriptions::new();
+        subscriptions.subscribe(create_subscription("alice"));
+
+        subscriptions.notify_pending(&create_transaction("alice", "bob"));
+        subscriptions.notify_pending(&create_transaction("carol", "dave"));
+        subscriptions.notify_pending(&create_transaction("bob", "alice"));
+
+        // only the transactions sent or received by "alice" are delivered
+        let deliveries = subscriptions.pop_deliveries();
+        assert_eq!(deliveries.len(), 2);
+        assert_eq!(deliveries[0].1.transaction().recipient, "bob");
+        assert_eq!(deliveries[1].1.transaction().sender, "bob");
+
+        // the queue must be empty afterwards
+        assert!(subscriptions.pop_deliveries().is_empty());
+    }
+
+    #[test]
+    fn should_deliver_confirmed_transactions() {
+        let subscriptions = Subscriptions::new();
+        subscriptions.subscribe(create_subscription("alice"));
+
+        let transactions = vec![
+            create_transaction("alice", "bob"),
+            create_transaction("carol", "dave"),
+        ];
+        let block = Block::new(1, 0, BlockHash::default(), transactions);
+        subscriptions.notify_confirmed(&block);
+
+        let deliveries = subscriptions.pop_deliveries();
+        assert_eq!(deliveries.len(), 1);
+        match &deliveries[0].1 {
+            AddressEvent::Confirmed { block_index, .. } => assert_eq!(*block_index, 1),
+            event => panic!("expected a confirmed event, got {:?}", event),
+        }
+    }
+
+    #[test]
+    fn should_deliver_transactions_unconfirmed_by_reorgs() {
+        let subscriptions = Subscriptions::new();
+        subscriptions.subscribe(create_subscription("alice"));
+        let blockchain = Blockchain::new(ProofOfWork::shared(0, 1, 1));
+        let events = blockchain.events().subscribe();
+        let ours = add_next_block(&blockchain, vec![create_transaction("alice", "bob")]);
+
+        // a fork with more work replaces our block, without the transaction of alice
+        let other = Blockchain::new(ProofOfWork::shared(0, 1, 1));
+        add_next_block(&other, vec![create_transaction("carol", "dave")]);
+        add_next_block(&other, Vec::new());
+        blockchain
+            .add_blocks(other.get_all_blocks()[1..].to_vec())
+            .unwrap();
+        for event in events.pending() {
+            subscriptions.notify_chain_event(&event);
+        }
+
+        let deliveries = subscriptions.pop_deliveries();
+        assert_eq!(deliveries.len(), 2);
+        assert!(matches!(deliveries[0].1, AddressEvent::Confirmed { .. }));
+        match &deliveries[1].1 {
+            AddressEvent::Unconfirmed {
+                block_index,
+                block_hash,
+                ..
+            } => {
+                assert_eq!(*block_index, 1);
+                assert_eq!(*block_hash, ours.header.hash);
+            }
+            event => panic!("expected an unconfirmed event, got {:?}", event),
+        }
+    }
+
+    #[test]
+    fn should_not_deliver_after_unsubscribing() {
+        let subscriptions = Subscriptions::new();
+        let id = subscriptions.subscribe(create_subscription("alice"));
+
+        assert!(subscriptions.unsubscribe(id));
+        assert!(!subscriptions.unsubscribe(id));
+
+        subscriptions.notify_pending(&create_transaction("alice", "bob"));
+        assert!(subscriptions.pop_deliveries().is_empty());
+    }
+
+    fn create_subscription(address: &str) -> Subscription {
+        Subscription {
+            url: "http://localhost:9000".to_string(),
+            addresses: vec![address.to_string()].into_iter().collect(),
+        }
+    }
+
+    // Mines a block on top of the last one, with the target required by the blockchain
+    fn add_next_block(blockchain: &Blockchain, transactions: Vec<Transaction>) -> Block {
+        let last_block = blockchain.get_last_block();
+        let mut block = Block::new(
+            last_block.header.index + 1,
+            0,
+            last_block.header.hash,
+            transactions,
+        );
+        block.header.timestamp = block.header.timestamp.max(blockchain.min_timestamp());
+        block.header.bits = blockchain.next_bits();
+        block.header.state_root = blockchain.next_state_root(&block.transactions);
+        block.header.hash = block.calculate_hash();
+        blockchain.add_block(block.clone()).unwrap();
+
+        block
+    }
+
+    fn create_transaction(sender: &str, recipient: &str) -> Transaction {
+        Transaction {
+            sender: sender.to_string(),
+            recipient: recipient.to_string(),
+            amount: 1,
+            signature: None,
+            multisig: None,
+            contract: None,
+            token: None,
+            data: None,
+        }
+    }
+}
@@ -1,19 +1,25 @@
 use std::panic;
 
 use crate::{
+    api::API_KEY_HEADER,
     model::{Block, Blockchain},
     util::{
         execution::{sleep_millis, Runnable},
-        Context,
+        Byzantine, Context,
     },
 };
 use anyhow::Result;
 use isahc::{ReadResponseExt, Request};
+use tracing::info_span;
 
 pub struct Peer {
     peer_addresses: Vec<String>,
     blockchain: Blockchain,
     peer_sync_ms: u64,
+    // Nodes of the same network share the api key, so we can push blocks to our peers
+    api_key: String,
+    byzantine: Byzantine,
+    light_client: bool,
 }
 
 impl Runnable for Peer {
@@ -28,6 +34,9 @@ impl Peer {
             peer_addresses: context.config.peers.clone(),
             blockchain: context.blockchain.clone(),
             peer_sync_ms: context.config.peer_sync_ms,
+            api_key: context.config.api_key.clone(),
+            byzantine: context.config.byzantine.clone(),
+            light_client: context.config.light_client,
         }
     }
 
@@ -37,6 +46,12 @@ impl Peer {
             return Ok(());
         }
 
+        // the api of the peers only serves whole blocks
+        if self.light_client {
+            info!("Light clients only follow the headers of their p2p peers, exiting peer sync system");
+            return Ok(());
+        }
+
         info!(
             "start peer system with peers: {}",
             self.peer_addresses.join(", ")
@@ -53,12 +68,14 @@ impl Peer {
     }
 
     fn get_last_block_index(&self) -> usize {
-        self.blockchain.get_last_block().index as usize
+        self.blockchain.get_last_block().header.index as usize
     }
 
     // Retrieve new blocks from all peers and add them to the blockchain
     fn try_receive_new_blocks(&self) {
         for address in self.peer_addresses.iter() {
+            let _span = info_span!("sync", peer = address.as_str()).entered();
+
             // we don't want to panic if one peer is down or not working properly
             let result = panic::catch_unwind(|| {
                 let new_blocks = self.get_new_blocks_from_peer(address);
@@ -75,46 +92,58 @@ impl Peer {
         }
     }
 
-    // Try to add a bunch of new blocks to our blockchain
+    // Try to add a bunch of new blocks to our blockchain, all of them or none if any is invalid
     fn add_new_blocks(&self, new_blocks: &[Block]) {
-        for block in new_blocks.iter() {
-            let result = self.blockchain.add_block(block.clone());
-
-            // if a block is invalid, no point in trying to add the next ones
-            if result.is_err() {
-                error!("Could not add peer block {} to the blockchain", block.index);
-                return;
-            }
-
-            info!("Added new peer block {} to the blockchain", block.index);
+        let first = new_blocks[0].header.index;
+        let last = new_blocks[new_blocks.len() - 1].header.index;
+        match self.blockchain.add_blocks(new_blocks.to_vec()) {
+            Ok(_) => info!("Added new peer blocks {}-{} to the blockchain", first, last),
+            Err(error) => error!(
+                "Could not add peer blocks {}-{} to the blockchain: {:#}",
+                first, last, error
+            ),
         }
     }
 
     // Retrieve only the new blocks from a peer
     fn get_new_blocks_from_peer(&self, address: &str) -> Vec<Block> {
-        // we need to know the last block index in our blockchain
-        let our_last_index = self.blockchain.get_last_block().index as usize;
-
         // we retrieve all the blocks from the peer
         let peer_blocks = self.get_blocks_from_peer(address);
-        let peer_last_index = peer_blocks.last().unwrap().index as usize;
 
-        // Check if the peer has new blocks
-        if peer_last_index <= our_last_index {
+        // Check if the chain of the peer is better than ours
+        // A longer chain is not enough, it must have taken more work to produce
+        if self.blockchain.chain_work(&peer_blocks) <= self.blockchain.total_work() {
             return Vec::<Block>::new();
         }
 
-        // The peer do have new blocks, and we return ONLY the new ones
-        let first_new = our_last_index + 1;
-        let last_new = peer_last_index;
-        let new_blocks_range = first_new..=last_new;
-        peer_blocks.get(new_blocks_range).unwrap().to_vec()
+        // The new blocks start after the last one that we both have
+        // which is our last block, unless the peer is on a fork
+        let our_last_index = self.get_last_block_index();
+        let mut first_new = peer_blocks.len().min(our_last_index + 1);
+        while first_new > 0 {
+            let peer_block = &peer_blocks[first_new - 1];
+            let our_block = self.blockchain.get_block_at(first_new as u64 - 1);
+            if our_block.map(|block| block.header.hash) == Some(peer_block.header.hash) {
+                break;
+            }
+            first_new -= 1;
+        }
+
+        // Not even the genesis block is the same, so it's another chain
+        if first_new == 0 {
+            return Vec::<Block>::new();
+        }
+        peer_blocks[first_new..].to_vec()
     }
 
     // Retrieve ALL blocks from a peer
     fn get_blocks_from_peer(&self, address: &str) -> Vec<Block> {
-        let uri = format!("{}/blocks", address);
-        let mut response = isahc::get(uri).unwrap();
+        let uri = format!("{}/v1/blocks", address);
+        let request = Request::get(uri)
+            .header(API_KEY_HEADER, &self.api_key)
+            .body(())
+            .unwrap();
+        let mut response = isahc::send(request).unwrap();
 
         // check that the response is sucessful
         assert_eq!(response.status().as_u16(), 200);
@@ -128,19 +157,31 @@ impl Peer {
     fn try_send_new_blocks(&self, last_send_block_index: usize) {
         let new_blocks = self.get_new_blocks_since(last_send_block_index);
 
+        // a byzantine node may tamper or withhold the blocks
+        let new_blocks = self.byzantine.corrupt_blocks(new_blocks);
+
         for block in new_blocks.iter() {
             for address in self.peer_addresses.iter() {
                 // we don't want to panic if one peer is down or not working properly
                 let result = panic::catch_unwind(|| {
-                    Peer::send_block_to_peer(address, block);
+                    if let Some(conflicting_block) = self.byzantine.conflicting_block(block) {
+                        self.send_block_to_peer(address, &conflicting_block);
+                    }
+                    self.send_block_to_peer(address, block);
                 });
 
                 if result.is_err() {
-                    error!("Could not send block {} to peer {}", block.index, address);
+                    error!(
+                        "Could not send block {} to peer {}",
+                        block.header.index, address
+                    );
                     return;
                 }
 
-                info!("Sended new block {} to peer {}", block.index, address);
+                info!(
+                    "Sended new block {} to peer {}",
+                    block.header.index, address
+                );
             }
         }
     }
@@ -157,12 +198,13 @@ impl Peer {
     }
 
     // Send a block to a peer using the REST API of the peer
-    fn send_block_to_peer(address: &str, block: &Block) {
-        let uri = format!("{}/blocks", address);
-        let body = serde_json::to_string(&block).unwrap();
+    fn send_block_to_peer(&self, address: &str, block: &Block) {
+        let uri = format!("{}/v1/blocks", address);
+        let body = self.byzantine.serialize(&block);
 
         let request = Request::post(uri)
             .header("Content-Type", "application/json")
+            .header(API_KEY_HEADER, &self.api_key)
             .body(body)
             .unwrap();
 
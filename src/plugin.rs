@@ -0,0 +1,168 @@
+use std::{sync::Arc, time::Duration};
+
+use actix_web::web;
+use anyhow::Result;
+
+use crate::{
+    model::{Block, Blockchain, ChainEvent, ReorgEvent, Transaction},
+    util::{execution::Runnable, Context},
+};
+
+// Max time waiting for the next event before waiting again
+const EVENTS_WAIT_MS: u64 = 1000;
+
+// Hooks for programs that embed the node (see "run") to attach their own logic, like an indexer,
+// without changing the node itself. Every hook is optional
+// Blocks and transactions are delivered in order from a thread of their own, so slow hooks
+// never hold back the node, but they may miss events if they fall too far behind
+pub trait NodePlugin: Send + Sync {
+    // Identifies the plugin in the logs, and it's where its routes are mounted
+    fn name(&self) -> &str;
+
+    // Called once before the node starts, with the chain as it was loaded
+    // An error stops the node, as it would run without what the plugin provides
+    fn on_startup(&self, _blockchain: &Blockchain) -> Result<()> {
+        Ok(())
+    }
+
+    // Called for every block added to the chain, by the miner, a peer or the api
+    fn on_block(&self, _block: &Block) {}
+
+    // Called when blocks of the chain are replaced by another branch, before the new blocks
+    // are delivered to "on_block", so whatever was derived from the reverted ones can be undone
+    fn on_reorg(&self, _reorg: &ReorgEvent) {}
+
+    // Called for every transaction that enters the pool
+    fn on_transaction(&self, _transaction: &Transaction) {}
+
+    // Extra routes of the api, mounted under "/plugins/<name>" so they can't take over the ones of the node
+    // They go through the same api key, rate limit and cors checks as the rest of the api
+    fn routes(&self, _config: &mut web::ServiceConfig) {}
+}
+
+// The plugins registered when the node was started, shared by the api and the thread of the hooks
+#[derive(Clone, Default)]
+pub struct Plugins {
+    plugins: Arc<Vec<Box<dyn NodePlugin>>>,
+}
+
+impl Plugins {
+    pub fn new(plugins: Vec<Box<dyn NodePlugin>>) -> Plugins {
+        Plugins {
+            plugins: Arc::new(plugins),
+        }
+    }
+
+    pub fn start(&self, blockchain: &Blockchain) -> Result<()> {
+        for plugin in self.plugins.iter() {
+            info!("starting plugin {}", plugin.name());
+            plugin.on_startup(blockchain)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn configure_routes(&self, config: &mut web::ServiceConfig) {
+        for plugin in self.plugins.iter() {
+            let path = format!("/plugins/{}", plugin.name());
+            config.service(web::scope(&path).configure(|scope| plugin.routes(scope)));
+        }
+    }
+
+    fn dispatch(&self, event: &ChainEvent) {
+        for plugin in self.plugins.iter() {
+            match event {
+                ChainEvent::BlockAdded(block) => plugin.on_block(block),
+                ChainEvent::TransactionAdmitted(transaction) => plugin.on_transaction(transaction),
+                ChainEvent::Reorg(reorg) => plugin.on_reorg(reorg),
+            }
+        }
+    }
+}
+
+// Delivers the events of the chain to the hooks of the plugins
+pub struct PluginRunner {
+    plugins: Plugins,
+    blockchain: Blockchain,
+}
+
+impl Runnable for PluginRunner {
+    fn run(&self) -> Result<()> {
+        self.start()
+    }
+}
+
+impl PluginRunner {
+    pub fn new(context: &Context) -> PluginRunner {
+        PluginRunner {
+            plugins: context.plugins.clone(),
+            blockchain: context.blockchain.clone(),
+        }
+    }
+
+    pub fn start(&self) -> Result<()> {
+        if self.plugins.plugins.is_empty() {
+            return Ok(());
+        }
+
+        let events = self.blockchain.events().subscribe();
+        loop {
+            if let Some(event) = events.wait(Duration::from_millis(EVENTS_WAIT_MS)) {
+                self.plugins.dispatch(&event);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::BlockHash;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct CountingPlugin {
+        blocks: Mutex<Vec<u64>>,
+        transactions: Mutex<Vec<u64>>,
+    }
+
+    impl NodePlugin for Arc<CountingPlugin> {
+        fn name(&self) -> &str {
+            "counter"
+        }
+
+        fn on_block(&self, block: &Block) {
+            self.blocks.lock().unwrap().push(block.header.index);
+        }
+
+        fn on_transaction(&self, transaction: &Transaction) {
+            self.transactions.lock().unwrap().push(transaction.amount);
+        }
+    }
+
+    #[test]
+    fn should_deliver_events_to_every_plugin() {
+        let first = Arc::new(CountingPlugin::default());
+        let second = Arc::new(CountingPlugin::default());
+        let plugins = Plugins::new(vec![Box::new(first.clone()), Box::new(second.clone())]);
+
+        let block = Block::new(1, 0, BlockHash::default(), Vec::new());
+        plugins.dispatch(&ChainEvent::BlockAdded(Arc::new(block)));
+        let transaction = Transaction {
+            sender: "alice".to_string(),
+            recipient: "bob".to_string(),
+            amount: 7,
+            signature: None,
+            multisig: None,
+            contract: None,
+            token: None,
+            data: None,
+        };
+        plugins.dispatch(&ChainEvent::TransactionAdmitted(Arc::new(transaction)));
+
+        for plugin in &[first, second] {
+            assert_eq!(*plugin.blocks.lock().unwrap(), vec![1]);
+            assert_eq!(*plugin.transactions.lock().unwrap(), vec![7]);
+        }
+    }
+}
@@ -0,0 +1,237 @@
+use std::{
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+
+use crate::util::{
+    execution::{sleep_millis, Runnable},
+    random::random_u64,
+    Context,
+};
+
+type Task = Box<dyn Fn() -> Result<()> + Send + Sync>;
+
+// A maintenance task that runs periodically
+struct Job {
+    name: &'static str,
+    interval: Duration,
+    task: Task,
+}
+
+// Runs all the periodic maintenance jobs of the node in a single thread
+// A random delay (jitter) is added to each run, so nodes started at the same time
+// don't all perform the same work at the same moment
+pub struct Scheduler {
+    jobs: Vec<Job>,
+    jitter_ms: u64,
+}
+
+impl Runnable for Scheduler {
+    fn run(&self) -> Result<()> {
+        self.start()
+    }
+}
+
+impl Scheduler {
+    pub fn new(context: &Context) -> Scheduler {
+        let mut scheduler = Scheduler {
+            jobs: Vec::new(),
+            jitter_ms: context.config.scheduler_jitter_ms,
+        };
+
+        // transactions that could never be mined would stay in the pool forever
+        let max_age = Duration::from_secs(context.config.mempool_expiry_secs);
+        if !max_age.is_zero() {
+            let pool = context.pool.clone();
+            scheduler.add(
+                "mempool expiry sweep",
+                context.config.mempool_sweep_ms,
+                move || {
+                    let expired = pool.expire(max_age);
+                    if expired > 0 {
+                        info!("expired {} transactions from the pool", expired);
+                    }
+                    Ok(())
+                },
+            );
+        }
+
+        // transactions that entered the pool before the peers connected would never reach them
+        if context.config.mempool_rebroadcast_ms > 0 {
+            let pool = context.pool.clone();
+            let gossip = context.gossip.clone();
+            let interval = Duration::from_millis(context.config.mempool_rebroadcast_ms);
+            scheduler.add(
+                "mempool rebroadcast",
+                context.config.mempool_rebroadcast_ms,
+                move || {
+                    let transactions = pool.take_rebroadcasts(interval);
+                    if !transactions.is_empty() {
+                        info!("rebroadcasting {} pending transactions", transactions.len());
+                    }
+                    for transaction in transactions.iter() {
+                        gossip.relay_transaction(transaction);
+                    }
+                    Ok(())
+                },
+            );
+        }
+
+        // the pool is also saved on shutdown, this only limits what a crash can lose
+        let mempool_path = context.config.mempool_path();
+        if !mempool_path.is_empty() && context.config.mempool_save_ms > 0 {
+            let pool = context.pool.clone();
+            scheduler.add("mempool save", context.config.mempool_save_ms, move || {
+                pool.save(Path::new(&mempool_path))
+            });
+        }
+
+        // good peers are saved periodically, so they survive restarts
+        if context.peer_book.is_persistent() {
+            let peer_book = context.peer_book.clone();
+            scheduler.add(
+                "peer book save",
+                context.config.p2p_peer_book_save_ms,
+                move || peer_book.save(),
+            );
+        }
+
+        scheduler
+    }
+
+    // Register a new job to run every "interval_ms", plus the jitter
+    pub fn add<F>(&mut self, name: &'static str, interval_ms: u64, task: F)
+    where
+        F: Fn() -> Result<()> + Send + Sync + 'static,
+    {
+        self.jobs.push(Job {
+            name,
+            interval: Duration::from_millis(interval_ms),
+            task: Box::new(task),
+        });
+    }
+
+    pub fn start(&self) -> Result<()> {
+        if self.jobs.is_empty() {
+            info!("No maintenance jobs configured, exiting scheduler");
+            return Ok(());
+        }
+
+        let job_names: Vec<&str> = self.jobs.iter().map(|job| job.name).collect();
+        info!("start scheduler with jobs: {}", job_names.join(", "));
+
+        let start = Instant::now();
+        let mut next_runs: Vec<Instant> = self
+            .jobs
+            .iter()
+            .map(|job| self.next_run(job, start))
+            .collect();
+        loop {
+            self.run_due_jobs(&mut next_runs, Instant::now());
+
+            // sleep until the next job is due
+            let next_run = next_runs.iter().min().unwrap();
+            let waiting_time = next_run.saturating_duration_since(Instant::now());
+            sleep_millis(waiting_time.as_millis() as u64);
+        }
+    }
+
+    // Run every job whose time has come, and schedule its next run
+    fn run_due_jobs(&self, next_runs: &mut [Instant], now: Instant) {
+        for (job, next_run) in self.jobs.iter().zip(next_runs.iter_mut()) {
+            if now < *next_run {
+                continue;
+            }
+
+            // a failing job must not stop the rest of them, it will be retried on the next run
+            if let Err(error) = (job.task)() {
+                error!("maintenance job '{}' failed: {}", job.name, error);
+            }
+
+            *next_run = self.next_run(job, Instant::now());
+        }
+    }
+
+    fn next_run(&self, job: &Job, from: Instant) -> Instant {
+        from + job.interval + Scheduler::jitter(self.jitter_ms)
+    }
+
+    // Random delay between zero and "max_ms"
+    fn jitter(max_ms: u64) -> Duration {
+        if max_ms == 0 {
+            return Duration::from_millis(0);
+        }
+
+        Duration::from_millis(random_u64() % (max_ms + 1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    #[test]
+    fn should_run_only_due_jobs() {
+        let mut scheduler = Scheduler {
+            jobs: Vec::new(),
+            jitter_ms: 0,
+        };
+        let frequent_runs = add_counting_job(&mut scheduler, 0);
+        let rare_runs = add_counting_job(&mut scheduler, 60_000);
+
+        let start = Instant::now();
+        let mut next_runs: Vec<Instant> = scheduler
+            .jobs
+            .iter()
+            .map(|job| scheduler.next_run(job, start))
+            .collect();
+
+        scheduler.run_due_jobs(&mut next_runs, Instant::now());
+        scheduler.run_due_jobs(&mut next_runs, Instant::now());
+
+        assert_eq!(frequent_runs.load(Ordering::SeqCst), 2);
+        assert_eq!(rare_runs.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn should_keep_running_after_failures() {
+        let mut scheduler = Scheduler {
+            jobs: Vec::new(),
+            jitter_ms: 0,
+        };
+        scheduler.add("failing", 0, || Err(anyhow::anyhow!("failure")));
+        let runs = add_counting_job(&mut scheduler, 0);
+
+        let mut next_runs = vec![Instant::now(), Instant::now()];
+        scheduler.run_due_jobs(&mut next_runs, Instant::now());
+
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn should_bound_the_jitter() {
+        assert_eq!(Scheduler::jitter(0), Duration::from_millis(0));
+
+        for _ in 0..100 {
+            assert!(Scheduler::jitter(10) <= Duration::from_millis(10));
+        }
+    }
+
+    // Adds a job that counts how many times it has run
+    fn add_counting_job(scheduler: &mut Scheduler, interval_ms: u64) -> Arc<AtomicUsize> {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let job_runs = runs.clone();
+        scheduler.add("counting", interval_ms, move || {
+            job_runs.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        });
+
+        runs
+    }
+}
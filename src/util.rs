@@ -1,11 +1,23 @@
+mod byzantine;
+mod clock;
 mod config;
 mod context;
 pub mod execution;
+mod lock;
 mod logger;
+pub mod random;
+mod startup;
 pub mod termination;
+pub mod watch;
 
 // Explicitly controlling which individual identifiers we export
 // It also avoids verbose module imports from other files
+pub use byzantine::Byzantine;
+#[cfg(test)]
+pub use clock::MockClock;
+pub use clock::{SharedClock, SystemClock};
 pub use config::Config;
 pub use context::Context;
+pub use lock::{Lock, LockError};
 pub use logger::initialize_logger;
+pub use startup::check_startup;
@@ -0,0 +1,192 @@
+use std::{collections::HashSet, str::FromStr};
+
+use crate::model::{Block, BlockHash};
+
+// Ways in which a node can be configured to misbehave
+// Only intended to test how honest nodes react to malicious peers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ByzantineBehavior {
+    // Blocks shared with peers have a broken link to their previous block
+    InvalidBlocks,
+    // Blocks mined by the node are never shared with peers
+    WithholdBlocks,
+    // Messages sent to peers can not be parsed
+    MalformedMessages,
+    // A conflicting block for the same index is sent along with each block
+    DoubleSign,
+}
+
+impl FromStr for ByzantineBehavior {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.trim() {
+            "invalid_blocks" => Ok(ByzantineBehavior::InvalidBlocks),
+            "withhold_blocks" => Ok(ByzantineBehavior::WithholdBlocks),
+            "malformed_messages" => Ok(ByzantineBehavior::MalformedMessages),
+            "double_sign" => Ok(ByzantineBehavior::DoubleSign),
+            _ => Err(format!("Unknown byzantine behavior `{}`", name)),
+        }
+    }
+}
+
+// Set of misbehaviors enabled in the node, empty for honest nodes
+#[derive(Debug, Clone, Default)]
+pub struct Byzantine {
+    behaviors: HashSet<ByzantineBehavior>,
+}
+
+impl Byzantine {
+    // Parse the behaviors from their names, ignoring unknown ones
+    // Byzantine behaviors are only available in debug builds, release nodes are always honest
+    pub fn from_names(names: &[String]) -> Byzantine {
+        if !cfg!(debug_assertions) {
+            if !names.is_empty() {
+                warn!("byzantine behaviors are ignored in release builds");
+            }
+            return Byzantine::default();
+        }
+
+        let mut behaviors = HashSet::new();
+        for name in names {
+            match name.parse::<ByzantineBehavior>() {
+                Ok(behavior) => {
+                    warn!("byzantine behavior enabled: {:?}", behavior);
+                    behaviors.insert(behavior);
+                }
+                Err(error) => warn!("{}", error),
+            }
+        }
+
+        Byzantine { behaviors }
+    }
+
+    pub fn is_enabled(&self, behavior: ByzantineBehavior) -> bool {
+        self.behaviors.contains(&behavior)
+    }
+
+    // Apply the enabled misbehaviors to a list of blocks to be shared with peers
+    pub fn corrupt_blocks(&self, blocks: Vec<Block>) -> Vec<Block> {
+        let mut blocks = blocks;
+
+        // we only share the genesis block, which every node already knows
+        if self.is_enabled(ByzantineBehavior::WithholdBlocks) {
+            blocks.retain(|block| block.header.index == 0);
+        }
+
+        if self.is_enabled(ByzantineBehavior::InvalidBlocks) {
+            for block in blocks.iter_mut().filter(|block| block.header.index > 0) {
+                block.header.previous_hash = BlockHash::default();
+                block.header.hash = block.calculate_hash();
+            }
+        }
+
+        blocks
+    }
+
+    // Create a block that conflicts with the original one, if double signing is enabled
+    pub fn conflicting_block(&self, block: &Block) -> Option<Block> {
+        if !self.is_enabled(ByzantineBehavior::DoubleSign) {
+            return None;
+        }
+
+        let mut conflicting_block = block.clone();
+        conflicting_block.header.nonce = block.header.nonce.wrapping_add(1);
+        conflicting_block.transactions.clear();
+        conflicting_block.header.merkle_root = conflicting_block.calculate_merkle_root();
+        conflicting_block.header.bloom = conflicting_block.calculate_bloom();
+        conflicting_block.header.hash = conflicting_block.calculate_hash();
+
+        Some(conflicting_block)
+    }
+
+    // Serialize a message to be sent to peers, breaking it if malformed messages are enabled
+    pub fn serialize<T: serde::Serialize>(&self, value: &T) -> String {
+        let serialized = serde_json::to_string(value).unwrap();
+
+        if self.is_enabled(ByzantineBehavior::MalformedMessages) {
+            return serialized[..serialized.len() / 2].to_string();
+        }
+
+        serialized
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_be_honest_by_default() {
+        let byzantine = Byzantine::default();
+        let blocks = create_mock_blocks();
+
+        // blocks are not modified at all
+        let shared_blocks = byzantine.corrupt_blocks(blocks.clone());
+        assert_eq!(shared_blocks.len(), blocks.len());
+        assert_eq!(shared_blocks[1].header.hash, blocks[1].header.hash);
+
+        // messages are serialized as usual
+        let message = byzantine.serialize(&blocks);
+        assert!(serde_json::from_str::<Vec<Block>>(&message).is_ok());
+
+        assert!(byzantine.conflicting_block(&blocks[1]).is_none());
+    }
+
+    #[test]
+    fn should_parse_behavior_names() {
+        let names = vec!["invalid_blocks".to_string(), "unknown".to_string()];
+        let byzantine = Byzantine::from_names(&names);
+
+        assert!(byzantine.is_enabled(ByzantineBehavior::InvalidBlocks));
+        assert!(!byzantine.is_enabled(ByzantineBehavior::WithholdBlocks));
+    }
+
+    #[test]
+    fn should_break_links_with_invalid_blocks() {
+        let byzantine = Byzantine::from_names(&["invalid_blocks".to_string()]);
+        let blocks = create_mock_blocks();
+
+        let shared_blocks = byzantine.corrupt_blocks(blocks.clone());
+        assert_ne!(shared_blocks[1].header.previous_hash, blocks[0].header.hash);
+    }
+
+    #[test]
+    fn should_withhold_blocks() {
+        let byzantine = Byzantine::from_names(&["withhold_blocks".to_string()]);
+        let blocks = create_mock_blocks();
+
+        let shared_blocks = byzantine.corrupt_blocks(blocks);
+        assert_eq!(shared_blocks.len(), 1);
+    }
+
+    #[test]
+    fn should_send_malformed_messages() {
+        let byzantine = Byzantine::from_names(&["malformed_messages".to_string()]);
+        let blocks = create_mock_blocks();
+
+        let message = byzantine.serialize(&blocks);
+        assert!(serde_json::from_str::<Vec<Block>>(&message).is_err());
+    }
+
+    #[test]
+    fn should_create_conflicting_blocks() {
+        let byzantine = Byzantine::from_names(&["double_sign".to_string()]);
+        let blocks = create_mock_blocks();
+
+        let conflicting_block = byzantine.conflicting_block(&blocks[1]).unwrap();
+        assert_eq!(conflicting_block.header.index, blocks[1].header.index);
+        assert_eq!(
+            conflicting_block.header.previous_hash,
+            blocks[1].header.previous_hash
+        );
+        assert_ne!(conflicting_block.header.hash, blocks[1].header.hash);
+    }
+
+    fn create_mock_blocks() -> Vec<Block> {
+        let genesis_block = Block::new(0, 0, BlockHash::default(), Vec::new());
+        let next_block = Block::new(1, 0, genesis_block.header.hash, Vec::new());
+
+        vec![genesis_block, next_block]
+    }
+}
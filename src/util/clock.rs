@@ -0,0 +1,89 @@
+use std::{fmt::Debug, panic::RefUnwindSafe, sync::Arc};
+
+use chrono::Utc;
+#[cfg(test)]
+use std::{
+    sync::atomic::{AtomicI64, Ordering},
+    time::Duration,
+};
+
+// Source of the current time for everything that depends on it (block timestamps, expiring
+// transactions...), so tests can control the time instead of waiting for it to pass
+pub trait Clock: Debug + Send + Sync + RefUnwindSafe {
+    // Milliseconds since the Unix epoch, like the timestamps of the blocks
+    fn now_millis(&self) -> i64;
+}
+
+pub type SharedClock = Arc<dyn Clock>;
+
+// The wall clock of the machine, used by the node
+#[derive(Debug, Default)]
+pub struct SystemClock;
+
+impl SystemClock {
+    pub fn shared() -> SharedClock {
+        Arc::new(SystemClock)
+    }
+}
+
+impl Clock for SystemClock {
+    fn now_millis(&self) -> i64 {
+        Utc::now().timestamp_millis()
+    }
+}
+
+// A clock that only moves when told to, so time-dependent behaviour is deterministic in tests
+#[cfg(test)]
+#[derive(Debug, Clone)]
+pub struct MockClock {
+    millis: Arc<AtomicI64>,
+}
+
+#[cfg(test)]
+impl MockClock {
+    pub fn new(millis: i64) -> MockClock {
+        MockClock {
+            millis: Arc::new(AtomicI64::new(millis)),
+        }
+    }
+
+    // Clones share the time, so a test can keep one to move the clock of the code under test
+    pub fn shared(&self) -> SharedClock {
+        Arc::new(self.clone())
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        self.millis
+            .fetch_add(duration.as_millis() as i64, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+impl Clock for MockClock {
+    fn now_millis(&self) -> i64 {
+        self.millis.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_only_move_the_mock_clock_when_told() {
+        let clock = MockClock::new(1_000);
+        let shared = clock.shared();
+        assert_eq!(shared.now_millis(), 1_000);
+        assert_eq!(shared.now_millis(), 1_000);
+
+        clock.advance(Duration::from_secs(60));
+        assert_eq!(shared.now_millis(), 61_000);
+    }
+
+    #[test]
+    fn should_follow_the_wall_clock() {
+        let before = Utc::now().timestamp_millis();
+        let now = SystemClock.now_millis();
+        assert!(before <= now && now <= Utc::now().timestamp_millis());
+    }
+}
@@ -2,25 +2,181 @@ extern crate dotenv;
 
 use dotenv::dotenv;
 use std::env;
+use std::path::Path;
 use std::str::FromStr;
 
+use super::Byzantine;
+use crate::model::DEFAULT_CHAIN_ID;
+
 type StringVec = Vec<String>;
 
+// Prefix of the variables that override a setting by its section, e.g. "NODE__API__PORT"
+// Container deployments usually group their settings like that, on top of the ".env" file
+const OVERRIDE_PREFIX: &str = "NODE__";
+
+// Name of each setting after the override prefix, grouped in sections like the fields of the config
+const OVERRIDE_NAMES: &[(&str, &str)] = &[
+    ("PORT", "API__PORT"),
+    ("LISTEN_ADDRESSES", "API__LISTEN_ADDRESSES"),
+    ("LONGPOLL_TIMEOUT_MS", "API__LONGPOLL_TIMEOUT_MS"),
+    ("API_KEY", "API__KEY"),
+    ("API_PUBLIC_READS", "API__PUBLIC_READS"),
+    ("API_MAX_BODY_BYTES", "API__MAX_BODY_BYTES"),
+    ("API_REQUEST_TIMEOUT_MS", "API__REQUEST_TIMEOUT_MS"),
+    ("RATE_LIMIT_PER_SEC", "API__RATE_LIMIT_PER_SEC"),
+    ("RATE_LIMIT_BURST", "API__RATE_LIMIT_BURST"),
+    ("CORS_ALLOWED_ORIGINS", "API__CORS_ALLOWED_ORIGINS"),
+    ("CORS_ALLOWED_METHODS", "API__CORS_ALLOWED_METHODS"),
+    ("CORS_MAX_AGE_SECS", "API__CORS_MAX_AGE_SECS"),
+    ("GRPC_PORT", "API__GRPC_PORT"),
+    ("TLS_CERT_PATH", "API__TLS_CERT_PATH"),
+    ("TLS_KEY_PATH", "API__TLS_KEY_PATH"),
+    ("DATA_DIR", "STORAGE__DATA_DIR"),
+    ("PRUNE_DEPTH", "STORAGE__PRUNE_DEPTH"),
+    ("SNAPSHOT_PATH", "STORAGE__SNAPSHOT_PATH"),
+    ("STORAGE_COMPRESSION", "STORAGE__COMPRESSION"),
+    ("SHUTDOWN_DRAIN_MS", "SHUTDOWN__DRAIN_MS"),
+    ("SHUTDOWN_TIMEOUT_SECS", "SHUTDOWN__TIMEOUT_SECS"),
+    ("FINALITY_DEPTH", "CHAIN__FINALITY_DEPTH"),
+    ("CHAIN_ID", "CHAIN__ID"),
+    ("CONTRACTS_ENABLED", "CHAIN__CONTRACTS_ENABLED"),
+    ("MAX_DATA_BYTES", "CHAIN__MAX_DATA_BYTES"),
+    ("MAX_TIME_DRIFT_SECS", "CHAIN__MAX_TIME_DRIFT_SECS"),
+    ("AMOUNT_DECIMALS", "CHAIN__AMOUNT_DECIMALS"),
+    ("CONSENSUS", "CONSENSUS__ENGINE"),
+    ("POA_SIGNERS", "CONSENSUS__POA_SIGNERS"),
+    ("POA_SIGNER_SEED", "CONSENSUS__POA_SIGNER_SEED"),
+    ("POA_BLOCK_INTERVAL_MS", "CONSENSUS__POA_BLOCK_INTERVAL_MS"),
+    ("PEERS", "PEERS__URLS"),
+    ("PEER_SYNC_MS", "PEERS__SYNC_MS"),
+    ("P2P_PORT", "P2P__PORT"),
+    ("P2P_PEERS", "P2P__PEERS"),
+    ("P2P_SEEDS", "P2P__SEEDS"),
+    ("P2P_MAX_PEERS", "P2P__MAX_PEERS"),
+    ("P2P_ANNOUNCE_MS", "P2P__ANNOUNCE_MS"),
+    ("P2P_PEER_BOOK", "P2P__PEER_BOOK"),
+    ("P2P_PEER_BOOK_SAVE_MS", "P2P__PEER_BOOK_SAVE_MS"),
+    ("P2P_BAN_SECS", "P2P__BAN_SECS"),
+    ("LIGHT_CLIENT", "P2P__LIGHT_CLIENT"),
+    ("P2P_COMPACT_BLOCKS", "P2P__COMPACT_BLOCKS"),
+    ("MAX_CLOCK_OFFSET_SECS", "P2P__MAX_CLOCK_OFFSET_SECS"),
+    ("AUTO_MINING", "MINER__ENABLED"),
+    ("MAX_BLOCKS", "MINER__MAX_BLOCKS"),
+    ("MAX_NONCE", "MINER__MAX_NONCE"),
+    ("DIFFICULTY", "MINER__DIFFICULTY"),
+    ("TARGET_BITS", "MINER__TARGET_BITS"),
+    ("TRANSACTION_WAITING_MS", "MINER__TRANSACTION_WAITING_MS"),
+    ("MINER_THREADS", "MINER__THREADS"),
+    (
+        "CLOCK_OFFSET_STOPS_MINING",
+        "MINER__CLOCK_OFFSET_STOPS_MINING",
+    ),
+    ("NOTIFICATION_POLL_MS", "NOTIFICATIONS__POLL_MS"),
+    ("SCHEDULER_JITTER_MS", "SCHEDULER__JITTER_MS"),
+    ("MEMPOOL_EXPIRY_SECS", "MEMPOOL__EXPIRY_SECS"),
+    ("MEMPOOL_SWEEP_MS", "MEMPOOL__SWEEP_MS"),
+    ("MEMPOOL_REBROADCAST_MS", "MEMPOOL__REBROADCAST_MS"),
+    ("MEMPOOL_SAVE_MS", "MEMPOOL__SAVE_MS"),
+    ("WALLET_MODE", "WALLET__MODE"),
+    ("WALLET_ADDRESSES", "WALLET__ADDRESSES"),
+    ("LOG_LEVEL", "LOGGING__LEVEL"),
+    ("LOG_FORMAT", "LOGGING__FORMAT"),
+    ("BYZANTINE_BEHAVIORS", "TESTING__BYZANTINE_BEHAVIORS"),
+];
+
 // Encapsulates configuration values to be used across the application
 // It ensures correct typing and that at least they will have a default value
 pub struct Config {
     // Networking settings
     pub port: u16,
+    pub listen_addresses: StringVec,
+
+    // Storage settings
+    pub data_dir: String,
+    pub prune_depth: u64,
+    pub snapshot_path: String,
+    pub storage_compression: bool,
+
+    // Shutdown settings
+    pub shutdown_drain_ms: u64,
+    pub shutdown_timeout_secs: u64,
+
+    // Chain settings
+    pub finality_depth: u64,
+    pub contracts_enabled: bool,
+    pub max_data_bytes: usize,
+    pub max_time_drift_secs: u64,
+    pub amount_decimals: u32,
+
+    // Api settings
+    pub longpoll_timeout_ms: u64,
+    pub api_key: String,
+    pub api_public_reads: bool,
+    pub api_max_body_bytes: usize,
+    pub api_request_timeout_ms: u64,
+    pub rate_limit_per_sec: u32,
+    pub rate_limit_burst: u32,
+    pub cors_allowed_origins: StringVec,
+    pub cors_allowed_methods: StringVec,
+    pub cors_max_age_secs: u64,
+    pub grpc_port: u16,
+    pub tls_cert_path: String,
+    pub tls_key_path: String,
+
+    // Consensus settings
+    pub consensus: String,
+    pub poa_signers: StringVec,
+    pub poa_signer_seed: String,
+    pub poa_block_interval_ms: u64,
 
     // Peer settings
     pub peers: StringVec,
     pub peer_sync_ms: u64,
 
+    // P2P network settings
+    pub chain_id: String,
+    pub p2p_port: u16,
+    pub p2p_peers: StringVec,
+    pub p2p_seeds: StringVec,
+    pub p2p_max_peers: usize,
+    pub p2p_announce_ms: u64,
+    pub p2p_peer_book: String,
+    pub p2p_peer_book_save_ms: u64,
+    pub p2p_ban_secs: u64,
+    pub light_client: bool,
+    pub p2p_compact_blocks: bool,
+    pub max_clock_offset_secs: u64,
+
     // Miner settings
+    pub auto_mining: bool,
     pub max_blocks: u64,
     pub max_nonce: u64,
     pub difficulty: u32,
+    pub target_bits: String,
     pub tx_waiting_ms: u64,
+    pub miner_threads: u64,
+    pub clock_offset_stops_mining: bool,
+
+    // Notification settings
+    pub notification_poll_ms: u64,
+
+    // Maintenance settings
+    pub scheduler_jitter_ms: u64,
+    pub mempool_expiry_secs: u64,
+    pub mempool_sweep_ms: u64,
+    pub mempool_rebroadcast_ms: u64,
+    pub mempool_save_ms: u64,
+
+    // Wallet settings
+    pub wallet_mode: String,
+    pub wallet_addresses: StringVec,
+
+    // Logging settings
+    pub log_level: String,
+    pub log_format: String,
+
+    // Testing settings
+    pub byzantine: Byzantine,
 }
 
 // The implementation reads the values from environment variables
@@ -33,22 +189,189 @@ impl Config {
         Config {
             // Networking settings
             port: Config::read_envvar::<u16>("PORT", 8000),
+            listen_addresses: Config::read_vec_envvar(
+                "LISTEN_ADDRESSES",
+                ",",
+                StringVec::default(), // only localhost, on PORT
+            ),
+
+            // Storage settings
+            data_dir: Config::read_envvar::<String>("DATA_DIR", String::default()), // only in memory
+            prune_depth: Config::read_envvar::<u64>("PRUNE_DEPTH", 0), // keep all the transactions
+            // without a snapshot the chain starts from the genesis block
+            snapshot_path: Config::read_envvar::<String>("SNAPSHOT_PATH", String::default()),
+            storage_compression: Config::read_envvar::<bool>("STORAGE_COMPRESSION", true),
+
+            // Shutdown settings
+            shutdown_drain_ms: Config::read_envvar::<u64>("SHUTDOWN_DRAIN_MS", 3000),
+            shutdown_timeout_secs: Config::read_envvar::<u64>("SHUTDOWN_TIMEOUT_SECS", 5),
+
+            // Chain settings
+            finality_depth: Config::read_envvar::<u64>("FINALITY_DEPTH", 6),
+            contracts_enabled: Config::read_envvar::<bool>("CONTRACTS_ENABLED", false),
+            max_data_bytes: Config::read_envvar::<usize>("MAX_DATA_BYTES", 256),
+            max_time_drift_secs: Config::read_envvar::<u64>("MAX_TIME_DRIFT_SECS", 7200),
+            // amounts are whole coins unless configured otherwise
+            amount_decimals: Config::read_envvar::<u32>("AMOUNT_DECIMALS", 0),
+
+            // Api settings
+            longpoll_timeout_ms: Config::read_envvar::<u64>("LONGPOLL_TIMEOUT_MS", 30000),
+            api_key: Config::read_envvar::<String>("API_KEY", String::default()), // no auth
+            api_public_reads: Config::read_envvar::<bool>("API_PUBLIC_READS", true),
+            api_max_body_bytes: Config::read_envvar::<usize>("API_MAX_BODY_BYTES", 1024 * 1024),
+            api_request_timeout_ms: Config::read_envvar::<u64>("API_REQUEST_TIMEOUT_MS", 5000),
+            rate_limit_per_sec: Config::read_envvar::<u32>("RATE_LIMIT_PER_SEC", 10), // 0 to disable
+            rate_limit_burst: Config::read_envvar::<u32>("RATE_LIMIT_BURST", 50),
+            cors_allowed_origins: Config::read_vec_envvar(
+                "CORS_ALLOWED_ORIGINS",
+                ",",
+                StringVec::default(), // no cross origin requests
+            ),
+            cors_allowed_methods: Config::read_vec_envvar(
+                "CORS_ALLOWED_METHODS",
+                ",",
+                vec!["GET".to_string()],
+            ),
+            cors_max_age_secs: Config::read_envvar::<u64>("CORS_MAX_AGE_SECS", 3600),
+            grpc_port: Config::read_envvar::<u16>("GRPC_PORT", 0), // not listening
+            // without them the api is served over plain HTTP
+            tls_cert_path: Config::read_envvar::<String>("TLS_CERT_PATH", String::default()),
+            tls_key_path: Config::read_envvar::<String>("TLS_KEY_PATH", String::default()),
+
+            // Consensus settings
+            consensus: Config::read_envvar::<String>("CONSENSUS", "pow".to_string()),
+            poa_signers: Config::read_vec_envvar("POA_SIGNERS", ",", StringVec::default()),
+            poa_signer_seed: Config::read_envvar::<String>("POA_SIGNER_SEED", String::default()),
+            poa_block_interval_ms: Config::read_envvar::<u64>("POA_BLOCK_INTERVAL_MS", 1000),
 
             // Peer settings
             peers: Config::read_vec_envvar("PEERS", ",", StringVec::default()),
             peer_sync_ms: Config::read_envvar::<u64>("PEER_SYNC_MS", 10000),
 
+            // P2P network settings
+            // also the network that transactions are signed for
+            chain_id: Config::read_envvar::<String>("CHAIN_ID", DEFAULT_CHAIN_ID.to_string()),
+            p2p_port: Config::read_envvar::<u16>("P2P_PORT", 0), // not listening
+            p2p_peers: Config::read_vec_envvar("P2P_PEERS", ",", StringVec::default()),
+            p2p_seeds: Config::read_vec_envvar("P2P_SEEDS", ",", StringVec::default()),
+            p2p_max_peers: Config::read_envvar::<usize>("P2P_MAX_PEERS", 8),
+            p2p_announce_ms: Config::read_envvar::<u64>("P2P_ANNOUNCE_MS", 100),
+            p2p_peer_book: Config::read_envvar::<String>("P2P_PEER_BOOK", String::default()),
+            p2p_peer_book_save_ms: Config::read_envvar::<u64>("P2P_PEER_BOOK_SAVE_MS", 60000),
+            p2p_ban_secs: Config::read_envvar::<u64>("P2P_BAN_SECS", 3600),
+            light_client: Config::read_envvar::<bool>("LIGHT_CLIENT", false),
+            p2p_compact_blocks: Config::read_envvar::<bool>("P2P_COMPACT_BLOCKS", true),
+            max_clock_offset_secs: Config::read_envvar::<u64>("MAX_CLOCK_OFFSET_SECS", 300),
+
             // Miner settings
+            auto_mining: Config::read_envvar::<bool>("AUTO_MINING", true),
             max_blocks: Config::read_envvar::<u64>("MAX_BLOCKS", 0), // unlimited blocks
             max_nonce: Config::read_envvar::<u64>("MAX_NONCE", 1_000_000),
             difficulty: Config::read_envvar::<u32>("DIFFICULTY", 10),
+            target_bits: Config::read_envvar::<String>("TARGET_BITS", String::default()),
             tx_waiting_ms: Config::read_envvar::<u64>("TRANSACTION_WAITING_MS", 10000),
+            miner_threads: Config::read_envvar::<u64>("MINER_THREADS", 1),
+            clock_offset_stops_mining: Config::read_envvar::<bool>(
+                "CLOCK_OFFSET_STOPS_MINING",
+                false,
+            ),
+
+            // Notification settings
+            notification_poll_ms: Config::read_envvar::<u64>("NOTIFICATION_POLL_MS", 1000),
+
+            // Maintenance settings
+            scheduler_jitter_ms: Config::read_envvar::<u64>("SCHEDULER_JITTER_MS", 1000),
+            mempool_expiry_secs: Config::read_envvar::<u64>("MEMPOOL_EXPIRY_SECS", 3600),
+            mempool_sweep_ms: Config::read_envvar::<u64>("MEMPOOL_SWEEP_MS", 60000),
+            mempool_rebroadcast_ms: Config::read_envvar::<u64>("MEMPOOL_REBROADCAST_MS", 60000),
+            mempool_save_ms: Config::read_envvar::<u64>("MEMPOOL_SAVE_MS", 5000),
+
+            // Wallet settings
+            wallet_mode: Config::read_envvar::<String>("WALLET_MODE", "hot".to_string()),
+            wallet_addresses: Config::read_vec_envvar(
+                "WALLET_ADDRESSES",
+                ",",
+                StringVec::default(),
+            ),
+
+            // Logging settings
+            log_level: Config::read_envvar::<String>("LOG_LEVEL", "info".to_string()),
+            log_format: Config::read_envvar::<String>("LOG_FORMAT", "text".to_string()),
+
+            // Testing settings
+            byzantine: Byzantine::from_names(&Config::read_vec_envvar(
+                "BYZANTINE_BEHAVIORS",
+                ",",
+                StringVec::default(),
+            )),
+        }
+    }
+
+    // Socket addresses ("host:port") where the api listens for requests
+    pub fn api_listen_addresses(&self) -> StringVec {
+        if self.listen_addresses.is_empty() {
+            return vec![format!("localhost:{}", self.port)];
+        }
+
+        self.listen_addresses.clone()
+    }
+
+    // File where the known p2p peers are saved, it goes into the data directory unless configured
+    pub fn peer_book_path(&self) -> String {
+        if !self.p2p_peer_book.is_empty() || self.data_dir.is_empty() {
+            return self.p2p_peer_book.clone();
+        }
+
+        let path = Path::new(&self.data_dir).join("peer_book.json");
+        path.to_string_lossy().to_string()
+    }
+
+    // File where the transactions of the pool are saved, so they survive restarts
+    // Without a data directory the pool only lives in memory, like the chain
+    pub fn mempool_path(&self) -> String {
+        if self.data_dir.is_empty() {
+            return String::new();
+        }
+
+        let path = Path::new(&self.data_dir).join("mempool.json");
+        path.to_string_lossy().to_string()
+    }
+
+    // Returns the override variables that don't match any setting, most likely a typo
+    // Otherwise the node would silently run with a different value than the intended one
+    pub fn unknown_overrides() -> StringVec {
+        Config::find_unknown_overrides(env::vars().map(|(name, _)| name))
+    }
+
+    fn find_unknown_overrides(names: impl Iterator<Item = String>) -> StringVec {
+        names
+            .filter(|name| match name.strip_prefix(OVERRIDE_PREFIX) {
+                Some(nested_name) => !OVERRIDE_NAMES
+                    .iter()
+                    .any(|(_, known)| *known == nested_name),
+                None => false,
+            })
+            .collect()
+    }
+
+    // Value of a setting, the "NODE__" variable of its section takes precedence over the plain one
+    fn read_value(key: &str) -> Result<String, env::VarError> {
+        let override_value = OVERRIDE_NAMES
+            .iter()
+            .find(|(plain, _)| *plain == key)
+            .and_then(|(_, nested_name)| {
+                env::var(format!("{}{}", OVERRIDE_PREFIX, nested_name)).ok()
+            });
+
+        match override_value {
+            Some(value) => Ok(value),
+            None => env::var(key),
         }
     }
 
     // Parses a singular value from a environment variable, accepting a default value if missing
     fn read_envvar<T: FromStr>(key: &str, default_value: T) -> T {
-        match env::var(key) {
+        match Config::read_value(key) {
             Ok(val) => val.parse::<T>().unwrap_or(default_value),
             Err(_e) => default_value,
         }
@@ -56,7 +379,7 @@ impl Config {
 
     // Parses a multiple value (Vec) from a environment variable, accepting a default value if missing
     fn read_vec_envvar(key: &str, separator: &str, default_value: StringVec) -> StringVec {
-        match env::var(key) {
+        match Config::read_value(key) {
             Ok(val) => val
                 .trim()
                 .split_terminator(separator)
@@ -78,7 +401,7 @@ mod tests {
         env::set_var(var_name, real_value.to_string());
 
         // read the present var, should NOT return the default value but the real one
-        let default_value = 8000 as u16;
+        let default_value = 8000_u16;
         let value = Config::read_envvar::<u16>(var_name, default_value);
 
         assert_eq!(value, real_value);
@@ -91,7 +414,7 @@ mod tests {
     fn read_present_vec_envvar() {
         let var_name = "PRESENT_VEC_ENVVAR";
         let value = "FOO,BAR";
-        env::set_var(var_name, value.to_string());
+        env::set_var(var_name, value);
 
         // read the present var, should NOT return the default value but the real one
         let default_value = StringVec::default();
@@ -112,7 +435,7 @@ mod tests {
         env::remove_var(var_name);
 
         // read the non present var, should return the default value
-        let default_value = 8000 as u16;
+        let default_value = 8000_u16;
         let value = Config::read_envvar::<u16>(var_name, default_value);
         assert_eq!(value, default_value);
 
@@ -128,7 +451,7 @@ mod tests {
         let var_name = "INVALID=VAR=NAME";
 
         // read the invalid var, should return the default value
-        let default_value = 8000 as u16;
+        let default_value = 8000_u16;
         let value = Config::read_envvar::<u16>(var_name, default_value);
         assert_eq!(value, default_value);
 
@@ -138,8 +461,49 @@ mod tests {
         assert!(do_vecs_match(&vec_value, &default_vec_value));
     }
 
+    #[test]
+    fn read_overridden_envvar() {
+        // the override of the section wins over the plain variable
+        env::set_var("P2P_BAN_SECS", "10");
+        env::set_var("NODE__P2P__BAN_SECS", "20");
+        assert_eq!(Config::read_envvar::<u64>("P2P_BAN_SECS", 0), 20);
+
+        env::remove_var("NODE__P2P__BAN_SECS");
+        assert_eq!(Config::read_envvar::<u64>("P2P_BAN_SECS", 0), 10);
+
+        // same for vec variables
+        env::set_var("NODE__P2P__SEEDS", "a:1,b:2");
+        let value = Config::read_vec_envvar("P2P_SEEDS", ",", StringVec::default());
+        assert!(do_vecs_match(
+            &value,
+            &["a:1".to_string(), "b:2".to_string()]
+        ));
+
+        // let's remove the vars at the end to not pollute the environment
+        env::remove_var("P2P_BAN_SECS");
+        env::remove_var("NODE__P2P__SEEDS");
+    }
+
+    #[test]
+    fn find_unknown_overrides() {
+        let names = vec!["NODE__API__PORT", "NODE__API__PROT", "API__PROT", "PORT"];
+        let unknown = Config::find_unknown_overrides(names.into_iter().map(str::to_string));
+        assert_eq!(unknown, vec!["NODE__API__PROT".to_string()]);
+
+        // every setting can be overridden, and only once
+        for (plain, nested_name) in OVERRIDE_NAMES {
+            let count = OVERRIDE_NAMES.iter().filter(|(p, _)| p == plain).count();
+            assert_eq!(count, 1, "{} is overridden more than once", plain);
+            let count = OVERRIDE_NAMES
+                .iter()
+                .filter(|(_, n)| n == nested_name)
+                .count();
+            assert_eq!(count, 1, "{} overrides more than one setting", nested_name);
+        }
+    }
+
     // All credit for this function to https://stackoverflow.com/a/58175659
-    fn do_vecs_match<T: PartialEq>(a: &Vec<T>, b: &Vec<T>) -> bool {
+    fn do_vecs_match<T: PartialEq>(a: &[T], b: &[T]) -> bool {
         let matching = a.iter().zip(b.iter()).filter(|&(a, b)| a == b).count();
         matching == a.len() && matching == b.len()
     }
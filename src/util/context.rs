@@ -1,8 +1,25 @@
-use super::Config;
-use crate::model::{Blockchain, TransactionPool};
+use super::{termination::Shutdown, Config};
+use crate::{
+    miner::MinerStats,
+    model::{Blockchain, TransactionPool},
+    network::{Gossip, InclusionProofs, NetworkTime, PeerBook, Peers},
+    notifier::Subscriptions,
+    plugin::Plugins,
+    wallet::Wallet,
+};
 
 pub struct Context {
     pub config: Config,
     pub blockchain: Blockchain,
     pub pool: TransactionPool,
+    pub shutdown: Shutdown,
+    pub subscriptions: Subscriptions,
+    pub wallet: Wallet,
+    pub miner_stats: MinerStats,
+    pub gossip: Gossip,
+    pub peer_book: PeerBook,
+    pub peers: Peers,
+    pub network_time: NetworkTime,
+    pub proofs: InclusionProofs,
+    pub plugins: Plugins,
 }
@@ -1,22 +1,11 @@
 use anyhow::Result;
-use crossbeam_utils::thread;
 use std::time;
 
-pub trait Runnable: Sync {
+// A system of the node that blocks its thread while it runs (see "Node")
+pub trait Runnable: Send {
     fn run(&self) -> Result<()>;
 }
 
-pub fn run_in_parallel(runnables: Vec<&dyn Runnable>) {
-    thread::scope(|s| {
-        for runnable in runnables {
-            s.spawn(move |_| {
-                runnable.run().unwrap();
-            });
-        }
-    })
-    .unwrap();
-}
-
 // Suspend the execution of the thread by a particular amount of milliseconds
 pub fn sleep_millis(millis: u64) {
     let wait_duration = time::Duration::from_millis(millis);
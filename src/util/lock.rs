@@ -0,0 +1,82 @@
+use std::{
+    any,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex, MutexGuard,
+    },
+};
+use thiserror::Error;
+
+// Error types to return when a value can't be trusted anymore
+#[derive(Error, PartialEq, Debug)]
+pub enum LockError {
+    #[error("A thread panicked while changing the {0}, it may be inconsistent")]
+    Poisoned(&'static str),
+}
+
+// A value shared between threads, which keeps working after a thread panics while holding it
+// With a plain mutex every later "lock().unwrap()" panics too, so a single bug would bring down
+// the api, the miner and the peers for good
+#[derive(Debug, Default)]
+pub struct Lock<T> {
+    mutex: Mutex<T>,
+    // the recovery is only logged once, the value is still locked many times afterwards
+    recovered: AtomicBool,
+}
+
+impl<T> Lock<T> {
+    pub fn new(value: T) -> Lock<T> {
+        Lock {
+            mutex: Mutex::new(value),
+            recovered: AtomicBool::new(false),
+        }
+    }
+
+    // Locks the value, even if a thread panicked while holding it
+    // Good enough for values that are never left half-changed, or that are only read
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        self.mutex.lock().unwrap_or_else(|error| {
+            if !self.recovered.swap(true, Ordering::SeqCst) {
+                error!(
+                    "a thread panicked while holding the {}, using it anyway",
+                    any::type_name::<T>()
+                );
+            }
+            error.into_inner()
+        })
+    }
+
+    // Locks the value, unless a thread panicked while holding it
+    // For the callers that change values that may have been left half-changed, which must not be built upon
+    pub fn try_lock(&self) -> Result<MutexGuard<'_, T>, LockError> {
+        self.mutex
+            .lock()
+            .map_err(|_| LockError::Poisoned(any::type_name::<T>()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{panic, sync::Arc, thread};
+
+    #[test]
+    fn should_keep_working_after_a_panic() {
+        let lock = Arc::new(Lock::new(0));
+        assert_eq!(*lock.try_lock().unwrap(), 0);
+
+        let cloned = lock.clone();
+        let result = thread::spawn(move || {
+            let mut value = cloned.lock();
+            *value += 1;
+            panic!("bug while holding the lock");
+        })
+        .join();
+        assert!(result.is_err());
+
+        // the value can still be read, but not trusted to be changed
+        assert_eq!(*lock.lock(), 1);
+        assert_eq!(lock.try_lock().unwrap_err(), LockError::Poisoned("i32"));
+        assert!(panic::catch_unwind(|| *lock.lock()).is_ok());
+    }
+}
@@ -1,9 +1,424 @@
-use env_logger::{Builder, Target};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt,
+    io::{self, Write},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use chrono::{SecondsFormat, Utc};
 use log::LevelFilter;
+use serde_json::{json, Map, Value};
+use thiserror::Error;
+use tracing::{
+    field::{Field, Visit},
+    span, Event, Metadata, Subscriber,
+};
+
+use super::Config;
+
+// Error types to return when the logging settings are not valid
+#[derive(Error, PartialEq, Debug)]
+pub enum LoggerError {
+    #[error("Invalid level `{0}` in LOG_LEVEL, valid levels are off, error, warn, info, debug and trace")]
+    InvalidLevel(String),
+
+    #[error("Unknown format `{0}` in LOG_FORMAT, valid formats are text and json")]
+    UnknownFormat(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = LoggerError;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(LoggerError::UnknownFormat(name.to_string())),
+        }
+    }
+}
+
+// Max level of the logs, for every module and for the ones under a path
+// e.g. "info,actix_server=warn" or "debug,rust_blockchain::network=trace"
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogFilter {
+    level: LevelFilter,
+    targets: Vec<(String, LevelFilter)>,
+}
+
+impl FromStr for LogFilter {
+    type Err = LoggerError;
+
+    fn from_str(directives: &str) -> Result<Self, Self::Err> {
+        let parse_level = |level: &str| {
+            LevelFilter::from_str(level.trim())
+                .map_err(|_| LoggerError::InvalidLevel(level.to_string()))
+        };
+
+        let mut filter = LogFilter {
+            level: LevelFilter::Info,
+            targets: Vec::new(),
+        };
+        for directive in directives.split(',').filter(|d| !d.trim().is_empty()) {
+            match directive.split_once('=') {
+                Some((target, level)) => {
+                    let target = target.trim().to_string();
+                    filter.targets.push((target, parse_level(level)?));
+                }
+                None => filter.level = parse_level(directive)?,
+            }
+        }
+
+        Ok(filter)
+    }
+}
+
+impl LogFilter {
+    // The most specific path wins
+    fn level_for(&self, target: &str) -> LevelFilter {
+        self.targets
+            .iter()
+            .filter(|(path, _)| target.starts_with(path.as_str()))
+            .max_by_key(|(path, _)| path.len())
+            .map_or(self.level, |(_, level)| *level)
+    }
+
+    fn max_level(&self) -> LevelFilter {
+        self.targets
+            .iter()
+            .map(|(_, level)| *level)
+            .fold(self.level, Ord::max)
+    }
+
+    fn enabled(&self, level: log::Level, target: &str) -> bool {
+        level <= self.level_for(target)
+    }
+}
+
+// Sets up the logs of both the "log" macros and the "tracing" spans and events
+// Every line carries the spans the thread is in (e.g. the block being validated or the api request)
+pub fn initialize_logger(config: &Config) {
+    // the startup check stops the node, but the rest of commands can still run with the defaults
+    let mut errors = Vec::new();
+    let filter = LogFilter::from_str(&config.log_level).unwrap_or_else(|error| {
+        errors.push(error);
+        LogFilter::from_str("info").unwrap()
+    });
+    let format = LogFormat::from_str(&config.log_format).unwrap_or_else(|error| {
+        errors.push(error);
+        LogFormat::Text
+    });
+
+    log::set_max_level(filter.max_level());
+    let logger = Logger(Arc::new(LoggerState {
+        filter,
+        format,
+        spans: Mutex::new(HashMap::new()),
+        next_span_id: AtomicU64::new(1),
+    }));
+    let _ = log::set_boxed_logger(Box::new(logger.clone()));
+    let _ = tracing::subscriber::set_global_default(logger);
+
+    for error in errors {
+        warn!("{}", error);
+    }
+}
+
+// Spans the current thread is in, from the outermost to the innermost
+// Futures enter their spans on every poll, so this also works for the api
+thread_local! {
+    static CURRENT_SPANS: RefCell<Vec<u64>> = const { RefCell::new(Vec::new()) };
+}
+
+struct SpanData {
+    name: &'static str,
+    fields: Vec<(&'static str, Value)>,
+    references: usize,
+}
+
+struct LoggerState {
+    filter: LogFilter,
+    format: LogFormat,
+    spans: Mutex<HashMap<u64, SpanData>>,
+    next_span_id: AtomicU64,
+}
+
+#[derive(Clone)]
+struct Logger(Arc<LoggerState>);
+
+impl Logger {
+    fn write(
+        &self,
+        level: log::Level,
+        target: &str,
+        message: &str,
+        fields: &[(&'static str, Value)],
+    ) {
+        let spans = self.0.spans.lock().unwrap();
+        let current: Vec<&SpanData> = CURRENT_SPANS.with(|current| {
+            current
+                .borrow()
+                .iter()
+                .filter_map(|id| spans.get(id))
+                .collect()
+        });
+        let timestamp = Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true);
+
+        let line = match self.0.format {
+            LogFormat::Text => {
+                let mut line = format!("[{} {:<5} {}] ", timestamp, level, target);
+                for span in current.iter() {
+                    line.push_str(&format!("{}{}: ", span.name, format_fields(&span.fields)));
+                }
+                line.push_str(message);
+                if !fields.is_empty() {
+                    line.push(' ');
+                    line.push_str(&format_fields(fields));
+                }
+                line
+            }
+            LogFormat::Json => {
+                let spans: Vec<Value> = current
+                    .iter()
+                    .map(|span| {
+                        let mut object = Map::new();
+                        object.insert("name".to_string(), json!(span.name));
+                        object.extend(to_object(&span.fields));
+                        Value::Object(object)
+                    })
+                    .collect();
+                json!({
+                    "timestamp": timestamp,
+                    "level": level.to_string(),
+                    "target": target,
+                    "spans": spans,
+                    "message": message,
+                    "fields": to_object(fields),
+                })
+                .to_string()
+            }
+        };
+        drop(spans);
+
+        // there is nowhere to report that the logs can't be written
+        let _ = writeln!(io::stdout().lock(), "{}", line);
+    }
+}
+
+impl log::Log for Logger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.0.filter.enabled(metadata.level(), metadata.target())
+    }
+
+    fn log(&self, record: &log::Record) {
+        if log::Log::enabled(self, record.metadata()) {
+            let message = record.args().to_string();
+            self.write(record.level(), record.target(), &message, &[]);
+        }
+    }
+
+    fn flush(&self) {
+        let _ = io::stdout().flush();
+    }
+}
+
+impl Subscriber for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.0
+            .filter
+            .enabled(to_log_level(metadata.level()), metadata.target())
+    }
+
+    fn new_span(&self, attributes: &span::Attributes) -> span::Id {
+        let mut visitor = FieldVisitor::default();
+        attributes.record(&mut visitor);
+
+        let id = self.0.next_span_id.fetch_add(1, Ordering::Relaxed);
+        let span = SpanData {
+            name: attributes.metadata().name(),
+            fields: visitor.fields,
+            references: 1,
+        };
+        self.0.spans.lock().unwrap().insert(id, span);
+        span::Id::from_u64(id)
+    }
+
+    fn record(&self, id: &span::Id, values: &span::Record) {
+        let mut visitor = FieldVisitor::default();
+        values.record(&mut visitor);
+
+        if let Some(span) = self.0.spans.lock().unwrap().get_mut(&id.into_u64()) {
+            span.fields.extend(visitor.fields);
+        }
+    }
+
+    fn record_follows_from(&self, _span: &span::Id, _follows: &span::Id) {}
+
+    fn event(&self, event: &Event) {
+        let mut visitor = FieldVisitor::default();
+        event.record(&mut visitor);
+
+        let metadata = event.metadata();
+        let message = visitor.message.unwrap_or_default();
+        let level = to_log_level(metadata.level());
+        self.write(level, metadata.target(), &message, &visitor.fields);
+    }
+
+    fn enter(&self, id: &span::Id) {
+        CURRENT_SPANS.with(|current| current.borrow_mut().push(id.into_u64()));
+    }
+
+    fn exit(&self, id: &span::Id) {
+        CURRENT_SPANS.with(|current| {
+            let mut current = current.borrow_mut();
+            if let Some(position) = current
+                .iter()
+                .rposition(|entered| *entered == id.into_u64())
+            {
+                current.remove(position);
+            }
+        });
+    }
+
+    fn clone_span(&self, id: &span::Id) -> span::Id {
+        if let Some(span) = self.0.spans.lock().unwrap().get_mut(&id.into_u64()) {
+            span.references += 1;
+        }
+        id.clone()
+    }
+
+    // Spans are forgotten once every handle to them is dropped
+    fn try_close(&self, id: span::Id) -> bool {
+        let mut spans = self.0.spans.lock().unwrap();
+        let closed = match spans.get_mut(&id.into_u64()) {
+            Some(span) => {
+                span.references -= 1;
+                span.references == 0
+            }
+            None => false,
+        };
+
+        if closed {
+            spans.remove(&id.into_u64());
+        }
+        closed
+    }
+}
+
+// Collects the fields of spans and events, keeping numbers and booleans as such for the json format
+#[derive(Default)]
+struct FieldVisitor {
+    message: Option<String>,
+    fields: Vec<(&'static str, Value)>,
+}
+
+impl Visit for FieldVisitor {
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.fields.push((field.name(), json!(value)));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.fields.push((field.name(), json!(value)));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.fields.push((field.name(), json!(value)));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.fields.push((field.name(), json!(value)));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        match field.name() {
+            "message" => self.message = Some(format!("{:?}", value)),
+            name => self.fields.push((name, json!(format!("{:?}", value)))),
+        }
+    }
+}
+
+// e.g. "{index=3 peer=localhost:9000}"
+fn format_fields(fields: &[(&'static str, Value)]) -> String {
+    if fields.is_empty() {
+        return String::new();
+    }
+
+    let fields: Vec<String> = fields
+        .iter()
+        .map(|(name, value)| match value {
+            // strings go without quotes, like in the rest of the logs
+            Value::String(value) => format!("{}={}", name, value),
+            value => format!("{}={}", name, value),
+        })
+        .collect();
+    format!("{{{}}}", fields.join(" "))
+}
+
+fn to_object(fields: &[(&'static str, Value)]) -> Map<String, Value> {
+    fields
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.clone()))
+        .collect()
+}
+
+fn to_log_level(level: &tracing::Level) -> log::Level {
+    match *level {
+        tracing::Level::ERROR => log::Level::Error,
+        tracing::Level::WARN => log::Level::Warn,
+        tracing::Level::INFO => log::Level::Info,
+        tracing::Level::DEBUG => log::Level::Debug,
+        tracing::Level::TRACE => log::Level::Trace,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_parse_filters() {
+        let filter =
+            LogFilter::from_str("debug,actix_server=warn,actix_server::worker=off").unwrap();
+        assert_eq!(
+            filter.level_for("rust_blockchain::miner"),
+            LevelFilter::Debug
+        );
+        assert_eq!(filter.level_for("actix_server::builder"), LevelFilter::Warn);
+        assert_eq!(filter.level_for("actix_server::worker"), LevelFilter::Off);
+        assert_eq!(filter.max_level(), LevelFilter::Debug);
+
+        // a filter with only paths keeps the default level for the rest
+        let filter = LogFilter::from_str("h2=trace").unwrap();
+        assert_eq!(filter.level_for("rust_blockchain"), LevelFilter::Info);
+        assert_eq!(filter.max_level(), LevelFilter::Trace);
+        assert!(filter.enabled(log::Level::Info, "rust_blockchain"));
+        assert!(!filter.enabled(log::Level::Debug, "rust_blockchain"));
+
+        assert_eq!(
+            LogFilter::from_str("info,h2=loud"),
+            Err(LoggerError::InvalidLevel("loud".to_string()))
+        );
+        assert_eq!(
+            LogFormat::from_str("xml"),
+            Err(LoggerError::UnknownFormat("xml".to_string()))
+        );
+    }
 
-pub fn initialize_logger() {
-    let mut builder = Builder::from_default_env();
-    builder.target(Target::Stdout);
-    builder.filter(None, LevelFilter::Info);
-    builder.init();
+    #[test]
+    fn should_format_fields() {
+        let fields = vec![("index", json!(3)), ("peer", json!("localhost:9000"))];
+        assert_eq!(format_fields(&fields), "{index=3 peer=localhost:9000}");
+        assert_eq!(format_fields(&[]), "");
+    }
 }
@@ -0,0 +1,10 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+};
+
+// Returns a random number, good enough for jitters and identifiers but NOT for cryptography
+// The standard library seeds every hasher randomly, so we don't need an extra crate
+pub fn random_u64() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
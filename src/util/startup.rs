@@ -0,0 +1,516 @@
+use std::{
+    net::{TcpListener, ToSocketAddrs},
+    str::FromStr,
+};
+
+use anyhow::{Context as _, Result};
+use crypto::{digest::Digest, ed25519, sha2::Sha256};
+use thiserror::Error;
+
+use super::{
+    logger::{LogFilter, LogFormat},
+    Config,
+};
+use crate::{
+    api::TlsAcceptor,
+    consensus,
+    model::{MAX_DATA_LENGTH, MAX_DECIMALS},
+    wallet::Wallet,
+};
+
+// Hashes are SHA 256, so no hash can have more leading zeros than this
+const MAX_DIFFICULTY: u32 = 256;
+
+// Known SHA 256 digest of "abc", from the NIST test vectors
+const SHA256_ABC_DIGEST: &str = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+
+// Error types to return when the node is not able to work properly with the current setup
+// Each message tells the user what to change
+#[derive(Error, PartialEq, Debug)]
+pub enum StartupError {
+    #[error("PORT {0} is already in use, stop the other process or choose a different PORT")]
+    PortInUse(u16),
+
+    #[error("Unknown setting {0}, check the name of the section and the setting")]
+    UnknownOverride(String),
+
+    #[error("PORT must not be 0")]
+    InvalidPort,
+
+    #[error(
+        "Invalid address `{0}` in LISTEN_ADDRESSES, it must be like 0.0.0.0:8000 or [::1]:8000"
+    )]
+    InvalidListenAddress(String),
+
+    #[error("Address {0} is already in use, stop the other process or change LISTEN_ADDRESSES")]
+    AddressInUse(String),
+
+    #[error("P2P_PORT {0} is already used by the api, choose a different one")]
+    P2pPortUsedByApi(u16),
+
+    #[error("GRPC_PORT {0} is already used by the api or the p2p network, choose a different one")]
+    GrpcPortInUseByNode(u16),
+
+    #[error(
+        "DIFFICULTY must be at most {} (the size of the hashes in bits), got {0}",
+        MAX_DIFFICULTY
+    )]
+    InvalidDifficulty(u32),
+
+    #[error("MAX_NONCE must be greater than 0, otherwise no block could ever be mined")]
+    InvalidMaxNonce,
+
+    #[error("MINER_THREADS must be greater than 0")]
+    InvalidMinerThreads,
+
+    #[error("API_MAX_BODY_BYTES must be greater than 0, otherwise no transaction could be sent")]
+    InvalidMaxBodyBytes,
+
+    #[error(
+        "PRUNE_DEPTH must be 0 or at least FINALITY_DEPTH ({0}), so only final blocks are pruned"
+    )]
+    InvalidPruneDepth(u64),
+
+    #[error(
+        "AMOUNT_DECIMALS must be at most {}, the digits of the largest amount",
+        MAX_DECIMALS
+    )]
+    InvalidAmountDecimals,
+
+    #[error(
+        "MAX_DATA_BYTES must be at most {}, the most data that a transaction can carry",
+        MAX_DATA_LENGTH
+    )]
+    InvalidMaxDataBytes,
+
+    #[error(
+        "MAX_CLOCK_OFFSET_SECS must be greater than 0 and lower than MAX_TIME_DRIFT_SECS ({0}), \
+        so skewed clocks are detected before they make the node reject blocks"
+    )]
+    InvalidMaxClockOffset(u64),
+
+    #[error("LIGHT_CLIENT only keeps the headers in memory, remove DATA_DIR")]
+    LightClientWithDataDir,
+
+    #[error("LIGHT_CLIENT requires CONSENSUS=pow, as the signatures of the blocks are not in their headers")]
+    LightClientWithoutPow,
+
+    #[error("TLS_CERT_PATH and TLS_KEY_PATH must be set together to serve the api over TLS")]
+    IncompleteTls,
+
+    #[error("Invalid peer address `{0}` in PEERS, it must start with http:// or https://")]
+    InvalidPeer(String),
+
+    #[error(
+        "POA_SIGNER_SEED does not match any of the POA_SIGNERS, remove it or add its public key"
+    )]
+    SignerNotInSignerSet,
+
+    #[error("Cryptography self-test failed: {0}")]
+    CryptoSelfTest(&'static str),
+}
+
+// Checks that the node can work properly before starting any process,
+// so we fail fast with an actionable error instead of panicking later
+pub fn check_startup(config: &Config) -> Result<()> {
+    run_crypto_self_test()?;
+    if let Some(name) = Config::unknown_overrides().first() {
+        return Err(StartupError::UnknownOverride(name.to_string()).into());
+    }
+    validate_config(config)?;
+    if config.listen_addresses.is_empty() {
+        check_port_is_free(config.port)?;
+    }
+    for address in &config.listen_addresses {
+        check_address_is_free(address)?;
+    }
+    if config.p2p_port != 0 {
+        check_port_is_free(config.p2p_port)?;
+    }
+    if config.grpc_port != 0 {
+        check_port_is_free(config.grpc_port)?;
+    }
+
+    info!("startup self-test passed");
+    Ok(())
+}
+
+// Make sure that the cryptographic primitives produce the expected results on this platform
+fn run_crypto_self_test() -> Result<()> {
+    let mut hasher = Sha256::new();
+    hasher.input_str("abc");
+    if hasher.result_str() != SHA256_ABC_DIGEST {
+        return Err(StartupError::CryptoSelfTest("unexpected SHA 256 digest").into());
+    }
+
+    let message = b"self-test";
+    let (secret_key, public_key) = ed25519::keypair(&[7; 32]);
+    let signature = ed25519::signature(message, &secret_key);
+    if !ed25519::verify(message, &public_key, &signature) {
+        return Err(StartupError::CryptoSelfTest("valid ed25519 signature rejected").into());
+    }
+    if ed25519::verify(b"tampered", &public_key, &signature) {
+        return Err(StartupError::CryptoSelfTest("invalid ed25519 signature accepted").into());
+    }
+
+    Ok(())
+}
+
+// Check that the configuration values are coherent with each other
+fn validate_config(config: &Config) -> Result<()> {
+    if config.port == 0 {
+        return Err(StartupError::InvalidPort.into());
+    }
+
+    if let Some(address) = config
+        .listen_addresses
+        .iter()
+        .find(|address| !is_socket_address(address))
+    {
+        return Err(StartupError::InvalidListenAddress(address.to_string()).into());
+    }
+
+    if config.p2p_port == config.port {
+        return Err(StartupError::P2pPortUsedByApi(config.p2p_port).into());
+    }
+
+    if config.grpc_port != 0
+        && (config.grpc_port == config.port || config.grpc_port == config.p2p_port)
+    {
+        return Err(StartupError::GrpcPortInUseByNode(config.grpc_port).into());
+    }
+
+    if config.tls_cert_path.is_empty() != config.tls_key_path.is_empty() {
+        return Err(StartupError::IncompleteTls.into());
+    }
+
+    if let Some(peer) = config
+        .peers
+        .iter()
+        .find(|peer| !peer.starts_with("http://") && !peer.starts_with("https://"))
+    {
+        return Err(StartupError::InvalidPeer(peer.to_string()).into());
+    }
+
+    if config.miner_threads == 0 {
+        return Err(StartupError::InvalidMinerThreads.into());
+    }
+
+    if config.api_max_body_bytes == 0 {
+        return Err(StartupError::InvalidMaxBodyBytes.into());
+    }
+
+    if config.prune_depth != 0 && config.prune_depth < config.finality_depth {
+        return Err(StartupError::InvalidPruneDepth(config.finality_depth).into());
+    }
+
+    if config.amount_decimals > MAX_DECIMALS {
+        return Err(StartupError::InvalidAmountDecimals.into());
+    }
+
+    if config.max_data_bytes > MAX_DATA_LENGTH {
+        return Err(StartupError::InvalidMaxDataBytes.into());
+    }
+
+    if config.max_clock_offset_secs == 0
+        || config.max_clock_offset_secs >= config.max_time_drift_secs
+    {
+        return Err(StartupError::InvalidMaxClockOffset(config.max_time_drift_secs).into());
+    }
+
+    if config.light_client && !config.data_dir.is_empty() {
+        return Err(StartupError::LightClientWithDataDir.into());
+    }
+
+    if config.light_client && config.consensus != "pow" {
+        return Err(StartupError::LightClientWithoutPow.into());
+    }
+
+    LogFilter::from_str(&config.log_level)?;
+    LogFormat::from_str(&config.log_format)?;
+
+    Wallet::from_config(config)
+        .context("Invalid wallet configuration, check WALLET_MODE and WALLET_ADDRESSES")?;
+
+    if !config.tls_cert_path.is_empty() {
+        let timeout_ms = config.api_request_timeout_ms;
+        TlsAcceptor::new(&config.tls_cert_path, &config.tls_key_path, timeout_ms)
+            .context("Invalid TLS configuration, check TLS_CERT_PATH and TLS_KEY_PATH")?;
+    }
+
+    // the engine already checks its own parameters (e.g. the keys of the signers)
+    let engine = consensus::from_config(config).context(
+        "Invalid consensus configuration, check CONSENSUS, TARGET_BITS, POA_SIGNERS and POA_SIGNER_SEED",
+    )?;
+
+    match config.consensus.as_str() {
+        "pow" if config.difficulty > MAX_DIFFICULTY => {
+            Err(StartupError::InvalidDifficulty(config.difficulty).into())
+        }
+        "pow" if config.max_nonce == 0 => Err(StartupError::InvalidMaxNonce.into()),
+        // a node with a seed is meant to be a signer, but it would never produce blocks
+        "poa" if !config.poa_signer_seed.trim().is_empty() && !engine.can_seal() => {
+            Err(StartupError::SignerNotInSignerSet.into())
+        }
+        _ => Ok(()),
+    }
+}
+
+// Addresses can have host names, as long as they resolve to some IP
+fn is_socket_address(address: &str) -> bool {
+    address
+        .to_socket_addrs()
+        .map(|mut addrs| addrs.next().is_some())
+        .unwrap_or(false)
+}
+
+// The api would fail to start much later, after the rest of processes are already running
+fn check_address_is_free(address: &str) -> Result<()> {
+    match TcpListener::bind(address) {
+        Ok(_) => Ok(()),
+        Err(_) => Err(StartupError::AddressInUse(address.to_string()).into()),
+    }
+}
+
+fn check_port_is_free(port: u16) -> Result<()> {
+    match TcpListener::bind(("localhost", port)) {
+        // the listener is closed right away when dropped
+        Ok(_) => Ok(()),
+        Err(_) => Err(StartupError::PortInUse(port).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::Byzantine;
+
+    #[test]
+    fn should_pass_crypto_self_test() {
+        assert!(run_crypto_self_test().is_ok());
+    }
+
+    #[test]
+    fn should_accept_default_config() {
+        let config = create_config();
+        assert!(validate_config(&config).is_ok());
+    }
+
+    #[test]
+    fn should_reject_incoherent_config() {
+        let mut config = create_config();
+        config.difficulty = MAX_DIFFICULTY + 1;
+        assert_err(
+            validate_config(&config),
+            StartupError::InvalidDifficulty(257),
+        );
+
+        let mut config = create_config();
+        config.max_nonce = 0;
+        assert_err(validate_config(&config), StartupError::InvalidMaxNonce);
+
+        let mut config = create_config();
+        config.miner_threads = 0;
+        assert_err(validate_config(&config), StartupError::InvalidMinerThreads);
+
+        let mut config = create_config();
+        config.api_max_body_bytes = 0;
+        assert_err(validate_config(&config), StartupError::InvalidMaxBodyBytes);
+
+        let mut config = create_config();
+        config.amount_decimals = MAX_DECIMALS + 1;
+        assert_err(
+            validate_config(&config),
+            StartupError::InvalidAmountDecimals,
+        );
+
+        let mut config = create_config();
+        config.max_data_bytes = MAX_DATA_LENGTH + 1;
+        assert_err(validate_config(&config), StartupError::InvalidMaxDataBytes);
+
+        let mut config = create_config();
+        config.max_clock_offset_secs = 0;
+        let expected_error = StartupError::InvalidMaxClockOffset(7200);
+        assert_err(validate_config(&config), expected_error);
+
+        let mut config = create_config();
+        config.max_clock_offset_secs = 7200;
+        let expected_error = StartupError::InvalidMaxClockOffset(7200);
+        assert_err(validate_config(&config), expected_error);
+
+        let mut config = create_config();
+        config.tls_cert_path = "cert.pem".to_string();
+        assert_err(validate_config(&config), StartupError::IncompleteTls);
+        config.tls_key_path = "missing.pem".to_string();
+        assert!(validate_config(&config).is_err());
+
+        let mut config = create_config();
+        config.peers = vec!["localhost:8001".to_string()];
+        let expected_error = StartupError::InvalidPeer("localhost:8001".to_string());
+        assert_err(validate_config(&config), expected_error);
+
+        let mut config = create_config();
+        config.p2p_port = config.port;
+        assert_err(
+            validate_config(&config),
+            StartupError::P2pPortUsedByApi(8000),
+        );
+
+        let mut config = create_config();
+        config.p2p_port = 9000;
+        config.grpc_port = 9000;
+        assert_err(
+            validate_config(&config),
+            StartupError::GrpcPortInUseByNode(9000),
+        );
+
+        let mut config = create_config();
+        config.listen_addresses = vec!["0.0.0.0".to_string()];
+        let expected_error = StartupError::InvalidListenAddress("0.0.0.0".to_string());
+        assert_err(validate_config(&config), expected_error);
+
+        let mut config = create_config();
+        config.prune_depth = 2;
+        assert_err(validate_config(&config), StartupError::InvalidPruneDepth(6));
+
+        let mut config = create_config();
+        config.light_client = true;
+        config.data_dir = "data".to_string();
+        assert_err(
+            validate_config(&config),
+            StartupError::LightClientWithDataDir,
+        );
+
+        let mut config = create_config();
+        config.light_client = true;
+        config.consensus = "poa".to_string();
+        assert_err(
+            validate_config(&config),
+            StartupError::LightClientWithoutPow,
+        );
+
+        let mut config = create_config();
+        config.wallet_mode = "warm".to_string();
+        assert!(validate_config(&config).is_err());
+
+        let mut config = create_config();
+        config.log_level = "info,actix_server=quiet".to_string();
+        assert!(validate_config(&config).is_err());
+
+        let mut config = create_config();
+        config.log_format = "xml".to_string();
+        assert!(validate_config(&config).is_err());
+    }
+
+    #[test]
+    fn should_reject_invalid_consensus_config() {
+        let mut config = create_config();
+        config.consensus = "foo".to_string();
+        assert!(validate_config(&config).is_err());
+
+        let mut config = create_config();
+        config.target_bits = "0x21010000".to_string();
+        assert!(validate_config(&config).is_err());
+
+        // the seed belongs to a signer that is not in the signer set
+        let mut config = create_config();
+        config.consensus = "poa".to_string();
+        config.poa_signers =
+            vec!["8a88e3dd7409f195fd52db2d3cba5d72ca6709bf1d94121bf3748801b40f6f5c".to_string()];
+        config.poa_signer_seed = "02".repeat(32);
+        assert_err(validate_config(&config), StartupError::SignerNotInSignerSet);
+
+        // with the right seed the node is a signer
+        config.poa_signer_seed = "01".repeat(32);
+        assert!(validate_config(&config).is_ok());
+    }
+
+    #[test]
+    fn should_detect_ports_in_use() {
+        let listener = TcpListener::bind(("localhost", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        assert_err(check_port_is_free(port), StartupError::PortInUse(port));
+
+        let address = format!("localhost:{}", port);
+        let expected_error = StartupError::AddressInUse(address.clone());
+        assert_err(check_address_is_free(&address), expected_error);
+
+        // once the port is released it can be used again
+        drop(listener);
+        assert!(check_port_is_free(port).is_ok());
+    }
+
+    fn create_config() -> Config {
+        Config {
+            port: 8000,
+            listen_addresses: Vec::new(),
+            data_dir: String::new(),
+            prune_depth: 0,
+            snapshot_path: String::new(),
+            storage_compression: false,
+            shutdown_drain_ms: 0,
+            shutdown_timeout_secs: 0,
+            finality_depth: 6,
+            contracts_enabled: false,
+            max_data_bytes: 256,
+            max_time_drift_secs: 7200,
+            amount_decimals: 0,
+            longpoll_timeout_ms: 0,
+            api_key: String::new(),
+            api_public_reads: true,
+            api_max_body_bytes: 1024 * 1024,
+            api_request_timeout_ms: 5000,
+            rate_limit_per_sec: 0,
+            rate_limit_burst: 50,
+            cors_allowed_origins: Vec::new(),
+            cors_allowed_methods: Vec::new(),
+            cors_max_age_secs: 0,
+            grpc_port: 0,
+            tls_cert_path: String::new(),
+            tls_key_path: String::new(),
+            consensus: "pow".to_string(),
+            poa_signers: Vec::new(),
+            poa_signer_seed: String::new(),
+            poa_block_interval_ms: 0,
+            peers: vec!["http://localhost:8001".to_string()],
+            peer_sync_ms: 0,
+            chain_id: "main".to_string(),
+            p2p_port: 0,
+            p2p_peers: Vec::new(),
+            p2p_seeds: Vec::new(),
+            p2p_max_peers: 8,
+            p2p_announce_ms: 0,
+            p2p_peer_book: String::new(),
+            p2p_peer_book_save_ms: 0,
+            p2p_ban_secs: 0,
+            light_client: false,
+            p2p_compact_blocks: true,
+            max_clock_offset_secs: 300,
+            auto_mining: true,
+            max_blocks: 0,
+            max_nonce: 1,
+            difficulty: 10,
+            target_bits: String::new(),
+            tx_waiting_ms: 0,
+            miner_threads: 1,
+            clock_offset_stops_mining: false,
+            notification_poll_ms: 0,
+            scheduler_jitter_ms: 0,
+            mempool_expiry_secs: 0,
+            mempool_sweep_ms: 0,
+            mempool_rebroadcast_ms: 0,
+            mempool_save_ms: 0,
+            wallet_mode: "hot".to_string(),
+            wallet_addresses: Vec::new(),
+            log_level: "info".to_string(),
+            log_format: "text".to_string(),
+            byzantine: Byzantine::default(),
+        }
+    }
+
+    fn assert_err(result: Result<()>, error_type: StartupError) {
+        let err = result.unwrap_err().downcast::<StartupError>().unwrap();
+        assert_eq!(err, error_type);
+    }
+}
@@ -1,7 +1,115 @@
-// Quit the program when the user inputs Ctrl-C
-pub fn set_ctrlc_handler() {
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use tokio::time::delay_for;
+
+use super::execution::sleep_millis;
+
+// Time interval to check if the shutdown sequence has progressed
+const SHUTDOWN_POLL_MS: u64 = 10;
+
+// Shared flags that track the progress of a graceful shutdown
+// Cloning only clones the pointers, so all clones observe the same state
+#[derive(Debug, Clone, Default)]
+pub struct Shutdown {
+    draining: Arc<AtomicBool>,
+    stopped: Arc<AtomicBool>,
+}
+
+impl Shutdown {
+    pub fn new() -> Shutdown {
+        Shutdown::default()
+    }
+
+    // Signal all components that the node is shutting down
+    pub fn start_draining(&self) {
+        self.draining.store(true, Ordering::SeqCst);
+    }
+
+    // While draining, the node must stop accepting new work
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+
+    // Indicate that the API has finished all in-flight requests and stopped listening
+    pub fn mark_stopped(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::SeqCst)
+    }
+
+    // Wait until the shutdown sequence starts, without blocking the runtime
+    pub async fn draining(&self) {
+        while !self.is_draining() {
+            delay_for(Duration::from_millis(SHUTDOWN_POLL_MS)).await;
+        }
+    }
+
+    // Block the current thread until the API has stopped or the max time has passed
+    pub fn wait_for_stop(&self, max_wait_ms: u64) {
+        let mut waited_ms = 0;
+        while !self.is_stopped() && waited_ms < max_wait_ms {
+            sleep_millis(SHUTDOWN_POLL_MS);
+            waited_ms += SHUTDOWN_POLL_MS;
+        }
+    }
+}
+
+// Quit the program when the user inputs Ctrl-C (or the process receives a SIGTERM)
+// Before exiting, we give the API the chance to drain all in-flight requests
+// and then run "on_exit", to save what only lives in memory
+pub fn set_ctrlc_handler<F>(shutdown: Shutdown, max_wait_ms: u64, on_exit: F)
+where
+    F: Fn() + Send + 'static,
+{
     ctrlc::set_handler(move || {
+        info!("shutdown requested, draining the api");
+        shutdown.start_draining();
+        shutdown.wait_for_stop(max_wait_ms);
+        on_exit();
         std::process::exit(0);
     })
     .expect("Error setting Ctrl-C handler");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_not_be_draining_after_creation() {
+        let shutdown = Shutdown::new();
+
+        assert!(!shutdown.is_draining());
+        assert!(!shutdown.is_stopped());
+    }
+
+    #[test]
+    fn should_share_state_between_clones() {
+        let shutdown = Shutdown::new();
+        let cloned_shutdown = shutdown.clone();
+
+        // changes in one clone must be visible in the other ones
+        cloned_shutdown.start_draining();
+        assert!(shutdown.is_draining());
+
+        cloned_shutdown.mark_stopped();
+        assert!(shutdown.is_stopped());
+    }
+
+    #[test]
+    fn should_not_wait_for_stop_beyond_max_time() {
+        let shutdown = Shutdown::new();
+
+        // the api never stops, so it must return after the max waiting time
+        shutdown.wait_for_stop(SHUTDOWN_POLL_MS);
+        assert!(!shutdown.is_stopped());
+    }
+}
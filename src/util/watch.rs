@@ -0,0 +1,113 @@
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Mutex,
+};
+
+// Shared state of a watch channel, a version number is increased on every change
+// so receivers can cheaply check for changes without locking the value
+#[derive(Debug)]
+struct Shared<T> {
+    version: AtomicU64,
+    value: Mutex<T>,
+}
+
+// Sending half of a watch channel, only the latest value is kept
+#[derive(Debug, Clone)]
+pub struct WatchSender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+// Receiving half of a watch channel, remembers the last version it has seen
+#[derive(Debug, Clone)]
+pub struct WatchReceiver<T> {
+    shared: Arc<Shared<T>>,
+    seen_version: u64,
+}
+
+// Creates a new watch channel with an initial value
+pub fn channel<T: Clone>(initial: T) -> (WatchSender<T>, WatchReceiver<T>) {
+    let shared = Arc::new(Shared {
+        version: AtomicU64::new(0),
+        value: Mutex::new(initial),
+    });
+
+    let sender = WatchSender {
+        shared: shared.clone(),
+    };
+    let receiver = WatchReceiver {
+        shared,
+        seen_version: 0,
+    };
+
+    (sender, receiver)
+}
+
+impl<T: Clone> WatchSender<T> {
+    // Replace the value, notifying all receivers
+    pub fn send(&self, value: T) {
+        let mut current = self.shared.value.lock().unwrap();
+        *current = value;
+        self.shared.version.fetch_add(1, Ordering::SeqCst);
+    }
+
+    // Create a new receiver that has already seen the current value
+    pub fn subscribe(&self) -> WatchReceiver<T> {
+        WatchReceiver {
+            shared: self.shared.clone(),
+            seen_version: self.shared.version.load(Ordering::SeqCst),
+        }
+    }
+}
+
+impl<T: Clone> WatchReceiver<T> {
+    // Returns true if the value changed since the last time we marked it as seen
+    pub fn has_changed(&self) -> bool {
+        self.shared.version.load(Ordering::SeqCst) != self.seen_version
+    }
+
+    // Returns a copy of the current value and marks it as seen
+    pub fn borrow_and_update(&mut self) -> T {
+        let value = self.shared.value.lock().unwrap();
+        self.seen_version = self.shared.version.load(Ordering::SeqCst);
+        value.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_not_change_after_creation() {
+        let (_sender, mut receiver) = channel(1);
+
+        assert!(!receiver.has_changed());
+        assert_eq!(receiver.borrow_and_update(), 1);
+    }
+
+    #[test]
+    fn should_notify_changes() {
+        let (sender, mut receiver) = channel(1);
+
+        sender.send(2);
+        assert!(receiver.has_changed());
+
+        // once we read the value it's marked as seen
+        assert_eq!(receiver.borrow_and_update(), 2);
+        assert!(!receiver.has_changed());
+    }
+
+    #[test]
+    fn should_subscribe_to_current_value() {
+        let (sender, _receiver) = channel(1);
+        sender.send(2);
+
+        // new subscribers only get notified of later changes
+        let mut receiver = sender.subscribe();
+        assert!(!receiver.has_changed());
+        assert_eq!(receiver.borrow_and_update(), 2);
+
+        sender.send(3);
+        assert!(receiver.has_changed());
+    }
+}
@@ -0,0 +1,44 @@
+mod interpreter;
+mod module;
+
+use thiserror::Error;
+
+// Explicitly controlling which individual identifiers we export
+// It also avoids verbose module imports from other files
+pub use interpreter::{execute, Gas, Memory};
+pub use module::{FuncType, Module, ValueType};
+
+// Error types to return when some code can't be run, or stops before finishing
+#[derive(Error, PartialEq, Debug, Clone)]
+pub enum VmError {
+    #[error("Invalid module: {0}")]
+    InvalidModule(String),
+
+    #[error("Unknown import `{0}`")]
+    UnknownImport(String),
+
+    #[error("Missing export `{0}`")]
+    MissingExport(String),
+
+    #[error("Out of gas")]
+    OutOfGas,
+
+    #[error("Trap: {0}")]
+    Trap(String),
+}
+
+// Functions that the node provides to the code, which imports them from the "env" module
+// Host functions must charge the gas of the work they do, the interpreter only charges the call
+pub trait Host {
+    // Signature of the function with the given name, None if the host doesn't provide it
+    fn signature(&self, name: &str) -> Option<FuncType>;
+
+    // Values are passed as in the stack of the interpreter: i32 values are zero extended to 64 bits
+    fn call(
+        &mut self,
+        name: &str,
+        args: &[u64],
+        memory: &mut Memory,
+        gas: &mut Gas,
+    ) -> Result<Option<u64>, VmError>;
+}
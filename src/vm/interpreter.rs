@@ -0,0 +1,1042 @@
+use super::{
+    module::{ConstExpr, Instr, Limits, MAX_TABLE_SIZE},
+    FuncType, Host, Module, VmError,
+};
+
+const PAGE_SIZE: u64 = 65536;
+
+// Max size of the memory, in pages of 64 KiB
+const MAX_PAGES: u32 = 16;
+
+// Max number of nested calls, and of values in the stack
+const MAX_CALL_DEPTH: usize = 256;
+const MAX_STACK_SIZE: usize = 65536;
+
+// Every instruction costs 1 gas, the ones that work on many bytes also pay for them
+const GAS_PER_PAGE: u64 = 10_000;
+const BYTES_PER_GAS: u64 = 32;
+
+// Amount of work that an execution is allowed to do
+#[derive(Debug)]
+pub struct Gas {
+    limit: u64,
+    used: u64,
+}
+
+impl Gas {
+    pub fn new(limit: u64) -> Gas {
+        Gas { limit, used: 0 }
+    }
+
+    pub fn used(&self) -> u64 {
+        self.used
+    }
+
+    // Running out of gas stops the execution, and all the gas is considered used
+    pub fn charge(&mut self, amount: u64) -> Result<(), VmError> {
+        self.used = self.used.saturating_add(amount);
+        if self.used > self.limit {
+            self.used = self.limit;
+            return Err(VmError::OutOfGas);
+        }
+
+        Ok(())
+    }
+
+    // Gas of copying or filling some bytes, on top of the instruction that does it
+    pub fn charge_bytes(&mut self, length: u64) -> Result<(), VmError> {
+        self.charge(length / BYTES_PER_GAS)
+    }
+}
+
+// Linear memory of an execution, accesses out of it are traps
+#[derive(Debug)]
+pub struct Memory {
+    bytes: Vec<u8>,
+    max_pages: u32,
+}
+
+impl Memory {
+    pub fn read(&self, address: u64, length: u64) -> Result<&[u8], VmError> {
+        let range = self.range(address, length)?;
+
+        Ok(&self.bytes[range])
+    }
+
+    pub fn write(&mut self, address: u64, bytes: &[u8]) -> Result<(), VmError> {
+        let range = self.range(address, bytes.len() as u64)?;
+        self.bytes[range].copy_from_slice(bytes);
+
+        Ok(())
+    }
+
+    fn range(&self, address: u64, length: u64) -> Result<std::ops::Range<usize>, VmError> {
+        match address.checked_add(length) {
+            Some(end) if end <= self.bytes.len() as u64 => Ok(address as usize..end as usize),
+            _ => Err(trap("out of bounds memory access")),
+        }
+    }
+
+    fn pages(&self) -> u32 {
+        (self.bytes.len() as u64 / PAGE_SIZE) as u32
+    }
+
+    // Returns the previous number of pages, or None if the memory can't grow that much
+    fn grow(&mut self, pages: u32) -> Option<u32> {
+        let previous = self.pages();
+        let total = previous
+            .checked_add(pages)
+            .filter(|total| *total <= self.max_pages)?;
+        self.bytes
+            .resize((u64::from(total) * PAGE_SIZE) as usize, 0);
+
+        Some(previous)
+    }
+}
+
+// Runs the exported function "name", which must take no arguments and return nothing
+// Memory and globals only live during the execution, anything to keep must be passed to the host
+pub fn execute(
+    module: &Module,
+    name: &str,
+    host: &mut dyn Host,
+    gas: &mut Gas,
+) -> Result<(), VmError> {
+    let index = *module
+        .exports
+        .get(name)
+        .ok_or_else(|| VmError::MissingExport(name.to_string()))?;
+    if (index as usize) < module.imports.len() {
+        return Err(VmError::InvalidModule(format!("`{}` is imported", name)));
+    }
+    let function = &module.functions[index as usize - module.imports.len()];
+    let func_type = module.func_type(function.type_index)?;
+    if !func_type.params.is_empty() || !func_type.results.is_empty() {
+        return Err(VmError::InvalidModule(format!(
+            "`{}` must take no arguments and return nothing",
+            name
+        )));
+    }
+
+    let mut machine = Machine::new(module, host)?;
+    machine.call(index, host, gas)?;
+    machine.run(host, gas)
+}
+
+// Values are untyped in the stack: i32 values are kept zero extended to 64 bits
+// Modules are not type checked before running, so a wrong module may compute nonsense,
+// but always the same nonsense in every node, and anything that can't be done is a trap
+struct Stack(Vec<u64>);
+
+impl Stack {
+    fn push(&mut self, value: u64) -> Result<(), VmError> {
+        if self.0.len() >= MAX_STACK_SIZE {
+            return Err(trap("stack overflow"));
+        }
+
+        self.0.push(value);
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Result<u64, VmError> {
+        self.0.pop().ok_or_else(|| trap("stack underflow"))
+    }
+
+    fn pop_i32(&mut self) -> Result<u32, VmError> {
+        Ok(self.pop()? as u32)
+    }
+
+    // Removes the last "count" values, to pass them somewhere else
+    fn split_off(&mut self, count: usize) -> Result<Vec<u64>, VmError> {
+        let start = self
+            .0
+            .len()
+            .checked_sub(count)
+            .ok_or_else(|| trap("stack underflow"))?;
+
+        Ok(self.0.split_off(start))
+    }
+}
+
+// Where a branch goes, and the values it carries from the stack
+#[derive(Debug, Clone, Copy)]
+struct Label {
+    height: usize,
+    arity: usize,
+    target: usize,
+    is_loop: bool,
+}
+
+struct Frame {
+    // index in the functions of the module, without the imported ones
+    function: usize,
+    pc: usize,
+    locals: Vec<u64>,
+    stack_base: usize,
+    labels_base: usize,
+    results: usize,
+}
+
+struct Machine<'a> {
+    module: &'a Module,
+    memory: Memory,
+    globals: Vec<u64>,
+    table: Vec<Option<u32>>,
+    stack: Stack,
+    labels: Vec<Label>,
+    frames: Vec<Frame>,
+}
+
+impl<'a> Machine<'a> {
+    // Instantiates the module: checks its imports, and initializes its memory, globals and table
+    fn new(module: &'a Module, host: &dyn Host) -> Result<Machine<'a>, VmError> {
+        for import in module.imports.iter() {
+            if host.signature(&import.name).as_ref() != Some(module.func_type(import.type_index)?) {
+                return Err(VmError::UnknownImport(import.name.clone()));
+            }
+        }
+
+        let limits = module.memory.unwrap_or(Limits {
+            min: 0,
+            max: Some(0),
+        });
+        if limits.min > MAX_PAGES {
+            return Err(VmError::InvalidModule("memory too large".to_string()));
+        }
+        let mut memory = Memory {
+            bytes: vec![0; (u64::from(limits.min) * PAGE_SIZE) as usize],
+            max_pages: limits.max.unwrap_or(MAX_PAGES).min(MAX_PAGES),
+        };
+
+        let mut globals: Vec<u64> = Vec::with_capacity(module.globals.len());
+        for global in module.globals.iter() {
+            let value = eval_const(&global.init, &globals)?;
+            globals.push(value);
+        }
+
+        let table_size = module
+            .table
+            .map_or(0, |limits| limits.min.min(MAX_TABLE_SIZE));
+        let mut table = vec![None; table_size as usize];
+        for segment in module.elements.iter() {
+            let offset = eval_const(&segment.offset, &globals)? as u32 as usize;
+            let slots = table
+                .get_mut(offset..offset.saturating_add(segment.functions.len()))
+                .ok_or_else(|| trap("out of bounds table access"))?;
+            for (slot, function) in slots.iter_mut().zip(segment.functions.iter()) {
+                if *function >= module.function_count() {
+                    return Err(VmError::InvalidModule("element out of range".to_string()));
+                }
+                *slot = Some(*function);
+            }
+        }
+
+        for segment in module.data.iter() {
+            let offset = eval_const(&segment.offset, &globals)? as u32;
+            memory.write(u64::from(offset), &segment.bytes)?;
+        }
+
+        Ok(Machine {
+            module,
+            memory,
+            globals,
+            table,
+            stack: Stack(Vec::new()),
+            labels: Vec::new(),
+            frames: Vec::new(),
+        })
+    }
+
+    // Runs until the first function called returns
+    fn run(&mut self, host: &mut dyn Host, gas: &mut Gas) -> Result<(), VmError> {
+        let module = self.module;
+
+        while let Some(frame) = self.frames.last_mut() {
+            let instr = module.functions[frame.function]
+                .code
+                .get(frame.pc)
+                .ok_or_else(|| trap("end of function"))?;
+            let pc = frame.pc;
+            frame.pc += 1;
+            gas.charge(1)?;
+
+            match instr {
+                Instr::Unreachable => return Err(trap("unreachable")),
+                Instr::Nop => {}
+                Instr::Block {
+                    params,
+                    results,
+                    end,
+                } => {
+                    self.push_label(*params, *results, end + 1, false)?;
+                }
+                Instr::Loop { params } => self.push_label(*params, *params, pc + 1, true)?,
+                Instr::If {
+                    params,
+                    results,
+                    else_at,
+                    end,
+                } => {
+                    let condition = self.stack.pop_i32()?;
+                    self.push_label(*params, *results, end + 1, false)?;
+                    if condition == 0 {
+                        // the "end" of the block pops its label
+                        self.frame().pc = else_at.map_or(*end, |else_at| else_at + 1);
+                    }
+                }
+                // the then branch finished, so it jumps to the end of the block
+                Instr::Else { end } => self.frame().pc = *end,
+                Instr::End => {
+                    if self.labels.len() > self.frame().labels_base {
+                        self.labels.pop();
+                    } else {
+                        self.return_from_function()?;
+                    }
+                }
+                Instr::Br(depth) => self.branch(*depth)?,
+                Instr::BrIf(depth) => {
+                    if self.stack.pop_i32()? != 0 {
+                        self.branch(*depth)?;
+                    }
+                }
+                Instr::BrTable(depths, default) => {
+                    let index = self.stack.pop_i32()? as usize;
+                    self.branch(*depths.get(index).unwrap_or(default))?;
+                }
+                Instr::Return => self.return_from_function()?,
+                Instr::Call(index) => self.call(*index, host, gas)?,
+                Instr::CallIndirect(type_index) => {
+                    let index = self.stack.pop_i32()? as usize;
+                    let function = self
+                        .table
+                        .get(index)
+                        .ok_or_else(|| trap("undefined element"))?
+                        .ok_or_else(|| trap("uninitialized element"))?;
+                    if self.function_type(function)? != module.func_type(*type_index)? {
+                        return Err(trap("indirect call type mismatch"));
+                    }
+                    self.call(function, host, gas)?;
+                }
+                Instr::Drop => {
+                    self.stack.pop()?;
+                }
+                Instr::Select => {
+                    let condition = self.stack.pop_i32()?;
+                    let second = self.stack.pop()?;
+                    let first = self.stack.pop()?;
+                    self.stack
+                        .push(if condition != 0 { first } else { second })?;
+                }
+                Instr::LocalGet(index) => {
+                    let value = *self.local(*index)?;
+                    self.stack.push(value)?;
+                }
+                Instr::LocalSet(index) => {
+                    let value = self.stack.pop()?;
+                    *self.local(*index)? = value;
+                }
+                Instr::LocalTee(index) => {
+                    let value = self.stack.pop()?;
+                    self.stack.push(value)?;
+                    *self.local(*index)? = value;
+                }
+                Instr::GlobalGet(index) => {
+                    let value = *self
+                        .globals
+                        .get(*index as usize)
+                        .ok_or_else(|| trap("unknown global"))?;
+                    self.stack.push(value)?;
+                }
+                Instr::GlobalSet(index) => {
+                    let value = self.stack.pop()?;
+                    match (
+                        module.globals.get(*index as usize),
+                        self.globals.get_mut(*index as usize),
+                    ) {
+                        (Some(global), Some(slot)) if global.mutable => *slot = value,
+                        _ => return Err(trap("global is not mutable")),
+                    }
+                }
+                Instr::Load(opcode, offset) => {
+                    let address = u64::from(self.stack.pop_i32()?) + u64::from(*offset);
+                    let value = load(&self.memory, *opcode, address)?;
+                    self.stack.push(value)?;
+                }
+                Instr::Store(opcode, offset) => {
+                    let value = self.stack.pop()?;
+                    let address = u64::from(self.stack.pop_i32()?) + u64::from(*offset);
+                    store(&mut self.memory, *opcode, address, value)?;
+                }
+                Instr::MemorySize => self.stack.push(u64::from(self.memory.pages()))?,
+                Instr::MemoryGrow => {
+                    let pages = self.stack.pop_i32()?;
+                    gas.charge(u64::from(pages).saturating_mul(GAS_PER_PAGE))?;
+                    let previous = self.memory.grow(pages).unwrap_or(u32::MAX);
+                    self.stack.push(u64::from(previous))?;
+                }
+                Instr::MemoryCopy => {
+                    let length = u64::from(self.stack.pop_i32()?);
+                    let source = u64::from(self.stack.pop_i32()?);
+                    let destination = u64::from(self.stack.pop_i32()?);
+                    gas.charge_bytes(length)?;
+                    let source = self.memory.range(source, length)?;
+                    self.memory.range(destination, length)?;
+                    self.memory.bytes.copy_within(source, destination as usize);
+                }
+                Instr::MemoryFill => {
+                    let length = u64::from(self.stack.pop_i32()?);
+                    let value = self.stack.pop_i32()? as u8;
+                    let destination = u64::from(self.stack.pop_i32()?);
+                    gas.charge_bytes(length)?;
+                    let range = self.memory.range(destination, length)?;
+                    self.memory.bytes[range]
+                        .iter_mut()
+                        .for_each(|byte| *byte = value);
+                }
+                Instr::Const(value) => self.stack.push(*value)?,
+                Instr::Numeric(opcode) => numeric(&mut self.stack, *opcode)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    fn frame(&mut self) -> &mut Frame {
+        self.frames.last_mut().unwrap()
+    }
+
+    fn local(&mut self, index: u32) -> Result<&mut u64, VmError> {
+        self.frame()
+            .locals
+            .get_mut(index as usize)
+            .ok_or_else(|| trap("unknown local"))
+    }
+
+    fn function_type(&self, index: u32) -> Result<&'a FuncType, VmError> {
+        let module = self.module;
+        let type_index = match module.imports.get(index as usize) {
+            Some(import) => import.type_index,
+            None => {
+                module
+                    .functions
+                    .get(index as usize - module.imports.len())
+                    .ok_or_else(|| trap("unknown function"))?
+                    .type_index
+            }
+        };
+
+        module.func_type(type_index)
+    }
+
+    // The values taken by the block stay in the stack, below them is what the block can't touch
+    fn push_label(
+        &mut self,
+        params: usize,
+        arity: usize,
+        target: usize,
+        is_loop: bool,
+    ) -> Result<(), VmError> {
+        let stack_base = self.frame().stack_base;
+        let height = self
+            .stack
+            .0
+            .len()
+            .checked_sub(params)
+            .filter(|height| *height >= stack_base)
+            .ok_or_else(|| trap("stack underflow"))?;
+
+        self.labels.push(Label {
+            height,
+            arity,
+            target,
+            is_loop,
+        });
+        Ok(())
+    }
+
+    // Branches to the label at "depth" (0 is the innermost one), keeping the values it carries
+    // Branching to a loop starts it again, branching to anything else leaves it
+    fn branch(&mut self, depth: u32) -> Result<(), VmError> {
+        let labels_base = self.frame().labels_base;
+        let open_labels = self.labels.len() - labels_base;
+        let depth = depth as usize;
+        if depth == open_labels {
+            // the body of the function is a block too
+            return self.return_from_function();
+        }
+        if depth > open_labels {
+            return Err(trap("unknown label"));
+        }
+
+        let index = self.labels.len() - 1 - depth;
+        let label = self.labels[index];
+        let values = self.stack.split_off(label.arity)?;
+        if self.stack.0.len() < label.height {
+            return Err(trap("stack underflow"));
+        }
+        self.stack.0.truncate(label.height);
+        self.stack.0.extend(values);
+
+        self.labels
+            .truncate(if label.is_loop { index + 1 } else { index });
+        self.frame().pc = label.target;
+        Ok(())
+    }
+
+    fn call(&mut self, index: u32, host: &mut dyn Host, gas: &mut Gas) -> Result<(), VmError> {
+        let module = self.module;
+        let func_type = self.function_type(index)?;
+        let args = self.stack.split_off(func_type.params.len())?;
+
+        if let Some(import) = module.imports.get(index as usize) {
+            let result = host.call(&import.name, &args, &mut self.memory, gas)?;
+            if !func_type.results.is_empty() {
+                self.stack.push(result.unwrap_or_default())?;
+            }
+            return Ok(());
+        }
+
+        if self.frames.len() >= MAX_CALL_DEPTH {
+            return Err(trap("call stack exhausted"));
+        }
+        let function = index as usize - module.imports.len();
+        let extra_locals = module.functions[function].locals;
+        gas.charge(extra_locals as u64)?;
+
+        let mut locals = args;
+        locals.resize(locals.len() + extra_locals, 0);
+        self.frames.push(Frame {
+            function,
+            pc: 0,
+            locals,
+            stack_base: self.stack.0.len(),
+            labels_base: self.labels.len(),
+            results: func_type.results.len(),
+        });
+        Ok(())
+    }
+
+    fn return_from_function(&mut self) -> Result<(), VmError> {
+        let frame = self.frames.pop().unwrap();
+        let results = self.stack.split_off(frame.results)?;
+        if self.stack.0.len() < frame.stack_base {
+            return Err(trap("stack underflow"));
+        }
+
+        self.stack.0.truncate(frame.stack_base);
+        self.stack.0.extend(results);
+        self.labels.truncate(frame.labels_base);
+        Ok(())
+    }
+}
+
+fn trap(message: &str) -> VmError {
+    VmError::Trap(message.to_string())
+}
+
+fn eval_const(expr: &ConstExpr, globals: &[u64]) -> Result<u64, VmError> {
+    match expr {
+        ConstExpr::Value(value) => Ok(*value),
+        ConstExpr::Global(index) => globals
+            .get(*index as usize)
+            .copied()
+            .ok_or_else(|| VmError::InvalidModule("unknown global".to_string())),
+    }
+}
+
+// Size in bytes, and whether the value is sign extended and 64 bits
+fn access(opcode: u8) -> (u64, bool, bool) {
+    match opcode {
+        0x28 | 0x36 => (4, false, false),
+        0x29 | 0x37 => (8, false, true),
+        0x2c => (1, true, false),
+        0x2d | 0x3a => (1, false, false),
+        0x2e => (2, true, false),
+        0x2f | 0x3b => (2, false, false),
+        0x30 => (1, true, true),
+        0x31 | 0x3c => (1, false, true),
+        0x32 => (2, true, true),
+        0x33 | 0x3d => (2, false, true),
+        0x34 => (4, true, true),
+        _ => (4, false, true), // 0x35 and 0x3e
+    }
+}
+
+fn load(memory: &Memory, opcode: u8, address: u64) -> Result<u64, VmError> {
+    let (size, signed, is_64) = access(opcode);
+    let mut bytes = [0; 8];
+    bytes[..size as usize].copy_from_slice(memory.read(address, size)?);
+
+    let mut value = u64::from_le_bytes(bytes);
+    if signed {
+        let shift = 64 - 8 * size;
+        value = (((value << shift) as i64) >> shift) as u64;
+    }
+    Ok(if is_64 { value } else { value as u32 as u64 })
+}
+
+fn store(memory: &mut Memory, opcode: u8, address: u64, value: u64) -> Result<(), VmError> {
+    let (size, _, _) = access(opcode);
+
+    memory.write(address, &value.to_le_bytes()[..size as usize])
+}
+
+fn numeric(stack: &mut Stack, opcode: u8) -> Result<(), VmError> {
+    let value = match opcode {
+        // i32 tests and comparisons
+        0x45 => u64::from(stack.pop_i32()? == 0),
+        0x46..=0x4f => {
+            let b = stack.pop_i32()?;
+            let a = stack.pop_i32()?;
+            let (sa, sb) = (a as i32, b as i32);
+            u64::from(match opcode {
+                0x46 => a == b,
+                0x47 => a != b,
+                0x48 => sa < sb,
+                0x49 => a < b,
+                0x4a => sa > sb,
+                0x4b => a > b,
+                0x4c => sa <= sb,
+                0x4d => a <= b,
+                0x4e => sa >= sb,
+                _ => a >= b,
+            })
+        }
+        // i64 tests and comparisons
+        0x50 => u64::from(stack.pop()? == 0),
+        0x51..=0x5a => {
+            let b = stack.pop()?;
+            let a = stack.pop()?;
+            let (sa, sb) = (a as i64, b as i64);
+            u64::from(match opcode {
+                0x51 => a == b,
+                0x52 => a != b,
+                0x53 => sa < sb,
+                0x54 => a < b,
+                0x55 => sa > sb,
+                0x56 => a > b,
+                0x57 => sa <= sb,
+                0x58 => a <= b,
+                0x59 => sa >= sb,
+                _ => a >= b,
+            })
+        }
+        // i32 arithmetic
+        0x67 => u64::from(stack.pop_i32()?.leading_zeros()),
+        0x68 => u64::from(stack.pop_i32()?.trailing_zeros()),
+        0x69 => u64::from(stack.pop_i32()?.count_ones()),
+        0x6a..=0x78 => {
+            let b = stack.pop_i32()?;
+            let a = stack.pop_i32()?;
+            u64::from(binary_i32(opcode, a, b)?)
+        }
+        // i64 arithmetic
+        0x79 => u64::from(stack.pop()?.leading_zeros()),
+        0x7a => u64::from(stack.pop()?.trailing_zeros()),
+        0x7b => u64::from(stack.pop()?.count_ones()),
+        0x7c..=0x8a => {
+            let b = stack.pop()?;
+            let a = stack.pop()?;
+            binary_i64(opcode, a, b)?
+        }
+        // conversions and sign extensions
+        0xa7 => u64::from(stack.pop_i32()?),
+        0xac => stack.pop_i32()? as i32 as i64 as u64,
+        0xad => u64::from(stack.pop_i32()?),
+        0xc0 => u64::from(stack.pop_i32()? as u8 as i8 as i32 as u32),
+        0xc1 => u64::from(stack.pop_i32()? as u16 as i16 as i32 as u32),
+        0xc2 => stack.pop()? as u8 as i8 as i64 as u64,
+        0xc3 => stack.pop()? as u16 as i16 as i64 as u64,
+        _ => stack.pop()? as u32 as i32 as i64 as u64, // 0xc4
+    };
+
+    stack.push(value)
+}
+
+fn binary_i32(opcode: u8, a: u32, b: u32) -> Result<u32, VmError> {
+    let (sa, sb) = (a as i32, b as i32);
+    Ok(match opcode {
+        0x6a => a.wrapping_add(b),
+        0x6b => a.wrapping_sub(b),
+        0x6c => a.wrapping_mul(b),
+        0x6d => signed_division(sa, sb, i32::checked_div)? as u32,
+        0x6e => a
+            .checked_div(b)
+            .ok_or_else(|| trap("integer divide by zero"))?,
+        0x6f if sb == 0 => return Err(trap("integer divide by zero")),
+        0x6f => sa.wrapping_rem(sb) as u32,
+        0x70 => a
+            .checked_rem(b)
+            .ok_or_else(|| trap("integer divide by zero"))?,
+        0x71 => a & b,
+        0x72 => a | b,
+        0x73 => a ^ b,
+        0x74 => a.wrapping_shl(b),
+        0x75 => sa.wrapping_shr(b) as u32,
+        0x76 => a.wrapping_shr(b),
+        0x77 => a.rotate_left(b % 32),
+        _ => a.rotate_right(b % 32), // 0x78
+    })
+}
+
+fn binary_i64(opcode: u8, a: u64, b: u64) -> Result<u64, VmError> {
+    let (sa, sb) = (a as i64, b as i64);
+    Ok(match opcode {
+        0x7c => a.wrapping_add(b),
+        0x7d => a.wrapping_sub(b),
+        0x7e => a.wrapping_mul(b),
+        0x7f => signed_division(sa, sb, i64::checked_div)? as u64,
+        0x80 => a
+            .checked_div(b)
+            .ok_or_else(|| trap("integer divide by zero"))?,
+        0x81 if sb == 0 => return Err(trap("integer divide by zero")),
+        0x81 => sa.wrapping_rem(sb) as u64,
+        0x82 => a
+            .checked_rem(b)
+            .ok_or_else(|| trap("integer divide by zero"))?,
+        0x83 => a & b,
+        0x84 => a | b,
+        0x85 => a ^ b,
+        0x86 => a.wrapping_shl(b as u32),
+        0x87 => sa.wrapping_shr(b as u32) as u64,
+        0x88 => a.wrapping_shr(b as u32),
+        0x89 => a.rotate_left((b % 64) as u32),
+        _ => a.rotate_right((b % 64) as u32), // 0x8a
+    })
+}
+
+// The only overflow of a signed division is the minimum value divided by -1
+fn signed_division<T: Default + PartialEq>(
+    a: T,
+    b: T,
+    checked_div: fn(T, T) -> Option<T>,
+) -> Result<T, VmError> {
+    if b == T::default() {
+        return Err(trap("integer divide by zero"));
+    }
+
+    checked_div(a, b).ok_or_else(|| trap("integer overflow"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::ValueType;
+
+    const I32: u8 = 0x7f;
+    const I64: u8 = 0x7e;
+
+    #[test]
+    fn should_run_loops() {
+        // 10! with a loop: acc = 1, n = 10; while n != 0 { acc *= n; n -= 1 }; report(acc)
+        let run = function(
+            &[],
+            &[],
+            &[I64, I64],
+            &[
+                0x42, 0x01, 0x21, 0x00, // acc = 1
+                0x42, 0x0a, 0x21, 0x01, // n = 10
+                0x02, 0x40, 0x03, 0x40, // block, loop
+                0x20, 0x01, 0x50, 0x0d, 0x01, // if n == 0, leave the block
+                0x20, 0x00, 0x20, 0x01, 0x7e, 0x21, 0x00, // acc *= n
+                0x20, 0x01, 0x42, 0x01, 0x7d, 0x21, 0x01, // n -= 1
+                0x0c, 0x00, 0x0b, 0x0b, // continue, end of the loop and the block
+                0x20, 0x00, 0x10, 0x00, // report(acc)
+            ],
+        );
+
+        assert_eq!(run_module(&[run], &[]), Ok(vec![3_628_800]));
+    }
+
+    #[test]
+    fn should_call_functions() {
+        // fib(n) = if n < 2 { n } else { fib(n - 1) + fib(n - 2) }
+        let fib = function(
+            &[I32],
+            &[I64],
+            &[],
+            &[
+                0x20, 0x00, 0x41, 0x02, 0x49, // n < 2
+                0x04, I64, 0x20, 0x00, 0xad, // then n
+                0x05, 0x20, 0x00, 0x41, 0x01, 0x6b, 0x10, 0x02, // else fib(n - 1)
+                0x20, 0x00, 0x41, 0x02, 0x6b, 0x10, 0x02, 0x7c, // + fib(n - 2)
+                0x0b,
+            ],
+        );
+        let run = function(
+            &[],
+            &[],
+            &[],
+            &[
+                0x41, 0x0f, 0x10, 0x02, 0x10, 0x00, // report(fib(15))
+                // the table has every function in order, so fib is the second element
+                0x41, 0x0a, 0x41, 0x01, 0x11, 0x02, 0x00, 0x10, 0x00, // report(table[1](10))
+            ],
+        );
+
+        assert_eq!(run_module(&[run, fib], &[]), Ok(vec![610, 55]));
+    }
+
+    #[test]
+    fn should_branch_out_of_blocks() {
+        // pick(n) returns 100, 200 or 300 for the first, second or default target of a br_table
+        let pick = function(
+            &[I32],
+            &[I64],
+            &[],
+            &[
+                0x02, 0x40, 0x02, 0x40, 0x02, 0x40, // three nested blocks
+                0x20, 0x00, 0x0e, 0x02, 0x00, 0x01, 0x02, 0x0b, // br_table [0, 1] 2
+                0x42, 0xe4, 0x00, 0x0f, 0x0b, // return 100
+                0x42, 0xc8, 0x01, 0x0f, 0x0b, // return 200
+                0x42, 0xac, 0x02, // 300
+            ],
+        );
+        let run = function(
+            &[],
+            &[],
+            &[],
+            &[
+                0x41, 0x00, 0x10, 0x02, 0x10, 0x00, // report(pick(0))
+                0x41, 0x01, 0x10, 0x02, 0x10, 0x00, // report(pick(1))
+                0x41, 0x05, 0x10, 0x02, 0x10, 0x00, // report(pick(5))
+                0x41, 0x00, 0x04, 0x40, 0x00, 0x0b, // an if without else that is skipped
+                0x42, 0x07, 0x42, 0x08, 0x41, 0x00, 0x1b, 0x10,
+                0x00, // report(select(7, 8, 0))
+            ],
+        );
+
+        assert_eq!(run_module(&[run, pick], &[]), Ok(vec![100, 200, 300, 8]));
+    }
+
+    #[test]
+    fn should_access_memory() {
+        let run = function(
+            &[],
+            &[],
+            &[],
+            &[
+                0x41, 0x03, 0x30, 0x00, 0x00, 0x10, 0x00, // report(i64.load8_s(3))
+                0x41, 0x00, 0x35, 0x02, 0x00, 0x10, 0x00, // report(i64.load32_u(0))
+                0x41, 0xe4, 0x00, 0x42, 0x2a, 0x37, 0x03, 0x08, // i64.store(100 + 8, 42)
+                0x41, 0xec, 0x00, 0x29, 0x03, 0x00, 0x10, 0x00, // report(i64.load(108))
+                0x41, 0x01, 0x40, 0x00, 0xad, 0x10, 0x00, // report(memory.grow(1))
+                0x41, 0x01, 0x40, 0x00, 0xad, 0x10,
+                0x00, // report(memory.grow(1)), over the max
+                0x3f, 0x00, 0xad, 0x10, 0x00, // report(memory.size)
+            ],
+        );
+
+        assert_eq!(
+            run_module(&[run], &[0x01, 0x02, 0x03, 0xff]),
+            Ok(vec![u64::MAX, 0xff03_0201, 42, 1, u64::from(u32::MAX), 2])
+        );
+    }
+
+    #[test]
+    fn should_trap() {
+        let cases: &[(&[u8], &str)] = &[
+            (&[0x00], "unreachable"),
+            (
+                &[0x41, 0x01, 0x41, 0x00, 0x6e, 0x1a],
+                "integer divide by zero",
+            ),
+            (
+                &[0x41, 0x80, 0x80, 0x80, 0x80, 0x78, 0x41, 0x7f, 0x6d, 0x1a],
+                "integer overflow",
+            ),
+            (
+                &[0x41, 0x80, 0x80, 0x04, 0x28, 0x02, 0x00, 0x1a],
+                "out of bounds memory access",
+            ),
+            (&[0x1a], "stack underflow"),
+            (&[0x10, 0x01], "call stack exhausted"),
+            // the table has "run" in its first element, which doesn't take an i32
+            (
+                &[0x41, 0x00, 0x41, 0x00, 0x11, 0x02, 0x00],
+                "indirect call type mismatch",
+            ),
+        ];
+
+        for (code, message) in cases {
+            let other = function(&[I32], &[], &[], &[]);
+            let run = function(&[], &[], &[], code);
+            assert_eq!(
+                run_module(&[run, other], &[]),
+                Err(trap(message)),
+                "{:02x?}",
+                code
+            );
+        }
+    }
+
+    #[test]
+    fn should_run_out_of_gas() {
+        let module = Module::parse(&build_module(
+            &[function(&[], &[], &[], &[0x03, 0x40, 0x0c, 0x00, 0x0b])],
+            &[],
+        ))
+        .unwrap();
+
+        let mut gas = Gas::new(1000);
+        let result = execute(&module, "run", &mut TestHost::default(), &mut gas);
+        assert_eq!(result, Err(VmError::OutOfGas));
+        assert_eq!(gas.used(), 1000);
+    }
+
+    #[test]
+    fn should_check_imports_and_exports() {
+        let module = Module::parse(&build_module(&[function(&[], &[], &[], &[])], &[])).unwrap();
+        let mut gas = Gas::new(1000);
+
+        let result = execute(&module, "other", &mut TestHost::default(), &mut gas);
+        assert_eq!(result, Err(VmError::MissingExport("other".to_string())));
+
+        let mut host = TestHost {
+            reports: Vec::new(),
+            provides_report: false,
+        };
+        let result = execute(&module, "run", &mut host, &mut gas);
+        assert_eq!(result, Err(VmError::UnknownImport("report".to_string())));
+    }
+
+    // Host of the test modules, "report" hands a value to the test
+    struct TestHost {
+        reports: Vec<u64>,
+        provides_report: bool,
+    }
+
+    impl Default for TestHost {
+        fn default() -> Self {
+            TestHost {
+                reports: Vec::new(),
+                provides_report: true,
+            }
+        }
+    }
+
+    impl Host for TestHost {
+        fn signature(&self, name: &str) -> Option<FuncType> {
+            Some(FuncType {
+                params: vec![ValueType::I64],
+                results: vec![],
+            })
+            .filter(|_| name == "report" && self.provides_report)
+        }
+
+        fn call(
+            &mut self,
+            _name: &str,
+            args: &[u64],
+            _memory: &mut Memory,
+            _gas: &mut Gas,
+        ) -> Result<Option<u64>, VmError> {
+            self.reports.push(args[0]);
+            Ok(None)
+        }
+    }
+
+    struct TestFunction {
+        params: Vec<u8>,
+        results: Vec<u8>,
+        locals: Vec<u8>,
+        code: Vec<u8>,
+    }
+
+    fn function(params: &[u8], results: &[u8], locals: &[u8], code: &[u8]) -> TestFunction {
+        TestFunction {
+            params: params.to_vec(),
+            results: results.to_vec(),
+            locals: locals.to_vec(),
+            code: code.to_vec(),
+        }
+    }
+
+    fn run_module(functions: &[TestFunction], data: &[u8]) -> Result<Vec<u64>, VmError> {
+        let module = Module::parse(&build_module(functions, data)).unwrap();
+        let mut host = TestHost::default();
+
+        execute(&module, "run", &mut host, &mut Gas::new(1_000_000))?;
+        Ok(host.reports)
+    }
+
+    // Encodes a module that imports "report" (function 0) and has the given functions after it,
+    // the first one exported as "run". Function "i" has type "i", and it's in the element "i - 1"
+    // of the table. The memory has one page, up to two, with the data at address 0
+    fn build_module(functions: &[TestFunction], data: &[u8]) -> Vec<u8> {
+        let count = functions.len() as u64;
+        let mut types = vec![[vec![0x60], vector(&[vec![I64]]), vector(&[])].concat()];
+        for function in functions {
+            let params: Vec<Vec<u8>> = function.params.iter().map(|t| vec![*t]).collect();
+            let results: Vec<Vec<u8>> = function.results.iter().map(|t| vec![*t]).collect();
+            types.push([vec![0x60], vector(&params), vector(&results)].concat());
+        }
+        let bodies: Vec<Vec<u8>> = functions
+            .iter()
+            .map(|function| {
+                let locals: Vec<Vec<u8>> = function.locals.iter().map(|t| vec![0x01, *t]).collect();
+                let body = [vector(&locals), function.code.clone(), vec![0x0b]].concat();
+                [leb(body.len() as u64), body].concat()
+            })
+            .collect();
+
+        [
+            b"\0asm".to_vec(),
+            vec![0x01, 0x00, 0x00, 0x00],
+            section(1, &types),
+            section(
+                2,
+                &[[name("env"), name("report"), vec![0x00, 0x00]].concat()],
+            ),
+            section(3, &(1..=count).map(leb).collect::<Vec<_>>()),
+            section(4, &[[vec![0x70, 0x00], leb(count)].concat()]),
+            section(5, &[vec![0x01, 0x01, 0x02]]),
+            section(7, &[[name("run"), vec![0x00, 0x01]].concat()]),
+            section(
+                9,
+                &[[
+                    vec![0x00, 0x41, 0x00, 0x0b],
+                    vector(&(1..=count).map(leb).collect::<Vec<_>>()),
+                ]
+                .concat()],
+            ),
+            section(10, &bodies),
+            section(
+                11,
+                &[[
+                    vec![0x00, 0x41, 0x00, 0x0b],
+                    leb(data.len() as u64),
+                    data.to_vec(),
+                ]
+                .concat()],
+            ),
+        ]
+        .concat()
+    }
+
+    fn section(id: u8, items: &[Vec<u8>]) -> Vec<u8> {
+        let content = vector(items);
+        [vec![id], leb(content.len() as u64), content].concat()
+    }
+
+    fn vector(items: &[Vec<u8>]) -> Vec<u8> {
+        [leb(items.len() as u64), items.concat()].concat()
+    }
+
+    fn name(name: &str) -> Vec<u8> {
+        [leb(name.len() as u64), name.as_bytes().to_vec()].concat()
+    }
+
+    fn leb(mut value: u64) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                bytes.push(byte);
+                return bytes;
+            }
+            bytes.push(byte | 0x80);
+        }
+    }
+}
@@ -0,0 +1,764 @@
+use std::{collections::HashMap, convert::TryFrom};
+
+use super::VmError;
+
+// Max number of locals of a function, besides its parameters
+const MAX_LOCALS: u32 = 4096;
+
+// Max number of elements of the table of functions
+pub const MAX_TABLE_SIZE: u32 = 65536;
+
+// Only integers are supported: floating point results could differ between nodes (e.g. NaNs)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ValueType {
+    I32,
+    I64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FuncType {
+    pub params: Vec<ValueType>,
+    pub results: Vec<ValueType>,
+}
+
+// Size of a memory or a table, in pages or elements
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    pub min: u32,
+    pub max: Option<u32>,
+}
+
+// Initial value of a global or the offset of a segment
+#[derive(Debug, Clone, Copy)]
+pub enum ConstExpr {
+    Value(u64),
+    Global(u32),
+}
+
+#[derive(Debug, Clone)]
+pub struct Global {
+    pub mutable: bool,
+    pub init: ConstExpr,
+}
+
+// Function imported from the host, by its name in the "env" module
+#[derive(Debug, Clone)]
+pub struct Import {
+    pub name: String,
+    pub type_index: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct Function {
+    pub type_index: u32,
+    // number of locals besides the parameters, all of them start at zero
+    pub locals: usize,
+    pub code: Vec<Instr>,
+}
+
+// Functions placed in the table at instantiation, for indirect calls
+#[derive(Debug, Clone)]
+pub struct ElementSegment {
+    pub offset: ConstExpr,
+    pub functions: Vec<u32>,
+}
+
+// Bytes copied to the memory at instantiation
+#[derive(Debug, Clone)]
+pub struct DataSegment {
+    pub offset: ConstExpr,
+    pub bytes: Vec<u8>,
+}
+
+// Decoded instructions, with the positions of the ends of the blocks already resolved
+// so branches don't need to look for them while running
+#[derive(Debug, Clone, PartialEq)]
+pub enum Instr {
+    Unreachable,
+    Nop,
+    Block {
+        params: usize,
+        results: usize,
+        end: usize,
+    },
+    Loop {
+        params: usize,
+    },
+    If {
+        params: usize,
+        results: usize,
+        else_at: Option<usize>,
+        end: usize,
+    },
+    Else {
+        end: usize,
+    },
+    End,
+    Br(u32),
+    BrIf(u32),
+    BrTable(Vec<u32>, u32),
+    Return,
+    Call(u32),
+    CallIndirect(u32),
+    Drop,
+    Select,
+    LocalGet(u32),
+    LocalSet(u32),
+    LocalTee(u32),
+    GlobalGet(u32),
+    GlobalSet(u32),
+    // the opcode tells the size and the extension, the offset is added to the address
+    Load(u8, u32),
+    Store(u8, u32),
+    MemorySize,
+    MemoryGrow,
+    MemoryCopy,
+    MemoryFill,
+    Const(u64),
+    // integer operations without immediates, by their opcode
+    Numeric(u8),
+}
+
+// A WebAssembly module, as defined by the binary format of the 1.0 specification
+// Only the integer subset is supported: floating point types and instructions are rejected,
+// and so are the features that a contract doesn't need (like imported memories or start functions)
+#[derive(Debug, Clone, Default)]
+pub struct Module {
+    pub types: Vec<FuncType>,
+    pub imports: Vec<Import>,
+    pub functions: Vec<Function>,
+    pub table: Option<Limits>,
+    pub memory: Option<Limits>,
+    pub globals: Vec<Global>,
+    // exported functions by name, other exports are ignored
+    pub exports: HashMap<String, u32>,
+    pub elements: Vec<ElementSegment>,
+    pub data: Vec<DataSegment>,
+}
+
+impl Module {
+    pub fn parse(bytes: &[u8]) -> Result<Module, VmError> {
+        let mut reader = Reader::new(bytes);
+        if reader.bytes(4)? != b"\0asm" {
+            return Err(invalid("missing the magic number"));
+        }
+        if reader.bytes(4)? != [1, 0, 0, 0] {
+            return Err(invalid("unsupported version"));
+        }
+
+        let mut module = Module::default();
+        let mut function_types = Vec::new();
+        while !reader.is_empty() {
+            let id = reader.byte()?;
+            let size = reader.u32()? as usize;
+            let mut section = Reader::new(reader.bytes(size)?);
+
+            match id {
+                0 => {} // custom sections (e.g. names) don't change the behavior
+                1 => module.types = section.vec(Reader::func_type)?,
+                2 => module.imports = section.vec(Reader::import)?,
+                3 => function_types = section.vec(Reader::u32)?,
+                4 => module.table = section.single(Reader::table)?,
+                5 => module.memory = section.single(Reader::limits)?,
+                6 => module.globals = section.vec(Reader::global)?,
+                7 => {
+                    for (name, index) in section.vec(Reader::export)?.into_iter().flatten() {
+                        module.exports.insert(name, index);
+                    }
+                }
+                8 => return Err(invalid("start functions are not supported")),
+                9 => module.elements = section.vec(Reader::element)?,
+                10 => {
+                    let bodies = section.vec(|reader| {
+                        let size = reader.u32()? as usize;
+                        Ok(Reader::new(reader.bytes(size)?))
+                    })?;
+                    if bodies.len() != function_types.len() {
+                        return Err(invalid("the number of functions and bodies don't match"));
+                    }
+                    // functions can call the ones after them, which are not decoded yet
+                    let function_count = (module.imports.len() + bodies.len()) as u32;
+                    for (type_index, mut body) in function_types.iter().zip(bodies) {
+                        let function = body.function(&module, *type_index, function_count)?;
+                        module.functions.push(function);
+                    }
+                }
+                11 => module.data = section.vec(Reader::data)?,
+                12 => {} // the data count is only needed to validate in a single pass
+                _ => return Err(invalid(&format!("unknown section {}", id))),
+            }
+
+            if !section.is_empty() {
+                return Err(invalid(&format!(
+                    "section {} is longer than its contents",
+                    id
+                )));
+            }
+        }
+
+        if module.functions.len() != function_types.len() {
+            return Err(invalid("the number of functions and bodies don't match"));
+        }
+        for import in module.imports.iter() {
+            module.func_type(import.type_index)?;
+        }
+        let function_count = module.function_count();
+        if module
+            .exports
+            .values()
+            .any(|index| *index >= function_count)
+        {
+            return Err(invalid("exported function out of range"));
+        }
+
+        Ok(module)
+    }
+
+    // Imported functions come first in the index space, then the ones of the module
+    pub fn function_count(&self) -> u32 {
+        (self.imports.len() + self.functions.len()) as u32
+    }
+
+    pub fn func_type(&self, index: u32) -> Result<&FuncType, VmError> {
+        self.types
+            .get(index as usize)
+            .ok_or_else(|| invalid("type out of range"))
+    }
+
+    pub fn has_export(&self, name: &str) -> bool {
+        self.exports.contains_key(name)
+    }
+}
+
+fn invalid(message: &str) -> VmError {
+    VmError::InvalidModule(message.to_string())
+}
+
+// Decodes the primitive values of the binary format, failing instead of reading past the end
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Reader<'a> {
+        Reader { bytes, position: 0 }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.position == self.bytes.len()
+    }
+
+    fn byte(&mut self) -> Result<u8, VmError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn bytes(&mut self, length: usize) -> Result<&'a [u8], VmError> {
+        if self.bytes.len() - self.position < length {
+            return Err(invalid("unexpected end"));
+        }
+
+        let bytes = &self.bytes[self.position..self.position + length];
+        self.position += length;
+        Ok(bytes)
+    }
+
+    // Integers are encoded in LEB128, with at most the bytes needed for their size
+    fn unsigned(&mut self, bits: u32) -> Result<u64, VmError> {
+        let mut result = 0;
+        for i in 0..bits.div_ceil(7) {
+            let byte = self.byte()?;
+            result |= u64::from(byte & 0x7f) << (7 * i);
+            if byte & 0x80 == 0 {
+                if bits < 64 && result >> bits != 0 {
+                    return Err(invalid("integer too large"));
+                }
+                return Ok(result);
+            }
+        }
+
+        Err(invalid("integer representation too long"))
+    }
+
+    fn signed(&mut self, bits: u32) -> Result<i64, VmError> {
+        let mut result = 0;
+        let mut shift = 0;
+        for _ in 0..bits.div_ceil(7) {
+            let byte = self.byte()?;
+            result |= i64::from(byte & 0x7f) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                if shift < 64 && byte & 0x40 != 0 {
+                    result |= -1 << shift;
+                }
+                let limit = 1i128 << (bits - 1);
+                if i128::from(result) < -limit || i128::from(result) >= limit {
+                    return Err(invalid("integer too large"));
+                }
+                return Ok(result);
+            }
+        }
+
+        Err(invalid("integer representation too long"))
+    }
+
+    fn u32(&mut self) -> Result<u32, VmError> {
+        Ok(self.unsigned(32)? as u32)
+    }
+
+    fn name(&mut self) -> Result<String, VmError> {
+        let length = self.u32()? as usize;
+        String::from_utf8(self.bytes(length)?.to_vec()).map_err(|_| invalid("invalid name"))
+    }
+
+    fn vec<T>(
+        &mut self,
+        mut item: impl FnMut(&mut Self) -> Result<T, VmError>,
+    ) -> Result<Vec<T>, VmError> {
+        let count = self.u32()?;
+        // every item takes at least a byte, so a bogus count can't make us allocate too much
+        if count as usize > self.bytes.len() - self.position {
+            return Err(invalid("unexpected end"));
+        }
+
+        (0..count).map(|_| item(self)).collect()
+    }
+
+    // Sections of things that a module can have at most one of (the table and the memory)
+    fn single<T>(
+        &mut self,
+        item: impl FnMut(&mut Self) -> Result<T, VmError>,
+    ) -> Result<Option<T>, VmError> {
+        let mut items = self.vec(item)?;
+        if items.len() > 1 {
+            return Err(invalid("only one table and one memory are supported"));
+        }
+
+        Ok(items.pop())
+    }
+
+    fn value_type(&mut self) -> Result<ValueType, VmError> {
+        match self.byte()? {
+            0x7f => Ok(ValueType::I32),
+            0x7e => Ok(ValueType::I64),
+            0x7d | 0x7c => Err(invalid("floating point numbers are not supported")),
+            _ => Err(invalid("unsupported value type")),
+        }
+    }
+
+    fn func_type(&mut self) -> Result<FuncType, VmError> {
+        if self.byte()? != 0x60 {
+            return Err(invalid("invalid function type"));
+        }
+
+        Ok(FuncType {
+            params: self.vec(Reader::value_type)?,
+            results: self.vec(Reader::value_type)?,
+        })
+    }
+
+    fn import(&mut self) -> Result<Import, VmError> {
+        let module = self.name()?;
+        let name = self.name()?;
+        if self.byte()? != 0x00 {
+            return Err(invalid("only functions can be imported"));
+        }
+        if module != "env" {
+            return Err(VmError::UnknownImport(format!("{}.{}", module, name)));
+        }
+
+        Ok(Import {
+            name,
+            type_index: self.u32()?,
+        })
+    }
+
+    fn limits(&mut self) -> Result<Limits, VmError> {
+        match self.byte()? {
+            0x00 => Ok(Limits {
+                min: self.u32()?,
+                max: None,
+            }),
+            0x01 => Ok(Limits {
+                min: self.u32()?,
+                max: Some(self.u32()?),
+            }),
+            _ => Err(invalid("invalid limits")),
+        }
+    }
+
+    fn table(&mut self) -> Result<Limits, VmError> {
+        if self.byte()? != 0x70 {
+            return Err(invalid("only tables of functions are supported"));
+        }
+
+        let limits = self.limits()?;
+        if limits.min > MAX_TABLE_SIZE {
+            return Err(invalid("table too large"));
+        }
+        Ok(limits)
+    }
+
+    fn global(&mut self) -> Result<Global, VmError> {
+        self.value_type()?;
+        let mutable = match self.byte()? {
+            0x00 => false,
+            0x01 => true,
+            _ => return Err(invalid("invalid mutability")),
+        };
+
+        Ok(Global {
+            mutable,
+            init: self.const_expr()?,
+        })
+    }
+
+    // Only functions are exported by index, the rest of the exports are skipped
+    fn export(&mut self) -> Result<Option<(String, u32)>, VmError> {
+        let name = self.name()?;
+        let kind = self.byte()?;
+        let index = self.u32()?;
+
+        Ok(if kind == 0x00 {
+            Some((name, index))
+        } else {
+            None
+        })
+    }
+
+    fn element(&mut self) -> Result<ElementSegment, VmError> {
+        if self.u32()? != 0 {
+            return Err(invalid("only active element segments are supported"));
+        }
+
+        Ok(ElementSegment {
+            offset: self.const_expr()?,
+            functions: self.vec(Reader::u32)?,
+        })
+    }
+
+    fn data(&mut self) -> Result<DataSegment, VmError> {
+        match self.u32()? {
+            0 => {}
+            2 if self.u32()? == 0 => {}
+            _ => return Err(invalid("only active data segments are supported")),
+        }
+
+        let offset = self.const_expr()?;
+        let length = self.u32()? as usize;
+        Ok(DataSegment {
+            offset,
+            bytes: self.bytes(length)?.to_vec(),
+        })
+    }
+
+    fn const_expr(&mut self) -> Result<ConstExpr, VmError> {
+        let expr = match self.byte()? {
+            0x41 => ConstExpr::Value(self.signed(32)? as i32 as u32 as u64),
+            0x42 => ConstExpr::Value(self.signed(64)? as u64),
+            0x23 => ConstExpr::Global(self.u32()?),
+            _ => return Err(invalid("unsupported constant expression")),
+        };
+
+        if self.byte()? != 0x0b {
+            return Err(invalid("unsupported constant expression"));
+        }
+        Ok(expr)
+    }
+
+    // Number of values taken and returned by a block
+    fn block_type(&mut self, module: &Module) -> Result<(usize, usize), VmError> {
+        match self.bytes.get(self.position) {
+            Some(0x40) => {
+                self.position += 1;
+                Ok((0, 0))
+            }
+            Some(0x7f) | Some(0x7e) | Some(0x7d) | Some(0x7c) => {
+                self.value_type()?;
+                Ok((0, 1))
+            }
+            _ => {
+                let index =
+                    u32::try_from(self.signed(33)?).map_err(|_| invalid("invalid block type"))?;
+                let func_type = module.func_type(index)?;
+                Ok((func_type.params.len(), func_type.results.len()))
+            }
+        }
+    }
+
+    fn function(
+        &mut self,
+        module: &Module,
+        type_index: u32,
+        function_count: u32,
+    ) -> Result<Function, VmError> {
+        module.func_type(type_index)?;
+
+        let mut locals: u32 = 0;
+        for (count, _) in self.vec(|reader| Ok((reader.u32()?, reader.value_type()?)))? {
+            locals = locals.saturating_add(count);
+        }
+        if locals > MAX_LOCALS {
+            return Err(invalid("too many locals"));
+        }
+
+        let code = self.code(module, function_count)?;
+        if !self.is_empty() {
+            return Err(invalid("code after the end of a function"));
+        }
+
+        Ok(Function {
+            type_index,
+            locals: locals as usize,
+            code,
+        })
+    }
+
+    // Decodes the instructions of a function body until its final "end"
+    // Blocks are matched with their "else" and "end" as we go, using a stack of the open ones
+    fn code(&mut self, module: &Module, function_count: u32) -> Result<Vec<Instr>, VmError> {
+        let mut code = Vec::new();
+        let mut open_blocks: Vec<usize> = Vec::new();
+
+        loop {
+            let position = code.len();
+            let opcode = self.byte()?;
+            let instr = match opcode {
+                0x00 => Instr::Unreachable,
+                0x01 => Instr::Nop,
+                0x02 => {
+                    let (params, results) = self.block_type(module)?;
+                    open_blocks.push(position);
+                    Instr::Block {
+                        params,
+                        results,
+                        end: 0,
+                    }
+                }
+                0x03 => {
+                    let (params, _) = self.block_type(module)?;
+                    open_blocks.push(position);
+                    Instr::Loop { params }
+                }
+                0x04 => {
+                    let (params, results) = self.block_type(module)?;
+                    open_blocks.push(position);
+                    Instr::If {
+                        params,
+                        results,
+                        else_at: None,
+                        end: 0,
+                    }
+                }
+                0x05 => {
+                    match open_blocks.last().map(|start| &mut code[*start]) {
+                        Some(Instr::If { else_at, .. }) if else_at.is_none() => {
+                            *else_at = Some(position);
+                        }
+                        _ => return Err(invalid("else without if")),
+                    }
+                    Instr::Else { end: 0 }
+                }
+                0x0b => {
+                    let start = match open_blocks.pop() {
+                        Some(start) => start,
+                        None => {
+                            // the end of the function itself
+                            code.push(Instr::End);
+                            return Ok(code);
+                        }
+                    };
+                    if let Instr::Block { end, .. } | Instr::If { end, .. } = &mut code[start] {
+                        *end = position;
+                    }
+                    if let Instr::If {
+                        else_at: Some(else_at),
+                        ..
+                    } = code[start]
+                    {
+                        code[else_at] = Instr::Else { end: position };
+                    }
+                    Instr::End
+                }
+                0x0c => Instr::Br(self.u32()?),
+                0x0d => Instr::BrIf(self.u32()?),
+                0x0e => {
+                    let labels = self.vec(Reader::u32)?;
+                    Instr::BrTable(labels, self.u32()?)
+                }
+                0x0f => Instr::Return,
+                0x10 => {
+                    let index = self.u32()?;
+                    if index >= function_count {
+                        return Err(invalid("called function out of range"));
+                    }
+                    Instr::Call(index)
+                }
+                0x11 => {
+                    let type_index = self.u32()?;
+                    module.func_type(type_index)?;
+                    if self.byte()? != 0x00 {
+                        return Err(invalid("only one table is supported"));
+                    }
+                    Instr::CallIndirect(type_index)
+                }
+                0x1a => Instr::Drop,
+                0x1b => Instr::Select,
+                0x1c => {
+                    self.vec(Reader::value_type)?;
+                    Instr::Select
+                }
+                0x20 => Instr::LocalGet(self.u32()?),
+                0x21 => Instr::LocalSet(self.u32()?),
+                0x22 => Instr::LocalTee(self.u32()?),
+                0x23 => Instr::GlobalGet(self.u32()?),
+                0x24 => Instr::GlobalSet(self.u32()?),
+                0x28 | 0x29 | 0x2c..=0x35 => {
+                    let _align = self.u32()?;
+                    Instr::Load(opcode, self.u32()?)
+                }
+                0x36 | 0x37 | 0x3a..=0x3e => {
+                    let _align = self.u32()?;
+                    Instr::Store(opcode, self.u32()?)
+                }
+                0x3f | 0x40 => {
+                    if self.byte()? != 0x00 {
+                        return Err(invalid("only one memory is supported"));
+                    }
+                    if opcode == 0x3f {
+                        Instr::MemorySize
+                    } else {
+                        Instr::MemoryGrow
+                    }
+                }
+                0x41 => Instr::Const(self.signed(32)? as i32 as u32 as u64),
+                0x42 => Instr::Const(self.signed(64)? as u64),
+                0x45..=0x5a | 0x67..=0x8a | 0xa7 | 0xac | 0xad | 0xc0..=0xc4 => {
+                    Instr::Numeric(opcode)
+                }
+                0xfc => match self.u32()? {
+                    10 if self.bytes(2)? == [0, 0] => Instr::MemoryCopy,
+                    11 if self.byte()? == 0 => Instr::MemoryFill,
+                    _ => return Err(invalid("unsupported instruction")),
+                },
+                0x2a
+                | 0x2b
+                | 0x38
+                | 0x39
+                | 0x43
+                | 0x44
+                | 0x5b..=0x66
+                | 0x8b..=0xa6
+                | 0xa8..=0xab
+                | 0xae..=0xbf => {
+                    return Err(invalid("floating point instructions are not supported"));
+                }
+                _ => return Err(invalid(&format!("unsupported instruction {:#04x}", opcode))),
+            };
+
+            code.push(instr);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: [u8; 8] = [0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00];
+
+    #[test]
+    fn should_decode_leb128_integers() {
+        assert_eq!(Reader::new(&[0xe5, 0x8e, 0x26]).u32(), Ok(624_485));
+        assert_eq!(Reader::new(&[0xc0, 0xbb, 0x78]).signed(32), Ok(-123_456));
+        assert_eq!(Reader::new(&[0x7f]).signed(64), Ok(-1));
+
+        // more bytes than the size needs, or a value that doesn't fit in it
+        let too_long = [0x80, 0x80, 0x80, 0x80, 0x80, 0x00];
+        assert!(Reader::new(&too_long).u32().is_err());
+        assert!(Reader::new(&[0xff, 0xff, 0xff, 0xff, 0x7f]).u32().is_err());
+        assert!(Reader::new(&[0x80]).u32().is_err());
+    }
+
+    #[test]
+    fn should_resolve_the_ends_of_blocks() {
+        // block, i32.const 1, if, nop, else, nop, end, end
+        let code = [
+            0x00, 0x02, 0x40, 0x41, 0x01, 0x04, 0x40, 0x01, 0x05, 0x01, 0x0b, 0x0b, 0x0b,
+        ];
+        let module = Module::parse(&module_with_code(&code)).unwrap();
+
+        assert_eq!(
+            module.functions[0].code,
+            vec![
+                Instr::Block {
+                    params: 0,
+                    results: 0,
+                    end: 7
+                },
+                Instr::Const(1),
+                Instr::If {
+                    params: 0,
+                    results: 0,
+                    else_at: Some(4),
+                    end: 6
+                },
+                Instr::Nop,
+                Instr::Else { end: 6 },
+                Instr::Nop,
+                Instr::End,
+                Instr::End,
+                Instr::End,
+            ]
+        );
+    }
+
+    #[test]
+    fn should_reject_floating_point() {
+        // a function type with an f32 parameter
+        let module = [&HEADER[..], &[0x01, 0x05, 0x01, 0x60, 0x01, 0x7d, 0x00]].concat();
+        assert_eq!(
+            Module::parse(&module).unwrap_err(),
+            invalid("floating point numbers are not supported")
+        );
+
+        // f32.const 1.0, drop
+        let code = [0x00, 0x43, 0x00, 0x00, 0x80, 0x3f, 0x1a, 0x0b];
+        assert_eq!(
+            Module::parse(&module_with_code(&code)).unwrap_err(),
+            invalid("floating point instructions are not supported")
+        );
+    }
+
+    #[test]
+    fn should_reject_invalid_modules() {
+        assert!(Module::parse(b"\0elf\x01\0\0\0").is_err());
+
+        // the code ends in the middle of a block
+        let code = [0x00, 0x02, 0x40, 0x0b];
+        assert_eq!(
+            Module::parse(&module_with_code(&code)).unwrap_err(),
+            invalid("unexpected end")
+        );
+
+        // a call to a function that doesn't exist
+        let code = [0x00, 0x10, 0x01, 0x0b];
+        assert_eq!(
+            Module::parse(&module_with_code(&code)).unwrap_err(),
+            invalid("called function out of range")
+        );
+    }
+
+    // Module with a single function, without parameters or results, and the given body
+    fn module_with_code(body: &[u8]) -> Vec<u8> {
+        let code = [&[0x01, body.len() as u8][..], body].concat();
+        [
+            &HEADER[..],
+            &[0x01, 0x04, 0x01, 0x60, 0x00, 0x00],
+            &[0x03, 0x02, 0x01, 0x00],
+            &[0x0a, code.len() as u8],
+            &code,
+        ]
+        .concat()
+    }
+}
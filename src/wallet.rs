@@ -0,0 +1,378 @@
+use std::str::FromStr;
+
+use anyhow::Result;
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::{
+    model::{is_multisig_address, Blockchain, MultiSigError, Transaction, TransactionPool},
+    util::Config,
+};
+
+mod address;
+
+pub use address::{decode_address, encode_address, Address, AddressError};
+
+// Error types to return when the wallet is misconfigured or rejects a transaction
+#[derive(Error, PartialEq, Debug)]
+pub enum WalletError {
+    #[error("Unknown wallet mode `{0}`")]
+    UnknownMode(String),
+
+    #[error(
+        "Invalid watched address `{0}`, it must be a hex-encoded ed25519 public key, a multisig address or their checksummed form"
+    )]
+    InvalidAddress(String),
+
+    #[error("Transaction must be signed by the sender outside of the node")]
+    MissingSignature,
+
+    #[error("Invalid transaction signature")]
+    InvalidSignature,
+
+    #[error("Invalid multisig transaction: {0}")]
+    InvalidMultiSig(MultiSigError),
+}
+
+// How the node handles the keys of the wallet
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WalletMode {
+    // Transactions are accepted as they are, signed or not
+    Hot,
+    // Only viewing keys (addresses) are on the node, so every transaction
+    // must be signed outside of the node by the sender
+    Cold,
+}
+
+impl FromStr for WalletMode {
+    type Err = WalletError;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.trim() {
+            "hot" => Ok(WalletMode::Hot),
+            "cold" => Ok(WalletMode::Cold),
+            _ => Err(WalletError::UnknownMode(name.to_string())),
+        }
+    }
+}
+
+// Amounts received and sent by a watched address in the blockchain
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AddressBalance {
+    pub address: String,
+    pub received: u64,
+    pub sent: u64,
+}
+
+impl AddressBalance {
+    // Amounts of the address in the blockchain, which keeps them up to date with every block
+    pub fn confirmed(address: &str, blockchain: &Blockchain) -> AddressBalance {
+        let amounts = blockchain.get_account(address);
+        AddressBalance {
+            address: address.to_string(),
+            received: amounts.received,
+            sent: amounts.sent,
+        }
+    }
+
+    // Amounts of the address up to the block "last_index", e.g. the safe one
+    pub fn confirmed_at(address: &str, blockchain: &Blockchain, last_index: u64) -> AddressBalance {
+        let amounts = blockchain.get_account_at(address, last_index);
+        AddressBalance {
+            address: address.to_string(),
+            received: amounts.received,
+            sent: amounts.sent,
+        }
+    }
+
+    // Count the amount of a transaction, if the address is its recipient or its sender
+    // Pending transactions may not fit with each other (the miner leaves them out), so it saturates
+    pub fn add_transaction(&mut self, transaction: &Transaction) {
+        if transaction.recipient == self.address {
+            self.received = self.received.saturating_add(transaction.amount);
+        }
+        if transaction.sender == self.address {
+            self.sent = self.sent.saturating_add(transaction.amount);
+        }
+    }
+
+    // Amount available to the address, it's negative if it sent more than it received
+    // as the node does not check the funds of the senders
+    pub fn balance(&self) -> i128 {
+        self.received as i128 - self.sent as i128
+    }
+}
+
+// Wallet of the node, holding only the viewing keys (i.e. the public keys) of the watched addresses
+#[derive(Debug, Clone)]
+pub struct Wallet {
+    mode: WalletMode,
+    addresses: Vec<String>,
+}
+
+impl Wallet {
+    // Addresses can be given in their checksummed form, the wallet keeps the raw one of transactions
+    pub fn new(mode: WalletMode, addresses: Vec<String>) -> Result<Wallet> {
+        let addresses = addresses
+            .iter()
+            .map(|address| decode_address(address))
+            .collect::<Result<Vec<String>, AddressError>>()?;
+        for address in addresses.iter() {
+            match hex::decode(address) {
+                Ok(public_key) if public_key.len() == 32 => {}
+                _ if is_multisig_address(address) => {}
+                _ => return Err(WalletError::InvalidAddress(address.to_string()).into()),
+            }
+        }
+
+        Ok(Wallet { mode, addresses })
+    }
+
+    pub fn from_config(config: &Config) -> Result<Wallet> {
+        let mode = config.wallet_mode.parse::<WalletMode>()?;
+        let addresses = config
+            .wallet_addresses
+            .iter()
+            .map(|address| address.trim().to_string())
+            .collect();
+
+        Wallet::new(mode, addresses)
+    }
+
+    pub fn mode(&self) -> WalletMode {
+        self.mode
+    }
+
+    // Check if a transaction can be accepted by the node, in the chain with the given id
+    // Signatures are always verified if present, and mandatory in cold mode
+    // Public keys and multisig addresses always need the signatures of their owners, as blocks need them too
+    pub fn check_transaction(&self, transaction: &Transaction, chain_id: &str) -> Result<()> {
+        if let Some(multisig) = &transaction.multisig {
+            return multisig
+                .verify(&transaction.sender, &transaction.message(chain_id))
+                .map_err(|error| WalletError::InvalidMultiSig(error).into());
+        }
+
+        match (&transaction.signature, self.mode) {
+            (None, WalletMode::Hot) if !transaction.needs_signature() => Ok(()),
+            (None, _) => Err(WalletError::MissingSignature.into()),
+            (Some(_), _) if transaction.has_valid_signature(chain_id) => Ok(()),
+            (Some(_), _) => Err(WalletError::InvalidSignature.into()),
+        }
+    }
+
+    // Calculate the amounts received and sent by each watched address
+    pub fn balances(&self, blockchain: &Blockchain) -> Vec<AddressBalance> {
+        self.addresses
+            .iter()
+            .map(|address| AddressBalance::confirmed(address, blockchain))
+            .collect()
+    }
+}
+
+// Calculate the amounts received and sent by any address, watched or not
+// Returns the confirmed amounts (the ones in the blockchain) and the ones including the pool
+pub fn address_balance(
+    address: &str,
+    blockchain: &Blockchain,
+    pool: &TransactionPool,
+) -> (AddressBalance, AddressBalance) {
+    let confirmed = AddressBalance::confirmed(address, blockchain);
+
+    let mut pending = confirmed.clone();
+    for transaction in pool.get_all().iter() {
+        pending.add_transaction(transaction);
+    }
+
+    (confirmed, pending)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        consensus::ProofOfWork,
+        model::{Block, Blockchain, MultiSig, DEFAULT_CHAIN_ID},
+    };
+
+    const CHAIN: &str = DEFAULT_CHAIN_ID;
+
+    #[test]
+    fn should_parse_wallet_modes() {
+        assert_eq!("hot".parse::<WalletMode>(), Ok(WalletMode::Hot));
+        assert_eq!("cold".parse::<WalletMode>(), Ok(WalletMode::Cold));
+        assert!("warm".parse::<WalletMode>().is_err());
+    }
+
+    #[test]
+    fn should_only_watch_valid_addresses() {
+        assert!(Wallet::new(WalletMode::Cold, vec![hex::encode([1; 32])]).is_ok());
+
+        let result = Wallet::new(WalletMode::Cold, vec!["alice".to_string()]);
+        assert_err(
+            result.map(|_| ()),
+            WalletError::InvalidAddress("alice".to_string()),
+        );
+
+        // checksummed addresses are watched by their raw form
+        let address = Address::Key([1; 32]).to_string();
+        let wallet = Wallet::new(WalletMode::Cold, vec![address]).unwrap();
+        assert_eq!(wallet.addresses, vec![hex::encode([1; 32])]);
+    }
+
+    #[test]
+    fn should_require_signatures_in_cold_mode() {
+        let hot_wallet = Wallet::new(WalletMode::Hot, Vec::new()).unwrap();
+        let cold_wallet = Wallet::new(WalletMode::Cold, Vec::new()).unwrap();
+
+        // unsigned transactions are only accepted in hot mode
+        let mut transaction = create_transaction("1", "2", 3);
+        assert!(hot_wallet.check_transaction(&transaction, CHAIN).is_ok());
+        assert_err(
+            cold_wallet.check_transaction(&transaction, CHAIN),
+            WalletError::MissingSignature,
+        );
+
+        // transactions signed by the sender are always accepted
+        transaction.sign(&[1; 32], CHAIN);
+        assert!(hot_wallet.check_transaction(&transaction, CHAIN).is_ok());
+        assert!(cold_wallet.check_transaction(&transaction, CHAIN).is_ok());
+
+        // unless they were signed for another chain
+        assert_err(
+            hot_wallet.check_transaction(&transaction, "testnet"),
+            WalletError::InvalidSignature,
+        );
+
+        // without the signature, not even hot wallets take it, as the sender is a public key
+        let signature = transaction.signature.take();
+        assert_err(
+            hot_wallet.check_transaction(&transaction, CHAIN),
+            WalletError::MissingSignature,
+        );
+        transaction.signature = signature;
+
+        // and invalid signatures are always rejected
+        transaction.amount += 1;
+        assert_err(
+            hot_wallet.check_transaction(&transaction, CHAIN),
+            WalletError::InvalidSignature,
+        );
+        assert_err(
+            cold_wallet.check_transaction(&transaction, CHAIN),
+            WalletError::InvalidSignature,
+        );
+    }
+
+    #[test]
+    fn should_require_signatures_of_multisig_owners() {
+        let hot_wallet = Wallet::new(WalletMode::Hot, Vec::new()).unwrap();
+        let multisig = MultiSig {
+            threshold: 2,
+            public_keys: vec![hex::encode([1; 32]), hex::encode([2; 32])],
+            signatures: Default::default(),
+        };
+        let address = multisig.address().unwrap();
+        assert!(Wallet::new(WalletMode::Cold, vec![address.clone()]).is_ok());
+
+        // not even hot wallets take unsigned transactions from multisig addresses
+        let mut transaction = create_transaction(&address, "bob", 3);
+        assert_err(
+            hot_wallet.check_transaction(&transaction, CHAIN),
+            WalletError::MissingSignature,
+        );
+
+        transaction.multisig = Some(multisig);
+        assert_err(
+            hot_wallet.check_transaction(&transaction, CHAIN),
+            WalletError::InvalidMultiSig(MultiSigError::NotEnoughSignatures(0, 2)),
+        );
+    }
+
+    #[test]
+    fn should_calculate_balances_of_watched_addresses() {
+        let mut spend = create_transaction("", "bob", 3);
+        spend.sign(&[1; 32], CHAIN);
+        let address = spend.sender.clone();
+        let wallet = Wallet::new(WalletMode::Cold, vec![address.clone()]).unwrap();
+
+        let blockchain = Blockchain::new(ProofOfWork::shared(0, 1, 1));
+        let transactions = vec![
+            create_transaction("alice", &address, 10),
+            spend,
+            create_transaction("alice", "bob", 100),
+        ];
+        let previous_hash = blockchain.get_last_block().header.hash;
+        let mut block = Block::new(1, 0, previous_hash, transactions);
+        block.header.bits = blockchain.next_bits();
+        block.header.state_root = blockchain.next_state_root(&block.transactions);
+        block.header.hash = block.calculate_hash();
+        blockchain.add_block(block).unwrap();
+
+        let balances = wallet.balances(&blockchain);
+        assert_eq!(
+            balances,
+            vec![AddressBalance {
+                address,
+                received: 10,
+                sent: 3,
+            }]
+        );
+    }
+
+    #[test]
+    fn should_calculate_pending_balances() {
+        let blockchain = Blockchain::new(ProofOfWork::shared(0, 1, 1));
+        let previous_hash = blockchain.get_last_block().header.hash;
+        let transactions = vec![create_transaction("alice", "bob", 10)];
+        let mut block = Block::new(1, 0, previous_hash, transactions);
+        block.header.bits = blockchain.next_bits();
+        block.header.state_root = blockchain.next_state_root(&block.transactions);
+        block.header.hash = block.calculate_hash();
+        blockchain.add_block(block).unwrap();
+
+        let pool = TransactionPool::new();
+        pool.add_transaction(create_transaction("bob", "carol", 4))
+            .unwrap();
+
+        let (confirmed, pending) = address_balance("bob", &blockchain, &pool);
+        assert_eq!(confirmed.balance(), 10);
+        assert_eq!(pending.balance(), 6);
+        assert_eq!(pending.sent, 4);
+
+        // senders may spend more than they have, as funds are not checked
+        let (confirmed, _) = address_balance("alice", &blockchain, &pool);
+        assert_eq!(confirmed.balance(), -10);
+
+        // the balances don't change when the transactions are pruned
+        let mut block = Block::new(2, 0, blockchain.get_last_block().header.hash, Vec::new());
+        block.header.timestamp = block.header.timestamp.max(blockchain.min_timestamp());
+        block.header.bits = blockchain.next_bits();
+        block.header.state_root = blockchain.next_state_root(&block.transactions);
+        block.header.hash = block.calculate_hash();
+        blockchain.add_block(block).unwrap();
+        blockchain.set_prune_depth(1);
+        assert!(blockchain.get_block_at(1).unwrap().pruned);
+        let (confirmed, _) = address_balance("bob", &blockchain, &pool);
+        assert_eq!(confirmed.balance(), 10);
+    }
+
+    fn create_transaction(sender: &str, recipient: &str, amount: u64) -> Transaction {
+        Transaction {
+            sender: sender.to_string(),
+            recipient: recipient.to_string(),
+            amount,
+            signature: None,
+            multisig: None,
+            contract: None,
+            token: None,
+            data: None,
+        }
+    }
+
+    fn assert_err(result: Result<()>, error_type: WalletError) {
+        let err = result.unwrap_err().downcast::<WalletError>().unwrap();
+        assert_eq!(err, error_type);
+    }
+}
@@ -0,0 +1,292 @@
+use std::{fmt, str::FromStr};
+
+use thiserror::Error;
+
+use crate::model::is_multisig_address;
+
+// Addresses are shown to people as bech32 strings (like "rb1q..."), with a prefix and a checksum
+// so a mistyped address is rejected instead of silently sending the amount to nobody
+// Blocks and transactions keep the raw form (hex public keys and multisig addresses), which doesn't change ids
+const HUMAN_READABLE_PART: &str = "rb";
+const CHARSET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+const CHECKSUM_LENGTH: usize = 6;
+
+// The first character after the separator tells what the address is
+const KEY_VERSION: u8 = 0;
+const MULTISIG_VERSION: u8 = 1;
+
+const MULTISIG_PREFIX: &str = "ms";
+
+// Error types to return when an address can't be decoded
+#[derive(Error, PartialEq, Debug)]
+pub enum AddressError {
+    #[error("Invalid address `{0}`")]
+    InvalidFormat(String),
+
+    #[error("Invalid checksum in address `{0}`, it may have a typo")]
+    InvalidChecksum(String),
+
+    #[error("Unknown address version {0}")]
+    UnknownVersion(u8),
+}
+
+// An address that can receive amounts, either of a single key or of a multisig script
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Address {
+    Key([u8; 32]),
+    MultiSig([u8; 32]),
+}
+
+impl Address {
+    // Reads the raw form used in transactions, None for anything else (e.g. contract names)
+    pub fn from_raw(raw: &str) -> Option<Address> {
+        let (hash, multisig) = match raw.strip_prefix(MULTISIG_PREFIX) {
+            Some(hash) if is_multisig_address(raw) => (hash, true),
+            _ => (raw, false),
+        };
+        let mut bytes = [0; 32];
+        hex::decode_to_slice(hash, &mut bytes).ok()?;
+
+        match multisig {
+            true => Some(Address::MultiSig(bytes)),
+            false => Some(Address::Key(bytes)),
+        }
+    }
+
+    // The form that goes in transactions and blocks
+    pub fn raw(&self) -> String {
+        match self {
+            Address::Key(key) => hex::encode(key),
+            Address::MultiSig(hash) => format!("{}{}", MULTISIG_PREFIX, hex::encode(hash)),
+        }
+    }
+
+    fn version_and_bytes(&self) -> (u8, &[u8; 32]) {
+        match self {
+            Address::Key(key) => (KEY_VERSION, key),
+            Address::MultiSig(hash) => (MULTISIG_VERSION, hash),
+        }
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (version, bytes) = self.version_and_bytes();
+        let mut data = vec![version];
+        data.extend(to_five_bits(bytes));
+        data.extend(checksum(&data));
+
+        let encoded: String = data
+            .iter()
+            .map(|value| CHARSET[*value as usize] as char)
+            .collect();
+        write!(f, "{}1{}", HUMAN_READABLE_PART, encoded)
+    }
+}
+
+impl FromStr for Address {
+    type Err = AddressError;
+
+    fn from_str(address: &str) -> Result<Self, Self::Err> {
+        let invalid = || AddressError::InvalidFormat(address.to_string());
+
+        // either all lowercase or all uppercase (e.g. for QR codes), but never mixed
+        let lowercase = address.to_lowercase();
+        if lowercase != address && address.to_uppercase() != address {
+            return Err(invalid());
+        }
+        let data = match lowercase.strip_prefix(HUMAN_READABLE_PART) {
+            Some(data) => data.strip_prefix('1').ok_or_else(invalid)?,
+            None => return Err(invalid()),
+        };
+        let data = data
+            .bytes()
+            .map(|byte| CHARSET.iter().position(|c| *c == byte).map(|v| v as u8))
+            .collect::<Option<Vec<u8>>>()
+            .ok_or_else(invalid)?;
+        if data.len() <= CHECKSUM_LENGTH {
+            return Err(invalid());
+        }
+
+        let (data, address_checksum) = data.split_at(data.len() - CHECKSUM_LENGTH);
+        if checksum(data) != address_checksum {
+            return Err(AddressError::InvalidChecksum(address.to_string()));
+        }
+
+        let mut bytes = [0; 32];
+        match from_five_bits(&data[1..]) {
+            Some(decoded) if decoded.len() == 32 => bytes.copy_from_slice(&decoded),
+            _ => return Err(invalid()),
+        }
+        match data[0] {
+            KEY_VERSION => Ok(Address::Key(bytes)),
+            MULTISIG_VERSION => Ok(Address::MultiSig(bytes)),
+            version => Err(AddressError::UnknownVersion(version)),
+        }
+    }
+}
+
+// Whether a string is meant to be a checksummed address, even if it's a malformed one
+pub fn is_checksummed_address(address: &str) -> bool {
+    address
+        .to_lowercase()
+        .starts_with(&format!("{}1", HUMAN_READABLE_PART))
+}
+
+// Turns an address given by a client into the raw form of transactions
+// Checksummed addresses must be valid, anything else is taken as it is
+pub fn decode_address(address: &str) -> Result<String, AddressError> {
+    match is_checksummed_address(address) {
+        true => Ok(address.parse::<Address>()?.raw()),
+        false => Ok(address.to_string()),
+    }
+}
+
+// Shows a raw address in its checksummed form, if it has one
+pub fn encode_address(raw: &str) -> String {
+    match Address::from_raw(raw) {
+        Some(address) => address.to_string(),
+        None => raw.to_string(),
+    }
+}
+
+// Checksum of the BIP-173 specification, over the human readable part and the data
+fn checksum(data: &[u8]) -> Vec<u8> {
+    let mut values = expand_human_readable_part();
+    values.extend_from_slice(data);
+    values.extend_from_slice(&[0; CHECKSUM_LENGTH]);
+
+    let polymod = polymod(&values) ^ 1;
+    (0..CHECKSUM_LENGTH)
+        .map(|i| ((polymod >> (5 * (5 - i))) & 31) as u8)
+        .collect()
+}
+
+fn expand_human_readable_part() -> Vec<u8> {
+    let bytes = HUMAN_READABLE_PART.bytes();
+    let mut values: Vec<u8> = bytes.clone().map(|byte| byte >> 5).collect();
+    values.push(0);
+    values.extend(bytes.map(|byte| byte & 31));
+    values
+}
+
+fn polymod(values: &[u8]) -> u32 {
+    const GENERATOR: [u32; 5] = [
+        0x3b6a_57b2,
+        0x2650_8e6d,
+        0x1ea1_19fa,
+        0x3d42_33dd,
+        0x2a14_62b3,
+    ];
+
+    let mut checksum = 1u32;
+    for value in values {
+        let top = checksum >> 25;
+        checksum = ((checksum & 0x01ff_ffff) << 5) ^ u32::from(*value);
+        for (i, generator) in GENERATOR.iter().enumerate() {
+            if (top >> i) & 1 == 1 {
+                checksum ^= generator;
+            }
+        }
+    }
+    checksum
+}
+
+// Regroups bytes in groups of 5 bits, padding the last one with zeros
+fn to_five_bits(bytes: &[u8]) -> Vec<u8> {
+    let mut values = Vec::new();
+    let (mut accumulator, mut bits) = (0u32, 0);
+    for byte in bytes {
+        accumulator = (accumulator << 8) | u32::from(*byte);
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            values.push(((accumulator >> bits) & 31) as u8);
+        }
+    }
+    if bits > 0 {
+        values.push(((accumulator << (5 - bits)) & 31) as u8);
+    }
+    values
+}
+
+// The opposite of to_five_bits, None if the padding is not made of zeros
+fn from_five_bits(values: &[u8]) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let (mut accumulator, mut bits) = (0u32, 0);
+    for value in values {
+        accumulator = (accumulator << 5) | u32::from(*value);
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push(((accumulator >> bits) & 255) as u8);
+        }
+    }
+    if bits >= 5 || (accumulator << (8 - bits)) & 255 != 0 {
+        return None;
+    }
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_encode_and_decode_addresses() {
+        let key = Address::Key([1; 32]);
+        let encoded = key.to_string();
+        assert!(encoded.starts_with("rb1q"));
+        assert_eq!(encoded.parse(), Ok(key));
+        assert_eq!(encoded.to_uppercase().parse(), Ok(key));
+
+        let multisig = Address::MultiSig([1; 32]);
+        assert_ne!(multisig.to_string(), encoded);
+        assert_eq!(multisig.to_string().parse(), Ok(multisig));
+    }
+
+    #[test]
+    fn should_convert_from_and_to_raw_addresses() {
+        let raw_key = hex::encode([2; 32]);
+        assert_eq!(Address::from_raw(&raw_key), Some(Address::Key([2; 32])));
+        assert_eq!(decode_address(&encode_address(&raw_key)), Ok(raw_key));
+
+        let raw_multisig = format!("ms{}", hex::encode([3; 32]));
+        let encoded = encode_address(&raw_multisig);
+        assert_eq!(decode_address(&encoded), Ok(raw_multisig));
+
+        // other addresses (like the ones of contracts) don't have a checksummed form
+        assert_eq!(encode_address("bob"), "bob");
+        assert_eq!(decode_address("bob"), Ok("bob".to_string()));
+    }
+
+    #[test]
+    fn should_reject_addresses_with_typos() {
+        let encoded = Address::Key([1; 32]).to_string();
+
+        // changing any character breaks the checksum
+        let mut typo = encoded.clone().into_bytes();
+        typo[10] = if typo[10] == b'q' { b'p' } else { b'q' };
+        let typo = String::from_utf8(typo).unwrap();
+        assert_eq!(
+            decode_address(&typo),
+            Err(AddressError::InvalidChecksum(typo.clone()))
+        );
+
+        let truncated = &encoded[..encoded.len() - 1];
+        assert!(decode_address(truncated).is_err());
+
+        let mixed_case = format!("RB{}", &encoded[2..]);
+        assert_eq!(
+            decode_address(&mixed_case),
+            Err(AddressError::InvalidFormat(mixed_case.clone()))
+        );
+
+        // "b" is not in the charset
+        let invalid = format!("{}b", &encoded[..encoded.len() - 1]);
+        assert_eq!(
+            decode_address(&invalid),
+            Err(AddressError::InvalidFormat(invalid.clone()))
+        );
+    }
+}
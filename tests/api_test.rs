@@ -1,8 +1,17 @@
 mod common;
 
 use serial_test::serial;
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
 
-use crate::common::{Api, Block, BlockHash, ServerBuilder, Transaction};
+use isahc::ReadResponseExt;
+
+use crate::common::{
+    Api, Block, BlockHash, Bloom, ServerBuilder, Transaction, TransactionId, TransactionResponse,
+    WebhookReceiver,
+};
 
 #[test]
 #[serial]
@@ -35,11 +44,16 @@ fn test_should_let_add_transactions() {
     let transaction = Transaction {
         sender: "1".to_string(),
         recipient: "2".to_string(),
-        amount: 100 as u64,
+        amount: 100_u64,
+        signature: None,
     };
-    let res = node.add_transaction(&transaction);
+    let mut res = node.add_transaction(&transaction);
     assert_eq!(res.status().as_u16(), 200);
 
+    // the response must include the id of the transaction
+    let body: TransactionResponse = serde_json::from_str(&res.text().unwrap()).unwrap();
+    assert_ne!(body.id, TransactionId::default());
+
     // wait for the transaction to be mined
     node.wait_for_mining();
 
@@ -58,6 +72,451 @@ fn test_should_let_add_transactions() {
     assert_eq!(*mined_transaction, transaction);
 }
 
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_report_pending_and_confirmed_transactions() {
+    // without mining, transactions stay pending
+    let node = ServerBuilder::new().manual_mining().start();
+    let transaction = Transaction {
+        sender: "1".to_string(),
+        recipient: "2".to_string(),
+        amount: 100_u64,
+        signature: None,
+    };
+    let mut res = node.add_transaction(&transaction);
+    let body: TransactionResponse = serde_json::from_str(&res.text().unwrap()).unwrap();
+    let id = serde_json::to_value(body.id).unwrap();
+
+    let pending = node.get_transactions();
+    assert_eq!(pending.as_array().unwrap().len(), 1);
+    assert_eq!(pending[0]["id"], id);
+    assert_eq!(pending[0]["amount"], 100);
+    assert!(pending[0]["age_ms"].is_u64());
+
+    let mut res = node.get_transaction(id.as_str().unwrap());
+    assert_eq!(res.status().as_u16(), 200);
+    let status: serde_json::Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    assert_eq!(status["status"], "pending");
+    assert_eq!(status["id"], id);
+
+    let mut res = node.get_transaction_status(id.as_str().unwrap());
+    assert_eq!(res.status().as_u16(), 200);
+    let fate: serde_json::Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    assert_eq!(fate["status"], "pending");
+    assert!(fate["age_ms"].is_u64());
+    drop(node);
+
+    // once mined, the transaction points to the block that includes it
+    let node = ServerBuilder::new().start();
+    node.add_transaction(&transaction);
+    let mined_block = node.wait_for_block(1);
+
+    let mut res = node.get_transaction(id.as_str().unwrap());
+    assert_eq!(res.status().as_u16(), 200);
+    let status: serde_json::Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    assert_eq!(status["status"], "confirmed");
+    assert_eq!(status["block_index"], 1);
+    assert_eq!(
+        status["block_hash"],
+        serde_json::to_value(mined_block.hash).unwrap()
+    );
+    assert!(node.get_transactions().as_array().unwrap().is_empty());
+
+    let mut res = node.get_transaction_status(id.as_str().unwrap());
+    let fate: serde_json::Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    assert_eq!(fate["status"], "confirmed");
+    assert_eq!(fate["block_index"], 1);
+    assert!(fate["confirmations"].as_u64().unwrap() >= 1);
+
+    let res = node.get_transaction("1");
+    assert_eq!(res.status().as_u16(), 404);
+    let res = node.get_transaction_status("1");
+    assert_eq!(res.status().as_u16(), 404);
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_report_balances_of_any_address() {
+    // without mining, transactions stay pending
+    let node = ServerBuilder::new().manual_mining().start();
+    let transaction = Transaction {
+        sender: "alice".to_string(),
+        recipient: "bob".to_string(),
+        amount: 10,
+        signature: None,
+    };
+    node.add_transaction(&transaction);
+
+    let balance = node.get_balance("bob");
+    assert_eq!(balance["address"], "bob");
+    assert_eq!(balance["confirmed"]["balance"], 0);
+    assert_eq!(balance["pending"]["received"], 10);
+    assert_eq!(balance["pending"]["balance"], 10);
+    drop(node);
+
+    // once mined, the amounts are confirmed
+    let node = ServerBuilder::new().start();
+    node.add_transaction(&transaction);
+    node.wait_for_block(1);
+
+    let balance = node.get_balance("alice");
+    assert_eq!(balance["confirmed"]["sent"], 10);
+    assert_eq!(balance["confirmed"]["balance"], -10);
+    assert_eq!(balance["pending"], balance["confirmed"]);
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_reject_transactions_that_overflow_amounts() {
+    let node = ServerBuilder::new().start();
+    let transaction = Transaction {
+        sender: "alice".to_string(),
+        recipient: "bob".to_string(),
+        amount: u64::MAX,
+        signature: None,
+    };
+    node.add_transaction(&transaction);
+    node.wait_for_block(1);
+
+    let overflowing = Transaction {
+        sender: "carol".to_string(),
+        amount: 1,
+        ..transaction
+    };
+    let mut res = node.add_transaction(&overflowing);
+    assert_eq!(res.status().as_u16(), 400);
+    let error: serde_json::Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    assert_eq!(error["message"], "The amounts of bob would overflow");
+
+    let balance = node.get_balance("bob");
+    assert_eq!(balance["confirmed"]["received"], u64::MAX);
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_reject_oversized_and_unknown_payloads() {
+    let node = ServerBuilder::new().max_body_bytes(1024).start();
+
+    let body = serde_json::json!({"sender": "a".repeat(2048), "recipient": "bob", "amount": 1});
+    let mut res = node.post_raw("/transactions", &body.to_string());
+    assert_eq!(res.status().as_u16(), 413);
+    let error: serde_json::Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    assert_eq!(error["code"], "payload_too_large");
+
+    // fields are only accepted if the node knows them
+    let body = serde_json::json!({"sender": "alice", "recipient": "bob", "amount": 1, "fee": 1});
+    let res = node.post_raw("/transactions", &body.to_string());
+    assert_eq!(res.status().as_u16(), 400);
+
+    // and they can't be bigger than they need to be
+    let body = serde_json::json!({"sender": "a".repeat(200), "recipient": "bob", "amount": 1});
+    let res = node.post_raw("/transactions", &body.to_string());
+    assert_eq!(res.status().as_u16(), 400);
+
+    assert!(node.get_transactions().as_array().unwrap().is_empty());
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_find_the_blocks_of_an_address() {
+    let node = ServerBuilder::new().manual_mining().start();
+    node.mine(true);
+    let transaction = Transaction {
+        sender: "alice".to_string(),
+        recipient: "bob".to_string(),
+        amount: 10,
+        signature: None,
+    };
+    node.add_transaction(&transaction);
+    node.mine(true);
+
+    // the headers of the blocks are returned, the bloom filters rule out the rest
+    let blocks = node.get_address_blocks("bob", 0);
+    assert_eq!(blocks["address"], "bob");
+    assert_eq!(blocks["height"], 2);
+    assert_eq!(blocks["blocks"].as_array().unwrap().len(), 1);
+    assert_eq!(blocks["blocks"][0]["index"], 2);
+    assert!(blocks["blocks"][0]["transactions"].is_null());
+    assert!(blocks["next"].is_null());
+
+    let blocks = node.get_address_blocks("carol", 0);
+    assert!(blocks["blocks"].as_array().unwrap().is_empty());
+
+    // blocks before the "since" one are skipped
+    let blocks = node.get_address_blocks("alice", 3);
+    assert!(blocks["blocks"].as_array().unwrap().is_empty());
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_list_the_transactions_of_an_address() {
+    let node = ServerBuilder::new().manual_mining().start();
+    for amount in 1..=3 {
+        let transaction = Transaction {
+            sender: "alice".to_string(),
+            recipient: "bob".to_string(),
+            amount,
+            signature: None,
+        };
+        node.add_transaction(&transaction);
+        node.mine(true);
+    }
+
+    let history = node.get_address_transactions("bob", 0, 2);
+    assert_eq!(history["address"], "bob");
+    assert_eq!(history["height"], 3);
+    assert_eq!(history["total"], 3);
+    assert_eq!(history["transactions"][0]["block_index"], 1);
+    assert_eq!(history["transactions"][0]["direction"], "received");
+    assert_eq!(history["transactions"][0]["amount"], 1);
+    assert_eq!(history["next"], 2);
+
+    let history = node.get_address_transactions("alice", 2, 2);
+    assert_eq!(history["transactions"].as_array().unwrap().len(), 1);
+    assert_eq!(history["transactions"][0]["direction"], "sent");
+    assert_eq!(history["transactions"][0]["amount"], 3);
+    assert!(history["next"].is_null());
+
+    let history = node.get_address_transactions("carol", 0, 2);
+    assert_eq!(history["total"], 0);
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_query_checksummed_addresses() {
+    // the checksummed form of the key, as shown by "wallet new"
+    let address = "rb1q5p2d79n748f4p50tyney96y5tt8r4ckgrg68sgl448c6fwl90mws2gjsrf";
+    let public_key = "a054df167ea9d350d1eb24f242e8945ace3ae2c81a347823f5a9f1a4bbe57edd";
+    let typo = format!("{}q", &address[..address.len() - 1]);
+
+    let node = ServerBuilder::new().manual_mining().start();
+    let webhook = WebhookReceiver::start(9100);
+    let res = node.add_subscription(&webhook.url(), &[address]);
+    assert_eq!(res.status().as_u16(), 200);
+
+    let transaction = Transaction {
+        sender: "alice".to_string(),
+        recipient: public_key.to_string(),
+        amount: 10,
+        signature: None,
+    };
+    node.add_transaction(&transaction);
+    let events = webhook.wait_for_events(1);
+    assert_eq!(events[0]["transaction"]["recipient"], public_key);
+    node.mine(true);
+
+    // both forms are the same address, which is answered in its raw form
+    let balance = node.get_balance(address);
+    assert_eq!(balance["address"], public_key);
+    assert_eq!(balance["confirmed"]["received"], 10);
+    let blocks = node.get_address_blocks(address, 0);
+    assert_eq!(blocks["address"], public_key);
+    assert_eq!(blocks["blocks"][0]["index"], 1);
+    let history = node.get_address_transactions(address, 0, 10);
+    assert_eq!(history["total"], 1);
+    assert_eq!(node.get_tokens(address)["address"], public_key);
+    let query = "query($address: String!) { address(address: $address) { address } }";
+    let response = node.graphql(query, serde_json::json!({ "address": address }));
+    assert_eq!(response["data"]["address"]["address"], public_key);
+
+    // and a typo is an error instead of an empty address
+    for path in ["balance", "blocks", "transactions", "tokens"] {
+        let res = node.get_raw(&format!("/addresses/{}/{}", typo, path));
+        assert_eq!(res.status().as_u16(), 400);
+    }
+    let response = node.graphql(query, serde_json::json!({ "address": typo }));
+    assert!(response["errors"][0]["message"]
+        .as_str()
+        .unwrap()
+        .contains("checksum"));
+    let res = node.add_subscription(&webhook.url(), &[&typo]);
+    assert_eq!(res.status().as_u16(), 400);
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_estimate_fees() {
+    let node = ServerBuilder::new().manual_mining().start();
+    for amount in 1..=2 {
+        let transaction = Transaction {
+            sender: "alice".to_string(),
+            recipient: "bob".to_string(),
+            amount,
+            signature: None,
+        };
+        node.add_transaction(&transaction);
+        node.mine(true);
+    }
+    node.add_transaction(&Transaction {
+        sender: "alice".to_string(),
+        recipient: "bob".to_string(),
+        amount: 3,
+        signature: None,
+    });
+
+    // transactions don't pay fees yet, so none is needed to be confirmed in time
+    let mut res = node.get_raw("/fees/estimate?confirm_within=3");
+    assert_eq!(res.status().as_u16(), 200);
+    let estimate: serde_json::Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    assert_eq!(estimate["confirm_within"], 3);
+    assert_eq!(estimate["fee_rate"], 0);
+    assert_eq!(estimate["blocks"], 2);
+    assert_eq!(estimate["confirmed_transactions"], 2);
+    assert_eq!(estimate["pending_transactions"], 1);
+
+    // the horizon must be within the analyzed blocks
+    for query in [
+        "",
+        "?confirm_within=0",
+        "?confirm_within=101",
+        "?confirm_within=x",
+    ] {
+        let mut res = node.get_raw(&format!("/fees/estimate{}", query));
+        assert_eq!(res.status().as_u16(), 400);
+        let error: serde_json::Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+        assert_eq!(error["code"], "bad_request");
+    }
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_mine_on_demand() {
+    let node = ServerBuilder::new().manual_mining().start();
+    let transaction = Transaction {
+        sender: "1".to_string(),
+        recipient: "2".to_string(),
+        amount: 100_u64,
+        signature: None,
+    };
+    node.add_transaction(&transaction);
+
+    // with automatic mining disabled, the transaction waits in the pool
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(node.get_blocks().len(), 1);
+
+    // the mined block is returned when waiting for it
+    let mut res = node.mine(true);
+    assert_eq!(res.status().as_u16(), 200);
+    let block: Block = serde_json::from_str(&res.text().unwrap()).unwrap();
+    assert_eq!(block.index, 1);
+    assert_eq!(block.transactions, vec![transaction]);
+    assert_eq!(node.get_last_block(), block);
+
+    // blocks can be mined without transactions, and without waiting for them
+    let res = node.mine(false);
+    assert_eq!(res.status().as_u16(), 202);
+    let last_block = node.wait_for_block(2);
+    assert_eq!(last_block.index, 2);
+    assert!(last_block.transactions.is_empty());
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_not_let_add_duplicate_transactions() {
+    let node = ServerBuilder::new().start();
+
+    let transaction = Transaction {
+        sender: "1".to_string(),
+        recipient: "2".to_string(),
+        amount: 100_u64,
+        signature: None,
+    };
+    let res = node.add_transaction(&transaction);
+    assert_eq!(res.status().as_u16(), 200);
+
+    // the same transaction is either in the pool or already mined, so it must be rejected
+    let mut res = node.add_transaction(&transaction);
+    assert_eq!(res.status().as_u16(), 409);
+    let error: serde_json::Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    assert_eq!(error["code"], "conflict");
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_add_batches_of_transactions() {
+    let node = ServerBuilder::new().manual_mining().start();
+
+    // each transaction is checked on its own, so the rejected ones don't stop the rest
+    let transaction = serde_json::json!({"sender": "alice", "recipient": "bob", "amount": 1});
+    let batch = serde_json::json!([
+        transaction,
+        {"sender": "alice", "recipient": "carol", "amount": 2},
+        transaction,
+        {"sender": "alice", "recipient": "bob", "amount": 1, "fee": 1},
+    ]);
+    let mut res = node.post_raw("/transactions/batch", &batch.to_string());
+    assert_eq!(res.status().as_u16(), 200);
+    let results: serde_json::Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    let statuses: Vec<&str> = results
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|result| result["status"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        statuses,
+        vec!["accepted", "accepted", "rejected", "rejected"]
+    );
+    assert_eq!(results[2]["code"], "conflict");
+    assert_eq!(results[3]["code"], "bad_request");
+
+    let pending = node.get_transactions();
+    let ids: Vec<&serde_json::Value> = pending
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|transaction| &transaction["id"])
+        .collect();
+    assert_eq!(ids.len(), 2);
+    assert!(ids.contains(&&results[0]["id"]));
+    assert!(ids.contains(&&results[1]["id"]));
+
+    // the whole batch is rejected if it's too big
+    let batch = serde_json::Value::Array(vec![transaction; 1001]);
+    let res = node.post_raw("/transactions/batch", &batch.to_string());
+    assert_eq!(res.status().as_u16(), 400);
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_add_transactions_with_data() {
+    let node = ServerBuilder::new().manual_mining().start();
+
+    let transaction = serde_json::json!({
+        "sender": "alice", "recipient": "bob", "amount": 1, "data": "cafe"
+    });
+    let res = node.post_raw("/transactions", &transaction.to_string());
+    assert_eq!(res.status().as_u16(), 200);
+    let pending = node.get_transactions();
+    assert_eq!(pending[0]["data"], "cafe");
+
+    // the data is limited by the policy of the node (256 bytes by default)
+    let transaction = serde_json::json!({
+        "sender": "alice", "recipient": "bob", "amount": 2, "data": "00".repeat(257)
+    });
+    let res = node.post_raw("/transactions", &transaction.to_string());
+    assert_eq!(res.status().as_u16(), 400);
+
+    // and it must be valid hex
+    let transaction = serde_json::json!({
+        "sender": "alice", "recipient": "bob", "amount": 3, "data": "xyz"
+    });
+    let res = node.post_raw("/transactions", &transaction.to_string());
+    assert_eq!(res.status().as_u16(), 400);
+}
+
 #[test]
 #[serial]
 #[cfg(unix)]
@@ -68,17 +527,44 @@ fn test_should_let_add_valid_block() {
     let valid_block = Block {
         // there is the genesis block already, so the next index is 1
         index: 1,
-        timestamp: 0,
+        // the timestamp must be after the median of the last blocks
+        timestamp: node.get_block_template(None)["min_timestamp"]
+            .as_i64()
+            .unwrap(),
         nonce: 0,
+        // the target is checked
+        bits: node.get_next_bits(),
         // the previous hash is checked
         previous_hash: genesis_block.hash,
         // the api automatically recalculates the hash...
         // ...so no need to add a valid one here
         hash: BlockHash::default(),
+        // same for the merkle root and the bloom
+        merkle_root: BlockHash::default(),
+        bloom: Bloom::default(),
+        // the state root is checked too, an empty block keeps the same state
+        state_root: genesis_block.state_root,
         transactions: [].to_vec(),
+        signature: None,
     };
-    let res = node.add_block(&valid_block);
+    let mut res = node.add_block(&valid_block);
     assert_eq!(res.status().as_u16(), 200);
+
+    // the node tells if the block made it into the chain
+    let result: serde_json::Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    assert_eq!(result["status"], "accepted");
+    assert_eq!(result["index"], 1);
+    assert_eq!(result["height"], 1);
+    assert_eq!(
+        result["hash"],
+        serde_json::json!(node.get_last_block().hash)
+    );
+
+    // sending it again is harmless
+    let mut res = node.add_block(&valid_block);
+    assert_eq!(res.status().as_u16(), 200);
+    let result: serde_json::Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    assert_eq!(result["status"], "duplicate");
 }
 
 #[test]
@@ -92,10 +578,923 @@ fn test_should_not_let_add_invalid_block() {
         index: 0, // not valid index, the genesis block already has index 0
         timestamp: 0,
         nonce: 0,
+        bits: 0,
         previous_hash: BlockHash::default(), // also not valid
+        merkle_root: BlockHash::default(),
+        bloom: Bloom::default(),
         hash: BlockHash::default(),
+        state_root: BlockHash::default(),
         transactions: [].to_vec(),
+        signature: None,
+    };
+    let mut res = node.add_block(&invalid_block);
+    assert_eq!(res.status().as_u16(), 400);
+
+    // the error explains what's wrong
+    let error: serde_json::Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    assert_eq!(error["code"], "bad_request");
+    assert_eq!(error["message"], "Invalid index");
+    assert_eq!(error["status"], "rejected");
+    assert_eq!(error["reason"], "invalid_index");
+
+    // so does a block that can't even be parsed
+    let mut res = node.post_raw("/blocks", "{\"index\": \"foo\"}");
+    assert_eq!(res.status().as_u16(), 400);
+    let error: serde_json::Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    assert_eq!(error["code"], "bad_request");
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_drain_on_shutdown() {
+    // give us enough time to make requests while the node is draining
+    let mut node = ServerBuilder::new().shutdown_drain_ms(1000).start();
+
+    // before the shutdown, the node is ready
+    let res = node.get_readiness();
+    assert_eq!(res.status().as_u16(), 200);
+
+    node.start_shutdown();
+
+    // while draining, the node must not advertise readiness...
+    let res = node.get_readiness();
+    assert_eq!(res.status().as_u16(), 503);
+
+    // ...nor accept new writes...
+    let transaction = Transaction {
+        sender: "1".to_string(),
+        recipient: "2".to_string(),
+        amount: 100_u64,
+        signature: None,
+    };
+    let res = node.add_transaction(&transaction);
+    assert_eq!(res.status().as_u16(), 503);
+
+    // ...but reads are still served
+    assert_eq!(node.get_blocks().len(), 1);
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_notify_subscribers_of_watched_addresses() {
+    let mut node = ServerBuilder::new().start();
+    let webhook = WebhookReceiver::start(9100);
+
+    // only transactions touching "alice" must be notified
+    let res = node.add_subscription(&webhook.url(), &["alice"]);
+    assert_eq!(res.status().as_u16(), 200);
+
+    let watched_transaction = Transaction {
+        sender: "alice".to_string(),
+        recipient: "bob".to_string(),
+        amount: 1,
+        signature: None,
+    };
+    let other_transaction = Transaction {
+        sender: "carol".to_string(),
+        recipient: "dave".to_string(),
+        amount: 1,
+        signature: None,
+    };
+    node.add_transaction(&other_transaction);
+    node.add_transaction(&watched_transaction);
+    node.wait_for_mining();
+
+    // the watched transaction is notified when entering the pool and when mined
+    let events = webhook.wait_for_events(2);
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0]["event"], "pending");
+    assert_eq!(events[0]["transaction"]["sender"], "alice");
+    assert_eq!(events[1]["event"], "confirmed");
+    assert_eq!(events[1]["transaction"]["sender"], "alice");
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_report_safe_tip() {
+    let node = ServerBuilder::new().finality_depth(1).start();
+    let genesis_block = node.get_last_block();
+
+    // add a new block, only the genesis block is buried under enough blocks
+    node.add_valid_block();
+    let last_block = node.get_last_block();
+
+    let status = node.get_status();
+    assert_eq!(status["latest"]["index"], 1);
+    assert_eq!(status["latest"]["hash"], serde_json::json!(last_block.hash));
+    assert_eq!(status["safe"]["index"], 0);
+    assert_eq!(
+        status["safe"]["hash"],
+        serde_json::json!(genesis_block.hash)
+    );
+
+    // blocks can also be queried at the safe tip
+    let safe_blocks = node.get_safe_blocks();
+    assert_eq!(safe_blocks.len(), 1);
+    assert_eq!(node.get_blocks().len(), 2);
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_query_addresses_at_the_safe_tip() {
+    let node = ServerBuilder::new()
+        .finality_depth(1)
+        .manual_mining()
+        .start();
+    for amount in [5, 3].iter() {
+        let transaction = Transaction {
+            sender: "alice".to_string(),
+            recipient: "bob".to_string(),
+            amount: *amount,
+            signature: None,
+        };
+        node.add_transaction(&transaction);
+        node.mine(true);
+    }
+
+    // only the first block is buried under enough blocks
+    let balance = node.get_safe_balance("bob");
+    assert_eq!(balance["confirmed"]["received"], 5);
+    assert_eq!(balance["pending"]["received"], 8);
+    assert_eq!(node.get_balance("bob")["confirmed"]["received"], 8);
+
+    let history = node.get_safe_address_transactions("alice");
+    assert_eq!(history["height"], 1);
+    assert_eq!(history["total"], 1);
+    assert_eq!(history["transactions"][0]["amount"], 5);
+    assert_eq!(history["transactions"][0]["direction"], "sent");
+    assert_eq!(node.get_address_transactions("alice", 0, 10)["total"], 2);
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_paginate_blocks() {
+    let node = ServerBuilder::new().start();
+    for _ in 0..4 {
+        node.add_valid_block();
+    }
+    let indices = |page: &serde_json::Value| {
+        page["blocks"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|block| block["index"].as_u64().unwrap())
+            .collect::<Vec<_>>()
+    };
+    let get_page = |query: &str| -> serde_json::Value {
+        let mut response = node.get_blocks_page(query);
+        assert_eq!(response.status().as_u16(), 200);
+        serde_json::from_str(&response.text().unwrap()).unwrap()
+    };
+
+    // pages follow each other through the "next" cursor
+    let page = get_page("limit=3");
+    assert_eq!(page["height"], 4);
+    assert_eq!(indices(&page), vec![0, 1, 2]);
+    assert_eq!(page["next"], 3);
+
+    let page = get_page("from=3&limit=3");
+    assert_eq!(indices(&page), vec![3, 4]);
+    assert!(page["next"].is_null());
+
+    // the most recent blocks come first in descending order
+    let page = get_page("order=desc&limit=2");
+    assert_eq!(indices(&page), vec![4, 3]);
+    assert_eq!(page["next"], 2);
+
+    let page = get_page("order=desc&from=1");
+    assert_eq!(indices(&page), vec![1, 0]);
+    assert!(page["next"].is_null());
+
+    // without pagination the whole chain is still returned as a list
+    assert_eq!(node.get_blocks().len(), 5);
+
+    let response = node.get_blocks_page("limit=0");
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_get_single_blocks() {
+    let node = ServerBuilder::new().start();
+    node.add_valid_block();
+    let last_block = node.get_last_block();
+
+    // blocks can be found both by index and by hash
+    let mut response = node.get_block("1");
+    assert_eq!(response.status().as_u16(), 200);
+    let block: Block = serde_json::from_str(&response.text().unwrap()).unwrap();
+    assert_eq!(block, last_block);
+
+    let hash = serde_json::to_value(last_block.hash).unwrap();
+    let mut response = node.get_block(hash.as_str().unwrap());
+    assert_eq!(response.status().as_u16(), 200);
+    let block: Block = serde_json::from_str(&response.text().unwrap()).unwrap();
+    assert_eq!(block, last_block);
+
+    // missing blocks are reported with an error message
+    let mut response = node.get_block("2");
+    assert_eq!(response.status().as_u16(), 404);
+    let error: serde_json::Value = serde_json::from_str(&response.text().unwrap()).unwrap();
+    assert_eq!(error["code"], "not_found");
+    assert_eq!(error["message"], "Block not found");
+
+    let response = node.get_block(&"1".repeat(64));
+    assert_eq!(response.status().as_u16(), 404);
+
+    let response = node.get_block("foo");
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_report_node_health() {
+    let node = ServerBuilder::new().manual_mining().start();
+    let transaction = Transaction {
+        sender: "1".to_string(),
+        recipient: "2".to_string(),
+        amount: 3,
+        signature: None,
+    };
+    node.add_transaction(&transaction);
+
+    let status = node.get_status();
+    assert_eq!(status["version"], env!("CARGO_PKG_VERSION"));
+    assert!(status["uptime_secs"].is_u64());
+    assert_eq!(status["mempool_size"], 1);
+    assert_eq!(status["peer_count"], 0);
+    // there are no peers to compare the clock with
+    assert!(status["clock_offset_ms"].is_null());
+    assert_eq!(status["mining"], "on_demand");
+    // only the genesis block, which has no work
+    assert_eq!(status["total_work"], "0".repeat(64));
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_fail_fast_on_startup_errors() {
+    let _node = ServerBuilder::new().port(8000).start();
+
+    // the port is already used by the other node, so the api could never start
+    let mut other_node = ServerBuilder::new().port(8000).start();
+
+    // the node stops right away explaining what's wrong
+    assert!(other_node.wait_for_exit());
+    assert!(other_node.has_logged("PORT 8000 is already in use"));
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_log_the_spans_of_requests_and_blocks() {
+    let mut node = ServerBuilder::new()
+        .manual_mining()
+        .log_level("debug,actix_server=warn")
+        .start();
+
+    let res = node.get_block("99");
+    assert_eq!(res.status().as_u16(), 404);
+    assert!(node.has_logged("request{method=GET path=/blocks/99}: responded with status 404"));
+
+    // blocks that don't fit in the chain are rejected inside the validation span
+    let mut block = node.get_last_block();
+    block.index = 5;
+    node.add_block(&block);
+    assert!(node.has_logged("validate_block{index=5}: rejected block: Invalid index"));
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_only_accept_signed_transactions_with_cold_wallet() {
+    // the node only knows the public key (viewing key) of the watched address
+    let seed = [1; 32];
+    let address = "8a88e3dd7409f195fd52db2d3cba5d72ca6709bf1d94121bf3748801b40f6f5c";
+    let mut node = ServerBuilder::new().cold_wallet(&[address]).start();
+
+    // unsigned transactions can not be spent from the node
+    let mut transaction = Transaction {
+        sender: address.to_string(),
+        recipient: "bob".to_string(),
+        amount: 5,
+        signature: None,
+    };
+    let res = node.add_transaction(&transaction);
+    assert_eq!(res.status().as_u16(), 400);
+
+    // transactions signed for another network are rejected, so they can't be replayed here
+    transaction.sign(&seed, "testnet");
+    assert_eq!(transaction.sender, address);
+    let res = node.add_transaction(&transaction);
+    assert_eq!(res.status().as_u16(), 400);
+
+    // transactions signed outside of the node for its network are accepted
+    transaction.sign(&seed, "main");
+    let res = node.add_transaction(&transaction);
+    assert_eq!(res.status().as_u16(), 200);
+
+    // once mined, the balance of the watched address is updated
+    node.wait_for_mining();
+    let wallet = node.get_wallet();
+    assert_eq!(wallet["mode"], "cold");
+    assert_eq!(wallet["balances"][0]["address"], address);
+    assert_eq!(wallet["balances"][0]["sent"], 5);
+    assert_eq!(wallet["balances"][0]["received"], 0);
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_long_poll_block_templates() {
+    let node = ServerBuilder::new().start();
+
+    // the template of the next block is built on top of the genesis block
+    let genesis_block = node.get_last_block();
+    let template = node.get_block_template(None);
+    assert_eq!(template["index"], 1);
+    assert_eq!(template["bits"], node.get_next_bits());
+    let previous_hash: BlockHash =
+        serde_json::from_value(template["previous_hash"].clone()).unwrap();
+    assert_eq!(previous_hash, genesis_block.hash);
+    // there are no transactions yet, so the merkle root is empty
+    let merkle_root: BlockHash = serde_json::from_value(template["merkle_root"].clone()).unwrap();
+    assert_eq!(merkle_root, BlockHash::default());
+    let longpoll_id = template["longpoll_id"].as_str().unwrap().to_string();
+
+    // with no changes, the long poll waits until the timeout and returns the same template
+    let start = Instant::now();
+    let template = node.get_block_template(Some(&longpoll_id));
+    assert!(start.elapsed() >= Duration::from_millis(1000));
+    assert_eq!(template["longpoll_id"], longpoll_id);
+
+    // a new transaction changes the template, so the long poll returns right away
+    let start = Instant::now();
+    let template = thread::scope(|scope| {
+        scope.spawn(|| {
+            thread::sleep(Duration::from_millis(200));
+            let transaction = Transaction {
+                sender: "1".to_string(),
+                recipient: "2".to_string(),
+                amount: 3,
+                signature: None,
+            };
+            node.add_transaction(&transaction);
+        });
+
+        node.get_block_template(Some(&longpoll_id))
+    });
+    assert!(start.elapsed() < Duration::from_millis(1000));
+    assert_ne!(template["longpoll_id"], longpoll_id);
+
+    // the state root of the template already includes the new transaction
+    let state_root: BlockHash = serde_json::from_value(template["state_root"].clone()).unwrap();
+    assert_ne!(state_root, genesis_block.state_root);
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_report_miner_stats() {
+    let mut node = ServerBuilder::new().start();
+
+    // nothing was mined yet
+    let stats = node.get_miner_stats();
+    assert_eq!(stats["blocks_found"], 0);
+    assert_eq!(stats["nonces_tried"], 0);
+    assert!(stats["avg_block_time_ms"].is_null());
+
+    // mine a new block
+    let transaction = Transaction {
+        sender: "1".to_string(),
+        recipient: "2".to_string(),
+        amount: 3,
+        signature: None,
+    };
+    node.add_transaction(&transaction);
+    node.wait_for_mining();
+
+    // the stats must account for the mined block
+    let stats = node.get_miner_stats();
+    assert_eq!(stats["blocks_found"], 1);
+    assert!(stats["nonces_tried"].as_u64().unwrap() >= 1);
+    assert!(stats["avg_block_time_ms"].is_u64());
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_require_api_key_for_writes() {
+    let node = ServerBuilder::new().api_key("secret").start();
+    let uri = format!("http://localhost:{}", node.config.port);
+    let transaction = Transaction {
+        sender: "1".to_string(),
+        recipient: "2".to_string(),
+        amount: 3,
+        signature: None,
     };
-    let res = node.add_block(&invalid_block);
+    let body = serde_json::to_string(&transaction).unwrap();
+
+    // writes without the key are rejected
+    let request = isahc::Request::post(format!("{}/transactions", uri))
+        .header("Content-Type", "application/json")
+        .body(body.clone())
+        .unwrap();
+    let mut res = isahc::send(request).unwrap();
+    assert_eq!(res.status().as_u16(), 401);
+    let error: serde_json::Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    assert_eq!(error["code"], "unauthorized");
+
+    // and so are the ones with a wrong key
+    let request = isahc::Request::post(format!("{}/transactions", uri))
+        .header("Content-Type", "application/json")
+        .header("X-Api-Key", "wrong")
+        .body(body)
+        .unwrap();
+    assert_eq!(isahc::send(request).unwrap().status().as_u16(), 401);
+
+    // JSON-RPC calls are reads, but submitting a transaction through them is not
+    let call = serde_json::json!(
+        {"jsonrpc": "2.0", "method": "tx_submit", "params": [transaction], "id": 1}
+    );
+    let mut res = isahc::post(format!("{}/rpc", uri), call.to_string()).unwrap();
+    let response: serde_json::Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    assert_eq!(response["error"]["data"], "unauthorized");
+
+    // the harness sends the right key
+    let res = node.add_transaction(&transaction);
+    assert_eq!(res.status().as_u16(), 200);
+
+    // reads are public by default
+    let res = isahc::get(format!("{}/blocks", uri)).unwrap();
+    assert_eq!(res.status().as_u16(), 200);
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_require_api_key_for_private_reads() {
+    let node = ServerBuilder::new()
+        .api_key("secret")
+        .private_reads()
+        .start();
+    let uri = format!("http://localhost:{}", node.config.port);
+
+    let res = isahc::get(format!("{}/blocks", uri)).unwrap();
+    assert_eq!(res.status().as_u16(), 401);
+    assert_eq!(node.get_blocks().len(), 1);
+
+    // the readiness check is always public
+    let res = isahc::get(format!("{}/ready", uri)).unwrap();
+    assert_eq!(res.status().as_u16(), 200);
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_serve_versioned_routes() {
+    let node = ServerBuilder::new().start();
+    let uri = format!("http://localhost:{}", node.config.port);
+
+    // every route of the api lives under its version
+    let mut res = isahc::get(format!("{}/v1/blocks", uri)).unwrap();
+    assert_eq!(res.status().as_u16(), 200);
+    assert_eq!(res.headers()["API-Version"], "1");
+    assert!(res.headers().get("Deprecation").is_none());
+    let blocks: Vec<Block> = serde_json::from_str(&res.text().unwrap()).unwrap();
+    assert_eq!(blocks, node.get_blocks());
+
+    // the routes at the root still work, but point to their successor
+    let res = isahc::get(format!("{}/blocks", uri)).unwrap();
+    assert_eq!(res.status().as_u16(), 200);
+    assert_eq!(res.headers()["API-Version"], "1");
+    assert_eq!(res.headers()["Deprecation"], "true");
+    assert_eq!(
+        res.headers()["Link"],
+        "</v1/blocks>; rel=\"successor-version\""
+    );
+
+    // unknown versions can't be asked for, by path or by header
+    let res = isahc::get(format!("{}/v2/blocks", uri)).unwrap();
+    assert_eq!(res.status().as_u16(), 404);
+    let request = isahc::Request::get(format!("{}/blocks", uri))
+        .header("API-Version", "2")
+        .body(())
+        .unwrap();
+    assert_eq!(isahc::send(request).unwrap().status().as_u16(), 400);
+
+    // the readiness check is not part of the api
+    let res = isahc::get(format!("{}/ready", uri)).unwrap();
+    assert_eq!(res.status().as_u16(), 200);
+    assert!(res.headers().get("Deprecation").is_none());
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_rate_limit_writes() {
+    let node = ServerBuilder::new()
+        .manual_mining()
+        .rate_limit(1, 2)
+        .start();
+    let transaction = |amount| Transaction {
+        sender: "1".to_string(),
+        recipient: "2".to_string(),
+        amount,
+        signature: None,
+    };
+
+    // the burst is allowed
+    assert_eq!(node.add_transaction(&transaction(1)).status().as_u16(), 200);
+    assert_eq!(node.add_transaction(&transaction(2)).status().as_u16(), 200);
+
+    // but then the client must wait
+    let mut res = node.add_transaction(&transaction(3));
+    assert_eq!(res.status().as_u16(), 429);
+    assert_eq!(res.headers()["Retry-After"], "1");
+    let error: serde_json::Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    assert_eq!(error["code"], "too_many_requests");
+
+    // reads are not limited
+    assert_eq!(node.get_transactions().as_array().unwrap().len(), 2);
+
+    thread::sleep(Duration::from_secs(1));
+    assert_eq!(node.add_transaction(&transaction(3)).status().as_u16(), 200);
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_allow_configured_cross_origin_requests() {
+    let node = ServerBuilder::new()
+        .cors_allowed_origins("https://explorer.example")
+        .start();
+    let uri = format!("http://localhost:{}/blocks", node.config.port);
+
+    // the browser asks first
+    let request = isahc::Request::builder()
+        .method("OPTIONS")
+        .uri(&uri)
+        .header("Origin", "https://explorer.example")
+        .header("Access-Control-Request-Method", "GET")
+        .body(())
+        .unwrap();
+    let res = isahc::send(request).unwrap();
+    assert_eq!(res.status().as_u16(), 204);
+    assert_eq!(
+        res.headers()["Access-Control-Allow-Origin"],
+        "https://explorer.example"
+    );
+    assert_eq!(res.headers()["Access-Control-Allow-Methods"], "GET");
+
+    // and then the response of the real request can be read
+    let request = isahc::Request::get(&uri)
+        .header("Origin", "https://explorer.example")
+        .body(())
+        .unwrap();
+    let res = isahc::send(request).unwrap();
+    assert_eq!(res.status().as_u16(), 200);
+    assert_eq!(
+        res.headers()["Access-Control-Allow-Origin"],
+        "https://explorer.example"
+    );
+
+    // other websites and methods are not allowed
+    let request = isahc::Request::get(&uri)
+        .header("Origin", "https://other.example")
+        .body(())
+        .unwrap();
+    let res = isahc::send(request).unwrap();
+    assert!(!res.headers().contains_key("Access-Control-Allow-Origin"));
+
+    let request = isahc::Request::builder()
+        .method("OPTIONS")
+        .uri(&uri)
+        .header("Origin", "https://explorer.example")
+        .header("Access-Control-Request-Method", "POST")
+        .body(())
+        .unwrap();
+    assert_eq!(isahc::send(request).unwrap().status().as_u16(), 400);
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_serve_api_documentation() {
+    // the documentation is public, even if reads are not
+    let node = ServerBuilder::new()
+        .api_key("secret")
+        .private_reads()
+        .start();
+    let base_url = format!("http://localhost:{}", node.config.port);
+
+    let mut res = isahc::get(format!("{}/openapi.json", base_url)).unwrap();
+    assert_eq!(res.status().as_u16(), 200);
+    let spec: serde_json::Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    assert!(spec["paths"]["/blocks/{id}"]["get"].is_object());
+    assert!(spec["paths"]["/transactions"]["post"].is_object());
+
+    let mut res = isahc::get(format!("{}/docs", base_url)).unwrap();
+    assert_eq!(res.status().as_u16(), 200);
+    assert!(res.text().unwrap().contains("/openapi.json"));
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_serve_the_explorer() {
+    // the page is public, the data is read from the api with the key
+    let node = ServerBuilder::new()
+        .api_key("secret")
+        .private_reads()
+        .start();
+    let base_url = format!("http://localhost:{}", node.config.port);
+
+    let mut res = isahc::get(format!("{}/explorer", base_url)).unwrap();
+    assert_eq!(res.status().as_u16(), 200);
+    assert_eq!(
+        res.headers()["content-type"].to_str().unwrap(),
+        "text/html; charset=utf-8"
+    );
+    let page = res.text().unwrap();
+    assert!(page.contains("/graphql"));
+    assert!(page.contains("X-Api-Key"));
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_answer_graphql_queries() {
+    let node = ServerBuilder::new().manual_mining().start();
+    let transaction = Transaction {
+        sender: "1".to_string(),
+        recipient: "2".to_string(),
+        amount: 100_u64,
+        signature: None,
+    };
+    let mut res = node.add_transaction(&transaction);
+    let id = serde_json::from_str::<TransactionResponse>(&res.text().unwrap())
+        .unwrap()
+        .id;
+    node.mine(true);
+    node.add_transaction(&Transaction {
+        amount: 5,
+        ..transaction.clone()
+    });
+
+    // nested data is fetched in a single request, in the requested order
+    let query = r#"
+        query Explorer($limit: Int!) {
+            chain { height }
+            blocks(order: DESC, limit: $limit) {
+                index
+                txs: transactions {
+                    amount
+                    status
+                    sender { address confirmed { sent balance } pending { sent } }
+                    block { index }
+                }
+            }
+            mempool { amount status }
+        }
+    "#;
+    let response = node.graphql(query, serde_json::json!({ "limit": 1 }));
+    let expected = serde_json::json!({
+        "data": {
+            "chain": { "height": 1 },
+            "blocks": [{
+                "index": 1,
+                "txs": [{
+                    "amount": 100,
+                    "status": "CONFIRMED",
+                    "sender": {
+                        "address": "1",
+                        "confirmed": { "sent": 100, "balance": -100 },
+                        "pending": { "sent": 105 }
+                    },
+                    "block": { "index": 1 }
+                }]
+            }],
+            "mempool": [{ "amount": 5, "status": "PENDING" }]
+        }
+    });
+    assert_eq!(response, expected);
+
+    // transactions can be looked up by id, wherever they are
+    let query = "query($id: String!) { transaction(id: $id) { id block { index } } }";
+    let response = node.graphql(query, serde_json::json!({ "id": id }));
+    assert_eq!(response["data"]["transaction"]["id"], serde_json::json!(id));
+    assert_eq!(response["data"]["transaction"]["block"]["index"], 1);
+
+    // invalid queries are answered with errors
+    let response = node.graphql("{ block { hash } }", serde_json::json!({}));
+    assert!(response["errors"][0]["message"].is_string());
+    let response = node.graphql("mutation { mine }", serde_json::json!({}));
+    assert!(response.get("data").is_none());
+    assert!(response["errors"][0]["message"].is_string());
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_answer_json_rpc_calls() {
+    let node = ServerBuilder::new().manual_mining().start();
+
+    let response = node.rpc(serde_json::json!(
+        {"jsonrpc": "2.0", "method": "chain_getHeight", "id": 1}
+    ));
+    assert_eq!(
+        response,
+        serde_json::json!({"jsonrpc": "2.0", "result": 0, "id": 1})
+    );
+
+    // params can be passed by name too
+    let transaction = Transaction {
+        sender: "1".to_string(),
+        recipient: "2".to_string(),
+        amount: 3,
+        signature: None,
+    };
+    let response = node.rpc(serde_json::json!(
+        {"jsonrpc": "2.0", "method": "tx_submit", "params": {"transaction": transaction}, "id": "a"}
+    ));
+    let id = response["result"].clone();
+    assert_eq!(node.get_transactions()[0]["id"], id);
+
+    // batches are answered in a single response, without the notifications
+    let responses = node.rpc(serde_json::json!([
+        {"jsonrpc": "2.0", "method": "mempool_content", "id": 1},
+        {"jsonrpc": "2.0", "method": "chain_getBlock", "params": [0], "id": 2},
+        {"jsonrpc": "2.0", "method": "chain_getBlock", "params": [99], "id": 3},
+        {"jsonrpc": "2.0", "method": "tx_get", "params": [id], "id": 4},
+        {"jsonrpc": "2.0", "method": "foo", "id": 5},
+        {"jsonrpc": "2.0", "method": "chain_getHeight"},
+    ]));
+    let responses = responses.as_array().unwrap();
+    assert_eq!(responses.len(), 5);
+    assert_eq!(responses[0]["result"][0]["id"], id);
+    let genesis_hash = serde_json::to_value(node.get_blocks()[0].hash).unwrap();
+    assert_eq!(responses[1]["result"]["hash"], genesis_hash);
+    assert_eq!(responses[2]["result"], serde_json::Value::Null);
+    assert_eq!(responses[3]["result"]["status"], "pending");
+    assert_eq!(responses[4]["error"]["code"], -32601);
+
+    let response = node.rpc(serde_json::json!(
+        {"jsonrpc": "2.0", "method": "tx_submit", "params": [transaction], "id": 6}
+    ));
+    assert_eq!(response["error"]["code"], -32003);
+    assert_eq!(response["error"]["data"], "conflict");
+
+    let mut response = node.post_raw("/rpc", "{");
+    let error: serde_json::Value = serde_json::from_str(&response.text().unwrap()).unwrap();
+    assert_eq!(error["error"]["code"], -32700);
+    assert_eq!(error["id"], serde_json::Value::Null);
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_listen_on_the_configured_addresses() {
+    let node = ServerBuilder::new()
+        .listen_address("127.0.0.1:8000")
+        .listen_address("127.0.0.1:8001")
+        .start();
+
+    for port in [8000, 8001] {
+        let res = isahc::get(format!("http://127.0.0.1:{}/ready", port)).unwrap();
+        assert_eq!(res.status().as_u16(), 200);
+    }
+    assert_eq!(node.get_last_block().index, 0);
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_serve_the_api_over_tls() {
+    let node = ServerBuilder::new().manual_mining().tls().start();
+
+    // the whole api works as usual, only encrypted
+    let transaction = Transaction {
+        sender: "alice".to_string(),
+        recipient: "bob".to_string(),
+        amount: 10,
+        signature: None,
+    };
+    let res = node.add_transaction(&transaction);
+    assert_eq!(res.status().as_u16(), 200);
+    node.mine(true);
+    assert_eq!(node.get_last_block().transactions, vec![transaction]);
+    assert_eq!(node.get_balance("bob")["confirmed"]["balance"], 10);
+
+    // and plain HTTP is not accepted
+    assert!(isahc::get("http://localhost:8000/ready").is_err());
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_run_contracts() {
+    // a counter that stores how many times it was called, see src/model/contract.rs
+    let code = "0061736d0100000001180460047f7f7f7f017f60047f7f7f7f0060027f7f00600000023503\
+        03656e760c73746f726167655f72656164000003656e760d73746f726167655f7772697465000103656e\
+        76066f75747075740002030201030503010001071102066d656d6f727902000463616c6c00030a2c012a\
+        00410041054110410810001a4110411029030042017c370300410041054110410810014110410810020b\
+        0b0b010041000b05636f756e74";
+    let deploy = serde_json::json!({
+        "sender": "alice",
+        "recipient": "counter",
+        "amount": 1,
+        "contract": {"type": "deploy", "code": code, "gas_limit": 100_000}
+    });
+    let call = serde_json::json!({
+        "sender": "alice",
+        "recipient": "counter",
+        "amount": 2,
+        "contract": {"type": "call", "gas_limit": 100_000}
+    });
+
+    // nodes don't accept contract transactions unless they are enabled
+    let node = ServerBuilder::new().manual_mining().start();
+    let res = node.post_raw("/transactions", &deploy.to_string());
+    assert_eq!(res.status().as_u16(), 400);
+    drop(node);
+
+    let node = ServerBuilder::new().contracts_enabled().start();
+    node.post_raw("/transactions", &deploy.to_string());
+    node.wait_for_block(1);
+    let res = node.get_contract("counter");
+    assert_eq!(res.status().as_u16(), 200);
+
+    let mut res = node.post_raw("/transactions", &call.to_string());
+    let body: TransactionResponse = serde_json::from_str(&res.text().unwrap()).unwrap();
+    let id = serde_json::to_value(body.id).unwrap();
+    node.wait_for_block(2);
+
+    // the call increments the counter in the storage of the contract...
+    let mut res = node.get_contract("counter");
+    let contract: serde_json::Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    assert_eq!(contract["address"], "counter");
+    assert_eq!(contract["code"], code);
+    assert_eq!(contract["storage"]["636f756e74"], "0100000000000000");
+
+    // ...and the receipt of the transaction has what it returned
+    let mut res = node.get_transaction(id.as_str().unwrap());
+    let status: serde_json::Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    assert_eq!(status["receipt"]["success"], true);
+    assert_eq!(status["receipt"]["output"], "0100000000000000");
+    assert!(status["receipt"]["gas_used"].as_u64().unwrap() > 0);
+
+    let res = node.get_contract("nobody");
+    assert_eq!(res.status().as_u16(), 404);
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_issue_and_transfer_tokens() {
+    let node = ServerBuilder::new().start();
+    let issue = serde_json::json!({
+        "sender": "alice",
+        "recipient": "alice",
+        "amount": 0,
+        "token": {"type": "issue", "name": "GOLD", "supply": 100}
+    });
+    let mut res = node.post_raw("/transactions", &issue.to_string());
+    let body: TransactionResponse = serde_json::from_str(&res.text().unwrap()).unwrap();
+    let token_id = serde_json::to_value(body.id).unwrap();
+    node.wait_for_block(1);
+
+    let transfer = serde_json::json!({
+        "sender": "alice",
+        "recipient": "bob",
+        "amount": 0,
+        "token": {"type": "transfer", "token_id": token_id, "amount": 30}
+    });
+    node.post_raw("/transactions", &transfer.to_string());
+    node.wait_for_block(2);
+
+    let tokens = node.get_tokens("alice");
+    assert_eq!(tokens["address"], "alice");
+    assert_eq!(tokens["tokens"][0]["token_id"], token_id);
+    assert_eq!(tokens["tokens"][0]["name"], "GOLD");
+    assert_eq!(tokens["tokens"][0]["balance"], 70);
+    assert_eq!(node.get_tokens("bob")["tokens"][0]["balance"], 30);
+
+    // the supply is fixed, so tokens can't be overdrawn
+    let mut overdraw = transfer.clone();
+    overdraw["token"]["amount"] = 31.into();
+    overdraw["sender"] = "bob".into();
+    let res = node.post_raw("/transactions", &overdraw.to_string());
     assert_eq!(res.status().as_u16(), 400);
+    assert!(node.get_tokens("carol")["tokens"]
+        .as_array()
+        .unwrap()
+        .is_empty());
 }
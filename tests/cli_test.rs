@@ -0,0 +1,242 @@
+mod common;
+
+use std::{env, fs, process::Command};
+
+use assert_cmd::cargo::cargo_bin;
+use serial_test::serial;
+
+use crate::common::{Api, ServerBuilder};
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_send_transactions_from_a_new_wallet() {
+    let node = ServerBuilder::new().port(8000).manual_mining().start();
+
+    let output = run_command(&["wallet", "new"], &[]);
+    let public_key = read_line_value(&output, "public key:");
+    let seed = read_line_value(&output, "seed:");
+
+    // the transaction is signed by the new address
+    run_command(
+        &["wallet", "send", "--to", "2", "--amount", "3"],
+        &[("WALLET_SEED", &seed)],
+    );
+    let pending = node.get_transactions();
+    assert_eq!(pending.as_array().unwrap().len(), 1);
+    assert_eq!(pending[0]["sender"], public_key.as_str());
+    assert!(pending[0]["signature"].is_string());
+
+    let output = run_command(&["mine", "once"], &[]);
+    assert!(output.contains("\"index\": 1"));
+
+    let output = run_command(&["wallet", "balance", "2"], &[]);
+    assert!(output.contains("\"received\": 3"));
+
+    // the seed is mandatory to send transactions
+    let status = Command::new(cargo_bin("rust_blockchain"))
+        .args(["wallet", "send", "--to", "2", "--amount", "3"])
+        .env("PORT", "8000")
+        .env("WALLET_SEED", "")
+        .status()
+        .unwrap();
+    assert!(!status.success());
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_send_to_checksummed_addresses() {
+    let node = ServerBuilder::new().port(8000).manual_mining().start();
+    let output = run_command(&["wallet", "new"], &[]);
+    let seed = read_line_value(&output, "seed:");
+    let output = run_command(&["wallet", "new"], &[]);
+    let address = read_line_value(&output, "address:");
+    let public_key = read_line_value(&output, "public key:");
+    assert!(address.starts_with("rb1"));
+
+    // the transaction goes to the key of the address
+    run_command(
+        &["wallet", "send", "--to", &address, "--amount", "3"],
+        &[("WALLET_SEED", &seed)],
+    );
+    assert_eq!(node.get_transactions()[0]["recipient"], public_key.as_str());
+    let output = run_command(&["wallet", "balance", &address], &[]);
+    assert_eq!(read_line_value(&output, "pending balance:"), "3");
+
+    // and a typo makes the checksum fail instead of sending the amount to nobody
+    let last = if address.ends_with('q') { 'p' } else { 'q' };
+    let typo = format!("{}{}", &address[..address.len() - 1], last);
+    let status = Command::new(cargo_bin("rust_blockchain"))
+        .args(["wallet", "send", "--to", &typo, "--amount", "3"])
+        .env("PORT", "8000")
+        .env("WALLET_SEED", &seed)
+        .status()
+        .unwrap();
+    assert!(!status.success());
+
+    // the node checks them too
+    let body = serde_json::json!({"sender": "alice", "recipient": typo, "amount": 3});
+    let res = node.post_raw("/transactions", &body.to_string());
+    assert_eq!(res.status().as_u16(), 400);
+    assert_eq!(node.get_transactions().as_array().unwrap().len(), 1);
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_send_and_show_amounts_with_decimals() {
+    let node = ServerBuilder::new().port(8000).manual_mining().start();
+    let output = run_command(&["wallet", "new"], &[]);
+    let seed = read_line_value(&output, "seed:");
+
+    // amounts are integers of the smallest unit in the node, the decimals are only for people
+    let decimals = ("AMOUNT_DECIMALS", "2");
+    run_command(
+        &["wallet", "send", "--to", "2", "--amount", "1.5"],
+        &[("WALLET_SEED", &seed), decimals],
+    );
+    assert_eq!(node.get_transactions()[0]["amount"], 150);
+
+    let output = run_command(&["wallet", "balance", "2"], &[decimals]);
+    assert_eq!(read_line_value(&output, "confirmed balance:"), "0.00");
+    assert_eq!(read_line_value(&output, "pending balance:"), "1.50");
+
+    // amounts can't be more precise than the smallest unit
+    let status = Command::new(cargo_bin("rust_blockchain"))
+        .args(["wallet", "send", "--to", "2", "--amount", "1.505"])
+        .env("PORT", "8000")
+        .env("WALLET_SEED", &seed)
+        .env("AMOUNT_DECIMALS", "2")
+        .status()
+        .unwrap();
+    assert!(!status.success());
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_export_and_import_chains() {
+    let source = ServerBuilder::new().port(8000).manual_mining().start();
+    let target = ServerBuilder::new().port(8001).manual_mining().start();
+    source.mine(true);
+    source.mine(true);
+
+    let path = env::temp_dir().join("rust_blockchain_cli_export.json");
+    let path = path.to_str().unwrap();
+    run_command(&["chain", "export", "--output", path], &[]);
+
+    // the genesis block is shared, so only the mined blocks are imported
+    let output = run_command(
+        &["chain", "import", path, "--node", "http://localhost:8001"],
+        &[],
+    );
+    assert!(output.contains("imported 2 of 3 blocks"));
+    assert_eq!(target.get_blocks(), source.get_blocks());
+
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_cosign_multisig_transactions() {
+    let node = ServerBuilder::new().port(8000).manual_mining().start();
+
+    let owners: Vec<(String, String)> = (0..3)
+        .map(|_| {
+            let output = run_command(&["wallet", "new"], &[]);
+            (
+                read_line_value(&output, "address:"),
+                read_line_value(&output, "seed:"),
+            )
+        })
+        .collect();
+    let public_keys: Vec<&str> = owners.iter().map(|(key, _)| key.as_str()).collect();
+    let output = run_command(
+        &[
+            "wallet",
+            "multisig",
+            "--threshold",
+            "2",
+            &public_keys.join(","),
+        ],
+        &[],
+    );
+    let address = read_line_value(&output, "address:");
+
+    // the transaction is only submitted once 2 of the owners signed it
+    let path = env::temp_dir().join("rust_blockchain_cli_multisig.json");
+    let path = path.to_str().unwrap();
+    let transaction = serde_json::json!({
+        "sender": address,
+        "recipient": "bob",
+        "amount": 5,
+        "multisig": {"threshold": 2, "public_keys": public_keys}
+    });
+    fs::write(path, transaction.to_string()).unwrap();
+
+    let output = run_command(
+        &["wallet", "cosign", path],
+        &[("WALLET_SEED", &owners[0].1)],
+    );
+    assert!(output.contains("signed, 1 of 2 signatures"));
+    assert!(node.get_transactions().as_array().unwrap().is_empty());
+
+    let output = run_command(
+        &["wallet", "cosign", path],
+        &[("WALLET_SEED", &owners[2].1)],
+    );
+    assert!(output.contains("submitted transaction"));
+    let pending = node.get_transactions();
+    assert!(pending[0]["sender"].as_str().unwrap().starts_with("ms"));
+    assert_eq!(
+        pending[0]["multisig"]["signatures"]
+            .as_object()
+            .unwrap()
+            .len(),
+        2
+    );
+
+    // not even the node takes unsigned transactions from the address
+    let unsigned = serde_json::json!({"sender": address, "recipient": "bob", "amount": 6});
+    let res = node.post_raw("/transactions", &unsigned.to_string());
+    assert_eq!(res.status().as_u16(), 400);
+
+    node.mine(true);
+    let output = run_command(&["wallet", "balance", &address], &[]);
+    assert_eq!(read_line_value(&output, "confirmed balance:"), "-5");
+
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+#[cfg(unix)]
+fn test_should_reject_unknown_commands() {
+    let status = Command::new(cargo_bin("rust_blockchain"))
+        .args(["wallet", "steal"])
+        .status()
+        .unwrap();
+    assert!(!status.success());
+}
+
+// runs a command against the node in port 8000 and returns its output
+fn run_command(args: &[&str], envs: &[(&str, &str)]) -> String {
+    let output = Command::new(cargo_bin("rust_blockchain"))
+        .args(args)
+        .env("PORT", "8000")
+        .envs(envs.iter().copied())
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+
+    String::from_utf8(output.stdout).unwrap()
+}
+
+fn read_line_value(output: &str, prefix: &str) -> String {
+    let line = output
+        .lines()
+        .find(|line| line.starts_with(prefix))
+        .unwrap();
+    line[prefix.len()..].trim().to_string()
+}
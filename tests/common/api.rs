@@ -1,19 +1,67 @@
+use crypto::{digest::Digest, ed25519, sha2::Sha256};
 use ethereum_types::U256;
-use isahc::{Body, ReadResponseExt, Request, Response};
-use serde::{Deserialize, Serialize};
+use isahc::{
+    config::{CaCertificate, Configurable},
+    http::request::Builder as RequestBuilder,
+    Body, ReadResponseExt, Request, Response,
+};
+use serde::{de, de::DeserializeOwned, Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
 
 use super::server::Server;
 
-pub type BlockHash = U256;
+// Hashes and ids exactly as the api must show them: 64 lowercase hex digits
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BlockHash(pub U256);
+pub type TransactionId = BlockHash;
+
+impl Serialize for BlockHash {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut bytes = [0; 32];
+        self.0.to_big_endian(&mut bytes);
+        serializer.serialize_str(&hex::encode(bytes))
+    }
+}
+
+impl<'de> Deserialize<'de> for BlockHash {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        let is_canonical = value.len() == 64
+            && value
+                .chars()
+                .all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c));
+        if !is_canonical {
+            return Err(de::Error::custom(format!(
+                "not a canonical hash `{}`",
+                value
+            )));
+        }
+
+        Ok(BlockHash(U256::from_big_endian(
+            &hex::decode(value).unwrap(),
+        )))
+    }
+}
+pub use ethereum_types::Bloom;
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct Block {
     pub index: u64,
     pub timestamp: i64,
     pub nonce: u64,
+    pub bits: u32,
     pub previous_hash: BlockHash,
+    pub merkle_root: BlockHash,
+    pub bloom: Bloom,
     pub hash: BlockHash,
+    pub state_root: BlockHash,
     pub transactions: Vec<Transaction>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -21,58 +69,322 @@ pub struct Transaction {
     pub sender: String,
     pub recipient: String,
     pub amount: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+}
+
+fn push_str(bytes: &mut Vec<u8>, value: &str) {
+    bytes.extend_from_slice(&(value.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(value.as_bytes());
+}
+
+fn sha256(bytes: &[u8]) -> [u8; 32] {
+    let mut hash = [0; 32];
+    let mut hasher = Sha256::new();
+    hasher.input(bytes);
+    hasher.result(&mut hash);
+    hash
+}
+
+#[allow(dead_code)]
+impl Transaction {
+    // Sign the transaction as a wallet outside of the node would do, for the chain with "chain_id"
+    // The sender becomes the public key matching the seed
+    pub fn sign(&mut self, seed: &[u8], chain_id: &str) {
+        let (secret_key, public_key) = ed25519::keypair(seed);
+        self.sender = hex::encode(public_key);
+        self.signature = None;
+
+        // the id is the hash of the canonical encoding of the transaction: the kind of value,
+        // the strings preceded by their length and the amount, then a zero byte for each missing
+        // action (multisig, contract and token)
+        let mut bytes = Vec::new();
+        for value in [
+            "rust-blockchain/transaction/v1",
+            &self.sender,
+            &self.recipient,
+        ] {
+            push_str(&mut bytes, value);
+        }
+        bytes.extend_from_slice(&self.amount.to_be_bytes());
+        bytes.extend_from_slice(&[0, 0, 0]);
+        let id = sha256(&bytes);
+
+        // and the signature is made over the hash of the id for the chain
+        let mut bytes = Vec::new();
+        push_str(&mut bytes, "rust-blockchain/signature/v1");
+        push_str(&mut bytes, chain_id);
+        bytes.extend_from_slice(&id);
+        let message = sha256(&bytes);
+
+        let signature = ed25519::signature(&message, &secret_key);
+        self.signature = Some(hex::encode(&signature[..]));
+    }
 }
 
+#[allow(dead_code)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TransactionResponse {
+    pub id: TransactionId,
+}
+
+#[allow(dead_code)]
 pub trait Api {
+    fn get_readiness(&self) -> Response<Body>;
+    fn get_status(&self) -> Value;
+    fn get_next_bits(&self) -> u32;
+    fn get_wallet(&self) -> Value;
+    fn get_balance(&self, address: &str) -> Value;
+    fn get_safe_balance(&self, address: &str) -> Value;
+    fn get_snapshot(&self) -> Value;
+    fn get_miner_stats(&self) -> Value;
+    fn get_peers(&self) -> Value;
+    fn get_block_template(&self, longpoll_id: Option<&str>) -> Value;
     fn get_blocks(&self) -> Vec<Block>;
+    fn get_safe_blocks(&self) -> Vec<Block>;
+    fn get_blocks_page(&self, query: &str) -> Response<Body>;
+    fn get_block(&self, id: &str) -> Response<Body>;
     fn get_last_block(&self) -> Block;
+    fn wait_for_block(&self, index: u64) -> Block;
     fn add_block(&self, block: &Block) -> Response<Body>;
     fn add_valid_block(&self) -> Response<Body>;
+    fn get_raw(&self, path: &str) -> Response<Body>;
+    fn post_raw(&self, path: &str, body: &str) -> Response<Body>;
+    fn graphql(&self, query: &str, variables: Value) -> Value;
+    fn rpc(&self, request: Value) -> Value;
+    fn mine(&self, wait: bool) -> Response<Body>;
     fn add_transaction(&self, transaction: &Transaction) -> Response<Body>;
+    fn get_transactions(&self) -> Value;
+    fn get_transaction(&self, id: &str) -> Response<Body>;
+    fn get_transaction_status(&self, id: &str) -> Response<Body>;
+    fn get_transaction_proof(&self, id: &str) -> Response<Body>;
+    fn get_contract(&self, address: &str) -> Response<Body>;
+    fn get_tokens(&self, address: &str) -> Value;
+    fn get_address_blocks(&self, address: &str, since: u64) -> Value;
+    fn get_address_transactions(&self, address: &str, from: u64, limit: u64) -> Value;
+    fn get_safe_address_transactions(&self, address: &str) -> Value;
+    fn add_subscription(&self, url: &str, addresses: &[&str]) -> Response<Body>;
+    fn add_peer(&self, address: &str) -> Response<Body>;
+    fn ban_peer(&self, id: &str) -> Response<Body>;
 }
 
 impl Api for Server {
+    fn get_readiness(&self) -> Response<Body> {
+        let uri = format!("{}/ready", get_base_url(self));
+        get_request(self, uri)
+    }
+
+    fn get_status(&self) -> Value {
+        let uri = format!("{}/status", get_base_url(self));
+        get_json(self, uri)
+    }
+
+    fn get_next_bits(&self) -> u32 {
+        self.get_status()["next_bits"].as_u64().unwrap() as u32
+    }
+
+    fn get_wallet(&self) -> Value {
+        let uri = format!("{}/wallet", get_base_url(self));
+        get_json(self, uri)
+    }
+
+    fn get_block_template(&self, longpoll_id: Option<&str>) -> Value {
+        let uri = match longpoll_id {
+            Some(id) => format!("{}/blocks/template?longpoll_id={}", get_base_url(self), id),
+            None => format!("{}/blocks/template", get_base_url(self)),
+        };
+        get_json(self, uri)
+    }
+
+    fn get_balance(&self, address: &str) -> Value {
+        let uri = format!("{}/addresses/{}/balance", get_base_url(self), address);
+        get_json(self, uri)
+    }
+
+    fn get_safe_balance(&self, address: &str) -> Value {
+        let uri = format!(
+            "{}/addresses/{}/balance?at=safe",
+            get_base_url(self),
+            address
+        );
+        get_json(self, uri)
+    }
+
+    fn get_snapshot(&self) -> Value {
+        let uri = format!("{}/snapshot", get_base_url(self));
+        get_json(self, uri)
+    }
+
+    fn get_miner_stats(&self) -> Value {
+        let uri = format!("{}/miner/stats", get_base_url(self));
+        get_json(self, uri)
+    }
+
+    fn get_peers(&self) -> Value {
+        let uri = format!("{}/peers", get_base_url(self));
+        get_json(self, uri)
+    }
+
     fn get_blocks(&self) -> Vec<Block> {
         // list the blocks by querying the REST API
         let uri = format!("{}/blocks", get_base_url(self));
-        let mut response = isahc::get(uri).unwrap();
+        get_json(self, uri)
+    }
 
-        // check that the response is sucessful
-        assert_eq!(response.status().as_u16(), 200);
+    fn get_safe_blocks(&self) -> Vec<Block> {
+        let uri = format!("{}/blocks?at=safe", get_base_url(self));
+        get_json(self, uri)
+    }
 
-        // parse the list of blocks from the response body
-        let raw_body = response.text().unwrap();
-        let blocks: Vec<Block> = serde_json::from_str(&raw_body).unwrap();
+    fn get_blocks_page(&self, query: &str) -> Response<Body> {
+        let uri = format!("{}/blocks?{}", get_base_url(self), query);
+        get_request(self, uri)
+    }
 
-        blocks
+    fn get_block(&self, id: &str) -> Response<Body> {
+        let uri = format!("{}/blocks/{}", get_base_url(self), id);
+        get_request(self, uri)
     }
 
     fn get_last_block(&self) -> Block {
         self.get_blocks().last().unwrap().to_owned()
     }
 
+    // The miner logs new blocks right before adding them, so we poll until they are in the chain
+    fn wait_for_block(&self, index: u64) -> Block {
+        let start = Instant::now();
+        while self.get_last_block().index < index && start.elapsed() < Duration::from_secs(1) {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        self.get_last_block()
+    }
+
     fn add_valid_block(&self) -> Response<Body> {
         let last_block = self.get_last_block();
         let valid_block = Block {
             index: last_block.index + 1,
-            timestamp: 0,
+            // it must be after the median of the last blocks, which is never after the last one
+            timestamp: last_block.timestamp + 1,
             nonce: 0,
+            // the target is checked
+            bits: self.get_next_bits(),
             // the previous hash is checked
             previous_hash: last_block.hash,
             // the api automatically recalculates the hash...
             // ...so no need to add a valid one here
             hash: BlockHash::default(),
+            // same for the merkle root and the bloom
+            merkle_root: BlockHash::default(),
+            bloom: Bloom::default(),
+            // there are no transactions, so the state doesn't change
+            state_root: last_block.state_root,
             transactions: [].to_vec(),
+            signature: None,
         };
         self.add_block(&valid_block)
     }
 
+    fn mine(&self, wait: bool) -> Response<Body> {
+        let uri = format!("{}/mine?wait={}", get_base_url(self), wait);
+        post_request(self, uri, String::new())
+    }
+
+    fn get_raw(&self, path: &str) -> Response<Body> {
+        let uri = format!("{}{}", get_base_url(self), path);
+        get_request(self, uri)
+    }
+
+    fn post_raw(&self, path: &str, body: &str) -> Response<Body> {
+        let uri = format!("{}{}", get_base_url(self), path);
+        post_request(self, uri, body.to_string())
+    }
+
+    fn graphql(&self, query: &str, variables: Value) -> Value {
+        let uri = format!("{}/graphql", get_base_url(self));
+        let body = serde_json::json!({ "query": query, "variables": variables });
+        let mut response = post_request(self, uri, body.to_string());
+
+        // errors are reported in the body, like any other result
+        assert_eq!(response.status().as_u16(), 200);
+        serde_json::from_str(&response.text().unwrap()).unwrap()
+    }
+
+    fn rpc(&self, request: Value) -> Value {
+        let uri = format!("{}/rpc", get_base_url(self));
+        let mut response = post_request(self, uri, request.to_string());
+
+        // like in GraphQL, errors are reported in the body
+        assert_eq!(response.status().as_u16(), 200);
+        serde_json::from_str(&response.text().unwrap()).unwrap()
+    }
+
     fn add_block(&self, block: &Block) -> Response<Body> {
         // send the request to the REST API
         let uri = format!("{}/blocks", get_base_url(self));
         let body = serde_json::to_string(&block).unwrap();
 
-        post_request(uri, body)
+        post_request(self, uri, body)
+    }
+
+    fn get_transactions(&self) -> Value {
+        let uri = format!("{}/transactions", get_base_url(self));
+        get_json(self, uri)
+    }
+
+    fn get_transaction(&self, id: &str) -> Response<Body> {
+        let uri = format!("{}/transactions/{}", get_base_url(self), id);
+        get_request(self, uri)
+    }
+
+    fn get_transaction_status(&self, id: &str) -> Response<Body> {
+        let uri = format!("{}/transactions/{}/status", get_base_url(self), id);
+        get_request(self, uri)
+    }
+
+    fn get_transaction_proof(&self, id: &str) -> Response<Body> {
+        let uri = format!("{}/transactions/{}/proof", get_base_url(self), id);
+        get_request(self, uri)
+    }
+
+    fn get_contract(&self, address: &str) -> Response<Body> {
+        let uri = format!("{}/contracts/{}", get_base_url(self), address);
+        get_request(self, uri)
+    }
+
+    fn get_tokens(&self, address: &str) -> Value {
+        let uri = format!("{}/addresses/{}/tokens", get_base_url(self), address);
+        get_json(self, uri)
+    }
+
+    fn get_address_transactions(&self, address: &str, from: u64, limit: u64) -> Value {
+        let uri = format!(
+            "{}/addresses/{}/transactions?from={}&limit={}",
+            get_base_url(self),
+            address,
+            from,
+            limit
+        );
+        get_json(self, uri)
+    }
+
+    fn get_safe_address_transactions(&self, address: &str) -> Value {
+        let uri = format!(
+            "{}/addresses/{}/transactions?at=safe",
+            get_base_url(self),
+            address
+        );
+        get_json(self, uri)
+    }
+
+    fn get_address_blocks(&self, address: &str, since: u64) -> Value {
+        let uri = format!(
+            "{}/addresses/{}/blocks?since={}",
+            get_base_url(self),
+            address,
+            since
+        );
+        get_json(self, uri)
     }
 
     fn add_transaction(&self, transaction: &Transaction) -> Response<Body> {
@@ -80,19 +392,81 @@ impl Api for Server {
         let uri = format!("{}/transactions", get_base_url(self));
         let body = serde_json::to_string(&transaction).unwrap();
 
-        post_request(uri, body)
+        post_request(self, uri, body)
+    }
+
+    fn add_subscription(&self, url: &str, addresses: &[&str]) -> Response<Body> {
+        let uri = format!("{}/subscriptions", get_base_url(self));
+        let body = serde_json::json!({ "url": url, "addresses": addresses }).to_string();
+
+        post_request(self, uri, body)
+    }
+
+    fn add_peer(&self, address: &str) -> Response<Body> {
+        let uri = format!("{}/peers", get_base_url(self));
+        let body = serde_json::json!({ "address": address }).to_string();
+
+        post_request(self, uri, body)
+    }
+
+    fn ban_peer(&self, id: &str) -> Response<Body> {
+        let uri = format!("{}/peers/{}", get_base_url(self), id);
+        delete_request(self, uri)
     }
 }
 
 fn get_base_url(server: &Server) -> String {
-    format!("http://localhost:{}", server.config.port)
+    match server.config.tls_cert_path.is_empty() {
+        true => format!("http://localhost:{}", server.config.port),
+        false => format!("https://localhost:{}", server.config.port),
+    }
 }
 
-fn post_request(uri: String, body: String) -> Response<Body> {
-    let request = Request::post(uri)
+fn get_json<T: DeserializeOwned>(server: &Server, uri: String) -> T {
+    let mut response = get_request(server, uri);
+
+    // check that the response is sucessful
+    assert_eq!(response.status().as_u16(), 200);
+
+    // parse the value from the response body
+    let raw_body = response.text().unwrap();
+    serde_json::from_str(&raw_body).unwrap()
+}
+
+fn get_request(server: &Server, uri: String) -> Response<Body> {
+    let request = authenticated(server, Request::get(uri)).body(()).unwrap();
+
+    isahc::send(request).unwrap()
+}
+
+fn post_request(server: &Server, uri: String, body: String) -> Response<Body> {
+    let request = authenticated(server, Request::post(uri))
         .header("Content-Type", "application/json")
         .body(body)
         .unwrap();
 
     isahc::send(request).unwrap()
 }
+
+fn delete_request(server: &Server, uri: String) -> Response<Body> {
+    let request = authenticated(server, Request::delete(uri))
+        .body(())
+        .unwrap();
+
+    isahc::send(request).unwrap()
+}
+
+// Requests carry the api key of the node, if it has one
+fn authenticated(server: &Server, mut request: RequestBuilder) -> RequestBuilder {
+    // the certificate of the node is self-signed, so it's the only one to trust
+    if !server.config.tls_cert_path.is_empty() {
+        let certificate = CaCertificate::file(&server.config.tls_cert_path);
+        request = request.ssl_ca_certificate(certificate);
+    }
+
+    if server.config.api_key.is_empty() {
+        return request;
+    }
+
+    request.header("X-Api-Key", &server.config.api_key)
+}
@@ -0,0 +1,372 @@
+use std::{convert::TryInto, time::Duration};
+
+use bytes::Bytes;
+use ethereum_types::{Bloom, U256};
+use h2::{
+    client::{self, SendRequest},
+    RecvStream,
+};
+use http::{HeaderMap, Request};
+use tokio::{net::TcpStream, runtime::Runtime, time::timeout};
+
+use super::{server::Server, Block, BlockHash, Transaction, TransactionId};
+
+// Max time to wait for a response or a streamed message from the server
+const READ_TIMEOUT_MS: u64 = 2000;
+
+// Status of a failed call, the code is one of the gRPC status codes
+#[derive(Debug, PartialEq)]
+pub struct GrpcStatus {
+    pub code: u32,
+    pub message: String,
+}
+
+// Minimal gRPC client for the service in "proto/node.proto", messages are encoded by hand
+pub struct GrpcClient {
+    runtime: Runtime,
+    sender: SendRequest<Bytes>,
+    api_key: String,
+    // response of the last streaming call, read one message at a time
+    stream: Option<RecvStream>,
+    buffer: Vec<u8>,
+}
+
+#[allow(dead_code)]
+impl GrpcClient {
+    // Connects to the gRPC api of the node, sending its api key (if any) on every call
+    pub fn connect(server: &Server) -> GrpcClient {
+        let mut client = GrpcClient::connect_without_key(server.config.grpc_port);
+        client.api_key = server.config.api_key.clone();
+        client
+    }
+
+    pub fn connect_without_key(port: u16) -> GrpcClient {
+        let mut runtime = tokio::runtime::Builder::new()
+            .basic_scheduler()
+            .enable_all()
+            .build()
+            .unwrap();
+
+        let sender = runtime.block_on(async {
+            let socket = TcpStream::connect(("localhost", port)).await.unwrap();
+            let (sender, connection) = client::handshake(socket).await.unwrap();
+            tokio::spawn(async move {
+                let _ = connection.await;
+            });
+            sender
+        });
+
+        GrpcClient {
+            runtime,
+            sender,
+            api_key: String::new(),
+            stream: None,
+            buffer: Vec::new(),
+        }
+    }
+
+    pub fn get_status(&mut self) -> Result<Vec<Field>, GrpcStatus> {
+        self.call("GetStatus", &[]).map(|message| decode(&message))
+    }
+
+    pub fn submit_transaction(
+        &mut self,
+        transaction: &Transaction,
+    ) -> Result<TransactionId, GrpcStatus> {
+        let message = self.call("SubmitTransaction", &encode_transaction(transaction))?;
+        Ok(BlockHash(U256::from_big_endian(
+            decode(&message)[0].bytes(),
+        )))
+    }
+
+    pub fn get_block(&mut self, index: u64) -> Result<Block, GrpcStatus> {
+        let mut request = Vec::new();
+        put_uint64(&mut request, 1, index);
+
+        let message = self.call("GetBlock", &request)?;
+        Ok(decode_block(&message))
+    }
+
+    pub fn get_transaction(&mut self, id: TransactionId) -> Result<Vec<Field>, GrpcStatus> {
+        let mut id_bytes = [0; 32];
+        id.0.to_big_endian(&mut id_bytes);
+        let mut request = Vec::new();
+        put_bytes(&mut request, 1, &id_bytes);
+
+        self.call("GetTransaction", &request)
+            .map(|message| decode(&message))
+    }
+
+    pub fn get_balance(&mut self, address: &str) -> Result<Vec<Field>, GrpcStatus> {
+        let mut request = Vec::new();
+        put_bytes(&mut request, 1, address.as_bytes());
+
+        self.call("GetBalance", &request)
+            .map(|message| decode(&message))
+    }
+
+    // Starts streaming blocks, read them with "next_streamed_block"
+    pub fn stream_blocks(&mut self, from_index: Option<u64>) {
+        let mut request = Vec::new();
+        if let Some(from_index) = from_index {
+            put_uint64(&mut request, 1, from_index);
+        }
+
+        let (body, _) = self.send("StreamBlocks", &request).unwrap();
+        self.stream = Some(body);
+        self.buffer.clear();
+    }
+
+    // Blocks until the next block of the stream arrives, panicking if it takes too long
+    pub fn next_streamed_block(&mut self) -> Block {
+        let stream = self.stream.as_mut().unwrap();
+        let buffer = &mut self.buffer;
+
+        let message = self.runtime.block_on(async {
+            loop {
+                if let Some(message) = take_message(buffer) {
+                    return message;
+                }
+
+                let wait = Duration::from_millis(READ_TIMEOUT_MS);
+                let data = timeout(wait, stream.data()).await.unwrap();
+                let data = data.expect("the stream ended").unwrap();
+                let _ = stream.flow_control().release_capacity(data.len());
+                buffer.extend_from_slice(&data);
+            }
+        });
+
+        decode_block(&message)
+    }
+
+    // Sends a unary call and waits for its single response message
+    fn call(&mut self, method: &str, request: &[u8]) -> Result<Vec<u8>, GrpcStatus> {
+        let (mut body, headers) = self.send(method, request)?;
+
+        self.runtime.block_on(async {
+            let mut buffer = Vec::new();
+            while let Some(data) = body.data().await {
+                let data = data.unwrap();
+                let _ = body.flow_control().release_capacity(data.len());
+                buffer.extend_from_slice(&data);
+            }
+
+            let trailers = body.trailers().await.unwrap().unwrap_or(headers);
+            check_status(&trailers)?;
+            Ok(take_message(&mut buffer).expect("missing response message"))
+        })
+    }
+
+    // Returns the body of the response, or the status if the server answered with trailers only
+    fn send(
+        &mut self,
+        method: &str,
+        request: &[u8],
+    ) -> Result<(RecvStream, HeaderMap), GrpcStatus> {
+        let mut builder = Request::post(format!("http://localhost/blockchain.v1.Node/{}", method))
+            .header("content-type", "application/grpc")
+            .header("te", "trailers");
+        if !self.api_key.is_empty() {
+            builder = builder.header("x-api-key", &self.api_key);
+        }
+        let http_request = builder.body(()).unwrap();
+
+        let mut frame = vec![0];
+        frame.extend_from_slice(&(request.len() as u32).to_be_bytes());
+        frame.extend_from_slice(request);
+
+        let sender = self.sender.clone();
+        let response = self.runtime.block_on(async move {
+            let mut sender = sender.ready().await.unwrap();
+            let (response, mut stream) = sender.send_request(http_request, false).unwrap();
+            stream.send_data(Bytes::from(frame), true).unwrap();
+
+            let wait = Duration::from_millis(READ_TIMEOUT_MS);
+            timeout(wait, response).await.unwrap().unwrap()
+        });
+        assert_eq!(response.status().as_u16(), 200);
+
+        let (parts, body) = response.into_parts();
+        if parts.headers.contains_key("grpc-status") {
+            check_status(&parts.headers)?;
+        }
+
+        Ok((body, parts.headers))
+    }
+}
+
+fn check_status(headers: &HeaderMap) -> Result<(), GrpcStatus> {
+    let code: u32 = headers["grpc-status"].to_str().unwrap().parse().unwrap();
+    if code == 0 {
+        return Ok(());
+    }
+
+    let message = headers
+        .get("grpc-message")
+        .map(|value| value.to_str().unwrap().to_string())
+        .unwrap_or_default();
+    Err(GrpcStatus { code, message })
+}
+
+// Removes the first complete message from the buffer, if there is one
+fn take_message(buffer: &mut Vec<u8>) -> Option<Vec<u8>> {
+    if buffer.len() < 5 {
+        return None;
+    }
+
+    let length = u32::from_be_bytes(buffer[1..5].try_into().unwrap()) as usize;
+    if buffer.len() < 5 + length {
+        return None;
+    }
+
+    let message = buffer[5..5 + length].to_vec();
+    buffer.drain(..5 + length);
+    Some(message)
+}
+
+// A decoded protobuf field, by number and with either a number or bytes as value
+#[derive(Debug, Clone, PartialEq)]
+pub struct Field {
+    pub number: u32,
+    pub value: FieldValue,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldValue {
+    Varint(u64),
+    Bytes(Vec<u8>),
+}
+
+#[allow(dead_code)]
+impl Field {
+    pub fn uint64(&self) -> u64 {
+        match self.value {
+            FieldValue::Varint(value) => value,
+            _ => panic!("field {} is not a number", self.number),
+        }
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        match &self.value {
+            FieldValue::Bytes(bytes) => bytes,
+            _ => panic!("field {} is not length delimited", self.number),
+        }
+    }
+
+    pub fn string(&self) -> String {
+        String::from_utf8(self.bytes().to_vec()).unwrap()
+    }
+
+    pub fn message(&self) -> Vec<Field> {
+        decode(self.bytes())
+    }
+}
+
+// Returns the first field with a number in a decoded message
+#[allow(dead_code)]
+pub fn field(fields: &[Field], number: u32) -> Option<&Field> {
+    fields.iter().find(|field| field.number == number)
+}
+
+// Only the wire types used by the node are supported (varints and length delimited)
+pub fn decode(mut bytes: &[u8]) -> Vec<Field> {
+    let mut fields = Vec::new();
+    while !bytes.is_empty() {
+        let key = read_varint(&mut bytes);
+        let number = (key >> 3) as u32;
+        let value = match key & 0x7 {
+            0 => FieldValue::Varint(read_varint(&mut bytes)),
+            2 => {
+                let length = read_varint(&mut bytes) as usize;
+                let (value, rest) = bytes.split_at(length);
+                bytes = rest;
+                FieldValue::Bytes(value.to_vec())
+            }
+            wire_type => panic!("unexpected wire type {}", wire_type),
+        };
+        fields.push(Field { number, value });
+    }
+    fields
+}
+
+fn read_varint(bytes: &mut &[u8]) -> u64 {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = bytes[0];
+        *bytes = &bytes[1..];
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte < 0x80 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+fn put_varint(buffer: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buffer.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+fn put_uint64(buffer: &mut Vec<u8>, number: u32, value: u64) {
+    put_varint(buffer, (number as u64) << 3);
+    put_varint(buffer, value);
+}
+
+fn put_bytes(buffer: &mut Vec<u8>, number: u32, value: &[u8]) {
+    put_varint(buffer, ((number as u64) << 3) | 2);
+    put_varint(buffer, value.len() as u64);
+    buffer.extend_from_slice(value);
+}
+
+fn encode_transaction(transaction: &Transaction) -> Vec<u8> {
+    let mut buffer = Vec::new();
+    put_bytes(&mut buffer, 1, transaction.sender.as_bytes());
+    put_bytes(&mut buffer, 2, transaction.recipient.as_bytes());
+    put_uint64(&mut buffer, 3, transaction.amount);
+    if let Some(signature) = &transaction.signature {
+        put_bytes(&mut buffer, 4, signature.as_bytes());
+    }
+    buffer
+}
+
+fn decode_transaction(fields: &[Field]) -> Transaction {
+    Transaction {
+        sender: field(fields, 1).unwrap().string(),
+        recipient: field(fields, 2).unwrap().string(),
+        amount: field(fields, 3).unwrap().uint64(),
+        signature: field(fields, 4)
+            .map(Field::string)
+            .filter(|signature| !signature.is_empty()),
+    }
+}
+
+fn hash(bytes: &[u8]) -> BlockHash {
+    BlockHash(U256::from_big_endian(bytes))
+}
+
+fn decode_block(message: &[u8]) -> Block {
+    let fields = decode(message);
+    Block {
+        index: field(&fields, 1).unwrap().uint64(),
+        timestamp: field(&fields, 2).unwrap().uint64() as i64,
+        nonce: field(&fields, 3).unwrap().uint64(),
+        bits: field(&fields, 4).unwrap().uint64() as u32,
+        previous_hash: hash(field(&fields, 5).unwrap().bytes()),
+        hash: hash(field(&fields, 6).unwrap().bytes()),
+        merkle_root: hash(field(&fields, 11).unwrap().bytes()),
+        bloom: Bloom::from_slice(field(&fields, 12).unwrap().bytes()),
+        state_root: hash(field(&fields, 10).unwrap().bytes()),
+        transactions: fields
+            .iter()
+            .filter(|field| field.number == 7)
+            .map(|field| decode_transaction(&field.message()))
+            .collect(),
+        signature: field(&fields, 8)
+            .map(Field::string)
+            .filter(|signature| !signature.is_empty()),
+    }
+}
@@ -1,5 +1,14 @@
 mod api;
+mod grpc;
 mod server;
+mod webhook;
+mod websocket;
 
 pub use api::*;
+#[allow(unused_imports)]
+pub use grpc::*;
 pub use server::*;
+#[allow(unused_imports)]
+pub use webhook::*;
+#[allow(unused_imports)]
+pub use websocket::*;
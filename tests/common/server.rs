@@ -13,14 +13,48 @@ use nix::{
     unistd::Pid,
 };
 
+#[allow(dead_code)]
 pub struct Config {
     pub port: u16,
+    pub listen_addresses: Vec<String>,
+    pub data_dir: String,
+    pub prune_depth: u64,
+    pub snapshot_path: String,
     pub peers: Vec<String>,
     pub peer_sync_ms: u64,
+    pub auto_mining: bool,
     pub max_blocks: u64,
     pub max_nonce: u64,
     pub difficulty: u32,
     pub tx_waiting_ms: u64,
+    pub shutdown_drain_ms: u64,
+    pub shutdown_timeout_secs: u64,
+    pub byzantine_behaviors: Vec<String>,
+    pub finality_depth: u64,
+    pub contracts_enabled: bool,
+    pub max_time_drift_secs: u64,
+    pub amount_decimals: u32,
+    pub consensus: String,
+    pub poa_signers: Vec<String>,
+    pub poa_signer_seed: String,
+    pub wallet_mode: String,
+    pub wallet_addresses: Vec<String>,
+    pub p2p_port: u16,
+    pub p2p_peers: Vec<String>,
+    pub p2p_seeds: Vec<String>,
+    pub p2p_peer_book: String,
+    pub light_client: bool,
+    pub chain_id: String,
+    pub api_key: String,
+    pub api_public_reads: bool,
+    pub api_max_body_bytes: usize,
+    pub rate_limit_per_sec: u32,
+    pub rate_limit_burst: u32,
+    pub cors_allowed_origins: String,
+    pub grpc_port: u16,
+    pub tls_cert_path: String,
+    pub tls_key_path: String,
+    pub log_level: String,
 }
 
 pub struct ServerBuilder {
@@ -33,6 +67,12 @@ impl ServerBuilder {
         // set the default values
         let config = Config {
             port: 8000,
+            // only localhost, on the port
+            listen_addresses: Vec::<String>::new(),
+            // the chain is only kept in memory by default
+            data_dir: String::new(),
+            prune_depth: 0,
+            snapshot_path: String::new(),
             // not to high to avoid waiting too much, not too shot to spam it
             peer_sync_ms: 10,
             // no difficulty to minimize the mining time
@@ -40,8 +80,48 @@ impl ServerBuilder {
             // not to high to avoid waiting, not too shot to spam it
             tx_waiting_ms: 10,
             peers: Vec::<String>::new(),
+            auto_mining: true,
             max_blocks: 0, // unlimited blocks
             max_nonce: 0,  // unlimited nonce
+            // not to high to avoid waiting too much when stopping the server
+            shutdown_drain_ms: 10,
+            // idle keep-alive connections would make us wait for the whole timeout
+            shutdown_timeout_secs: 1,
+            // honest node by default
+            byzantine_behaviors: Vec::<String>::new(),
+            finality_depth: 6,
+            contracts_enabled: false,
+            max_time_drift_secs: 7200,
+            amount_decimals: 0,
+            // proof of work by default
+            consensus: "pow".to_string(),
+            poa_signers: Vec::<String>::new(),
+            poa_signer_seed: String::new(),
+            // unsigned transactions are accepted by default
+            wallet_mode: "hot".to_string(),
+            wallet_addresses: Vec::<String>::new(),
+            // no p2p networking by default
+            p2p_port: 0,
+            p2p_peers: Vec::<String>::new(),
+            p2p_seeds: Vec::<String>::new(),
+            p2p_peer_book: String::new(),
+            light_client: false,
+            chain_id: "main".to_string(),
+            // no authentication by default
+            api_key: String::new(),
+            api_public_reads: true,
+            api_max_body_bytes: 1024 * 1024,
+            // tests send requests as fast as they can
+            rate_limit_per_sec: 0,
+            rate_limit_burst: 1,
+            cors_allowed_origins: String::new(),
+            // no gRPC api by default
+            grpc_port: 0,
+            // plain HTTP by default
+            tls_cert_path: String::new(),
+            tls_key_path: String::new(),
+            // the tests wait for messages logged at the info level
+            log_level: "info".to_string(),
         };
 
         ServerBuilder { config }
@@ -52,17 +132,164 @@ impl ServerBuilder {
         self
     }
 
+    // blocks are only mined when requested through the api
+    pub fn manual_mining(mut self) -> ServerBuilder {
+        self.config.auto_mining = false;
+        self
+    }
+
     pub fn port(mut self, port: u16) -> ServerBuilder {
         self.config.port = port;
         self
     }
 
+    // listen on these addresses ("host:port") instead of localhost
+    pub fn listen_address(mut self, address: &str) -> ServerBuilder {
+        self.config.listen_addresses.push(address.to_string());
+        self
+    }
+
     pub fn peer(mut self, port: u64) -> ServerBuilder {
         let address = format!("http://localhost:{}", port);
         self.config.peers.push(address);
         self
     }
 
+    pub fn shutdown_drain_ms(mut self, shutdown_drain_ms: u64) -> ServerBuilder {
+        self.config.shutdown_drain_ms = shutdown_drain_ms;
+        self
+    }
+
+    pub fn finality_depth(mut self, finality_depth: u64) -> ServerBuilder {
+        self.config.finality_depth = finality_depth;
+        self
+    }
+
+    pub fn contracts_enabled(mut self) -> ServerBuilder {
+        self.config.contracts_enabled = true;
+        self
+    }
+
+    // use proof of authority with the given signer set, signing blocks if a seed is present
+    pub fn proof_of_authority(mut self, signers: &[&str], seed: &str) -> ServerBuilder {
+        self.config.consensus = "poa".to_string();
+        self.config.poa_signers = signers.iter().map(|signer| signer.to_string()).collect();
+        self.config.poa_signer_seed = seed.to_string();
+        self
+    }
+
+    // only keep the viewing keys of the wallet, so transactions must be signed externally
+    pub fn cold_wallet(mut self, addresses: &[&str]) -> ServerBuilder {
+        self.config.wallet_mode = "cold".to_string();
+        self.config.wallet_addresses = addresses.iter().map(|a| a.to_string()).collect();
+        self
+    }
+
+    // listen for p2p connections in a port
+    pub fn p2p_port(mut self, port: u16) -> ServerBuilder {
+        self.config.p2p_port = port;
+        self
+    }
+
+    // connect to the p2p port of another node
+    pub fn p2p_peer(mut self, port: u16) -> ServerBuilder {
+        let address = format!("localhost:{}", port);
+        self.config.p2p_peers.push(address);
+        self
+    }
+
+    // join the p2p network through the node listening in a port
+    pub fn p2p_seed(mut self, port: u16) -> ServerBuilder {
+        let address = format!("localhost:{}", port);
+        self.config.p2p_seeds.push(address);
+        self
+    }
+
+    // only follow the headers of the chain of the p2p peers
+    pub fn light_client(mut self) -> ServerBuilder {
+        self.config.light_client = true;
+        self
+    }
+
+    // save the known p2p peers in a file, to remember them across restarts
+    pub fn data_dir(mut self, path: &str) -> ServerBuilder {
+        self.config.data_dir = path.to_string();
+        self
+    }
+
+    pub fn prune_depth(mut self, depth: u64) -> ServerBuilder {
+        self.config.prune_depth = depth;
+        self
+    }
+
+    pub fn snapshot_path(mut self, path: &str) -> ServerBuilder {
+        self.config.snapshot_path = path.to_string();
+        self
+    }
+
+    pub fn p2p_peer_book(mut self, path: &str) -> ServerBuilder {
+        self.config.p2p_peer_book = path.to_string();
+        self
+    }
+
+    // join a different p2p network
+    pub fn chain_id(mut self, chain_id: &str) -> ServerBuilder {
+        self.config.chain_id = chain_id.to_string();
+        self
+    }
+
+    pub fn api_key(mut self, api_key: &str) -> ServerBuilder {
+        self.config.api_key = api_key.to_string();
+        self
+    }
+
+    // the api key is also needed to read from the node
+    pub fn private_reads(mut self) -> ServerBuilder {
+        self.config.api_public_reads = false;
+        self
+    }
+
+    pub fn max_body_bytes(mut self, max_body_bytes: usize) -> ServerBuilder {
+        self.config.api_max_body_bytes = max_body_bytes;
+        self
+    }
+
+    pub fn rate_limit(mut self, per_sec: u32, burst: u32) -> ServerBuilder {
+        self.config.rate_limit_per_sec = per_sec;
+        self.config.rate_limit_burst = burst;
+        self
+    }
+
+    pub fn cors_allowed_origins(mut self, origins: &str) -> ServerBuilder {
+        self.config.cors_allowed_origins = origins.to_string();
+        self
+    }
+
+    // serve the gRPC api in a port
+    pub fn grpc_port(mut self, port: u16) -> ServerBuilder {
+        self.config.grpc_port = port;
+        self
+    }
+
+    // serve the api over TLS, with the self-signed certificate of "localhost" in the fixtures
+    pub fn tls(mut self) -> ServerBuilder {
+        let fixtures = format!("{}/tests/fixtures", env!("CARGO_MANIFEST_DIR"));
+        self.config.tls_cert_path = format!("{}/localhost-cert.pem", fixtures);
+        self.config.tls_key_path = format!("{}/localhost-key.pem", fixtures);
+        self
+    }
+
+    pub fn log_level(mut self, log_level: &str) -> ServerBuilder {
+        self.config.log_level = log_level.to_string();
+        self
+    }
+
+    // make the node misbehave, to test how honest nodes react to it
+    pub fn byzantine(mut self, behavior: &str) -> ServerBuilder {
+        self.config.byzantine_behaviors.push(behavior.to_string());
+        self
+    }
+
     pub fn start(self) -> Server {
         Server::new(self.config)
     }
@@ -91,6 +318,9 @@ impl Server {
         // We return the server only after all the processes have started
         // The last process to start is the rest api, so we wait until de output indicates it
         server.wait_for_log_message("actix-web-service");
+        if server.config.grpc_port != 0 {
+            server.wait_for_log_message("gRPC api listening");
+        }
 
         server
     }
@@ -99,10 +329,55 @@ impl Server {
     fn start_process(config: &Config) -> Child {
         Command::new(cargo_bin("rust_blockchain"))
             .env("PORT", config.port.to_string())
+            .env("LISTEN_ADDRESSES", config.listen_addresses.join(","))
+            .env("DATA_DIR", &config.data_dir)
+            .env("PRUNE_DEPTH", config.prune_depth.to_string())
+            .env("SNAPSHOT_PATH", &config.snapshot_path)
             .env("PEERS", config.peers.join(","))
+            .env("AUTO_MINING", config.auto_mining.to_string())
             .env("DIFFICULTY", config.difficulty.to_string())
             .env("TRANSACTION_WAITING_MS", config.tx_waiting_ms.to_string())
             .env("PEER_SYNC_MS", config.peer_sync_ms.to_string())
+            .env("SHUTDOWN_DRAIN_MS", config.shutdown_drain_ms.to_string())
+            .env(
+                "SHUTDOWN_TIMEOUT_SECS",
+                config.shutdown_timeout_secs.to_string(),
+            )
+            .env("FINALITY_DEPTH", config.finality_depth.to_string())
+            .env("CONTRACTS_ENABLED", config.contracts_enabled.to_string())
+            .env(
+                "MAX_TIME_DRIFT_SECS",
+                config.max_time_drift_secs.to_string(),
+            )
+            .env("AMOUNT_DECIMALS", config.amount_decimals.to_string())
+            .env("NOTIFICATION_POLL_MS", "10")
+            .env("LONGPOLL_TIMEOUT_MS", "1000")
+            .env("BYZANTINE_BEHAVIORS", config.byzantine_behaviors.join(","))
+            .env("CONSENSUS", &config.consensus)
+            .env("POA_SIGNERS", config.poa_signers.join(","))
+            .env("POA_SIGNER_SEED", &config.poa_signer_seed)
+            .env("POA_BLOCK_INTERVAL_MS", "10")
+            .env("WALLET_MODE", &config.wallet_mode)
+            .env("WALLET_ADDRESSES", config.wallet_addresses.join(","))
+            .env("CHAIN_ID", &config.chain_id)
+            .env("API_KEY", &config.api_key)
+            .env("API_PUBLIC_READS", config.api_public_reads.to_string())
+            .env("API_MAX_BODY_BYTES", config.api_max_body_bytes.to_string())
+            .env("RATE_LIMIT_PER_SEC", config.rate_limit_per_sec.to_string())
+            .env("RATE_LIMIT_BURST", config.rate_limit_burst.to_string())
+            .env("CORS_ALLOWED_ORIGINS", &config.cors_allowed_origins)
+            .env("GRPC_PORT", config.grpc_port.to_string())
+            .env("TLS_CERT_PATH", &config.tls_cert_path)
+            .env("TLS_KEY_PATH", &config.tls_key_path)
+            .env("LOG_LEVEL", &config.log_level)
+            .env("P2P_PORT", config.p2p_port.to_string())
+            .env("P2P_PEERS", config.p2p_peers.join(","))
+            .env("P2P_SEEDS", config.p2p_seeds.join(","))
+            .env("P2P_ANNOUNCE_MS", "10")
+            .env("P2P_PEER_BOOK", &config.p2p_peer_book)
+            .env("P2P_PEER_BOOK_SAVE_MS", "10")
+            .env("LIGHT_CLIENT", config.light_client.to_string())
+            .env("SCHEDULER_JITTER_MS", "0")
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
             .spawn()
@@ -143,11 +418,44 @@ impl Server {
         self.wait_for_log_message("Added new peer block");
     }
 
+    // block the execution until we receive a new block via p2p network
+    pub fn wait_for_network_block(&mut self) {
+        self.wait_for_log_message("Added new network block");
+    }
+
     // block the execution until we receive a new block via api
     pub fn wait_to_receive_block_in_api(&mut self) {
         self.wait_for_log_message("Received new block");
     }
 
+    // send a termination signal without waiting for the server to finish
+    pub fn start_shutdown(&mut self) {
+        kill(self.get_pid(), SIGTERM).unwrap();
+        self.wait_for_log_message("shutdown requested");
+    }
+
+    // block the execution until the process finishes on its own (e.g. on startup errors)
+    // returns false if it's still running after a max time
+    pub fn wait_for_exit(&mut self) -> bool {
+        let max_waiting_in_millis = 2000;
+        let wait_time = 50;
+
+        for _ in 0..(max_waiting_in_millis / wait_time) {
+            if self.process.try_wait().unwrap().is_some() {
+                return true;
+            }
+            Server::sleep_millis(wait_time);
+        }
+
+        false
+    }
+
+    // check if a message was already logged by the process
+    pub fn has_logged(&mut self, message: &str) -> bool {
+        self.wait_for_log_message(message);
+        self.search_message_in_output(message)
+    }
+
     // block the execution until a message is contained in the process output
     // or until a max time has passed
     fn wait_for_log_message(&mut self, message: &str) {
@@ -180,6 +488,10 @@ impl Server {
     fn stop(&mut self) {
         println!("Shutting down server on port {}", self.config.port);
 
+        // the process may have already finished on its own (e.g. on startup errors)
+        if self.process.try_wait().unwrap().is_some() {
+            return;
+        }
         kill(self.get_pid(), SIGTERM).unwrap();
 
         // block the thread until the server has finished
@@ -0,0 +1,108 @@
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use serde_json::Value;
+
+type SyncedEvents = Arc<Mutex<Vec<Value>>>;
+
+// Minimal HTTP server that records the JSON bodies of all the requests it receives
+pub struct WebhookReceiver {
+    pub port: u16,
+    events: SyncedEvents,
+    stopped: Arc<AtomicBool>,
+}
+
+#[allow(dead_code)]
+impl WebhookReceiver {
+    pub fn start(port: u16) -> WebhookReceiver {
+        let listener = TcpListener::bind(("localhost", port)).unwrap();
+        listener.set_nonblocking(true).unwrap();
+
+        let events = SyncedEvents::default();
+        let stopped = Arc::new(AtomicBool::new(false));
+
+        let thread_events = events.clone();
+        let thread_stopped = stopped.clone();
+        thread::spawn(move || {
+            // we poll for new connections, so we can stop listening when the receiver is dropped
+            while !thread_stopped.load(Ordering::SeqCst) {
+                match listener.accept() {
+                    Ok((stream, _)) => {
+                        let body = WebhookReceiver::read_body(stream);
+                        thread_events.lock().unwrap().push(body);
+                    }
+                    Err(_) => thread::sleep(Duration::from_millis(10)),
+                }
+            }
+        });
+
+        WebhookReceiver {
+            port,
+            events,
+            stopped,
+        }
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://localhost:{}", self.port)
+    }
+
+    // block the execution until the number of events is received
+    // or until a max time has passed, returning all received events
+    pub fn wait_for_events(&self, count: usize) -> Vec<Value> {
+        let max_wait_time = Duration::from_millis(2000);
+
+        let start = Instant::now();
+        while Instant::now() < start + max_wait_time {
+            if self.events.lock().unwrap().len() >= count {
+                break;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+
+        self.events.lock().unwrap().clone()
+    }
+
+    fn read_body(stream: TcpStream) -> Value {
+        stream.set_nonblocking(false).unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+        // read the headers to know the length of the body
+        let mut content_length = 0;
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            let line = line.trim().to_lowercase();
+            if line.is_empty() {
+                break;
+            }
+            if let Some(value) = line.strip_prefix("content-length:") {
+                content_length = value.trim().parse().unwrap();
+            }
+        }
+
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body).unwrap();
+
+        let mut stream = stream;
+        stream
+            .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+            .unwrap();
+
+        serde_json::from_slice(&body).unwrap()
+    }
+}
+
+impl Drop for WebhookReceiver {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+    }
+}
@@ -0,0 +1,99 @@
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpStream,
+    time::Duration,
+};
+
+use serde_json::Value;
+
+// Max time to wait for a message from the server
+const READ_TIMEOUT_MS: u64 = 2000;
+
+// Minimal websocket client, only supports unfragmented text messages
+pub struct WebSocketClient {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+#[allow(dead_code)]
+impl WebSocketClient {
+    pub fn connect(port: u16) -> WebSocketClient {
+        let mut writer = TcpStream::connect(("localhost", port)).unwrap();
+        let timeout = Some(Duration::from_millis(READ_TIMEOUT_MS));
+        writer.set_read_timeout(timeout).unwrap();
+        let mut reader = BufReader::new(writer.try_clone().unwrap());
+
+        // the key is a fixed example, we don't check the accept header of the response
+        let request = format!(
+            "GET /ws HTTP/1.1\r\nHost: localhost:{}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+            Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n",
+            port
+        );
+        writer.write_all(request.as_bytes()).unwrap();
+
+        let mut status_line = String::new();
+        reader.read_line(&mut status_line).unwrap();
+        assert!(status_line.contains("101"), "{}", status_line);
+
+        // skip the rest of the headers
+        let mut line = String::new();
+        while line != "\r\n" {
+            line.clear();
+            reader.read_line(&mut line).unwrap();
+        }
+
+        WebSocketClient { reader, writer }
+    }
+
+    // Sends a JSON value in a masked text frame, as required for clients
+    pub fn send_json(&mut self, value: &Value) {
+        let payload = value.to_string().into_bytes();
+        let mask = [1u8, 2, 3, 4];
+
+        let mut frame = vec![0x81];
+        if payload.len() < 126 {
+            frame.push(0x80 | payload.len() as u8);
+        } else {
+            frame.push(0x80 | 126);
+            frame.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+        }
+        frame.extend_from_slice(&mask);
+        frame.extend(
+            payload
+                .iter()
+                .enumerate()
+                .map(|(i, byte)| byte ^ mask[i % 4]),
+        );
+
+        self.writer.write_all(&frame).unwrap();
+    }
+
+    // Blocks until the next text message arrives, panicking if it takes too long
+    pub fn receive_json(&mut self) -> Value {
+        loop {
+            let mut header = [0u8; 2];
+            self.reader.read_exact(&mut header).unwrap();
+
+            let payload_len = match header[1] & 0x7f {
+                126 => {
+                    let mut len = [0u8; 2];
+                    self.reader.read_exact(&mut len).unwrap();
+                    u16::from_be_bytes(len) as usize
+                }
+                127 => {
+                    let mut len = [0u8; 8];
+                    self.reader.read_exact(&mut len).unwrap();
+                    u64::from_be_bytes(len) as usize
+                }
+                len => len as usize,
+            };
+            let mut payload = vec![0u8; payload_len];
+            self.reader.read_exact(&mut payload).unwrap();
+
+            // server frames are never masked, and we skip the ones that are not text (e.g. pings)
+            if header[0] & 0x0f == 0x1 {
+                return serde_json::from_slice(&payload).unwrap();
+            }
+        }
+    }
+}
@@ -0,0 +1,69 @@
+mod common;
+
+use std::process::Command;
+
+use assert_cmd::cargo::cargo_bin;
+use serial_test::serial;
+
+use crate::common::{Api, ServerBuilder, Transaction};
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_report_fork_point_between_nodes() {
+    // the nodes are not peers, so each one mines its own chain
+    let mut local_node = ServerBuilder::new().port(8000).start();
+    let mut remote_node = ServerBuilder::new().port(8001).start();
+
+    // nodes with the same blocks are identical
+    let output = run_compare(&["--remote", "http://localhost:8001"]);
+    assert!(output.contains("the chains are identical up to block 0"));
+
+    // mine a different block in each node
+    local_node.add_transaction(&create_transaction(1));
+    local_node.wait_for_mining();
+    remote_node.add_transaction(&create_transaction(2));
+    remote_node.wait_for_mining();
+
+    // both chains share the genesis block and forked right after it
+    let output = run_compare(&["--remote", "http://localhost:8001"]);
+    assert!(output.contains("the chains forked at index 1, last common block is 0"));
+    assert!(output.contains("block 1: local"));
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_fail_comparing_unreachable_nodes() {
+    let _node = ServerBuilder::new().port(8000).start();
+
+    // there is no node running in the remote port
+    let status = Command::new(cargo_bin("rust_blockchain"))
+        .args(["compare", "--remote", "http://localhost:8001"])
+        .env("PORT", "8000")
+        .status()
+        .unwrap();
+    assert!(!status.success());
+}
+
+// run the comparison against the node in port 8000 and return its report
+fn run_compare(args: &[&str]) -> String {
+    let output = Command::new(cargo_bin("rust_blockchain"))
+        .arg("compare")
+        .args(args)
+        .env("PORT", "8000")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    String::from_utf8(output.stdout).unwrap()
+}
+
+fn create_transaction(amount: u64) -> Transaction {
+    Transaction {
+        sender: "1".to_string(),
+        recipient: "2".to_string(),
+        amount,
+        signature: None,
+    }
+}
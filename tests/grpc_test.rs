@@ -0,0 +1,109 @@
+mod common;
+
+use crate::common::{field, Api, GrpcClient, ServerBuilder, Transaction};
+use serial_test::serial;
+
+// gRPC status codes returned by the node
+const NOT_FOUND: u32 = 5;
+const ALREADY_EXISTS: u32 = 6;
+const UNAUTHENTICATED: u32 = 16;
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_answer_grpc_calls() {
+    let node = ServerBuilder::new().manual_mining().grpc_port(7000).start();
+    let mut client = GrpcClient::connect(&node);
+
+    let status = client.get_status().unwrap();
+    assert_eq!(
+        field(&status, 1).unwrap().string(),
+        env!("CARGO_PKG_VERSION")
+    );
+    let latest = field(&status, 3).unwrap().message();
+    assert_eq!(field(&latest, 1).unwrap().uint64(), 0);
+
+    // submitted transactions go to the same pool as the ones of the REST api
+    let transaction = Transaction {
+        sender: "1".to_string(),
+        recipient: "2".to_string(),
+        amount: 3,
+        signature: None,
+    };
+    let id = client.submit_transaction(&transaction).unwrap();
+    let pending = node.get_transactions();
+    assert_eq!(pending[0]["id"], serde_json::json!(id));
+
+    let error = client.submit_transaction(&transaction).unwrap_err();
+    assert_eq!(error.code, ALREADY_EXISTS);
+
+    let response = client.get_transaction(id).unwrap();
+    assert!(field(&response, 2).is_some());
+    let balance = client.get_balance("2").unwrap();
+    let pending_amounts = field(&balance, 3).unwrap().message();
+    assert_eq!(field(&pending_amounts, 1).unwrap().uint64(), 3);
+
+    // once mined, the transaction is in a block
+    node.mine(true);
+    let block = client.get_block(1).unwrap();
+    assert_eq!(block, node.get_last_block());
+    assert_eq!(block.transactions, vec![transaction]);
+
+    let response = client.get_transaction(id).unwrap();
+    let confirmed = field(&response, 3).unwrap().message();
+    assert_eq!(field(&confirmed, 1).unwrap().uint64(), 1);
+
+    let error = client.get_block(99).unwrap_err();
+    assert_eq!(error.code, NOT_FOUND);
+    assert_eq!(error.message, "Block not found");
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_require_api_key_for_grpc_writes() {
+    let node = ServerBuilder::new()
+        .manual_mining()
+        .api_key("secret")
+        .grpc_port(7000)
+        .start();
+    let transaction = Transaction {
+        sender: "1".to_string(),
+        recipient: "2".to_string(),
+        amount: 3,
+        signature: None,
+    };
+
+    // reads are public by default, but writes need the key in the metadata
+    let mut client = GrpcClient::connect_without_key(node.config.grpc_port);
+    assert!(client.get_status().is_ok());
+    let error = client.submit_transaction(&transaction).unwrap_err();
+    assert_eq!(error.code, UNAUTHENTICATED);
+
+    let mut client = GrpcClient::connect(&node);
+    assert!(client.submit_transaction(&transaction).is_ok());
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_stream_blocks() {
+    let node = ServerBuilder::new().manual_mining().grpc_port(7000).start();
+    let mut client = GrpcClient::connect(&node);
+
+    // existing blocks are sent first when asking for them
+    client.stream_blocks(Some(0));
+    let genesis = client.next_streamed_block();
+    assert_eq!(genesis, node.get_blocks()[0]);
+
+    node.mine(true);
+    let block = client.next_streamed_block();
+    assert_eq!(block.index, 1);
+    assert_eq!(block.previous_hash, genesis.hash);
+
+    // without an index, only the new blocks are sent
+    let mut client = GrpcClient::connect(&node);
+    client.stream_blocks(None);
+    node.mine(true);
+    assert_eq!(client.next_streamed_block().index, 2);
+}
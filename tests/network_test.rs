@@ -0,0 +1,273 @@
+mod common;
+
+use std::{env, fs, thread, time::Duration};
+
+use crate::common::{Api, ServerBuilder, Transaction};
+use isahc::ReadResponseExt;
+use serde_json::Value;
+use serial_test::serial;
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_announce_new_blocks() {
+    let node = ServerBuilder::new().port(8000).p2p_port(9000).start();
+    let mut connected_node = ServerBuilder::new().port(8001).p2p_peer(9000).start();
+
+    // the first node announces the block to every connected node
+    node.add_valid_block();
+    connected_node.wait_for_network_block();
+
+    assert_eq!(connected_node.get_blocks().len(), 2);
+    assert_eq!(connected_node.get_last_block(), node.get_last_block());
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_catch_up_when_connecting() {
+    // the first node already has some blocks before the other one connects
+    let node = ServerBuilder::new().port(8000).p2p_port(9000).start();
+    node.add_valid_block();
+    node.add_valid_block();
+
+    // the new node requests the blocks it's missing right after connecting
+    let mut new_node = ServerBuilder::new().port(8001).p2p_peer(9000).start();
+    new_node.wait_for_network_block();
+
+    assert_eq!(new_node.get_blocks().len(), 3);
+    assert_eq!(new_node.get_last_block(), node.get_last_block());
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_sync_blocks_in_batches() {
+    // the first node has more blocks than the ones downloaded in a single batch
+    let node = ServerBuilder::new().port(8000).p2p_port(9000).start();
+    for _ in 0..60 {
+        node.add_valid_block();
+    }
+
+    let mut new_node = ServerBuilder::new().port(8001).p2p_peer(9000).start();
+    assert!(new_node.has_logged("Synchronized 50 of 60 blocks"));
+    assert!(new_node.has_logged("Finished synchronizing with p2p peer"));
+
+    assert_eq!(new_node.get_blocks().len(), 61);
+    assert_eq!(new_node.get_last_block(), node.get_last_block());
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_not_connect_to_other_chains() {
+    let node = ServerBuilder::new().port(8000).p2p_port(9000).start();
+    node.add_valid_block();
+
+    let mut other_node = ServerBuilder::new()
+        .port(8001)
+        .p2p_peer(9000)
+        .chain_id("testnet")
+        .start();
+    assert!(other_node.has_logged("Disconnected from incompatible p2p peer localhost:9000"));
+
+    // the nodes never mix their chains
+    assert_eq!(other_node.get_blocks().len(), 1);
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_relay_transactions() {
+    let mut node = ServerBuilder::new().port(8000).p2p_port(9000).start();
+    let connected_node = ServerBuilder::new().port(8001).p2p_peer(9000).start();
+
+    // the transaction is added to the other node, which relays it to the network
+    let transaction = Transaction {
+        sender: "1".to_string(),
+        recipient: "2".to_string(),
+        amount: 3,
+        signature: None,
+    };
+    let res = connected_node.add_transaction(&transaction);
+    assert_eq!(res.status().as_u16(), 200);
+
+    assert!(node.has_logged("Added new network transaction"));
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_relay_transactions_through_the_network() {
+    // the nodes are connected in a line, so the transaction needs two hops
+    let mut node = ServerBuilder::new().port(8000).p2p_port(9000).start();
+    let _middle_node = ServerBuilder::new()
+        .port(8001)
+        .p2p_port(9001)
+        .p2p_peer(9000)
+        .start();
+    let last_node = ServerBuilder::new().port(8002).p2p_peer(9001).start();
+
+    let transaction = Transaction {
+        sender: "1".to_string(),
+        recipient: "2".to_string(),
+        amount: 3,
+        signature: None,
+    };
+    let res = last_node.add_transaction(&transaction);
+    assert_eq!(res.status().as_u16(), 200);
+
+    assert!(node.has_logged("Added new network transaction"));
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_discover_peers_through_seeds() {
+    let _seed_node = ServerBuilder::new().port(8000).p2p_port(9000).start();
+    let _node = ServerBuilder::new()
+        .port(8001)
+        .p2p_port(9001)
+        .p2p_seed(9000)
+        .start();
+
+    // the new node only knows the seed, which shares the address of the other node
+    let mut new_node = ServerBuilder::new()
+        .port(8002)
+        .p2p_port(9002)
+        .p2p_seed(9000)
+        .start();
+
+    assert!(new_node.has_logged("Connected to p2p peer localhost:9000"));
+    assert!(new_node.has_logged("Connected to p2p peer 127.0.0.1:9001"));
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_remember_peers_across_restarts() {
+    let path = env::temp_dir().join("rust_blockchain_peer_book_test.json");
+    let path = path.to_str().unwrap();
+    let _ = fs::remove_file(path);
+
+    let _seed_node = ServerBuilder::new().port(8000).p2p_port(9000).start();
+
+    // the node joins through the seed and saves it as a good peer
+    let node = ServerBuilder::new()
+        .port(8001)
+        .p2p_seed(9000)
+        .p2p_peer_book(path)
+        .start();
+    thread::sleep(Duration::from_millis(200));
+    drop(node);
+    assert!(fs::read_to_string(path).unwrap().contains("localhost:9000"));
+
+    // after a restart, the node connects to the saved peer without any seed
+    let mut restarted_node = ServerBuilder::new().port(8001).p2p_peer_book(path).start();
+    assert!(restarted_node.has_logged("Connected to p2p peer localhost:9000"));
+
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_let_operators_ban_peers() {
+    let _node = ServerBuilder::new().port(8000).p2p_port(9000).start();
+    let mut connected_node = ServerBuilder::new().port(8001).p2p_peer(9000).start();
+    assert!(connected_node.has_logged("Connected to p2p peer localhost:9000"));
+
+    let peers = connected_node.get_peers();
+    assert_eq!(peers[0]["id"], "localhost:9000");
+    assert_eq!(peers[0]["connected"], true);
+    assert_eq!(peers[0]["score"], 0);
+
+    // unknown peers can't be banned
+    assert_eq!(
+        connected_node.ban_peer("localhost:9999").status().as_u16(),
+        404
+    );
+    assert_eq!(
+        connected_node.ban_peer("localhost:9000").status().as_u16(),
+        200
+    );
+
+    // the node does not connect to the banned peer again
+    thread::sleep(Duration::from_millis(1500));
+    let peers = connected_node.get_peers();
+    assert_eq!(peers[0]["connected"], false);
+    assert_eq!(peers[0]["banned"], true);
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_let_operators_connect_to_peers() {
+    let node = ServerBuilder::new().port(8000).p2p_port(9000).start();
+    node.mine(true);
+    // the other node doesn't know any peer, nor listens for them
+    let mut other_node = ServerBuilder::new().port(8001).start();
+    assert!(other_node.get_peers().as_array().unwrap().is_empty());
+
+    assert_eq!(other_node.add_peer("localhost").status().as_u16(), 400);
+    assert_eq!(other_node.add_peer("localhost:9000").status().as_u16(), 202);
+    assert!(other_node.has_logged("Connected to p2p peer localhost:9000"));
+    thread::sleep(Duration::from_millis(200));
+
+    let peers = other_node.get_peers();
+    assert_eq!(peers[0]["id"], "localhost:9000");
+    assert_eq!(peers[0]["address"], "localhost:9000");
+    assert_eq!(peers[0]["connected"], true);
+    assert_eq!(peers[0]["outbound"], true);
+    assert_eq!(peers[0]["height"], 1);
+    assert!(peers[0]["latency_ms"].is_u64());
+
+    // banned peers can't be added again
+    assert_eq!(other_node.ban_peer("localhost:9000").status().as_u16(), 200);
+    assert_eq!(other_node.add_peer("localhost:9000").status().as_u16(), 409);
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_verify_transactions_as_light_client() {
+    // the transaction is mined by a full node
+    let mut node = ServerBuilder::new().port(8000).p2p_port(9000).start();
+    let transaction = Transaction {
+        sender: "1".to_string(),
+        recipient: "2".to_string(),
+        amount: 3,
+        signature: None,
+    };
+    let mut res = node.add_transaction(&transaction);
+    let body: Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    let id = body["id"].as_str().unwrap().to_string();
+    node.wait_for_mining();
+    let block = node.get_last_block();
+
+    // the light client only downloads the header of the block...
+    let mut light_client = ServerBuilder::new()
+        .port(8001)
+        .p2p_peer(9000)
+        .light_client()
+        .start();
+    assert!(light_client.has_logged("Added new network header 1"));
+    let light_block = light_client.get_last_block();
+    assert_eq!(light_block.hash, block.hash);
+    assert!(light_block.transactions.is_empty());
+
+    // ...and checks that it includes the transaction with the proof of the full node
+    let mut res = light_client.get_transaction_proof(&id);
+    assert_eq!(res.status().as_u16(), 200);
+    let proof: Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    assert_eq!(proof["transaction_id"], id);
+    assert_eq!(
+        proof["block_hash"],
+        serde_json::to_value(block.hash).unwrap()
+    );
+    assert_eq!(proof["confirmations"], 1);
+    assert!(light_client.has_logged("Verified the inclusion of transaction"));
+
+    // the full node doesn't sync from the light client
+    assert!(!node.has_logged("Synchronizing with p2p peer"));
+}
@@ -1,8 +1,13 @@
 mod common;
 
-use crate::common::{Api, ServerBuilder};
+use crate::common::{Api, Block, BlockHash, Bloom, ServerBuilder, Transaction};
 use serial_test::serial;
 
+// ed25519 keys of the only signer in the proof of authority tests
+const POA_SIGNER_SEED: &str = "0101010101010101010101010101010101010101010101010101010101010101";
+const POA_SIGNER_PUBLIC_KEY: &str =
+    "8a88e3dd7409f195fd52db2d3cba5d72ca6709bf1d94121bf3748801b40f6f5c";
+
 #[test]
 #[serial]
 #[cfg(unix)]
@@ -108,3 +113,166 @@ fn test_should_send_new_blocks() {
     let last_follower_block = leader_node.get_last_block();
     assert_eq!(last_follower_block, last_leader_block);
 }
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_send_new_blocks_with_api_key() {
+    // peers must share the key, as they read and send blocks through the api
+    let mut follower_node = ServerBuilder::new()
+        .port(8000)
+        .api_key("secret")
+        .private_reads()
+        .start();
+    let leader_node = ServerBuilder::new()
+        .port(8001)
+        .peer(8000)
+        .api_key("secret")
+        .private_reads()
+        .start();
+
+    leader_node.add_valid_block();
+    assert_eq!(leader_node.get_blocks().len(), 2);
+
+    follower_node.wait_to_receive_block_in_api();
+    assert_eq!(follower_node.get_last_block(), leader_node.get_last_block());
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_not_receive_invalid_blocks_from_byzantine_peer() {
+    // This node will share blocks with a broken link to the previous block
+    let byzantine_node = ServerBuilder::new()
+        .port(8000)
+        .byzantine("invalid_blocks")
+        .start();
+    let mut follower_node = ServerBuilder::new().port(8001).peer(8000).start();
+
+    // we create a new valid block in the byzantine node
+    byzantine_node.add_valid_block();
+
+    // the follower node should eventually ask and receive the new block
+    follower_node.wait_for_peer_sync();
+
+    // but the block should not be added as it's not valid
+    assert_eq!(follower_node.get_blocks().len(), 1);
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_not_receive_withheld_blocks_from_byzantine_peer() {
+    // This node will never share its blocks
+    let byzantine_node = ServerBuilder::new()
+        .port(8000)
+        .byzantine("withhold_blocks")
+        .start();
+    let mut follower_node = ServerBuilder::new().port(8001).peer(8000).start();
+
+    // we create a new valid block in the byzantine node
+    byzantine_node.add_valid_block();
+
+    // the follower keeps asking, but never gets the new block
+    follower_node.wait_for_peer_sync();
+    assert_eq!(follower_node.get_blocks().len(), 1);
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_ignore_malformed_messages_from_byzantine_peer() {
+    // This node will send messages that can not be parsed
+    let byzantine_node = ServerBuilder::new()
+        .port(8000)
+        .byzantine("malformed_messages")
+        .start();
+    let mut follower_node = ServerBuilder::new().port(8001).peer(8000).start();
+
+    // we can't read the blocks from the byzantine node, but all nodes share the genesis block
+    let genesis_block = follower_node.get_last_block();
+    let bits = byzantine_node.get_next_bits();
+    byzantine_node.add_block(&create_next_block(&genesis_block, bits));
+
+    // the follower node should ignore the malformed responses without crashing
+    follower_node.wait_for_peer_sync();
+    assert_eq!(follower_node.get_blocks().len(), 1);
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_accept_only_one_block_from_double_signing_peer() {
+    // This node will send two conflicting blocks for each index
+    let mut follower_node = ServerBuilder::new().port(8000).start();
+    let byzantine_node = ServerBuilder::new()
+        .port(8001)
+        .peer(8000)
+        .byzantine("double_sign")
+        .start();
+
+    // we create a new valid block in the byzantine node
+    byzantine_node.add_valid_block();
+
+    // the follower node should accept only the first of the conflicting blocks
+    follower_node.wait_to_receive_block_in_api();
+    assert_eq!(follower_node.get_blocks().len(), 2);
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_follow_blocks_from_authority_signer() {
+    // The signer set has a single signer, which always has the turn
+    let signers = [POA_SIGNER_PUBLIC_KEY];
+    let mut signer_node = ServerBuilder::new()
+        .port(8000)
+        .proof_of_authority(&signers, POA_SIGNER_SEED)
+        .start();
+
+    // This node is not in the signer set, so it can only follow the signer
+    let mut follower_node = ServerBuilder::new()
+        .port(8001)
+        .peer(8000)
+        .proof_of_authority(&signers, "")
+        .start();
+
+    // the signer produces a new signed block with the transaction
+    let transaction = Transaction {
+        sender: "1".to_string(),
+        recipient: "2".to_string(),
+        amount: 3,
+        signature: None,
+    };
+    signer_node.add_transaction(&transaction);
+    signer_node.wait_for_mining();
+
+    let signed_block = signer_node.get_last_block();
+    assert_eq!(signed_block.index, 1);
+    assert!(signed_block.signature.is_some());
+
+    // the follower node should eventually ask and add the signed block
+    follower_node.wait_for_peer_sync();
+    assert_eq!(follower_node.get_last_block(), signed_block);
+
+    // blocks without a valid signature are rejected
+    let res = follower_node.add_valid_block();
+    assert_eq!(res.status().as_u16(), 400);
+}
+
+fn create_next_block(last_block: &Block, bits: u32) -> Block {
+    Block {
+        index: last_block.index + 1,
+        timestamp: last_block.timestamp + 1,
+        nonce: 0,
+        bits,
+        previous_hash: last_block.hash,
+        // the api automatically recalculates the hash, the merkle root and the bloom
+        hash: BlockHash::default(),
+        merkle_root: BlockHash::default(),
+        bloom: Bloom::default(),
+        state_root: last_block.state_root,
+        transactions: [].to_vec(),
+        signature: None,
+    }
+}
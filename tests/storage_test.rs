@@ -0,0 +1,261 @@
+mod common;
+
+use std::{env, fs, io::Write, path::Path};
+
+use isahc::ReadResponseExt;
+use serde_json::{json, Value};
+use serial_test::serial;
+
+use crate::common::{Api, ServerBuilder, Transaction, TransactionResponse};
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_keep_blocks_across_restarts() {
+    let data_dir = env::temp_dir().join("rust_blockchain_storage_test");
+    let data_dir = data_dir.to_str().unwrap();
+    let _ = fs::remove_dir_all(data_dir);
+
+    let node = ServerBuilder::new()
+        .manual_mining()
+        .data_dir(data_dir)
+        .start();
+    node.mine(true);
+    node.mine(true);
+    let blocks = node.get_blocks();
+    drop(node);
+
+    // the process died in the middle of writing a block
+    let mut file = fs::OpenOptions::new()
+        .append(true)
+        .open(Path::new(data_dir).join("blocks.log"))
+        .unwrap();
+    file.write_all(&[0, 0, 1, 0, 42, 42]).unwrap();
+    drop(file);
+
+    // the partial block is discarded and the complete ones are loaded again
+    let mut node = ServerBuilder::new()
+        .manual_mining()
+        .data_dir(data_dir)
+        .start();
+    assert!(node.has_logged("discarding 6 bytes of a partially written block"));
+    assert_eq!(node.get_blocks(), blocks);
+
+    // new blocks go right after the last complete one
+    node.mine(true);
+    drop(node);
+    let node = ServerBuilder::new()
+        .manual_mining()
+        .data_dir(data_dir)
+        .start();
+    assert_eq!(node.get_last_block().index, 3);
+
+    fs::remove_dir_all(data_dir).unwrap();
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_migrate_older_data_directories() {
+    let data_dir = env::temp_dir().join("rust_blockchain_schema_test");
+    let data_dir = data_dir.to_str().unwrap();
+    let _ = fs::remove_dir_all(data_dir);
+    let schema_path = Path::new(data_dir).join("schema_version");
+
+    let node = ServerBuilder::new()
+        .manual_mining()
+        .data_dir(data_dir)
+        .start();
+    node.mine(true);
+    let blocks = node.get_blocks();
+    drop(node);
+    assert_eq!(fs::read_to_string(&schema_path).unwrap(), "1\n");
+
+    // directories written before the versioning are upgraded, keeping their blocks
+    fs::remove_file(&schema_path).unwrap();
+    let mut node = ServerBuilder::new()
+        .manual_mining()
+        .data_dir(data_dir)
+        .start();
+    assert!(node.has_logged("to schema version 1"));
+    assert_eq!(node.get_blocks(), blocks);
+    drop(node);
+    assert_eq!(fs::read_to_string(&schema_path).unwrap(), "1\n");
+
+    // while the ones of newer versions are not touched
+    fs::write(&schema_path, "2\n").unwrap();
+    let mut node = ServerBuilder::new()
+        .manual_mining()
+        .data_dir(data_dir)
+        .start();
+    assert!(node.wait_for_exit());
+    assert!(node.has_logged("this node only supports up to 1"));
+
+    fs::remove_dir_all(data_dir).unwrap();
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_prune_old_transactions() {
+    let node = ServerBuilder::new()
+        .manual_mining()
+        .finality_depth(1)
+        .prune_depth(1)
+        .start();
+
+    let mut ids = Vec::new();
+    for amount in 1..=3 {
+        let transaction = Transaction {
+            sender: "alice".to_string(),
+            recipient: "bob".to_string(),
+            amount,
+            signature: None,
+        };
+        let mut res = node.add_transaction(&transaction);
+        let body: TransactionResponse = serde_json::from_str(&res.text().unwrap()).unwrap();
+        ids.push(serde_json::to_value(body.id).unwrap());
+        node.mine(true);
+    }
+
+    // only the last block keeps its transactions
+    let mut res = node.get_block("1");
+    let block: Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    assert_eq!(block["pruned"], true);
+    assert_eq!(block["transactions"], json!([]));
+
+    let mut res = node.get_block("3");
+    let block: Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    assert!(block.get("pruned").is_none());
+    assert_eq!(block["transactions"][0]["amount"], 3);
+
+    // the pruned transactions are still accounted for
+    let balance = node.get_balance("bob");
+    assert_eq!(balance["confirmed"]["received"], 6);
+
+    let mut res = node.get_transaction(ids[0].as_str().unwrap());
+    let status: Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    assert_eq!(status["status"], "confirmed");
+    assert_eq!(status["block_index"], 1);
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_start_from_a_snapshot() {
+    let data_dir = env::temp_dir().join("rust_blockchain_snapshot_test");
+    let data_dir = data_dir.to_str().unwrap();
+    let _ = fs::remove_dir_all(data_dir);
+    let snapshot_path = env::temp_dir().join("rust_blockchain_snapshot.json");
+    let snapshot_path = snapshot_path.to_str().unwrap();
+
+    let source = ServerBuilder::new()
+        .manual_mining()
+        .finality_depth(1)
+        .start();
+    let transaction = Transaction {
+        sender: "alice".to_string(),
+        recipient: "bob".to_string(),
+        amount: 5,
+        signature: None,
+    };
+    source.add_transaction(&transaction);
+    source.mine(true);
+    source.mine(true);
+
+    // the snapshot is taken at the safe block by default
+    let snapshot = source.get_snapshot();
+    assert_eq!(snapshot["height"], 1);
+    assert_eq!(snapshot["balances"]["bob"]["received"], 5);
+    fs::write(snapshot_path, snapshot.to_string()).unwrap();
+
+    // the new node starts right at the snapshot, and syncs the rest from its peer
+    let node = ServerBuilder::new()
+        .port(8001)
+        .peer(8000)
+        .manual_mining()
+        .data_dir(data_dir)
+        .snapshot_path(snapshot_path)
+        .start();
+    assert_eq!(node.wait_for_block(2).hash, source.get_last_block().hash);
+    assert_eq!(node.get_balance("bob")["confirmed"]["received"], 5);
+    assert!(node
+        .get_block("1")
+        .text()
+        .unwrap()
+        .contains("\"pruned\":true"));
+    drop(node);
+
+    // the snapshot is kept with the blocks after it, so it's not needed again
+    fs::remove_file(snapshot_path).unwrap();
+    let node = ServerBuilder::new()
+        .port(8001)
+        .manual_mining()
+        .data_dir(data_dir)
+        .start();
+    assert_eq!(node.get_last_block().index, 2);
+    assert_eq!(node.get_balance("bob")["confirmed"]["received"], 5);
+
+    fs::remove_dir_all(data_dir).unwrap();
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_keep_pending_transactions_across_restarts() {
+    let data_dir = env::temp_dir().join("rust_blockchain_mempool_test");
+    let data_dir = data_dir.to_str().unwrap();
+    let _ = fs::remove_dir_all(data_dir);
+
+    let node = ServerBuilder::new()
+        .manual_mining()
+        .data_dir(data_dir)
+        .start();
+    let mut signed = Transaction {
+        sender: String::new(),
+        recipient: "bob".to_string(),
+        amount: 5,
+        signature: None,
+    };
+    signed.sign(&[1; 32], "main");
+    let unsigned = Transaction {
+        sender: "alice".to_string(),
+        recipient: "bob".to_string(),
+        amount: 7,
+        signature: None,
+    };
+    let mut ids = Vec::new();
+    for transaction in [&signed, &unsigned] {
+        let mut res = node.add_transaction(transaction);
+        let body: TransactionResponse = serde_json::from_str(&res.text().unwrap()).unwrap();
+        ids.push(serde_json::to_value(body.id).unwrap());
+    }
+    drop(node);
+
+    // the pool is checked again on startup, and unsigned transactions are no longer valid in cold mode
+    let node = ServerBuilder::new()
+        .manual_mining()
+        .data_dir(data_dir)
+        .cold_wallet(&[&signed.sender])
+        .start();
+    let pending = node.get_transactions();
+    assert_eq!(pending.as_array().unwrap().len(), 1);
+    assert_eq!(pending[0]["id"], ids[0]);
+
+    let mut res = node.get_transaction_status(ids[1].as_str().unwrap());
+    let status: Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    assert_eq!(status["status"], "dropped");
+    assert_eq!(status["reason"], "invalid");
+
+    // once mined, they don't come back after the next restart
+    node.mine(true);
+    drop(node);
+    let node = ServerBuilder::new()
+        .manual_mining()
+        .data_dir(data_dir)
+        .start();
+    assert_eq!(node.get_transactions(), json!([]));
+    assert_eq!(node.get_balance("bob")["confirmed"]["received"], 5);
+
+    fs::remove_dir_all(data_dir).unwrap();
+}
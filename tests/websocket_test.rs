@@ -0,0 +1,70 @@
+mod common;
+
+use crate::common::{Api, ServerBuilder, Transaction, WebSocketClient};
+use serde_json::json;
+use serial_test::serial;
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_push_subscribed_events() {
+    let node = ServerBuilder::new().start();
+    let mut client = WebSocketClient::connect(node.config.port);
+
+    client.send_json(&json!({"action": "subscribe", "events": ["newBlock", "newTransaction"]}));
+    let reply = client.receive_json();
+    assert_eq!(reply["event"], "subscribed");
+    assert_eq!(reply["data"], json!(["newBlock", "newTransaction"]));
+
+    let transaction = Transaction {
+        sender: "1".to_string(),
+        recipient: "2".to_string(),
+        amount: 3,
+        signature: None,
+    };
+    node.add_transaction(&transaction);
+    let event = client.receive_json();
+    assert_eq!(event["event"], "newTransaction");
+    assert_eq!(event["data"]["amount"], 3);
+
+    node.add_valid_block();
+    let event = client.receive_json();
+    assert_eq!(event["event"], "newBlock");
+    assert_eq!(event["data"]["index"], 1);
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_only_push_subscribed_events() {
+    let node = ServerBuilder::new().start();
+    let mut client = WebSocketClient::connect(node.config.port);
+
+    client.send_json(&json!({"action": "subscribe", "events": ["newBlock"]}));
+    client.receive_json();
+
+    // the transaction is not pushed, so the next event is the block
+    let transaction = Transaction {
+        sender: "1".to_string(),
+        recipient: "2".to_string(),
+        amount: 3,
+        signature: None,
+    };
+    node.add_transaction(&transaction);
+    node.add_valid_block();
+
+    let event = client.receive_json();
+    assert_eq!(event["event"], "newBlock");
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_reject_invalid_requests() {
+    let node = ServerBuilder::new().start();
+    let mut client = WebSocketClient::connect(node.config.port);
+
+    client.send_json(&json!({"action": "subscribe", "events": ["unknown"]}));
+    let reply = client.receive_json();
+    assert_eq!(reply["event"], "error");
+}